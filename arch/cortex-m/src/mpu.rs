@@ -326,6 +326,25 @@ impl CortexMRegion {
         }
     }
 
+    /// A region that only privileged code can access, and that is never
+    /// executable.
+    fn privileged_only(start: *const u8, size: usize, region_num: usize) -> CortexMRegion {
+        let base_address = RegionBaseAddress::ADDR.val((start as u32) >> 5)
+            + RegionBaseAddress::VALID::UseRBAR
+            + RegionBaseAddress::REGION.val(region_num as u32);
+
+        let attributes = RegionAttributes::ENABLE::SET
+            + RegionAttributes::SIZE.val(math::log_base_two(size as u32) - 1)
+            + RegionAttributes::AP::PrivilegedOnly
+            + RegionAttributes::XN::Disable;
+
+        CortexMRegion {
+            location: Some((start, size)),
+            base_address: base_address,
+            attributes: attributes,
+        }
+    }
+
     fn empty(region_num: usize) -> CortexMRegion {
         CortexMRegion {
             location: None,
@@ -721,6 +740,33 @@ impl<const NUM_REGIONS: usize, const MIN_REGION_SIZE: usize> mpu::MPU
         Ok(())
     }
 
+    fn allocate_stack_guard_region(
+        &self,
+        min_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        let (region_start, region_size) = config.regions[APP_MEMORY_REGION_NUM].location()?;
+        let region_num = config.unused_region_number()?;
+
+        // The guard must be a power of two of at least the minimum region
+        // size, aligned to its size. The app memory region is aligned to its
+        // own, larger, size.
+        let size = cmp::max(
+            math::closest_power_of_two(min_size as u32) as usize,
+            MIN_REGION_SIZE,
+        );
+        if size >= region_size / 8 || region_start as usize % size != 0 {
+            return None;
+        }
+
+        // Where regions overlap the highest numbered region applies, and the
+        // app memory region is region 0.
+        config.regions[region_num] = CortexMRegion::privileged_only(region_start, size, region_num);
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(region_start, size))
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &ProcessId) {
         // If the hardware is already configured for this app and the app's MPU
         // configuration has not changed, then skip the hardware update.
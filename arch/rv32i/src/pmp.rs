@@ -492,8 +492,11 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::platform::mpu::MPU
             return Err(());
         }
 
-        // Get size of updated region
-        let region_size = app_memory_break - region_start as usize;
+        // Get size of updated region. The region starts after the stack
+        // guard, if there is one.
+        let region_size = app_memory_break
+            .checked_sub(region_start as usize)
+            .ok_or(())?;
 
         let region = PMPRegion::new(region_start as *const u8, region_size, permissions);
 
@@ -503,6 +506,35 @@ impl<const MAX_AVAILABLE_REGIONS_OVER_TWO: usize> kernel::platform::mpu::MPU
         Ok(())
     }
 
+    fn allocate_stack_guard_region(
+        &self,
+        min_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<mpu::Region> {
+        let region_num = config.app_memory_region.extract()?;
+        let region = config.regions[region_num]?;
+        let (region_start, region_size) = region.location();
+
+        // Guard size always has to align to 4 bytes
+        let mut size = min_size;
+        if size % 4 != 0 {
+            size += 4 - (size % 4);
+        }
+        if size >= region_size {
+            return None;
+        }
+
+        // User mode cannot access memory that no region covers, so starting
+        // the app memory region after the guard protects it.
+        config.regions[region_num] = Some(PMPRegion {
+            location: (region_start.wrapping_add(size), region_size - size),
+            cfg: region.cfg,
+        });
+        config.is_dirty.set(true);
+
+        Some(mpu::Region::new(region_start, size))
+    }
+
     fn configure_mpu(&self, config: &Self::MpuConfig, app_id: &ProcessId) {
         // Is the PMP already configured for this app?
         let last_configured_for_this_app = self
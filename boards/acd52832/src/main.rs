@@ -64,7 +64,11 @@ pub struct Platform {
         nrf52832::ble_radio::Radio<'static>,
         VirtualMuxAlarm<'static, Rtc<'static>>,
     >,
-    button: &'static capsules::button::Button<'static, nrf52832::gpio::GPIOPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        nrf52832::gpio::GPIOPin<'static>,
+        VirtualMuxAlarm<'static, nrf52832::rtc::Rtc<'static>>,
+    >,
     console: &'static capsules::console::Console<'static>,
     gpio: &'static capsules::gpio::GPIO<'static, nrf52832::gpio::GPIOPin<'static>>,
     led: &'static capsules::led::LedDriver<
@@ -235,12 +239,24 @@ pub unsafe fn main() {
         LedLow::new(&nrf52832_peripherals.gpio_port[LED4_PIN]),
     ));
 
+    //
+    // RTC for Timers
+    //
+    let rtc = &base_peripherals.rtc;
+    let _ = rtc.start();
+    let mux_alarm = static_init!(
+        capsules::virtual_alarm::MuxAlarm<'static, nrf52832::rtc::Rtc>,
+        capsules::virtual_alarm::MuxAlarm::new(&base_peripherals.rtc)
+    );
+    rtc.set_alarm_client(mux_alarm);
+
     //
     // Buttons
     //
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             nrf52832::gpio::GPIOPin,
             // 13
@@ -269,18 +285,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(nrf52832::gpio::GPIOPin));
-
-    //
-    // RTC for Timers
-    //
-    let rtc = &base_peripherals.rtc;
-    let _ = rtc.start();
-    let mux_alarm = static_init!(
-        capsules::virtual_alarm::MuxAlarm<'static, nrf52832::rtc::Rtc>,
-        capsules::virtual_alarm::MuxAlarm::new(&base_peripherals.rtc)
-    );
-    rtc.set_alarm_client(mux_alarm);
+    .finalize(components::button_component_buf!(
+        nrf52832::gpio::GPIOPin,
+        nrf52832::rtc::Rtc
+    ));
 
     //
     // Timer/Alarm
@@ -55,7 +55,11 @@ struct ArtyE21 {
         hil::led::LedHigh<'static, arty_e21_chip::gpio::GpioPin<'static>>,
         3,
     >,
-    button: &'static capsules::button::Button<'static, arty_e21_chip::gpio::GpioPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        arty_e21_chip::gpio::GpioPin<'static>,
+        VirtualMuxAlarm<'static, sifive::clint::Clint<'static>>,
+    >,
     // ipc: kernel::ipc::IPC<NUM_PROCS>,
     scheduler: &'static PrioritySched,
 }
@@ -208,6 +212,7 @@ pub unsafe fn main() {
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             arty_e21_chip::gpio::GpioPin,
             (
@@ -218,7 +223,8 @@ pub unsafe fn main() {
         ),
     )
     .finalize(components::button_component_buf!(
-        arty_e21_chip::gpio::GpioPin
+        arty_e21_chip::gpio::GpioPin,
+        sifive::clint::Clint
     ));
 
     // set GPIO driver controlling remaining GPIO pins
@@ -152,7 +152,11 @@ pub struct Platform {
         LedHigh<'static, nrf52::gpio::GPIOPin<'static>>,
         2,
     >,
-    button: &'static capsules::button::Button<'static, nrf52::gpio::GPIOPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        nrf52::gpio::GPIOPin<'static>,
+        VirtualMuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
+    >,
     screen: &'static capsules::screen::Screen<'static>,
     rng: &'static capsules::rng::RngDriver<'static>,
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
@@ -333,28 +337,6 @@ pub unsafe fn main() {
         LedHigh::new(&nrf52840_peripherals.gpio_port[LED_WHITE_PIN])
     ));
 
-    //--------------------------------------------------------------------------
-    // Buttons
-    //--------------------------------------------------------------------------
-    let button = components::button::ButtonComponent::new(
-        board_kernel,
-        capsules::button::DRIVER_NUM,
-        components::button_component_helper!(
-            nrf52840::gpio::GPIOPin,
-            (
-                &nrf52840_peripherals.gpio_port[BUTTON_LEFT],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ), // Left
-            (
-                &nrf52840_peripherals.gpio_port[BUTTON_RIGHT],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ) // Right
-        ),
-    )
-    .finalize(components::button_component_buf!(nrf52840::gpio::GPIOPin));
-
     //--------------------------------------------------------------------------
     // Deferred Call (Dynamic) Setup
     //--------------------------------------------------------------------------
@@ -376,6 +358,33 @@ pub unsafe fn main() {
 
     let mux_alarm = components::alarm::AlarmMuxComponent::new(rtc)
         .finalize(components::alarm_mux_component_helper!(nrf52::rtc::Rtc));
+
+    //--------------------------------------------------------------------------
+    // Buttons
+    //--------------------------------------------------------------------------
+    let button = components::button::ButtonComponent::new(
+        board_kernel,
+        capsules::button::DRIVER_NUM,
+        mux_alarm,
+        components::button_component_helper!(
+            nrf52840::gpio::GPIOPin,
+            (
+                &nrf52840_peripherals.gpio_port[BUTTON_LEFT],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp
+            ), // Left
+            (
+                &nrf52840_peripherals.gpio_port[BUTTON_RIGHT],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp
+            ) // Right
+        ),
+    )
+    .finalize(components::button_component_buf!(
+        nrf52840::gpio::GPIOPin,
+        nrf52::rtc::Rtc
+    ));
+
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
         capsules::alarm::DRIVER_NUM,
@@ -1,10 +1,13 @@
 use capsules::adc::AdcVirtualized;
+use capsules::adc_stream::AdcStream;
 use capsules::virtual_adc::{AdcDevice, MuxAdc};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil::adc;
+use kernel::hil::time::{self, Alarm};
 use kernel::static_init_half;
 
 #[macro_export]
@@ -49,6 +52,29 @@ macro_rules! adc_syscall_component_helper {
     };};
 }
 
+#[macro_export]
+macro_rules! adc_stream_component_helper {
+    ($A:ty, $($P:expr),+ $(,)?) => {{
+        use capsules::adc_stream::AdcStream;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        use kernel::count_expressions;
+        use kernel::static_init;
+        const NUM_CHANNELS: usize = count_expressions!($($P),+);
+
+        let channels = static_init!(
+            [&'static dyn kernel::hil::adc::AdcChannel; NUM_CHANNELS],
+            [
+                $($P,)*
+            ]
+        );
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<AdcStream<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, channels)
+    };};
+}
+
 pub struct AdcMuxComponent<A: 'static + adc::Adc> {
     adc: &'static A,
 }
@@ -141,3 +167,57 @@ impl Component for AdcVirtualComponent {
         adc
     }
 }
+
+pub struct AdcStreamComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + time::Alarm<'static>> AdcStreamComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> AdcStreamComponent<A> {
+        AdcStreamComponent {
+            board_kernel: board_kernel,
+            driver_num: driver_num,
+            alarm_mux: alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for AdcStreamComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<AdcStream<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static [&'static dyn kernel::hil::adc::AdcChannel],
+    );
+    type Output = &'static AdcStream<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_adc_stream = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        let virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        virtual_alarm.setup();
+
+        let adc_stream = static_init_half!(
+            static_buffer.1,
+            AdcStream<'static, VirtualMuxAlarm<'static, A>>,
+            AdcStream::new(static_buffer.2, virtual_alarm, grant_adc_stream)
+        );
+
+        virtual_alarm.set_alarm_client(adc_stream);
+        for channel in static_buffer.2 {
+            kernel::hil::adc::AdcChannel::set_client(*channel, adc_stream);
+        }
+
+        adc_stream
+    }
+}
@@ -0,0 +1,147 @@
+//! Component for a BLE peripheral with a GATT server.
+//!
+//! This sets up the connection link layer over a radio that implements
+//! `hil::ble_advertising::BleConnectionDriver`, the GATT server, and the
+//! system call driver through which a process declares services.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ble_gatt = components::ble_gatt::BleGattComponent::new(
+//!     board_kernel,
+//!     capsules::ble_gatt_driver::DRIVER_NUM,
+//!     radio,
+//!     mux_alarm,
+//!     [0xf0, 0x11, 0x22, 0x33, 0x44, 0xf5],
+//!     b"Tock",
+//! )
+//! .finalize(components::ble_gatt_component_helper!(
+//!     nrf52::ble_radio::Radio<'static>,
+//!     nrf52::rtc::Rtc<'static>
+//! ));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::ble_connection::BleConnection;
+use capsules::ble_gatt::GattServer;
+use capsules::ble_gatt_driver::BleGattDriver;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ble_advertising::BleConnectionDriver;
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! ble_gatt_component_helper {
+    ($R:ty, $A:ty $(,)?) => {{
+        use capsules::ble_connection::BleConnection;
+        use capsules::ble_gatt::GattServer;
+        use capsules::ble_gatt_driver::BleGattDriver;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<BleConnection<'static, $R, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<GattServer<'static>> = MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<BleGattDriver<'static, $R, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4)
+    };};
+}
+
+pub struct BleGattComponent<
+    R: 'static + BleConnectionDriver<'static>,
+    A: 'static + time::Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static R,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    address: [u8; 6],
+    name: &'static [u8],
+}
+
+impl<R: 'static + BleConnectionDriver<'static>, A: 'static + time::Alarm<'static>>
+    BleGattComponent<R, A>
+{
+    /// `address` is the device's static random address, and `name` the
+    /// device name in the Generic Access service.
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static R,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        address: [u8; 6],
+        name: &'static [u8],
+    ) -> BleGattComponent<R, A> {
+        BleGattComponent {
+            board_kernel,
+            driver_num,
+            radio,
+            alarm_mux,
+            address,
+            name,
+        }
+    }
+}
+
+impl<R: 'static + BleConnectionDriver<'static>, A: 'static + time::Alarm<'static>> Component
+    for BleGattComponent<R, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<BleConnection<'static, R, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<GattServer<'static>>,
+        &'static mut MaybeUninit<BleGattDriver<'static, R, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static BleGattDriver<'static, R, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let ble_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        ble_alarm.setup();
+
+        let ble_connection = static_init_half!(
+            static_buffer.1,
+            BleConnection<'static, R, VirtualMuxAlarm<'static, A>>,
+            BleConnection::new(
+                self.radio,
+                ble_alarm,
+                &mut capsules::ble_connection::BUF,
+                self.address
+            )
+        );
+        self.radio.set_connection_client(ble_connection);
+        ble_alarm.set_alarm_client(ble_connection);
+
+        let gatt_server = static_init_half!(
+            static_buffer.2,
+            GattServer<'static>,
+            GattServer::new(self.name)
+        );
+        gatt_server.set_bearer(ble_connection);
+        ble_connection.set_att_server(gatt_server);
+
+        let ble_gatt = static_init_half!(
+            static_buffer.3,
+            BleGattDriver<'static, R, VirtualMuxAlarm<'static, A>>,
+            BleGattDriver::new(
+                ble_connection,
+                gatt_server,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap)
+            )
+        );
+        gatt_server.set_client(ble_gatt);
+
+        ble_gatt
+    }
+}
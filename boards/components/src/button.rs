@@ -12,6 +12,8 @@
 //! ```rust
 //! let button = components::button::ButtonComponent::new(
 //!     board_kernel,
+//!     capsules::button::DRIVER_NUM,
+//!     mux_alarm,
 //!     components::button_component_helper!(
 //!         sam4l::gpio::GPIOPin,
 //!         (
@@ -21,32 +23,51 @@
 //!         )
 //!     ),
 //! )
-//! .finalize(button_component_buf!(sam4l::gpio::GPIOPin));
+//! .finalize(button_component_buf!(sam4l::gpio::GPIOPin, sam4l::ast::Ast));
 //! ```
 //!
 //! Typically, `ActivationMode::ActiveLow` will be associated with `FloatingState::PullUp`
 //! whereas `ActivationMode::ActiveHigh` will be paired with `FloatingState::PullDown`.
 //! `FloatingState::None` will be used when the board provides external pull-up/pull-down
 //! resistors.
+//!
+//! A button can be given a `capsules::button::ButtonConfig` as a fourth
+//! element, to debounce it or report long presses and double clicks:
+//!
+//! ```rust
+//! (
+//!     &sam4l::gpio::PC[24],
+//!     kernel::hil::gpio::ActivationMode::ActiveLow,
+//!     kernel::hil::gpio::FloatingState::PullUp,
+//!     capsules::button::ButtonConfig {
+//!         debounce_ms: 20,
+//!         long_press_ms: 1000,
+//!         double_click_ms: 0,
+//!     }
+//! )
+//! ```
 
-use capsules::button::Button;
+use capsules::button::{Button, ButtonState};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::create_capability;
 use kernel::hil::gpio;
 use kernel::hil::gpio::InterruptWithValue;
+use kernel::hil::time::{self, Alarm};
 use kernel::static_init_half;
 
 #[macro_export]
 macro_rules! button_component_helper_owned {
-    ($Pin:ty, $(($P:expr, $M:expr, $F:expr)),+ $(,)?) => {
+    ($Pin:ty, $(($P:expr, $M:expr, $F:expr $(, $C:expr)?)),+ $(,)?) => {
         $crate::button_component_helper!(
             $Pin,
             $((
                 static_init!($Pin, $P),
                 $M,
                 $F
+                $(, $C)?
             ),)*
         )
     };
@@ -54,20 +75,22 @@ macro_rules! button_component_helper_owned {
 
 #[macro_export]
 macro_rules! button_component_helper {
-    ($Pin:ty, $(($P:expr, $M:expr, $F:expr)),+ $(,)?) => {{
+    ($Pin:ty, $(($P:expr, $M:expr, $F:expr $(, $C:expr)?)),+ $(,)?) => {{
         use kernel::static_init;
         use kernel::count_expressions;
         use kernel::hil::gpio::InterruptValueWrapper;
+        use capsules::button::ButtonState;
         const NUM_BUTTONS: usize = count_expressions!($($P),+);
 
         static_init!(
-            [(&'static InterruptValueWrapper<'static, $Pin>, kernel::hil::gpio::ActivationMode, kernel::hil::gpio::FloatingState); NUM_BUTTONS],
+            [(&'static InterruptValueWrapper<'static, $Pin>, kernel::hil::gpio::ActivationMode, kernel::hil::gpio::FloatingState, ButtonState); NUM_BUTTONS],
             [
                 $(
                     (static_init!(InterruptValueWrapper<$Pin>, InterruptValueWrapper::new($P))
                     .finalize(),
                     $M,
-                    $F
+                    $F,
+                    ButtonState::new($crate::button_component_config!($($C)?))
                     ),
                 )*
             ]
@@ -75,59 +98,98 @@ macro_rules! button_component_helper {
     };};
 }
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! button_component_config {
+    () => {
+        capsules::button::ButtonConfig::default()
+    };
+    ($C:expr) => {
+        $C
+    };
+}
+
 #[macro_export]
 macro_rules! button_component_buf {
-    ($Pin:ty $(,)?) => {{
+    ($Pin:ty, $A:ty $(,)?) => {{
         use capsules::button::Button;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
         use core::mem::MaybeUninit;
-        static mut BUF: MaybeUninit<Button<'static, $Pin>> = MaybeUninit::uninit();
-        &mut BUF
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<Button<'static, $Pin, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
     };};
 }
 
-pub struct ButtonComponent<IP: 'static + gpio::InterruptPin<'static>> {
+pub struct ButtonComponent<
+    IP: 'static + gpio::InterruptPin<'static>,
+    A: 'static + time::Alarm<'static>,
+> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
     button_pins: &'static [(
         &'static gpio::InterruptValueWrapper<'static, IP>,
         gpio::ActivationMode,
         gpio::FloatingState,
+        ButtonState,
     )],
 }
 
-impl<IP: 'static + gpio::InterruptPin<'static>> ButtonComponent<IP> {
+impl<IP: 'static + gpio::InterruptPin<'static>, A: 'static + time::Alarm<'static>>
+    ButtonComponent<IP, A>
+{
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
         button_pins: &'static [(
             &'static gpio::InterruptValueWrapper<'static, IP>,
             gpio::ActivationMode,
             gpio::FloatingState,
+            ButtonState,
         )],
     ) -> Self {
         Self {
             board_kernel: board_kernel,
             driver_num,
+            alarm_mux,
             button_pins,
         }
     }
 }
 
-impl<IP: 'static + gpio::InterruptPin<'static>> Component for ButtonComponent<IP> {
-    type StaticInput = &'static mut MaybeUninit<Button<'static, IP>>;
-    type Output = &'static Button<'static, IP>;
+impl<IP: 'static + gpio::InterruptPin<'static>, A: 'static + time::Alarm<'static>> Component
+    for ButtonComponent<IP, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Button<'static, IP, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Button<'static, IP, VirtualMuxAlarm<'static, A>>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
         let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        virtual_alarm.setup();
+
         let button = static_init_half!(
-            static_buffer,
-            capsules::button::Button<'static, IP>,
+            static_buffer.1,
+            capsules::button::Button<'static, IP, VirtualMuxAlarm<'static, A>>,
             capsules::button::Button::new(
                 self.button_pins,
+                virtual_alarm,
                 self.board_kernel.create_grant(self.driver_num, &grant_cap)
             )
         );
-        for (pin, _, _) in self.button_pins.iter() {
+        virtual_alarm.set_alarm_client(button);
+        for (pin, _, _, _) in self.button_pins.iter() {
             pin.set_client(button);
         }
 
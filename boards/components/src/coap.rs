@@ -0,0 +1,205 @@
+//! Component to initialize the userland CoAP driver.
+//!
+//! This provides one Component, CoapComponent. This component binds the CoAP
+//! port in the UDP port table and initializes a userspace driver that lets
+//! apps serve and request CoAP resources. Like `UDPDriverComponent`, it works
+//! over 6LoWPAN (`UDPMuxComponent`), Ethernet (`UDPMuxEthernetComponent`) or a
+//! serial line (`UDPMuxSerialComponent`); the helper macro takes the alarm
+//! type, the Ethernet device and alarm types, or `serial` and the alarm type,
+//! respectively.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let coap_driver = CoapComponent::new(
+//!        board_kernel,
+//!        capsules::net::coap::DRIVER_NUM,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        mux_alarm,
+//!     )
+//!     .finalize(components::coap_component_helper!(nrf52840::rtc::Rtc));
+//! ```
+
+use capsules;
+use capsules::net::coap::driver::{CoapDriver, COAP_PORT};
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_recv::UDPReceiver;
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::hil::time::{self, Alarm};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::{create_capability, static_init, static_init_half};
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+static mut COAP_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! coap_component_helper {
+    (serial, $A:ty $(,)?) => {{
+        use capsules::net::coap::driver::CoapDriver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<'static, capsules::net::ipv6::ipv6_serial::IP6SerialStruct<'static>>,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+    (ethernet: $E:ty, $A:ty $(,)?) => {{
+        use capsules::net::coap::driver::CoapDriver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct<
+                    'static,
+                    $E,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+    ($A:ty $(,)?) => {{
+        use capsules::net::coap::driver::CoapDriver;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::rpl::Rpl<
+                    'static,
+                    capsules::net::ipv6::neighbor_discovery::NeighborDiscovery<
+                        'static,
+                        capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                            'static,
+                            VirtualMuxAlarm<'static, $A>,
+                        >,
+                        VirtualMuxAlarm<'static, $A>,
+                    >,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct CoapComponent<S: IP6Sender<'static> + 'static, A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    udp_send_mux: &'static MuxUdpSender<'static, S>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<S: IP6Sender<'static>, A: 'static + time::Alarm<'static>> CoapComponent<S, A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        udp_send_mux: &'static MuxUdpSender<'static, S>,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+        }
+    }
+}
+
+impl<S: IP6Sender<'static>, A: 'static + time::Alarm<'static>> Component for CoapComponent<S, A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<UDPSendStruct<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<CoapDriver<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static CoapDriver<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let udp_send = static_init_half!(
+            static_buffer.0,
+            UDPSendStruct<'static, S>,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+
+        let coap_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        coap_alarm.setup();
+
+        let coap_driver = static_init_half!(
+            static_buffer.2,
+            CoapDriver<'static, VirtualMuxAlarm<'static, A>>,
+            CoapDriver::new(
+                udp_send,
+                coap_alarm,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+                LeasableMutableBuffer::new(&mut COAP_BUF),
+                net_cap,
+            )
+        );
+        udp_send.set_client(coap_driver);
+        udp_recv.set_client(coap_driver);
+        coap_alarm.set_alarm_client(coap_driver);
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .unwrap_or_else(|_| panic!("CoAP: no free UDP socket"));
+        let (send_bind, recv_bind) = self
+            .port_table
+            .bind(socket, COAP_PORT, net_cap)
+            .unwrap_or_else(|_| panic!("CoAP: failed to bind port {}", COAP_PORT));
+        udp_send.set_binding(send_bind);
+        udp_recv.set_binding(recv_bind);
+        self.udp_recv_mux.add_client(udp_recv);
+
+        coap_driver
+    }
+}
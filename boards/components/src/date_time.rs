@@ -0,0 +1,150 @@
+//! Components for calendar real-time clocks.
+//!
+//! This provides two Components:
+//!
+//! * `DateTimeComponent` exposes a `hil::date_time` clock to userspace.
+//! * `SoftDateTimeComponent` provides a software clock over a virtual alarm,
+//!   for chips without a calendar real-time clock.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let date_time = components::date_time::DateTimeComponent::new(
+//!     board_kernel,
+//!     capsules::date_time::DRIVER_NUM,
+//!     &peripherals.rtc,
+//! )
+//! .finalize(components::date_time_component_helper!(sifive::rtc::Rtc<'static>));
+//!
+//! let soft_date_time = components::date_time::SoftDateTimeComponent::new(
+//!     mux_alarm,
+//!     dynamic_deferred_caller,
+//! )
+//! .finalize(components::soft_date_time_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::date_time::DateTimeDriver;
+use capsules::soft_date_time::SoftDateTime;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::dynamic_deferred_call::DynamicDeferredCall;
+use kernel::hil::date_time::{DateTime, DateTimeAlarm};
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! date_time_component_helper {
+    ($D:ty $(,)?) => {{
+        use capsules::date_time::DateTimeDriver;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<DateTimeDriver<'static, $D>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! soft_date_time_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::soft_date_time::SoftDateTime;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<SoftDateTime<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct DateTimeComponent<D: 'static + DateTime<'static> + DateTimeAlarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    date_time: &'static D,
+}
+
+impl<D: 'static + DateTime<'static> + DateTimeAlarm<'static>> DateTimeComponent<D> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        date_time: &'static D,
+    ) -> DateTimeComponent<D> {
+        DateTimeComponent {
+            board_kernel,
+            driver_num,
+            date_time,
+        }
+    }
+}
+
+impl<D: 'static + DateTime<'static> + DateTimeAlarm<'static>> Component for DateTimeComponent<D> {
+    type StaticInput = &'static mut MaybeUninit<DateTimeDriver<'static, D>>;
+    type Output = &'static DateTimeDriver<'static, D>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let date_time_driver = static_init_half!(
+            static_buffer,
+            DateTimeDriver<'static, D>,
+            DateTimeDriver::new(
+                self.date_time,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap)
+            )
+        );
+        DateTime::set_client(self.date_time, date_time_driver);
+        DateTimeAlarm::set_alarm_client(self.date_time, date_time_driver);
+
+        date_time_driver
+    }
+}
+
+pub struct SoftDateTimeComponent<A: 'static + time::Alarm<'static>> {
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    deferred_caller: &'static DynamicDeferredCall,
+}
+
+impl<A: 'static + time::Alarm<'static>> SoftDateTimeComponent<A> {
+    pub fn new(
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        deferred_caller: &'static DynamicDeferredCall,
+    ) -> SoftDateTimeComponent<A> {
+        SoftDateTimeComponent {
+            alarm_mux,
+            deferred_caller,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for SoftDateTimeComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<SoftDateTime<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static SoftDateTime<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let date_time_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        date_time_alarm.setup();
+
+        let soft_date_time = static_init_half!(
+            static_buffer.1,
+            SoftDateTime<'static, VirtualMuxAlarm<'static, A>>,
+            SoftDateTime::new(date_time_alarm, self.deferred_caller)
+        );
+        date_time_alarm.set_alarm_client(soft_date_time);
+        soft_date_time.initialize_callback_handle(
+            self.deferred_caller.register(soft_date_time).unwrap(), // Unwrap fail = no deferred call slot available for the date and time
+        );
+        soft_date_time.start();
+
+        soft_date_time
+    }
+}
@@ -1,8 +1,13 @@
 //! Component for IEEE 802.15.4 radio syscall interface.
 //!
-//! This provides one Component, `Ieee802154Component`, which implements a
+//! This provides three Components. `Ieee802154Component` implements a
 //! userspace syscall interface to a full 802.15.4 stack with a
 //! always-on MAC implementation, as well as multiplexed access to that MAC implementation.
+//! `Ieee802154CsmaComponent` builds the same stack on a MAC that performs
+//! CSMA-CA, acknowledgements and retransmissions in software, for radios that
+//! do not do so in hardware.
+//! `Ieee802154MlmeComponent` adds the MAC management services, channel scans,
+//! coordinator beacons and association, to that syscall interface.
 //!
 //! Usage
 //! -----
@@ -19,17 +24,52 @@
 //!     nrf52::ieee802154_radio::Radio,
 //!     nrf52::aes::AesECB<'static>
 //! ));
+//!
+//! let (radio, mux_mac) = components::ieee802154::Ieee802154CsmaComponent::new(
+//!     board_kernel,
+//!     capsules::ieee802154::DRIVER_NUM,
+//!     rf233,
+//!     aes_mux,
+//!     PAN_ID,
+//!     SRC_MAC,
+//!     deferred_caller,
+//!     mux_alarm,
+//!     random,
+//! )
+//! .finalize(components::ieee802154_csma_component_helper!(
+//!     capsules::rf233::RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
+//!     sam4l::aes::Aes<'static>,
+//!     sam4l::ast::Ast<'static>
+//! ));
+//!
+//! let mlme = components::ieee802154::Ieee802154MlmeComponent::new(
+//!     radio,
+//!     mux_mac,
+//!     &nrf52::ieee802154_radio::RADIO,
+//!     mux_alarm,
+//! )
+//! .finalize(components::ieee802154_mlme_component_helper!(
+//!     nrf52::ieee802154_radio::Radio,
+//!     nrf52::rtc::Rtc<'static>
+//! ));
 //! ```
 
 use capsules;
+use capsules::ieee802154::csma::CsmaMac;
 use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::framer::Framer;
 use capsules::ieee802154::mac::{AwakeMac, Mac};
+use capsules::ieee802154::mlme::{MacManagement, Mlme};
+use capsules::virtual_aes_ccm::{MuxAES128CCM, VirtualAES128CCM};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use core::mem::MaybeUninit;
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::dynamic_deferred_call::DynamicDeferredCall;
 use kernel::hil::radio;
+use kernel::hil::rng::Random;
 use kernel::hil::symmetric_encryption::{self, AES128Ctr, AES128, AES128CBC, AES128CCM, AES128ECB};
+use kernel::hil::time::{self, Alarm};
 use kernel::{create_capability, static_init, static_init_half};
 
 // Setup static space for the objects.
@@ -54,6 +94,43 @@ macro_rules! ieee802154_component_helper {
     };};
 }
 
+#[macro_export]
+macro_rules! ieee802154_csma_component_helper {
+    ($R:ty, $A:ty, $T:ty $(,)?) => {{
+        use capsules::ieee802154::csma::CsmaMac;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+
+        static mut BUF1: MaybeUninit<capsules::virtual_aes_ccm::VirtualAES128CCM<'static, $A>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<VirtualMuxAlarm<'static, $T>> = MaybeUninit::uninit();
+        static mut BUF3: MaybeUninit<CsmaMac<'static, $R, VirtualMuxAlarm<'static, $T>>> =
+            MaybeUninit::uninit();
+        static mut BUF4: MaybeUninit<
+            capsules::ieee802154::framer::Framer<
+                'static,
+                CsmaMac<'static, $R, VirtualMuxAlarm<'static, $T>>,
+                capsules::virtual_aes_ccm::VirtualAES128CCM<'static, $A>,
+            >,
+        > = MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4)
+    };};
+}
+
+#[macro_export]
+macro_rules! ieee802154_mlme_component_helper {
+    ($R:ty, $A:ty $(,)?) => {{
+        use capsules::ieee802154::mlme::MacManagement;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<MacManagement<'static, $R, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
 pub struct Ieee802154Component<
     R: 'static + kernel::hil::radio::Radio,
     A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
@@ -125,15 +202,7 @@ impl<
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
-        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
-
-        let aes_ccm = static_init_half!(
-            static_buffer.0,
-            capsules::virtual_aes_ccm::VirtualAES128CCM<'static, A>,
-            capsules::virtual_aes_ccm::VirtualAES128CCM::new(self.aes_mux, &mut CRYPT_BUF)
-        );
-
-        aes_ccm.setup();
+        let aes_ccm = aes_ccm(self.aes_mux, static_buffer.0);
 
         // Keeps the radio on permanently; pass-through layer
         let awake_mac = static_init_half!(
@@ -144,53 +213,276 @@ impl<
         self.radio.set_transmit_client(awake_mac);
         self.radio.set_receive_client(awake_mac, &mut RADIO_RX_BUF);
 
-        let mac_device = static_init_half!(
+        radio_driver(
+            awake_mac,
+            aes_ccm,
             static_buffer.2,
-            capsules::ieee802154::framer::Framer<
+            self.board_kernel,
+            self.driver_num,
+            self.pan_id,
+            self.short_addr,
+            self.deferred_caller,
+        )
+    }
+}
+
+pub struct Ieee802154CsmaComponent<
+    R: 'static + kernel::hil::radio::Radio,
+    A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+    T: 'static + time::Alarm<'static>,
+> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    radio: &'static R,
+    aes_mux: &'static capsules::virtual_aes_ccm::MuxAES128CCM<'static, A>,
+    pan_id: capsules::net::ieee802154::PanID,
+    short_addr: u16,
+    deferred_caller: &'static DynamicDeferredCall,
+    alarm_mux: &'static MuxAlarm<'static, T>,
+    random: &'static dyn Random<'static>,
+}
+
+impl<
+        R: 'static + kernel::hil::radio::Radio,
+        A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+        T: 'static + time::Alarm<'static>,
+    > Ieee802154CsmaComponent<R, A, T>
+{
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        radio: &'static R,
+        aes_mux: &'static capsules::virtual_aes_ccm::MuxAES128CCM<'static, A>,
+        pan_id: capsules::net::ieee802154::PanID,
+        short_addr: u16,
+        deferred_caller: &'static DynamicDeferredCall,
+        alarm_mux: &'static MuxAlarm<'static, T>,
+        random: &'static dyn Random<'static>,
+    ) -> Self {
+        Self {
+            board_kernel,
+            driver_num,
+            radio,
+            aes_mux,
+            pan_id,
+            short_addr,
+            deferred_caller,
+            alarm_mux,
+            random,
+        }
+    }
+}
+
+// Acknowledgements sent by the CSMA MAC are built in this buffer.
+static mut ACK_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+
+impl<
+        R: 'static + kernel::hil::radio::Radio,
+        A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+        T: 'static + time::Alarm<'static>,
+    > Component for Ieee802154CsmaComponent<R, A, T>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualAES128CCM<'static, A>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, T>>,
+        &'static mut MaybeUninit<CsmaMac<'static, R, VirtualMuxAlarm<'static, T>>>,
+        &'static mut MaybeUninit<
+            Framer<
                 'static,
-                AwakeMac<'static, R>,
-                capsules::virtual_aes_ccm::VirtualAES128CCM<'static, A>,
+                CsmaMac<'static, R, VirtualMuxAlarm<'static, T>>,
+                VirtualAES128CCM<'static, A>,
             >,
-            capsules::ieee802154::framer::Framer::new(awake_mac, aes_ccm)
+        >,
+    );
+    type Output = (
+        &'static capsules::ieee802154::RadioDriver<'static>,
+        &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let aes_ccm = aes_ccm(self.aes_mux, static_buffer.0);
+
+        let csma_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, T>,
+            VirtualMuxAlarm::new(self.alarm_mux)
         );
-        AES128CCM::set_client(aes_ccm, mac_device);
-        awake_mac.set_transmit_client(mac_device);
-        awake_mac.set_receive_client(mac_device);
-        awake_mac.set_config_client(mac_device);
-
-        let mux_mac = static_init!(
-            capsules::ieee802154::virtual_mac::MuxMac<'static>,
-            capsules::ieee802154::virtual_mac::MuxMac::new(mac_device)
+        csma_alarm.setup();
+
+        // Backs off, acknowledges and retransmits in software. The MAC takes
+        // the radio's energy detect client for clear channel assessment.
+        let csma_mac = static_init_half!(
+            static_buffer.2,
+            CsmaMac<'static, R, VirtualMuxAlarm<'static, T>>,
+            CsmaMac::new(self.radio, csma_alarm, self.random)
         );
-        mac_device.set_transmit_client(mux_mac);
-        mac_device.set_receive_client(mux_mac);
+        csma_alarm.set_alarm_client(csma_mac);
+        self.radio.set_transmit_client(csma_mac);
+        self.radio.set_receive_client(csma_mac, &mut RADIO_RX_BUF);
+        self.radio.set_energy_detect_client(csma_mac);
+        let _ = csma_mac.initialize(&mut ACK_BUF);
 
-        let userspace_mac = static_init!(
+        radio_driver(
+            csma_mac,
+            aes_ccm,
+            static_buffer.3,
+            self.board_kernel,
+            self.driver_num,
+            self.pan_id,
+            self.short_addr,
+            self.deferred_caller,
+        )
+    }
+}
+
+/// Set up AES-CCM* for the framer on the AES virtualizer.
+unsafe fn aes_ccm<A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB>(
+    aes_mux: &'static MuxAES128CCM<'static, A>,
+    buf: &'static mut MaybeUninit<VirtualAES128CCM<'static, A>>,
+) -> &'static VirtualAES128CCM<'static, A> {
+    let aes_ccm = static_init_half!(
+        buf,
+        VirtualAES128CCM<'static, A>,
+        VirtualAES128CCM::new(aes_mux, &mut CRYPT_BUF)
+    );
+    aes_ccm.setup();
+    aes_ccm
+}
+
+/// Build the framer, the MAC virtualizer and the syscall driver on top of
+/// `mac`, which must already be set up as the client of the radio.
+unsafe fn radio_driver<
+    M: 'static + Mac,
+    A: 'static + AES128<'static> + AES128Ctr + AES128CBC + AES128ECB,
+>(
+    mac: &'static M,
+    aes_ccm: &'static VirtualAES128CCM<'static, A>,
+    framer_buf: &'static mut MaybeUninit<Framer<'static, M, VirtualAES128CCM<'static, A>>>,
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    pan_id: capsules::net::ieee802154::PanID,
+    short_addr: u16,
+    deferred_caller: &'static DynamicDeferredCall,
+) -> (
+    &'static capsules::ieee802154::RadioDriver<'static>,
+    &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+) {
+    let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+    let mac_device = static_init_half!(
+        framer_buf,
+        Framer<'static, M, VirtualAES128CCM<'static, A>>,
+        Framer::new(mac, aes_ccm)
+    );
+    AES128CCM::set_client(aes_ccm, mac_device);
+    mac.set_transmit_client(mac_device);
+    mac.set_receive_client(mac_device);
+    mac.set_config_client(mac_device);
+
+    let mux_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        capsules::ieee802154::virtual_mac::MuxMac::new(mac_device)
+    );
+    mac_device.set_transmit_client(mux_mac);
+    mac_device.set_receive_client(mux_mac);
+
+    let userspace_mac = static_init!(
+        capsules::ieee802154::virtual_mac::MacUser<'static>,
+        capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
+    );
+    mux_mac.add_user(userspace_mac);
+
+    let radio_driver = static_init!(
+        capsules::ieee802154::RadioDriver<'static>,
+        capsules::ieee802154::RadioDriver::new(
+            userspace_mac,
+            board_kernel.create_grant(driver_num, &grant_cap),
+            &mut RADIO_BUF,
+            deferred_caller,
+        )
+    );
+
+    mac_device.set_key_procedure(radio_driver);
+    mac_device.set_device_procedure(radio_driver);
+    userspace_mac.set_transmit_client(radio_driver);
+    userspace_mac.set_receive_client(radio_driver);
+    userspace_mac.set_pan(pan_id);
+    userspace_mac.set_address(short_addr);
+    radio_driver.initialize_callback_handle(
+        deferred_caller.register(radio_driver).unwrap(), // Unwrap fail = no deferred call slot available for ieee802154 driver
+    );
+
+    (radio_driver, mux_mac)
+}
+
+pub struct Ieee802154MlmeComponent<
+    R: 'static + kernel::hil::radio::Radio,
+    A: 'static + time::Alarm<'static>,
+> {
+    radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
+    mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+    radio: &'static R,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<R: 'static + kernel::hil::radio::Radio, A: 'static + time::Alarm<'static>>
+    Ieee802154MlmeComponent<R, A>
+{
+    pub fn new(
+        radio_driver: &'static capsules::ieee802154::RadioDriver<'static>,
+        mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
+        radio: &'static R,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            radio_driver,
+            mux_mac,
+            radio,
+            alarm_mux,
+        }
+    }
+}
+
+impl<R: 'static + kernel::hil::radio::Radio, A: 'static + time::Alarm<'static>> Component
+    for Ieee802154MlmeComponent<R, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<MacManagement<'static, R, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static MacManagement<'static, R, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let mlme_mac = static_init!(
             capsules::ieee802154::virtual_mac::MacUser<'static>,
-            capsules::ieee802154::virtual_mac::MacUser::new(mux_mac)
+            capsules::ieee802154::virtual_mac::MacUser::new(self.mux_mac)
         );
-        mux_mac.add_user(userspace_mac);
-
-        let radio_driver = static_init!(
-            capsules::ieee802154::RadioDriver<'static>,
-            capsules::ieee802154::RadioDriver::new(
-                userspace_mac,
-                self.board_kernel.create_grant(self.driver_num, &grant_cap),
-                &mut RADIO_BUF,
-                self.deferred_caller,
-            )
+        self.mux_mac.add_user(mlme_mac);
+
+        let mlme_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
         );
+        mlme_alarm.setup();
 
-        mac_device.set_key_procedure(radio_driver);
-        mac_device.set_device_procedure(radio_driver);
-        userspace_mac.set_transmit_client(radio_driver);
-        userspace_mac.set_receive_client(radio_driver);
-        userspace_mac.set_pan(self.pan_id);
-        userspace_mac.set_address(self.short_addr);
-        radio_driver.initialize_callback_handle(
-            self.deferred_caller.register(radio_driver).unwrap(), // Unwrap fail = no deferred call slot available for ieee802154 driver
+        let mlme = static_init_half!(
+            static_buffer.1,
+            MacManagement<'static, R, VirtualMuxAlarm<'static, A>>,
+            MacManagement::new(
+                mlme_mac,
+                self.radio,
+                mlme_alarm,
+                &mut capsules::ieee802154::mlme::BUF
+            )
         );
+        mlme_mac.set_transmit_client(mlme);
+        mlme_mac.set_receive_client(mlme);
+        self.radio.set_energy_detect_client(mlme);
+        mlme_alarm.set_alarm_client(mlme);
+        self.radio_driver.set_mlme(mlme);
+        mlme.set_client(self.radio_driver);
 
-        (radio_driver, mux_mac)
+        mlme
     }
 }
@@ -0,0 +1,56 @@
+//! Component for the message-queue based IPC driver.
+//!
+//! This provides one Component, IpcMessageComponent, which implements a
+//! userspace syscall interface for exchanging request and response messages
+//! between processes.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let ipc_message = components::ipc_message::IpcMessageComponent::new(
+//!     board_kernel,
+//!     capsules::ipc_message::DRIVER_NUM,
+//! )
+//! .finalize(());
+//! ```
+
+use capsules::ipc_message::IpcMessage;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::static_init;
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct IpcMessageComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl IpcMessageComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel, driver_num: usize) -> IpcMessageComponent {
+        IpcMessageComponent {
+            board_kernel,
+            driver_num,
+        }
+    }
+}
+
+impl Component for IpcMessageComponent {
+    type StaticInput = ();
+    type Output = &'static IpcMessage<Capability>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        static_init!(
+            IpcMessage<Capability>,
+            IpcMessage::new(
+                self.board_kernel,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+                Capability,
+            )
+        )
+    }
+}
@@ -6,15 +6,18 @@ pub mod air_quality;
 pub mod alarm;
 pub mod analog_comparator;
 pub mod app_flash_driver;
+pub mod ble_gatt;
 pub mod bme280;
 pub mod bmp280;
 pub mod bus;
 pub mod button;
 pub mod ccs811;
 pub mod cdc;
+pub mod coap;
 pub mod console;
 pub mod crc;
 pub mod ctap;
+pub mod date_time;
 pub mod debug_queue;
 pub mod debug_writer;
 pub mod digest;
@@ -28,6 +31,7 @@ pub mod hts221;
 pub mod humidity;
 pub mod i2c;
 pub mod ieee802154;
+pub mod ipc_message;
 pub mod isl29035;
 pub mod kv_system;
 pub mod l3gd20;
@@ -44,15 +48,21 @@ pub mod ninedof;
 pub mod nonvolatile_storage;
 pub mod nrf51822;
 pub mod panic_button;
+pub mod pressure;
 pub mod process_console;
+pub mod process_info;
 pub mod process_printer;
+pub mod pwm;
 pub mod rng;
 pub mod sched;
 pub mod screen;
 pub mod segger_rtt;
+pub mod sensor_sampling;
 pub mod sha;
+pub mod shared_memory;
 pub mod sht3x;
 pub mod si7021;
+pub mod sntp;
 pub mod sound_pressure;
 pub mod spi;
 pub mod st77xx;
@@ -65,3 +75,6 @@ pub mod tickv;
 pub mod touch;
 pub mod udp_driver;
 pub mod udp_mux;
+pub mod udp_mux_ethernet;
+pub mod udp_mux_serial;
+pub mod wall_clock;
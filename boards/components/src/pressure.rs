@@ -0,0 +1,55 @@
+//! Component for any barometric pressure sensor.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let pressure =
+//!     PressureComponent::new(board_kernel, capsules::pressure::DRIVER_NUM, bme280).finalize(());
+//! ```
+
+use capsules::pressure::PressureSensor;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil;
+use kernel::static_init;
+
+pub struct PressureComponent<T: 'static + hil::sensors::PressureDriver<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    pressure_sensor: &'static T,
+}
+
+impl<T: 'static + hil::sensors::PressureDriver<'static>> PressureComponent<T> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        pressure_sensor: &'static T,
+    ) -> PressureComponent<T> {
+        PressureComponent {
+            board_kernel,
+            driver_num,
+            pressure_sensor,
+        }
+    }
+}
+
+impl<T: 'static + hil::sensors::PressureDriver<'static>> Component for PressureComponent<T> {
+    type StaticInput = ();
+    type Output = &'static PressureSensor<'static>;
+
+    unsafe fn finalize(self, _s: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let pressure = static_init!(
+            PressureSensor<'static>,
+            PressureSensor::new(
+                self.pressure_sensor,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap)
+            )
+        );
+
+        hil::sensors::PressureDriver::set_client(self.pressure_sensor, pressure);
+        pressure
+    }
+}
@@ -0,0 +1,56 @@
+//! Component for the ProcessInfo driver.
+//!
+//! This provides one Component, ProcessInfoComponent, which implements a
+//! userspace syscall interface for inspecting the state and resource usage of
+//! the processes running on the board.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let process_info = components::process_info::ProcessInfoComponent::new(
+//!     board_kernel,
+//!     capsules::process_info::DRIVER_NUM,
+//! )
+//! .finalize(());
+//! ```
+
+use capsules::process_info::ProcessInfo;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::static_init;
+
+pub struct Capability;
+unsafe impl capabilities::ProcessManagementCapability for Capability {}
+
+pub struct ProcessInfoComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl ProcessInfoComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel, driver_num: usize) -> ProcessInfoComponent {
+        ProcessInfoComponent {
+            board_kernel,
+            driver_num,
+        }
+    }
+}
+
+impl Component for ProcessInfoComponent {
+    type StaticInput = ();
+    type Output = &'static ProcessInfo<Capability>;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        static_init!(
+            ProcessInfo<Capability>,
+            ProcessInfo::new(
+                self.board_kernel,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap),
+                Capability,
+            )
+        )
+    }
+}
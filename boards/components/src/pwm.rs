@@ -0,0 +1,147 @@
+//! Components for PWM outputs and the userspace PWM driver.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let mux_pwm = components::pwm::PwmMuxComponent::new(&base_peripherals.pwm0)
+//!     .finalize(components::pwm_mux_component_helper!(nrf52840::pwm::Pwm));
+//! let pwm_a0 = components::pwm::PwmPinUserComponent::new(
+//!     mux_pwm,
+//!     nrf52840::pinmux::Pinmux::new(PWM_A0_PIN as u32),
+//! )
+//! .finalize(components::pwm_pin_user_component_helper!(nrf52840::pwm::Pwm));
+//! let pwm = components::pwm::PwmDriverComponent::new(board_kernel, capsules::pwm::DRIVER_NUM)
+//!     .finalize(components::pwm_driver_component_helper!(pwm_a0));
+//! ```
+
+use capsules::pwm::Pwm;
+use capsules::virtual_pwm::{MuxPwm, PwmPinUser};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::pwm;
+use kernel::static_init_half;
+
+#[macro_export]
+macro_rules! pwm_mux_component_helper {
+    ($P:ty $(,)?) => {{
+        use capsules::virtual_pwm::MuxPwm;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<MuxPwm<'static, $P>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! pwm_pin_user_component_helper {
+    ($P:ty $(,)?) => {{
+        use capsules::virtual_pwm::PwmPinUser;
+        use core::mem::MaybeUninit;
+        static mut BUF: MaybeUninit<PwmPinUser<'static, $P>> = MaybeUninit::uninit();
+        &mut BUF
+    };};
+}
+
+#[macro_export]
+macro_rules! pwm_driver_component_helper {
+    ($($P:expr),+ $(,)?) => {{
+        use capsules::pwm::Pwm;
+        use core::mem::MaybeUninit;
+        use kernel::count_expressions;
+        use kernel::static_init;
+        const NUM_PINS: usize = count_expressions!($($P),+);
+
+        let pins = static_init!(
+            [&'static dyn kernel::hil::pwm::PwmPin; NUM_PINS],
+            [
+                $($P,)*
+            ]
+        );
+        static mut BUF: MaybeUninit<Pwm<'static, NUM_PINS>> = MaybeUninit::uninit();
+        (&mut BUF, pins)
+    };};
+}
+
+pub struct PwmMuxComponent<P: 'static + pwm::Pwm> {
+    pwm: &'static P,
+}
+
+impl<P: 'static + pwm::Pwm> PwmMuxComponent<P> {
+    pub fn new(pwm: &'static P) -> Self {
+        PwmMuxComponent { pwm: pwm }
+    }
+}
+
+impl<P: 'static + pwm::Pwm> Component for PwmMuxComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<MuxPwm<'static, P>>;
+    type Output = &'static MuxPwm<'static, P>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        static_init_half!(static_buffer, MuxPwm<'static, P>, MuxPwm::new(self.pwm))
+    }
+}
+
+pub struct PwmPinUserComponent<P: 'static + pwm::Pwm> {
+    pwm_mux: &'static MuxPwm<'static, P>,
+    pin: P::Pin,
+}
+
+impl<P: 'static + pwm::Pwm> PwmPinUserComponent<P> {
+    pub fn new(mux: &'static MuxPwm<'static, P>, pin: P::Pin) -> Self {
+        PwmPinUserComponent {
+            pwm_mux: mux,
+            pin: pin,
+        }
+    }
+}
+
+impl<P: 'static + pwm::Pwm> Component for PwmPinUserComponent<P> {
+    type StaticInput = &'static mut MaybeUninit<PwmPinUser<'static, P>>;
+    type Output = &'static PwmPinUser<'static, P>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let pwm_pin = static_init_half!(
+            static_buffer,
+            PwmPinUser<'static, P>,
+            PwmPinUser::new(self.pwm_mux, self.pin)
+        );
+
+        pwm_pin.add_to_mux();
+
+        pwm_pin
+    }
+}
+
+pub struct PwmDriverComponent<const NUM_PINS: usize> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl<const NUM_PINS: usize> PwmDriverComponent<NUM_PINS> {
+    pub fn new(board_kernel: &'static kernel::Kernel, driver_num: usize) -> Self {
+        PwmDriverComponent {
+            board_kernel: board_kernel,
+            driver_num: driver_num,
+        }
+    }
+}
+
+impl<const NUM_PINS: usize> Component for PwmDriverComponent<NUM_PINS> {
+    type StaticInput = (
+        &'static mut MaybeUninit<Pwm<'static, NUM_PINS>>,
+        &'static [&'static dyn kernel::hil::pwm::PwmPin; NUM_PINS],
+    );
+    type Output = &'static Pwm<'static, NUM_PINS>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_pwm = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        static_init_half!(
+            static_buffer.0,
+            Pwm<'static, NUM_PINS>,
+            Pwm::new(static_buffer.1, grant_pwm)
+        )
+    }
+}
@@ -0,0 +1,110 @@
+//! Component for sampling sensors periodically on behalf of processes.
+//!
+//! The sensors are given as `SampledSensor` adapters, which the board
+//! creates and makes the clients of their sensors.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sampled_temperature = static_init!(
+//!     capsules::sensor_sampling::SampledTemperature<'static>,
+//!     capsules::sensor_sampling::SampledTemperature::new(lsm303agr)
+//! );
+//! kernel::hil::sensors::TemperatureDriver::set_client(lsm303agr, sampled_temperature);
+//!
+//! let sensor_sampling = components::sensor_sampling::SensorSamplingComponent::new(
+//!     board_kernel,
+//!     capsules::sensor_sampling::DRIVER_NUM,
+//!     mux_alarm,
+//! )
+//! .finalize(components::sensor_sampling_component_helper!(
+//!     nrf52833::rtc::Rtc<'static>,
+//!     sampled_temperature
+//! ));
+//! ```
+
+use capsules::sensor_sampling::{SampledSensor, SensorSampling};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+#[macro_export]
+macro_rules! sensor_sampling_component_helper {
+    ($A:ty, $($S:expr),+ $(,)?) => {{
+        use capsules::sensor_sampling::{SampledSensor, SensorSampling};
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        use kernel::count_expressions;
+        use kernel::static_init;
+        const NUM_SENSORS: usize = count_expressions!($($S),+);
+
+        let sensors = static_init!(
+            [&'static dyn SampledSensor<'static>; NUM_SENSORS],
+            [
+                $($S,)*
+            ]
+        );
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<SensorSampling<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2, sensors)
+    };};
+}
+
+pub struct SensorSamplingComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<A: 'static + time::Alarm<'static>> SensorSamplingComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> SensorSamplingComponent<A> {
+        SensorSamplingComponent {
+            board_kernel,
+            driver_num,
+            alarm_mux,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for SensorSamplingComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<SensorSampling<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static [&'static dyn SampledSensor<'static>],
+    );
+    type Output = &'static SensorSampling<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+        let grant_sensor_sampling = self.board_kernel.create_grant(self.driver_num, &grant_cap);
+
+        let virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        virtual_alarm.setup();
+
+        let sensor_sampling = static_init_half!(
+            static_buffer.1,
+            SensorSampling<'static, VirtualMuxAlarm<'static, A>>,
+            SensorSampling::new(static_buffer.2, virtual_alarm, grant_sensor_sampling)
+        );
+
+        virtual_alarm.set_alarm_client(sensor_sampling);
+        for sensor in static_buffer.2 {
+            sensor.set_client(sensor_sampling);
+        }
+
+        sensor_sampling
+    }
+}
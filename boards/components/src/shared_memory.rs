@@ -0,0 +1,51 @@
+//! Component for the MPU-enforced shared memory driver.
+//!
+//! This provides one Component, SharedMemoryComponent, which implements a
+//! userspace syscall interface for sharing memory between processes. Boards
+//! that include it must also return it from
+//! `KernelResources::context_switch_callback()` so that revoked regions are
+//! removed from a process's MPU configuration before it runs.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let shared_memory = components::shared_memory::SharedMemoryComponent::new(
+//!     board_kernel,
+//!     kernel::shared_memory::DRIVER_NUM,
+//! )
+//! .finalize(());
+//! ```
+
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::shared_memory::SharedMemory;
+use kernel::static_init;
+
+pub struct SharedMemoryComponent {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+}
+
+impl SharedMemoryComponent {
+    pub fn new(board_kernel: &'static kernel::Kernel, driver_num: usize) -> SharedMemoryComponent {
+        SharedMemoryComponent {
+            board_kernel,
+            driver_num,
+        }
+    }
+}
+
+impl Component for SharedMemoryComponent {
+    type StaticInput = ();
+    type Output = &'static SharedMemory;
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        static_init!(
+            SharedMemory,
+            SharedMemory::new(self.board_kernel, self.driver_num, &grant_cap)
+        )
+    }
+}
@@ -0,0 +1,203 @@
+//! Component for the SNTP client.
+//!
+//! This provides one Component, `SntpComponent`, which binds the NTP port in
+//! the UDP port table and creates an SNTP client that asks `server` for the
+//! time. It is normally used as the time source of a `WallClockComponent`.
+//! Like `CoapComponent`, it works over 6LoWPAN, Ethernet or a serial line,
+//! and the helper macro takes the alarm type, the Ethernet device and alarm
+//! types, or `serial` and the alarm type, respectively.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let sntp = components::sntp::SntpComponent::new(
+//!     udp_send_mux,
+//!     udp_recv_mux,
+//!     udp_port_table,
+//!     mux_alarm,
+//!     NTP_SERVER,
+//! )
+//! .finalize(components::sntp_component_helper!(sam4l::ast::Ast));
+//! ```
+
+use capsules;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::network_capabilities::{
+    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::sntp::{Sntp, NTP_PACKET_LEN, NTP_PORT};
+use capsules::net::udp::udp_port_table::UdpPortManager;
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_recv::UDPReceiver;
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::mem::MaybeUninit;
+use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::component::Component;
+use kernel::hil::time::{self, Alarm};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::{create_capability, static_init, static_init_half};
+
+static mut SNTP_BUF: [u8; NTP_PACKET_LEN] = [0; NTP_PACKET_LEN];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! sntp_component_helper {
+    (serial, $A:ty $(,)?) => {{
+        use capsules::net::sntp::Sntp;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<'static, capsules::net::ipv6::ipv6_serial::IP6SerialStruct<'static>>,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<Sntp<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+    (ethernet: $E:ty, $A:ty $(,)?) => {{
+        use capsules::net::sntp::Sntp;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct<
+                    'static,
+                    $E,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<Sntp<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+    ($A:ty $(,)?) => {{
+        use capsules::net::sntp::Sntp;
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::rpl::Rpl<
+                    'static,
+                    capsules::net::ipv6::neighbor_discovery::NeighborDiscovery<
+                        'static,
+                        capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                            'static,
+                            VirtualMuxAlarm<'static, $A>,
+                        >,
+                        VirtualMuxAlarm<'static, $A>,
+                    >,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<Sntp<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct SntpComponent<S: IP6Sender<'static> + 'static, A: 'static + time::Alarm<'static>> {
+    udp_send_mux: &'static MuxUdpSender<'static, S>,
+    udp_recv_mux: &'static MuxUdpReceiver<'static>,
+    port_table: &'static UdpPortManager,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    server: IPAddr,
+}
+
+impl<S: IP6Sender<'static>, A: 'static + time::Alarm<'static>> SntpComponent<S, A> {
+    pub fn new(
+        udp_send_mux: &'static MuxUdpSender<'static, S>,
+        udp_recv_mux: &'static MuxUdpReceiver<'static>,
+        port_table: &'static UdpPortManager,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        server: IPAddr,
+    ) -> Self {
+        Self {
+            udp_send_mux,
+            udp_recv_mux,
+            port_table,
+            alarm_mux,
+            server,
+        }
+    }
+}
+
+impl<S: IP6Sender<'static>, A: 'static + time::Alarm<'static>> Component for SntpComponent<S, A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<UDPSendStruct<'static, S>>,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<Sntp<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static Sntp<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let create_cap = create_capability!(NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let udp_send = static_init_half!(
+            static_buffer.0,
+            UDPSendStruct<'static, S>,
+            UDPSendStruct::new(self.udp_send_mux, udp_vis)
+        );
+        let udp_recv = static_init!(UDPReceiver<'static>, UDPReceiver::new());
+
+        // The client only talks to the NTP port of its server.
+        let net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(
+                AddrRange::Addr(self.server),
+                PortRange::Port(NTP_PORT),
+                PortRange::Port(NTP_PORT),
+                &create_cap
+            )
+        );
+
+        let sntp_alarm = static_init_half!(
+            static_buffer.1,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        sntp_alarm.setup();
+
+        let sntp = static_init_half!(
+            static_buffer.2,
+            Sntp<'static, VirtualMuxAlarm<'static, A>>,
+            Sntp::new(
+                udp_send,
+                sntp_alarm,
+                self.server,
+                LeasableMutableBuffer::new(&mut SNTP_BUF),
+                net_cap,
+            )
+        );
+        udp_send.set_client(sntp);
+        udp_recv.set_client(sntp);
+        sntp_alarm.set_alarm_client(sntp);
+
+        let socket = self
+            .port_table
+            .create_socket()
+            .unwrap_or_else(|_| panic!("SNTP: no free UDP socket"));
+        let (send_bind, recv_bind) = self
+            .port_table
+            .bind(socket, NTP_PORT, net_cap)
+            .unwrap_or_else(|_| panic!("SNTP: failed to bind port {}", NTP_PORT));
+        udp_send.set_binding(send_bind);
+        udp_recv.set_binding(recv_bind);
+        self.udp_recv_mux.add_client(udp_recv);
+
+        sntp
+    }
+}
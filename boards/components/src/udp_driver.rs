@@ -1,7 +1,12 @@
 //! Component to initialize the userland UDP driver.
 //!
 //! This provides one Component, UDPDriverComponent. This component initializes a userspace
-//! UDP driver that allows apps to use the UDP stack.
+//! UDP driver that allows apps to use the UDP stack. The stack can be built over 6LoWPAN
+//! (`UDPMuxComponent`), Ethernet (`UDPMuxEthernetComponent`) or a serial line
+//! (`UDPMuxSerialComponent`); the helper macro takes the alarm type, the Ethernet device
+//! and alarm types, or `serial`, respectively. Apps that do not declare network
+//! permissions in their TBF header get the board-wide capability created here, which
+//! allows any port and address.
 //!
 //! Usage
 //! -----
@@ -14,26 +19,39 @@
 //!        local_ip_ifaces,
 //!        PAYLOAD_LEN,
 //!     )
-//!     .finalize();
+//!     .finalize(components::udp_driver_component_helper!(nrf52840::rtc::Rtc));
+//!
+//!    // Over Ethernet
+//!    let udp_driver = UDPDriverComponent::new(
+//!        board_kernel,
+//!        udp_send_mux,
+//!        udp_recv_mux,
+//!        udp_port_table,
+//!        local_ip_ifaces,
+//!        PAYLOAD_LEN,
+//!     )
+//!     .finalize(components::udp_driver_component_helper!(
+//!         ethernet: LiteEth<'static, SoCRegisterFmt>,
+//!         Timer<'static, SoCRegisterFmt>
+//!     ));
 //! ```
 
 use capsules;
 use capsules::net::ipv6::ip_utils::IPAddr;
-use capsules::net::ipv6::ipv6_send::IP6SendStruct;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
 use capsules::net::network_capabilities::{
-    AddrRange, NetworkCapability, PortRange, UdpVisibilityCapability,
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
 };
 use capsules::net::udp::udp_port_table::UdpPortManager;
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
 use capsules::net::udp::udp_recv::UDPReceiver;
 use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendStruct, UDPSender};
-use capsules::virtual_alarm::VirtualMuxAlarm;
+use core::cell::Cell;
 use core::mem::MaybeUninit;
 use kernel;
 use kernel::capabilities;
 use kernel::capabilities::NetworkCapabilityCreationCapability;
 use kernel::component::Component;
-use kernel::hil::time::Alarm;
 use kernel::{create_capability, static_init, static_init_half};
 
 const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
@@ -43,15 +61,46 @@ static mut DRIVER_BUF: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
 // Setup static space for the objects.
 #[macro_export]
 macro_rules! udp_driver_component_helper {
+    (serial $(,)?) => {{
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<'static, capsules::net::ipv6::ipv6_serial::IP6SerialStruct<'static>>,
+        > = MaybeUninit::uninit();
+        (&mut BUF0,)
+    };};
+    (ethernet: $E:ty, $A:ty $(,)?) => {{
+        use capsules::net::udp::udp_send::UDPSendStruct;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<
+            UDPSendStruct<
+                'static,
+                capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct<
+                    'static,
+                    $E,
+                    capsules::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                >,
+            >,
+        > = MaybeUninit::uninit();
+        (&mut BUF0,)
+    };};
     ($A:ty $(,)?) => {{
         use capsules::net::udp::udp_send::UDPSendStruct;
         use core::mem::MaybeUninit;
         static mut BUF0: MaybeUninit<
             UDPSendStruct<
                 'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                capsules::net::ipv6::rpl::Rpl<
                     'static,
-                    VirtualMuxAlarm<'static, $A>,
+                    capsules::net::ipv6::neighbor_discovery::NeighborDiscovery<
+                        'static,
+                        capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                            'static,
+                            capsules::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                        >,
+                        capsules::virtual_alarm::VirtualMuxAlarm<'static, $A>,
+                    >,
+                    capsules::virtual_alarm::VirtualMuxAlarm<'static, $A>,
                 >,
             >,
         > = MaybeUninit::uninit();
@@ -59,27 +108,23 @@ macro_rules! udp_driver_component_helper {
     };};
 }
 
-pub struct UDPDriverComponent<A: Alarm<'static> + 'static> {
+pub struct UDPDriverComponent<S: IP6Sender<'static> + 'static> {
     board_kernel: &'static kernel::Kernel,
     driver_num: usize,
-    udp_send_mux:
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+    udp_send_mux: &'static MuxUdpSender<'static, S>,
     udp_recv_mux: &'static MuxUdpReceiver<'static>,
     port_table: &'static UdpPortManager,
-    interface_list: &'static [IPAddr],
+    interface_list: &'static [Cell<IPAddr>],
 }
 
-impl<A: Alarm<'static>> UDPDriverComponent<A> {
+impl<S: IP6Sender<'static>> UDPDriverComponent<S> {
     pub fn new(
         board_kernel: &'static kernel::Kernel,
         driver_num: usize,
-        udp_send_mux: &'static MuxUdpSender<
-            'static,
-            IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-        >,
+        udp_send_mux: &'static MuxUdpSender<'static, S>,
         udp_recv_mux: &'static MuxUdpReceiver<'static>,
         port_table: &'static UdpPortManager,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static [Cell<IPAddr>],
     ) -> Self {
         Self {
             board_kernel,
@@ -92,15 +137,8 @@ impl<A: Alarm<'static>> UDPDriverComponent<A> {
     }
 }
 
-impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
-    type StaticInput = (
-        &'static mut MaybeUninit<
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
-        >,
-    );
+impl<S: IP6Sender<'static>> Component for UDPDriverComponent<S> {
+    type StaticInput = (&'static mut MaybeUninit<UDPSendStruct<'static, S>>,);
     type Output = &'static capsules::net::udp::UDPDriver<'static>;
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -113,10 +151,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         );
         let udp_send = static_init_half!(
             static_buffer.0,
-            UDPSendStruct<
-                'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
-            >,
+            UDPSendStruct<'static, S>,
             UDPSendStruct::new(self.udp_send_mux, udp_vis)
         );
 
@@ -126,6 +161,16 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
         unsafe impl capabilities::UdpDriverCapability for DriverCap {}
         static DRIVER_CAP: DriverCap = DriverCap;
 
+        // The driver creates a network capability for each process from the
+        // permissions in its TBF header.
+        struct NetCreateCap;
+        unsafe impl NetworkCapabilityCreationCapability for NetCreateCap {}
+        static NET_CREATE_CAP: NetCreateCap = NetCreateCap;
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
         let net_cap = static_init!(
             NetworkCapability,
             NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
@@ -142,6 +187,9 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
                 kernel::utilities::leasable_buffer::LeasableMutableBuffer::new(&mut DRIVER_BUF),
                 &DRIVER_CAP,
                 net_cap,
+                &NET_CREATE_CAP,
+                udp_vis,
+                ip_vis,
             )
         );
         udp_send.set_client(udp_driver);
@@ -149,6 +197,7 @@ impl<A: Alarm<'static>> Component for UDPDriverComponent<A> {
 
         let udp_driver_rcvr = static_init!(UDPReceiver<'static>, UDPReceiver::new());
         self.udp_recv_mux.set_driver(udp_driver);
+        udp_driver.set_multicast_groups(self.udp_recv_mux);
         self.udp_recv_mux.add_client(udp_driver_rcvr);
         udp_driver
     }
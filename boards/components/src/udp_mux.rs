@@ -4,20 +4,26 @@
 //! exposes a MuxUdpSender that other components can implement
 //! UDPSenders on top of to use the UDP/6Lowpan stack.
 //!
+//! The IPv6 addresses of the interface are configured at runtime with
+//! Neighbor Discovery and stateless address autoconfiguration, starting from
+//! the 802.15.4 source address. The component returns the list of addresses,
+//! which is filled in as they are assigned, for the UDP driver.
+//!
+//! The interface joins an RPL mesh as a router: it selects a parent towards
+//! the border router, advertises its address to it, and forwards packets for
+//! other nodes.
+//!
 //! Usage
 //! -----
 //! ```rust
-//!    let (udp_mux, udp_recv) = UDPMuxComponent::new(
+//!    let (udp_send_mux, udp_recv_mux, udp_port_table, local_ip_ifaces) = UDPMuxComponent::new(
 //!        mux_mac,
 //!        DEFAULT_CTX_PREFIX_LEN,
 //!        DEFAULT_CTX_PREFIX,
-//!        DST_MAC_ADDR,
 //!        src_mac_from_serial_num,
-//!        local_ip_ifaces,
 //!        mux_alarm,
-//!        MAX_PAYLOAD_LEN,
 //!    )
-//!    .finalize();
+//!    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
 //! ```
 
 // Author: Hudson Ayers <hayers@stanford.edu>
@@ -30,14 +36,19 @@ use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::net::ipv6::ipv6_recv::IP6Receiver;
 use capsules::net::ipv6::ipv6_send::IP6SendStruct;
 use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::neighbor_discovery::{self, NeighborDiscovery};
+use capsules::net::ipv6::rpl::{self, Rpl};
 use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
-use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
 use capsules::net::sixlowpan::{sixlowpan_compression, sixlowpan_state};
 use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
 use capsules::net::udp::udp_recv::MuxUdpReceiver;
 use capsules::net::udp::udp_send::MuxUdpSender;
 use capsules::net::udp::UDPHeader;
 use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::cell::Cell;
 use core::mem::MaybeUninit;
 use kernel;
 use kernel::capabilities;
@@ -52,6 +63,8 @@ use kernel::{static_init, static_init_half};
 //   1. RADIO_BUF: buffer the IP6_Sender uses to pass frames to the radio after fragmentation
 //   2. SIXLOWPAN_RX_BUF: Buffer to hold full IP packets after they are decompressed by 6LoWPAN
 //   3. UDP_DGRAM: The payload of the IP6_Packet, which holds full IP Packets before they are tx'd.
+//   4. ND_BUF: Buffer that Neighbor Discovery messages are built in before they are tx'd.
+//   5. RPL_BUF: Buffer that RPL messages are built in before they are tx'd.
 //
 //   Additionally, every capsule using the stack needs an additional buffer to craft packets for
 //   tx which can then be passed to the MuxUdpSender for tx.
@@ -61,6 +74,8 @@ static mut SIXLOWPAN_RX_BUF: [u8; 1280] = [0x00; 1280];
 
 pub const MAX_PAYLOAD_LEN: usize = 200; //The max size UDP message that can be sent by userspace apps or capsules
 static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+static mut ND_BUF: [u8; neighbor_discovery::BUF_LEN] = [0; neighbor_discovery::BUF_LEN];
+static mut RPL_BUF: [u8; rpl::BUF_LEN] = [0; rpl::BUF_LEN];
 
 // Rather than require a data structure with 65535 slots (number of UDP ports), we
 // use a structure that can hold up to 16 port bindings. Any given capsule can bind
@@ -97,17 +112,52 @@ macro_rules! udp_mux_component_helper {
         static mut BUF4: MaybeUninit<
             capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, $A>>,
         > = MaybeUninit::uninit();
-        static mut BUF5: MaybeUninit<
-            MuxUdpSender<
+        static mut BUF5: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF6: MaybeUninit<
+            capsules::net::ipv6::neighbor_discovery::NeighborDiscovery<
                 'static,
                 capsules::net::ipv6::ipv6_send::IP6SendStruct<
                     'static,
                     VirtualMuxAlarm<'static, $A>,
                 >,
+                VirtualMuxAlarm<'static, $A>,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF7: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF8: MaybeUninit<
+            capsules::net::ipv6::rpl::Rpl<
+                'static,
+                capsules::net::ipv6::neighbor_discovery::NeighborDiscovery<
+                    'static,
+                    capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                        'static,
+                        VirtualMuxAlarm<'static, $A>,
+                    >,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
+                VirtualMuxAlarm<'static, $A>,
+            >,
+        > = MaybeUninit::uninit();
+        static mut BUF9: MaybeUninit<
+            MuxUdpSender<
+                'static,
+                capsules::net::ipv6::rpl::Rpl<
+                    'static,
+                    capsules::net::ipv6::neighbor_discovery::NeighborDiscovery<
+                        'static,
+                        capsules::net::ipv6::ipv6_send::IP6SendStruct<
+                            'static,
+                            VirtualMuxAlarm<'static, $A>,
+                        >,
+                        VirtualMuxAlarm<'static, $A>,
+                    >,
+                    VirtualMuxAlarm<'static, $A>,
+                >,
             >,
         > = MaybeUninit::uninit();
         (
-            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5,
+            &mut BUF0, &mut BUF1, &mut BUF2, &mut BUF3, &mut BUF4, &mut BUF5, &mut BUF6, &mut BUF7,
+            &mut BUF8, &mut BUF9,
         )
     };};
 }
@@ -116,9 +166,7 @@ pub struct UDPMuxComponent<A: Alarm<'static> + 'static> {
     mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
     ctx_pfix_len: u8,
     ctx_pfix: [u8; 16],
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
-    interface_list: &'static [IPAddr],
     alarm_mux: &'static MuxAlarm<'static, A>,
 }
 
@@ -127,18 +175,14 @@ impl<A: Alarm<'static> + 'static> UDPMuxComponent<A> {
         mux_mac: &'static capsules::ieee802154::virtual_mac::MuxMac<'static>,
         ctx_pfix_len: u8,
         ctx_pfix: [u8; 16],
-        dst_mac_addr: MacAddress,
         src_mac_addr: MacAddress,
-        interface_list: &'static [IPAddr],
         alarm_mux: &'static MuxAlarm<'static, A>,
     ) -> Self {
         Self {
             mux_mac,
             ctx_pfix_len,
             ctx_pfix,
-            dst_mac_addr,
             src_mac_addr,
-            interface_list,
             alarm_mux,
         }
     }
@@ -159,17 +203,57 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         &'static mut MaybeUninit<
             capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
         >,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            NeighborDiscovery<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                VirtualMuxAlarm<'static, A>,
+            >,
+        >,
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<
+            Rpl<
+                'static,
+                NeighborDiscovery<
+                    'static,
+                    IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                    VirtualMuxAlarm<'static, A>,
+                >,
+                VirtualMuxAlarm<'static, A>,
+            >,
+        >,
         &'static mut MaybeUninit<
             MuxUdpSender<
                 'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                Rpl<
+                    'static,
+                    NeighborDiscovery<
+                        'static,
+                        IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                        VirtualMuxAlarm<'static, A>,
+                    >,
+                    VirtualMuxAlarm<'static, A>,
+                >,
             >,
         >,
     );
     type Output = (
-        &'static MuxUdpSender<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>>,
+        &'static MuxUdpSender<
+            'static,
+            Rpl<
+                'static,
+                NeighborDiscovery<
+                    'static,
+                    IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                    VirtualMuxAlarm<'static, A>,
+                >,
+                VirtualMuxAlarm<'static, A>,
+            >,
+        >,
         &'static MuxUdpReceiver<'static>,
         &'static UdpPortManager,
+        &'static [Cell<IPAddr>],
     );
 
     unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
@@ -231,12 +315,11 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
         };
         let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
 
-        // In current design, all udp senders share same IP sender, and the IP sender
-        // holds the destination mac address. This means all UDP senders must send to
-        // the same mac address...this works fine under the assumption
-        // of all packets being routed via a single gateway router, but doesn't work
-        // if multiple senders want to send to different addresses on a local network.
-        // This will be fixed once we have an ipv6_nd cache mapping IP addresses to dst macs
+        // All udp senders share the same IP sender. Packets to link-local
+        // addresses are sent to the MAC address in their interface identifier,
+        // and all other packets to the next hop chosen by RPL, or to the router
+        // found by Neighbor Discovery. Until either is found, they are
+        // broadcast.
         let ip_send = static_init_half!(
             static_buffer.4,
             capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
@@ -246,18 +329,12 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
                 &mut RADIO_BUF,
                 sixlowpan_tx,
                 udp_mac,
-                self.dst_mac_addr,
+                MacAddress::Short(0xffff),
                 self.src_mac_addr,
                 ip_vis,
             )
         );
         ipsender_virtual_alarm.set_alarm_client(ip_send);
-
-        // Initially, set src IP of the sender to be the first IP in the Interface
-        // list. Userland apps can change this if they so choose.
-        // Notably, the src addr is the same regardless of if messages are sent from
-        // userland or capsules.
-        ip_send.set_addr(self.interface_list[0]);
         udp_mac.set_transmit_client(ip_send);
 
         let ip_receive = static_init!(
@@ -265,18 +342,102 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
         );
         sixlowpan_state.set_rx_client(ip_receive);
+
+        // Neighbor Discovery sits between the IP layer and the UDP muxes. It
+        // sets the src IP of the sender once an address has been assigned.
+        // Notably, the src addr is the same regardless of if messages are sent
+        // from userland or capsules.
+        let nd_virtual_alarm = static_init_half!(
+            static_buffer.5,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        nd_virtual_alarm.setup();
+        let nd_net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let nd = static_init_half!(
+            static_buffer.6,
+            NeighborDiscovery<
+                'static,
+                IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                VirtualMuxAlarm<'static, A>,
+            >,
+            NeighborDiscovery::new(
+                ip_send,
+                nd_virtual_alarm,
+                self.src_mac_addr,
+                &mut ND_BUF,
+                nd_net_cap,
+            )
+        );
+        nd_virtual_alarm.set_alarm_client(nd);
+        ip_send.set_client(nd);
+        ip_receive.set_client(nd);
+
+        // RPL sits between Neighbor Discovery and the UDP muxes. It picks the
+        // next hop of packets that leave the link, and packets for other
+        // nodes are forwarded by the IP layer.
+        let rpl_virtual_alarm = static_init_half!(
+            static_buffer.7,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        rpl_virtual_alarm.setup();
+        let rpl_net_cap = static_init!(
+            NetworkCapability,
+            NetworkCapability::new(AddrRange::Any, PortRange::Any, PortRange::Any, &create_cap)
+        );
+        let rpl = static_init_half!(
+            static_buffer.8,
+            Rpl<
+                'static,
+                NeighborDiscovery<
+                    'static,
+                    IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                    VirtualMuxAlarm<'static, A>,
+                >,
+                VirtualMuxAlarm<'static, A>,
+            >,
+            Rpl::new(
+                nd,
+                rpl_virtual_alarm,
+                nd.addresses(),
+                &mut RPL_BUF,
+                rpl_net_cap,
+            )
+        );
+        rpl_virtual_alarm.set_alarm_client(rpl);
+        nd.set_client(rpl);
+        nd.set_receive_client(rpl);
+        rpl.set_prefix_client(nd);
+        ip_send.set_router(rpl);
+        ip_receive.set_forwarder(ip_send, nd.addresses());
+
         let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
-        ip_receive.set_client(udp_recv_mux);
+        rpl.set_receive_client(udp_recv_mux);
+        udp_recv_mux.set_multicast_groups(ip_receive);
 
         let udp_send_mux = static_init_half!(
-            static_buffer.5,
+            static_buffer.9,
             MuxUdpSender<
                 'static,
-                capsules::net::ipv6::ipv6_send::IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                Rpl<
+                    'static,
+                    NeighborDiscovery<
+                        'static,
+                        IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>,
+                        VirtualMuxAlarm<'static, A>,
+                    >,
+                    VirtualMuxAlarm<'static, A>,
+                >,
             >,
-            MuxUdpSender::new(ip_send)
+            MuxUdpSender::new(rpl)
         );
-        ip_send.set_client(udp_send_mux);
+        rpl.set_client(udp_send_mux);
+        nd.start();
+        rpl.start();
 
         let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
         let udp_port_table = static_init!(
@@ -284,6 +445,6 @@ impl<A: Alarm<'static> + 'static> Component for UDPMuxComponent<A> {
             UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
         );
 
-        (udp_send_mux, udp_recv_mux, udp_port_table)
+        (udp_send_mux, udp_recv_mux, udp_port_table, nd.addresses())
     }
 }
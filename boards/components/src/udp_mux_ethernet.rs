@@ -0,0 +1,181 @@
+//! Component to initialize the UDP stack over an Ethernet device.
+//!
+//! This provides one Component, UDPMuxEthernetComponent. Like UDPMuxComponent
+//! it exposes a MuxUdpSender, a MuxUdpReceiver and a UdpPortManager that
+//! other components can build UDP users on top of, but the IPv6 packets are
+//! carried in Ethernet frames rather than over 6LoWPAN.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (udp_send_mux, udp_recv_mux, udp_port_table) = UDPMuxEthernetComponent::new(
+//!        ethmac0,
+//!        local_ip_ifaces,
+//!        mux_alarm,
+//!    )
+//!    .finalize(components::udp_mux_ethernet_component_helper!(
+//!        LiteEth<'static, SoCRegisterFmt>,
+//!        Timer<'static, SoCRegisterFmt>
+//!    ));
+//! ```
+
+use capsules;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct;
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader, UDP_HDR_LEN};
+use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
+use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_send::MuxUdpSender;
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::ethernet::{self, Ethernet};
+use kernel::hil::time::Alarm;
+use kernel::{static_init, static_init_half};
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+// The IPv6 sender needs a buffer for outgoing frames (the Ethernet header,
+// the IPv6 header and the UDP datagram), and the IPv6 packet needs a buffer
+// for the UDP payload. Received frames are handed up by the Ethernet device
+// and need no buffer here.
+const FRAME_BUF_LEN: usize = ethernet::HEADER_LEN + 40 + UDP_HDR_LEN + MAX_PAYLOAD_LEN;
+static mut FRAME_BUF: [u8; FRAME_BUF_LEN] = [0x00; FRAME_BUF_LEN];
+static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+
+// See udp_mux.rs for how kernel port bindings are tracked.
+static mut USED_KERNEL_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
+    [None; MAX_NUM_BOUND_PORTS];
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! udp_mux_ethernet_component_helper {
+    ($E:ty, $A:ty $(,)?) => {{
+        use capsules::net::ipv6::ipv6_ethernet::IP6EthernetStruct;
+        use capsules::net::udp::udp_send::MuxUdpSender;
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use core::mem::MaybeUninit;
+        static mut BUF0: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF1: MaybeUninit<IP6EthernetStruct<'static, $E, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<
+            MuxUdpSender<'static, IP6EthernetStruct<'static, $E, VirtualMuxAlarm<'static, $A>>>,
+        > = MaybeUninit::uninit();
+        (&mut BUF0, &mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct UDPMuxEthernetComponent<E: Ethernet<'static> + 'static, A: Alarm<'static> + 'static> {
+    ethernet: &'static E,
+    interface_list: &'static [Cell<IPAddr>],
+    alarm_mux: &'static MuxAlarm<'static, A>,
+}
+
+impl<E: Ethernet<'static> + 'static, A: Alarm<'static> + 'static> UDPMuxEthernetComponent<E, A> {
+    pub fn new(
+        ethernet: &'static E,
+        interface_list: &'static [Cell<IPAddr>],
+        alarm_mux: &'static MuxAlarm<'static, A>,
+    ) -> Self {
+        Self {
+            ethernet,
+            interface_list,
+            alarm_mux,
+        }
+    }
+}
+
+impl<E: Ethernet<'static> + 'static, A: Alarm<'static> + 'static> Component
+    for UDPMuxEthernetComponent<E, A>
+{
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<IP6EthernetStruct<'static, E, VirtualMuxAlarm<'static, A>>>,
+        &'static mut MaybeUninit<
+            MuxUdpSender<'static, IP6EthernetStruct<'static, E, VirtualMuxAlarm<'static, A>>>,
+        >,
+    );
+    type Output = (
+        &'static MuxUdpSender<'static, IP6EthernetStruct<'static, E, VirtualMuxAlarm<'static, A>>>,
+        &'static MuxUdpReceiver<'static>,
+        &'static UdpPortManager,
+    );
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let ipsender_virtual_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        ipsender_virtual_alarm.setup();
+
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut UDP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init_half!(
+            static_buffer.1,
+            IP6EthernetStruct<'static, E, VirtualMuxAlarm<'static, A>>,
+            IP6EthernetStruct::new(
+                self.ethernet,
+                ipsender_virtual_alarm,
+                ip6_dg,
+                &mut FRAME_BUF,
+                ip_vis,
+            )
+        );
+        ipsender_virtual_alarm.set_alarm_client(ip_send);
+        self.ethernet.set_transmit_client(ip_send);
+        self.ethernet.set_receive_client(ip_send);
+
+        // As with 6LoWPAN, the source address is the first address in the
+        // interface list, and it is also the address answered in Neighbor
+        // Discovery.
+        ip_send.set_addr(self.interface_list[0].get());
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        ip_send.set_receiver(ip_receive);
+        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+        ip_receive.set_client(udp_recv_mux);
+        udp_recv_mux.set_multicast_groups(ip_receive);
+
+        let udp_send_mux = static_init_half!(
+            static_buffer.2,
+            MuxUdpSender<'static, IP6EthernetStruct<'static, E, VirtualMuxAlarm<'static, A>>>,
+            MuxUdpSender::new(ip_send)
+        );
+        ip_send.set_client(udp_send_mux);
+
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let udp_port_table = static_init!(
+            UdpPortManager,
+            UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
+        );
+
+        (udp_send_mux, udp_recv_mux, udp_port_table)
+    }
+}
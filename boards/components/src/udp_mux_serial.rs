@@ -0,0 +1,153 @@
+//! Component to initialize the UDP stack over a serial line.
+//!
+//! This provides one Component, UDPMuxSerialComponent. Like UDPMuxComponent
+//! it exposes a MuxUdpSender, a MuxUdpReceiver and a UdpPortManager that
+//! other components can build UDP users on top of, but the IPv6 packets are
+//! framed with SLIP or HDLC-like framing over a UART. The UART must not also
+//! carry the console.
+//!
+//! Usage
+//! -----
+//! ```rust
+//!    let (udp_send_mux, udp_recv_mux, udp_port_table) = UDPMuxSerialComponent::new(
+//!        uart_mux,
+//!        Framing::Slip,
+//!        local_ip_ifaces,
+//!    )
+//!    .finalize(());
+//! ```
+
+use capsules;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::IP6Receiver;
+use capsules::net::ipv6::ipv6_send::IP6Sender;
+use capsules::net::ipv6::ipv6_serial::IP6SerialStruct;
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader, UDP_HDR_LEN};
+use capsules::net::network_capabilities::{IpVisibilityCapability, UdpVisibilityCapability};
+use capsules::net::serial::{hdlc, Framing};
+use capsules::net::udp::udp_port_table::{SocketBindingEntry, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::MuxUdpReceiver;
+use capsules::net::udp::udp_send::MuxUdpSender;
+use capsules::net::udp::UDPHeader;
+use capsules::virtual_uart::{MuxUart, UartDevice};
+use core::cell::Cell;
+use kernel;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::uart;
+use kernel::static_init;
+
+const MAX_PAYLOAD_LEN: usize = super::udp_mux::MAX_PAYLOAD_LEN;
+
+// Outgoing packets are encoded into PACKET_BUF and then framed into
+// FRAME_BUF, which must hold the packet with every byte escaped. Received
+// frames are decoded byte by byte into RX_FRAME_BUF; larger packets are
+// dropped.
+const PACKET_LEN: usize = 40 + UDP_HDR_LEN + MAX_PAYLOAD_LEN;
+const FRAME_BUF_LEN: usize = 2 * (PACKET_LEN + hdlc::FCS_LEN) + 2;
+static mut PACKET_BUF: [u8; PACKET_LEN] = [0x00; PACKET_LEN];
+static mut FRAME_BUF: [u8; FRAME_BUF_LEN] = [0x00; FRAME_BUF_LEN];
+static mut RX_BYTE: [u8; 1] = [0x00; 1];
+static mut RX_FRAME_BUF: [u8; PACKET_LEN + hdlc::FCS_LEN] = [0x00; PACKET_LEN + hdlc::FCS_LEN];
+static mut UDP_DGRAM: [u8; MAX_PAYLOAD_LEN] = [0; MAX_PAYLOAD_LEN];
+
+// See udp_mux.rs for how kernel port bindings are tracked.
+static mut USED_KERNEL_PORTS: [Option<SocketBindingEntry>; MAX_NUM_BOUND_PORTS] =
+    [None; MAX_NUM_BOUND_PORTS];
+
+pub struct UDPMuxSerialComponent {
+    uart_mux: &'static MuxUart<'static>,
+    framing: Framing,
+    interface_list: &'static [Cell<IPAddr>],
+}
+
+impl UDPMuxSerialComponent {
+    pub fn new(
+        uart_mux: &'static MuxUart<'static>,
+        framing: Framing,
+        interface_list: &'static [Cell<IPAddr>],
+    ) -> Self {
+        Self {
+            uart_mux,
+            framing,
+            interface_list,
+        }
+    }
+}
+
+impl Component for UDPMuxSerialComponent {
+    type StaticInput = ();
+    type Output = (
+        &'static MuxUdpSender<'static, IP6SerialStruct<'static>>,
+        &'static MuxUdpReceiver<'static>,
+        &'static UdpPortManager,
+    );
+
+    unsafe fn finalize(self, _static_buffer: Self::StaticInput) -> Self::Output {
+        let serial_uart = static_init!(UartDevice, UartDevice::new(self.uart_mux, true));
+        serial_uart.setup();
+
+        let create_cap = create_capability!(capabilities::NetworkCapabilityCreationCapability);
+        let udp_vis = static_init!(
+            UdpVisibilityCapability,
+            UdpVisibilityCapability::new(&create_cap)
+        );
+        let ip_vis = static_init!(
+            IpVisibilityCapability,
+            IpVisibilityCapability::new(&create_cap)
+        );
+
+        let tr_hdr = TransportHeader::UDP(UDPHeader::new());
+        let ip_pyld: IPPayload = IPPayload {
+            header: tr_hdr,
+            payload: &mut UDP_DGRAM,
+        };
+        let ip6_dg = static_init!(IP6Packet<'static>, IP6Packet::new(ip_pyld));
+
+        let ip_send = static_init!(
+            IP6SerialStruct<'static>,
+            IP6SerialStruct::new(
+                serial_uart,
+                self.framing,
+                ip6_dg,
+                &mut PACKET_BUF,
+                &mut FRAME_BUF,
+                &mut RX_BYTE,
+                &mut RX_FRAME_BUF,
+                ip_vis,
+            )
+        );
+        uart::Transmit::set_transmit_client(serial_uart, ip_send);
+        uart::Receive::set_receive_client(serial_uart, ip_send);
+
+        // As with 6LoWPAN, the source address is the first address in the
+        // interface list.
+        ip_send.set_addr(self.interface_list[0].get());
+
+        let ip_receive = static_init!(
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct<'static>,
+            capsules::net::ipv6::ipv6_recv::IP6RecvStruct::new()
+        );
+        ip_send.set_receiver(ip_receive);
+        let udp_recv_mux = static_init!(MuxUdpReceiver<'static>, MuxUdpReceiver::new());
+        ip_receive.set_client(udp_recv_mux);
+        udp_recv_mux.set_multicast_groups(ip_receive);
+
+        let udp_send_mux = static_init!(
+            MuxUdpSender<'static, IP6SerialStruct<'static>>,
+            MuxUdpSender::new(ip_send)
+        );
+        ip_send.set_client(udp_send_mux);
+
+        let create_table_cap = create_capability!(capabilities::CreatePortTableCapability);
+        let udp_port_table = static_init!(
+            UdpPortManager,
+            UdpPortManager::new(&create_table_cap, &mut USED_KERNEL_PORTS, udp_vis)
+        );
+
+        let _ = ip_send.start();
+
+        (udp_send_mux, udp_recv_mux, udp_port_table)
+    }
+}
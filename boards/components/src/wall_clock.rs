@@ -0,0 +1,100 @@
+//! Component for the wall-clock time service.
+//!
+//! This provides one Component, `WallClockComponent`, which keeps UTC time
+//! over a virtual alarm and exposes it to userspace. To synchronize the time
+//! over the network, set an `SntpComponent` as its time source. Processes can
+//! only set the time if `userspace_set` is true, as a process that sets a
+//! wrong time affects every other user of the clock.
+//!
+//! Usage
+//! -----
+//! ```rust
+//! let wall_clock = components::wall_clock::WallClockComponent::new(
+//!     board_kernel,
+//!     capsules::wall_clock::DRIVER_NUM,
+//!     mux_alarm,
+//!     false,
+//! )
+//! .finalize(components::wall_clock_component_helper!(sam4l::ast::Ast));
+//! wall_clock.set_time_source(sntp);
+//! sntp.set_client(wall_clock);
+//! ```
+
+use core::mem::MaybeUninit;
+
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+use capsules::wall_clock::WallClock;
+use kernel::capabilities;
+use kernel::component::Component;
+use kernel::create_capability;
+use kernel::hil::time::{self, Alarm};
+use kernel::static_init_half;
+
+// Setup static space for the objects.
+#[macro_export]
+macro_rules! wall_clock_component_helper {
+    ($A:ty $(,)?) => {{
+        use capsules::virtual_alarm::VirtualMuxAlarm;
+        use capsules::wall_clock::WallClock;
+        use core::mem::MaybeUninit;
+        static mut BUF1: MaybeUninit<VirtualMuxAlarm<'static, $A>> = MaybeUninit::uninit();
+        static mut BUF2: MaybeUninit<WallClock<'static, VirtualMuxAlarm<'static, $A>>> =
+            MaybeUninit::uninit();
+        (&mut BUF1, &mut BUF2)
+    };};
+}
+
+pub struct WallClockComponent<A: 'static + time::Alarm<'static>> {
+    board_kernel: &'static kernel::Kernel,
+    driver_num: usize,
+    alarm_mux: &'static MuxAlarm<'static, A>,
+    userspace_set: bool,
+}
+
+impl<A: 'static + time::Alarm<'static>> WallClockComponent<A> {
+    pub fn new(
+        board_kernel: &'static kernel::Kernel,
+        driver_num: usize,
+        alarm_mux: &'static MuxAlarm<'static, A>,
+        userspace_set: bool,
+    ) -> WallClockComponent<A> {
+        WallClockComponent {
+            board_kernel,
+            driver_num,
+            alarm_mux,
+            userspace_set,
+        }
+    }
+}
+
+impl<A: 'static + time::Alarm<'static>> Component for WallClockComponent<A> {
+    type StaticInput = (
+        &'static mut MaybeUninit<VirtualMuxAlarm<'static, A>>,
+        &'static mut MaybeUninit<WallClock<'static, VirtualMuxAlarm<'static, A>>>,
+    );
+    type Output = &'static WallClock<'static, VirtualMuxAlarm<'static, A>>;
+
+    unsafe fn finalize(self, static_buffer: Self::StaticInput) -> Self::Output {
+        let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+
+        let wall_clock_alarm = static_init_half!(
+            static_buffer.0,
+            VirtualMuxAlarm<'static, A>,
+            VirtualMuxAlarm::new(self.alarm_mux)
+        );
+        wall_clock_alarm.setup();
+
+        let wall_clock = static_init_half!(
+            static_buffer.1,
+            WallClock<'static, VirtualMuxAlarm<'static, A>>,
+            WallClock::new(
+                wall_clock_alarm,
+                self.userspace_set,
+                self.board_kernel.create_grant(self.driver_num, &grant_cap)
+            )
+        );
+        wall_clock_alarm.set_alarm_client(wall_clock);
+
+        wall_clock
+    }
+}
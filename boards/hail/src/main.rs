@@ -74,7 +74,11 @@ struct Hail {
         LedLow<'static, sam4l::gpio::GPIOPin<'static>>,
         3,
     >,
-    button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        sam4l::gpio::GPIOPin<'static>,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     rng: &'static capsules::rng::RngDriver<'static>,
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
     crc: &'static capsules::crc::CrcDriver<'static, sam4l::crccu::Crccu<'static>>,
@@ -407,6 +411,7 @@ pub unsafe fn main() {
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             sam4l::gpio::GPIOPin,
             (
@@ -416,7 +421,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(sam4l::gpio::GPIOPin));
+    .finalize(components::button_component_buf!(
+        sam4l::gpio::GPIOPin,
+        sam4l::ast::Ast
+    ));
 
     // Setup ADC
     let adc_channels = static_init!(
@@ -58,6 +58,7 @@ struct HiFive1 {
         'static,
         capsules::virtual_uart::UartDevice<'static>,
     >,
+    date_time: &'static capsules::date_time::DateTimeDriver<'static, sifive::rtc::Rtc<'static>>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         VirtualMuxAlarm<'static, sifive::clint::Clint<'static>>,
@@ -78,6 +79,7 @@ impl SyscallDriverLookup for HiFive1 {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::low_level_debug::DRIVER_NUM => f(Some(self.lldb)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             _ => f(None),
         }
     }
@@ -146,7 +148,7 @@ pub unsafe fn main() {
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 2], Default::default());
+        static_init!([DynamicDeferredCallClientState; 3], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
@@ -251,6 +253,20 @@ pub unsafe fn main() {
     )
     .finalize(());
 
+    // The RTC keeps the date and time in the always-on domain.
+    peripherals.rtc.initialize(
+        dynamic_deferred_caller,
+        dynamic_deferred_caller.register(&peripherals.rtc).unwrap(), // Unwrap fail = no deferred call slot available for the RTC
+    );
+    let date_time = components::date_time::DateTimeComponent::new(
+        board_kernel,
+        capsules::date_time::DRIVER_NUM,
+        &peripherals.rtc,
+    )
+    .finalize(components::date_time_component_helper!(
+        sifive::rtc::Rtc<'static>
+    ));
+
     // Need two debug!() calls to actually test with QEMU. QEMU seems to have
     // a much larger UART TX buffer (or it transmits faster).
     debug!("HiFive1 initialization complete.");
@@ -280,6 +296,7 @@ pub unsafe fn main() {
         console: console,
         alarm: alarm,
         lldb: lldb,
+        date_time,
         led,
         scheduler,
         scheduler_timer,
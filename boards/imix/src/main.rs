@@ -17,6 +17,7 @@ use capsules::virtual_aes_ccm::MuxAES128CCM;
 use capsules::virtual_alarm::VirtualMuxAlarm;
 use capsules::virtual_i2c::MuxI2C;
 use capsules::virtual_spi::VirtualSpiMasterDevice;
+use capsules::wall_clock::TimeSource;
 //use capsules::virtual_timer::MuxTimer;
 use kernel::capabilities;
 use kernel::component::Component;
@@ -25,6 +26,7 @@ use kernel::hil::i2c::I2CMaster;
 use kernel::hil::radio;
 #[allow(unused_imports)]
 use kernel::hil::radio::{RadioConfig, RadioData};
+use kernel::hil::rng::{Random, Rng};
 use kernel::hil::symmetric_encryption::AES128;
 use kernel::platform::{KernelResources, SyscallDriverLookup};
 use kernel::scheduler::round_robin::RoundRobinSched;
@@ -45,7 +47,6 @@ use components::isl29035::AmbientLightComponent;
 use components::led::LedsComponent;
 use components::nrf51822::Nrf51822Component;
 use components::process_console::ProcessConsoleComponent;
-use components::rng::RngComponent;
 use components::si7021::{HumidityComponent, SI7021Component};
 use components::spi::{SpiComponent, SpiSyscallComponent};
 use imix_components::adc::AdcComponent;
@@ -84,10 +85,14 @@ const NUM_PROCS: usize = 4;
 // onto each device. This makes MAC address configuration a good target for capabilities -
 // only allow one app per board to have control of MAC address configuration?
 const RADIO_CHANNEL: u8 = 26;
-const DST_MAC_ADDR: MacAddress = MacAddress::Short(49138);
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compression
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 const PAN_ID: u16 = 0xABCD;
+// The NTP server the wall clock is synchronized with, reachable through the
+// border router.
+const NTP_SERVER: IPAddr = IPAddr([
+    0x20, 0x01, 0x0d, 0xb8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x23,
+]);
 
 // how should the kernel respond when a process faults
 const FAULT_RESPONSE: kernel::process::PanicFaultPolicy = kernel::process::PanicFaultPolicy {};
@@ -121,7 +126,11 @@ struct Imix {
         LedHigh<'static, sam4l::gpio::GPIOPin<'static>>,
         1,
     >,
-    button: &'static capsules::button::Button<'static, sam4l::gpio::GPIOPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        sam4l::gpio::GPIOPin<'static>,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
     rng: &'static capsules::rng::RngDriver<'static>,
     analog_comparator: &'static capsules::analog_comparator::AnalogComparator<
         'static,
@@ -134,6 +143,21 @@ struct Imix {
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
+    coap_driver: &'static capsules::net::coap::CoapDriver<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    wall_clock: &'static capsules::wall_clock::WallClock<
+        'static,
+        VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+    >,
+    date_time: &'static capsules::date_time::DateTimeDriver<
+        'static,
+        capsules::soft_date_time::SoftDateTime<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        >,
+    >,
     crc: &'static capsules::crc::CrcDriver<'static, sam4l::crccu::Crccu<'static>>,
     usb_driver: &'static capsules::usb::usb_user::UsbSyscallDriver<
         'static,
@@ -179,6 +203,9 @@ impl SyscallDriverLookup for Imix {
             capsules::crc::DRIVER_NUM => f(Some(self.crc)),
             capsules::usb::usb_user::DRIVER_NUM => f(Some(self.usb_driver)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
+            capsules::net::coap::DRIVER_NUM => f(Some(self.coap_driver)),
+            capsules::wall_clock::DRIVER_NUM => f(Some(self.wall_clock)),
+            capsules::date_time::DRIVER_NUM => f(Some(self.date_time)),
             capsules::nrf51822_serialization::DRIVER_NUM => f(Some(self.nrf51822)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
@@ -350,7 +377,7 @@ pub unsafe fn main() {
     let board_kernel = static_init!(kernel::Kernel, kernel::Kernel::new(&PROCESSES));
 
     let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 5], Default::default());
+        static_init!([DynamicDeferredCallClientState; 6], Default::default());
     let dynamic_deferred_caller = static_init!(
         DynamicDeferredCall,
         DynamicDeferredCall::new(dynamic_deferred_call_clients)
@@ -473,6 +500,7 @@ pub unsafe fn main() {
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             sam4l::gpio::GPIOPin,
             (
@@ -482,7 +510,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(sam4l::gpio::GPIOPin));
+    .finalize(components::button_component_buf!(
+        sam4l::gpio::GPIOPin,
+        sam4l::ast::Ast
+    ));
 
     let crc = CrcComponent::new(board_kernel, capsules::crc::DRIVER_NUM, &peripherals.crccu)
         .finalize(components::crc_component_helper!(sam4l::crccu::Crccu));
@@ -516,8 +547,39 @@ pub unsafe fn main() {
         capsules::analog_comparator::DRIVER_NUM,
     )
     .finalize(components::acomp_component_buf!(sam4l::acifc::Acifc));
-    let rng =
-        RngComponent::new(board_kernel, capsules::rng::DRIVER_NUM, &peripherals.trng).finalize(());
+
+    // The TRNG is shared by the RNG syscall driver and the random backoffs of
+    // the 802.15.4 MAC.
+    let entropy_to_random = static_init!(
+        capsules::rng::Entropy32ToRandom<'static>,
+        capsules::rng::Entropy32ToRandom::new(&peripherals.trng)
+    );
+    let rng_mux = static_init!(
+        capsules::virtual_rng::MuxRngMaster<'static>,
+        capsules::virtual_rng::MuxRngMaster::new(entropy_to_random)
+    );
+    let rng_device = static_init!(
+        capsules::virtual_rng::VirtualRngMasterDevice<'static>,
+        capsules::virtual_rng::VirtualRngMasterDevice::new(rng_mux)
+    );
+    let rng = static_init!(
+        capsules::rng::RngDriver<'static>,
+        capsules::rng::RngDriver::new(
+            rng_device,
+            board_kernel.create_grant(capsules::rng::DRIVER_NUM, &grant_cap)
+        )
+    );
+    rng_device.set_client(rng);
+
+    let csma_rng_device = static_init!(
+        capsules::virtual_rng::VirtualRngMasterDevice<'static>,
+        capsules::virtual_rng::VirtualRngMasterDevice::new(rng_mux)
+    );
+    let csma_random = static_init!(
+        capsules::rng::SynchronousRandom<'static>,
+        capsules::rng::SynchronousRandom::new(csma_rng_device)
+    );
+    csma_random.initialize();
 
     // For now, assign the 802.15.4 MAC address on the device as
     // simply a 16-bit short address which represents the last 16 bits
@@ -539,7 +601,9 @@ pub unsafe fn main() {
 
     // Can this initialize be pushed earlier, or into component? -pal
     let _ = rf233.initialize(&mut RF233_BUF, &mut RF233_REG_WRITE, &mut RF233_REG_READ);
-    let (_, mux_mac) = components::ieee802154::Ieee802154Component::new(
+    // The RF233 is configured with no frame or CSMA retries, so the MAC backs
+    // off and retransmits in software.
+    let (_, mux_mac) = components::ieee802154::Ieee802154CsmaComponent::new(
         board_kernel,
         capsules::ieee802154::DRIVER_NUM,
         rf233,
@@ -547,10 +611,13 @@ pub unsafe fn main() {
         PAN_ID,
         serial_num_bottom_16,
         dynamic_deferred_caller,
+        mux_alarm,
+        csma_random,
     )
-    .finalize(components::ieee802154_component_helper!(
+    .finalize(components::ieee802154_csma_component_helper!(
         capsules::rf233::RF233<'static, VirtualSpiMasterDevice<'static, sam4l::spi::SpiHw>>,
-        sam4l::aes::Aes<'static>
+        sam4l::aes::Aes<'static>,
+        sam4l::ast::Ast<'static>
     ));
 
     let usb_driver = UsbComponent::new(
@@ -581,32 +648,16 @@ pub unsafe fn main() {
         sam4l::flashcalw::FLASHCALW
     ));
 
-    let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
-        [
-            IPAddr([
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
-                0x0e, 0x0f,
-            ]),
-            IPAddr([
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
-                0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(src_mac_from_serial_num),
-        ]
-    );
-
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
-        mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num, //comment out for dual rx test only
-        //MacAddress::Short(49138), //comment in for dual rx test only
-        local_ip_ifaces,
-        mux_alarm,
-    )
-    .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
+    let (udp_send_mux, udp_recv_mux, udp_port_table, local_ip_ifaces) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            src_mac_from_serial_num, //comment out for dual rx test only
+            //MacAddress::Short(49138), //comment in for dual rx test only
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(sam4l::ast::Ast));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -619,6 +670,54 @@ pub unsafe fn main() {
     )
     .finalize(components::udp_driver_component_helper!(sam4l::ast::Ast));
 
+    let coap_driver = components::coap::CoapComponent::new(
+        board_kernel,
+        capsules::net::coap::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+    )
+    .finalize(components::coap_component_helper!(sam4l::ast::Ast));
+
+    let sntp = components::sntp::SntpComponent::new(
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        mux_alarm,
+        NTP_SERVER,
+    )
+    .finalize(components::sntp_component_helper!(sam4l::ast::Ast));
+
+    let wall_clock = components::wall_clock::WallClockComponent::new(
+        board_kernel,
+        capsules::wall_clock::DRIVER_NUM,
+        mux_alarm,
+        false,
+    )
+    .finalize(components::wall_clock_component_helper!(sam4l::ast::Ast));
+    wall_clock.set_time_source(sntp);
+    sntp.set_client(wall_clock);
+
+    // The SAM4L has no calendar RTC, so the date and time is kept in
+    // software over the AST.
+    let soft_date_time =
+        components::date_time::SoftDateTimeComponent::new(mux_alarm, dynamic_deferred_caller)
+            .finalize(components::soft_date_time_component_helper!(
+                sam4l::ast::Ast
+            ));
+    let date_time = components::date_time::DateTimeComponent::new(
+        board_kernel,
+        capsules::date_time::DRIVER_NUM,
+        soft_date_time,
+    )
+    .finalize(components::date_time_component_helper!(
+        capsules::soft_date_time::SoftDateTime<
+            'static,
+            VirtualMuxAlarm<'static, sam4l::ast::Ast<'static>>,
+        >
+    ));
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
 
@@ -640,6 +739,9 @@ pub unsafe fn main() {
         ipc: kernel::ipc::IPC::new(board_kernel, kernel::ipc::DRIVER_NUM, &grant_cap),
         ninedof,
         udp_driver,
+        coap_driver,
+        wall_clock,
+        date_time,
         usb_driver,
         nrf51822: nrf_serialization,
         nonvolatile_storage,
@@ -73,7 +73,11 @@ struct Imxrt1050EVKB {
         'static,
         VirtualMuxAlarm<'static, imxrt1050::gpt::Gpt1<'static>>,
     >,
-    button: &'static capsules::button::Button<'static, imxrt1050::gpio::Pin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        imxrt1050::gpio::Pin<'static>,
+        VirtualMuxAlarm<'static, imxrt1050::gpt::Gpt1<'static>>,
+    >,
     console: &'static capsules::console::Console<'static>,
     gpio: &'static capsules::gpio::GPIO<'static, imxrt1050::gpio::Pin<'static>>,
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
@@ -342,10 +346,17 @@ pub unsafe fn main() {
         LedLow::new(peripherals.ports.pin(imxrt1050::gpio::PinId::AdB0_09)),
     ));
 
+    // ALARM
+    let gpt1 = &peripherals.gpt1;
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(gpt1).finalize(
+        components::alarm_mux_component_helper!(imxrt1050::gpt::Gpt1),
+    );
+
     // BUTTONs
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             imxrt1050::gpio::Pin,
             (
@@ -355,13 +366,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(imxrt1050::gpio::Pin));
-
-    // ALARM
-    let gpt1 = &peripherals.gpt1;
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(gpt1).finalize(
-        components::alarm_mux_component_helper!(imxrt1050::gpt::Gpt1),
-    );
+    .finalize(components::button_component_buf!(
+        imxrt1050::gpio::Pin,
+        imxrt1050::gpt::Gpt1
+    ));
 
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
@@ -411,9 +411,6 @@ pub unsafe fn main() {
 
     // ---------- ETHERNET ----------
 
-    // Packet receive buffer
-    let ethmac0_rxbuf0 = static_init!([u8; 1522], [0; 1522]);
-
     // ETHMAC peripheral
     let ethmac0 = static_init!(
         litex_vexriscv::liteeth::LiteEth<socc::SoCRegisterFmt>,
@@ -427,7 +424,8 @@ pub unsafe fn main() {
             socc::ETHMAC_SLOT_SIZE,
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
-            ethmac0_rxbuf0,
+            // Default MAC address of the LiteX SoC builder
+            kernel::hil::ethernet::MacAddress([0x10, 0xe2, 0xd5, 0x00, 0x00, 0x00]),
         )
     );
 
@@ -9,6 +9,7 @@ use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
 use kernel::capabilities;
 use kernel::component::Component;
 use kernel::dynamic_deferred_call::{DynamicDeferredCall, DynamicDeferredCallClientState};
+use kernel::hil::ethernet::Ethernet;
 use kernel::hil::led::LedHigh;
 use kernel::hil::time::{Alarm, Timer};
 use kernel::platform::chip::InterruptService;
@@ -114,6 +115,15 @@ struct LiteXSim {
     button_driver: &'static capsules::button::Button<
         'static,
         litex_vexriscv::gpio::LiteXGPIOPin<'static, 'static, socc::SoCRegisterFmt>,
+        VirtualMuxAlarm<
+            'static,
+            litex_vexriscv::timer::LiteXAlarm<
+                'static,
+                'static,
+                socc::SoCRegisterFmt,
+                socc::ClockFrequency,
+            >,
+        >,
     >,
     led_driver: &'static capsules::led::LedDriver<
         'static,
@@ -140,6 +150,7 @@ struct LiteXSim {
             >,
         >,
     >,
+    udp_driver: &'static capsules::net::udp::UDPDriver<'static>,
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
     scheduler: &'static CooperativeSched<'static>,
     scheduler_timer: &'static VirtualSchedulerTimer<
@@ -168,6 +179,7 @@ impl SyscallDriverLookup for LiteXSim {
             capsules::console::DRIVER_NUM => f(Some(self.console)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::low_level_debug::DRIVER_NUM => f(Some(self.lldb)),
+            capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
         }
@@ -389,9 +401,6 @@ pub unsafe fn main() {
 
     // ---------- ETHERNET ----------
 
-    // Packet receive buffer
-    let ethmac0_rxbuf0 = static_init!([u8; 1522], [0; 1522]);
-
     // ETHMAC peripheral
     let ethmac0 = static_init!(
         litex_vexriscv::liteeth::LiteEth<socc::SoCRegisterFmt>,
@@ -405,13 +414,54 @@ pub unsafe fn main() {
             socc::ETHMAC_SLOT_SIZE,
             socc::ETHMAC_RX_SLOTS,
             socc::ETHMAC_TX_SLOTS,
-            ethmac0_rxbuf0,
+            // Default MAC address of the LiteX SoC builder
+            kernel::hil::ethernet::MacAddress([0x10, 0xe2, 0xd5, 0x00, 0x00, 0x00]),
         )
     );
 
     // Initialize the ETHMAC controller
     ethmac0.initialize();
 
+    // ---------- UDP OVER ETHERNET ----------
+
+    type LiteXAlarm = litex_vexriscv::timer::LiteXAlarm<
+        'static,
+        'static,
+        socc::SoCRegisterFmt,
+        socc::ClockFrequency,
+    >;
+    type LiteEth = litex_vexriscv::liteeth::LiteEth<'static, socc::SoCRegisterFmt>;
+
+    let local_ip_ifaces = static_init!(
+        [core::cell::Cell<capsules::net::ipv6::ip_utils::IPAddr>; 1],
+        [core::cell::Cell::new(
+            capsules::net::ethernet::link_local_address(&ethmac0.mac_address())
+        )]
+    );
+
+    let (udp_send_mux, udp_recv_mux, udp_port_table) =
+        components::udp_mux_ethernet::UDPMuxEthernetComponent::new(
+            ethmac0,
+            local_ip_ifaces,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_ethernet_component_helper!(
+            LiteEth, LiteXAlarm
+        ));
+
+    let udp_driver = components::udp_driver::UDPDriverComponent::new(
+        board_kernel,
+        capsules::net::udp::DRIVER_NUM,
+        udp_send_mux,
+        udp_recv_mux,
+        udp_port_table,
+        local_ip_ifaces,
+    )
+    .finalize(components::udp_driver_component_helper!(
+        ethernet: LiteEth,
+        LiteXAlarm
+    ));
+
     // --------- GPIO CONTROLLER ----------
     type GPIOPin = litex_vexriscv::gpio::LiteXGPIOPin<'static, 'static, socc::SoCRegisterFmt>;
 
@@ -489,6 +539,7 @@ pub unsafe fn main() {
     let button_driver = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper_owned!(
             GPIOPin,
             (
@@ -533,7 +584,15 @@ pub unsafe fn main() {
             ),
         ),
     )
-    .finalize(components::button_component_buf!(GPIOPin));
+    .finalize(components::button_component_buf!(
+        GPIOPin,
+        litex_vexriscv::timer::LiteXAlarm<
+            'static,
+            'static,
+            socc::SoCRegisterFmt,
+            socc::ClockFrequency,
+        >
+    ));
 
     // ---------- INITIALIZE CHIP, ENABLE INTERRUPTS ----------
 
@@ -614,6 +673,7 @@ pub unsafe fn main() {
         console: console,
         alarm: alarm,
         lldb: lldb,
+        udp_driver,
         ipc: kernel::ipc::IPC::new(
             board_kernel,
             kernel::ipc::DRIVER_NUM,
@@ -96,13 +96,25 @@ pub struct MicroBit {
         >,
         25,
     >,
-    button: &'static capsules::button::Button<'static, nrf52::gpio::GPIOPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        nrf52::gpio::GPIOPin<'static>,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
+    >,
     rng: &'static capsules::rng::RngDriver<'static>,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     lsm303agr: &'static capsules::lsm303agr::Lsm303agrI2C<'static>,
     temperature: &'static capsules::temperature::TemperatureSensor<'static>,
+    sensor_sampling: &'static capsules::sensor_sampling::SensorSampling<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52833::rtc::Rtc<'static>>,
+    >,
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
     adc: &'static capsules::adc::AdcVirtualized<'static>,
+    adc_stream: &'static capsules::adc_stream::AdcStream<
+        'static,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52833::rtc::Rtc<'static>>,
+    >,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         capsules::virtual_alarm::VirtualMuxAlarm<'static, nrf52::rtc::Rtc<'static>>,
@@ -131,8 +143,10 @@ impl SyscallDriverLookup for MicroBit {
             capsules::led::DRIVER_NUM => f(Some(self.led)),
             capsules::ninedof::DRIVER_NUM => f(Some(self.ninedof)),
             capsules::adc::DRIVER_NUM => f(Some(self.adc)),
+            capsules::adc_stream::DRIVER_NUM => f(Some(self.adc_stream)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temperature)),
             capsules::lsm303agr::DRIVER_NUM => f(Some(self.lsm303agr)),
+            capsules::sensor_sampling::DRIVER_NUM => f(Some(self.sensor_sampling)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::buzzer_driver::DRIVER_NUM => f(Some(self.buzzer)),
@@ -250,23 +264,53 @@ pub unsafe fn main() {
     )
     .finalize(components::gpio_component_buf!(nrf52833::gpio::GPIOPin));
 
+    //--------------------------------------------------------------------------
+    // Deferred Call (Dynamic) Setup
+    //--------------------------------------------------------------------------
+
+    let dynamic_deferred_call_clients =
+        static_init!([DynamicDeferredCallClientState; 3], Default::default());
+    let dynamic_deferred_caller = static_init!(
+        DynamicDeferredCall,
+        DynamicDeferredCall::new(dynamic_deferred_call_clients)
+    );
+    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
+
+    //--------------------------------------------------------------------------
+    // ALARM & TIMER
+    //--------------------------------------------------------------------------
+
+    let rtc = &base_peripherals.rtc;
+    let _ = rtc.start();
+
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(rtc)
+        .finalize(components::alarm_mux_component_helper!(nrf52::rtc::Rtc));
+
     //--------------------------------------------------------------------------
     // Buttons
     //--------------------------------------------------------------------------
+    // The mechanical buttons bounce, unlike the touch logo
+    let button_debounce = capsules::button::ButtonConfig {
+        debounce_ms: 20,
+        ..capsules::button::ButtonConfig::default()
+    };
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             nrf52833::gpio::GPIOPin,
             (
                 &nrf52833_peripherals.gpio_port[BUTTON_A],
                 kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullNone
+                kernel::hil::gpio::FloatingState::PullNone,
+                button_debounce
             ), // A
             (
                 &nrf52833_peripherals.gpio_port[BUTTON_B],
                 kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullNone
+                kernel::hil::gpio::FloatingState::PullNone,
+                button_debounce
             ), // B
             (
                 &nrf52833_peripherals.gpio_port[TOUCH_LOGO],
@@ -275,29 +319,11 @@ pub unsafe fn main() {
             ), // Touch Logo
         ),
     )
-    .finalize(components::button_component_buf!(nrf52833::gpio::GPIOPin));
-
-    //--------------------------------------------------------------------------
-    // Deferred Call (Dynamic) Setup
-    //--------------------------------------------------------------------------
-
-    let dynamic_deferred_call_clients =
-        static_init!([DynamicDeferredCallClientState; 3], Default::default());
-    let dynamic_deferred_caller = static_init!(
-        DynamicDeferredCall,
-        DynamicDeferredCall::new(dynamic_deferred_call_clients)
-    );
-    DynamicDeferredCall::set_global_instance(dynamic_deferred_caller);
-
-    //--------------------------------------------------------------------------
-    // ALARM & TIMER
-    //--------------------------------------------------------------------------
-
-    let rtc = &base_peripherals.rtc;
-    let _ = rtc.start();
+    .finalize(components::button_component_buf!(
+        nrf52833::gpio::GPIOPin,
+        nrf52::rtc::Rtc
+    ));
 
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(rtc)
-        .finalize(components::alarm_mux_component_helper!(nrf52::rtc::Rtc));
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
         capsules::alarm::DRIVER_NUM,
@@ -436,6 +462,24 @@ pub unsafe fn main() {
     )
     .finalize(());
 
+    // Samples the temperature of the LSM303AGR, which is otherwise unused, in
+    // the background
+    let sampled_temperature = static_init!(
+        capsules::sensor_sampling::SampledTemperature<'static>,
+        capsules::sensor_sampling::SampledTemperature::new(lsm303agr)
+    );
+    kernel::hil::sensors::TemperatureDriver::set_client(lsm303agr, sampled_temperature);
+
+    let sensor_sampling = components::sensor_sampling::SensorSamplingComponent::new(
+        board_kernel,
+        capsules::sensor_sampling::DRIVER_NUM,
+        mux_alarm,
+    )
+    .finalize(components::sensor_sampling_component_helper!(
+        nrf52833::rtc::Rtc,
+        sampled_temperature
+    ));
+
     //--------------------------------------------------------------------------
     // ADC
     //--------------------------------------------------------------------------
@@ -468,6 +512,31 @@ pub unsafe fn main() {
                 .finalize(components::adc_component_helper!(nrf52833::adc::Adc))
             ));
 
+    // Streams samples from P0, P1 and P2, alongside the single samples above
+    let adc_stream = components::adc::AdcStreamComponent::new(
+        board_kernel,
+        capsules::adc_stream::DRIVER_NUM,
+        mux_alarm,
+    )
+    .finalize(components::adc_stream_component_helper!(
+        nrf52833::rtc::Rtc,
+        components::adc::AdcComponent::new(
+            &adc_mux,
+            nrf52833::adc::AdcChannelSetup::new(nrf52833::adc::AdcChannel::AnalogInput0)
+        )
+        .finalize(components::adc_component_helper!(nrf52833::adc::Adc)),
+        components::adc::AdcComponent::new(
+            &adc_mux,
+            nrf52833::adc::AdcChannelSetup::new(nrf52833::adc::AdcChannel::AnalogInput1)
+        )
+        .finalize(components::adc_component_helper!(nrf52833::adc::Adc)),
+        components::adc::AdcComponent::new(
+            &adc_mux,
+            nrf52833::adc::AdcChannelSetup::new(nrf52833::adc::AdcChannel::AnalogInput2)
+        )
+        .finalize(components::adc_component_helper!(nrf52833::adc::Adc))
+    ));
+
     // Microphone
 
     let adc_microphone = components::adc_microphone::AdcMicrophoneComponent::new().finalize(
@@ -649,11 +718,13 @@ pub unsafe fn main() {
         led,
         rng,
         temperature,
+        sensor_sampling,
         lsm303agr,
         ninedof,
         buzzer,
         sound_pressure,
         adc: adc_syscall,
+        adc_stream,
         alarm,
         app_flash,
         ipc: kernel::ipc::IPC::new(
@@ -51,7 +51,11 @@ struct MspExp432P401R {
         3,
     >,
     console: &'static capsules::console::Console<'static>,
-    button: &'static capsules::button::Button<'static, msp432::gpio::IntPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        msp432::gpio::IntPin<'static>,
+        capsules::virtual_alarm::VirtualMuxAlarm<'static, msp432::timer::TimerA<'static>>,
+    >,
     gpio: &'static capsules::gpio::GPIO<'static, msp432::gpio::IntPin<'static>>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
@@ -235,26 +239,6 @@ pub unsafe fn main() {
     );
     CHIP = Some(chip);
 
-    // Setup buttons
-    let button = components::button::ButtonComponent::new(
-        board_kernel,
-        capsules::button::DRIVER_NUM,
-        components::button_component_helper!(
-            msp432::gpio::IntPin,
-            (
-                &peripherals.gpio.int_pins[msp432::gpio::IntPinNr::P01_1 as usize],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ),
-            (
-                &peripherals.gpio.int_pins[msp432::gpio::IntPinNr::P01_4 as usize],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            )
-        ),
-    )
-    .finalize(components::button_component_buf!(msp432::gpio::IntPin));
-
     // Setup LEDs
     let leds = components::led::LedsComponent::new().finalize(components::led_component_helper!(
         kernel::hil::led::LedHigh<'static, msp432::gpio::IntPin>,
@@ -355,6 +339,31 @@ pub unsafe fn main() {
     let mux_alarm = components::alarm::AlarmMuxComponent::new(timer0).finalize(
         components::alarm_mux_component_helper!(msp432::timer::TimerA),
     );
+
+    // Setup buttons
+    let button = components::button::ButtonComponent::new(
+        board_kernel,
+        capsules::button::DRIVER_NUM,
+        mux_alarm,
+        components::button_component_helper!(
+            msp432::gpio::IntPin,
+            (
+                &peripherals.gpio.int_pins[msp432::gpio::IntPinNr::P01_1 as usize],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp
+            ),
+            (
+                &peripherals.gpio.int_pins[msp432::gpio::IntPinNr::P01_4 as usize],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp
+            )
+        ),
+    )
+    .finalize(components::button_component_buf!(
+        msp432::gpio::IntPin,
+        msp432::timer::TimerA
+    ));
+
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
         capsules::alarm::DRIVER_NUM,
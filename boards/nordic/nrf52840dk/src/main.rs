@@ -39,6 +39,16 @@
 //! | 21 | P0.31 AIN7 | P2 6   | A5      |
 //! | 22 | P0.02 AIN0 | P4 8   | AVDD    |
 //!
+//! ### `PWM`
+//!
+//! | Channel | Pin   | Header | Arduino |
+//! |---------|-------|--------|---------|
+//! | 0       | P0.03 | P2 1   | A0      |
+//! | 1       | P0.04 | P2 2   | A1      |
+//!
+//! The PWM channels share the single PWM0 peripheral, so only one of them
+//! outputs at a time.
+//!
 //! ### Onboard Functions
 //!
 //! | Pin   | Header | Function |
@@ -71,7 +81,6 @@
 
 use capsules::i2c_master_slave_driver::I2CMasterSlaveDriver;
 use capsules::net::ieee802154::MacAddress;
-use capsules::net::ipv6::ip_utils::IPAddr;
 use capsules::virtual_aes_ccm::MuxAES128CCM;
 use capsules::virtual_alarm::VirtualMuxAlarm;
 use kernel::component::Component;
@@ -121,10 +130,12 @@ const SPI_MX25R6435F_HOLD_PIN: Pin = Pin::P0_23;
 const I2C_SDA_PIN: Pin = Pin::P0_26;
 const I2C_SCL_PIN: Pin = Pin::P0_27;
 
+/// PWM pins
+const PWM_A0_PIN: Pin = Pin::P0_03;
+const PWM_A1_PIN: Pin = Pin::P0_04;
+
 // Constants related to the configuration of the 15.4 network stack
 const PAN_ID: u16 = 0xABCD;
-const DST_MAC_ADDR: capsules::net::ieee802154::MacAddress =
-    capsules::net::ieee802154::MacAddress::Short(49138);
 const DEFAULT_CTX_PREFIX_LEN: u8 = 8; //Length of context for 6LoWPAN compression
 const DEFAULT_CTX_PREFIX: [u8; 16] = [0x0 as u8; 16]; //Context for 6LoWPAN Compression
 
@@ -161,8 +172,17 @@ pub struct Platform {
         nrf52840::ble_radio::Radio<'static>,
         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
     >,
+    ble_gatt: &'static capsules::ble_gatt_driver::BleGattDriver<
+        'static,
+        nrf52840::ble_radio::Radio<'static>,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
     ieee802154_radio: &'static capsules::ieee802154::RadioDriver<'static>,
-    button: &'static capsules::button::Button<'static, nrf52840::gpio::GPIOPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        nrf52840::gpio::GPIOPin<'static>,
+        VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
+    >,
     pconsole: &'static capsules::process_console::ProcessConsole<
         'static,
         VirtualMuxAlarm<'static, nrf52840::rtc::Rtc<'static>>,
@@ -175,9 +195,14 @@ pub struct Platform {
         kernel::hil::led::LedLow<'static, nrf52840::gpio::GPIOPin<'static>>,
         4,
     >,
+    pwm: &'static capsules::pwm::Pwm<'static, 2>,
     rng: &'static capsules::rng::RngDriver<'static>,
     temp: &'static capsules::temperature::TemperatureSensor<'static>,
     ipc: kernel::ipc::IPC<{ NUM_PROCS as u8 }>,
+    shared_memory: &'static kernel::shared_memory::SharedMemory,
+    ipc_message: &'static capsules::ipc_message::IpcMessage<components::ipc_message::Capability>,
+    process_info:
+        &'static capsules::process_info::ProcessInfo<components::process_info::Capability>,
     analog_comparator: &'static capsules::analog_comparator::AnalogComparator<
         'static,
         nrf52840::acomp::Comparator<'static>,
@@ -207,15 +232,20 @@ impl SyscallDriverLookup for Platform {
             capsules::gpio::DRIVER_NUM => f(Some(self.gpio)),
             capsules::alarm::DRIVER_NUM => f(Some(self.alarm)),
             capsules::led::DRIVER_NUM => f(Some(self.led)),
+            capsules::pwm::DRIVER_NUM => f(Some(self.pwm)),
             capsules::button::DRIVER_NUM => f(Some(self.button)),
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
+            capsules::ble_gatt_driver::DRIVER_NUM => f(Some(self.ble_gatt)),
             capsules::ieee802154::DRIVER_NUM => f(Some(self.ieee802154_radio)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temp)),
             capsules::analog_comparator::DRIVER_NUM => f(Some(self.analog_comparator)),
             capsules::nonvolatile_storage_driver::DRIVER_NUM => f(Some(self.nonvolatile_storage)),
             capsules::net::udp::DRIVER_NUM => f(Some(self.udp_driver)),
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
+            kernel::shared_memory::DRIVER_NUM => f(Some(self.shared_memory)),
+            capsules::ipc_message::DRIVER_NUM => f(Some(self.ipc_message)),
+            capsules::process_info::DRIVER_NUM => f(Some(self.process_info)),
             capsules::i2c_master_slave_driver::DRIVER_NUM => f(Some(self.i2c_master_slave)),
             capsules::spi_controller::DRIVER_NUM => f(Some(self.spi_controller)),
             _ => f(None),
@@ -246,7 +276,7 @@ impl KernelResources<nrf52840::chip::NRF52<'static, Nrf52840DefaultPeripherals<'
     type Scheduler = RoundRobinSched<'static>;
     type SchedulerTimer = cortexm4::systick::SysTick;
     type WatchDog = ();
-    type ContextSwitchCallback = ();
+    type ContextSwitchCallback = kernel::shared_memory::SharedMemory;
 
     fn syscall_driver_lookup(&self) -> &Self::SyscallDriverLookup {
         &self
@@ -267,7 +297,7 @@ impl KernelResources<nrf52840::chip::NRF52<'static, Nrf52840DefaultPeripherals<'
         &()
     }
     fn context_switch_callback(&self) -> &Self::ContextSwitchCallback {
-        &()
+        self.shared_memory
     }
 }
 
@@ -322,35 +352,6 @@ pub unsafe fn main() {
     )
     .finalize(components::gpio_component_buf!(nrf52840::gpio::GPIOPin));
 
-    let button = components::button::ButtonComponent::new(
-        board_kernel,
-        capsules::button::DRIVER_NUM,
-        components::button_component_helper!(
-            nrf52840::gpio::GPIOPin,
-            (
-                &nrf52840_peripherals.gpio_port[BUTTON1_PIN],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ), //13
-            (
-                &nrf52840_peripherals.gpio_port[BUTTON2_PIN],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ), //14
-            (
-                &nrf52840_peripherals.gpio_port[BUTTON3_PIN],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ), //15
-            (
-                &nrf52840_peripherals.gpio_port[BUTTON4_PIN],
-                kernel::hil::gpio::ActivationMode::ActiveLow,
-                kernel::hil::gpio::FloatingState::PullUp
-            ) //16
-        ),
-    )
-    .finalize(components::button_component_buf!(nrf52840::gpio::GPIOPin));
-
     let led = components::led::LedsComponent::new().finalize(components::led_component_helper!(
         LedLow<'static, nrf52840::gpio::GPIOPin>,
         LedLow::new(&nrf52840_peripherals.gpio_port[LED1_PIN]),
@@ -359,6 +360,22 @@ pub unsafe fn main() {
         LedLow::new(&nrf52840_peripherals.gpio_port[LED4_PIN]),
     ));
 
+    let mux_pwm = components::pwm::PwmMuxComponent::new(&base_peripherals.pwm0)
+        .finalize(components::pwm_mux_component_helper!(nrf52840::pwm::Pwm));
+    let pwm_a0 = components::pwm::PwmPinUserComponent::new(
+        mux_pwm,
+        nrf52840::pinmux::Pinmux::new(PWM_A0_PIN as u32),
+    )
+    .finalize(components::pwm_pin_user_component_helper!(
+        nrf52840::pwm::Pwm
+    ));
+    let pwm_a1 = components::pwm::PwmPinUserComponent::new(
+        mux_pwm,
+        nrf52840::pinmux::Pinmux::new(PWM_A1_PIN as u32),
+    )
+    .finalize(components::pwm_pin_user_component_helper!(
+        nrf52840::pwm::Pwm
+    ));
     let chip = static_init!(
         nrf52840::chip::NRF52<Nrf52840DefaultPeripherals>,
         nrf52840::chip::NRF52::new(nrf52840_peripherals)
@@ -391,6 +408,51 @@ pub unsafe fn main() {
     let _ = rtc.start();
     let mux_alarm = components::alarm::AlarmMuxComponent::new(rtc)
         .finalize(components::alarm_mux_component_helper!(nrf52840::rtc::Rtc));
+
+    let pwm = components::pwm::PwmDriverComponent::new(board_kernel, capsules::pwm::DRIVER_NUM)
+        .finalize(components::pwm_driver_component_helper!(pwm_a0, pwm_a1));
+
+    let button_debounce = capsules::button::ButtonConfig {
+        debounce_ms: 20,
+        ..capsules::button::ButtonConfig::default()
+    };
+    let button = components::button::ButtonComponent::new(
+        board_kernel,
+        capsules::button::DRIVER_NUM,
+        mux_alarm,
+        components::button_component_helper!(
+            nrf52840::gpio::GPIOPin,
+            (
+                &nrf52840_peripherals.gpio_port[BUTTON1_PIN],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp,
+                button_debounce
+            ), //13
+            (
+                &nrf52840_peripherals.gpio_port[BUTTON2_PIN],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp,
+                button_debounce
+            ), //14
+            (
+                &nrf52840_peripherals.gpio_port[BUTTON3_PIN],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp,
+                button_debounce
+            ), //15
+            (
+                &nrf52840_peripherals.gpio_port[BUTTON4_PIN],
+                kernel::hil::gpio::ActivationMode::ActiveLow,
+                kernel::hil::gpio::FloatingState::PullUp,
+                button_debounce
+            ) //16
+        ),
+    )
+    .finalize(components::button_component_buf!(
+        nrf52840::gpio::GPIOPin,
+        nrf52840::rtc::Rtc
+    ));
+
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
         capsules::alarm::DRIVER_NUM,
@@ -450,6 +512,23 @@ pub unsafe fn main() {
     )
     .finalize(());
 
+    // The device address doubles as a static random address once its two
+    // most significant bits are set
+    let mut ble_address = nrf52840::ficr::FICR_INSTANCE.address();
+    ble_address[5] |= 0xc0;
+    let ble_gatt = components::ble_gatt::BleGattComponent::new(
+        board_kernel,
+        capsules::ble_gatt_driver::DRIVER_NUM,
+        &base_peripherals.ble_radio,
+        mux_alarm,
+        ble_address,
+        b"Tock",
+    )
+    .finalize(components::ble_gatt_component_helper!(
+        nrf52840::ble_radio::Radio<'static>,
+        nrf52840::rtc::Rtc<'static>
+    ));
+
     let aes_mux = static_init!(
         MuxAES128CCM<'static, nrf52840::aes::AesECB>,
         MuxAES128CCM::new(&base_peripherals.ecb, dynamic_deferred_caller)
@@ -476,33 +555,26 @@ pub unsafe fn main() {
         nrf52840::aes::AesECB<'static>
     ));
 
-    let local_ip_ifaces = static_init!(
-        [IPAddr; 3],
-        [
-            IPAddr([
-                0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d,
-                0x0e, 0x0f,
-            ]),
-            IPAddr([
-                0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d,
-                0x1e, 0x1f,
-            ]),
-            IPAddr::generate_from_mac(capsules::net::ieee802154::MacAddress::Short(
-                serial_num_bottom_16
-            )),
-        ]
-    );
-
-    let (udp_send_mux, udp_recv_mux, udp_port_table) = components::udp_mux::UDPMuxComponent::new(
+    components::ieee802154::Ieee802154MlmeComponent::new(
+        ieee802154_radio,
         mux_mac,
-        DEFAULT_CTX_PREFIX_LEN,
-        DEFAULT_CTX_PREFIX,
-        DST_MAC_ADDR,
-        src_mac_from_serial_num,
-        local_ip_ifaces,
+        &base_peripherals.ieee802154_radio,
         mux_alarm,
     )
-    .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
+    .finalize(components::ieee802154_mlme_component_helper!(
+        nrf52840::ieee802154_radio::Radio,
+        nrf52840::rtc::Rtc<'static>
+    ));
+
+    let (udp_send_mux, udp_recv_mux, udp_port_table, local_ip_ifaces) =
+        components::udp_mux::UDPMuxComponent::new(
+            mux_mac,
+            DEFAULT_CTX_PREFIX_LEN,
+            DEFAULT_CTX_PREFIX,
+            src_mac_from_serial_num,
+            mux_alarm,
+        )
+        .finalize(components::udp_mux_component_helper!(nrf52840::rtc::Rtc));
 
     // UDP driver initialization happens here
     let udp_driver = components::udp_driver::UDPDriverComponent::new(
@@ -660,16 +732,36 @@ pub unsafe fn main() {
     // ctap.enable();
     // ctap.attach();
 
+    let shared_memory = components::shared_memory::SharedMemoryComponent::new(
+        board_kernel,
+        kernel::shared_memory::DRIVER_NUM,
+    )
+    .finalize(());
+
+    let ipc_message = components::ipc_message::IpcMessageComponent::new(
+        board_kernel,
+        capsules::ipc_message::DRIVER_NUM,
+    )
+    .finalize(());
+
+    let process_info = components::process_info::ProcessInfoComponent::new(
+        board_kernel,
+        capsules::process_info::DRIVER_NUM,
+    )
+    .finalize(());
+
     let scheduler = components::sched::round_robin::RoundRobinComponent::new(&PROCESSES)
         .finalize(components::rr_component_helper!(NUM_PROCS));
 
     let platform = Platform {
         button,
         ble_radio,
+        ble_gatt,
         ieee802154_radio,
         pconsole,
         console,
         led,
+        pwm,
         gpio,
         rng,
         temp,
@@ -682,6 +774,9 @@ pub unsafe fn main() {
             kernel::ipc::DRIVER_NUM,
             &memory_allocation_capability,
         ),
+        shared_memory,
+        ipc_message,
+        process_info,
         i2c_master_slave,
         spi_controller,
         scheduler,
@@ -59,7 +59,11 @@ struct NucleoF446RE {
         LedHigh<'static, stm32f446re::gpio::Pin<'static>>,
         1,
     >,
-    button: &'static capsules::button::Button<'static, stm32f446re::gpio::Pin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        stm32f446re::gpio::Pin<'static>,
+        VirtualMuxAlarm<'static, stm32f446re::tim2::Tim2<'static>>,
+    >,
     adc: &'static capsules::adc::AdcVirtualized<'static>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
@@ -353,10 +357,17 @@ pub unsafe fn main() {
         LedHigh::new(gpio_ports.get_pin(stm32f446re::gpio::PinId::PA05).unwrap()),
     ));
 
+    // ALARM
+    let tim2 = &base_peripherals.tim2;
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
+        components::alarm_mux_component_helper!(stm32f446re::tim2::Tim2),
+    );
+
     // BUTTONs
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             stm32f446re::gpio::Pin,
             (
@@ -366,13 +377,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(stm32f446re::gpio::Pin));
-
-    // ALARM
-    let tim2 = &base_peripherals.tim2;
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
-        components::alarm_mux_component_helper!(stm32f446re::tim2::Tim2),
-    );
+    .finalize(components::button_component_buf!(
+        stm32f446re::gpio::Pin,
+        stm32f446re::tim2::Tim2
+    ));
 
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
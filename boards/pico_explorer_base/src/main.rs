@@ -78,7 +78,11 @@ pub struct PicoExplorerBase {
     adc: &'static capsules::adc::AdcVirtualized<'static>,
     temperature: &'static capsules::temperature::TemperatureSensor<'static>,
 
-    button: &'static capsules::button::Button<'static, RPGpioPin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        RPGpioPin<'static>,
+        VirtualMuxAlarm<'static, rp2040::timer::RPTimer<'static>>,
+    >,
     screen: &'static capsules::screen::Screen<'static>,
 
     scheduler: &'static RoundRobinSched<'static>,
@@ -446,6 +450,7 @@ pub unsafe fn main() {
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             RPGpioPin,
             (
@@ -470,7 +475,7 @@ pub unsafe fn main() {
             ), // Y
         ),
     )
-    .finalize(components::button_component_buf!(RPGpioPin));
+    .finalize(components::button_component_buf!(RPGpioPin, RPTimer));
 
     let screen = components::screen::ScreenComponent::new(
         board_kernel,
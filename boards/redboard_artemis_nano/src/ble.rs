@@ -72,9 +72,7 @@ impl Component for BLEComponent {
                 ble_radio_virtual_alarm
             )
         );
-        kernel::hil::ble_advertising::BleAdvertisementDriver::set_receive_client(
-            self.radio, ble_radio,
-        );
+        kernel::hil::ble_advertising::BleScanDriver::set_scan_client(self.radio, ble_radio);
         kernel::hil::ble_advertising::BleAdvertisementDriver::set_transmit_client(
             self.radio, ble_radio,
         );
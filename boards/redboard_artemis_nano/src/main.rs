@@ -93,6 +93,7 @@ struct RedboardArtemisNano {
     >,
     temperature: &'static capsules::temperature::TemperatureSensor<'static>,
     humidity: &'static capsules::humidity::HumiditySensor<'static>,
+    pressure: &'static capsules::pressure::PressureSensor<'static>,
     air_quality: &'static capsules::air_quality::AirQualitySensor<'static>,
     scheduler: &'static RoundRobinSched<'static>,
     systick: cortexm4::systick::SysTick,
@@ -113,6 +114,7 @@ impl SyscallDriverLookup for RedboardArtemisNano {
             capsules::ble_advertising_driver::DRIVER_NUM => f(Some(self.ble_radio)),
             capsules::temperature::DRIVER_NUM => f(Some(self.temperature)),
             capsules::humidity::DRIVER_NUM => f(Some(self.humidity)),
+            capsules::pressure::DRIVER_NUM => f(Some(self.pressure)),
             capsules::air_quality::DRIVER_NUM => f(Some(self.air_quality)),
             _ => f(None),
         }
@@ -301,6 +303,12 @@ unsafe fn setup() -> (
         bme280,
     )
     .finalize(());
+    let pressure = components::pressure::PressureComponent::new(
+        board_kernel,
+        capsules::pressure::DRIVER_NUM,
+        bme280,
+    )
+    .finalize(());
     BME280 = Some(bme280);
 
     let ccs811 = Ccs811Component::new(mux_i2c, 0x5B, dynamic_deferred_caller)
@@ -362,6 +370,7 @@ unsafe fn setup() -> (
             ble_radio,
             temperature,
             humidity,
+            pressure,
             air_quality,
             scheduler,
             systick,
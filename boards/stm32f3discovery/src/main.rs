@@ -62,7 +62,11 @@ struct STM32F3Discovery {
         LedHigh<'static, stm32f303xc::gpio::Pin<'static>>,
         8,
     >,
-    button: &'static capsules::button::Button<'static, stm32f303xc::gpio::Pin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        stm32f303xc::gpio::Pin<'static>,
+        VirtualMuxAlarm<'static, stm32f303xc::tim2::Tim2<'static>>,
+    >,
     ninedof: &'static capsules::ninedof::NineDof<'static>,
     l3gd20: &'static capsules::l3gd20::L3gd20Spi<'static>,
     lsm303dlhc: &'static capsules::lsm303dlhc::Lsm303dlhcI2C<'static>,
@@ -489,10 +493,18 @@ pub unsafe fn main() {
         ),
     ));
 
+    // ALARM
+
+    let tim2 = &peripherals.tim2;
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
+        components::alarm_mux_component_helper!(stm32f303xc::tim2::Tim2),
+    );
+
     // BUTTONs
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             stm32f303xc::gpio::Pin<'static>,
             (
@@ -506,16 +518,10 @@ pub unsafe fn main() {
         ),
     )
     .finalize(components::button_component_buf!(
-        stm32f303xc::gpio::Pin<'static>
+        stm32f303xc::gpio::Pin<'static>,
+        stm32f303xc::tim2::Tim2
     ));
 
-    // ALARM
-
-    let tim2 = &peripherals.tim2;
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
-        components::alarm_mux_component_helper!(stm32f303xc::tim2::Tim2),
-    );
-
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
         capsules::alarm::DRIVER_NUM,
@@ -52,7 +52,11 @@ struct STM32F412GDiscovery {
         LedLow<'static, stm32f412g::gpio::Pin<'static>>,
         4,
     >,
-    button: &'static capsules::button::Button<'static, stm32f412g::gpio::Pin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        stm32f412g::gpio::Pin<'static>,
+        VirtualMuxAlarm<'static, stm32f412g::tim2::Tim2<'static>>,
+    >,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
         VirtualMuxAlarm<'static, stm32f412g::tim2::Tim2<'static>>,
@@ -496,10 +500,18 @@ pub unsafe fn main() {
         ),
     ));
 
+    // ALARM
+
+    let tim2 = &base_peripherals.tim2;
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
+        components::alarm_mux_component_helper!(stm32f412g::tim2::Tim2),
+    );
+
     // BUTTONs
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             stm32f412g::gpio::Pin,
             // Select
@@ -549,14 +561,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(stm32f412g::gpio::Pin));
-
-    // ALARM
-
-    let tim2 = &base_peripherals.tim2;
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
-        components::alarm_mux_component_helper!(stm32f412g::tim2::Tim2),
-    );
+    .finalize(components::button_component_buf!(
+        stm32f412g::gpio::Pin,
+        stm32f412g::tim2::Tim2
+    ));
 
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
@@ -53,7 +53,11 @@ struct STM32F429IDiscovery {
         LedHigh<'static, stm32f429zi::gpio::Pin<'static>>,
         4,
     >,
-    button: &'static capsules::button::Button<'static, stm32f429zi::gpio::Pin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        stm32f429zi::gpio::Pin<'static>,
+        VirtualMuxAlarm<'static, stm32f429zi::tim2::Tim2<'static>>,
+    >,
     adc: &'static capsules::adc::AdcVirtualized<'static>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
@@ -367,10 +371,18 @@ pub unsafe fn main() {
         LedHigh::new(gpio_ports.get_pin(stm32f429zi::gpio::PinId::PC05).unwrap()),
     ));
 
+    // ALARM
+
+    let tim2 = &base_peripherals.tim2;
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
+        components::alarm_mux_component_helper!(stm32f429zi::tim2::Tim2),
+    );
+
     // BUTTONs
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             stm32f429zi::gpio::Pin,
             (
@@ -380,14 +392,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(stm32f429zi::gpio::Pin));
-
-    // ALARM
-
-    let tim2 = &base_peripherals.tim2;
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
-        components::alarm_mux_component_helper!(stm32f429zi::tim2::Tim2),
-    );
+    .finalize(components::button_component_buf!(
+        stm32f429zi::gpio::Pin,
+        stm32f429zi::tim2::Tim2
+    ));
 
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
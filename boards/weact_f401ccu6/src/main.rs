@@ -52,7 +52,11 @@ struct WeactF401CC {
         LedLow<'static, stm32f401cc::gpio::Pin<'static>>,
         1,
     >,
-    button: &'static capsules::button::Button<'static, stm32f401cc::gpio::Pin<'static>>,
+    button: &'static capsules::button::Button<
+        'static,
+        stm32f401cc::gpio::Pin<'static>,
+        VirtualMuxAlarm<'static, stm32f401cc::tim2::Tim2<'static>>,
+    >,
     adc: &'static capsules::adc::AdcVirtualized<'static>,
     alarm: &'static capsules::alarm::AlarmDriver<
         'static,
@@ -311,10 +315,18 @@ pub unsafe fn main() {
         LedLow::new(gpio_ports.get_pin(stm32f401cc::gpio::PinId::PC13).unwrap()),
     ));
 
+    // ALARM
+
+    let tim2 = &base_peripherals.tim2;
+    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
+        components::alarm_mux_component_helper!(stm32f401cc::tim2::Tim2),
+    );
+
     // BUTTONs
     let button = components::button::ButtonComponent::new(
         board_kernel,
         capsules::button::DRIVER_NUM,
+        mux_alarm,
         components::button_component_helper!(
             stm32f401cc::gpio::Pin,
             (
@@ -324,14 +336,10 @@ pub unsafe fn main() {
             )
         ),
     )
-    .finalize(components::button_component_buf!(stm32f401cc::gpio::Pin));
-
-    // ALARM
-
-    let tim2 = &base_peripherals.tim2;
-    let mux_alarm = components::alarm::AlarmMuxComponent::new(tim2).finalize(
-        components::alarm_mux_component_helper!(stm32f401cc::tim2::Tim2),
-    );
+    .finalize(components::button_component_buf!(
+        stm32f401cc::gpio::Pin,
+        stm32f401cc::tim2::Tim2
+    ));
 
     let alarm = components::alarm::AlarmDriverComponent::new(
         board_kernel,
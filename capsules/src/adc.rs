@@ -16,6 +16,7 @@
 //! ADC. It also supports multiple processes requesting ADC samples
 //! concurrently. However, it only supports processes requesting single
 //! ADC samples: they cannot sample continuously or at high speed.
+//! `adc_stream` provides continuous sampling on top of the virtualizer.
 //!
 //!
 //! Usage
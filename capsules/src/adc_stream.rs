@@ -0,0 +1,425 @@
+//! Streams ADC samples at a fixed rate into a process's ring buffer.
+//!
+//! `AdcVirtualized` only takes single samples, so a process that wants a
+//! steady stream of samples has to ask for every one of them. This driver
+//! instead samples an ADC channel continuously, at a rate the process picks,
+//! and writes the samples into a ring buffer the process has shared with the
+//! kernel. The process is notified each time half of the ring buffer has
+//! been written, and hands space back to the driver once it has read it.
+//!
+//! The samples are taken one at a time with an alarm, through
+//! `virtual_adc::AdcDevice`s, so streaming works on any `hil::adc::Adc` and
+//! the ADC stays available to other users between samples. To reduce noise,
+//! the driver can oversample: with a decimation factor of `n`, it samples at
+//! `n` times the requested rate and writes the average of each group of `n`
+//! samples into the ring buffer.
+//!
+//! A sample is lost, and counted as an overrun, when the ring buffer is full
+//! or when the ADC, shared with other users, could not take any sample for
+//! it. Only one process can stream at a time.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Allow
+//!
+//! * ReadWrite 0: The ring buffer. Each sample takes two bytes: the raw,
+//!   left-justified ADC value as a little-endian `u16`. A buffer that is
+//!   allowed again while streaming is taken as empty.
+//!
+//! ### Subscribe
+//!
+//! * 0: called each time half of the ring buffer has been written, with the
+//!      number of unread samples, the index of the first unread sample in the
+//!      ring buffer, and the number of overruns since streaming started.
+//!
+//! ### Command
+//!
+//! * 0: driver check. Returns the number of channels.
+//! * 1: start streaming channel `arg1` at `arg2` samples per second.
+//! * 2: stop streaming.
+//! * 3: set the decimation factor to `arg1`, from 1 to `MAX_DECIMATION`. It
+//!      takes effect the next time the process starts streaming.
+//! * 4: mark the `arg1` oldest unread samples as read, making their space
+//!      available again.
+//!
+//! Command 1 returns `NODEVICE` for channels that do not exist, `INVAL` if
+//! the rate, times the decimation factor, is zero or faster than the alarm,
+//! `SIZE` if no ring buffer is allowed, and `BUSY` if another process is
+//! streaming. Command 4 returns `INVAL` for more samples than are unread.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let adc_stream_channels = static_init!(
+//!     [&'static dyn kernel::hil::adc::AdcChannel; 1],
+//!     [adc_device_a0]
+//! );
+//! let adc_stream = static_init!(
+//!     capsules::adc_stream::AdcStream<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::adc_stream::AdcStream::new(
+//!         adc_stream_channels,
+//!         adc_stream_alarm,
+//!         board_kernel.create_grant(capsules::adc_stream::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! adc_stream_alarm.set_alarm_client(adc_stream);
+//! adc_device_a0.set_client(adc_stream);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil;
+use kernel::hil::time::{Frequency, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::AdcStream as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const RING_BUFFER: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// The largest decimation factor.
+pub const MAX_DECIMATION: usize = 256;
+
+/// The size of a sample in the ring buffer.
+const SAMPLE_LEN: usize = 2;
+
+pub struct App {
+    decimation: usize,
+    /// The number of samples the ring buffer holds.
+    capacity: usize,
+    /// The index of the oldest unread sample.
+    read: usize,
+    unread: usize,
+    /// The number of samples written or lost since the last upcall.
+    since_upcall: usize,
+    overruns: usize,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            decimation: 1,
+            capacity: 0,
+            read: 0,
+            unread: 0,
+            since_upcall: 0,
+            overruns: 0,
+        }
+    }
+}
+
+impl App {
+    fn ring_capacity(kernel_data: &GrantKernelData) -> usize {
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::RING_BUFFER)
+            .map_or(0, |buffer| buffer.len() / SAMPLE_LEN)
+    }
+
+    // Writes a sample at the end of the ring buffer, or counts it as an
+    // overrun if the ring buffer is full.
+    fn push_sample(&mut self, kernel_data: &GrantKernelData, sample: u16) {
+        let capacity = Self::ring_capacity(kernel_data);
+        if capacity != self.capacity {
+            // A new buffer was allowed, which starts out empty
+            self.capacity = capacity;
+            self.read = 0;
+            self.unread = 0;
+        }
+        if self.unread == capacity {
+            self.overruns += 1;
+            return;
+        }
+
+        let offset = (self.read + self.unread) % capacity * SAMPLE_LEN;
+        let _ = kernel_data
+            .get_readwrite_processbuffer(rw_allow::RING_BUFFER)
+            .and_then(|ring| {
+                ring.mut_enter(|ring| {
+                    ring[offset..offset + SAMPLE_LEN].copy_from_slice(&sample.to_le_bytes())
+                })
+            });
+        self.unread += 1;
+    }
+}
+
+pub struct AdcStream<'a, A: hil::time::Alarm<'a>> {
+    channels: &'a [&'a dyn hil::adc::AdcChannel],
+    alarm: &'a A,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+
+    /// The streaming process, if any.
+    owner: OptionalCell<ProcessId>,
+    channel: Cell<usize>,
+    decimation: Cell<usize>,
+
+    // Sample timing: samples are taken every `interval` ticks, plus one tick
+    // every time `remainder` adds up to `rate`.
+    reference: Cell<A::Ticks>,
+    interval: Cell<A::Ticks>,
+    remainder: Cell<u32>,
+    phase: Cell<u32>,
+    rate: Cell<u32>,
+
+    // The group of samples currently being averaged.
+    sampling: Cell<bool>,
+    group_ticks: Cell<usize>,
+    group_sum: Cell<u32>,
+    group_samples: Cell<usize>,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> AdcStream<'a, A> {
+    pub fn new(
+        channels: &'a [&'a dyn hil::adc::AdcChannel],
+        alarm: &'a A,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    ) -> AdcStream<'a, A> {
+        AdcStream {
+            channels,
+            alarm,
+            apps: grant,
+            owner: OptionalCell::empty(),
+            channel: Cell::new(0),
+            decimation: Cell::new(1),
+            reference: Cell::new(A::Ticks::from(0)),
+            interval: Cell::new(A::Ticks::from(0)),
+            remainder: Cell::new(0),
+            phase: Cell::new(0),
+            rate: Cell::new(1),
+            sampling: Cell::new(false),
+            group_ticks: Cell::new(0),
+            group_sum: Cell::new(0),
+            group_samples: Cell::new(0),
+        }
+    }
+
+    fn start(
+        &self,
+        channel: usize,
+        frequency: usize,
+        processid: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        if channel >= self.channels.len() {
+            return Err(ErrorCode::NODEVICE);
+        }
+        if let Some(owner) = self.owner.extract() {
+            if owner != processid && self.apps.enter(owner, |_, _| {}).is_ok() {
+                return Err(ErrorCode::BUSY);
+            }
+            // The owner is restarting, or has exited without stopping
+            self.stop_sampling();
+        }
+
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let rate = frequency
+                    .checked_mul(app.decimation)
+                    .and_then(|rate| u32::try_from(rate).ok())
+                    .filter(|rate| *rate > 0 && *rate <= A::Frequency::frequency())
+                    .ok_or(ErrorCode::INVAL)?;
+                let capacity = App::ring_capacity(kernel_data);
+                if capacity == 0 {
+                    return Err(ErrorCode::SIZE);
+                }
+
+                app.capacity = capacity;
+                app.read = 0;
+                app.unread = 0;
+                app.since_upcall = 0;
+                app.overruns = 0;
+
+                self.owner.set(processid);
+                self.channel.set(channel);
+                self.decimation.set(app.decimation);
+                self.rate.set(rate);
+                self.interval
+                    .set(A::Ticks::from(A::Frequency::frequency() / rate));
+                self.remainder.set(A::Frequency::frequency() % rate);
+                self.phase.set(0);
+                self.group_ticks.set(0);
+                self.group_sum.set(0);
+                self.group_samples.set(0);
+
+                let now = self.alarm.now();
+                self.reference.set(now);
+                self.alarm.set_alarm(now, self.next_interval());
+                Ok(())
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    fn stop(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        if self.owner.contains(&processid) {
+            self.stop_sampling();
+            self.owner.clear();
+        }
+        Ok(())
+    }
+
+    fn stop_sampling(&self) {
+        let _ = self.alarm.disarm();
+        if self.sampling.take() {
+            let _ = self.channels[self.channel.get()].stop_sampling();
+        }
+    }
+
+    // The number of ticks until the next sample, spreading the remainder of
+    // the division by the rate over the samples.
+    fn next_interval(&self) -> A::Ticks {
+        let phase = self.phase.get() + self.remainder.get();
+        if phase >= self.rate.get() {
+            self.phase.set(phase - self.rate.get());
+            self.interval.get().wrapping_add(A::Ticks::from(1))
+        } else {
+            self.phase.set(phase);
+            self.interval.get()
+        }
+    }
+
+    // Writes the average of the finished group of samples to the owner's
+    // ring buffer, and notifies the owner every half ring buffer.
+    fn finish_group(&self, owner: ProcessId) -> Result<(), kernel::process::Error> {
+        let samples = self.group_samples.take();
+        let sum = self.group_sum.take();
+        self.apps.enter(owner, |app, kernel_data| {
+            if samples == 0 {
+                app.overruns += 1;
+            } else {
+                app.push_sample(kernel_data, (sum / samples as u32) as u16);
+            }
+
+            app.since_upcall += 1;
+            if app.since_upcall >= core::cmp::max(app.capacity / 2, 1) {
+                app.since_upcall = 0;
+                kernel_data
+                    .schedule_upcall(0, (app.unread, app.read, app.overruns))
+                    .ok();
+            }
+        })
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> hil::time::AlarmClient for AdcStream<'a, A> {
+    fn alarm(&self) {
+        let owner = match self.owner.extract() {
+            Some(owner) => owner,
+            None => return,
+        };
+
+        if self.group_ticks.get() == self.decimation.get() {
+            self.group_ticks.set(0);
+            if self.finish_group(owner).is_err() {
+                // The owner has exited
+                self.stop_sampling();
+                self.owner.clear();
+                return;
+            }
+        }
+
+        let reference = self.reference.get().wrapping_add(self.interval.get());
+        let next = self.next_interval();
+        if self
+            .alarm
+            .now()
+            .within_range(reference, reference.wrapping_add(next))
+        {
+            self.reference.set(reference);
+            self.alarm.set_alarm(reference, next);
+        } else {
+            // Too far behind to catch up, so the samples in between are lost
+            let now = self.alarm.now();
+            self.reference.set(now);
+            self.alarm.set_alarm(now, next);
+        }
+
+        self.group_ticks.set(self.group_ticks.get() + 1);
+        // If the last sample is still being taken, the ADC is too busy and
+        // this one is skipped
+        if !self.sampling.get() && self.channels[self.channel.get()].sample().is_ok() {
+            self.sampling.set(true);
+        }
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> hil::adc::Client for AdcStream<'a, A> {
+    fn sample_ready(&self, sample: u16) {
+        if self.sampling.take() {
+            self.group_sum.set(self.group_sum.get() + sample as u32);
+            self.group_samples.set(self.group_samples.get() + 1);
+        }
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SyscallDriver for AdcStream<'a, A> {
+    /// Control the ADC stream.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, returns the number of channels.
+    /// - `1`: Start streaming channel `data1` at `data2` samples per second.
+    /// - `2`: Stop streaming.
+    /// - `3`: Set the decimation factor to `data1`.
+    /// - `4`: Mark the `data1` oldest unread samples as read.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success_u32(self.channels.len() as u32),
+
+            1 => CommandReturn::from(self.start(data1, data2, processid)),
+
+            2 => CommandReturn::from(self.stop(processid)),
+
+            3 => {
+                if data1 == 0 || data1 > MAX_DECIMATION {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| app.decimation = data1)
+                    .map_err(ErrorCode::from);
+                CommandReturn::from(result)
+            }
+
+            4 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        if data1 > app.unread {
+                            return Err(ErrorCode::INVAL);
+                        }
+                        if data1 > 0 {
+                            app.read = (app.read + data1) % app.capacity;
+                            app.unread -= data1;
+                        }
+                        Ok(())
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                CommandReturn::from(result)
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
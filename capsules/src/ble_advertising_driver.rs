@@ -10,14 +10,23 @@
 //! Data payloads are limited to 31 bytes since the maximum advertising channel
 //! protocol data unit (PDU) is 37 bytes and includes a 6-byte header.
 //!
+//! Scanning can be passive, or active, in which case the driver sends scan
+//! requests to scannable advertisers and also reports their scan responses.
+//! Each process can set filter rules that the driver applies before reporting
+//! a PDU, and results are queued in the process's scan buffer so that it is
+//! woken up at most once per scanning event.
+//!
 //! ### Allow system calls
 //!
-//! There is one ReadWrite and one ReadOnly allow buffers, both at index `0`.
+//! There are two ReadOnly allow buffers and one ReadWrite allow buffer.
 //!
-//! * ReadOnly: Advertising data, containing the full _payload_ (i.e. excluding the header) the
-//!             process wishes to advertise.
-//! * ReadWrite: Passive scanning buffer, which is populated during BLE scans with complete (i.e.
-//!              including headers) advertising packets received on channels 37, 38 and 39.
+//! * ReadOnly 0: Advertising data, containing the full _payload_ (i.e. excluding the header) the
+//!               process wishes to advertise.
+//! * ReadOnly 1: The advertiser address for an address filter rule.
+//! * ReadWrite 0: Scan result queue. Each result takes `RESULT_LEN` bytes: the length of the
+//!                PDU, its RSSI in dBm as a signed byte, the channel index (37, 38 or 39), a
+//!                flags byte with bit 0 set for scan responses to this process's requests, and
+//!                the complete (i.e. including headers) PDU.
 //!
 //! The possible return codes from the 'allow' system call indicate the following:
 //!
@@ -33,8 +42,10 @@
 //!  The `subscribe` system call supports two arguments `subscribe number' and `callback`.
 //!  The `subscribe` is used to specify the specific operation, currently:
 //!
-//! * 0: provides a callback user-space when a device scanning for advertisements
-//!      and the callback is used to invoke user-space processes.
+//! * 0: called at the end of a scanning event in which results were queued, with a status
+//!      code, the number of queued results and the number of results dropped because the
+//!      queue was full. If the radio cannot scan, it is called with the error and scanning
+//!      stops.
 //!
 //! The possible return codes from the `allow` system call indicate the following:
 //!
@@ -49,7 +60,14 @@
 //!
 //! * 0: start advertisement
 //! * 1: stop advertisement or scanning
-//! * 5: start scanning
+//! * 2: configure the transmit power
+//! * 5: start scanning, passively if `data` is 0 and actively if it is 1
+//! * 6: add a filter rule. `data` selects the kind of rule: 0 for the advertiser address in
+//!      ReadOnly allow 1, 1 for an AD type given in `interval`, and 2 for the lowest RSSI in dBm,
+//!      given in `interval` as a signed integer. A PDU is reported if, for each kind of rule
+//!      set, it matches one of the rules of that kind. At most `MAX_FILTER_RULES` can be set.
+//! * 7: remove all filter rules
+//! * 8: empty the scan result queue, once the process has read it
 //!
 //! The possible return codes from the `command` system call indicate the following:
 //!
@@ -122,8 +140,9 @@ pub const DRIVER_NUM: usize = driver::NUM::BleAdvertising as usize;
 /// Ids for read-only allow buffers
 mod ro_allow {
     pub const ADV_DATA: usize = 0;
+    pub const FILTER_ADDRESS: usize = 1;
     /// The number of allow buffers the kernel stores for this grant
-    pub const COUNT: u8 = 1;
+    pub const COUNT: u8 = 2;
 }
 
 /// Ids for read-write allow buffers
@@ -139,6 +158,18 @@ pub static mut BUF: [u8; PACKET_LENGTH] = [0; PACKET_LENGTH];
 const PACKET_ADDR_LEN: usize = 6;
 const PACKET_LENGTH: usize = 39;
 const ADV_HEADER_TXADD_OFFSET: usize = 6;
+const ADV_HEADER_RXADD_OFFSET: usize = 7;
+const ADV_HEADER_TYPE_MASK: u8 = 0x0f;
+const ADV_HEADER_LENGTH_MASK: u8 = 0x3f;
+
+/// The size of a scan result in the scan result queue.
+pub const RESULT_LEN: usize = 4 + PACKET_LENGTH;
+
+/// Set in the flags of a scan result that is a response to a scan request.
+const RESULT_FLAG_SCAN_RESPONSE: u8 = 1 << 0;
+
+/// The number of filter rules each process can set.
+pub const MAX_FILTER_RULES: usize = 4;
 
 #[derive(PartialEq, Debug)]
 enum BLEState {
@@ -175,14 +206,99 @@ const ADV_IND: AdvPduType = 0b0000;
 #[allow(dead_code)]
 const ADV_DIRECTED_IND: AdvPduType = 0b0001;
 const ADV_NONCONN_IND: AdvPduType = 0b0010;
-#[allow(dead_code)]
 const SCAN_REQ: AdvPduType = 0b0011;
-#[allow(dead_code)]
 const SCAN_RESP: AdvPduType = 0b0100;
 #[allow(dead_code)]
 const CONNECT_IND: AdvPduType = 0b0101;
 const ADV_SCAN_IND: AdvPduType = 0b0110;
 
+/// A rule that reported scan results match.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum FilterRule {
+    /// The advertiser's address.
+    Address([u8; PACKET_ADDR_LEN]),
+    /// The type of an AD structure in the advertising data.
+    AdType(u8),
+    /// The lowest signal strength, in dBm.
+    MinRssi(i8),
+}
+
+impl FilterRule {
+    fn same_kind(&self, other: &FilterRule) -> bool {
+        core::mem::discriminant(self) == core::mem::discriminant(other)
+    }
+
+    fn matches(&self, pdu: &[u8], rssi: i8) -> bool {
+        match *self {
+            FilterRule::Address(address) => advertiser_address(pdu) == Some(&address[..]),
+            FilterRule::AdType(ad_type) => has_ad_type(pdu, ad_type),
+            FilterRule::MinRssi(min_rssi) => rssi >= min_rssi,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct ScanFilter {
+    rules: [Option<FilterRule>; MAX_FILTER_RULES],
+}
+
+impl ScanFilter {
+    fn add(&mut self, rule: FilterRule) -> Result<(), ErrorCode> {
+        let slot = self
+            .rules
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        *slot = Some(rule);
+        Ok(())
+    }
+
+    /// Whether a PDU matches, for each kind of rule set, one of the rules of
+    /// that kind. Rules of the kinds in `skip` are not checked.
+    fn matches(&self, pdu: &[u8], rssi: i8, skip: &[FilterRule]) -> bool {
+        self.rules.iter().flatten().all(|rule| {
+            skip.iter().any(|skipped| rule.same_kind(skipped))
+                || self
+                    .rules
+                    .iter()
+                    .flatten()
+                    .filter(|other| other.same_kind(rule))
+                    .any(|other| other.matches(pdu, rssi))
+        })
+    }
+}
+
+/// The advertiser address of PDUs that have one.
+fn advertiser_address(pdu: &[u8]) -> Option<&[u8]> {
+    match pdu.first().map(|header| header & ADV_HEADER_TYPE_MASK) {
+        Some(ADV_IND)
+        | Some(ADV_DIRECTED_IND)
+        | Some(ADV_NONCONN_IND)
+        | Some(SCAN_RESP)
+        | Some(ADV_SCAN_IND) => pdu.get(2..2 + PACKET_ADDR_LEN),
+        _ => None,
+    }
+}
+
+/// Whether a PDU's advertising data holds an AD structure of `ad_type`.
+fn has_ad_type(pdu: &[u8], ad_type: u8) -> bool {
+    let mut data = match pdu.first().map(|header| header & ADV_HEADER_TYPE_MASK) {
+        Some(ADV_IND) | Some(ADV_NONCONN_IND) | Some(SCAN_RESP) | Some(ADV_SCAN_IND) => {
+            let end = cmp::min(2 + (pdu[1] & ADV_HEADER_LENGTH_MASK) as usize, pdu.len());
+            pdu.get(2 + PACKET_ADDR_LEN..end).unwrap_or(&[])
+        }
+        _ => &[],
+    };
+    // Each AD structure is a length, a type and length - 1 bytes of data
+    while data.len() >= 2 && data[0] > 0 {
+        if data[1] == ad_type {
+            return true;
+        }
+        data = data.get(1 + data[0] as usize..).unwrap_or(&[]);
+    }
+    false
+}
+
 /// Process specific memory
 pub struct App {
     process_status: Option<BLEState>,
@@ -199,6 +315,16 @@ pub struct App {
     /// It should be read using the `random_number` method, which updates it as
     /// well.
     random_nonce: u32,
+
+    // Scanning meta-data
+    active_scan: bool,
+    filter: ScanFilter,
+    /// The number of results in the scan result queue.
+    queued: usize,
+    /// The number of results dropped since the queue was last emptied.
+    dropped: usize,
+    /// Whether results were queued in the current scanning event.
+    new_results: bool,
 }
 
 impl Default for App {
@@ -212,6 +338,11 @@ impl Default for App {
             advertisement_interval_ms: 200,
             // Just use any non-zero starting value by default
             random_nonce: 0xdeadbeef,
+            active_scan: false,
+            filter: ScanFilter::default(),
+            queued: 0,
+            dropped: 0,
+            new_results: false,
         }
     }
 }
@@ -253,7 +384,9 @@ impl App {
         channel: RadioChannel,
     ) -> Result<(), ErrorCode>
     where
-        B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+        B: ble_advertising::BleAdvertisementDriver<'a>
+            + ble_advertising::BleScanDriver<'a>
+            + ble_advertising::BleConfig,
         A: kernel::hil::time::Alarm<'a>,
     {
         // Ensure we have an address set before advertisement
@@ -298,6 +431,45 @@ impl App {
             .unwrap_or(Err(ErrorCode::FAIL))
     }
 
+    // Appends a scan result to the process's scan result queue, or counts it
+    // as dropped if the queue is full.
+    fn queue_result(
+        &mut self,
+        kernel_data: &GrantKernelData,
+        pdu: &[u8],
+        rssi: i8,
+        channel: RadioChannel,
+        flags: u8,
+    ) {
+        let mut result = [0; RESULT_LEN];
+        let len = cmp::min(pdu.len(), PACKET_LENGTH);
+        result[0] = len as u8;
+        result[1] = rssi as u8;
+        result[2] = channel.get_channel_index() as u8;
+        result[3] = flags;
+        result[4..4 + len].copy_from_slice(&pdu[..len]);
+
+        let offset = self.queued * RESULT_LEN;
+        let stored = kernel_data
+            .get_readwrite_processbuffer(rw_allow::SCAN_BUFFER)
+            .and_then(|scan_buffer| {
+                scan_buffer.mut_enter(|queue| match queue.get(offset..offset + RESULT_LEN) {
+                    Some(slot) => {
+                        slot.copy_from_slice(&result);
+                        true
+                    }
+                    None => false,
+                })
+            })
+            .unwrap_or(false);
+        if stored {
+            self.queued += 1;
+            self.new_results = true;
+        } else {
+            self.dropped += 1;
+        }
+    }
+
     // Returns a new pseudo-random number and updates the randomness state.
     //
     // Uses the [Xorshift](https://en.wikipedia.org/wiki/Xorshift) algorithm to
@@ -323,7 +495,9 @@ impl App {
 
 pub struct BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver<'a>
+        + ble_advertising::BleScanDriver<'a>
+        + ble_advertising::BleConfig,
     A: kernel::hil::time::Alarm<'a>,
 {
     radio: &'a B,
@@ -338,11 +512,15 @@ where
     alarm: &'a A,
     sending_app: OptionalCell<kernel::ProcessId>,
     receiving_app: OptionalCell<kernel::ProcessId>,
+    /// The advertiser a scan request was sent to in the current scan.
+    scan_request: OptionalCell<[u8; PACKET_ADDR_LEN]>,
 }
 
 impl<'a, B, A> BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver<'a>
+        + ble_advertising::BleScanDriver<'a>
+        + ble_advertising::BleConfig,
     A: kernel::hil::time::Alarm<'a>,
 {
     pub fn new(
@@ -364,9 +542,17 @@ where
             alarm: alarm,
             sending_app: OptionalCell::empty(),
             receiving_app: OptionalCell::empty(),
+            scan_request: OptionalCell::empty(),
         }
     }
 
+    fn scan(&self, channel: RadioChannel) {
+        self.scan_request.clear();
+        self.kernel_tx
+            .take()
+            .map(|buf| self.radio.scan(buf, channel));
+    }
+
     // Determines which app timer will expire next and sets the underlying alarm
     // to it.
     //
@@ -403,7 +589,9 @@ where
 // Timer alarm
 impl<'a, B, A> kernel::hil::time::AlarmClient for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver<'a>
+        + ble_advertising::BleScanDriver<'a>
+        + ble_advertising::BleConfig,
     A: kernel::hil::time::Alarm<'a>,
 {
     // When an alarm is fired, we find which apps have expired timers. Expired
@@ -459,8 +647,8 @@ where
                                 Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37));
                             self.receiving_app.set(appid);
                             let _ = self.radio.set_tx_power(app.tx_power);
-                            self.radio
-                                .receive_advertisement(RadioChannel::AdvertisingChannel37);
+                            let _ = app.generate_random_address(appid);
+                            self.scan(RadioChannel::AdvertisingChannel37);
                         }
                         _ => debug!("app: {:?} \t invalid state {:?}", appid, app.process_status),
                     }
@@ -471,68 +659,127 @@ where
     }
 }
 
-// Callback from the radio once a RX event occur
-impl<'a, B, A> ble_advertising::RxClient for BLE<'a, B, A>
+// Callbacks from the radio while scanning
+impl<'a, B, A> ble_advertising::ScanClient for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver<'a>
+        + ble_advertising::BleScanDriver<'a>
+        + ble_advertising::BleConfig,
     A: kernel::hil::time::Alarm<'a>,
 {
-    fn receive_event(&self, buf: &'static mut [u8], len: u8, result: Result<(), ErrorCode>) {
+    fn advertisement_received(&self, buf: &mut [u8], len: usize, rssi: i8) -> usize {
+        // Validate the received data, because ordinary BLE packets can be bigger than 39 bytes.
+        // Packets that are bigger than 39 bytes are likely `Channel PDUs` which should only be
+        // sent on the other 37 RadioChannel channels.
+        if len < 2 || len > PACKET_LENGTH || len > buf.len() {
+            return 0;
+        }
+        self.receiving_app.map_or(0, |appid| {
+            self.app
+                .enter(*appid, |app, kernel_data| {
+                    let channel = match app.process_status {
+                        Some(BLEState::Scanning(channel)) => channel,
+                        _ => return 0,
+                    };
+                    let pdu = &buf[..len];
+                    let pdu_type = pdu[0] & ADV_HEADER_TYPE_MASK;
+
+                    let response = pdu_type == SCAN_RESP
+                        && self.scan_request.map_or(false, |requested| {
+                            advertiser_address(pdu) == Some(&requested[..])
+                        });
+                    if app.filter.matches(pdu, rssi, &[]) {
+                        let flags = if response {
+                            RESULT_FLAG_SCAN_RESPONSE
+                        } else {
+                            0
+                        };
+                        app.queue_result(kernel_data, pdu, rssi, channel, flags);
+                    }
+
+                    // Request the scan response of scannable advertisers that pass the filter,
+                    // except for their advertising data, which the response might complete.
+                    let scannable = pdu_type == ADV_IND || pdu_type == ADV_SCAN_IND;
+                    if !app.active_scan
+                        || !scannable
+                        || self.scan_request.is_some()
+                        || !app.filter.matches(pdu, rssi, &[FilterRule::AdType(0)])
+                    {
+                        return 0;
+                    }
+                    let mut advertiser = [0; PACKET_ADDR_LEN];
+                    match advertiser_address(pdu) {
+                        Some(address) => advertiser.copy_from_slice(address),
+                        None => return 0,
+                    }
+                    self.scan_request.set(advertiser);
+
+                    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.3.2.1
+                    let advertiser_random = pdu[0] & (1 << ADV_HEADER_TXADD_OFFSET) != 0;
+                    buf[0] = SCAN_REQ
+                        | 1 << ADV_HEADER_TXADD_OFFSET
+                        | (advertiser_random as u8) << ADV_HEADER_RXADD_OFFSET;
+                    buf[1] = (2 * PACKET_ADDR_LEN) as u8;
+                    buf[2..2 + PACKET_ADDR_LEN].copy_from_slice(&app.address);
+                    buf[2 + PACKET_ADDR_LEN..2 + 2 * PACKET_ADDR_LEN].copy_from_slice(&advertiser);
+                    2 + 2 * PACKET_ADDR_LEN
+                })
+                .unwrap_or(0)
+        })
+    }
+
+    fn scan_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>) {
+        self.kernel_tx.replace(buf);
         self.receiving_app.map(|appid| {
             let _ = self.app.enter(*appid, |app, kernel_data| {
-                // Validate the received data, because ordinary BLE packets can be bigger than 39
-                // bytes. Thus, we need to check for that!
-                // Moreover, we use the packet header to find size but the radio reads maximum
-                // 39 bytes.
-                // Therefore, we ignore payloads with a header size bigger than 39 because the
-                // channels 37, 38 and 39 should only be used for advertisements!
-                // Packets that are bigger than 39 bytes are likely `Channel PDUs` which should
-                // only be sent on the other 37 RadioChannel channels.
-
-                if len <= PACKET_LENGTH as u8 && result == Ok(()) {
-                    // write to buffer in userland
-
-                    let success = kernel_data
-                        .get_readwrite_processbuffer(rw_allow::SCAN_BUFFER)
-                        .and_then(|scan_buffer| {
-                            scan_buffer.mut_enter(|userland| {
-                                userland[0..len as usize]
-                                    .copy_from_slice_or_err(&buf[0..len as usize])
-                                    .is_ok()
-                            })
-                        })
-                        .unwrap_or(false);
-
-                    if success {
-                        kernel_data
-                            .schedule_upcall(
-                                0,
-                                (kernel::errorcode::into_statuscode(result), len as usize, 0),
-                            )
-                            .ok();
-                    }
+                if result.is_err() {
+                    // The radio cannot scan, so scanning stops and the
+                    // process is told why
+                    self.busy.set(false);
+                    app.process_status = Some(BLEState::Idle);
+                    kernel_data
+                        .schedule_upcall(
+                            0,
+                            (
+                                kernel::errorcode::into_statuscode(result),
+                                app.queued,
+                                app.dropped,
+                            ),
+                        )
+                        .ok();
+                    return;
                 }
-
                 match app.process_status {
                     Some(BLEState::Scanning(RadioChannel::AdvertisingChannel37)) => {
                         app.process_status =
                             Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38));
                         self.receiving_app.set(*appid);
                         let _ = self.radio.set_tx_power(app.tx_power);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel38);
+                        self.scan(RadioChannel::AdvertisingChannel38);
                     }
                     Some(BLEState::Scanning(RadioChannel::AdvertisingChannel38)) => {
                         app.process_status =
                             Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39));
                         self.receiving_app.set(*appid);
-                        self.radio
-                            .receive_advertisement(RadioChannel::AdvertisingChannel39);
+                        self.scan(RadioChannel::AdvertisingChannel39);
                     }
                     Some(BLEState::Scanning(RadioChannel::AdvertisingChannel39)) => {
                         self.busy.set(false);
                         app.process_status = Some(BLEState::ScanningIdle);
                         app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
+                        if app.new_results {
+                            app.new_results = false;
+                            kernel_data
+                                .schedule_upcall(
+                                    0,
+                                    (
+                                        kernel::errorcode::into_statuscode(Ok(())),
+                                        app.queued,
+                                        app.dropped,
+                                    ),
+                                )
+                                .ok();
+                        }
                     }
                     // Invalid state => don't care
                     _ => (),
@@ -546,7 +793,9 @@ where
 // Callback from the radio once a TX event occur
 impl<'a, B, A> ble_advertising::TxClient for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver<'a>
+        + ble_advertising::BleScanDriver<'a>
+        + ble_advertising::BleConfig,
     A: kernel::hil::time::Alarm<'a>,
 {
     // The Result<(), ErrorCode> indicates valid CRC or not, not used yet but could be used for
@@ -598,7 +847,9 @@ where
 // System Call implementation
 impl<'a, B, A> SyscallDriver for BLE<'a, B, A>
 where
-    B: ble_advertising::BleAdvertisementDriver<'a> + ble_advertising::BleConfig,
+    B: ble_advertising::BleAdvertisementDriver<'a>
+        + ble_advertising::BleScanDriver<'a>
+        + ble_advertising::BleConfig,
     A: kernel::hil::time::Alarm<'a>,
 {
     fn command(
@@ -686,11 +937,12 @@ where
                     .unwrap_or_else(|err| err.into())
             }
 
-            // Passive scanning mode
+            // Scanning mode, active if `data` is 1 and passive otherwise
             5 => {
                 self.app
                     .enter(appid, |app, _| {
                         if let Some(BLEState::Idle) = app.process_status {
+                            app.active_scan = data == 1;
                             app.process_status = Some(BLEState::ScanningIdle);
                             app.set_next_alarm::<A::Frequency>(self.alarm.now().into_u32());
                             Ok(())
@@ -711,6 +963,47 @@ where
                     )
             }
 
+            // Add a filter rule
+            6 => self
+                .app
+                .enter(appid, |app, kernel_data| {
+                    let rule = match data {
+                        0 => kernel_data
+                            .get_readonly_processbuffer(ro_allow::FILTER_ADDRESS)
+                            .and_then(|address| {
+                                address.enter(|address| {
+                                    let mut rule = [0; PACKET_ADDR_LEN];
+                                    address
+                                        .get(0..PACKET_ADDR_LEN)
+                                        .ok_or(ErrorCode::SIZE)?
+                                        .copy_to_slice(&mut rule);
+                                    Ok(FilterRule::Address(rule))
+                                })
+                            })
+                            .unwrap_or(Err(ErrorCode::RESERVE))?,
+                        1 => FilterRule::AdType(interval as u8),
+                        2 => FilterRule::MinRssi(interval as i32 as i8),
+                        _ => return Err(ErrorCode::INVAL),
+                    };
+                    app.filter.add(rule)
+                })
+                .map_or_else(|err| err.into(), CommandReturn::from),
+
+            // Remove all filter rules
+            7 => self
+                .app
+                .enter(appid, |app, _| app.filter = ScanFilter::default())
+                .map_or_else(|err| err.into(), |_| CommandReturn::success()),
+
+            // Empty the scan result queue
+            8 => self
+                .app
+                .enter(appid, |app, _| {
+                    app.queued = 0;
+                    app.dropped = 0;
+                })
+                .map_or_else(|err| err.into(), |_| CommandReturn::success()),
+
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
         .into()
@@ -720,3 +1013,70 @@ where
         self.app.enter(processid, |_, _| {})
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADVERTISER: [u8; PACKET_ADDR_LEN] = [1, 2, 3, 4, 5, 6];
+
+    // An ADV_IND with flags and a complete local name
+    fn advertisement() -> [u8; 17] {
+        [
+            ADV_IND, 15, 1, 2, 3, 4, 5, 6, 2, 0x01, 0x06, 4, 0x09, b'T', b'o', b'c', b'k',
+        ]
+    }
+
+    #[test]
+    fn no_rules_match_everything() {
+        assert!(ScanFilter::default().matches(&advertisement(), -90, &[]));
+    }
+
+    #[test]
+    fn rules_of_one_kind_are_alternatives() {
+        let mut filter = ScanFilter::default();
+        filter.add(FilterRule::AdType(0xff)).unwrap();
+        assert!(!filter.matches(&advertisement(), -40, &[]));
+        filter.add(FilterRule::AdType(0x09)).unwrap();
+        assert!(filter.matches(&advertisement(), -40, &[]));
+    }
+
+    #[test]
+    fn rules_of_different_kinds_must_all_match() {
+        let mut filter = ScanFilter::default();
+        filter.add(FilterRule::Address(ADVERTISER)).unwrap();
+        filter.add(FilterRule::MinRssi(-60)).unwrap();
+        assert!(filter.matches(&advertisement(), -50, &[]));
+        assert!(!filter.matches(&advertisement(), -70, &[]));
+
+        let mut other = advertisement();
+        other[2] = 0xff;
+        assert!(!filter.matches(&other, -50, &[]));
+    }
+
+    #[test]
+    fn skipped_kinds_are_not_checked() {
+        let mut filter = ScanFilter::default();
+        filter.add(FilterRule::AdType(0xff)).unwrap();
+        filter.add(FilterRule::MinRssi(-60)).unwrap();
+        assert!(filter.matches(&advertisement(), -50, &[FilterRule::AdType(0)]));
+        assert!(!filter.matches(&advertisement(), -70, &[FilterRule::AdType(0)]));
+    }
+
+    #[test]
+    fn ad_structures_past_the_pdu_length_are_ignored() {
+        let mut pdu = advertisement();
+        pdu[1] = 9;
+        assert!(has_ad_type(&pdu, 0x01));
+        assert!(!has_ad_type(&pdu, 0x09));
+    }
+
+    #[test]
+    fn filter_rules_are_limited() {
+        let mut filter = ScanFilter::default();
+        for _ in 0..MAX_FILTER_RULES {
+            filter.add(FilterRule::MinRssi(-60)).unwrap();
+        }
+        assert_eq!(filter.add(FilterRule::MinRssi(-60)), Err(ErrorCode::NOMEM));
+    }
+}
@@ -0,0 +1,935 @@
+//! Bluetooth Low Energy link layer for connections, as a peripheral.
+//!
+//! `BleConnection` advertises as connectable and undirected on the three
+//! advertising channels, answers scan requests, and accepts a connection from
+//! a central. Once connected, it wakes up for every connection event on a
+//! virtual alarm, hops channels with channel selection algorithm #1, and
+//! exchanges one packet pair with the central per event. Connection
+//! parameter updates, channel map updates, termination and the other
+//! mandatory control procedures are handled here; encryption is not
+//! supported.
+//!
+//! Above the link layer, L2CAP frames must fit in a single PDU, which holds
+//! the default ATT MTU of 23 bytes. Frames on the ATT channel are passed to an
+//! `AttServer` such as `ble_gatt::GattServer`. Requests on the LE signaling
+//! channel are rejected, and pairing requests are answered with "pairing not
+//! supported".
+//!
+//! Timing
+//! ------
+//!
+//! The anchor point of each connection event is taken from the first packet
+//! the central sends in it, and the radio starts listening early enough to
+//! cover both devices' sleep clock accuracy since the last anchor point. The
+//! peripheral never uses slave latency, and ends each event after one
+//! exchange. The connection is dropped if no packet is received for the
+//! supervision timeout, or in the first six events.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ble_connection = static_init!(
+//!     capsules::ble_connection::BleConnection<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ble_connection::BleConnection::new(
+//!         radio,
+//!         ble_alarm,
+//!         &mut capsules::ble_connection::BUF,
+//!         [0xf0, 0x11, 0x22, 0x33, 0x44, 0xf5],
+//!     )
+//! );
+//! kernel::hil::ble_advertising::BleConnectionDriver::set_connection_client(radio, ble_connection);
+//! ble_alarm.set_alarm_client(ble_connection);
+//! ble_connection.set_att_server(gatt_server);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::hil::ble_advertising::{BleConnectionDriver, ConnectionClient, RadioChannel};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The largest PDU, an advertising channel PDU with its header.
+pub const BUF_LEN: usize = 39;
+
+/// The radio buffer.
+pub static mut BUF: [u8; BUF_LEN] = [0; BUF_LEN];
+
+/// The largest data channel PDU payload, without the data length extension.
+pub const MAX_PAYLOAD: usize = 27;
+
+/// The ATT MTU, which is the largest L2CAP payload that fits in one PDU.
+pub const ATT_MTU: usize = MAX_PAYLOAD - L2CAP_HEADER_LEN;
+
+/// The largest advertising or scan response data.
+pub const MAX_ADV_DATA: usize = 31;
+
+const ADDRESS_LEN: usize = 6;
+const L2CAP_HEADER_LEN: usize = 4;
+
+// Bluetooth Core Specification 5.0, Vol 6, Part B, section 2.3
+mod adv_pdu {
+    pub const ADV_IND: u8 = 0b0000;
+    pub const SCAN_REQ: u8 = 0b0011;
+    pub const SCAN_RSP: u8 = 0b0100;
+    pub const CONNECT_IND: u8 = 0b0101;
+    pub const TYPE_MASK: u8 = 0x0f;
+    /// The advertiser's address is random.
+    pub const TX_ADD: u8 = 1 << 6;
+    pub const CONNECT_IND_LEN: usize = 34;
+}
+
+// Bluetooth Core Specification 5.0, Vol 6, Part B, section 2.4
+mod data_pdu {
+    pub const LLID_CONTINUATION: u8 = 0b01;
+    pub const LLID_START: u8 = 0b10;
+    pub const LLID_CONTROL: u8 = 0b11;
+    pub const LLID_MASK: u8 = 0b11;
+    pub const NESN: u8 = 1 << 2;
+    pub const SN: u8 = 1 << 3;
+}
+
+// Bluetooth Core Specification 5.0, Vol 6, Part B, section 2.4.2
+mod control {
+    pub const CONNECTION_UPDATE_IND: u8 = 0x00;
+    pub const CHANNEL_MAP_IND: u8 = 0x01;
+    pub const TERMINATE_IND: u8 = 0x02;
+    pub const ENC_REQ: u8 = 0x03;
+    pub const UNKNOWN_RSP: u8 = 0x07;
+    pub const FEATURE_REQ: u8 = 0x08;
+    pub const FEATURE_RSP: u8 = 0x09;
+    pub const VERSION_IND: u8 = 0x0c;
+    pub const REJECT_IND: u8 = 0x0d;
+    pub const PING_REQ: u8 = 0x12;
+    pub const PING_RSP: u8 = 0x13;
+
+    /// Bluetooth 4.2.
+    pub const VERSION: u8 = 0x08;
+    /// No company identifier is assigned.
+    pub const COMPANY_ID: u16 = 0xffff;
+
+    pub const REMOTE_USER_TERMINATED: u8 = 0x13;
+    pub const UNSUPPORTED_REMOTE_FEATURE: u8 = 0x1a;
+}
+
+mod l2cap {
+    pub const CID_ATT: u16 = 0x0004;
+    pub const CID_SIGNALING: u16 = 0x0005;
+    pub const CID_SMP: u16 = 0x0006;
+
+    pub const COMMAND_REJECT: u8 = 0x01;
+    pub const CONNECTION_PARAMETER_UPDATE_RSP: u8 = 0x13;
+    pub const LE_CREDIT_CONNECTION_RSP: u8 = 0x15;
+
+    pub const SMP_PAIRING_REQUEST: u8 = 0x01;
+    pub const SMP_PAIRING_FAILED: u8 = 0x05;
+    pub const SMP_PAIRING_NOT_SUPPORTED: u8 = 0x05;
+}
+
+/// The sleep clock accuracy of this device, in ppm.
+const SCA_PPM: u32 = 50;
+
+/// The sleep clock accuracies a central can announce, in ppm.
+const CENTRAL_SCA_PPM: [u32; 8] = [500, 250, 150, 100, 75, 50, 30, 20];
+
+/// Time on air of a packet's preamble, access address and CRC, in bytes.
+const PACKET_OVERHEAD: u32 = 1 + 4 + 3;
+
+/// Connection timing is given in units of 1.25 ms.
+const UNIT_US: u32 = 1250;
+
+/// A connection is dropped if it is not established in this many events.
+const ESTABLISHMENT_EVENTS: u16 = 6;
+
+/// Data channels by channel index.
+const DATA_CHANNELS: [RadioChannel; 37] = [
+    RadioChannel::DataChannel0,
+    RadioChannel::DataChannel1,
+    RadioChannel::DataChannel2,
+    RadioChannel::DataChannel3,
+    RadioChannel::DataChannel4,
+    RadioChannel::DataChannel5,
+    RadioChannel::DataChannel6,
+    RadioChannel::DataChannel7,
+    RadioChannel::DataChannel8,
+    RadioChannel::DataChannel9,
+    RadioChannel::DataChannel10,
+    RadioChannel::DataChannel11,
+    RadioChannel::DataChannel12,
+    RadioChannel::DataChannel13,
+    RadioChannel::DataChannel14,
+    RadioChannel::DataChannel15,
+    RadioChannel::DataChannel16,
+    RadioChannel::DataChannel17,
+    RadioChannel::DataChannel18,
+    RadioChannel::DataChannel19,
+    RadioChannel::DataChannel20,
+    RadioChannel::DataChannel21,
+    RadioChannel::DataChannel22,
+    RadioChannel::DataChannel23,
+    RadioChannel::DataChannel24,
+    RadioChannel::DataChannel25,
+    RadioChannel::DataChannel26,
+    RadioChannel::DataChannel27,
+    RadioChannel::DataChannel28,
+    RadioChannel::DataChannel29,
+    RadioChannel::DataChannel30,
+    RadioChannel::DataChannel31,
+    RadioChannel::DataChannel32,
+    RadioChannel::DataChannel33,
+    RadioChannel::DataChannel34,
+    RadioChannel::DataChannel35,
+    RadioChannel::DataChannel36,
+];
+
+/// The layer above the link layer that handles the ATT channel.
+pub trait AttServer {
+    /// Called with an ATT PDU received from the central. The server may write
+    /// a response of at most `ATT_MTU` bytes into `response` and return its
+    /// length, or return 0 to not respond.
+    ///
+    /// This is called while the radio turns around, so it must be quick.
+    fn att_received(&self, pdu: &[u8], response: &mut [u8]) -> usize;
+
+    /// Called when a PDU passed to `BleConnection::send_att` was received by
+    /// the central.
+    fn att_sent(&self);
+
+    fn connected(&self);
+
+    fn disconnected(&self);
+}
+
+/// A link that carries ATT PDUs to the central.
+pub trait AttBearer {
+    /// Sends an ATT PDU that is not a response, such as a notification.
+    /// `AttServer::att_sent` is called once the central receives it. Returns
+    /// `OFF` if there is no connection, `BUSY` if a PDU is already waiting to
+    /// be sent and `SIZE` if the PDU is longer than `ATT_MTU`.
+    fn send_att(&self, pdu: &[u8]) -> Result<(), ErrorCode>;
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    u16::from_le_bytes([buf[0], buf[1]])
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PduKind {
+    Response,
+    Server,
+    Terminate,
+}
+
+/// A data channel PDU waiting to be sent or acknowledged.
+#[derive(Clone, Copy)]
+struct Pdu {
+    llid: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+    kind: PduKind,
+}
+
+impl Pdu {
+    fn new(llid: u8, data: &[u8], kind: PduKind) -> Pdu {
+        let len = cmp::min(data.len(), MAX_PAYLOAD);
+        let mut payload = [0; MAX_PAYLOAD];
+        payload[..len].copy_from_slice(&data[..len]);
+        Pdu {
+            llid,
+            len,
+            payload,
+            kind,
+        }
+    }
+
+    fn empty() -> Pdu {
+        Pdu::new(data_pdu::LLID_CONTINUATION, &[], PduKind::Response)
+    }
+
+    fn l2cap(cid: u16, data: &[u8], kind: PduKind) -> Pdu {
+        let mut frame = [0; MAX_PAYLOAD];
+        let len = cmp::min(data.len(), ATT_MTU);
+        frame[0..2].copy_from_slice(&(len as u16).to_le_bytes());
+        frame[2..4].copy_from_slice(&cid.to_le_bytes());
+        frame[L2CAP_HEADER_LEN..L2CAP_HEADER_LEN + len].copy_from_slice(&data[..len]);
+        Pdu::new(data_pdu::LLID_START, &frame[..L2CAP_HEADER_LEN + len], kind)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ConnectionUpdate {
+    win_size_us: u32,
+    win_offset_us: u32,
+    interval_us: u32,
+    timeout_us: u32,
+    instant: u16,
+}
+
+#[derive(Clone, Copy)]
+struct Connection {
+    access_address: u32,
+    crc_init: u32,
+    interval_us: u32,
+    timeout_us: u32,
+    central_sca_ppm: u32,
+    hop: u8,
+    channel_map: [u8; 5],
+    last_unmapped_channel: u8,
+    channel: u8,
+    event_counter: u16,
+    /// The time from the last anchor point the central was heard at to the
+    /// next one.
+    since_anchor_us: u32,
+    /// The transmit window the next anchor point falls in.
+    window_us: u32,
+    established: bool,
+    sn: bool,
+    nesn: bool,
+    update: Option<ConnectionUpdate>,
+    channel_map_update: Option<([u8; 5], u16)>,
+    terminate: bool,
+}
+
+impl Connection {
+    fn is_used(&self, channel: u8) -> bool {
+        self.channel_map[channel as usize / 8] & (1 << (channel % 8)) != 0
+    }
+
+    fn used_channels(&self) -> u8 {
+        (0..37).filter(|channel| self.is_used(*channel)).count() as u8
+    }
+
+    /// Channel selection algorithm #1 (Vol 6, Part B, section 4.5.8.2).
+    fn select_channel(&mut self) {
+        let unmapped = (self.last_unmapped_channel + self.hop) % 37;
+        self.last_unmapped_channel = unmapped;
+        self.channel = if self.is_used(unmapped) {
+            unmapped
+        } else {
+            let remapping_index = unmapped % self.used_channels();
+            (0..37)
+                .filter(|channel| self.is_used(*channel))
+                .nth(remapping_index as usize)
+                .unwrap_or(0)
+        };
+    }
+
+    /// How early to start listening for the central, in microseconds
+    /// (Vol 6, Part B, section 4.5.7).
+    fn window_widening_us(&self) -> u32 {
+        let drift =
+            (self.central_sca_ppm + SCA_PPM) as u64 * self.since_anchor_us as u64 / 1_000_000;
+        drift as u32 + 16
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum State {
+    Idle,
+    AdvertisingIdle,
+    Advertising(RadioChannel),
+    /// A connection request was accepted while advertising.
+    Connecting,
+    Connected,
+    ConnectionEvent,
+}
+
+pub struct BleConnection<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    address: [u8; ADDRESS_LEN],
+    buffer: TakeCell<'static, [u8]>,
+    state: Cell<State>,
+    advertise: Cell<bool>,
+    interval_ms: Cell<u32>,
+    adv_data: Cell<[u8; MAX_ADV_DATA]>,
+    adv_data_len: Cell<usize>,
+    scan_rsp_data: Cell<[u8; MAX_ADV_DATA]>,
+    scan_rsp_data_len: Cell<usize>,
+    connection: Cell<Option<Connection>>,
+    anchor: Cell<A::Ticks>,
+    synced: Cell<bool>,
+    unacked: Cell<Option<Pdu>>,
+    response: Cell<Option<Pdu>>,
+    server_pdu: Cell<Option<Pdu>>,
+    terminate: Cell<bool>,
+    server: OptionalCell<&'a dyn AttServer>,
+    random_nonce: Cell<u32>,
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> BleConnection<'a, R, A> {
+    /// `address` is the device's static random address, with the two most
+    /// significant bits of its last byte set.
+    pub fn new(
+        radio: &'a R,
+        alarm: &'a A,
+        buffer: &'static mut [u8],
+        address: [u8; ADDRESS_LEN],
+    ) -> BleConnection<'a, R, A> {
+        BleConnection {
+            radio,
+            alarm,
+            address,
+            buffer: TakeCell::new(buffer),
+            state: Cell::new(State::Idle),
+            advertise: Cell::new(false),
+            interval_ms: Cell::new(100),
+            adv_data: Cell::new([0; MAX_ADV_DATA]),
+            adv_data_len: Cell::new(0),
+            scan_rsp_data: Cell::new([0; MAX_ADV_DATA]),
+            scan_rsp_data_len: Cell::new(0),
+            connection: Cell::new(None),
+            anchor: Cell::new(A::Ticks::from(0)),
+            synced: Cell::new(false),
+            unacked: Cell::new(None),
+            response: Cell::new(None),
+            server_pdu: Cell::new(None),
+            terminate: Cell::new(false),
+            server: OptionalCell::empty(),
+            // Just use any non-zero starting value
+            random_nonce: Cell::new(0xdeadbeef),
+        }
+    }
+
+    pub fn set_att_server(&self, server: &'a dyn AttServer) {
+        self.server.set(server);
+    }
+
+    /// Sets the data in advertisements. Returns `SIZE` if it is longer than
+    /// `MAX_ADV_DATA`.
+    pub fn set_advertising_data(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        let mut buf = [0; MAX_ADV_DATA];
+        buf.get_mut(..data.len())
+            .ok_or(ErrorCode::SIZE)?
+            .copy_from_slice(data);
+        self.adv_data.set(buf);
+        self.adv_data_len.set(data.len());
+        Ok(())
+    }
+
+    /// Sets the data in responses to scan requests. Returns `SIZE` if it is
+    /// longer than `MAX_ADV_DATA`.
+    pub fn set_scan_response_data(&self, data: &[u8]) -> Result<(), ErrorCode> {
+        let mut buf = [0; MAX_ADV_DATA];
+        buf.get_mut(..data.len())
+            .ok_or(ErrorCode::SIZE)?
+            .copy_from_slice(data);
+        self.scan_rsp_data.set(buf);
+        self.scan_rsp_data_len.set(data.len());
+        Ok(())
+    }
+
+    /// Starts advertising every `interval_ms` milliseconds, at least 20. While
+    /// a connection is open, advertising resumes once it closes.
+    pub fn start_advertising(&self, interval_ms: u32) {
+        self.interval_ms.set(cmp::max(20, interval_ms));
+        self.advertise.set(true);
+        if self.state.get() == State::Idle {
+            self.state.set(State::AdvertisingIdle);
+            self.alarm.set_alarm(self.alarm.now(), A::Ticks::from(0));
+        }
+    }
+
+    /// Stops advertising, and terminates the connection if there is one.
+    pub fn stop(&self) {
+        self.advertise.set(false);
+        match self.state.get() {
+            State::AdvertisingIdle => {
+                let _ = self.alarm.disarm();
+                self.state.set(State::Idle);
+            }
+            State::Connected | State::ConnectionEvent => self.terminate.set(true),
+            // Advertising stops once the current event is done
+            _ => {}
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection.get().is_some()
+    }
+
+    // Xorshift, as in the advertising driver
+    fn random(&self) -> u32 {
+        let mut nonce = core::num::Wrapping(self.random_nonce.get());
+        nonce ^= nonce << 13;
+        nonce ^= nonce >> 17;
+        nonce ^= nonce << 5;
+        self.random_nonce.set(nonce.0);
+        nonce.0
+    }
+
+    fn advertise_on(&self, channel: RadioChannel) {
+        match self.buffer.take() {
+            Some(buf) => {
+                let data_len = self.adv_data_len.get();
+                buf[0] = adv_pdu::ADV_IND | adv_pdu::TX_ADD;
+                buf[1] = (ADDRESS_LEN + data_len) as u8;
+                buf[2..2 + ADDRESS_LEN].copy_from_slice(&self.address);
+                buf[2 + ADDRESS_LEN..2 + ADDRESS_LEN + data_len]
+                    .copy_from_slice(&self.adv_data.get()[..data_len]);
+                self.state.set(State::Advertising(channel));
+                self.radio.transmit_connectable_advertisement(
+                    buf,
+                    2 + ADDRESS_LEN + data_len,
+                    channel,
+                );
+            }
+            None => self.schedule_advertising(),
+        }
+    }
+
+    fn schedule_advertising(&self) {
+        self.state.set(State::AdvertisingIdle);
+        // advDelay is a pseudo-random 0 to 10 ms
+        let delay_ms = self.interval_ms.get() + self.random() % 11;
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(delay_ms));
+    }
+
+    /// Parses a connection request and enters the connection state. Returns
+    /// whether the request is valid.
+    fn accept_connection(&self, pdu: &[u8]) -> bool {
+        let interval = read_u16(&pdu[22..24]) as u32;
+        let timeout = read_u16(&pdu[26..28]) as u32;
+        let hop = pdu[33] & 0x1f;
+        let mut channel_map = [0; 5];
+        channel_map.copy_from_slice(&pdu[28..33]);
+        channel_map[4] &= 0x1f;
+        let win_size = pdu[19] as u32;
+        let win_offset = read_u16(&pdu[20..22]) as u32;
+
+        let mut connection = Connection {
+            access_address: u32::from_le_bytes([pdu[12], pdu[13], pdu[14], pdu[15]]),
+            crc_init: u32::from_le_bytes([pdu[16], pdu[17], pdu[18], 0]),
+            interval_us: interval * UNIT_US,
+            timeout_us: timeout * 10_000,
+            central_sca_ppm: CENTRAL_SCA_PPM[(pdu[33] >> 5) as usize],
+            hop,
+            channel_map,
+            last_unmapped_channel: 0,
+            channel: 0,
+            event_counter: 0,
+            since_anchor_us: UNIT_US + win_offset * UNIT_US,
+            window_us: win_size * UNIT_US,
+            established: false,
+            sn: false,
+            nesn: false,
+            update: None,
+            channel_map_update: None,
+            terminate: false,
+        };
+        if !(6..=3200).contains(&interval)
+            || !(5..=16).contains(&hop)
+            || win_size == 0
+            || connection.used_channels() < 2
+        {
+            return false;
+        }
+        connection.select_channel();
+
+        // The transmit window starts 1.25 ms plus the window offset after the
+        // end of the request, which is now.
+        self.anchor.set(
+            self.alarm
+                .now()
+                .wrapping_add(self.alarm.ticks_from_us(connection.since_anchor_us)),
+        );
+        self.connection.set(Some(connection));
+        self.synced.set(false);
+        self.terminate.set(false);
+        self.unacked.set(None);
+        self.response.set(None);
+        self.server_pdu.set(None);
+        self.state.set(State::Connecting);
+        true
+    }
+
+    fn schedule_event(&self) {
+        if let Some(connection) = self.connection.get() {
+            self.state.set(State::Connected);
+            let widening = self.alarm.ticks_from_us(connection.window_widening_us());
+            let start = self.anchor.get().wrapping_sub(widening);
+            let now = self.alarm.now();
+            let mut dt = start.wrapping_sub(now);
+            if dt > A::Ticks::half_max_value() {
+                // The start of the event already passed
+                dt = A::Ticks::from(0);
+            }
+            self.alarm.set_alarm(now, dt);
+        }
+    }
+
+    fn start_event(&self) {
+        let connection = match self.connection.get() {
+            Some(connection) => connection,
+            None => return,
+        };
+        match self.buffer.take() {
+            Some(buf) => {
+                self.state.set(State::ConnectionEvent);
+                self.synced.set(false);
+                let timeout = 2 * connection.window_widening_us() + connection.window_us;
+                self.radio.connection_event(
+                    buf,
+                    DATA_CHANNELS[connection.channel as usize],
+                    connection.access_address,
+                    connection.crc_init,
+                    timeout,
+                );
+            }
+            None => self.next_event(connection),
+        }
+    }
+
+    /// Moves on to the next connection event.
+    fn next_event(&self, mut connection: Connection) {
+        if connection.terminate {
+            self.disconnect();
+            return;
+        }
+
+        connection.since_anchor_us += connection.interval_us;
+        connection.event_counter = connection.event_counter.wrapping_add(1);
+        self.anchor.set(
+            self.anchor
+                .get()
+                .wrapping_add(self.alarm.ticks_from_us(connection.interval_us)),
+        );
+        let lost = if connection.established {
+            connection.since_anchor_us >= connection.timeout_us
+        } else {
+            connection.event_counter >= ESTABLISHMENT_EVENTS
+        };
+
+        if let Some(update) = connection.update {
+            if connection.event_counter == update.instant {
+                connection.update = None;
+                connection.interval_us = update.interval_us;
+                connection.timeout_us = update.timeout_us;
+                connection.window_us = update.win_size_us;
+                connection.since_anchor_us += update.win_offset_us;
+                self.anchor.set(
+                    self.anchor
+                        .get()
+                        .wrapping_add(self.alarm.ticks_from_us(update.win_offset_us)),
+                );
+            }
+        }
+        if let Some((channel_map, instant)) = connection.channel_map_update {
+            if connection.event_counter == instant {
+                connection.channel_map_update = None;
+                connection.channel_map = channel_map;
+            }
+        }
+        // An instant that is already in the past means the link is lost
+        let passed = |instant: u16| instant.wrapping_sub(connection.event_counter) >= 0x8000;
+        let missed_instant = connection.update.map_or(false, |u| passed(u.instant))
+            || connection
+                .channel_map_update
+                .map_or(false, |(_, instant)| passed(instant));
+
+        if lost || missed_instant {
+            self.disconnect();
+            return;
+        }
+        connection.select_channel();
+        self.connection.set(Some(connection));
+        self.schedule_event();
+    }
+
+    fn disconnect(&self) {
+        let _ = self.alarm.disarm();
+        self.connection.set(None);
+        self.unacked.set(None);
+        self.response.set(None);
+        self.server_pdu.set(None);
+        self.terminate.set(false);
+        self.state.set(State::Idle);
+        self.server.map(|server| server.disconnected());
+        if self.advertise.get() {
+            self.schedule_advertising();
+        }
+    }
+
+    fn respond(&self, llid: u8, data: &[u8]) {
+        self.response
+            .set(Some(Pdu::new(llid, data, PduKind::Response)));
+    }
+
+    fn receive_control(&self, payload: &[u8]) {
+        let mut connection = match self.connection.get() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let opcode = match payload.first() {
+            Some(opcode) => *opcode,
+            None => return,
+        };
+        match opcode {
+            control::CONNECTION_UPDATE_IND if payload.len() >= 12 => {
+                connection.update = Some(ConnectionUpdate {
+                    win_size_us: payload[1] as u32 * UNIT_US,
+                    win_offset_us: read_u16(&payload[2..4]) as u32 * UNIT_US,
+                    interval_us: read_u16(&payload[4..6]) as u32 * UNIT_US,
+                    timeout_us: read_u16(&payload[8..10]) as u32 * 10_000,
+                    instant: read_u16(&payload[10..12]),
+                });
+            }
+            control::CHANNEL_MAP_IND if payload.len() >= 8 => {
+                let mut channel_map = [0; 5];
+                channel_map.copy_from_slice(&payload[1..6]);
+                channel_map[4] &= 0x1f;
+                connection.channel_map_update = Some((channel_map, read_u16(&payload[6..8])));
+            }
+            control::TERMINATE_IND => connection.terminate = true,
+            control::ENC_REQ => self.respond(
+                data_pdu::LLID_CONTROL,
+                &[control::REJECT_IND, control::UNSUPPORTED_REMOTE_FEATURE],
+            ),
+            control::FEATURE_REQ => {
+                let mut rsp = [0; 9];
+                rsp[0] = control::FEATURE_RSP;
+                self.respond(data_pdu::LLID_CONTROL, &rsp);
+            }
+            control::VERSION_IND => {
+                let company = control::COMPANY_ID.to_le_bytes();
+                self.respond(
+                    data_pdu::LLID_CONTROL,
+                    &[
+                        control::VERSION_IND,
+                        control::VERSION,
+                        company[0],
+                        company[1],
+                        0,
+                        0,
+                    ],
+                );
+            }
+            control::PING_REQ => self.respond(data_pdu::LLID_CONTROL, &[control::PING_RSP]),
+            // Responses to procedures this device does not start
+            control::UNKNOWN_RSP | control::FEATURE_RSP | control::REJECT_IND => {}
+            _ => self.respond(data_pdu::LLID_CONTROL, &[control::UNKNOWN_RSP, opcode]),
+        }
+        self.connection.set(Some(connection));
+    }
+
+    fn receive_l2cap(&self, payload: &[u8]) {
+        // Only frames that fit in one PDU are supported
+        if payload.len() < L2CAP_HEADER_LEN
+            || read_u16(&payload[0..2]) as usize != payload.len() - L2CAP_HEADER_LEN
+        {
+            return;
+        }
+        let data = &payload[L2CAP_HEADER_LEN..];
+        match read_u16(&payload[2..4]) {
+            l2cap::CID_ATT => {
+                let mut response = [0; ATT_MTU];
+                let len = self
+                    .server
+                    .map_or(0, |server| server.att_received(data, &mut response));
+                if len > 0 {
+                    self.response.set(Some(Pdu::l2cap(
+                        l2cap::CID_ATT,
+                        &response[..cmp::min(len, ATT_MTU)],
+                        PduKind::Response,
+                    )));
+                }
+            }
+            l2cap::CID_SIGNALING if data.len() >= 2 => match data[0] {
+                l2cap::COMMAND_REJECT
+                | l2cap::CONNECTION_PARAMETER_UPDATE_RSP
+                | l2cap::LE_CREDIT_CONNECTION_RSP => {}
+                _ => {
+                    // Command not understood
+                    let reject = [l2cap::COMMAND_REJECT, data[1], 2, 0, 0, 0];
+                    self.response.set(Some(Pdu::l2cap(
+                        l2cap::CID_SIGNALING,
+                        &reject,
+                        PduKind::Response,
+                    )));
+                }
+            },
+            l2cap::CID_SMP if data.first() == Some(&l2cap::SMP_PAIRING_REQUEST) => {
+                let failed = [l2cap::SMP_PAIRING_FAILED, l2cap::SMP_PAIRING_NOT_SUPPORTED];
+                self.response
+                    .set(Some(Pdu::l2cap(l2cap::CID_SMP, &failed, PduKind::Response)));
+            }
+            _ => {}
+        }
+    }
+
+    /// Picks the PDU to send next: a retransmission, a response, a
+    /// termination, a PDU from the server, or an empty PDU.
+    fn next_pdu(&self) -> Pdu {
+        if let Some(pdu) = self.unacked.get() {
+            return pdu;
+        }
+        let pdu = if let Some(pdu) = self.response.take() {
+            pdu
+        } else if self.terminate.take() {
+            Pdu::new(
+                data_pdu::LLID_CONTROL,
+                &[control::TERMINATE_IND, control::REMOTE_USER_TERMINATED],
+                PduKind::Terminate,
+            )
+        } else if let Some(pdu) = self.server_pdu.take() {
+            pdu
+        } else {
+            Pdu::empty()
+        };
+        self.unacked.set(Some(pdu));
+        pdu
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> AttBearer for BleConnection<'a, R, A> {
+    fn send_att(&self, pdu: &[u8]) -> Result<(), ErrorCode> {
+        if self.connection.get().is_none() {
+            return Err(ErrorCode::OFF);
+        }
+        if pdu.len() > ATT_MTU {
+            return Err(ErrorCode::SIZE);
+        }
+        if self.server_pdu.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.server_pdu
+            .set(Some(Pdu::l2cap(l2cap::CID_ATT, pdu, PduKind::Server)));
+        Ok(())
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> time::AlarmClient
+    for BleConnection<'a, R, A>
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::AdvertisingIdle => self.advertise_on(RadioChannel::AdvertisingChannel37),
+            State::Connected => self.start_event(),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> ConnectionClient
+    for BleConnection<'a, R, A>
+{
+    fn advertising_request(&self, buf: &mut [u8], len: usize) -> usize {
+        if !matches!(self.state.get(), State::Advertising(_)) || len < 2 {
+            return 0;
+        }
+        let pdu_len = cmp::min(buf[1] as usize, len - 2);
+        match buf[0] & adv_pdu::TYPE_MASK {
+            adv_pdu::SCAN_REQ
+                if pdu_len >= 2 * ADDRESS_LEN
+                    && buf[2 + ADDRESS_LEN..2 + 2 * ADDRESS_LEN] == self.address =>
+            {
+                let data_len = self.scan_rsp_data_len.get();
+                buf[0] = adv_pdu::SCAN_RSP | adv_pdu::TX_ADD;
+                buf[1] = (ADDRESS_LEN + data_len) as u8;
+                buf[2..2 + ADDRESS_LEN].copy_from_slice(&self.address);
+                buf[2 + ADDRESS_LEN..2 + ADDRESS_LEN + data_len]
+                    .copy_from_slice(&self.scan_rsp_data.get()[..data_len]);
+                2 + ADDRESS_LEN + data_len
+            }
+            adv_pdu::CONNECT_IND
+                if self.advertise.get()
+                    && pdu_len >= adv_pdu::CONNECT_IND_LEN
+                    && buf[2 + ADDRESS_LEN..2 + 2 * ADDRESS_LEN] == self.address =>
+            {
+                self.accept_connection(&buf[2..2 + adv_pdu::CONNECT_IND_LEN]);
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    fn advertisement_done(&self, buf: &'static mut [u8], _result: Result<(), ErrorCode>) {
+        self.buffer.replace(buf);
+        match self.state.get() {
+            State::Connecting => {
+                self.server.map(|server| server.connected());
+                self.schedule_event();
+            }
+            State::Advertising(_) if !self.advertise.get() => self.state.set(State::Idle),
+            State::Advertising(RadioChannel::AdvertisingChannel37) => {
+                self.advertise_on(RadioChannel::AdvertisingChannel38)
+            }
+            State::Advertising(RadioChannel::AdvertisingChannel38) => {
+                self.advertise_on(RadioChannel::AdvertisingChannel39)
+            }
+            State::Advertising(_) => self.schedule_advertising(),
+            _ => {}
+        }
+    }
+
+    fn data_received(&self, buf: &mut [u8], len: usize, crc_ok: bool) -> usize {
+        let mut connection = match self.connection.get() {
+            Some(connection) => connection,
+            None => return 0,
+        };
+
+        let mut acked = None;
+        if crc_ok && len >= 2 {
+            if !self.synced.get() {
+                // The anchor point is the start of the central's first packet
+                self.synced.set(true);
+                let airtime_us = (PACKET_OVERHEAD + len as u32) * 8;
+                self.anchor.set(
+                    self.alarm
+                        .now()
+                        .wrapping_sub(self.alarm.ticks_from_us(airtime_us)),
+                );
+                connection.since_anchor_us = 0;
+                connection.window_us = 0;
+                connection.established = true;
+            }
+
+            let header = buf[0];
+            if (header & data_pdu::NESN != 0) != connection.sn {
+                connection.sn = !connection.sn;
+                acked = self.unacked.take().map(|pdu| pdu.kind);
+            }
+            if acked == Some(PduKind::Terminate) {
+                connection.terminate = true;
+            }
+            if (header & data_pdu::SN != 0) == connection.nesn {
+                connection.nesn = !connection.nesn;
+                let mut payload = [0; MAX_PAYLOAD];
+                let payload_len = cmp::min(cmp::min(buf[1] as usize, len - 2), MAX_PAYLOAD);
+                payload[..payload_len].copy_from_slice(&buf[2..2 + payload_len]);
+
+                self.connection.set(Some(connection));
+                match header & data_pdu::LLID_MASK {
+                    data_pdu::LLID_CONTROL => self.receive_control(&payload[..payload_len]),
+                    data_pdu::LLID_START => self.receive_l2cap(&payload[..payload_len]),
+                    // Empty PDUs, and continuations of frames that are too
+                    // long to be supported
+                    _ => {}
+                }
+                connection = self.connection.get().unwrap_or(connection);
+            }
+        }
+        self.connection.set(Some(connection));
+        if acked == Some(PduKind::Server) {
+            self.server.map(|server| server.att_sent());
+        }
+
+        let pdu = self.next_pdu();
+        buf[0] = pdu.llid
+            | if connection.nesn { data_pdu::NESN } else { 0 }
+            | if connection.sn { data_pdu::SN } else { 0 };
+        buf[1] = pdu.len as u8;
+        buf[2..2 + pdu.len].copy_from_slice(&pdu.payload[..pdu.len]);
+        2 + pdu.len
+    }
+
+    fn connection_event_done(&self, buf: &'static mut [u8], _result: Result<(), ErrorCode>) {
+        self.buffer.replace(buf);
+        if let Some(connection) = self.connection.get() {
+            self.next_event(connection);
+        }
+    }
+}
@@ -0,0 +1,703 @@
+//! A minimal GATT server over the BLE link layer.
+//!
+//! `GattServer` keeps a fixed table of attributes with 16-bit UUIDs. It
+//! always holds the Generic Access service with the device name and
+//! appearance, and other services and their characteristics are added at run
+//! time, usually by `ble_gatt_driver` on behalf of a process. Characteristic
+//! values are at most `MAX_VALUE_LEN` bytes and are stored in the table.
+//!
+//! The server answers the ATT requests a central needs to discover services
+//! and characteristics, and to read and write values. Writes are passed to a
+//! `GattClient`. Characteristics with the notify property get a client
+//! characteristic configuration descriptor, and changing their value sends a
+//! notification if the central enabled them.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let gatt_server = static_init!(
+//!     capsules::ble_gatt::GattServer<'static>,
+//!     capsules::ble_gatt::GattServer::new(b"Tock")
+//! );
+//! gatt_server.set_bearer(ble_connection);
+//! ble_connection.set_att_server(gatt_server);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+use crate::ble_connection::{AttBearer, AttServer, ATT_MTU};
+
+/// The number of attributes, including the five of the Generic Access
+/// service.
+pub const MAX_ATTRIBUTES: usize = 32;
+
+/// The longest characteristic value.
+pub const MAX_VALUE_LEN: usize = 20;
+
+/// Characteristic properties.
+pub mod properties {
+    pub const READ: u8 = 0x02;
+    pub const WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+    pub const WRITE: u8 = 0x08;
+    pub const NOTIFY: u8 = 0x10;
+}
+
+mod uuid {
+    pub const GENERIC_ACCESS: u16 = 0x1800;
+    pub const PRIMARY_SERVICE: u16 = 0x2800;
+    pub const CHARACTERISTIC: u16 = 0x2803;
+    pub const CLIENT_CONFIGURATION: u16 = 0x2902;
+    pub const DEVICE_NAME: u16 = 0x2a00;
+    pub const APPEARANCE: u16 = 0x2a01;
+}
+
+// Bluetooth Core Specification 5.0, Vol 3, Part F, section 3.4
+mod opcode {
+    pub const ERROR_RSP: u8 = 0x01;
+    pub const EXCHANGE_MTU_REQ: u8 = 0x02;
+    pub const EXCHANGE_MTU_RSP: u8 = 0x03;
+    pub const FIND_INFORMATION_REQ: u8 = 0x04;
+    pub const FIND_INFORMATION_RSP: u8 = 0x05;
+    pub const FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+    pub const FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+    pub const READ_BY_TYPE_REQ: u8 = 0x08;
+    pub const READ_BY_TYPE_RSP: u8 = 0x09;
+    pub const READ_REQ: u8 = 0x0a;
+    pub const READ_RSP: u8 = 0x0b;
+    pub const READ_BLOB_REQ: u8 = 0x0c;
+    pub const READ_BLOB_RSP: u8 = 0x0d;
+    pub const READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+    pub const READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+    pub const WRITE_REQ: u8 = 0x12;
+    pub const WRITE_RSP: u8 = 0x13;
+    pub const HANDLE_VALUE_NTF: u8 = 0x1b;
+    pub const WRITE_CMD: u8 = 0x52;
+    /// Set in the opcodes of commands, which have no response.
+    pub const COMMAND_FLAG: u8 = 0x40;
+}
+
+mod error {
+    pub const INVALID_HANDLE: u8 = 0x01;
+    pub const READ_NOT_PERMITTED: u8 = 0x02;
+    pub const WRITE_NOT_PERMITTED: u8 = 0x03;
+    pub const INVALID_PDU: u8 = 0x04;
+    pub const REQUEST_NOT_SUPPORTED: u8 = 0x06;
+    pub const INVALID_OFFSET: u8 = 0x07;
+    pub const ATTRIBUTE_NOT_FOUND: u8 = 0x0a;
+    pub const INVALID_ATTRIBUTE_VALUE_LENGTH: u8 = 0x0d;
+    pub const UNSUPPORTED_GROUP_TYPE: u8 = 0x10;
+}
+
+/// Told about writes from the central and about the connection.
+pub trait GattClient {
+    /// The central wrote `value` to the characteristic value at `handle`.
+    fn written(&self, handle: u16, value: &[u8]);
+
+    fn connection_changed(&self, connected: bool);
+}
+
+#[derive(Clone, Copy)]
+enum Attribute {
+    Unused,
+    Service {
+        uuid: u16,
+    },
+    Declaration {
+        properties: u8,
+        uuid: u16,
+    },
+    Value {
+        uuid: u16,
+        properties: u8,
+        value: [u8; MAX_VALUE_LEN],
+        len: u8,
+        notify_pending: bool,
+    },
+    ClientConfiguration {
+        notify: bool,
+    },
+}
+
+impl Default for Attribute {
+    fn default() -> Attribute {
+        Attribute::Unused
+    }
+}
+
+impl Attribute {
+    fn value(uuid: u16, properties: u8, data: &[u8]) -> Attribute {
+        let len = cmp::min(data.len(), MAX_VALUE_LEN);
+        let mut value = [0; MAX_VALUE_LEN];
+        value[..len].copy_from_slice(&data[..len]);
+        Attribute::Value {
+            uuid,
+            properties,
+            value,
+            len: len as u8,
+            notify_pending: false,
+        }
+    }
+
+    fn uuid(&self) -> u16 {
+        match *self {
+            Attribute::Unused => 0,
+            Attribute::Service { .. } => uuid::PRIMARY_SERVICE,
+            Attribute::Declaration { .. } => uuid::CHARACTERISTIC,
+            Attribute::Value { uuid, .. } => uuid,
+            Attribute::ClientConfiguration { .. } => uuid::CLIENT_CONFIGURATION,
+        }
+    }
+}
+
+/// Writes an error response and returns its length.
+fn error_response(response: &mut [u8], request: u8, handle: u16, code: u8) -> usize {
+    response[0] = opcode::ERROR_RSP;
+    response[1] = request;
+    response[2..4].copy_from_slice(&handle.to_le_bytes());
+    response[4] = code;
+    5
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    u16::from_le_bytes([buf[0], buf[1]])
+}
+
+pub struct GattServer<'a> {
+    attributes: [Cell<Attribute>; MAX_ATTRIBUTES],
+    /// The number of attributes in use.
+    count: Cell<usize>,
+    connected: Cell<bool>,
+    notifying: Cell<bool>,
+    bearer: OptionalCell<&'a dyn AttBearer>,
+    client: OptionalCell<&'a dyn GattClient>,
+}
+
+impl<'a> GattServer<'a> {
+    /// Creates a server with the Generic Access service, using `name` as the
+    /// device name. Names longer than `MAX_VALUE_LEN` are truncated.
+    pub fn new(name: &[u8]) -> GattServer<'a> {
+        let server = GattServer {
+            attributes: Default::default(),
+            count: Cell::new(0),
+            connected: Cell::new(false),
+            notifying: Cell::new(false),
+            bearer: OptionalCell::empty(),
+            client: OptionalCell::empty(),
+        };
+        let gap = [
+            Attribute::Service {
+                uuid: uuid::GENERIC_ACCESS,
+            },
+            Attribute::Declaration {
+                properties: properties::READ,
+                uuid: uuid::DEVICE_NAME,
+            },
+            Attribute::value(uuid::DEVICE_NAME, properties::READ, name),
+            Attribute::Declaration {
+                properties: properties::READ,
+                uuid: uuid::APPEARANCE,
+            },
+            // Unknown appearance
+            Attribute::value(uuid::APPEARANCE, properties::READ, &[0, 0]),
+        ];
+        for (attribute, cell) in gap.iter().zip(server.attributes.iter()) {
+            cell.set(*attribute);
+        }
+        server.count.set(gap.len());
+        server
+    }
+
+    pub fn set_bearer(&self, bearer: &'a dyn AttBearer) {
+        self.bearer.set(bearer);
+    }
+
+    pub fn set_client(&self, client: &'a dyn GattClient) {
+        self.client.set(client);
+    }
+
+    fn get(&self, handle: u16) -> Option<Attribute> {
+        let index = (handle as usize).checked_sub(1)?;
+        if index < self.count.get() {
+            Some(self.attributes[index].get())
+        } else {
+            None
+        }
+    }
+
+    fn set(&self, handle: u16, attribute: Attribute) {
+        self.attributes[handle as usize - 1].set(attribute);
+    }
+
+    fn last_handle(&self) -> u16 {
+        self.count.get() as u16
+    }
+
+    fn push(&self, attributes: &[Attribute]) -> Result<u16, ErrorCode> {
+        let count = self.count.get();
+        if count + attributes.len() > MAX_ATTRIBUTES {
+            return Err(ErrorCode::NOMEM);
+        }
+        for (i, attribute) in attributes.iter().enumerate() {
+            self.attributes[count + i].set(*attribute);
+        }
+        self.count.set(count + attributes.len());
+        Ok(count as u16 + 1)
+    }
+
+    /// Adds a primary service and returns its handle. Returns `NOMEM` if the
+    /// attribute table is full.
+    pub fn add_service(&self, uuid: u16) -> Result<u16, ErrorCode> {
+        self.push(&[Attribute::Service { uuid }])
+    }
+
+    /// Adds a characteristic to the last service added, and returns the
+    /// handle of its value, which starts empty. Returns `INVAL` if no service
+    /// was added and `NOMEM` if the attribute table is full.
+    pub fn add_characteristic(&self, uuid: u16, properties: u8) -> Result<u16, ErrorCode> {
+        if self.count.get() <= 5 {
+            return Err(ErrorCode::INVAL);
+        }
+        let declaration = Attribute::Declaration { properties, uuid };
+        let value = Attribute::value(uuid, properties, &[]);
+        let handle = if properties & properties::NOTIFY != 0 {
+            self.push(&[
+                declaration,
+                value,
+                Attribute::ClientConfiguration { notify: false },
+            ])?
+        } else {
+            self.push(&[declaration, value])?
+        };
+        Ok(handle + 1)
+    }
+
+    /// Removes all services except Generic Access.
+    pub fn remove_services(&self) {
+        for attribute in self.attributes[5..].iter() {
+            attribute.set(Attribute::Unused);
+        }
+        self.count.set(5);
+    }
+
+    /// Sets a characteristic value, and notifies the central if it asked to
+    /// be. Returns `INVAL` if `handle` is not a characteristic value and
+    /// `SIZE` if `value` is longer than `MAX_VALUE_LEN`.
+    pub fn set_value(&self, handle: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(ErrorCode::SIZE);
+        }
+        match self.get(handle) {
+            Some(Attribute::Value {
+                uuid, properties, ..
+            }) => {
+                let mut attribute = Attribute::value(uuid, properties, value);
+                if let Attribute::Value {
+                    ref mut notify_pending,
+                    ..
+                } = attribute
+                {
+                    *notify_pending = self.notifications_enabled(handle);
+                }
+                self.set(handle, attribute);
+                self.notify_next();
+                Ok(())
+            }
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+
+    fn notifications_enabled(&self, value_handle: u16) -> bool {
+        matches!(
+            self.get(value_handle + 1),
+            Some(Attribute::ClientConfiguration { notify: true })
+        )
+    }
+
+    /// Sends a notification for the first value that changed since the
+    /// central last heard of it.
+    fn notify_next(&self) {
+        if self.notifying.get() || !self.connected.get() {
+            return;
+        }
+        for handle in 1..=self.last_handle() {
+            if let Some(Attribute::Value {
+                uuid,
+                properties,
+                value,
+                len,
+                notify_pending: true,
+            }) = self.get(handle)
+            {
+                let len = cmp::min(len as usize, ATT_MTU - 3);
+                let mut pdu = [0; ATT_MTU];
+                pdu[0] = opcode::HANDLE_VALUE_NTF;
+                pdu[1..3].copy_from_slice(&handle.to_le_bytes());
+                pdu[3..3 + len].copy_from_slice(&value[..len]);
+                let result = self.bearer.map_or(Err(ErrorCode::OFF), |bearer| {
+                    bearer.send_att(&pdu[..3 + len])
+                });
+                if result != Err(ErrorCode::BUSY) {
+                    self.set(
+                        handle,
+                        Attribute::Value {
+                            uuid,
+                            properties,
+                            value,
+                            len: len as u8,
+                            notify_pending: false,
+                        },
+                    );
+                }
+                self.notifying.set(result.is_ok());
+                return;
+            }
+        }
+    }
+
+    /// The value of an attribute as the central reads it.
+    fn read_value(&self, handle: u16, attribute: Attribute, buf: &mut [u8]) -> usize {
+        match attribute {
+            Attribute::Unused => 0,
+            Attribute::Service { uuid } => {
+                buf[..2].copy_from_slice(&uuid.to_le_bytes());
+                2
+            }
+            Attribute::Declaration { properties, uuid } => {
+                buf[0] = properties;
+                buf[1..3].copy_from_slice(&(handle + 1).to_le_bytes());
+                buf[3..5].copy_from_slice(&uuid.to_le_bytes());
+                5
+            }
+            Attribute::Value { value, len, .. } => {
+                buf[..len as usize].copy_from_slice(&value[..len as usize]);
+                len as usize
+            }
+            Attribute::ClientConfiguration { notify } => {
+                buf[0] = notify as u8;
+                buf[1] = 0;
+                2
+            }
+        }
+    }
+
+    /// The last handle in the service that starts at `handle`.
+    fn group_end(&self, handle: u16) -> u16 {
+        (handle + 1..=self.last_handle())
+            .find(|next| matches!(self.get(*next), Some(Attribute::Service { .. })))
+            .map_or(self.last_handle(), |next| next - 1)
+    }
+
+    /// Checks a handle range, returning the error response if it is invalid.
+    fn handle_range(&self, pdu: &[u8]) -> Result<(u16, u16), u8> {
+        let start = read_u16(&pdu[1..3]);
+        let end = read_u16(&pdu[3..5]);
+        if start == 0 || start > end {
+            Err(error::INVALID_HANDLE)
+        } else {
+            Ok((start, cmp::min(end, self.last_handle())))
+        }
+    }
+
+    fn find_information(&self, start: u16, end: u16, response: &mut [u8]) -> usize {
+        response[0] = opcode::FIND_INFORMATION_RSP;
+        // 16-bit UUIDs
+        response[1] = 0x01;
+        let mut len = 2;
+        for handle in start..=end {
+            if len + 4 > ATT_MTU {
+                break;
+            }
+            if let Some(attribute) = self.get(handle) {
+                response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                response[len + 2..len + 4].copy_from_slice(&attribute.uuid().to_le_bytes());
+                len += 4;
+            }
+        }
+        len
+    }
+
+    fn find_services(&self, start: u16, end: u16, service: u16, response: &mut [u8]) -> usize {
+        response[0] = opcode::FIND_BY_TYPE_VALUE_RSP;
+        let mut len = 1;
+        for handle in start..=end {
+            if len + 4 > ATT_MTU {
+                break;
+            }
+            if let Some(Attribute::Service { uuid }) = self.get(handle) {
+                if uuid == service {
+                    response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                    response[len + 2..len + 4]
+                        .copy_from_slice(&self.group_end(handle).to_le_bytes());
+                    len += 4;
+                }
+            }
+        }
+        len
+    }
+
+    /// Reads the attributes of a type, all with the same length as the
+    /// first.
+    fn read_by_type(&self, start: u16, end: u16, uuid: u16, response: &mut [u8]) -> usize {
+        response[0] = opcode::READ_BY_TYPE_RSP;
+        let mut len = 2;
+        let mut entry_len = 0;
+        for handle in start..=end {
+            match self.get(handle) {
+                Some(attribute) if attribute.uuid() == uuid => {
+                    if let Attribute::Value { properties, .. } = attribute {
+                        if properties & properties::READ == 0 {
+                            continue;
+                        }
+                    }
+                    let mut value = [0; MAX_VALUE_LEN];
+                    let value_len =
+                        cmp::min(self.read_value(handle, attribute, &mut value), ATT_MTU - 4);
+                    if entry_len == 0 {
+                        entry_len = value_len + 2;
+                    } else if value_len + 2 != entry_len || len + entry_len > ATT_MTU {
+                        break;
+                    }
+                    response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                    response[len + 2..len + entry_len].copy_from_slice(&value[..value_len]);
+                    len += entry_len;
+                }
+                _ => {}
+            }
+        }
+        response[1] = entry_len as u8;
+        len
+    }
+
+    fn read_services(&self, start: u16, end: u16, response: &mut [u8]) -> usize {
+        response[0] = opcode::READ_BY_GROUP_TYPE_RSP;
+        response[1] = 6;
+        let mut len = 2;
+        for handle in start..=end {
+            if len + 6 > ATT_MTU {
+                break;
+            }
+            if let Some(Attribute::Service { uuid }) = self.get(handle) {
+                response[len..len + 2].copy_from_slice(&handle.to_le_bytes());
+                response[len + 2..len + 4].copy_from_slice(&self.group_end(handle).to_le_bytes());
+                response[len + 4..len + 6].copy_from_slice(&uuid.to_le_bytes());
+                len += 6;
+            }
+        }
+        len
+    }
+
+    /// Reads an attribute from `offset`, returning an error code if it cannot
+    /// be read.
+    fn read(&self, handle: u16, offset: usize, response: &mut [u8]) -> Result<usize, u8> {
+        let attribute = self.get(handle).ok_or(error::INVALID_HANDLE)?;
+        if let Attribute::Value { properties, .. } = attribute {
+            if properties & properties::READ == 0 {
+                return Err(error::READ_NOT_PERMITTED);
+            }
+        }
+        let mut value = [0; MAX_VALUE_LEN];
+        let len = self.read_value(handle, attribute, &mut value);
+        if offset > len {
+            return Err(error::INVALID_OFFSET);
+        }
+        let read_len = cmp::min(len - offset, ATT_MTU - 1);
+        response[1..1 + read_len].copy_from_slice(&value[offset..offset + read_len]);
+        Ok(1 + read_len)
+    }
+
+    /// Writes an attribute, returning an error code if it cannot be written.
+    fn write(&self, handle: u16, data: &[u8], with_response: bool) -> Result<(), u8> {
+        match self.get(handle).ok_or(error::INVALID_HANDLE)? {
+            Attribute::ClientConfiguration { .. } => {
+                if data.len() != 2 {
+                    return Err(error::INVALID_ATTRIBUTE_VALUE_LENGTH);
+                }
+                self.set(
+                    handle,
+                    Attribute::ClientConfiguration {
+                        notify: data[0] & 0x01 != 0,
+                    },
+                );
+                Ok(())
+            }
+            Attribute::Value {
+                uuid, properties, ..
+            } => {
+                let permitted = if with_response {
+                    properties::WRITE
+                } else {
+                    properties::WRITE_WITHOUT_RESPONSE
+                };
+                if properties & permitted == 0 {
+                    return Err(error::WRITE_NOT_PERMITTED);
+                }
+                if data.len() > MAX_VALUE_LEN {
+                    return Err(error::INVALID_ATTRIBUTE_VALUE_LENGTH);
+                }
+                self.set(handle, Attribute::value(uuid, properties, data));
+                self.client.map(|client| client.written(handle, data));
+                Ok(())
+            }
+            _ => Err(error::WRITE_NOT_PERMITTED),
+        }
+    }
+
+    fn handle_request(&self, pdu: &[u8], response: &mut [u8]) -> Result<usize, (u16, u8)> {
+        let invalid = (0, error::INVALID_PDU);
+        match pdu[0] {
+            opcode::EXCHANGE_MTU_REQ if pdu.len() == 3 => {
+                // The MTU stays at the default
+                response[0] = opcode::EXCHANGE_MTU_RSP;
+                response[1..3].copy_from_slice(&(ATT_MTU as u16).to_le_bytes());
+                Ok(3)
+            }
+            opcode::FIND_INFORMATION_REQ if pdu.len() == 5 => {
+                let (start, end) = self
+                    .handle_range(pdu)
+                    .map_err(|e| (read_u16(&pdu[1..3]), e))?;
+                match self.find_information(start, end, response) {
+                    2 => Err((start, error::ATTRIBUTE_NOT_FOUND)),
+                    len => Ok(len),
+                }
+            }
+            opcode::FIND_BY_TYPE_VALUE_REQ if pdu.len() >= 7 => {
+                let (start, end) = self
+                    .handle_range(pdu)
+                    .map_err(|e| (read_u16(&pdu[1..3]), e))?;
+                // Only services with 16-bit UUIDs can be found
+                let len = if read_u16(&pdu[5..7]) == uuid::PRIMARY_SERVICE && pdu.len() == 9 {
+                    self.find_services(start, end, read_u16(&pdu[7..9]), response)
+                } else {
+                    1
+                };
+                match len {
+                    1 => Err((start, error::ATTRIBUTE_NOT_FOUND)),
+                    len => Ok(len),
+                }
+            }
+            opcode::READ_BY_TYPE_REQ if pdu.len() == 7 || pdu.len() == 21 => {
+                let (start, end) = self
+                    .handle_range(pdu)
+                    .map_err(|e| (read_u16(&pdu[1..3]), e))?;
+                let len = if pdu.len() == 7 {
+                    self.read_by_type(start, end, read_u16(&pdu[5..7]), response)
+                } else {
+                    2
+                };
+                match len {
+                    2 => Err((start, error::ATTRIBUTE_NOT_FOUND)),
+                    len => Ok(len),
+                }
+            }
+            opcode::READ_REQ | opcode::READ_BLOB_REQ => {
+                let blob = pdu[0] == opcode::READ_BLOB_REQ;
+                if pdu.len() != if blob { 5 } else { 3 } {
+                    return Err(invalid);
+                }
+                let handle = read_u16(&pdu[1..3]);
+                let offset = if blob {
+                    read_u16(&pdu[3..5]) as usize
+                } else {
+                    0
+                };
+                response[0] = if blob {
+                    opcode::READ_BLOB_RSP
+                } else {
+                    opcode::READ_RSP
+                };
+                self.read(handle, offset, response).map_err(|e| (handle, e))
+            }
+            opcode::READ_BY_GROUP_TYPE_REQ if pdu.len() == 7 || pdu.len() == 21 => {
+                let (start, end) = self
+                    .handle_range(pdu)
+                    .map_err(|e| (read_u16(&pdu[1..3]), e))?;
+                if pdu.len() != 7 || read_u16(&pdu[5..7]) != uuid::PRIMARY_SERVICE {
+                    return Err((start, error::UNSUPPORTED_GROUP_TYPE));
+                }
+                match self.read_services(start, end, response) {
+                    2 => Err((start, error::ATTRIBUTE_NOT_FOUND)),
+                    len => Ok(len),
+                }
+            }
+            opcode::WRITE_REQ if pdu.len() >= 3 => {
+                let handle = read_u16(&pdu[1..3]);
+                self.write(handle, &pdu[3..], true)
+                    .map_err(|e| (handle, e))?;
+                response[0] = opcode::WRITE_RSP;
+                Ok(1)
+            }
+            opcode::EXCHANGE_MTU_REQ
+            | opcode::FIND_INFORMATION_REQ
+            | opcode::FIND_BY_TYPE_VALUE_REQ
+            | opcode::READ_BY_TYPE_REQ
+            | opcode::READ_BY_GROUP_TYPE_REQ
+            | opcode::WRITE_REQ => Err(invalid),
+            _ => Err((0, error::REQUEST_NOT_SUPPORTED)),
+        }
+    }
+}
+
+impl<'a> AttServer for GattServer<'a> {
+    fn att_received(&self, pdu: &[u8], response: &mut [u8]) -> usize {
+        let request = match pdu.first() {
+            Some(request) => *request,
+            None => return 0,
+        };
+        if request & opcode::COMMAND_FLAG != 0 {
+            if request == opcode::WRITE_CMD && pdu.len() >= 3 {
+                let _ = self.write(read_u16(&pdu[1..3]), &pdu[3..], false);
+            }
+            return 0;
+        }
+        match self.handle_request(pdu, response) {
+            Ok(len) => len,
+            Err((handle, code)) => error_response(response, request, handle, code),
+        }
+    }
+
+    fn att_sent(&self) {
+        self.notifying.set(false);
+        self.notify_next();
+    }
+
+    fn connected(&self) {
+        self.connected.set(true);
+        self.client.map(|client| client.connection_changed(true));
+    }
+
+    fn disconnected(&self) {
+        self.connected.set(false);
+        self.notifying.set(false);
+        // Without bonding, notifications are off in each new connection
+        for handle in 1..=self.last_handle() {
+            match self.get(handle) {
+                Some(Attribute::ClientConfiguration { .. }) => {
+                    self.set(handle, Attribute::ClientConfiguration { notify: false })
+                }
+                Some(Attribute::Value {
+                    uuid,
+                    properties,
+                    value,
+                    len,
+                    ..
+                }) => self.set(
+                    handle,
+                    Attribute::Value {
+                        uuid,
+                        properties,
+                        value,
+                        len,
+                        notify_pending: false,
+                    },
+                ),
+                _ => {}
+            }
+        }
+        self.client.map(|client| client.connection_changed(false));
+    }
+}
@@ -0,0 +1,278 @@
+//! Provides userspace with a BLE peripheral with a GATT server.
+//!
+//! One process at a time owns the peripheral. It declares services and
+//! characteristics with 16-bit UUIDs, advertises, and is told when a central
+//! connects and when it writes a characteristic. The Generic Access service
+//! is always present. When the owner exits, the next process to use the
+//! driver takes over and its services replace the old ones.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Allow
+//!
+//! * ReadOnly 0: the value for command 3, or the advertising data for
+//!   command 4.
+//! * ReadOnly 1: the scan response data for command 4.
+//! * ReadWrite 0: receives values written by the central.
+//!
+//! ### Subscribe
+//!
+//! * 0: a central connected or disconnected. The first argument is 1 if a
+//!   central is connected and 0 if not.
+//! * 1: the central wrote a characteristic. The arguments are the handle of
+//!   the value and its length. The value is copied into ReadWrite allow 0, and
+//!   truncated if the buffer is too short.
+//!
+//! ### Command
+//!
+//! * 0: driver check.
+//! * 1: add a primary service with UUID `arg1`. Returns its handle.
+//! * 2: add a characteristic with UUID `arg1` and properties `arg2` to the
+//!   last service added. Returns the handle of its value.
+//! * 3: set the value with handle `arg1` to the first `arg2` bytes of
+//!   ReadOnly allow 0, notifying the central if it asked to be.
+//! * 4: start advertising every `arg1` milliseconds, with the data in the
+//!   ReadOnly allows.
+//! * 5: stop advertising, and disconnect the central if there is one.
+//! * 6: remove all services.
+//!
+//! All commands other than 0 return `RESERVE` if another process owns the
+//! peripheral, and commands 1 and 2 return `NOMEM` once the attribute table
+//! is full.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ble_gatt = static_init!(
+//!     capsules::ble_gatt_driver::BleGattDriver<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ble_gatt_driver::BleGattDriver::new(
+//!         ble_connection,
+//!         gatt_server,
+//!         board_kernel.create_grant(capsules::ble_gatt_driver::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! gatt_server.set_client(ble_gatt);
+//! ```
+
+use core::cmp;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::ble_advertising::BleConnectionDriver;
+use kernel::hil::time;
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::ble_connection::{BleConnection, MAX_ADV_DATA};
+use crate::ble_gatt::{GattClient, GattServer, MAX_VALUE_LEN};
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::BleGatt as usize;
+
+mod upcall {
+    pub const CONNECTION: usize = 0;
+    pub const WRITTEN: usize = 1;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const DATA: usize = 0;
+    pub const SCAN_RESPONSE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const WRITTEN: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+#[derive(Default)]
+pub struct App {}
+
+pub struct BleGattDriver<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> {
+    link: &'a BleConnection<'a, R, A>,
+    server: &'a GattServer<'a>,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    owner: OptionalCell<ProcessId>,
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> BleGattDriver<'a, R, A> {
+    pub fn new(
+        link: &'a BleConnection<'a, R, A>,
+        server: &'a GattServer<'a>,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> BleGattDriver<'a, R, A> {
+        BleGattDriver {
+            link,
+            server,
+            apps: grant,
+            owner: OptionalCell::empty(),
+        }
+    }
+
+    /// Makes `processid` the owner, unless a live process already is.
+    fn claim(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        match self.owner.extract() {
+            Some(owner) if owner == processid => Ok(()),
+            Some(owner) if self.apps.enter(owner, |_, _| {}).is_ok() => Err(ErrorCode::RESERVE),
+            _ => {
+                // The last owner is gone, along with its services
+                self.link.stop();
+                self.server.remove_services();
+                self.owner.set(processid);
+                Ok(())
+            }
+        }
+    }
+
+    /// Copies `len` bytes from a read-only allow buffer.
+    fn read_allowed(
+        &self,
+        processid: ProcessId,
+        allow: usize,
+        len: Option<usize>,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(allow)
+                    .and_then(|data| {
+                        data.enter(|data| {
+                            let len = len.unwrap_or(data.len());
+                            if len > data.len() || len > buf.len() {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            data[..len].copy_to_slice(&mut buf[..len]);
+                            Ok(len)
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::FAIL))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> GattClient for BleGattDriver<'a, R, A> {
+    fn written(&self, handle: u16, value: &[u8]) {
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |_, kernel_data| {
+                let _ = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::WRITTEN)
+                    .and_then(|written| {
+                        written.mut_enter(|written| {
+                            let len = cmp::min(written.len(), value.len());
+                            written[..len].copy_from_slice(&value[..len]);
+                        })
+                    });
+                kernel_data
+                    .schedule_upcall(upcall::WRITTEN, (handle as usize, value.len(), 0))
+                    .ok();
+            });
+        });
+    }
+
+    fn connection_changed(&self, connected: bool) {
+        self.owner.map(|owner| {
+            let _ = self.apps.enter(*owner, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(upcall::CONNECTION, (connected as usize, 0, 0))
+                    .ok();
+            });
+        });
+    }
+}
+
+impl<'a, R: BleConnectionDriver<'a>, A: time::Alarm<'a>> SyscallDriver for BleGattDriver<'a, R, A> {
+    /// BLE peripheral control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Add a primary service with UUID `arg1`.
+    /// - `2`: Add a characteristic with UUID `arg1` and properties `arg2`.
+    /// - `3`: Set the value with handle `arg1` to `arg2` bytes of ReadOnly
+    ///        allow 0.
+    /// - `4`: Start advertising every `arg1` milliseconds.
+    /// - `5`: Stop advertising and disconnect.
+    /// - `6`: Remove all services.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        if command_num == 0 {
+            return CommandReturn::success();
+        }
+        if let Err(e) = self.claim(processid) {
+            return CommandReturn::failure(e);
+        }
+        match command_num {
+            1 => match self.server.add_service(arg1 as u16) {
+                Ok(handle) => CommandReturn::success_u32(handle as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+            2 => match self.server.add_characteristic(arg1 as u16, arg2 as u8) {
+                Ok(handle) => CommandReturn::success_u32(handle as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+            3 => {
+                let mut value = [0; MAX_VALUE_LEN];
+                CommandReturn::from(
+                    self.read_allowed(processid, ro_allow::DATA, Some(arg2), &mut value)
+                        .and_then(|len| self.server.set_value(arg1 as u16, &value[..len])),
+                )
+            }
+            4 => {
+                let mut data = [0; MAX_ADV_DATA];
+                let mut scan_response = [0; MAX_ADV_DATA];
+                let result = self
+                    .read_allowed(processid, ro_allow::DATA, None, &mut data)
+                    .and_then(|len| self.link.set_advertising_data(&data[..len]))
+                    .and_then(|()| {
+                        self.read_allowed(
+                            processid,
+                            ro_allow::SCAN_RESPONSE,
+                            None,
+                            &mut scan_response,
+                        )
+                    })
+                    .and_then(|len| self.link.set_scan_response_data(&scan_response[..len]))
+                    .map(|()| self.link.start_advertising(arg1 as u32));
+                CommandReturn::from(result)
+            }
+            5 => {
+                self.link.stop();
+                CommandReturn::success()
+            }
+            6 => {
+                self.server.remove_services();
+                CommandReturn::success()
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
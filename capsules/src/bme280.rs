@@ -6,13 +6,15 @@
 
 use core::cell::Cell;
 use kernel::hil::i2c::{self, I2CClient, I2CDevice};
-use kernel::hil::sensors::{HumidityClient, HumidityDriver, TemperatureClient, TemperatureDriver};
+use kernel::hil::sensors::{
+    HumidityClient, HumidityDriver, PressureClient, PressureDriver, TemperatureClient,
+    TemperatureDriver,
+};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::ErrorCode;
 
 const HUM_MSB: u8 = 0xFD;
 const TEMP_MSB: u8 = 0xFA;
-#[allow(dead_code)]
 const PRESS_MSB: u8 = 0xF7;
 #[allow(dead_code)]
 const CONFIG: u8 = 0xF5;
@@ -40,7 +42,6 @@ enum DeviceState {
     Normal,
 }
 
-#[allow(dead_code)]
 #[derive(Clone, Copy, PartialEq)]
 enum Operation {
     None,
@@ -73,6 +74,39 @@ struct CalibrationData {
     hum6: u16,
 }
 
+impl CalibrationData {
+    /// Pressure in pascals, from the raw pressure and temperature read
+    /// together. This is the 64-bit formula from the datasheet.
+    fn pressure(&self, adc_pressure: i64, adc_temperature: i64) -> u32 {
+        let temp1 = self.temp1 as i64;
+        let var1 = (((adc_temperature >> 3) - (temp1 << 1)) * (self.temp2 as i16 as i64)) >> 11;
+        let var2 = (((((adc_temperature >> 4) - temp1) * ((adc_temperature >> 4) - temp1)) >> 12)
+            * (self.temp3 as i16 as i64))
+            >> 14;
+        let t_fine = var1 + var2;
+
+        let mut var1 = t_fine - 128000;
+        let mut var2 = var1 * var1 * (self.press6 as i16 as i64);
+        var2 += (var1 * (self.press5 as i16 as i64)) << 17;
+        var2 += (self.press4 as i16 as i64) << 35;
+        var1 = ((var1 * var1 * (self.press3 as i16 as i64)) >> 8)
+            + ((var1 * (self.press2 as i16 as i64)) << 12);
+        var1 = (((1 << 47) + var1) * (self.press1 as i64)) >> 33;
+        if var1 == 0 {
+            // Avoid a division by zero
+            return 0;
+        }
+        let mut p = 1048576 - adc_pressure;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = ((self.press9 as i16 as i64) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = ((self.press8 as i16 as i64) * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + ((self.press7 as i16 as i64) << 4);
+
+        // p is in 1/256 Pa
+        (p >> 8) as u32
+    }
+}
+
 impl Default for CalibrationData {
     fn default() -> Self {
         CalibrationData {
@@ -106,6 +140,7 @@ pub struct Bme280<'a> {
     calibration: Cell<CalibrationData>,
     temperature_client: OptionalCell<&'a dyn TemperatureClient>,
     humidity_client: OptionalCell<&'a dyn HumidityClient>,
+    pressure_client: OptionalCell<&'a dyn PressureClient>,
     state: Cell<DeviceState>,
     op: Cell<Operation>,
     t_fine: Cell<usize>,
@@ -119,6 +154,7 @@ impl<'a> Bme280<'a> {
             calibration: Cell::new(CalibrationData::default()),
             temperature_client: OptionalCell::empty(),
             humidity_client: OptionalCell::empty(),
+            pressure_client: OptionalCell::empty(),
             state: Cell::new(DeviceState::Identify),
             op: Cell::new(Operation::None),
             t_fine: Cell::new(0),
@@ -186,6 +222,32 @@ impl<'a> HumidityDriver<'a> for Bme280<'a> {
     }
 }
 
+impl<'a> PressureDriver<'a> for Bme280<'a> {
+    fn set_client(&self, client: &'a dyn PressureClient) {
+        self.pressure_client.set(client);
+    }
+
+    fn read_pressure(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != DeviceState::Normal {
+            return Err(ErrorCode::BUSY);
+        }
+
+        if self.op.get() != Operation::None {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.buffer.take().map(|buffer| {
+            // Compensating the pressure needs the temperature, which follows
+            buffer[0] = PRESS_MSB;
+
+            self.op.set(Operation::Pressure);
+            self.i2c.write_read(buffer, 1, 6).unwrap();
+        });
+
+        Ok(())
+    }
+}
+
 impl<'a> I2CClient for Bme280<'a> {
     fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
         if status.is_err() {
@@ -196,7 +258,8 @@ impl<'a> I2CClient for Bme280<'a> {
                     self.temperature_client.map(|client| client.callback(0));
                 }
                 Operation::Pressure => {
-                    unimplemented!();
+                    self.pressure_client
+                        .map(|client| client.callback(Err(ErrorCode::FAIL)));
                 }
                 Operation::Humidity => {
                     self.humidity_client.map(|client| client.callback(0));
@@ -321,7 +384,26 @@ impl<'a> I2CClient for Bme280<'a> {
                             .map(|client| client.callback(temperature));
                     }
                     Operation::Pressure => {
-                        unimplemented!();
+                        let calib = self.calibration.get();
+                        let adc_pressure = (buffer[0] as i64) << 12
+                            | (buffer[1] as i64) << 4
+                            | (((buffer[2] as i64) >> 4) & 0x0F);
+                        let adc_temperature = (buffer[3] as i64) << 12
+                            | (buffer[4] as i64) << 4
+                            | (((buffer[5] as i64) >> 4) & 0x0F);
+
+                        if adc_pressure == 0 || adc_temperature == 0 {
+                            // We got a misread, try again
+                            self.buffer.replace(buffer);
+                            self.op.set(Operation::None);
+                            let _ = self.read_pressure();
+                            return;
+                        }
+
+                        let pressure = calib.pressure(adc_pressure, adc_temperature);
+
+                        self.pressure_client
+                            .map(|client| client.callback(Ok(pressure)));
                     }
                     Operation::Humidity => {
                         let calib = self.calibration.get();
@@ -366,3 +448,31 @@ impl<'a> I2CClient for Bme280<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The compensation example from section 8.1 of the BMP280 datasheet,
+    /// whose temperature and pressure compensation the BME280 shares. The
+    /// 64-bit formula gives 25767233 / 256 = 100653.25 Pa.
+    #[test]
+    fn pressure_compensation_matches_datasheet() {
+        let calibration = CalibrationData {
+            temp1: 27504,
+            temp2: 26435,
+            temp3: -1000i16 as u16,
+            press1: 36477,
+            press2: -10685i16 as u16,
+            press3: 3024,
+            press4: 2855,
+            press5: 140,
+            press6: -7i16 as u16,
+            press7: 15500,
+            press8: -14600i16 as u16,
+            press9: 6000,
+            ..CalibrationData::default()
+        };
+        assert_eq!(calibration.pressure(415148, 519888), 100653);
+    }
+}
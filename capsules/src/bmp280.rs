@@ -3,8 +3,6 @@
 //! Written by Dorota <gihu.dcz@porcupinefactory.org>
 //!
 //! Based off the SHT3x code.
-//!
-//! Not implemented: pressure
 
 use core::cell::Cell;
 use kernel::debug;
@@ -16,8 +14,8 @@ use kernel::ErrorCode;
 
 pub static BASE_ADDR: u8 = 0x76;
 
-/// Currently sized enough for temperature readings only.
-pub const BUFFER_SIZE: usize = 6;
+/// Sized for the calibration data, the largest read.
+pub const BUFFER_SIZE: usize = 24;
 
 #[allow(non_camel_case_types)]
 #[allow(dead_code)]
@@ -27,6 +25,15 @@ enum Register {
     DIG_T1 = 0x88,
     DIG_T2 = 0x8a,
     DIG_T3 = 0x8c,
+    DIG_P1 = 0x8e,
+    DIG_P2 = 0x90,
+    DIG_P3 = 0x92,
+    DIG_P4 = 0x94,
+    DIG_P5 = 0x96,
+    DIG_P6 = 0x98,
+    DIG_P7 = 0x9a,
+    DIG_P8 = 0x9c,
+    DIG_P9 = 0x9e,
     ID = 0xd0,
     RESET = 0xe0,
     /// measuring: [3]
@@ -55,7 +62,15 @@ struct CalibrationData {
     dig_t1: u16,
     dig_t2: i16,
     dig_t3: i16,
-    // TODO: pressure calibration
+    dig_p1: u16,
+    dig_p2: i16,
+    dig_p3: i16,
+    dig_p4: i16,
+    dig_p5: i16,
+    dig_p6: i16,
+    dig_p7: i16,
+    dig_p8: i16,
+    dig_p9: i16,
 }
 
 /// CAUTION: calibration data puts least significant byte in the lowest address,
@@ -70,10 +85,20 @@ impl CalibrationData {
             dig_t1: twobyte(i2c_raw[0], i2c_raw[1]) as u16,
             dig_t2: twobyte(i2c_raw[2], i2c_raw[3]) as i16,
             dig_t3: twobyte(i2c_raw[4], i2c_raw[5]) as i16,
+            dig_p1: twobyte(i2c_raw[6], i2c_raw[7]) as u16,
+            dig_p2: twobyte(i2c_raw[8], i2c_raw[9]) as i16,
+            dig_p3: twobyte(i2c_raw[10], i2c_raw[11]) as i16,
+            dig_p4: twobyte(i2c_raw[12], i2c_raw[13]) as i16,
+            dig_p5: twobyte(i2c_raw[14], i2c_raw[15]) as i16,
+            dig_p6: twobyte(i2c_raw[16], i2c_raw[17]) as i16,
+            dig_p7: twobyte(i2c_raw[18], i2c_raw[19]) as i16,
+            dig_p8: twobyte(i2c_raw[20], i2c_raw[21]) as i16,
+            dig_p9: twobyte(i2c_raw[22], i2c_raw[23]) as i16,
         }
     }
 
-    fn temp_from_raw(&self, raw_temp: u32) -> i32 {
+    /// The fine temperature, which pressure compensation depends on.
+    fn t_fine(&self, raw_temp: u32) -> i32 {
         let temp = raw_temp as i32; // guaranteed to succeed because raw temp has only 20 significant bits maximum.
         let dig_t1 = self.dig_t1 as i32; // same, 16-bits
         let dig_t2 = self.dig_t2 as i32; // same, 16-bits
@@ -82,9 +107,34 @@ impl CalibrationData {
         let var1 = (((temp >> 3) - (dig_t1 << 1)) * dig_t2) >> 11;
         let a = (temp >> 4) - dig_t1;
         let var2 = (((a * a) >> 12) * dig_t3) >> 14;
-        let t_fine = var1 + var2;
+        var1 + var2
+    }
+
+    fn temp_from_t_fine(t_fine: i32) -> i32 {
         ((t_fine * 5) + 128) >> 8
     }
+
+    /// Pressure in pascals, using the 64-bit formula from the datasheet.
+    fn pressure_from_raw(&self, raw_pressure: u32, t_fine: i32) -> u32 {
+        let pressure = raw_pressure as i64;
+        let mut var1 = t_fine as i64 - 128000;
+        let mut var2 = var1 * var1 * self.dig_p6 as i64;
+        var2 += (var1 * self.dig_p5 as i64) << 17;
+        var2 += (self.dig_p4 as i64) << 35;
+        var1 = ((var1 * var1 * self.dig_p3 as i64) >> 8) + ((var1 * self.dig_p2 as i64) << 12);
+        var1 = (((1 << 47) + var1) * self.dig_p1 as i64) >> 33;
+        if var1 == 0 {
+            // Avoids dividing by zero on bad calibration data
+            return 0;
+        }
+        let mut p = 1048576 - pressure;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (self.dig_p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (self.dig_p8 as i64 * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + ((self.dig_p7 as i64) << 4);
+        // `p` is in 1/256 Pa
+        (p / 256) as u32
+    }
 }
 
 /// Internal state.
@@ -178,6 +228,11 @@ impl<'a> I2cWrapper<'a> {
 pub struct Bmp280<'a, A: Alarm<'a>> {
     i2c: I2cWrapper<'a>,
     temperature_client: OptionalCell<&'a dyn hil::sensors::TemperatureClient>,
+    pressure_client: OptionalCell<&'a dyn hil::sensors::PressureClient>,
+    /// Each reading measures both temperature and pressure, and reports them
+    /// to the clients that asked.
+    temperature_requested: Cell<bool>,
+    pressure_requested: Cell<bool>,
     // This might be better as a `RefCell`,
     // because `State` is multiple bytes due to the `CalibrationData`.
     // `Cell` requires Copy, which might get expensive, while `RefCell` doesn't.
@@ -196,6 +251,9 @@ impl<'a, A: Alarm<'a>> Bmp280<'a, A> {
         Self {
             i2c: I2cWrapper { i2c },
             temperature_client: OptionalCell::empty(),
+            pressure_client: OptionalCell::empty(),
+            temperature_requested: Cell::new(false),
+            pressure_requested: Cell::new(false),
             state: Cell::new(State::Uninitialized),
             buffer: TakeCell::new(buffer),
             alarm: alarm,
@@ -225,6 +283,40 @@ impl<'a, A: Alarm<'a>> Bmp280<'a, A> {
     }
 
     pub fn read_temperature(&self) -> Result<(), ErrorCode> {
+        self.request(&self.temperature_requested)
+    }
+
+    pub fn read_pressure(&self) -> Result<(), ErrorCode> {
+        self.request(&self.pressure_requested)
+    }
+
+    /// Starts a reading, or joins the reading in progress, for the value
+    /// `requested` flags.
+    fn request(&self, requested: &Cell<bool>) -> Result<(), ErrorCode> {
+        match self.state.get() {
+            State::Configuring(_)
+            | State::WaitingForAlarm(_)
+            | State::Waiting(_)
+            | State::Reading(_)
+                if !requested.get() =>
+            {
+                requested.set(true);
+                Ok(())
+            }
+            _ => {
+                let ret = self.start_reading();
+                if ret.is_ok() {
+                    // Forget requests for readings that failed silently
+                    self.temperature_requested.set(false);
+                    self.pressure_requested.set(false);
+                    requested.set(true);
+                }
+                ret
+            }
+        }
+    }
+
+    fn start_reading(&self) -> Result<(), ErrorCode> {
         match self.state.get() {
             // Actually, the sensor might be on, just in default state.
             State::Uninitialized => Err(ErrorCode::OFF),
@@ -235,8 +327,8 @@ impl<'a, A: Alarm<'a>> Bmp280<'a, A> {
             State::Idle(calibration) => {
                 self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buffer| {
                     // todo: use bitfield crate
-                    // forced mode, oversampling 1
-                    let val = 0b00100001;
+                    // forced mode, temperature and pressure oversampling 1
+                    let val = 0b00100101;
                     let (ret, new_state) = match self.i2c.write(buffer, Register::CTRL_MEAS, [val])
                     {
                         Ok(()) => (Ok(()), State::Configuring(calibration)),
@@ -287,8 +379,8 @@ impl<'a, A: Alarm<'a>> Bmp280<'a, A> {
     }
 
     fn arm_alarm(&self) {
-        // Datasheet says temp oversampling=1 makes a reading typically take 5.5ms.
-        // (Maximally 6.4ms).
+        // Datasheet says temp and pressure oversampling=1 makes a reading
+        // typically take 5.5ms. (Maximally 6.4ms).
         let delay = self.alarm.ticks_from_us(6400);
         self.alarm.set_alarm(self.alarm.now(), delay);
     }
@@ -329,6 +421,7 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
     fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
         const INVALID_TEMPERATURE: i32 = i32::MIN;
         let mut temp_readout = None;
+        let mut pressure_readout = None;
         let mut i2c_op = I2cOperation::Disable;
 
         let new_state = match status {
@@ -356,7 +449,7 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
                         // finished init
                         i2c_op = I2cOperation::Read {
                             addr: Register::DIG_T1,
-                            count: 6,
+                            count: 24,
                             fail_state: State::Error,
                         };
                         State::InitReadingCalibration
@@ -366,7 +459,7 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
                     }
                 }
                 State::InitReadingCalibration => {
-                    let data = I2cWrapper::parse_read(buffer, 6);
+                    let data = I2cWrapper::parse_read(buffer, 24);
                     let calibration = CalibrationData::new(data);
                     State::Idle(calibration)
                 }
@@ -380,8 +473,8 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
                     // not waiting
                     if waiting_value[0] & 0b1000 == 0 {
                         i2c_op = I2cOperation::Read {
-                            addr: Register::TEMP_MSB,
-                            count: 3,
+                            addr: Register::PRESS_MSB,
+                            count: 6,
                             fail_state: State::Idle(calibration),
                         };
                         State::Reading(calibration)
@@ -391,12 +484,16 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
                     }
                 }
                 State::Reading(calibration) => {
-                    let readout = I2cWrapper::parse_read(buffer, 3);
-                    let msb = readout[0] as u32;
-                    let lsb = readout[1] as u32;
-                    let xlsb = readout[2] as u32;
-                    let raw_temp = (msb << 12) + (lsb << 4) + (xlsb >> 4);
-                    temp_readout = Some(calibration.temp_from_raw(raw_temp));
+                    let readout = I2cWrapper::parse_read(buffer, 6);
+                    let raw = |msb: u8, lsb: u8, xlsb: u8| {
+                        ((msb as u32) << 12) + ((lsb as u32) << 4) + ((xlsb as u32) >> 4)
+                    };
+                    let raw_pressure = raw(readout[0], readout[1], readout[2]);
+                    let raw_temp = raw(readout[3], readout[4], readout[5]);
+                    let t_fine = calibration.t_fine(raw_temp);
+                    temp_readout = Some(CalibrationData::temp_from_t_fine(t_fine));
+                    pressure_readout =
+                        Some(Ok(calibration.pressure_from_raw(raw_pressure, t_fine)));
                     State::Idle(calibration)
                 }
                 other => {
@@ -409,6 +506,7 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
                 | State::Waiting(calibration)
                 | State::Reading(calibration) => {
                     temp_readout = Some(INVALID_TEMPERATURE);
+                    pressure_readout = Some(Err(ErrorCode::FAIL));
                     State::Idle(calibration)
                 }
                 State::InitId
@@ -461,7 +559,14 @@ impl<'a, A: Alarm<'a>> i2c::I2CClient for Bmp280<'a, A> {
         // in case the callback wants to use the same driver again.
         self.state.set(new_state);
         if let Some(temp) = temp_readout {
-            self.temperature_client.map(|cb| cb.callback(temp as usize));
+            if self.temperature_requested.take() {
+                self.temperature_client.map(|cb| cb.callback(temp as usize));
+            }
+        }
+        if let Some(pressure) = pressure_readout {
+            if self.pressure_requested.take() {
+                self.pressure_client.map(|cb| cb.callback(pressure));
+            }
         }
     }
 }
@@ -476,8 +581,45 @@ impl<'a, A: Alarm<'a>> hil::sensors::TemperatureDriver<'a> for Bmp280<'a, A> {
     }
 }
 
+impl<'a, A: Alarm<'a>> hil::sensors::PressureDriver<'a> for Bmp280<'a, A> {
+    fn set_client(&self, client: &'a dyn hil::sensors::PressureClient) {
+        self.pressure_client.set(client)
+    }
+
+    fn read_pressure(&self) -> Result<(), ErrorCode> {
+        self.read_pressure()
+    }
+}
+
 impl<'a, A: hil::time::Alarm<'a>> hil::time::AlarmClient for Bmp280<'a, A> {
     fn alarm(&self) {
         self.handle_alarm()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The compensation example from section 8.1 of the datasheet.
+    #[test]
+    fn compensation_matches_datasheet() {
+        let calibration = CalibrationData {
+            dig_t1: 27504,
+            dig_t2: 26435,
+            dig_t3: -1000,
+            dig_p1: 36477,
+            dig_p2: -10685,
+            dig_p3: 3024,
+            dig_p4: 2855,
+            dig_p5: 140,
+            dig_p6: -7,
+            dig_p7: 15500,
+            dig_p8: -14600,
+            dig_p9: 6000,
+        };
+        let t_fine = calibration.t_fine(519888);
+        assert_eq!(CalibrationData::temp_from_t_fine(t_fine), 2508);
+        assert_eq!(calibration.pressure_from_raw(415148, t_fine), 100653);
+    }
+}
@@ -4,6 +4,14 @@
 //! having to know which of the GPIO pins exposed across the syscall interface
 //! are buttons.
 //!
+//! Each button has a `ButtonConfig`, set by the board. By default a button
+//! reports every edge of its pin. A button with a debounce interval instead
+//! waits until its pin has been stable for that long, and only reports the
+//! press or release if the state has changed. A button can also report long
+//! presses, when it has been held down for a given time, and double clicks,
+//! when it is pressed twice within a given time. These are reported on top of
+//! the press and release events, which are never delayed.
+//!
 //! Usage
 //! -----
 //!
@@ -11,13 +19,16 @@
 //! # use kernel::static_init;
 //!
 //! let button_pins = static_init!(
-//!     [&'static sam4l::gpio::GPIOPin; 1],
-//!     [&sam4l::gpio::PA[16]]);
+//!     [(&'static InterruptValueWrapper<'static, sam4l::gpio::GPIOPin>,
+//!       ActivationMode, FloatingState, ButtonState); 1],
+//!     [(button_pin, ActivationMode::ActiveLow, FloatingState::PullUp,
+//!       ButtonState::new(ButtonConfig { debounce_ms: 20, ..ButtonConfig::default() }))]);
 //! let button = static_init!(
-//!     capsules::button::Button<'static>,
-//!     capsules::button::Button::new(button_pins, board_kernel.create_grant(&grant_cap)));
+//!     capsules::button::Button<'static, sam4l::gpio::GPIOPin, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::button::Button::new(button_pins, button_alarm, board_kernel.create_grant(&grant_cap)));
+//! button_alarm.set_alarm_client(button);
 //! for btn in button_pins.iter() {
-//!     btn.set_client(button);
+//!     btn.0.set_client(button);
 //! }
 //! ```
 //!
@@ -47,15 +58,17 @@
 //!
 //! - `0`: Set callback for pin interrupts. Note setting this callback has
 //!   no reliance on individual pins being configured as interrupts. The
-//!   interrupt will be called with two parameters: the index of the button
-//!   that triggered the interrupt and the pressed (1) or not pressed (0) state
-//!   of the button.
+//!   interrupt will be called with three parameters: the index of the button
+//!   that triggered the interrupt, the pressed (1) or not pressed (0) state
+//!   of the button, and the event: `EVENT_STATE_CHANGE` (0) for presses and
+//!   releases, `EVENT_LONG_PRESS` (1) or `EVENT_DOUBLE_CLICK` (2).
 
 use core::cell::Cell;
 
 use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil::gpio;
 use kernel::hil::gpio::{Configure, Input, InterruptWithValue};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
 use kernel::syscall::{CommandReturn, SyscallDriver};
 use kernel::{ErrorCode, ProcessId};
 
@@ -76,34 +89,89 @@ pub struct App {
     subscribe_map: u32,
 }
 
+/// The button was pressed or released.
+pub const EVENT_STATE_CHANGE: usize = 0;
+/// The button has been held down for its long press time.
+pub const EVENT_LONG_PRESS: usize = 1;
+/// The button was pressed a second time within its double click time.
+pub const EVENT_DOUBLE_CLICK: usize = 2;
+
+/// How a button reports its events. An interval of 0 turns the matching
+/// feature off, which is the default.
+#[derive(Clone, Copy, Default)]
+pub struct ButtonConfig {
+    /// How long the pin has to be stable before a press or release is
+    /// reported.
+    pub debounce_ms: u32,
+    /// How long the button has to be held down to report a long press.
+    pub long_press_ms: u32,
+    /// How soon after a press a second press is reported as a double click.
+    pub double_click_ms: u32,
+}
+
+/// The configuration of a button, and the timing of its events.
+///
+/// Timers are kept as the number of alarm ticks left until they expire.
+pub struct ButtonState {
+    config: ButtonConfig,
+    /// Whether the button was pressed when last reported.
+    pressed: Cell<bool>,
+    /// Until the pin has been stable long enough, if it is bouncing.
+    settle: Cell<Option<u32>>,
+    /// Until a press becomes a long press, while the button is held down.
+    hold: Cell<Option<u32>>,
+    /// Until a second press is no longer a double click.
+    click_window: Cell<Option<u32>>,
+}
+
+impl ButtonState {
+    pub const fn new(config: ButtonConfig) -> ButtonState {
+        ButtonState {
+            config,
+            pressed: Cell::new(false),
+            settle: Cell::new(None),
+            hold: Cell::new(None),
+            click_window: Cell::new(None),
+        }
+    }
+}
+
 /// Manages the list of GPIO pins that are connected to buttons and which apps
 /// are listening for interrupts from which buttons.
-pub struct Button<'a, P: gpio::InterruptPin<'a>> {
+pub struct Button<'a, P: gpio::InterruptPin<'a>, A: time::Alarm<'a>> {
     pins: &'a [(
         &'a gpio::InterruptValueWrapper<'a, P>,
         gpio::ActivationMode,
         gpio::FloatingState,
+        ButtonState,
     )],
+    alarm: &'a A,
+    /// The time the button timers were last brought up to date.
+    reference: Cell<A::Ticks>,
     apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
 }
 
-impl<'a, P: gpio::InterruptPin<'a>> Button<'a, P> {
+impl<'a, P: gpio::InterruptPin<'a>, A: time::Alarm<'a>> Button<'a, P, A> {
     pub fn new(
         pins: &'a [(
             &'a gpio::InterruptValueWrapper<'a, P>,
             gpio::ActivationMode,
             gpio::FloatingState,
+            ButtonState,
         )],
+        alarm: &'a A,
         grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
     ) -> Self {
-        for (i, &(pin, _, floating_state)) in pins.iter().enumerate() {
+        for (i, (pin, _, floating_state, _)) in pins.iter().enumerate() {
             pin.make_input();
             pin.set_value(i as u32);
-            pin.set_floating_state(floating_state);
+            pin.set_floating_state(*floating_state);
         }
 
         Self {
             pins: pins,
+            alarm: alarm,
+            reference: Cell::new(A::Ticks::from(0)),
             apps: grant,
         }
     }
@@ -112,18 +180,144 @@ impl<'a, P: gpio::InterruptPin<'a>> Button<'a, P> {
         let pin = &self.pins[pin_num as usize];
         pin.0.read_activation(pin.1)
     }
+
+    // Converts ticks to a timer value, saturating for long times.
+    fn timer_ticks(ticks: A::Ticks) -> u32 {
+        if ticks > A::Ticks::from(u32::MAX) {
+            u32::MAX
+        } else {
+            ticks.into_u32()
+        }
+    }
+
+    fn timer_from_ms(&self, ms: u32) -> u32 {
+        core::cmp::max(Self::timer_ticks(self.alarm.ticks_from_ms(ms)), 1)
+    }
+
+    // Schedules an upcall to the apps that listen to `pin_num`, and returns
+    // how many there are.
+    fn notify(&self, pin_num: u32, pressed: bool, event: usize) -> usize {
+        let mut interrupt_count = 0;
+        self.apps.each(|_, cntr, upcalls| {
+            if cntr.subscribe_map & (1 << pin_num) != 0 {
+                interrupt_count += 1;
+                upcalls
+                    .schedule_upcall(UPCALL_NUM, (pin_num as usize, pressed as usize, event))
+                    .ok();
+            }
+        });
+        interrupt_count
+    }
+
+    // Reports a press or release of a button, and starts or stops the timers
+    // of its long press and double click events.
+    fn report(&self, pin_num: u32, pressed: bool) {
+        let state = &self.pins[pin_num as usize].3;
+        state.pressed.set(pressed);
+        let interrupt_count = self.notify(pin_num, pressed, EVENT_STATE_CHANGE);
+
+        if pressed {
+            if state.config.long_press_ms > 0 {
+                state
+                    .hold
+                    .set(Some(self.timer_from_ms(state.config.long_press_ms)));
+            }
+            if state.config.double_click_ms > 0 {
+                if state.click_window.take().is_some() {
+                    self.notify(pin_num, pressed, EVENT_DOUBLE_CLICK);
+                } else {
+                    state
+                        .click_window
+                        .set(Some(self.timer_from_ms(state.config.double_click_ms)));
+                }
+            }
+        } else {
+            state.hold.set(None);
+        }
+
+        // It's possible we got an interrupt for a process that has since died
+        // (and didn't unregister the interrupt). Lazily disable interrupts for
+        // this button if so.
+        if interrupt_count == 0 {
+            self.pins[pin_num as usize].0.disable_interrupts();
+            state.settle.set(None);
+            state.hold.set(None);
+        }
+    }
+
+    // Takes the time since the last update off the button timers, and reports
+    // the events of the timers that expire.
+    fn update_timers(&self) {
+        let now = self.alarm.now();
+        let elapsed = Self::timer_ticks(now.wrapping_sub(self.reference.get()));
+        self.reference.set(now);
+
+        for (i, (_, _, _, state)) in self.pins.iter().enumerate() {
+            state.click_window.set(
+                state
+                    .click_window
+                    .get()
+                    .and_then(|t| t.checked_sub(elapsed)),
+            );
+
+            if let Some(t) = state.hold.get() {
+                if t > elapsed {
+                    state.hold.set(Some(t - elapsed));
+                } else {
+                    state.hold.set(None);
+                    self.notify(i as u32, true, EVENT_LONG_PRESS);
+                }
+            }
+
+            if let Some(t) = state.settle.get() {
+                if t > elapsed {
+                    state.settle.set(Some(t - elapsed));
+                } else {
+                    state.settle.set(None);
+                    let pressed = self.get_button_state(i as u32) == gpio::ActivationState::Active;
+                    if pressed != state.pressed.get() {
+                        self.report(i as u32, pressed);
+                    }
+                }
+            }
+        }
+    }
+
+    // Sets the alarm for the first timer to expire, if any.
+    fn schedule_alarm(&self) {
+        let next = self
+            .pins
+            .iter()
+            .flat_map(|(_, _, _, state)| {
+                [
+                    state.settle.get(),
+                    state.hold.get(),
+                    state.click_window.get(),
+                ]
+            })
+            .flatten()
+            .min();
+        match next {
+            Some(t) => self
+                .alarm
+                .set_alarm(self.reference.get(), A::Ticks::from(t)),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
 }
 
 /// ### `subscribe_num`
 ///
 /// - `0`: Set callback for pin interrupts. Note setting this callback has
 ///   no reliance on individual pins being configured as interrupts. The
-///   interrupt will be called with two parameters: the index of the button
-///   that triggered the interrupt and the pressed/not pressed state of the
-///   button.
+///   interrupt will be called with three parameters: the index of the button
+///   that triggered the interrupt, the pressed/not pressed state of the
+///   button, and the event.
 const UPCALL_NUM: usize = 0;
 
-impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
+impl<'a, P: gpio::InterruptPin<'a>, A: time::Alarm<'a>> SyscallDriver for Button<'a, P, A> {
     /// Configure interrupts and read state for buttons.
     ///
     /// `data` is the index of the button in the button array as passed to
@@ -158,6 +352,9 @@ impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
                     self.apps
                         .enter(appid, |cntr, _| {
                             cntr.subscribe_map |= 1 << data;
+                            pins[data].3.pressed.set(
+                                self.get_button_state(data as u32) == gpio::ActivationState::Active,
+                            );
                             let _ = pins[data]
                                 .0
                                 .enable_interrupts(gpio::InterruptEdge::EitherEdge);
@@ -219,27 +416,29 @@ impl<'a, P: gpio::InterruptPin<'a>> SyscallDriver for Button<'a, P> {
     }
 }
 
-impl<'a, P: gpio::InterruptPin<'a>> gpio::ClientWithValue for Button<'a, P> {
+impl<'a, P: gpio::InterruptPin<'a>, A: time::Alarm<'a>> gpio::ClientWithValue for Button<'a, P, A> {
     fn fired(&self, pin_num: u32) {
-        // Read the value of the pin and get the button state.
-        let button_state = self.get_button_state(pin_num);
-        let interrupt_count = Cell::new(0);
-
-        // schedule callback with the pin number and value
-        self.apps.each(|_, cntr, upcalls| {
-            if cntr.subscribe_map & (1 << pin_num) != 0 {
-                interrupt_count.set(interrupt_count.get() + 1);
-                upcalls
-                    .schedule_upcall(UPCALL_NUM, (pin_num as usize, button_state as usize, 0))
-                    .ok();
-            }
-        });
+        self.update_timers();
 
-        // It's possible we got an interrupt for a process that has since died
-        // (and didn't unregister the interrupt). Lazily disable interrupts for
-        // this button if so.
-        if interrupt_count.get() == 0 {
-            self.pins[pin_num as usize].0.disable_interrupts();
+        let state = &self.pins[pin_num as usize].3;
+        if state.config.debounce_ms > 0 {
+            // Wait for the pin to settle, starting over at every edge
+            state
+                .settle
+                .set(Some(self.timer_from_ms(state.config.debounce_ms)));
+        } else {
+            // Read the value of the pin and get the button state.
+            let button_state = self.get_button_state(pin_num);
+            self.report(pin_num, button_state == gpio::ActivationState::Active);
         }
+
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, P: gpio::InterruptPin<'a>, A: time::Alarm<'a>> time::AlarmClient for Button<'a, P, A> {
+    fn alarm(&self) {
+        self.update_timers();
+        self.schedule_alarm();
     }
 }
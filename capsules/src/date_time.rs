@@ -0,0 +1,294 @@
+//! Provides userspace with access to a calendar real-time clock.
+//!
+//! Processes can read and set the date and time, and each process can set
+//! one calendar alarm. The driver keeps the hardware alarm set for the
+//! earliest alarm of all processes, so a process can sleep until its alarm
+//! fires even while the chip sleeps.
+//!
+//! Dates and times are passed packed into two words:
+//!
+//! * date: `year << 9 | month << 5 | day`, with months and days starting
+//!   at 1.
+//! * time: `day_of_week << 17 | hour << 12 | minute << 6 | seconds`, with
+//!   Sunday as day 0. The day of the week is ignored when setting the clock
+//!   or an alarm.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Subscribe
+//!
+//! * 0: a get or set command completed. The arguments are a status code and,
+//!   for a get, the packed date and time.
+//! * 1: the process's alarm fired. The arguments are the packed date and
+//!   time of the alarm.
+//!
+//! ### Command
+//!
+//! * 0: driver check.
+//! * 1: read the date and time.
+//! * 2: set the date and time to `arg1` (date) and `arg2` (time).
+//! * 3: set the process's alarm to `arg1` (date) and `arg2` (time),
+//!   replacing any alarm it set before.
+//! * 4: cancel the process's alarm.
+//!
+//! Commands 1 and 2 return `BUSY` if the process already has one of them
+//! outstanding. Commands 2 and 3 return `INVAL` if the date or time is not
+//! valid.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let date_time = static_init!(
+//!     capsules::date_time::DateTimeDriver<'static, sifive::rtc::Rtc<'static>>,
+//!     capsules::date_time::DateTimeDriver::new(
+//!         &peripherals.rtc,
+//!         board_kernel.create_grant(capsules::date_time::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! kernel::hil::date_time::DateTime::set_client(&peripherals.rtc, date_time);
+//! kernel::hil::date_time::DateTimeAlarm::set_alarm_client(&peripherals.rtc, date_time);
+//! ```
+
+use core::cell::Cell;
+use core::convert::TryFrom;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::date_time::{
+    DateTime, DateTimeAlarm, DateTimeAlarmClient, DateTimeClient, DateTimeValues, DayOfWeek, Month,
+};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::DateTime as usize;
+
+mod upcall {
+    pub const DONE: usize = 0;
+    pub const ALARM: usize = 1;
+}
+
+#[derive(Clone, Copy)]
+enum Task {
+    Get,
+    Set(DateTimeValues),
+}
+
+#[derive(Default)]
+pub struct App {
+    task: Option<Task>,
+    /// The alarm, in seconds since the Unix epoch.
+    alarm: Option<u64>,
+}
+
+fn pack(date_time: DateTimeValues) -> (usize, usize) {
+    let date =
+        (date_time.year as usize) << 9 | (date_time.month as usize) << 5 | date_time.day as usize;
+    let time = (date_time.day_of_week as usize) << 17
+        | (date_time.hour as usize) << 12
+        | (date_time.minute as usize) << 6
+        | date_time.seconds as usize;
+    (date, time)
+}
+
+fn unpack(date: usize, time: usize) -> Result<DateTimeValues, ErrorCode> {
+    if date >> 25 != 0 || time >> 17 != 0 {
+        return Err(ErrorCode::INVAL);
+    }
+    let date_time = DateTimeValues {
+        year: (date >> 9) as u16,
+        month: Month::try_from(date >> 5 & 0xf)?,
+        day: (date & 0x1f) as u8,
+        day_of_week: DayOfWeek::Sunday,
+        hour: (time >> 12 & 0x1f) as u8,
+        minute: (time >> 6 & 0x3f) as u8,
+        seconds: (time & 0x3f) as u8,
+    };
+    // Validates the fields
+    date_time.to_unix_seconds()?;
+    Ok(date_time)
+}
+
+pub struct DateTimeDriver<'a, D: DateTime<'a> + DateTimeAlarm<'a>> {
+    date_time: &'a D,
+    apps: Grant<App, UpcallCount<2>, AllowRoCount<0>, AllowRwCount<0>>,
+    in_progress: OptionalCell<ProcessId>,
+    /// The alarm the clock is set for, in seconds since the Unix epoch.
+    armed: Cell<Option<u64>>,
+}
+
+impl<'a, D: DateTime<'a> + DateTimeAlarm<'a>> DateTimeDriver<'a, D> {
+    pub fn new(
+        date_time: &'a D,
+        grant: Grant<App, UpcallCount<2>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> DateTimeDriver<'a, D> {
+        DateTimeDriver {
+            date_time: date_time,
+            apps: grant,
+            in_progress: OptionalCell::empty(),
+            armed: Cell::new(None),
+        }
+    }
+
+    /// Starts the next queued task, if the clock is idle.
+    fn run_next(&self) {
+        while self.in_progress.is_none() {
+            let next = self.apps.iter().find_map(|app| {
+                let processid = app.processid();
+                app.enter(|app, _| app.task.map(|task| (processid, task)))
+            });
+            let (processid, task) = match next {
+                Some(next) => next,
+                None => return,
+            };
+            let result = match task {
+                Task::Get => self.date_time.get_date_time(),
+                Task::Set(date_time) => self.date_time.set_date_time(date_time),
+            };
+            match result {
+                Ok(()) => self.in_progress.set(processid),
+                Err(e) => self.complete(processid, Err(e), (0, 0)),
+            }
+        }
+    }
+
+    fn complete(
+        &self,
+        processid: ProcessId,
+        result: Result<(), ErrorCode>,
+        packed: (usize, usize),
+    ) {
+        let _ = self.apps.enter(processid, |app, kernel_data| {
+            app.task = None;
+            kernel_data
+                .schedule_upcall(upcall::DONE, (into_statuscode(result), packed.0, packed.1))
+                .ok();
+        });
+    }
+
+    fn finish(&self, result: Result<(), ErrorCode>, packed: (usize, usize)) {
+        if let Some(processid) = self.in_progress.take() {
+            self.complete(processid, result, packed);
+        }
+        self.run_next();
+    }
+
+    /// Sets the clock's alarm for the earliest process alarm.
+    fn update_alarm(&self) {
+        let earliest = self
+            .apps
+            .iter()
+            .filter_map(|app| app.enter(|app, _| app.alarm))
+            .min();
+        if earliest == self.armed.get() {
+            return;
+        }
+        let result = match earliest {
+            Some(seconds) => self
+                .date_time
+                .set_alarm(DateTimeValues::from_unix_seconds(seconds)),
+            None => self.date_time.disarm_alarm(),
+        };
+        if result.is_ok() {
+            self.armed.set(earliest);
+        }
+    }
+}
+
+impl<'a, D: DateTime<'a> + DateTimeAlarm<'a>> DateTimeClient for DateTimeDriver<'a, D> {
+    fn get_date_time_done(&self, datetime: Result<DateTimeValues, ErrorCode>) {
+        match datetime {
+            Ok(date_time) => self.finish(Ok(()), pack(date_time)),
+            Err(e) => self.finish(Err(e), (0, 0)),
+        }
+    }
+
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>) {
+        self.finish(result, (0, 0));
+    }
+}
+
+impl<'a, D: DateTime<'a> + DateTimeAlarm<'a>> DateTimeAlarmClient for DateTimeDriver<'a, D> {
+    fn alarm_fired(&self) {
+        let fired = match self.armed.take() {
+            Some(fired) => fired,
+            None => return,
+        };
+        self.apps.each(|_, app, kernel_data| {
+            if let Some(alarm) = app.alarm {
+                if alarm <= fired {
+                    app.alarm = None;
+                    let (date, time) = pack(DateTimeValues::from_unix_seconds(alarm));
+                    kernel_data
+                        .schedule_upcall(upcall::ALARM, (date, time, 0))
+                        .ok();
+                }
+            }
+        });
+        self.update_alarm();
+    }
+}
+
+impl<'a, D: DateTime<'a> + DateTimeAlarm<'a>> SyscallDriver for DateTimeDriver<'a, D> {
+    /// Date and time control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Read the date and time.
+    /// - `2`: Set the date and time to `arg1` (date) and `arg2` (time).
+    /// - `3`: Set the process's alarm to `arg1` (date) and `arg2` (time).
+    /// - `4`: Cancel the process's alarm.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_num {
+            0 => Ok(()),
+            1 | 2 => {
+                let task = if command_num == 1 {
+                    Ok(Task::Get)
+                } else {
+                    unpack(arg1, arg2).map(Task::Set)
+                };
+                task.and_then(|task| {
+                    self.apps
+                        .enter(processid, |app, _| {
+                            if app.task.is_some() {
+                                return Err(ErrorCode::BUSY);
+                            }
+                            app.task = Some(task);
+                            Ok(())
+                        })
+                        .unwrap_or_else(|err| Err(err.into()))
+                })
+                .map(|()| self.run_next())
+            }
+            3 => unpack(arg1, arg2)
+                .and_then(|date_time| date_time.to_unix_seconds())
+                .and_then(|seconds| {
+                    self.apps
+                        .enter(processid, |app, _| app.alarm = Some(seconds))
+                        .map_err(ErrorCode::from)
+                })
+                .map(|()| self.update_alarm()),
+            4 => self
+                .apps
+                .enter(processid, |app, _| app.alarm = None)
+                .map_err(ErrorCode::from)
+                .map(|()| self.update_alarm()),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        CommandReturn::from(result)
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
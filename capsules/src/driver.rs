@@ -18,9 +18,15 @@ pub enum NUM {
     AnalogComparator      = 0x00007,
     LowLevelDebug         = 0x00008,
     ReadOnlyState         = 0x00009,
+    WallClock             = 0x0000A,
+    Pwm                   = 0x0000B,
+    AdcStream             = 0x0000C,
 
     // Kernel
     Ipc                   = 0x10000,
+    ProcessInfo           = 0x10001,
+    SharedMemory          = 0x10002,
+    IpcMessage            = 0x10003,
 
     // HW Buses
     Spi                   = 0x20001,
@@ -33,6 +39,8 @@ pub enum NUM {
     BleAdvertising        = 0x30000,
     Ieee802154            = 0x30001,
     Udp                   = 0x30002,
+    Coap                  = 0x30003,
+    BleGatt               = 0x30004,
 
     // Cryptography
     Rng                   = 0x40001,
@@ -52,14 +60,18 @@ pub enum NUM {
     Temperature           = 0x60000,
     Humidity              = 0x60001,
     AmbientLight          = 0x60002,
+    Pressure              = 0x60003,
     NINEDOF               = 0x60004,
     Proximity             = 0x60005,
     SoundPressure         = 0x60006,
     AirQuality            = 0x60007,
+    SensorSampling        = 0x60008,
 
     // Sensor ICs
     Tsl2561               = 0x70000,
     Tmp006                = 0x70001,
+    // Deprecated: the LPS25HB is read through `Pressure`. The number stays
+    // reserved so that it is not given to another driver.
     Lps25hb               = 0x70004,
     L3gd20                = 0x70005,
     Lsm303dlch            = 0x70006,
@@ -79,5 +91,6 @@ pub enum NUM {
     Touch                 = 0x90002,
     TextScreen            = 0x90003,
     SevenSegment          = 0x90004,
+    DateTime              = 0x90005,
 }
 }
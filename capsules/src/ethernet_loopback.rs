@@ -0,0 +1,121 @@
+//! Simulated Ethernet device that loops transmitted frames back.
+//!
+//! Every frame passed to `transmit` is delivered to the receive client, and
+//! then completed with `transmit_done`, from a deferred call. This makes it
+//! possible to exercise an Ethernet network stack on boards without an
+//! Ethernet controller.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let loopback = static_init!(
+//!     capsules::ethernet_loopback::EthernetLoopback<'static>,
+//!     capsules::ethernet_loopback::EthernetLoopback::new(
+//!         MacAddress([0x02, 0, 0, 0, 0, 0x01]),
+//!         dynamic_deferred_caller,
+//!     )
+//! );
+//! loopback.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(loopback).unwrap(),
+//! );
+//! ```
+
+use core::cell::Cell;
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::ethernet::{Ethernet, MacAddress, RxClient, TxClient, MAX_FRAME_LEN};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+pub struct EthernetLoopback<'a> {
+    mac_address: Cell<MacAddress>,
+    tx_client: OptionalCell<&'a dyn TxClient>,
+    rx_client: OptionalCell<&'a dyn RxClient>,
+    frame: TakeCell<'static, [u8]>,
+    frame_len: Cell<usize>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a> EthernetLoopback<'a> {
+    pub fn new(
+        mac_address: MacAddress,
+        deferred_caller: &'a DynamicDeferredCall,
+    ) -> EthernetLoopback<'a> {
+        EthernetLoopback {
+            mac_address: Cell::new(mac_address),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            frame: TakeCell::empty(),
+            frame_len: Cell::new(0),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+}
+
+impl<'a> Ethernet<'a> for EthernetLoopback<'a> {
+    fn set_transmit_client(&self, client: &'a dyn TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'a dyn RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address.get()
+    }
+
+    fn set_mac_address(&self, address: MacAddress) -> Result<(), ErrorCode> {
+        self.mac_address.set(address);
+        Ok(())
+    }
+
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if len > frame.len() {
+            return Err((ErrorCode::INVAL, frame));
+        }
+        if len > MAX_FRAME_LEN {
+            return Err((ErrorCode::SIZE, frame));
+        }
+        if self.frame.is_some() {
+            return Err((ErrorCode::BUSY, frame));
+        }
+        match self.handle.extract() {
+            Some(handle) => {
+                self.frame.replace(frame);
+                self.frame_len.set(len);
+                self.deferred_caller.set(handle);
+                Ok(())
+            }
+            None => Err((ErrorCode::OFF, frame)),
+        }
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for EthernetLoopback<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        self.frame.take().map(|frame| {
+            let len = self.frame_len.get();
+            self.rx_client
+                .map(|client| client.received_frame(&frame[..len]));
+            self.tx_client
+                .map(move |client| client.transmit_done(Ok(()), frame, len));
+        });
+    }
+}
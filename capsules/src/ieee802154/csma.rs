@@ -0,0 +1,518 @@
+//! Software unslotted CSMA-CA and acknowledgements for 802.15.4 radios that
+//! lack them in hardware.
+//!
+//! `CsmaMac` implements `capsules::ieee802154::mac::Mac` like `AwakeMac`,
+//! keeping the radio on, but rather than handing each frame straight to the
+//! radio it follows IEEE 802.15.4-2006, 7.5.1.4 and 7.5.6.4:
+//!
+//! - Before each attempt, it waits a random number of backoff periods, then
+//!   assesses the channel by measuring its energy. If the channel is busy, it
+//!   backs off again with a larger exponent, up to `max_csma_backoffs` times,
+//!   after which the frame fails with `ErrorCode::BUSY`.
+//! - Frames that request an acknowledgement are sent again if no ACK arrives
+//!   in time, up to `max_frame_retries` times, after which the frame fails
+//!   with `ErrorCode::NOACK`.
+//! - Received frames that request an acknowledgement are acknowledged, and
+//!   retransmissions of a frame already received are dropped.
+//!
+//! These parameters are from the MAC PIB, and can be changed with
+//! `set_params`. How many times each frame backed off and was resent is
+//! reported by `Mac::last_tx_stats`.
+//!
+//! Radios that cannot measure energy are assumed to always find the channel
+//! clear, so frames are still sent after a random backoff.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! static mut ACK_BUF: [u8; radio::MAX_BUF_SIZE] = [0x00; radio::MAX_BUF_SIZE];
+//!
+//! let csma_mac = static_init!(
+//!     capsules::ieee802154::csma::CsmaMac<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::csma::CsmaMac::new(radio, csma_alarm, random));
+//! csma_alarm.set_alarm_client(csma_mac);
+//! radio.set_transmit_client(csma_mac);
+//! radio.set_receive_client(csma_mac, &mut RADIO_RX_BUF);
+//! radio.set_energy_detect_client(csma_mac);
+//! csma_mac.initialize(&mut ACK_BUF);
+//!
+//! // The CsmaMac can now back a Framer, as an AwakeMac would
+//! ```
+
+use crate::ieee802154::mac::{self, Mac, TxStats};
+use crate::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress};
+use core::cell::Cell;
+use core::cmp;
+use kernel::hil::radio;
+use kernel::hil::rng::Random;
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+// IEEE 802.15.4-2006, Table 85: aUnitBackoffPeriod, 20 symbols of 16 us
+const UNIT_BACKOFF_US: u32 = 320;
+// IEEE 802.15.4-2006, 6.9.9: the CCA detection time, 8 symbols
+const CCA_DURATION_US: u32 = 128;
+// IEEE 802.15.4-2006, Table 86: macAckWaitDuration, 54 symbols at 2.4 GHz
+const ACK_WAIT_US: u32 = 864;
+
+/// The energy level at and above which the channel is considered busy.
+pub const DEFAULT_CCA_THRESHOLD: u8 = 0x40;
+
+/// The CSMA-CA and retransmission attributes of the MAC PIB.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CsmaParams {
+    /// macMinBE: the backoff exponent of the first backoff, up to `max_be`.
+    pub min_be: u8,
+    /// macMaxBE: the largest backoff exponent, from 3 to 8.
+    pub max_be: u8,
+    /// macMaxCSMABackoffs: how many times to back off from a busy channel
+    /// before giving up, up to 5.
+    pub max_csma_backoffs: u8,
+    /// macMaxFrameRetries: how many times to resend a frame that was not
+    /// acknowledged, up to 7.
+    pub max_frame_retries: u8,
+}
+
+impl Default for CsmaParams {
+    /// The defaults of IEEE 802.15.4-2006, Table 86.
+    fn default() -> CsmaParams {
+        CsmaParams {
+            min_be: 3,
+            max_be: 5,
+            max_csma_backoffs: 4,
+            max_frame_retries: 3,
+        }
+    }
+}
+
+impl CsmaParams {
+    fn is_valid(&self) -> bool {
+        (3..=8).contains(&self.max_be)
+            && self.min_be <= self.max_be
+            && self.max_csma_backoffs <= 5
+            && self.max_frame_retries <= 7
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Waiting a random number of backoff periods.
+    Backoff,
+    /// Measuring the energy on the channel.
+    ChannelAssessment,
+    Transmitting,
+    /// Waiting for the receiver to acknowledge the frame.
+    WaitingForAck,
+}
+
+pub struct CsmaMac<'a, R: radio::Radio, A: time::Alarm<'a>> {
+    radio: &'a R,
+    alarm: &'a A,
+    random: &'a dyn Random<'a>,
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    params: Cell<CsmaParams>,
+    cca_threshold: Cell<u8>,
+    state: Cell<State>,
+
+    /// The frame being sent, kept between attempts.
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    /// The sequence number of the frame being sent, if it must be
+    /// acknowledged.
+    awaited_ack: Cell<Option<u8>>,
+    /// NB and BE of the current attempt.
+    backoffs: Cell<u8>,
+    backoff_exponent: Cell<u8>,
+    stats: Cell<TxStats>,
+
+    ack_buf: TakeCell<'static, [u8]>,
+    ack_in_flight: Cell<bool>,
+    /// The source and sequence number of the last frame acknowledged, to
+    /// drop retransmissions of it.
+    last_acked: Cell<Option<(MacAddress, u8)>>,
+}
+
+impl<'a, R: radio::Radio, A: time::Alarm<'a>> CsmaMac<'a, R, A> {
+    pub fn new(radio: &'a R, alarm: &'a A, random: &'a dyn Random<'a>) -> CsmaMac<'a, R, A> {
+        CsmaMac {
+            radio: radio,
+            alarm: alarm,
+            random: random,
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            params: Cell::new(CsmaParams::default()),
+            cca_threshold: Cell::new(DEFAULT_CCA_THRESHOLD),
+            state: Cell::new(State::Idle),
+            tx_buf: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            awaited_ack: Cell::new(None),
+            backoffs: Cell::new(0),
+            backoff_exponent: Cell::new(0),
+            stats: Cell::new(TxStats::default()),
+            ack_buf: TakeCell::empty(),
+            ack_in_flight: Cell::new(false),
+            last_acked: Cell::new(None),
+        }
+    }
+
+    /// Sets the CSMA-CA and retransmission attributes, which apply from the
+    /// next frame sent. Returns `INVAL` if any is out of range.
+    pub fn set_params(&self, params: CsmaParams) -> Result<(), ErrorCode> {
+        if !params.is_valid() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.params.set(params);
+        Ok(())
+    }
+
+    pub fn params(&self) -> CsmaParams {
+        self.params.get()
+    }
+
+    /// Sets the energy level at and above which the channel is busy.
+    pub fn set_cca_threshold(&self, level: u8) {
+        self.cca_threshold.set(level);
+    }
+
+    // Channel access
+
+    fn start_attempt(&self) {
+        self.backoffs.set(0);
+        self.backoff_exponent.set(self.params.get().min_be);
+        self.backoff();
+    }
+
+    /// Waits a random number of backoff periods, from 0 to 2^BE - 1.
+    fn backoff(&self) {
+        let periods = self.random.random() % (1 << self.backoff_exponent.get());
+        self.state.set(State::Backoff);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_us(periods * UNIT_BACKOFF_US),
+        );
+    }
+
+    fn assess_channel(&self) {
+        if self.ack_in_flight.get() {
+            // Busy with our own ACK
+            self.channel_busy();
+            return;
+        }
+        match self.radio.energy_detect(CCA_DURATION_US) {
+            Ok(()) => self.state.set(State::ChannelAssessment),
+            Err(ErrorCode::NOSUPPORT) => self.send_frame(),
+            Err(_) => self.channel_busy(),
+        }
+    }
+
+    fn channel_busy(&self) {
+        let params = self.params.get();
+        let mut stats = self.stats.get();
+        stats.busy_backoffs = stats.busy_backoffs.saturating_add(1);
+        self.stats.set(stats);
+        self.backoffs.set(self.backoffs.get() + 1);
+        self.backoff_exponent
+            .set(cmp::min(self.backoff_exponent.get() + 1, params.max_be));
+        if self.backoffs.get() > params.max_csma_backoffs {
+            // Channel access failure
+            self.finish(false, Err(ErrorCode::BUSY));
+        } else {
+            self.backoff();
+        }
+    }
+
+    fn send_frame(&self) {
+        let buf = match self.tx_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        match self.radio.transmit(buf, self.tx_len.get()) {
+            Ok(()) => self.state.set(State::Transmitting),
+            Err((ecode, buf)) => {
+                self.tx_buf.replace(buf);
+                if ecode == ErrorCode::BUSY {
+                    self.channel_busy();
+                } else {
+                    self.finish(false, Err(ecode));
+                }
+            }
+        }
+    }
+
+    fn no_ack(&self) {
+        let mut stats = self.stats.get();
+        if stats.retries >= self.params.get().max_frame_retries {
+            self.finish(false, Err(ErrorCode::NOACK));
+            return;
+        }
+        stats.retries += 1;
+        self.stats.set(stats);
+        self.start_attempt();
+    }
+
+    fn finish(&self, acked: bool, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        self.awaited_ack.set(None);
+        self.tx_buf.take().map(|buf| {
+            self.tx_client.map(move |client| {
+                client.send_done(buf, acked, result);
+            });
+        });
+    }
+
+    // Acknowledgements
+
+    fn send_ack(&self, seq: u8) {
+        // The radio is in use, so the sender will have to try again
+        if self.ack_in_flight.get()
+            || self.state.get() == State::Transmitting
+            || self.state.get() == State::ChannelAssessment
+        {
+            return;
+        }
+        let buf = match self.ack_buf.take() {
+            Some(buf) => buf,
+            None => return,
+        };
+        let header = Header {
+            frame_type: FrameType::Acknowledgement,
+            frame_pending: false,
+            ack_requested: false,
+            version: FrameVersion::V2006,
+            seq: Some(seq),
+            dst_pan: None,
+            dst_addr: None,
+            src_pan: None,
+            src_addr: None,
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+        let frame_len = match header.encode(&mut buf[radio::PSDU_OFFSET..], false).done() {
+            Some((frame_len, _)) => frame_len,
+            None => {
+                self.ack_buf.replace(buf);
+                return;
+            }
+        };
+        match self.radio.transmit(buf, frame_len) {
+            Ok(()) => self.ack_in_flight.set(true),
+            Err((_, buf)) => {
+                self.ack_buf.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: time::Alarm<'a>> Mac for CsmaMac<'a, R, A> {
+    fn initialize(&self, mac_buf: &'static mut [u8]) -> Result<(), ErrorCode> {
+        // ACKs are sent from this buffer
+        self.ack_buf.replace(mac_buf);
+        Ok(())
+    }
+
+    fn is_on(&self) -> bool {
+        self.radio.is_on()
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.radio.set_config_client(client)
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.radio.set_address(addr)
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.radio.set_address_long(addr)
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.radio.set_pan(id)
+    }
+
+    fn get_address(&self) -> u16 {
+        self.radio.get_address()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.radio.get_address_long()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.radio.get_pan()
+    }
+
+    fn config_commit(&self) {
+        self.radio.config_commit()
+    }
+
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn set_receive_buffer(&self, buffer: &'static mut [u8]) {
+        self.radio.set_receive_buffer(buffer);
+    }
+
+    fn transmit(
+        &self,
+        full_mac_frame: &'static mut [u8],
+        frame_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if self.state.get() != State::Idle {
+            return Err((ErrorCode::BUSY, full_mac_frame));
+        }
+        let awaited_ack = match Header::decode(
+            &full_mac_frame[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len],
+            false,
+        )
+        .done()
+        {
+            Some((_, (header, _))) => match header.dst_addr {
+                // Broadcast frames are never acknowledged
+                Some(MacAddress::Short(mac::BROADCAST_ADDRESS)) | None => None,
+                Some(_) if header.ack_requested => header.seq,
+                Some(_) => None,
+            },
+            None => return Err((ErrorCode::INVAL, full_mac_frame)),
+        };
+        self.tx_buf.replace(full_mac_frame);
+        self.tx_len.set(frame_len);
+        self.awaited_ack.set(awaited_ack);
+        self.stats.set(TxStats::default());
+        self.start_attempt();
+        Ok(())
+    }
+
+    fn last_tx_stats(&self) -> TxStats {
+        self.stats.get()
+    }
+}
+
+impl<'a, R: radio::Radio, A: time::Alarm<'a>> time::AlarmClient for CsmaMac<'a, R, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Backoff => self.assess_channel(),
+            State::WaitingForAck => self.no_ack(),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: time::Alarm<'a>> radio::EnergyDetectClient for CsmaMac<'a, R, A> {
+    fn energy_detect_done(&self, result: Result<u8, ErrorCode>) {
+        if self.state.get() != State::ChannelAssessment {
+            return;
+        }
+        match result {
+            Ok(level) if level < self.cca_threshold.get() => self.send_frame(),
+            Err(ErrorCode::NOSUPPORT) => self.send_frame(),
+            _ => self.channel_busy(),
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: time::Alarm<'a>> radio::TxClient for CsmaMac<'a, R, A> {
+    fn send_done(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+        if self.state.get() != State::Transmitting {
+            // Only ACKs are sent outside of a transmission
+            self.ack_in_flight.set(false);
+            self.ack_buf.replace(buf);
+            return;
+        }
+        self.tx_buf.replace(buf);
+        match (result, self.awaited_ack.get()) {
+            // The radio found the channel busy after all
+            (Err(ErrorCode::BUSY), _) => self.channel_busy(),
+            (Err(ecode), _) => self.finish(false, Err(ecode)),
+            // The radio handled the acknowledgement itself
+            (Ok(()), Some(_)) if acked => self.finish(true, Ok(())),
+            (Ok(()), Some(_)) => {
+                self.state.set(State::WaitingForAck);
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(ACK_WAIT_US));
+            }
+            (Ok(()), None) => self.finish(acked, Ok(())),
+        }
+    }
+}
+
+impl<'a, R: radio::Radio, A: time::Alarm<'a>> radio::RxClient for CsmaMac<'a, R, A> {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        crc_valid: bool,
+        result: Result<(), ErrorCode>,
+    ) {
+        let decoded = Header::decode(&buf[radio::PSDU_OFFSET..], false)
+            .done()
+            .map(|(_, (header, _))| {
+                (
+                    header.frame_type,
+                    header.seq,
+                    header.ack_requested,
+                    header.dst_addr,
+                    header.src_addr,
+                    mac::is_addressed_to(self.radio, &header),
+                )
+            });
+        let (frame_type, seq, ack_requested, dst_addr, src_addr, addressed) = match decoded {
+            Some(fields) => fields,
+            None => {
+                self.radio.set_receive_buffer(buf);
+                return;
+            }
+        };
+
+        if frame_type == FrameType::Acknowledgement {
+            self.radio.set_receive_buffer(buf);
+            let awaited = self.state.get() == State::WaitingForAck
+                && seq.is_some()
+                && seq == self.awaited_ack.get();
+            if crc_valid && awaited {
+                let _ = self.alarm.disarm();
+                self.finish(true, Ok(()));
+            }
+            return;
+        }
+        if !addressed {
+            self.radio.set_receive_buffer(buf);
+            return;
+        }
+
+        let unicast = match dst_addr {
+            Some(MacAddress::Short(mac::BROADCAST_ADDRESS)) | None => false,
+            Some(_) => true,
+        };
+        let mut duplicate = false;
+        if let (true, true, true, Some(seq)) = (crc_valid, ack_requested, unicast, seq) {
+            self.send_ack(seq);
+            if let Some(src_addr) = src_addr {
+                duplicate = self.last_acked.get() == Some((src_addr, seq));
+                self.last_acked.set(Some((src_addr, seq)));
+            }
+        }
+
+        if duplicate {
+            self.radio.set_receive_buffer(buf);
+        } else {
+            self.rx_client.map(move |client| {
+                client.receive(buf, frame_len, crc_valid, result);
+            });
+        }
+    }
+}
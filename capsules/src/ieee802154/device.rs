@@ -12,7 +12,8 @@
 //! procedure in hardware, as opposed to requiring a software implementation.
 
 use crate::ieee802154::framer::Frame;
-use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::ieee802154::mac::TxStats;
+use crate::net::ieee802154::{FrameType, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use kernel::ErrorCode;
 
 pub trait MacDevice<'a> {
@@ -69,10 +70,37 @@ pub trait MacDevice<'a> {
         security_needed: Option<(SecurityLevel, KeyId)>,
     ) -> Result<Frame, &'static mut [u8]>;
 
+    /// Prepares a mutable buffer slice as an unsecured 802.15.4 frame of any
+    /// type, such as the beacon and MAC command frames exchanged by the MAC
+    /// sublayer management entity. Unlike data frames, these may omit the
+    /// destination or source addressing fields.
+    ///
+    /// - `buf`: The mutable buffer slice to use
+    /// - `frame_type`: The type of the frame
+    /// - `dst`: The destination PAN ID and MAC address, if any
+    /// - `src`: The source PAN ID and MAC address, if any
+    /// - `ack_requested`: Whether the receiver should acknowledge the frame
+    ///
+    /// Returns either a Frame that is ready to have payload appended to it, or
+    /// the mutable buffer if the frame cannot be prepared for any reason
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        frame_type: FrameType,
+        dst: Option<(PanID, MacAddress)>,
+        src: Option<(PanID, MacAddress)>,
+        ack_requested: bool,
+    ) -> Result<Frame, &'static mut [u8]>;
+
     /// Transmits a frame that has been prepared by the above process. If the
     /// transmission process fails, the buffer inside the frame is returned so
     /// that it can be re-used.
     fn transmit(&self, frame: Frame) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// How the frame last reported to the transmit client was sent: how often
+    /// the MAC backed off from a busy channel and resent it for lack of an
+    /// ACK.
+    fn last_tx_stats(&self) -> TxStats;
 }
 
 /// Trait to be implemented by any user of the IEEE 802.15.4 device that
@@ -2,8 +2,11 @@
 //!
 //! Implements a userspace interface for sending and receiving IEEE 802.15.4
 //! frames. Also provides a minimal list-based interface for managing keys and
-//! known link neighbors, which is needed for 802.15.4 security.
+//! known link neighbors, which is needed for 802.15.4 security, and, when the
+//! board provides a MAC sublayer management entity, lets processes scan for,
+//! join and coordinate PANs.
 
+use crate::ieee802154::mlme::{self, AssociationStatus, DisassociationReason, ScanType};
 use crate::ieee802154::{device, framer};
 use crate::net::ieee802154::{AddressMode, Header, KeyId, MacAddress, PanID, SecurityLevel};
 use crate::net::stream::{decode_bytes, decode_u8, encode_bytes, encode_u8, SResult};
@@ -38,6 +41,19 @@ mod rw_allow {
     pub const COUNT: u8 = 2;
 }
 
+/// Kinds of MAC management events, the first argument of upcall 2
+mod mlme_event {
+    pub const SCAN_DONE: usize = 0;
+    pub const ASSOCIATE_DONE: usize = 1;
+    pub const DISASSOCIATED: usize = 2;
+    pub const DEVICE_ASSOCIATED: usize = 3;
+    pub const DEVICE_DISASSOCIATED: usize = 4;
+}
+
+/// The size of a PAN descriptor in the config buffer after an active or
+/// passive scan.
+const PAN_DESCRIPTOR_LEN: usize = 14;
+
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Ieee802154 as usize;
 
@@ -191,7 +207,7 @@ pub struct RadioDriver<'a> {
     /// Grant of apps that use this radio driver.
     apps: Grant<
         App,
-        UpcallCount<2>,
+        UpcallCount<3>,
         AllowRoCount<{ ro_allow::COUNT }>,
         AllowRwCount<{ rw_allow::COUNT }>,
     >,
@@ -212,6 +228,11 @@ pub struct RadioDriver<'a> {
 
     /// Used to save result for passing a callback from a deferred call.
     saved_result: OptionalCell<Result<(), ErrorCode>>,
+
+    /// MAC sublayer management entity, if the board provides one
+    mlme: OptionalCell<&'a dyn mlme::Mlme<'a>>,
+    /// ID of app whose scan or association request is being processed.
+    mlme_app: OptionalCell<ProcessId>,
 }
 
 impl<'a> RadioDriver<'a> {
@@ -219,7 +240,7 @@ impl<'a> RadioDriver<'a> {
         mac: &'a dyn device::MacDevice<'a>,
         grant: Grant<
             App,
-            UpcallCount<2>,
+            UpcallCount<3>,
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
@@ -239,6 +260,8 @@ impl<'a> RadioDriver<'a> {
             saved_appid: OptionalCell::empty(),
             saved_result: OptionalCell::empty(),
             handle: OptionalCell::empty(),
+            mlme: OptionalCell::empty(),
+            mlme_app: OptionalCell::empty(),
         }
     }
 
@@ -246,6 +269,96 @@ impl<'a> RadioDriver<'a> {
         self.handle.replace(handle);
     }
 
+    pub fn set_mlme(&self, mlme: &'a dyn mlme::Mlme<'a>) {
+        self.mlme.set(mlme);
+    }
+
+    // MAC management functions
+
+    /// Starts a MAC management operation for `appid`, which is told about
+    /// its outcome through upcall 2.
+    fn mlme_request<F>(&self, appid: ProcessId, request: F) -> CommandReturn
+    where
+        F: FnOnce(&dyn mlme::Mlme<'a>) -> Result<(), ErrorCode>,
+    {
+        match self.mlme.extract() {
+            None => CommandReturn::failure(ErrorCode::NOSUPPORT),
+            Some(mlme) => {
+                if self.mlme_app.is_some() {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                let result = request(mlme);
+                if result.is_ok() {
+                    self.mlme_app.set(appid);
+                }
+                result.into()
+            }
+        }
+    }
+
+    /// Schedules a MAC management upcall for every app.
+    fn mlme_event(&self, event: usize, arg1: usize, arg2: usize) {
+        self.apps.each(|_, _, kernel_data| {
+            kernel_data.schedule_upcall(2, (event, arg1, arg2)).ok();
+        });
+    }
+
+    /// Copies the results of the last scan into the config buffer of
+    /// `appid`, returning how many were copied.
+    fn copy_scan_results(&self, appid: ProcessId, mlme: &dyn mlme::Mlme<'a>) -> usize {
+        self.apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::CFG)
+                    .and_then(|cfg| {
+                        cfg.mut_enter(|cfg| {
+                            let mut count = 0;
+                            // Energy levels, as (channel, level) pairs
+                            for channel in mlme::MIN_CHANNEL..=mlme::MAX_CHANNEL {
+                                if let Some(level) = mlme.energy_level(channel) {
+                                    if let Some(pair) = cfg.get(2 * count..2 * count + 2) {
+                                        pair.copy_from_slice(&[channel, level]);
+                                        count += 1;
+                                    }
+                                }
+                            }
+                            // PAN descriptors
+                            let mut index = 0;
+                            while let Some(pan) = mlme.pan_descriptor(index) {
+                                let offset = index * PAN_DESCRIPTOR_LEN;
+                                if let Some(record) = cfg.get(offset..offset + PAN_DESCRIPTOR_LEN) {
+                                    let pan_id = pan.pan.to_le_bytes();
+                                    let superframe_spec = pan.superframe_spec.to_le_bytes();
+                                    let mut encoded = [0; PAN_DESCRIPTOR_LEN];
+                                    encoded[..6].copy_from_slice(&[
+                                        pan.channel,
+                                        pan_id[0],
+                                        pan_id[1],
+                                        superframe_spec[0],
+                                        superframe_spec[1],
+                                        AddressMode::from(&Some(pan.coordinator)) as u8,
+                                    ]);
+                                    match pan.coordinator {
+                                        MacAddress::Short(addr) => {
+                                            encoded[6..8].copy_from_slice(&addr.to_le_bytes())
+                                        }
+                                        MacAddress::Long(addr) => {
+                                            encoded[6..14].copy_from_slice(&addr)
+                                        }
+                                    }
+                                    record.copy_from_slice(&encoded);
+                                    count += 1;
+                                }
+                                index += 1;
+                            }
+                            count
+                        })
+                    })
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0)
+    }
+
     // Neighbor management functions
 
     /// Add a new neighbor to the end of the list if there is still space
@@ -547,7 +660,13 @@ impl SyscallDriver for RadioDriver<'_> {
     // ### `subscribe_num`
     //
     // - `0`: Setup callback for when frame is received.
-    // - `1`: Setup callback for when frame is transmitted.
+    // - `1`: Setup callback for when frame is transmitted. The arguments
+    //        are the status, whether the frame was acknowledged, and how it
+    //        was sent: the number of times the MAC backed off from a busy
+    //        channel in bits 0-7, and resent it for lack of an ACK in bits
+    //        8-15.
+    // - `2`: Setup callback for MAC management events: scan and association
+    //        results, and devices joining or leaving a coordinated PAN.
 
     /// IEEE 802.15.4 MAC device control.
     ///
@@ -598,11 +717,32 @@ impl SyscallDriver for RadioDriver<'_> {
     ///                      9 bytes: the key ID (might not use all bytes) +
     ///                      16 bytes: the key.
     /// - `25`: Remove the key at an index.
+    /// - `26`: Transmit a frame to the given short address.
+    ///        app_cfg (in): 1 byte: the security level +
+    ///                      up to 10 bytes: the key ID mode and key ID.
+    /// - `27`: Scan the channels set in the bitmask `arg2`. The low byte of
+    ///         `arg1` is the scan type: 0 for energy detection, 1 for an
+    ///         active scan, and 2 for a passive scan. The next byte is the
+    ///         scan duration exponent, from 0 to 14. Upcall 2 reports the
+    ///         number of results once done.
+    ///        app_cfg (out): energy detection: 2 bytes per channel: the
+    ///                       channel and its peak energy.
+    ///                       Active or passive: 14 bytes per PAN found: the
+    ///                       channel, the PAN ID, the superframe spec, the
+    ///                       address mode, and the coordinator's address.
+    ///                       Multi-byte values are little-endian.
+    /// - `28`: Associate with the PAN at index `arg1` in the results of the
+    ///         last scan, with the capability information `arg2`. Upcall 2
+    ///         reports the association status and the short address.
+    /// - `29`: Disassociate from the PAN.
+    /// - `30`: Start coordinating the PAN `arg1` on the channel in the low
+    ///         byte of `arg2`, accepting new devices if bit 8 of `arg2` is set.
+    /// - `31`: Stop coordinating the PAN.
     fn command(
         &self,
         command_number: usize,
         arg1: usize,
-        _: usize,
+        arg2: usize,
         appid: ProcessId,
     ) -> CommandReturn {
         match command_number {
@@ -886,6 +1026,37 @@ impl SyscallDriver for RadioDriver<'_> {
                         },
                     )
             }
+            27 => {
+                let scan_type = match arg1 & 0xff {
+                    0 => ScanType::EnergyDetect,
+                    1 => ScanType::Active,
+                    2 => ScanType::Passive,
+                    _ => return CommandReturn::failure(ErrorCode::INVAL),
+                };
+                let duration = (arg1 >> 8) as u8;
+                self.mlme_request(appid, |mlme| mlme.scan(scan_type, arg2 as u32, duration))
+            }
+            28 => self.mlme_request(appid, |mlme| {
+                let pan = mlme.pan_descriptor(arg1).ok_or(ErrorCode::INVAL)?;
+                mlme.associate(pan.channel, pan.pan, pan.coordinator, arg2 as u8)
+            }),
+            29 => self
+                .mlme
+                .map_or(Err(ErrorCode::NOSUPPORT), |mlme| mlme.disassociate())
+                .into(),
+            30 => self
+                .mlme
+                .map_or(Err(ErrorCode::NOSUPPORT), |mlme| {
+                    mlme.start_coordinator(arg1 as u16, arg2 as u8, arg2 & (1 << 8) != 0)
+                })
+                .into(),
+            31 => self
+                .mlme
+                .map_or(Err(ErrorCode::NOSUPPORT), |mlme| {
+                    mlme.stop_coordinator();
+                    Ok(())
+                })
+                .into(),
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -898,6 +1069,7 @@ impl SyscallDriver for RadioDriver<'_> {
 impl device::TxClient for RadioDriver<'_> {
     fn send_done(&self, spi_buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
         self.kernel_tx.replace(spi_buf);
+        let stats = self.mac.last_tx_stats();
         self.current_app.take().map(|appid| {
             let _ = self.apps.enter(appid, |_app, upcalls| {
                 upcalls
@@ -906,7 +1078,7 @@ impl device::TxClient for RadioDriver<'_> {
                         (
                             kernel::errorcode::into_statuscode(result),
                             acked as usize,
-                            0,
+                            stats.busy_backoffs as usize | (stats.retries as usize) << 8,
                         ),
                     )
                     .ok();
@@ -961,3 +1133,55 @@ impl device::RxClient for RadioDriver<'_> {
         });
     }
 }
+
+impl<'a> mlme::MlmeClient for RadioDriver<'a> {
+    fn scan_done(&self, result: Result<(), ErrorCode>) {
+        self.mlme_app.take().map(|appid| {
+            let count = match (self.mlme.extract(), result) {
+                (Some(mlme), Ok(())) => self.copy_scan_results(appid, mlme),
+                _ => 0,
+            };
+            let _ = self.apps.enter(appid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        2,
+                        (
+                            mlme_event::SCAN_DONE,
+                            kernel::errorcode::into_statuscode(result),
+                            count,
+                        ),
+                    )
+                    .ok();
+            });
+        });
+    }
+
+    fn associate_done(&self, status: AssociationStatus, short_addr: u16) {
+        self.mlme_app.take().map(|appid| {
+            let _ = self.apps.enter(appid, |_, kernel_data| {
+                kernel_data
+                    .schedule_upcall(
+                        2,
+                        (
+                            mlme_event::ASSOCIATE_DONE,
+                            status as usize,
+                            short_addr as usize,
+                        ),
+                    )
+                    .ok();
+            });
+        });
+    }
+
+    fn disassociated(&self, reason: DisassociationReason) {
+        self.mlme_event(mlme_event::DISASSOCIATED, reason as usize, 0);
+    }
+
+    fn device_associated(&self, short_addr: u16, _addr_long: [u8; 8]) {
+        self.mlme_event(mlme_event::DEVICE_ASSOCIATED, short_addr as usize, 0);
+    }
+
+    fn device_disassociated(&self, short_addr: u16) {
+        self.mlme_event(mlme_event::DEVICE_DISASSOCIATED, short_addr as usize, 0);
+    }
+}
@@ -67,12 +67,14 @@
 
 //
 // TODO: Encryption/decryption
-// TODO: Sending beacon frames
-// TODO: Channel scanning
+//
+// Beacon frames and channel scanning are handled by the MAC sublayer
+// management entity in `capsules::ieee802154::mlme`, which prepares its frames
+// through `MacDevice::prepare_frame`.
 //
 
 use crate::ieee802154::device::{MacDevice, RxClient, TxClient};
-use crate::ieee802154::mac::Mac;
+use crate::ieee802154::mac::{Mac, TxStats};
 use crate::net::ieee802154::{
     FrameType, FrameVersion, Header, KeyId, MacAddress, PanID, Security, SecurityLevel,
 };
@@ -764,6 +766,51 @@ impl<'a, M: Mac, A: AES128CCM<'a>> MacDevice<'a> for Framer<'a, M, A> {
         }
     }
 
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        frame_type: FrameType,
+        dst: Option<(PanID, MacAddress)>,
+        src: Option<(PanID, MacAddress)>,
+        ack_requested: bool,
+    ) -> Result<Frame, &'static mut [u8]> {
+        let header = Header {
+            frame_type: frame_type,
+            frame_pending: false,
+            ack_requested: ack_requested,
+            version: FrameVersion::V2006,
+            seq: Some(self.data_sequence.get()),
+            dst_pan: dst.map(|(pan, _)| pan),
+            dst_addr: dst.map(|(_, addr)| addr),
+            src_pan: src.map(|(pan, _)| pan),
+            src_addr: src.map(|(_, addr)| addr),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+
+        match header.encode(&mut buf[radio::PSDU_OFFSET..], true).done() {
+            Some((data_offset, mac_payload_offset)) => Ok(Frame {
+                buf: buf,
+                info: FrameInfo {
+                    frame_type: frame_type,
+                    mac_payload_offset: mac_payload_offset,
+                    data_offset: data_offset,
+                    data_len: 0,
+                    mic_len: 0,
+                    security_params: None,
+                },
+            }),
+            None => Err(buf),
+        }
+    }
+
+    fn last_tx_stats(&self) -> TxStats {
+        self.mac.last_tx_stats()
+    }
+
     fn transmit(&self, frame: Frame) -> Result<(), (ErrorCode, &'static mut [u8])> {
         let Frame { buf, info } = frame;
         let state = match self.tx_state.take() {
@@ -7,14 +7,39 @@
 //!
 //! AwakeMac provides a default implementation of such a layer, maintaining
 //! the underlying kernel::hil::radio::Radio powered at all times and passing
-//! through each frame for transmission.
+//! through each frame for transmission. For radios without hardware channel
+//! access or acknowledgements, ieee802154::csma::CsmaMac adds both in software.
 
-use crate::net::ieee802154::{Header, MacAddress};
+use crate::net::ieee802154::{FrameType, Header, MacAddress};
 use kernel::debug;
 use kernel::hil::radio;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
 
+/// The short address frames are broadcast to.
+pub(crate) const BROADCAST_ADDRESS: u16 = 0xffff;
+
+/// How the frame last reported to the transmit client was sent.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub struct TxStats {
+    /// The number of times the channel was found busy, over all attempts to
+    /// send the frame.
+    pub busy_backoffs: u8,
+    /// The number of times the frame was sent again for lack of an ACK.
+    pub retries: u8,
+}
+
+/// Whether a frame with `header` is meant for `radio`, which receives in
+/// promiscuous mode.
+pub(crate) fn is_addressed_to<R: radio::RadioConfig>(radio: &R, header: &Header) -> bool {
+    match header.dst_addr {
+        Some(MacAddress::Short(addr)) => addr == radio.get_address() || addr == BROADCAST_ADDRESS,
+        Some(MacAddress::Long(long_addr)) => long_addr == radio.get_address_long(),
+        // Beacons have no destination, and are needed to scan channels
+        None => header.frame_type == FrameType::Beacon,
+    }
+}
+
 pub trait Mac {
     /// Initializes the layer; may require a buffer to temporarily retaining frames to be
     /// transmitted
@@ -60,6 +85,10 @@ pub trait Mac {
         full_mac_frame: &'static mut [u8],
         frame_len: usize,
     ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+
+    /// How the frame last reported to the transmit client was sent. Layers
+    /// that leave channel access to the radio report no backoffs or retries.
+    fn last_tx_stats(&self) -> TxStats;
 }
 
 ///
@@ -145,6 +174,10 @@ impl<R: radio::Radio> Mac for AwakeMac<'_, R> {
     ) -> Result<(), (ErrorCode, &'static mut [u8])> {
         self.radio.transmit(full_mac_frame, frame_len)
     }
+
+    fn last_tx_stats(&self) -> TxStats {
+        TxStats::default()
+    }
 }
 
 impl<R: radio::Radio> radio::TxClient for AwakeMac<'_, R> {
@@ -166,12 +199,7 @@ impl<R: radio::Radio> radio::RxClient for AwakeMac<'_, R> {
         // Filter packets by destination because radio is in promiscuous mode
         let mut addr_match = false;
         if let Some((_, (header, _))) = Header::decode(&buf[radio::PSDU_OFFSET..], false).done() {
-            if let Some(dst_addr) = header.dst_addr {
-                addr_match = match dst_addr {
-                    MacAddress::Short(addr) => addr == self.radio.get_address(),
-                    MacAddress::Long(long_addr) => long_addr == self.radio.get_address_long(),
-                };
-            }
+            addr_match = is_addressed_to(self.radio, &header);
         }
 
         if addr_match {
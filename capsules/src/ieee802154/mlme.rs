@@ -0,0 +1,896 @@
+//! IEEE 802.15.4 MAC sublayer management entity (MLME).
+//!
+//! Implements the management services that let devices find and join a PAN
+//! at runtime, rather than using a PAN ID and channel fixed by the board:
+//!
+//! - MLME-SCAN: energy detection, active and passive scans over a set of
+//!   channels, collecting the energy measured on each channel or the PAN
+//!   descriptors of the coordinators heard.
+//! - MLME-ASSOCIATE: joining the PAN of a coordinator found by a scan, which
+//!   assigns the device a short address.
+//! - MLME-DISASSOCIATE: leaving the PAN, or being asked to by the coordinator.
+//! - MLME-START: acting as the coordinator of a PAN, which answers beacon
+//!   requests with beacons and association requests with short addresses.
+//!
+//! PANs are assumed to be nonbeacon-enabled, as is usual with 6LoWPAN and
+//! Thread: coordinators only send beacons when asked to, and the association
+//! response is transmitted indirectly, when the device polls for it with a
+//! data request. Frames are exchanged unsecured, through a `MacDevice`, while
+//! the channel is switched and measured through the radio directly.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let mlme_mac = static_init!(
+//!     capsules::ieee802154::virtual_mac::MacUser<'static>,
+//!     capsules::ieee802154::virtual_mac::MacUser::new(mux_mac));
+//! mux_mac.add_user(mlme_mac);
+//! let mlme = static_init!(
+//!     capsules::ieee802154::mlme::MacManagement<'static, Radio, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::mlme::MacManagement::new(
+//!         mlme_mac, radio, mlme_alarm, &mut capsules::ieee802154::mlme::BUF));
+//! mlme_mac.set_transmit_client(mlme);
+//! mlme_mac.set_receive_client(mlme);
+//! radio.set_energy_detect_client(mlme);
+//! mlme_alarm.set_alarm_client(mlme);
+//! radio_driver.set_mlme(mlme);
+//! mlme.set_client(radio_driver);
+//! ```
+
+use crate::ieee802154::device::{MacDevice, RxClient, TxClient};
+use crate::net::ieee802154::{FrameType, Header, MacAddress, PanID};
+
+use core::cell::Cell;
+
+use kernel::hil::radio;
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// The buffer MAC command and beacon frames are prepared in.
+pub static mut BUF: [u8; radio::MAX_BUF_SIZE] = [0; radio::MAX_BUF_SIZE];
+
+/// The lowest and highest channels of the 2.4 GHz O-QPSK PHY.
+pub const MIN_CHANNEL: u8 = 11;
+pub const MAX_CHANNEL: u8 = 26;
+const NUM_CHANNELS: usize = (MAX_CHANNEL - MIN_CHANNEL + 1) as usize;
+
+/// The number of PAN descriptors a scan collects.
+pub const MAX_PAN_DESCRIPTORS: usize = 8;
+/// The number of devices a coordinator accepts in its PAN.
+pub const MAX_ASSOCIATED_DEVICES: usize = 8;
+/// The largest scan duration exponent.
+pub const MAX_SCAN_DURATION: u8 = 14;
+
+/// The PAN ID and short address frames are broadcast to.
+const BROADCAST: u16 = 0xffff;
+/// The short address a coordinator takes in the PAN it starts.
+const COORDINATOR_SHORT_ADDRESS: u16 = 0x0000;
+
+// IEEE 802.15.4-2006, 6.5.3.2: each symbol takes 16 us at 2.4 GHz
+const SYMBOL_US: u32 = 16;
+// IEEE 802.15.4-2006, Table 85: aBaseSuperframeDuration
+const BASE_SUPERFRAME_SYMBOLS: u32 = 960;
+// IEEE 802.15.4-2006, Table 86: macResponseWaitTime, in aBaseSuperframeDuration
+const RESPONSE_WAIT_SUPERFRAMES: u32 = 32;
+// IEEE 802.15.4-2006, 7.4.2: macMaxFrameTotalWaitTime with the default
+// CSMA-CA parameters
+const MAX_FRAME_TOTAL_WAIT_SYMBOLS: u32 = 1220;
+
+/// IEEE 802.15.4-2006, Table 82: MAC command frame identifiers.
+mod command {
+    pub const ASSOCIATION_REQUEST: u8 = 0x01;
+    pub const ASSOCIATION_RESPONSE: u8 = 0x02;
+    pub const DISASSOCIATION_NOTIFICATION: u8 = 0x03;
+    pub const DATA_REQUEST: u8 = 0x04;
+    pub const BEACON_REQUEST: u8 = 0x07;
+}
+
+/// IEEE 802.15.4-2006, 7.3.1.2: capability information bits.
+pub mod capability {
+    /// The device is a full-function device.
+    pub const DEVICE_TYPE: u8 = 1 << 1;
+    /// The device is mains-powered.
+    pub const POWER_SOURCE: u8 = 1 << 2;
+    /// The device keeps its receiver on when idle.
+    pub const RECEIVER_ON_WHEN_IDLE: u8 = 1 << 3;
+    /// The device wants a short address.
+    pub const ALLOCATE_ADDRESS: u8 = 1 << 7;
+}
+
+// IEEE 802.15.4-2006, 7.2.2.1.2: superframe specification bits
+const SUPERFRAME_NONBEACON: u16 = 0x0fff;
+const SUPERFRAME_PAN_COORDINATOR: u16 = 1 << 14;
+const SUPERFRAME_ASSOCIATION_PERMIT: u16 = 1 << 15;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ScanType {
+    /// Measures the peak energy on each channel.
+    EnergyDetect,
+    /// Sends a beacon request on each channel and collects the beacons.
+    Active,
+    /// Collects the beacons heard on each channel.
+    Passive,
+}
+
+/// IEEE 802.15.4-2006, Table 83: the association status, along with the MAC
+/// status codes an association can fail with.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum AssociationStatus {
+    Success = 0x00,
+    PanAtCapacity = 0x01,
+    PanAccessDenied = 0x02,
+    ChannelAccessFailure = 0xe1,
+    NoData = 0xeb,
+}
+
+impl AssociationStatus {
+    fn from_u8(status: u8) -> AssociationStatus {
+        match status {
+            0x00 => AssociationStatus::Success,
+            0x01 => AssociationStatus::PanAtCapacity,
+            _ => AssociationStatus::PanAccessDenied,
+        }
+    }
+}
+
+/// IEEE 802.15.4-2006, Table 84: disassociation reasons.
+#[repr(u8)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DisassociationReason {
+    CoordinatorRequest = 0x01,
+    DeviceRequest = 0x02,
+}
+
+/// A coordinator found by an active or passive scan.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct PanDescriptor {
+    pub channel: u8,
+    pub pan: PanID,
+    pub coordinator: MacAddress,
+    pub superframe_spec: u16,
+}
+
+impl PanDescriptor {
+    /// Whether the coordinator accepts association requests.
+    pub fn association_permit(&self) -> bool {
+        self.superframe_spec & SUPERFRAME_ASSOCIATION_PERMIT != 0
+    }
+
+    /// Whether the coordinator is the PAN coordinator.
+    pub fn pan_coordinator(&self) -> bool {
+        self.superframe_spec & SUPERFRAME_PAN_COORDINATOR != 0
+    }
+}
+
+/// The management services of an IEEE 802.15.4 MAC.
+pub trait Mlme<'a> {
+    fn set_client(&self, client: &'a dyn MlmeClient);
+
+    /// Scans the channels set in the `channels` bitmask, spending
+    /// `aBaseSuperframeDuration * (2^duration + 1)` symbols on each.
+    /// `MlmeClient::scan_done` is called once all channels are scanned, and
+    /// the results are then available from `energy_level` or
+    /// `pan_descriptor`, depending on the scan type.
+    fn scan(&self, scan_type: ScanType, channels: u32, duration: u8) -> Result<(), ErrorCode>;
+
+    /// The peak energy measured on `channel` by the last energy detection
+    /// scan, if it was scanned.
+    fn energy_level(&self, channel: u8) -> Option<u8>;
+
+    /// The PAN descriptor at `index` among those found by the last active or
+    /// passive scan.
+    fn pan_descriptor(&self, index: usize) -> Option<PanDescriptor>;
+
+    /// Requests association with the PAN `pan` on `channel` through its
+    /// `coordinator`, with the capability information `capability`.
+    /// `MlmeClient::associate_done` is called with the outcome.
+    fn associate(
+        &self,
+        channel: u8,
+        pan: PanID,
+        coordinator: MacAddress,
+        capability: u8,
+    ) -> Result<(), ErrorCode>;
+
+    /// Leaves the PAN the device is associated with, notifying its
+    /// coordinator. `MlmeClient::disassociated` is called once done.
+    fn disassociate(&self) -> Result<(), ErrorCode>;
+
+    /// Starts acting as the coordinator of a PAN on `channel`, which accepts
+    /// new devices if `association_permit` is set.
+    fn start_coordinator(
+        &self,
+        pan: PanID,
+        channel: u8,
+        association_permit: bool,
+    ) -> Result<(), ErrorCode>;
+
+    /// Stops acting as a coordinator, forgetting the associated devices.
+    fn stop_coordinator(&self);
+}
+
+pub trait MlmeClient {
+    /// Called when a scan is done.
+    fn scan_done(&self, result: Result<(), ErrorCode>);
+
+    /// Called when an association attempt is done, with the short address
+    /// assigned if it succeeded.
+    fn associate_done(&self, status: AssociationStatus, short_addr: u16);
+
+    /// Called when the device left its PAN.
+    fn disassociated(&self, reason: DisassociationReason);
+
+    /// Called on a coordinator when a device joined its PAN.
+    fn device_associated(&self, short_addr: u16, addr_long: [u8; 8]);
+
+    /// Called on a coordinator when a device left its PAN.
+    fn device_disassociated(&self, short_addr: u16);
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum State {
+    Idle,
+    /// Measuring the energy on the current scan channel.
+    EnergyDetecting,
+    /// Collecting beacons on the current scan channel.
+    Listening,
+    /// Sending the association request.
+    RequestingAssociation,
+    /// Waiting before polling the coordinator for the association response.
+    WaitingForResponse,
+    /// Sending the data request that polls for the association response.
+    Polling,
+    /// Listening for the association response.
+    ReceivingResponse,
+    /// Sending the disassociation notification.
+    Disassociating,
+}
+
+/// The frame being transmitted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum Tx {
+    None,
+    /// A frame sent on behalf of the device state machine.
+    Device,
+    Beacon,
+    /// The association response to the device at this index.
+    AssociationResponse(usize),
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Coordinator {
+    pan: PanID,
+    association_permit: bool,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct AssociatedDevice {
+    addr_long: [u8; 8],
+    short_addr: u16,
+    /// The association status still to be sent when the device polls.
+    pending: Option<AssociationStatus>,
+}
+
+pub struct MacManagement<'a, R: radio::RadioConfig, A: time::Alarm<'a>> {
+    mac: &'a dyn MacDevice<'a>,
+    radio: &'a R,
+    alarm: &'a A,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx: Cell<Tx>,
+    state: Cell<State>,
+    client: OptionalCell<&'a dyn MlmeClient>,
+
+    scan_type: Cell<ScanType>,
+    scan_channels: Cell<u32>,
+    scan_duration: Cell<u8>,
+    /// The channel being scanned.
+    scan_channel: Cell<u8>,
+    /// The channel to return to after a scan.
+    home_channel: Cell<u8>,
+    energy: MapCell<[Option<u8>; NUM_CHANNELS]>,
+    pans: MapCell<[Option<PanDescriptor>; MAX_PAN_DESCRIPTORS]>,
+
+    /// The PAN and coordinator this device is associating or associated with.
+    parent: OptionalCell<(PanID, MacAddress)>,
+    associated: Cell<bool>,
+
+    /// The PAN this device is the coordinator of.
+    coordinator: OptionalCell<Coordinator>,
+    devices: MapCell<[Option<AssociatedDevice>; MAX_ASSOCIATED_DEVICES]>,
+}
+
+impl<'a, R: radio::RadioConfig, A: time::Alarm<'a>> MacManagement<'a, R, A> {
+    pub fn new(
+        mac: &'a dyn MacDevice<'a>,
+        radio: &'a R,
+        alarm: &'a A,
+        tx_buf: &'static mut [u8],
+    ) -> MacManagement<'a, R, A> {
+        MacManagement {
+            mac,
+            radio,
+            alarm,
+            tx_buf: TakeCell::new(tx_buf),
+            tx: Cell::new(Tx::None),
+            state: Cell::new(State::Idle),
+            client: OptionalCell::empty(),
+            scan_type: Cell::new(ScanType::EnergyDetect),
+            scan_channels: Cell::new(0),
+            scan_duration: Cell::new(0),
+            scan_channel: Cell::new(0),
+            home_channel: Cell::new(0),
+            energy: MapCell::new([None; NUM_CHANNELS]),
+            pans: MapCell::new([None; MAX_PAN_DESCRIPTORS]),
+            parent: OptionalCell::empty(),
+            associated: Cell::new(false),
+            coordinator: OptionalCell::empty(),
+            devices: MapCell::new([None; MAX_ASSOCIATED_DEVICES]),
+        }
+    }
+
+    fn set_alarm_symbols(&self, symbols: u32) {
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_us(symbols * SYMBOL_US),
+        );
+    }
+
+    fn set_channel(&self, channel: u8) -> Result<(), ErrorCode> {
+        self.radio.set_channel(channel)?;
+        self.radio.config_commit();
+        Ok(())
+    }
+
+    /// Prepares and transmits a frame whose payload is `payload`.
+    fn send(
+        &self,
+        tx: Tx,
+        frame_type: FrameType,
+        dst: Option<(PanID, MacAddress)>,
+        src: Option<(PanID, MacAddress)>,
+        payload: &[u8],
+    ) -> Result<(), ErrorCode> {
+        let buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+        let ack_requested = match dst {
+            Some((_, MacAddress::Short(BROADCAST))) | None => false,
+            Some(_) => true,
+        };
+        let mut frame = match self
+            .mac
+            .prepare_frame(buf, frame_type, dst, src, ack_requested)
+        {
+            Ok(frame) => frame,
+            Err(buf) => {
+                self.tx_buf.replace(buf);
+                return Err(ErrorCode::FAIL);
+            }
+        };
+        if let Err(e) = frame.append_payload(payload) {
+            self.tx_buf.replace(frame.into_buf());
+            return Err(e);
+        }
+        match self.mac.transmit(frame) {
+            Ok(()) => {
+                self.tx.set(tx);
+                Ok(())
+            }
+            Err((e, buf)) => {
+                self.tx_buf.replace(buf);
+                Err(e)
+            }
+        }
+    }
+
+    fn own_long_address(&self) -> MacAddress {
+        MacAddress::Long(self.mac.get_address_long())
+    }
+
+    // Scanning
+
+    /// Moves on to the next channel to scan, or finishes the scan.
+    fn scan_next_channel(&self) {
+        let channels = self.scan_channels.get();
+        let next = (self.scan_channel.get() + 1..=MAX_CHANNEL).find(|ch| channels & (1 << ch) != 0);
+        let channel = match next {
+            Some(channel) => channel,
+            None => {
+                self.finish_scan(Ok(()));
+                return;
+            }
+        };
+        self.scan_channel.set(channel);
+        if let Err(e) = self.set_channel(channel) {
+            self.finish_scan(Err(e));
+            return;
+        }
+
+        let duration_us =
+            BASE_SUPERFRAME_SYMBOLS * ((1 << self.scan_duration.get()) + 1) * SYMBOL_US;
+        match self.scan_type.get() {
+            ScanType::EnergyDetect => {
+                self.state.set(State::EnergyDetecting);
+                if let Err(e) = self.radio.energy_detect(duration_us) {
+                    self.finish_scan(Err(e));
+                }
+            }
+            ScanType::Active | ScanType::Passive => {
+                self.state.set(State::Listening);
+                if self.scan_type.get() == ScanType::Active {
+                    // A channel where the request cannot be sent is still
+                    // listened on, in case beacons are heard anyway
+                    let _ = self.send(
+                        Tx::Device,
+                        FrameType::MACCommand,
+                        Some((BROADCAST, MacAddress::Short(BROADCAST))),
+                        None,
+                        &[command::BEACON_REQUEST],
+                    );
+                }
+                self.alarm
+                    .set_alarm(self.alarm.now(), self.alarm.ticks_from_us(duration_us));
+            }
+        }
+    }
+
+    fn finish_scan(&self, result: Result<(), ErrorCode>) {
+        self.state.set(State::Idle);
+        let _ = self.set_channel(self.home_channel.get());
+        self.client.map(|client| client.scan_done(result));
+    }
+
+    fn record_beacon(&self, header: &Header, payload: &[u8]) {
+        let (pan, coordinator) = match (header.src_pan, header.src_addr) {
+            (Some(pan), Some(addr)) => (pan, addr),
+            _ => return,
+        };
+        if payload.len() < 2 {
+            return;
+        }
+        let descriptor = PanDescriptor {
+            channel: self.scan_channel.get(),
+            pan: pan,
+            coordinator: coordinator,
+            superframe_spec: u16::from_le_bytes([payload[0], payload[1]]),
+        };
+        self.pans.map(|pans| {
+            let known = pans.iter().flatten().any(|known| {
+                known.channel == descriptor.channel
+                    && known.pan == descriptor.pan
+                    && known.coordinator == descriptor.coordinator
+            });
+            if !known {
+                if let Some(slot) = pans.iter_mut().find(|slot| slot.is_none()) {
+                    *slot = Some(descriptor);
+                }
+            }
+        });
+    }
+
+    // Association, as a device
+
+    fn poll_for_response(&self) {
+        let result = self
+            .parent
+            .map_or(Err(ErrorCode::FAIL), |(pan, coordinator)| {
+                self.send(
+                    Tx::Device,
+                    FrameType::MACCommand,
+                    Some((*pan, *coordinator)),
+                    Some((*pan, self.own_long_address())),
+                    &[command::DATA_REQUEST],
+                )
+            });
+        match result {
+            Ok(()) => self.state.set(State::Polling),
+            Err(_) => self.finish_association(AssociationStatus::ChannelAccessFailure, BROADCAST),
+        }
+    }
+
+    /// Whether a frame comes from the coordinator this device is associating
+    /// or associated with. A coordinator found by its short address still
+    /// sends association responses and disassociation notifications from
+    /// its extended address (IEEE 802.15.4-2006, 7.3.2 and 7.3.3), so those
+    /// are accepted from any extended address in its PAN.
+    fn from_parent(&self, header: &Header) -> bool {
+        self.parent
+            .map_or(false, |(pan, parent)| match (header.src_addr, *parent) {
+                (Some(src), parent) if src == parent => true,
+                (Some(MacAddress::Long(_)), MacAddress::Short(_)) => header.src_pan == Some(*pan),
+                _ => false,
+            })
+    }
+
+    fn finish_association(&self, status: AssociationStatus, short_addr: u16) {
+        self.state.set(State::Idle);
+        if status == AssociationStatus::Success {
+            self.associated.set(true);
+            self.mac.set_address(short_addr);
+            self.mac.config_commit();
+        } else {
+            self.parent.clear();
+        }
+        self.client
+            .map(|client| client.associate_done(status, short_addr));
+    }
+
+    fn leave(&self, reason: DisassociationReason) {
+        self.associated.set(false);
+        self.parent.clear();
+        self.mac.set_address(BROADCAST);
+        self.mac.set_pan(BROADCAST);
+        self.mac.config_commit();
+        self.client.map(|client| client.disassociated(reason));
+    }
+
+    // Association, as a coordinator
+
+    fn send_beacon(&self, coordinator: Coordinator) {
+        let mut superframe_spec = SUPERFRAME_NONBEACON | SUPERFRAME_PAN_COORDINATOR;
+        if coordinator.association_permit {
+            superframe_spec |= SUPERFRAME_ASSOCIATION_PERMIT;
+        }
+        let spec = superframe_spec.to_le_bytes();
+        // Superframe specification, then empty GTS and pending address fields
+        let payload = [spec[0], spec[1], 0, 0];
+        let _ = self.send(
+            Tx::Beacon,
+            FrameType::Beacon,
+            None,
+            Some((coordinator.pan, MacAddress::Short(self.mac.get_address()))),
+            &payload,
+        );
+    }
+
+    fn association_requested(&self, coordinator: Coordinator, addr_long: [u8; 8]) {
+        self.devices.map(|devices| {
+            if let Some(device) = devices
+                .iter_mut()
+                .flatten()
+                .find(|device| device.addr_long == addr_long)
+            {
+                // The device lost its association, give it the same address
+                device.pending = Some(AssociationStatus::Success);
+                return;
+            }
+            let status = if coordinator.association_permit {
+                AssociationStatus::Success
+            } else {
+                AssociationStatus::PanAccessDenied
+            };
+            // A device that cannot be given a slot to keep its response in
+            // is ignored, and times out waiting for the response
+            if let Some((index, slot)) = devices
+                .iter_mut()
+                .enumerate()
+                .find(|(_, slot)| slot.is_none())
+            {
+                *slot = Some(AssociatedDevice {
+                    addr_long: addr_long,
+                    short_addr: index as u16 + 1,
+                    pending: Some(status),
+                });
+            }
+        });
+    }
+
+    fn data_requested(&self, coordinator: Coordinator, addr_long: [u8; 8]) {
+        let pending = self.devices.and_then(|devices| {
+            devices
+                .iter()
+                .enumerate()
+                .find_map(|(index, device)| match device {
+                    Some(device) if device.addr_long == addr_long => {
+                        device.pending.map(|status| (index, *device, status))
+                    }
+                    _ => None,
+                })
+        });
+        if let Some((index, device, status)) = pending {
+            let short_addr = match status {
+                AssociationStatus::Success => device.short_addr,
+                _ => BROADCAST,
+            };
+            let short_addr = short_addr.to_le_bytes();
+            let _ = self.send(
+                Tx::AssociationResponse(index),
+                FrameType::MACCommand,
+                Some((coordinator.pan, MacAddress::Long(addr_long))),
+                Some((coordinator.pan, self.own_long_address())),
+                &[
+                    command::ASSOCIATION_RESPONSE,
+                    short_addr[0],
+                    short_addr[1],
+                    status as u8,
+                ],
+            );
+        }
+    }
+
+    fn response_sent(&self, index: usize, result: Result<(), ErrorCode>) {
+        if result.is_err() {
+            // Sent again when the device polls again
+            return;
+        }
+        let joined = self.devices.and_then(|devices| {
+            let device = devices[index].as_mut()?;
+            match device.pending.take() {
+                Some(AssociationStatus::Success) => Some((device.short_addr, device.addr_long)),
+                _ => {
+                    devices[index] = None;
+                    None
+                }
+            }
+        });
+        if let Some((short_addr, addr_long)) = joined {
+            self.client
+                .map(|client| client.device_associated(short_addr, addr_long));
+        }
+    }
+
+    fn device_left(&self, addr_long: [u8; 8]) {
+        let short_addr = self.devices.and_then(|devices| {
+            let slot = devices
+                .iter_mut()
+                .find(|slot| slot.map_or(false, |device| device.addr_long == addr_long))?;
+            slot.take().map(|device| device.short_addr)
+        });
+        if let Some(short_addr) = short_addr {
+            self.client
+                .map(|client| client.device_disassociated(short_addr));
+        }
+    }
+
+    fn command_received(&self, header: &Header, payload: &[u8]) {
+        let src_long = match header.src_addr {
+            Some(MacAddress::Long(addr)) => Some(addr),
+            _ => None,
+        };
+        let coordinator = self.coordinator.extract();
+        match (payload[0], coordinator, src_long) {
+            (command::BEACON_REQUEST, Some(coordinator), _) => self.send_beacon(coordinator),
+            (command::ASSOCIATION_REQUEST, Some(coordinator), Some(addr_long)) => {
+                self.association_requested(coordinator, addr_long)
+            }
+            (command::DATA_REQUEST, Some(coordinator), Some(addr_long)) => {
+                self.data_requested(coordinator, addr_long)
+            }
+            (command::DISASSOCIATION_NOTIFICATION, Some(_), Some(addr_long)) => {
+                self.device_left(addr_long)
+            }
+            (command::ASSOCIATION_RESPONSE, None, _) => {
+                let awaited = match self.state.get() {
+                    State::WaitingForResponse | State::Polling | State::ReceivingResponse => true,
+                    _ => false,
+                };
+                if awaited && self.from_parent(header) && payload.len() >= 4 {
+                    let _ = self.alarm.disarm();
+                    let short_addr = u16::from_le_bytes([payload[1], payload[2]]);
+                    self.finish_association(AssociationStatus::from_u8(payload[3]), short_addr);
+                }
+            }
+            (command::DISASSOCIATION_NOTIFICATION, None, _) => {
+                if self.associated.get() && self.from_parent(header) {
+                    self.leave(DisassociationReason::CoordinatorRequest);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<'a, R: radio::RadioConfig, A: time::Alarm<'a>> Mlme<'a> for MacManagement<'a, R, A> {
+    fn set_client(&self, client: &'a dyn MlmeClient) {
+        self.client.set(client);
+    }
+
+    fn scan(&self, scan_type: ScanType, channels: u32, duration: u8) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let valid_channels = ((1 << NUM_CHANNELS) - 1) << MIN_CHANNEL;
+        if channels & valid_channels == 0
+            || channels & !valid_channels != 0
+            || duration > MAX_SCAN_DURATION
+        {
+            return Err(ErrorCode::INVAL);
+        }
+        self.scan_type.set(scan_type);
+        self.scan_channels.set(channels);
+        self.scan_duration.set(duration);
+        self.scan_channel.set(MIN_CHANNEL - 1);
+        self.home_channel.set(self.radio.get_channel());
+        self.energy.map(|energy| *energy = [None; NUM_CHANNELS]);
+        self.pans.map(|pans| *pans = [None; MAX_PAN_DESCRIPTORS]);
+        self.scan_next_channel();
+        Ok(())
+    }
+
+    fn energy_level(&self, channel: u8) -> Option<u8> {
+        if channel < MIN_CHANNEL || channel > MAX_CHANNEL {
+            return None;
+        }
+        self.energy
+            .and_then(|energy| energy[(channel - MIN_CHANNEL) as usize])
+    }
+
+    fn pan_descriptor(&self, index: usize) -> Option<PanDescriptor> {
+        self.pans
+            .and_then(|pans| pans.get(index).copied().flatten())
+    }
+
+    fn associate(
+        &self,
+        channel: u8,
+        pan: PanID,
+        coordinator: MacAddress,
+        capability: u8,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.associated.get() || self.coordinator.is_some() {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.set_channel(channel)?;
+        self.mac.set_pan(pan);
+        self.mac.config_commit();
+        self.send(
+            Tx::Device,
+            FrameType::MACCommand,
+            Some((pan, coordinator)),
+            Some((BROADCAST, self.own_long_address())),
+            &[command::ASSOCIATION_REQUEST, capability],
+        )?;
+        self.parent.set((pan, coordinator));
+        self.state.set(State::RequestingAssociation);
+        Ok(())
+    }
+
+    fn disassociate(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if !self.associated.get() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.parent
+            .map_or(Err(ErrorCode::FAIL), |(pan, coordinator)| {
+                self.send(
+                    Tx::Device,
+                    FrameType::MACCommand,
+                    Some((*pan, *coordinator)),
+                    Some((*pan, self.own_long_address())),
+                    &[
+                        command::DISASSOCIATION_NOTIFICATION,
+                        DisassociationReason::DeviceRequest as u8,
+                    ],
+                )
+            })?;
+        self.state.set(State::Disassociating);
+        Ok(())
+    }
+
+    fn start_coordinator(
+        &self,
+        pan: PanID,
+        channel: u8,
+        association_permit: bool,
+    ) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        if self.associated.get() {
+            return Err(ErrorCode::ALREADY);
+        }
+        if pan == BROADCAST {
+            return Err(ErrorCode::INVAL);
+        }
+        self.radio.set_channel(channel)?;
+        self.mac.set_pan(pan);
+        self.mac.set_address(COORDINATOR_SHORT_ADDRESS);
+        self.mac.config_commit();
+        if self.coordinator.map_or(false, |current| current.pan != pan) {
+            self.devices
+                .map(|devices| *devices = [None; MAX_ASSOCIATED_DEVICES]);
+        }
+        self.coordinator.set(Coordinator {
+            pan,
+            association_permit,
+        });
+        Ok(())
+    }
+
+    fn stop_coordinator(&self) {
+        self.coordinator.clear();
+        self.devices
+            .map(|devices| *devices = [None; MAX_ASSOCIATED_DEVICES]);
+    }
+}
+
+impl<'a, R: radio::RadioConfig, A: time::Alarm<'a>> TxClient for MacManagement<'a, R, A> {
+    fn send_done(&self, spi_buf: &'static mut [u8], _acked: bool, result: Result<(), ErrorCode>) {
+        self.tx_buf.replace(spi_buf);
+        match self.tx.replace(Tx::None) {
+            Tx::Device => match self.state.get() {
+                State::RequestingAssociation => {
+                    if result.is_ok() {
+                        self.state.set(State::WaitingForResponse);
+                        self.set_alarm_symbols(RESPONSE_WAIT_SUPERFRAMES * BASE_SUPERFRAME_SYMBOLS);
+                    } else {
+                        self.finish_association(AssociationStatus::ChannelAccessFailure, BROADCAST);
+                    }
+                }
+                State::Polling => {
+                    if result.is_ok() {
+                        self.state.set(State::ReceivingResponse);
+                        self.set_alarm_symbols(MAX_FRAME_TOTAL_WAIT_SYMBOLS);
+                    } else {
+                        self.finish_association(AssociationStatus::ChannelAccessFailure, BROADCAST);
+                    }
+                }
+                State::Disassociating => {
+                    // The device leaves even if its coordinator did not hear
+                    self.state.set(State::Idle);
+                    self.leave(DisassociationReason::DeviceRequest);
+                }
+                _ => {}
+            },
+            Tx::AssociationResponse(index) => self.response_sent(index, result),
+            Tx::Beacon | Tx::None => {}
+        }
+    }
+}
+
+impl<'a, R: radio::RadioConfig, A: time::Alarm<'a>> RxClient for MacManagement<'a, R, A> {
+    fn receive<'b>(&self, buf: &'b [u8], header: Header<'b>, data_offset: usize, data_len: usize) {
+        let payload = match buf.get(data_offset..data_offset + data_len) {
+            Some(payload) if !payload.is_empty() => payload,
+            _ => return,
+        };
+        match header.frame_type {
+            FrameType::Beacon => {
+                if self.state.get() == State::Listening {
+                    self.record_beacon(&header, payload);
+                }
+            }
+            FrameType::MACCommand => self.command_received(&header, payload),
+            _ => {}
+        }
+    }
+}
+
+impl<'a, R: radio::RadioConfig, A: time::Alarm<'a>> radio::EnergyDetectClient
+    for MacManagement<'a, R, A>
+{
+    fn energy_detect_done(&self, result: Result<u8, ErrorCode>) {
+        if self.state.get() != State::EnergyDetecting {
+            return;
+        }
+        match result {
+            Ok(level) => {
+                let index = (self.scan_channel.get() - MIN_CHANNEL) as usize;
+                self.energy.map(|energy| energy[index] = Some(level));
+                self.scan_next_channel();
+            }
+            Err(e) => self.finish_scan(Err(e)),
+        }
+    }
+}
+
+impl<'a, R: radio::RadioConfig, A: time::Alarm<'a>> time::AlarmClient for MacManagement<'a, R, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Listening => self.scan_next_channel(),
+            State::WaitingForResponse => self.poll_for_response(),
+            State::ReceivingResponse => {
+                self.finish_association(AssociationStatus::NoData, BROADCAST)
+            }
+            _ => {}
+        }
+    }
+}
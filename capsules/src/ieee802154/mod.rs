@@ -1,8 +1,11 @@
 //! Support for IEEE 802.15.4.
 
+pub mod csma;
 pub mod device;
 pub mod framer;
 pub mod mac;
+pub mod mlme;
+pub mod sim_radio;
 pub mod virtual_mac;
 pub mod xmac;
 
@@ -0,0 +1,654 @@
+//! A simulated 802.15.4 radio, for running the MAC and network layers on a
+//! host without radio hardware.
+//!
+//! Any number of `SimRadio`s, up to `MAX_RADIOS`, share a `SimMedium`.
+//! Each frame a radio transmits is copied onto the medium, and once it has
+//! been on the air for as long as it would take at 250 kbit/s it is
+//! delivered to every other radio that is on, tuned to the same channel and
+//! in range of the sender. How the medium treats frames is set with
+//! `LinkParams`:
+//!
+//! - Each receiver loses a frame with probability `loss_percent`.
+//! - Frames are delivered `delay_us` after they leave the air, plus a random
+//!   jitter of up to `jitter_us`. Frames sent less than `jitter_us` apart may
+//!   arrive out of order.
+//!
+//! The random numbers come from a generator seeded with `set_seed`, so a
+//! simulation runs the same way every time. Which radios can hear each other
+//! is set with `set_in_range`; by default, all of them can.
+//!
+//! Radios measure the channel as busy while another radio in range is
+//! transmitting on it, so a MAC that does CSMA-CA backs off. The simulated
+//! radios do no address filtering and send no acknowledgements, so they are
+//! best paired with `capsules::ieee802154::csma::CsmaMac`.
+//!
+//! Tests can also make a channel busy with `set_noise`, lose the next frames
+//! a radio sends with `lose_next`, and take energy detection away from a
+//! radio with `SimRadio::set_energy_detection`.
+//!
+//! All callbacks, including those for `start`, `stop` and `config_commit`,
+//! are made from the medium's alarm rather than from within the call that
+//! causes them, as they would be with real hardware.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let medium = static_init!(
+//!     capsules::ieee802154::sim_radio::SimMedium<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::sim_radio::SimMedium::new(medium_alarm));
+//! medium_alarm.set_alarm_client(medium);
+//! medium.set_params(LinkParams { loss_percent: 10, delay_us: 0, jitter_us: 0 });
+//!
+//! let radio = static_init!(
+//!     capsules::ieee802154::sim_radio::SimRadio<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::ieee802154::sim_radio::SimRadio::new(medium));
+//! medium.add_radio(radio)?;
+//!
+//! // The radio can now back a CsmaMac, as a hardware radio would
+//! ```
+
+use core::cell::Cell;
+use kernel::collections::list::{List, ListLink, ListNode};
+use kernel::hil::radio;
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+/// How many radios can share a medium.
+pub const MAX_RADIOS: usize = 32;
+
+/// How many frames can be on the medium, sent but not yet delivered, at
+/// once.
+pub const MAX_IN_FLIGHT: usize = 8;
+
+// The O-QPSK PHY sends 250 kbit/s, so each byte takes 32 us
+const BYTE_US: u32 = 32;
+// The synchronization header and PHY header are sent before each frame
+const PHY_HEADER_LEN: u32 = 6;
+// The energy measured on a channel a frame is being sent on
+const BUSY_ENERGY: u8 = 0xff;
+
+/// How the medium treats frames.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub struct LinkParams {
+    /// The chance that each receiver loses a frame, in percent.
+    pub loss_percent: u8,
+    /// How long after a frame leaves the air it is delivered, in
+    /// microseconds.
+    pub delay_us: u32,
+    /// The most extra delay, in microseconds, chosen at random for each
+    /// frame.
+    pub jitter_us: u32,
+}
+
+/// A frame on the medium.
+struct Transmission<T: Ticks> {
+    sender: usize,
+    channel: u8,
+    /// When the frame started and finished going out on the air.
+    start: T,
+    end: T,
+    /// When the frame is delivered to receivers.
+    due: T,
+    /// The length of the frame, including the `PSDU_OFFSET` bytes before it.
+    len: usize,
+    /// Whether the frame is lost for all receivers.
+    lost: bool,
+    buf: [u8; radio::MAX_BUF_SIZE],
+}
+
+/// Something for the medium to do once its time has come.
+enum Event<'a, A: time::Alarm<'a>> {
+    Deliver(usize),
+    TransmitDone(&'a SimRadio<'a, A>),
+    EnergyDetectDone(&'a SimRadio<'a, A>),
+    ConfigDone(&'a SimRadio<'a, A>),
+    PowerChanged(&'a SimRadio<'a, A>),
+}
+
+pub struct SimMedium<'a, A: time::Alarm<'a>> {
+    alarm: &'a A,
+    radios: List<'a, SimRadio<'a, A>>,
+    num_radios: Cell<usize>,
+    params: Cell<LinkParams>,
+    seed: Cell<u32>,
+    /// The noisy channels, one bit per channel number.
+    noise: Cell<u32>,
+    frames: [MapCell<Transmission<A::Ticks>>; MAX_IN_FLIGHT],
+}
+
+impl<'a, A: time::Alarm<'a>> SimMedium<'a, A> {
+    pub fn new(alarm: &'a A) -> SimMedium<'a, A> {
+        SimMedium {
+            alarm: alarm,
+            radios: List::new(),
+            num_radios: Cell::new(0),
+            params: Cell::new(LinkParams::default()),
+            seed: Cell::new(1),
+            noise: Cell::new(0),
+            frames: core::array::from_fn(|_| MapCell::empty()),
+        }
+    }
+
+    /// Connects a radio to the medium. Returns NOMEM if `MAX_RADIOS` radios
+    /// are already connected.
+    pub fn add_radio(&self, radio: &'a SimRadio<'a, A>) -> Result<(), ErrorCode> {
+        let id = self.num_radios.get();
+        if id >= MAX_RADIOS {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.num_radios.set(id + 1);
+        radio.id.set(id);
+        self.radios.push_head(radio);
+        Ok(())
+    }
+
+    pub fn params(&self) -> LinkParams {
+        self.params.get()
+    }
+
+    /// Changes how frames are treated, including those already sent.
+    /// Returns INVAL if `loss_percent` is above 100.
+    pub fn set_params(&self, params: LinkParams) -> Result<(), ErrorCode> {
+        if params.loss_percent > 100 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.params.set(params);
+        Ok(())
+    }
+
+    /// Seeds the generator that decides which frames are lost and how much
+    /// they are delayed.
+    pub fn set_seed(&self, seed: u32) {
+        // Xorshift never leaves zero
+        self.seed.set(if seed == 0 { 1 } else { seed });
+    }
+
+    /// Sets whether radios `a` and `b` can hear each other.
+    pub fn set_in_range(&self, a: &SimRadio<'a, A>, b: &SimRadio<'a, A>, in_range: bool) {
+        a.set_in_range(b.id.get(), in_range);
+        b.set_in_range(a.id.get(), in_range);
+    }
+
+    /// Sets whether `channel` is noisy. Radios measure a noisy channel as
+    /// busy, whether or not anyone is sending on it.
+    pub fn set_noise(&self, channel: u8, noisy: bool) {
+        if noisy {
+            self.noise.set(self.noise.get() | 1 << channel);
+        } else {
+            self.noise.set(self.noise.get() & !(1 << channel));
+        }
+    }
+
+    /// Loses the next `count` frames `radio` sends, so that no radio
+    /// receives them. They still keep the channel busy while on the air.
+    pub fn lose_next(&self, radio: &SimRadio<'a, A>, count: usize) {
+        radio.lose_next.set(count);
+    }
+
+    fn random(&self) -> u32 {
+        // Xorshift32, from Marsaglia, "Xorshift RNGs"
+        let mut x = self.seed.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.seed.set(x);
+        x
+    }
+
+    fn transmit(
+        &self,
+        radio: &SimRadio<'a, A>,
+        buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let len = radio::PSDU_OFFSET + frame_len;
+        if len > buf.len() || frame_len > radio::MAX_FRAME_SIZE {
+            return Err((ErrorCode::SIZE, buf));
+        }
+        let slot = match self.frames.iter().position(|frame| frame.is_none()) {
+            Some(slot) => slot,
+            None => return Err((ErrorCode::BUSY, buf)),
+        };
+
+        let params = self.params.get();
+        let start = self.alarm.now();
+        let air_time = (PHY_HEADER_LEN + frame_len as u32) * BYTE_US;
+        let end = start.wrapping_add(self.alarm.ticks_from_us(air_time));
+        let jitter = if params.jitter_us > 0 {
+            self.random() % (params.jitter_us + 1)
+        } else {
+            0
+        };
+        let due = end.wrapping_add(self.alarm.ticks_from_us(params.delay_us + jitter));
+        let lost = radio.lose_next.get() > 0;
+        if lost {
+            radio.lose_next.set(radio.lose_next.get() - 1);
+        }
+
+        let mut transmission = Transmission {
+            sender: radio.id.get(),
+            channel: radio.channel.get(),
+            start: start,
+            end: end,
+            due: due,
+            len: len,
+            lost: lost,
+            buf: [0; radio::MAX_BUF_SIZE],
+        };
+        transmission.buf[..len].copy_from_slice(&buf[..len]);
+        self.frames[slot].put(transmission);
+
+        radio.tx_buf.replace(buf);
+        radio.tx_done.set(Some(end));
+        radio.transmissions.set(radio.transmissions.get() + 1);
+        self.schedule();
+        Ok(())
+    }
+
+    /// Returns whether `radio`'s channel is noisy, or a radio other than
+    /// `radio` and in its range is sending on it at `time`.
+    fn channel_busy(&self, radio: &SimRadio<'a, A>, time: A::Ticks) -> bool {
+        if self.noise.get() & 1 << radio.channel.get() != 0 {
+            return true;
+        }
+        self.frames.iter().any(|frame| {
+            frame
+                .map(|frame| {
+                    frame.sender != radio.id.get()
+                        && frame.channel == radio.channel.get()
+                        && radio.in_range_of(frame.sender)
+                        && time.within_range(frame.start, frame.end)
+                })
+                .unwrap_or(false)
+        })
+    }
+
+    fn deliver(&self, slot: usize) {
+        let frame = match self.frames[slot].take() {
+            Some(frame) if !frame.lost => frame,
+            _ => return,
+        };
+        let loss_percent = self.params.get().loss_percent as u32;
+        for radio in self.radios.iter() {
+            if radio.id.get() == frame.sender
+                || !radio.on.get()
+                || radio.channel.get() != frame.channel
+                || !radio.in_range_of(frame.sender)
+            {
+                continue;
+            }
+            if loss_percent > 0 && self.random() % 100 < loss_percent {
+                continue;
+            }
+            // Frames that arrive while the receiver has no buffer are missed,
+            // as they would be by real hardware
+            if let Some(buf) = radio.rx_buf.take() {
+                buf[..frame.len].copy_from_slice(&frame.buf[..frame.len]);
+                let frame_len = frame.len - radio::PSDU_OFFSET;
+                match radio.rx_client.extract() {
+                    Some(client) => client.receive(buf, frame_len, true, Ok(())),
+                    None => {
+                        radio.rx_buf.replace(buf);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the event that has been due for the longest, if any.
+    fn next_event(&self) -> Option<(Event<'a, A>, A::Ticks)> {
+        let frames = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, frame)| frame.map(|frame| (Event::Deliver(slot), frame.due)));
+        let radios = self.radios.iter().flat_map(|radio| {
+            let now = self.alarm.now();
+            let power = radio.power_pending.get().then(|| now);
+            let config = radio.config_pending.get().then(|| now);
+            [
+                radio.tx_done.get().map(|t| (Event::TransmitDone(radio), t)),
+                radio
+                    .ed_done
+                    .get()
+                    .map(|t| (Event::EnergyDetectDone(radio), t)),
+                config.map(|t| (Event::ConfigDone(radio), t)),
+                power.map(|t| (Event::PowerChanged(radio), t)),
+            ]
+            .into_iter()
+            .flatten()
+        });
+        // Measured from a time long enough ago that everything due has
+        // passed it, the event due the longest has the smallest offset
+        let reference = self.alarm.now().wrapping_sub(A::Ticks::half_max_value());
+        frames
+            .chain(radios)
+            .min_by_key(|(_, due)| due.wrapping_sub(reference))
+    }
+
+    /// Sets the alarm for the next event, if there is one.
+    fn schedule(&self) {
+        match self.next_event() {
+            Some((_, due)) => {
+                let now = self.alarm.now();
+                let dt = if self.is_due(due, now) {
+                    A::Ticks::from(0)
+                } else {
+                    due.wrapping_sub(now)
+                };
+                self.alarm.set_alarm(now, dt);
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    fn is_due(&self, due: A::Ticks, now: A::Ticks) -> bool {
+        now.wrapping_sub(due) <= A::Ticks::half_max_value()
+    }
+
+    fn handle(&self, event: Event<'a, A>) {
+        match event {
+            Event::Deliver(slot) => self.deliver(slot),
+            Event::TransmitDone(radio) => {
+                radio.tx_done.set(None);
+                if let Some(buf) = radio.tx_buf.take() {
+                    radio
+                        .tx_client
+                        .map(move |client| client.send_done(buf, false, Ok(())));
+                }
+            }
+            Event::EnergyDetectDone(radio) => {
+                radio.ed_done.set(None);
+                radio
+                    .energy_detections
+                    .set(radio.energy_detections.get() + 1);
+                let busy = radio.ed_busy.get() || self.channel_busy(radio, self.alarm.now());
+                let energy = if busy { BUSY_ENERGY } else { 0 };
+                radio
+                    .ed_client
+                    .map(|client| client.energy_detect_done(Ok(energy)));
+            }
+            Event::ConfigDone(radio) => {
+                radio.config_pending.set(false);
+                radio.config_client.map(|client| client.config_done(Ok(())));
+            }
+            Event::PowerChanged(radio) => {
+                radio.power_pending.set(false);
+                let on = radio.on.get();
+                radio.power_client.map(|client| client.changed(on));
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for SimMedium<'a, A> {
+    fn alarm(&self) {
+        while let Some((event, due)) = self.next_event() {
+            if !self.is_due(due, self.alarm.now()) {
+                break;
+            }
+            self.handle(event);
+        }
+        self.schedule();
+    }
+}
+
+pub struct SimRadio<'a, A: time::Alarm<'a>> {
+    medium: &'a SimMedium<'a, A>,
+    next: ListLink<'a, SimRadio<'a, A>>,
+    id: Cell<usize>,
+    /// The radios this one can hear, by id.
+    in_range: Cell<u32>,
+
+    on: Cell<bool>,
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+    channel: Cell<u8>,
+    tx_power: Cell<i8>,
+
+    tx_client: OptionalCell<&'static dyn radio::TxClient>,
+    rx_client: OptionalCell<&'static dyn radio::RxClient>,
+    config_client: OptionalCell<&'static dyn radio::ConfigClient>,
+    power_client: OptionalCell<&'static dyn radio::PowerClient>,
+    ed_client: OptionalCell<&'static dyn radio::EnergyDetectClient>,
+
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_buf: TakeCell<'static, [u8]>,
+
+    /// When the callbacks for the current operations are due.
+    tx_done: Cell<Option<A::Ticks>>,
+    ed_done: Cell<Option<A::Ticks>>,
+    /// Whether the channel was busy when energy detection started.
+    ed_busy: Cell<bool>,
+    ed_supported: Cell<bool>,
+    config_pending: Cell<bool>,
+    power_pending: Cell<bool>,
+
+    /// How many of the next frames sent are lost.
+    lose_next: Cell<usize>,
+    transmissions: Cell<usize>,
+    energy_detections: Cell<usize>,
+}
+
+impl<'a, A: time::Alarm<'a>> SimRadio<'a, A> {
+    pub fn new(medium: &'a SimMedium<'a, A>) -> SimRadio<'a, A> {
+        SimRadio {
+            medium: medium,
+            next: ListLink::empty(),
+            id: Cell::new(0),
+            in_range: Cell::new(u32::MAX),
+            on: Cell::new(false),
+            addr: Cell::new(0),
+            addr_long: Cell::new([0; 8]),
+            pan: Cell::new(0),
+            channel: Cell::new(26),
+            tx_power: Cell::new(0),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
+            config_client: OptionalCell::empty(),
+            power_client: OptionalCell::empty(),
+            ed_client: OptionalCell::empty(),
+            tx_buf: TakeCell::empty(),
+            rx_buf: TakeCell::empty(),
+            tx_done: Cell::new(None),
+            ed_done: Cell::new(None),
+            ed_busy: Cell::new(false),
+            ed_supported: Cell::new(true),
+            config_pending: Cell::new(false),
+            power_pending: Cell::new(false),
+            lose_next: Cell::new(0),
+            transmissions: Cell::new(0),
+            energy_detections: Cell::new(0),
+        }
+    }
+
+    /// Sets whether the radio supports energy detection. Without it,
+    /// `energy_detect` returns NOSUPPORT, as it does on radios that lack it.
+    pub fn set_energy_detection(&self, supported: bool) {
+        self.ed_supported.set(supported);
+    }
+
+    /// The number of frames the radio has sent.
+    pub fn transmissions(&self) -> usize {
+        self.transmissions.get()
+    }
+
+    /// The number of energy detections the radio has finished.
+    pub fn energy_detections(&self) -> usize {
+        self.energy_detections.get()
+    }
+
+    fn in_range_of(&self, id: usize) -> bool {
+        self.in_range.get() & (1 << id) != 0
+    }
+
+    fn set_in_range(&self, id: usize, in_range: bool) {
+        if in_range {
+            self.in_range.set(self.in_range.get() | 1 << id);
+        } else {
+            self.in_range.set(self.in_range.get() & !(1 << id));
+        }
+    }
+
+    fn set_power(&self, on: bool) {
+        self.on.set(on);
+        self.power_pending.set(true);
+        self.medium.schedule();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> ListNode<'a, SimRadio<'a, A>> for SimRadio<'a, A> {
+    fn next(&'a self) -> &'a ListLink<'a, SimRadio<'a, A>> {
+        &self.next
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> radio::RadioConfig for SimRadio<'a, A> {
+    fn initialize(
+        &self,
+        _spi_buf: &'static mut [u8],
+        _reg_write: &'static mut [u8],
+        _reg_read: &'static mut [u8],
+    ) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn reset(&self) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), ErrorCode> {
+        self.set_power(true);
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ErrorCode> {
+        self.set_power(false);
+        Ok(())
+    }
+
+    fn is_on(&self) -> bool {
+        self.on.get()
+    }
+
+    fn busy(&self) -> bool {
+        self.tx_buf.is_some()
+    }
+
+    fn set_power_client(&self, client: &'static dyn radio::PowerClient) {
+        self.power_client.set(client);
+    }
+
+    fn config_commit(&self) {
+        self.config_pending.set(true);
+        self.medium.schedule();
+    }
+
+    fn set_config_client(&self, client: &'static dyn radio::ConfigClient) {
+        self.config_client.set(client);
+    }
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn get_tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    fn get_channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.addr.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.addr_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn set_tx_power(&self, power: i8) -> Result<(), ErrorCode> {
+        self.tx_power.set(power);
+        Ok(())
+    }
+
+    fn set_channel(&self, chan: u8) -> Result<(), ErrorCode> {
+        if !(11..=26).contains(&chan) {
+            return Err(ErrorCode::INVAL);
+        }
+        self.channel.set(chan);
+        Ok(())
+    }
+
+    fn energy_detect(&self, duration_us: u32) -> Result<(), ErrorCode> {
+        if !self.ed_supported.get() {
+            return Err(ErrorCode::NOSUPPORT);
+        }
+        if !self.on.get() {
+            return Err(ErrorCode::OFF);
+        }
+        if self.ed_done.get().is_some() || self.tx_buf.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        let now = self.medium.alarm.now();
+        self.ed_busy.set(self.medium.channel_busy(self, now));
+        self.ed_done.set(Some(
+            now.wrapping_add(self.medium.alarm.ticks_from_us(duration_us)),
+        ));
+        self.medium.schedule();
+        Ok(())
+    }
+
+    fn set_energy_detect_client(&self, client: &'static dyn radio::EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> radio::RadioData for SimRadio<'a, A> {
+    fn set_transmit_client(&self, client: &'static dyn radio::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'static dyn radio::RxClient, buf: &'static mut [u8]) {
+        self.rx_client.set(client);
+        self.rx_buf.replace(buf);
+    }
+
+    fn set_receive_buffer(&self, buf: &'static mut [u8]) {
+        self.rx_buf.replace(buf);
+    }
+
+    fn transmit(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.on.get() {
+            return Err((ErrorCode::OFF, buf));
+        }
+        if self.tx_buf.is_some() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        self.medium.transmit(self, buf, frame_len)
+    }
+}
@@ -28,8 +28,9 @@
 //! mux_mac.add_user(virtual_mac);
 //! ```
 
+use crate::ieee802154::mac::TxStats;
 use crate::ieee802154::{device, framer};
-use crate::net::ieee802154::{Header, KeyId, MacAddress, PanID, SecurityLevel};
+use crate::net::ieee802154::{FrameType, Header, KeyId, MacAddress, PanID, SecurityLevel};
 
 use core::cell::Cell;
 
@@ -283,6 +284,23 @@ impl<'a> device::MacDevice<'a> for MacUser<'a> {
             .prepare_data_frame(buf, dst_pan, dst_addr, src_pan, src_addr, security_needed)
     }
 
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        frame_type: FrameType,
+        dst: Option<(PanID, MacAddress)>,
+        src: Option<(PanID, MacAddress)>,
+        ack_requested: bool,
+    ) -> Result<framer::Frame, &'static mut [u8]> {
+        self.mux
+            .mac
+            .prepare_frame(buf, frame_type, dst, src, ack_requested)
+    }
+
+    fn last_tx_stats(&self) -> TxStats {
+        self.mux.mac.last_tx_stats()
+    }
+
     fn transmit(&self, frame: framer::Frame) -> Result<(), (ErrorCode, &'static mut [u8])> {
         // If the muxer is idle, immediately transmit the frame, otherwise
         // attempt to queue the transmission request. However, each MAC user can
@@ -78,7 +78,7 @@
 // Date: Nov 21 2017
 //
 
-use crate::ieee802154::mac::Mac;
+use crate::ieee802154::mac::{Mac, TxStats};
 use crate::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress, PanID};
 use core::cell::Cell;
 use kernel::hil::radio;
@@ -461,6 +461,11 @@ impl<'a, R: radio::Radio, A: Alarm<'a>> Mac for XMac<'a, R, A> {
 
         Ok(())
     }
+
+    fn last_tx_stats(&self) -> TxStats {
+        // Preambles are strobed until the receiver wakes, rather than retried
+        TxStats::default()
+    }
 }
 
 // Core of the XMAC protocol - when the timer fires, the protocol state
@@ -0,0 +1,602 @@
+//! Message-queue based inter-process communication.
+//!
+//! This capsule is an alternative to the kernel's shared-buffer IPC mechanism.
+//! Instead of sharing memory, processes exchange small messages that are
+//! copied into bounded per-process queues stored in each process's grant
+//! region. Requests sent to a service are tagged with a correlation ID, which
+//! the service echoes back when it responds so that a client can match
+//! responses to the requests it issued.
+//!
+//! Services and Permissions
+//! ------------------------
+//!
+//! A process becomes a service by registering itself (command 1). Services are
+//! identified by their package name. A client discovers a service by name
+//! (command 2), which returns a descriptor to use when sending requests.
+//!
+//! Clients may only discover and send requests to the services listed in the
+//! `IPC Services` element of their TBF header. A process without that header
+//! element cannot reach any service. To a client, a service it is not allowed
+//! to reach is indistinguishable from a service that does not exist.
+//!
+//! A service may only respond to requests it has received and not yet
+//! responded to. At most `QUEUE_LEN` requests can be awaiting a response at
+//! any time. A service that will not answer a request can drop it (command
+//! 6), and requests from clients that no longer exist are dropped
+//! automatically when the service needs room for a new request.
+//!
+//! Message Layout
+//! --------------
+//!
+//! Command 5 copies the next queued message into the read-write allowed
+//! buffer. All fields are little-endian.
+//!
+//! ```text
+//! Offset  Size  Field
+//!      0     4  Kind (0: request, 1: response)
+//!      4     4  Descriptor of the process that sent the message
+//!      8     4  Correlation ID
+//!     12     N  Payload
+//! ```
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let ipc_message = static_init!(
+//!     capsules::ipc_message::IpcMessage<Capability>,
+//!     capsules::ipc_message::IpcMessage::new(
+//!         board_kernel,
+//!         board_kernel.create_grant(capsules::ipc_message::DRIVER_NUM, &grant_cap),
+//!         Capability,
+//!     )
+//! );
+//! ```
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::process::{self, Process, ProcessId};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, Kernel};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::IpcMessage as usize;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// Name of the service to discover.
+    pub const SERVICE_NAME: usize = 0;
+    /// Payload of the next message to send.
+    pub const MESSAGE: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const RECEIVE: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Ids for upcalls
+mod upcall {
+    /// Called when a message is added to the process's queue. The arguments
+    /// are the kind of the message, the descriptor of the sender, and the
+    /// number of messages now queued.
+    pub const MESSAGE_RECEIVED: usize = 0;
+    /// The number of upcalls the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Number of messages that can be queued for each process.
+pub const QUEUE_LEN: usize = 4;
+/// Maximum payload length of a single message.
+pub const MAX_MESSAGE_LEN: usize = 32;
+/// Maximum length of a service name passed to discovery.
+pub const MAX_SERVICE_NAME_LEN: usize = 32;
+/// Length of the header written before the payload by command 5.
+pub const MESSAGE_HEADER_LEN: usize = 12;
+
+#[derive(Clone, Copy, PartialEq)]
+enum MessageKind {
+    Request = 0,
+    Response = 1,
+}
+
+#[derive(Clone, Copy)]
+struct Message {
+    kind: MessageKind,
+    /// Descriptor (process identifier) of the process that sent the message.
+    sender: usize,
+    correlation: u32,
+    len: usize,
+    data: [u8; MAX_MESSAGE_LEN],
+}
+
+#[derive(Default)]
+pub struct App {
+    /// Whether this process has registered itself as a service.
+    service: bool,
+    queue: [Option<Message>; QUEUE_LEN],
+    head: usize,
+    queued: usize,
+    /// Correlation ID to assign to the next request this process sends.
+    next_correlation: u32,
+    /// Requests this process has received but not yet responded to, as
+    /// (client descriptor, correlation ID) pairs.
+    awaiting_response: [Option<(usize, u32)>; QUEUE_LEN],
+}
+
+impl App {
+    fn push(&mut self, message: Message) -> Result<(), ErrorCode> {
+        if self.queued == QUEUE_LEN {
+            return Err(ErrorCode::NOMEM);
+        }
+        self.queue[(self.head + self.queued) % QUEUE_LEN] = Some(message);
+        self.queued += 1;
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<Message> {
+        if self.queued == 0 {
+            None
+        } else {
+            self.queue[self.head]
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.queued > 0 {
+            self.queue[self.head] = None;
+            self.head = (self.head + 1) % QUEUE_LEN;
+            self.queued -= 1;
+        }
+    }
+
+    /// Find a free slot to track a request awaiting a response. If all slots
+    /// are in use, requests from clients for which `client_exists` returns
+    /// false are dropped to make room.
+    fn free_awaiting_slot<F>(&mut self, client_exists: F) -> Option<usize>
+    where
+        F: Fn(usize) -> bool,
+    {
+        if !self.awaiting_response.iter().any(|entry| entry.is_none()) {
+            for entry in self.awaiting_response.iter_mut() {
+                if entry.map_or(false, |(client, _)| !client_exists(client)) {
+                    *entry = None;
+                }
+            }
+        }
+        self.awaiting_response
+            .iter()
+            .position(|entry| entry.is_none())
+    }
+
+    /// Stop tracking the request from `client` with ID `correlation`.
+    fn drop_awaiting(&mut self, client: usize, correlation: u32) -> Result<(), ErrorCode> {
+        let entry = self
+            .awaiting_response
+            .iter_mut()
+            .find(|entry| **entry == Some((client, correlation)))
+            .ok_or(ErrorCode::INVAL)?;
+        *entry = None;
+        Ok(())
+    }
+}
+
+pub struct IpcMessage<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> IpcMessage<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        capability: C,
+    ) -> IpcMessage<C> {
+        IpcMessage {
+            kernel,
+            apps: grant,
+            capability,
+        }
+    }
+
+    /// Run `closure` on the process with identifier `id`, if it exists.
+    fn with_process<F, R>(&self, id: usize, closure: F) -> Option<R>
+    where
+        F: Fn(&dyn Process) -> R,
+    {
+        let mut result = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if result.is_none() && process.processid().id() == id {
+                    result = Some(closure(process));
+                }
+            });
+        result
+    }
+
+    /// Return whether `client` may communicate with the service `service`.
+    /// The service must exist, must have registered itself, and must be listed
+    /// in the client's TBF header.
+    fn service_reachable(&self, client: ProcessId, service: usize) -> bool {
+        if client.id() == service {
+            return false;
+        }
+        let registered = self
+            .apps
+            .iter()
+            .find(|app| app.processid().id() == service)
+            .map_or(false, |app| app.enter(|app, _| app.service));
+        registered
+            && self
+                .with_process(service, |process| process.get_process_name())
+                .map_or(false, |name| {
+                    self.with_process(client.id(), |process| process.ipc_service_permitted(name))
+                        .unwrap_or(false)
+                })
+    }
+
+    /// Find the descriptor of the service whose name the client shared with
+    /// allow.
+    fn discover(&self, client: ProcessId) -> Result<usize, ErrorCode> {
+        let mut name = [0; MAX_SERVICE_NAME_LEN];
+        let name_len = self
+            .apps
+            .enter(client, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::SERVICE_NAME)
+                    .and_then(|buffer| {
+                        buffer.enter(|buf| {
+                            if buf.len() > MAX_SERVICE_NAME_LEN {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                buf.copy_to_slice(&mut name[..buf.len()]);
+                                Ok(buf.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))?;
+
+        let mut service = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if service.is_none() && process.get_process_name().as_bytes() == &name[..name_len] {
+                    service = Some(process.processid().id());
+                }
+            });
+
+        service
+            .filter(|service| self.service_reachable(client, *service))
+            .ok_or(ErrorCode::NODEVICE)
+    }
+
+    /// Copy the payload the process shared with allow into a message.
+    fn build_message(
+        &self,
+        sender: ProcessId,
+        kind: MessageKind,
+        correlation: Option<u32>,
+    ) -> Result<Message, ErrorCode> {
+        self.apps
+            .enter(sender, |app, kernel_data| {
+                let mut message = Message {
+                    kind,
+                    sender: sender.id(),
+                    correlation: 0,
+                    len: 0,
+                    data: [0; MAX_MESSAGE_LEN],
+                };
+                message.len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::MESSAGE)
+                    .and_then(|buffer| {
+                        buffer.enter(|buf| {
+                            if buf.len() > MAX_MESSAGE_LEN {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                buf.copy_to_slice(&mut message.data[..buf.len()]);
+                                Ok(buf.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+                message.correlation = correlation.unwrap_or_else(|| {
+                    let correlation = app.next_correlation;
+                    app.next_correlation = app.next_correlation.wrapping_add(1);
+                    correlation
+                });
+                Ok(message)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Add `message` to the queue of the process with identifier `to`, and
+    /// notify it.
+    fn deliver(&self, to: usize, message: Message) -> Result<(), ErrorCode> {
+        self.apps
+            .iter()
+            .find(|app| app.processid().id() == to)
+            .map_or(Err(ErrorCode::NODEVICE), |app| {
+                app.enter(|app, kernel_data| {
+                    if message.kind == MessageKind::Request && !app.service {
+                        return Err(ErrorCode::NODEVICE);
+                    }
+                    app.push(message)?;
+                    let _ = kernel_data.schedule_upcall(
+                        upcall::MESSAGE_RECEIVED,
+                        (message.kind as usize, message.sender, app.queued),
+                    );
+                    Ok(())
+                })
+            })
+    }
+
+    fn send_request(&self, client: ProcessId, service: usize) -> Result<u32, ErrorCode> {
+        if !self.service_reachable(client, service) {
+            return Err(ErrorCode::NODEVICE);
+        }
+        let message = self.build_message(client, MessageKind::Request, None)?;
+        self.deliver(service, message)?;
+        Ok(message.correlation)
+    }
+
+    fn send_response(
+        &self,
+        service: ProcessId,
+        client: usize,
+        correlation: u32,
+    ) -> Result<(), ErrorCode> {
+        let awaiting = self
+            .apps
+            .enter(service, |app, _| {
+                app.awaiting_response
+                    .iter()
+                    .position(|entry| *entry == Some((client, correlation)))
+            })
+            .map_err(ErrorCode::from)?
+            .ok_or(ErrorCode::INVAL)?;
+
+        let message = self.build_message(service, MessageKind::Response, Some(correlation))?;
+        let result = self.deliver(client, message);
+
+        // The request is answered once the response is delivered. If the
+        // client no longer exists the request can never be answered, so stop
+        // tracking it as well.
+        if result.is_ok() || result == Err(ErrorCode::NODEVICE) {
+            let _ = self.apps.enter(service, |app, _| {
+                app.awaiting_response[awaiting] = None;
+            });
+        }
+        result
+    }
+
+    /// Copy the next queued message into the buffer the process shared with
+    /// allow and remove it from the queue. Returns the payload length.
+    fn receive(&self, processid: ProcessId) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(processid, |app, kernel_data| {
+                let message = app.peek().ok_or(ErrorCode::FAIL)?;
+
+                let slot = if message.kind == MessageKind::Request {
+                    Some(
+                        app.free_awaiting_slot(|client| {
+                            self.with_process(client, |_| ()).is_some()
+                        })
+                        .ok_or(ErrorCode::BUSY)?,
+                    )
+                } else {
+                    None
+                };
+
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::RECEIVE)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buf| {
+                            if buf.len() < MESSAGE_HEADER_LEN + message.len {
+                                return Err(ErrorCode::SIZE);
+                            }
+                            let header = [
+                                message.kind as u32,
+                                message.sender as u32,
+                                message.correlation,
+                            ];
+                            for (i, word) in header.iter().enumerate() {
+                                buf[i * 4..(i + 1) * 4].copy_from_slice(&word.to_le_bytes());
+                            }
+                            buf[MESSAGE_HEADER_LEN..MESSAGE_HEADER_LEN + message.len]
+                                .copy_from_slice(&message.data[..message.len]);
+                            Ok(())
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))?;
+
+                if let Some(slot) = slot {
+                    app.awaiting_response[slot] = Some((message.sender, message.correlation));
+                }
+                app.pop();
+                Ok(message.len)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<C: ProcessManagementCapability> SyscallDriver for IpcMessage<C> {
+    /// Send and receive IPC messages.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register the calling process as a service, named by its package
+    ///        name.
+    /// - `2`: Discover the service whose name is in the `SERVICE_NAME` allow
+    ///        buffer. Returns the service descriptor. Returns `NODEVICE` if
+    ///        the service does not exist or the caller may not reach it.
+    /// - `3`: Send the contents of the `MESSAGE` allow buffer as a request to
+    ///        the service with descriptor `data`. Returns the correlation ID
+    ///        assigned to the request. Returns `NOMEM` if the service's queue
+    ///        is full.
+    /// - `4`: Send the contents of the `MESSAGE` allow buffer as the response
+    ///        to the request from the client with descriptor `data` that has
+    ///        correlation ID `data2`. Returns `INVAL` if there is no such
+    ///        request awaiting a response, and `NOMEM` if the client's queue
+    ///        is full.
+    /// - `5`: Copy the next queued message into the `RECEIVE` allow buffer and
+    ///        remove it from the queue. Returns the payload length. Returns
+    ///        `FAIL` if no message is queued, `SIZE` if the buffer is too
+    ///        small, and `BUSY` if the message is a request and too many
+    ///        requests are already awaiting a response.
+    /// - `6`: Drop the request from the client with descriptor `data` that has
+    ///        correlation ID `data2` without responding to it. Returns `INVAL`
+    ///        if there is no such request awaiting a response.
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                let named = self
+                    .with_process(appid.id(), |process| !process.get_process_name().is_empty())
+                    .unwrap_or(false);
+                if !named {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.service = true;
+                        CommandReturn::success()
+                    })
+                    .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+            }
+
+            2 => match self.discover(appid) {
+                Ok(service) => CommandReturn::success_u32(service as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            3 => match self.send_request(appid, data) {
+                Ok(correlation) => CommandReturn::success_u32(correlation),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            4 => self.send_response(appid, data, data2 as u32).into(),
+
+            5 => match self.receive(appid) {
+                Ok(len) => CommandReturn::success_u32(len as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            6 => self
+                .apps
+                .enter(appid, |app, _| app.drop_awaiting(data, data2 as u32))
+                .unwrap_or_else(|err| Err(err.into()))
+                .into(),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(sender: usize, correlation: u32) -> Message {
+        Message {
+            kind: MessageKind::Request,
+            sender,
+            correlation,
+            len: 0,
+            data: [0; MAX_MESSAGE_LEN],
+        }
+    }
+
+    #[test]
+    fn queue_wraps_around() {
+        let mut app = App::default();
+        for round in 0..3 {
+            for i in 0..QUEUE_LEN {
+                assert!(app.push(request(i, round)).is_ok());
+            }
+            assert!(app.push(request(QUEUE_LEN, round)) == Err(ErrorCode::NOMEM));
+            for i in 0..QUEUE_LEN {
+                let message = app.peek().unwrap();
+                assert_eq!((message.sender, message.correlation), (i, round));
+                app.pop();
+            }
+            assert!(app.peek().is_none());
+        }
+    }
+
+    #[test]
+    fn awaiting_slots_used_in_order() {
+        let mut app = App::default();
+        for i in 0..QUEUE_LEN {
+            let slot = app.free_awaiting_slot(|_| true).unwrap();
+            assert_eq!(slot, i);
+            app.awaiting_response[slot] = Some((i, 0));
+        }
+        assert_eq!(app.free_awaiting_slot(|_| true), None);
+    }
+
+    #[test]
+    fn awaiting_slots_of_dead_clients_freed_when_full() {
+        let mut app = App::default();
+        for i in 0..QUEUE_LEN {
+            app.awaiting_response[i] = Some((i, 7));
+        }
+        // Clients 1 and 2 exited without their requests being answered.
+        let slot = app.free_awaiting_slot(|client| client != 1 && client != 2);
+        assert_eq!(slot, Some(1));
+        assert_eq!(app.awaiting_response[0], Some((0, 7)));
+        assert_eq!(app.awaiting_response[1], None);
+        assert_eq!(app.awaiting_response[2], None);
+        assert_eq!(app.awaiting_response[3], Some((3, 7)));
+    }
+
+    #[test]
+    fn awaiting_slots_kept_while_free_slot_exists() {
+        let mut app = App::default();
+        app.awaiting_response[0] = Some((5, 1));
+        assert_eq!(app.free_awaiting_slot(|_| false), Some(1));
+        assert_eq!(app.awaiting_response[0], Some((5, 1)));
+    }
+
+    #[test]
+    fn drop_awaiting_request() {
+        let mut app = App::default();
+        app.awaiting_response[2] = Some((5, 9));
+        assert!(app.drop_awaiting(5, 8) == Err(ErrorCode::INVAL));
+        assert!(app.drop_awaiting(4, 9) == Err(ErrorCode::INVAL));
+        assert!(app.drop_awaiting(5, 9).is_ok());
+        assert_eq!(app.awaiting_response[2], None);
+        assert!(app.drop_awaiting(5, 9) == Err(ErrorCode::INVAL));
+    }
+}
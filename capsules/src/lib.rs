@@ -8,6 +8,7 @@ pub mod net;
 
 pub mod adc;
 pub mod adc_microphone;
+pub mod adc_stream;
 pub mod air_quality;
 pub mod alarm;
 pub mod ambient_light;
@@ -16,6 +17,9 @@ pub mod analog_sensor;
 pub mod apds9960;
 pub mod app_flash_driver;
 pub mod ble_advertising_driver;
+pub mod ble_connection;
+pub mod ble_gatt;
+pub mod ble_gatt_driver;
 pub mod bme280;
 pub mod bmp280;
 pub mod bus;
@@ -26,8 +30,10 @@ pub mod console;
 pub mod crc;
 pub mod ctap;
 pub mod dac;
+pub mod date_time;
 pub mod debug_process_restart;
 pub mod driver;
+pub mod ethernet_loopback;
 pub mod fm25cl;
 pub mod ft6x06;
 pub mod fxos8700cq;
@@ -40,6 +46,7 @@ pub mod humidity;
 pub mod i2c_master;
 pub mod i2c_master_slave_driver;
 pub mod ieee802154;
+pub mod ipc_message;
 pub mod isl29035;
 pub mod kv_driver;
 pub mod kv_store;
@@ -65,9 +72,12 @@ pub mod nonvolatile_to_pages;
 pub mod nrf51822_serialization;
 pub mod panic_button;
 pub mod pca9544a;
+pub mod pressure;
 pub mod process_console;
+pub mod process_info;
 pub mod proximity;
 pub mod public_key_crypto;
+pub mod pwm;
 pub mod read_only_state;
 pub mod rf233;
 pub mod rf233_const;
@@ -75,12 +85,14 @@ pub mod rng;
 pub mod screen;
 pub mod sdcard;
 pub mod segger_rtt;
+pub mod sensor_sampling;
 pub mod seven_segment;
 pub mod sha;
 pub mod sha256;
 pub mod sht3x;
 pub mod si7021;
 pub mod sip_hash;
+pub mod soft_date_time;
 pub mod sound_pressure;
 pub mod spi_controller;
 pub mod spi_peripheral;
@@ -107,3 +119,4 @@ pub mod virtual_sha;
 pub mod virtual_spi;
 pub mod virtual_timer;
 pub mod virtual_uart;
+pub mod wall_clock;
@@ -1,7 +1,10 @@
-//! SyscallDriver for the ST LPS25HB pressure sensor.
+//! Driver for the ST LPS25HB pressure sensor.
 //!
 //! <http://www.st.com/en/mems-and-sensors/lps25hb.html>
 //!
+//! The sensor provides `hil::sensors::PressureDriver`, so userspace reaches
+//! it through `capsules::pressure`.
+//!
 //! Usage
 //! -----
 //!
@@ -20,17 +23,11 @@
 
 use core::cell::Cell;
 
-use kernel::errorcode::into_statuscode;
-use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use kernel::hil::gpio;
 use kernel::hil::i2c;
-use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::hil::sensors::{PressureClient, PressureDriver};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
-use kernel::{ErrorCode, ProcessId};
-
-/// Syscall driver number.
-use crate::driver;
-pub const DRIVER_NUM: usize = driver::NUM::Lps25hb as usize;
+use kernel::ErrorCode;
 
 // Buffer to use for I2C messages
 pub static mut BUFFER: [u8; 5] = [0; 5];
@@ -87,26 +84,26 @@ enum State {
     TakeMeasurementClear,
     /// Enable a single shot measurement with interrupt when data is ready.
     TakeMeasurementConfigure,
+    /// Wait for the interrupt that signals the data is ready.
+    WaitingForMeasurement,
 
     /// Read the 3 pressure registers.
     ReadMeasurement,
-    /// Calculate pressure and call the callback with the value.
+    /// Calculate pressure and power the chip off.
     GotMeasurement,
 
-    /// Disable I2C and release buffer
+    /// Disable I2C, release buffer and call the callback with the pressure.
     Done,
 }
 
-#[derive(Default)]
-pub struct App {}
-
 pub struct LPS25HB<'a> {
     i2c: &'a dyn i2c::I2CDevice,
     interrupt_pin: &'a dyn gpio::InterruptPin<'a>,
     state: Cell<State>,
     buffer: TakeCell<'static, [u8]>,
-    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
-    owning_process: OptionalCell<ProcessId>,
+    pressure_client: OptionalCell<&'a dyn PressureClient>,
+    /// A reading to report once the sensor is powered off again.
+    pressure_pa: OptionalCell<u32>,
 }
 
 impl<'a> LPS25HB<'a> {
@@ -114,7 +111,6 @@ impl<'a> LPS25HB<'a> {
         i2c: &'a dyn i2c::I2CDevice,
         interrupt_pin: &'a dyn gpio::InterruptPin<'a>,
         buffer: &'static mut [u8],
-        apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
     ) -> Self {
         // setup and return struct
         Self {
@@ -122,12 +118,15 @@ impl<'a> LPS25HB<'a> {
             interrupt_pin: interrupt_pin,
             state: Cell::new(State::Idle),
             buffer: TakeCell::new(buffer),
-            apps,
-            owning_process: OptionalCell::empty(),
+            pressure_client: OptionalCell::empty(),
+            pressure_pa: OptionalCell::empty(),
         }
     }
 
     pub fn read_whoami(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
         self.buffer.take().map_or(Err(ErrorCode::NOMEM), |buf| {
             // turn on i2c to send commands
             self.i2c.enable();
@@ -146,6 +145,9 @@ impl<'a> LPS25HB<'a> {
     }
 
     pub fn take_measurement(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
         self.interrupt_pin.make_input();
         self.interrupt_pin
             .enable_interrupts(gpio::InterruptEdge::RisingEdge);
@@ -163,6 +165,7 @@ impl<'a> LPS25HB<'a> {
             if let Err((_error, buf)) = self.i2c.write(buf, 5) {
                 self.buffer.replace(buf);
                 self.i2c.disable();
+                self.interrupt_pin.disable_interrupts();
                 Err(_error.into())
             } else {
                 self.state.set(State::TakeMeasurementInit);
@@ -170,18 +173,30 @@ impl<'a> LPS25HB<'a> {
             }
         })
     }
+
+    /// Gives up on the current measurement, reporting `error` to the client.
+    fn measurement_failed(&self, buffer: &'static mut [u8], error: ErrorCode) {
+        self.state.set(State::Idle);
+        self.buffer.replace(buffer);
+        self.i2c.disable();
+        self.interrupt_pin.disable_interrupts();
+        self.pressure_pa.clear();
+        self.pressure_client
+            .map(|client| client.callback(Err(error)));
+    }
 }
 
 impl i2c::I2CClient for LPS25HB<'_> {
     fn command_complete(&self, buffer: &'static mut [u8], status: Result<(), i2c::Error>) {
-        if status != Ok(()) {
-            self.state.set(State::Idle);
-            self.buffer.replace(buffer);
-            self.owning_process.map(|pid| {
-                let _ = self.apps.enter(*pid, |_app, upcalls| {
-                    upcalls.schedule_upcall(0, (0, 0, 0)).ok();
-                });
-            });
+        if let Err(error) = status {
+            match self.state.get() {
+                State::SelectWhoAmI | State::ReadingWhoAmI => {
+                    self.state.set(State::Idle);
+                    self.buffer.replace(buffer);
+                    self.i2c.disable();
+                }
+                _ => self.measurement_failed(buffer, error.into()),
+            }
             return;
         }
         match self.state.get() {
@@ -189,6 +204,7 @@ impl i2c::I2CClient for LPS25HB<'_> {
                 if let Err((_error, buffer)) = self.i2c.read(buffer, 1) {
                     self.state.set(State::Idle);
                     self.buffer.replace(buffer);
+                    self.i2c.disable();
                 } else {
                     self.state.set(State::ReadingWhoAmI);
                 }
@@ -201,30 +217,14 @@ impl i2c::I2CClient for LPS25HB<'_> {
             State::TakeMeasurementInit => {
                 buffer[0] = Registers::PressOutXl as u8 | REGISTER_AUTO_INCREMENT;
                 if let Err((error, buffer)) = self.i2c.write(buffer, 1) {
-                    self.state.set(State::Idle);
-                    self.buffer.replace(buffer);
-                    self.owning_process.map(|pid| {
-                        let _ = self.apps.enter(*pid, |_app, upcalls| {
-                            upcalls
-                                .schedule_upcall(0, (into_statuscode(Err(error.into())), 0, 0))
-                                .ok();
-                        });
-                    });
+                    self.measurement_failed(buffer, error.into());
                 } else {
                     self.state.set(State::TakeMeasurementClear);
                 }
             }
             State::TakeMeasurementClear => {
                 if let Err((error, buffer)) = self.i2c.read(buffer, 3) {
-                    self.state.set(State::Idle);
-                    self.buffer.replace(buffer);
-                    self.owning_process.map(|pid| {
-                        let _ = self.apps.enter(*pid, |_app, upcalls| {
-                            upcalls
-                                .schedule_upcall(0, (into_statuscode(Err(error.into())), 0, 0))
-                                .ok();
-                        });
-                    });
+                    self.measurement_failed(buffer, error.into());
                 } else {
                     self.state.set(State::TakeMeasurementConfigure);
                 }
@@ -235,30 +235,18 @@ impl i2c::I2CClient for LPS25HB<'_> {
                 buffer[2] = CTRL_REG2_ONE_SHOT;
 
                 if let Err((error, buffer)) = self.i2c.write(buffer, 3) {
-                    self.state.set(State::Idle);
-                    self.buffer.replace(buffer);
-                    self.owning_process.map(|pid| {
-                        let _ = self.apps.enter(*pid, |_app, upcalls| {
-                            upcalls
-                                .schedule_upcall(0, (into_statuscode(Err(error.into())), 0, 0))
-                                .ok();
-                        });
-                    });
+                    self.measurement_failed(buffer, error.into());
                 } else {
-                    self.state.set(State::Done);
+                    self.state.set(State::WaitingForMeasurement);
                 }
             }
+            State::WaitingForMeasurement => {
+                self.buffer.replace(buffer);
+                self.i2c.disable();
+            }
             State::ReadMeasurement => {
                 if let Err((error, buffer)) = self.i2c.read(buffer, 3) {
-                    self.state.set(State::Idle);
-                    self.buffer.replace(buffer);
-                    self.owning_process.map(|pid| {
-                        let _ = self.apps.enter(*pid, |_app, upcalls| {
-                            upcalls
-                                .schedule_upcall(0, (into_statuscode(Err(error.into())), 0, 0))
-                                .ok();
-                        });
-                    });
+                    self.measurement_failed(buffer, error.into());
                 } else {
                     self.state.set(State::GotMeasurement);
                 }
@@ -268,32 +256,17 @@ impl i2c::I2CClient for LPS25HB<'_> {
                     | ((buffer[1] as u32) << 8)
                     | (buffer[0] as u32)) as u32;
 
-                // Returned as microbars
-                let pressure_ubar = (pressure * 1000) / 4096;
-
-                self.owning_process.map(|pid| {
-                    let _ = self.apps.enter(*pid, |_app, upcalls| {
-                        upcalls
-                            .schedule_upcall(0, (pressure_ubar as usize, 0, 0))
-                            .ok();
-                    });
-                });
+                // The sensor counts 4096 per hectopascal
+                let pressure_pa = (pressure * 100) / 4096;
 
                 buffer[0] = Registers::CtrlReg1 as u8;
                 buffer[1] = 0;
 
                 if let Err((error, buffer)) = self.i2c.write(buffer, 2) {
-                    self.state.set(State::Idle);
-                    self.buffer.replace(buffer);
-                    self.owning_process.map(|pid| {
-                        let _ = self.apps.enter(*pid, |_app, upcalls| {
-                            upcalls
-                                .schedule_upcall(0, (into_statuscode(Err(error.into())), 0, 0))
-                                .ok();
-                        });
-                    });
+                    self.measurement_failed(buffer, error.into());
                 } else {
                     self.interrupt_pin.disable_interrupts();
+                    self.pressure_pa.set(pressure_pa);
                     self.state.set(State::Done);
                 }
             }
@@ -301,6 +274,10 @@ impl i2c::I2CClient for LPS25HB<'_> {
                 self.buffer.replace(buffer);
                 self.i2c.disable();
                 self.state.set(State::Idle);
+                self.pressure_pa.take().map(|pressure_pa| {
+                    self.pressure_client
+                        .map(|client| client.callback(Ok(pressure_pa)))
+                });
             }
             _ => {}
         }
@@ -309,6 +286,9 @@ impl i2c::I2CClient for LPS25HB<'_> {
 
 impl gpio::Client for LPS25HB<'_> {
     fn fired(&self) {
+        if self.state.get() != State::WaitingForMeasurement {
+            return;
+        }
         self.buffer.take().map(|buf| {
             // turn on i2c to send commands
             self.i2c.enable();
@@ -316,9 +296,8 @@ impl gpio::Client for LPS25HB<'_> {
             // select sensor voltage register and read it
             buf[0] = Registers::PressOutXl as u8 | REGISTER_AUTO_INCREMENT;
 
-            if let Err((_error, buf)) = self.i2c.write(buf, 1) {
-                self.buffer.replace(buf);
-                self.i2c.disable();
+            if let Err((error, buf)) = self.i2c.write(buf, 1) {
+                self.measurement_failed(buf, error.into());
             } else {
                 self.state.set(State::ReadMeasurement);
             }
@@ -326,43 +305,12 @@ impl gpio::Client for LPS25HB<'_> {
     }
 }
 
-impl SyscallDriver for LPS25HB<'_> {
-    fn command(
-        &self,
-        command_num: usize,
-        _: usize,
-        _: usize,
-        process_id: ProcessId,
-    ) -> CommandReturn {
-        if command_num == 0 {
-            // Handle this first as it should be returned
-            // unconditionally
-            return CommandReturn::success();
-        }
-        // Check if this non-virtualized driver is already in use by
-        // some (alive) process
-        let match_or_empty_or_nonexistant = self.owning_process.map_or(true, |current_process| {
-            self.apps
-                .enter(*current_process, |_, _| current_process == &process_id)
-                .unwrap_or(true)
-        });
-        if match_or_empty_or_nonexistant {
-            self.owning_process.set(process_id);
-        } else {
-            return CommandReturn::failure(ErrorCode::NOMEM);
-        }
-        match command_num {
-            // Take a pressure measurement
-            1 => match self.take_measurement() {
-                Ok(()) => CommandReturn::success(),
-                Err(error) => CommandReturn::failure(error),
-            },
-            // default
-            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
-        }
+impl<'a> PressureDriver<'a> for LPS25HB<'a> {
+    fn set_client(&self, client: &'a dyn PressureClient) {
+        self.pressure_client.set(client);
     }
 
-    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
-        self.apps.enter(processid, |_, _| {})
+    fn read_pressure(&self) -> Result<(), ErrorCode> {
+        self.take_measurement()
     }
 }
@@ -0,0 +1,1326 @@
+//! CoAP userspace interface for serving and requesting resources.
+//!
+//! The driver binds the CoAP port (5683) once for the whole kernel and
+//! multiplexes it between processes. Each process can register up to
+//! `MAX_RESOURCES` URI paths that it serves, and can have one outstanding
+//! client request at a time. The driver handles the messaging layer:
+//! confirmable requests are retransmitted with exponential backoff, duplicate
+//! requests are suppressed, and acknowledgements and resets are generated and
+//! matched in the kernel. Bodies larger than one block are transferred with
+//! Block1/Block2 (RFC 7959), and each resource can have one observer
+//! (RFC 7641) that is sent a notification whenever the process calls
+//! `notify`.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Allow
+//!
+//! * Read-only 0: the payload of a response, notification or request.
+//! * Read-only 1: configuration. For `register`, the resource path (e.g.
+//!   `sensors/temp`). For `request`, the 16-byte destination address, the
+//!   2-byte destination port in host byte order, and the resource path.
+//! * Read-write 0: receives the payload of requests to the process's
+//!   resources.
+//! * Read-write 1: receives the payload of responses to the process's
+//!   request.
+//!
+//! ### Subscribe
+//!
+//! * 0: a request to a resource arrived. The arguments are the resource
+//!   index, the method code and the payload length.
+//! * 1: the outstanding request completed, or a notification for an observed
+//!   resource arrived. The arguments are a status code, the response code and
+//!   the payload length.
+//!
+//! ### Command
+//!
+//! * 0: driver check.
+//! * 1: register the resource path in read-only allow 1. Returns the index of
+//!   the resource, or `BUSY` if another process serves the path.
+//! * 2: unregister resource `arg1`.
+//! * 3: respond to the pending request with code `arg1` and the first `arg2`
+//!   bytes of read-only allow 0.
+//! * 4: notify the observer of resource `arg1` with the first `arg2` bytes of
+//!   read-only allow 0. Returns the number of observers notified.
+//! * 5: send a request to the destination in read-only allow 1. The low byte
+//!   of `arg1` is the method code, and `CONFIRMABLE` and `OBSERVE` may be
+//!   set. The payload is the first `arg2` bytes of read-only allow 0.
+//! * 6: cancel the outstanding request, including an observation.
+
+use crate::net::coap::message::{self, code, msg_type, option, Block, Encoder, Message, Token};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::net::util::host_slice_to_u16;
+
+use core::cell::Cell;
+use core::cmp;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::MapCell;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Coap as usize;
+
+/// The UDP port the driver binds.
+pub const COAP_PORT: u16 = 5683;
+
+/// The number of resources each process can serve.
+pub const MAX_RESOURCES: usize = 4;
+/// The longest resource path, without leading and trailing slashes.
+pub const MAX_PATH_LEN: usize = 32;
+
+/// Flags in `arg1` of the request command.
+pub const CONFIRMABLE: usize = 0x100;
+pub const OBSERVE: usize = 0x200;
+
+/// The block size exponent used for blockwise transfers: 64-byte blocks fit
+/// in a single 6LoWPAN-compressed datagram.
+const BLOCK_SZX: u8 = 2;
+
+// Transmission parameters from RFC 7252, section 4.8.
+const ACK_TIMEOUT_MS: u32 = 2000;
+const ACK_RANDOM_MS: u32 = 1000;
+const MAX_RETRANSMIT: u8 = 4;
+/// How long to wait for a separate response once a request is acknowledged.
+const RESPONSE_TIMEOUT_MS: u32 = 30000;
+
+/// The number of empty and error messages that can wait for transmission.
+const CONTROL_QUEUE_LEN: usize = 4;
+
+mod upcall {
+    pub const SERVER_REQUEST: usize = 0;
+    pub const CLIENT_RESPONSE: usize = 1;
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    pub const TX: usize = 0;
+    pub const CFG: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const SERVER_RX: usize = 0;
+    pub const CLIENT_RX: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 2;
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Peer {
+    addr: IPAddr,
+    port: u16,
+}
+
+impl PartialEq for Peer {
+    fn eq(&self, other: &Peer) -> bool {
+        self.addr.0 == other.addr.0 && self.port == other.port
+    }
+}
+
+/// A resource path, stored without leading, trailing or repeated slashes so
+/// that paths can be compared bytewise.
+#[derive(Copy, Clone, Default, PartialEq)]
+struct Path {
+    bytes: [u8; MAX_PATH_LEN],
+    len: u8,
+}
+
+impl Path {
+    fn push(&mut self, segment: &[u8]) -> Option<()> {
+        let len = self.len as usize;
+        let sep = if len == 0 { 0 } else { 1 };
+        if len + sep + segment.len() > MAX_PATH_LEN {
+            return None;
+        }
+        if sep == 1 {
+            self.bytes[len] = b'/';
+        }
+        self.bytes[len + sep..len + sep + segment.len()].copy_from_slice(segment);
+        self.len = (len + sep + segment.len()) as u8;
+        Some(())
+    }
+
+    fn parse(path: &[u8]) -> Option<Path> {
+        let mut result = Path::default();
+        for segment in path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            result.push(segment)?;
+        }
+        Some(result)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Observer {
+    peer: Peer,
+    token: Token,
+    /// The message ID of the last notification, to match resets.
+    message_id: u16,
+    /// The length of a notification waiting to be sent.
+    pending: Option<usize>,
+}
+
+#[derive(Copy, Clone)]
+struct Resource {
+    path: Path,
+    observer: Option<Observer>,
+}
+
+/// A request to one of the process's resources.
+#[derive(Copy, Clone)]
+struct ServerExchange {
+    peer: Peer,
+    resource: usize,
+    token: Token,
+    message_id: u16,
+    confirmable: bool,
+    /// Whether the request registered an observer.
+    observe: bool,
+    /// The last block of a Block1 transfer, echoed in the response.
+    block1: Option<Block>,
+    /// The requested block of the response.
+    block2: Block,
+    /// The response code and length, once the process has responded.
+    response: Option<(u8, usize)>,
+}
+
+/// A Block1 transfer in progress to one of the process's resources.
+#[derive(Copy, Clone)]
+struct Block1Transfer {
+    peer: Peer,
+    resource: usize,
+    next_num: u32,
+}
+
+/// The last response too large for one block, kept so that later blocks are
+/// served without waking the process. The body stays in read-only allow 0.
+#[derive(Copy, Clone)]
+struct Block2Cache {
+    resource: usize,
+    code: u8,
+    len: usize,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ClientState {
+    /// The next message of the exchange is waiting for transmission.
+    Send,
+    AwaitAck,
+    AwaitResponse,
+    Observing,
+}
+
+#[derive(Copy, Clone)]
+struct ClientExchange {
+    peer: Peer,
+    path: Path,
+    method: u8,
+    confirmable: bool,
+    observe: bool,
+    token: Token,
+    /// The ID of the current message; kept for retransmissions.
+    message_id: Option<u16>,
+    payload_len: usize,
+    block1: Block,
+    block2: Option<Block>,
+    rx_len: usize,
+    state: ClientState,
+    retransmissions: u8,
+    timeout_ms: u32,
+    /// The reference and interval of the retransmission or response timer,
+    /// in ticks.
+    timer: Option<(u32, u32)>,
+}
+
+/// An empty or error message sent on behalf of the driver.
+#[derive(Copy, Clone)]
+struct Control {
+    peer: Peer,
+    msg_type: u8,
+    code: u8,
+    message_id: u16,
+    token: Token,
+    block1: Option<Block>,
+}
+
+#[derive(Default)]
+pub struct App {
+    resources: [Option<Resource>; MAX_RESOURCES],
+    server: Option<ServerExchange>,
+    block1: Option<Block1Transfer>,
+    block2_cache: Option<Block2Cache>,
+    client: Option<ClientExchange>,
+}
+
+pub struct CoapDriver<'a, A: time::Alarm<'a>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    apps: Grant<
+        App,
+        UpcallCount<{ upcall::COUNT }>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+    tx_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+    control: [Cell<Option<Control>>; CONTROL_QUEUE_LEN],
+    message_id: Cell<u16>,
+    observe_seq: Cell<u32>,
+    random: Cell<u32>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> CoapDriver<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        grant: Grant<
+            App,
+            UpcallCount<{ upcall::COUNT }>,
+            AllowRoCount<{ ro_allow::COUNT }>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+        tx_buffer: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> CoapDriver<'a, A> {
+        CoapDriver {
+            sender: sender,
+            alarm: alarm,
+            apps: grant,
+            tx_buffer: MapCell::new(tx_buffer),
+            control: Default::default(),
+            message_id: Cell::new(0),
+            observe_seq: Cell::new(0),
+            random: Cell::new(0x2545_f491),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Returns a pseudo-random number for tokens and retransmission jitter.
+    /// Tokens only need to be hard to guess for off-path attackers, so the
+    /// alarm counter is mixed into a xorshift generator.
+    fn next_random(&self) -> u32 {
+        let mut x = self.random.get() ^ self.alarm.now().into_u32();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    fn next_message_id(&self) -> u16 {
+        let id = self.message_id.get();
+        if id == 0 {
+            // Start from a random ID, so that IDs are not reused right away
+            // after a reboot.
+            self.message_id.set(self.next_random() as u16 | 1);
+        }
+        let id = self.message_id.get();
+        self.message_id.set(id.wrapping_add(1));
+        id
+    }
+
+    fn queue_control(&self, control: Control) {
+        // If the queue is full, the message is dropped; the peer
+        // retransmits confirmable messages.
+        if let Some(slot) = self.control.iter().find(|slot| slot.get().is_none()) {
+            slot.set(Some(control));
+        }
+    }
+
+    /// Queues an error response (or a 2.31 Continue) to a request that is
+    /// not passed to a process.
+    fn reply(&self, peer: Peer, request: &Message, code: u8, block1: Option<Block>) {
+        let (msg_type, message_id) = if request.msg_type == msg_type::CON {
+            (msg_type::ACK, request.message_id)
+        } else {
+            (msg_type::NON, self.next_message_id())
+        };
+        self.queue_control(Control {
+            peer: peer,
+            msg_type: msg_type,
+            code: code,
+            message_id: message_id,
+            token: request.token,
+            block1: block1,
+        });
+    }
+
+    fn queue_empty(&self, peer: Peer, msg_type: u8, message_id: u16) {
+        self.queue_control(Control {
+            peer: peer,
+            msg_type: msg_type,
+            code: code::EMPTY,
+            message_id: message_id,
+            token: Token::default(),
+            block1: None,
+        });
+    }
+
+    fn start_timer(&self, client: &mut ClientExchange, ms: u32) {
+        client.timer = Some((
+            self.alarm.now().into_u32(),
+            self.alarm.ticks_from_ms(ms).into_u32(),
+        ));
+    }
+
+    fn fail_client(&self, app: &mut App, kernel_data: &GrantKernelData, error: ErrorCode) {
+        app.client = None;
+        kernel_data
+            .schedule_upcall(upcall::CLIENT_RESPONSE, (into_statuscode(Err(error)), 0, 0))
+            .ok();
+    }
+
+    /// Arms the alarm for the earliest client timer, if any.
+    fn arm_timer(&self) {
+        let now = self.alarm.now();
+        let mut next: Option<(u32, u32, u32)> = None;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if let Some((reference, dt)) = app.client.and_then(|client| client.timer) {
+                    let elapsed = now.wrapping_sub(A::Ticks::from(reference)).into_u32();
+                    let remaining = dt.saturating_sub(elapsed);
+                    if next.map_or(true, |(_, _, r)| remaining < r) {
+                        next = Some((reference, dt, remaining));
+                    }
+                }
+            });
+        }
+        match next {
+            Some((reference, dt, _)) => {
+                self.alarm
+                    .set_alarm(A::Ticks::from(reference), A::Ticks::from(dt));
+            }
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    /// Sends the next waiting message, if the buffer is free.
+    fn do_next_tx(&self) {
+        while let Some(mut buf) = self.tx_buffer.take() {
+            match self.prepare_next(&mut buf[..]) {
+                Some((peer, len)) => {
+                    buf.slice(0..len);
+                    match self.sender.send_to(peer.addr, peer.port, buf, self.net_cap) {
+                        Ok(()) => break,
+                        Err(mut buf) => {
+                            // Treat the message as lost and move on to the
+                            // next one.
+                            buf.reset();
+                            self.tx_buffer.replace(buf);
+                        }
+                    }
+                }
+                None => {
+                    self.tx_buffer.replace(buf);
+                    break;
+                }
+            }
+        }
+        self.arm_timer();
+    }
+
+    /// Encodes the next waiting message into `buf` and marks it sent.
+    /// Messages that do not fit are dropped.
+    fn prepare_next(&self, buf: &mut [u8]) -> Option<(Peer, usize)> {
+        loop {
+            let control = match self.control.iter().find_map(|slot| slot.take()) {
+                Some(control) => control,
+                None => break,
+            };
+            let encoded = Encoder::new(
+                buf,
+                control.msg_type,
+                control.code,
+                control.message_id,
+                &control.token,
+            )
+            .and_then(|mut encoder| {
+                if let Some(block1) = control.block1 {
+                    encoder.uint_option(option::BLOCK1, block1.encode())?;
+                }
+                Ok(encoder.len())
+            });
+            if let Ok(len) = encoded {
+                return Some((control.peer, len));
+            }
+        }
+
+        for cntr in self.apps.iter() {
+            let next = cntr.enter(|app, kernel_data| self.prepare_app(app, kernel_data, buf));
+            if next.is_some() {
+                return next;
+            }
+        }
+        None
+    }
+
+    fn prepare_app(
+        &self,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        buf: &mut [u8],
+    ) -> Option<(Peer, usize)> {
+        if let Some(exchange) = app.server.filter(|exchange| exchange.response.is_some()) {
+            app.server = None;
+            // Responses to confirmable requests are piggybacked on the ACK
+            let message_id = if exchange.confirmable {
+                exchange.message_id
+            } else {
+                self.next_message_id()
+            };
+            if let Ok(len) = self.encode_response(app, kernel_data, &exchange, message_id, buf) {
+                return Some((exchange.peer, len));
+            }
+        }
+
+        for idx in 0..MAX_RESOURCES {
+            let observer = match app.resources[idx].and_then(|r| r.observer) {
+                Some(observer) => observer,
+                None => continue,
+            };
+            if let Some(len) = observer.pending {
+                let message_id = self.next_message_id();
+                app.resources[idx].as_mut().map(|resource| {
+                    resource.observer = Some(Observer {
+                        message_id: message_id,
+                        pending: None,
+                        ..observer
+                    });
+                });
+                let exchange = ServerExchange {
+                    peer: observer.peer,
+                    resource: idx,
+                    token: observer.token,
+                    message_id: message_id,
+                    confirmable: false,
+                    observe: true,
+                    block1: None,
+                    block2: Block {
+                        num: 0,
+                        more: false,
+                        szx: BLOCK_SZX,
+                    },
+                    response: Some((code::CONTENT, len)),
+                };
+                if let Ok(len) = self.encode_response(app, kernel_data, &exchange, message_id, buf)
+                {
+                    return Some((observer.peer, len));
+                }
+            }
+        }
+
+        if let Some(mut client) = app.client.filter(|c| c.state == ClientState::Send) {
+            let encoded = self.encode_request(&mut client, kernel_data, buf);
+            if client.confirmable {
+                client.state = ClientState::AwaitAck;
+                let timeout_ms = client.timeout_ms;
+                self.start_timer(&mut client, timeout_ms);
+            } else {
+                client.state = ClientState::AwaitResponse;
+                self.start_timer(&mut client, RESPONSE_TIMEOUT_MS);
+            }
+            app.client = Some(client);
+            match encoded {
+                Ok(len) => return Some((client.peer, len)),
+                Err(e) => self.fail_client(app, kernel_data, e),
+            }
+        }
+        None
+    }
+
+    fn encode_response(
+        &self,
+        app: &mut App,
+        kernel_data: &GrantKernelData,
+        exchange: &ServerExchange,
+        message_id: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let (response_code, len) = exchange.response.unwrap_or((code::EMPTY, 0));
+        let msg_type = if exchange.confirmable {
+            msg_type::ACK
+        } else {
+            msg_type::NON
+        };
+        let mut encoder = Encoder::new(buf, msg_type, response_code, message_id, &exchange.token)?;
+        if exchange.observe {
+            let seq = self.observe_seq.get().wrapping_add(1) & 0xff_ffff;
+            self.observe_seq.set(seq);
+            encoder.uint_option(option::OBSERVE, seq)?;
+        }
+
+        let block = exchange.block2;
+        let (start, end) = if len > block.size() {
+            app.block2_cache = Some(Block2Cache {
+                resource: exchange.resource,
+                code: response_code,
+                len: len,
+            });
+            let start = cmp::min(block.offset(), len);
+            let end = cmp::min(start + block.size(), len);
+            encoder.uint_option(
+                option::BLOCK2,
+                Block {
+                    more: end < len,
+                    ..block
+                }
+                .encode(),
+            )?;
+            (start, end)
+        } else {
+            if app
+                .block2_cache
+                .map_or(false, |cache| cache.resource == exchange.resource)
+            {
+                app.block2_cache = None;
+            }
+            (0, len)
+        };
+        if let Some(block1) = exchange.block1 {
+            encoder.uint_option(option::BLOCK1, block1.encode())?;
+        }
+
+        let payload = encoder.payload(end - start)?;
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::TX)
+            .and_then(|tx| {
+                tx.enter(|tx| {
+                    let end = cmp::min(end, tx.len());
+                    let start = cmp::min(start, end);
+                    tx[start..end].copy_to_slice(&mut payload[..end - start]);
+                })
+            })?;
+        Ok(encoder.len())
+    }
+
+    fn encode_request(
+        &self,
+        client: &mut ClientExchange,
+        kernel_data: &GrantKernelData,
+        buf: &mut [u8],
+    ) -> Result<usize, ErrorCode> {
+        let message_id = match client.message_id {
+            Some(id) => id,
+            None => self.next_message_id(),
+        };
+        client.message_id = Some(message_id);
+        let msg_type = if client.confirmable {
+            msg_type::CON
+        } else {
+            msg_type::NON
+        };
+        let mut encoder = Encoder::new(buf, msg_type, client.method, message_id, &client.token)?;
+        // Only the first request of an exchange registers the observation
+        if client.observe && client.block1.num == 0 && client.block2.is_none() {
+            encoder.uint_option(option::OBSERVE, 0)?;
+        }
+        encoder.path_options(option::URI_PATH, client.path.as_slice())?;
+        if let Some(block2) = client.block2 {
+            encoder.uint_option(option::BLOCK2, block2.encode())?;
+        }
+        let (start, end) = if client.payload_len > client.block1.size() {
+            let start = cmp::min(client.block1.offset(), client.payload_len);
+            let end = cmp::min(start + client.block1.size(), client.payload_len);
+            encoder.uint_option(
+                option::BLOCK1,
+                Block {
+                    more: end < client.payload_len,
+                    ..client.block1
+                }
+                .encode(),
+            )?;
+            (start, end)
+        } else {
+            (0, client.payload_len)
+        };
+
+        let payload = encoder.payload(end - start)?;
+        kernel_data
+            .get_readonly_processbuffer(ro_allow::TX)
+            .and_then(|tx| {
+                tx.enter(|tx| {
+                    if end > tx.len() {
+                        Err(ErrorCode::SIZE)
+                    } else {
+                        tx[start..end].copy_to_slice(payload);
+                        Ok(())
+                    }
+                })
+            })??;
+        Ok(encoder.len())
+    }
+
+    fn handle_empty(&self, peer: Peer, msg: &Message) {
+        match msg.msg_type {
+            msg_type::CON => {
+                // A CoAP ping
+                self.queue_empty(peer, msg_type::RST, msg.message_id);
+            }
+            msg_type::ACK | msg_type::RST => {
+                self.apps.each(|_, app, kernel_data| {
+                    if let Some(mut client) = app.client {
+                        if client.peer == peer && client.message_id == Some(msg.message_id) {
+                            if msg.msg_type == msg_type::RST {
+                                self.fail_client(app, kernel_data, ErrorCode::CANCEL);
+                            } else if client.state == ClientState::AwaitAck {
+                                client.state = ClientState::AwaitResponse;
+                                self.start_timer(&mut client, RESPONSE_TIMEOUT_MS);
+                                app.client = Some(client);
+                            }
+                        }
+                    }
+                    if msg.msg_type == msg_type::RST {
+                        // A reset to a notification cancels the observation
+                        for resource in app.resources.iter_mut().flatten() {
+                            if resource
+                                .observer
+                                .map_or(false, |o| o.peer == peer && o.message_id == msg.message_id)
+                            {
+                                resource.observer = None;
+                            }
+                        }
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_request(&self, peer: Peer, msg: &Message) {
+        let mut path = Path::default();
+        for (number, value) in msg.options() {
+            match number {
+                option::URI_PATH => {
+                    if path.push(value).is_none() {
+                        self.reply(peer, msg, code::NOT_FOUND, None);
+                        return;
+                    }
+                }
+                option::URI_HOST
+                | option::URI_PORT
+                | option::URI_QUERY
+                | option::ACCEPT
+                | option::BLOCK1
+                | option::BLOCK2 => {}
+                n if option::is_critical(n) => {
+                    self.reply(peer, msg, code::BAD_OPTION, None);
+                    return;
+                }
+                _ => {}
+            }
+        }
+        let block1 = msg.block1();
+        let block2 = msg.block2();
+        if (block1.is_none() && msg.option(option::BLOCK1).is_some())
+            || (block2.is_none() && msg.option(option::BLOCK2).is_some())
+        {
+            self.reply(peer, msg, code::BAD_OPTION, None);
+            return;
+        }
+
+        let mut target: Option<(ProcessId, usize)> = None;
+        for cntr in self.apps.iter() {
+            let processid = cntr.processid();
+            cntr.enter(|app, _| {
+                for (idx, resource) in app.resources.iter().enumerate() {
+                    if resource.map_or(false, |r| r.path == path) {
+                        target = Some((processid, idx));
+                    }
+                }
+            });
+        }
+        let (processid, idx) = match target {
+            Some(target) => target,
+            None => {
+                self.reply(peer, msg, code::NOT_FOUND, None);
+                return;
+            }
+        };
+
+        let _ = self.apps.enter(processid, |app, kernel_data| {
+            if let Some(exchange) = app.server {
+                if exchange.peer == peer && exchange.message_id == msg.message_id {
+                    // A retransmission of the request the process is
+                    // handling; the piggybacked response answers both.
+                    return;
+                }
+                self.reply(peer, msg, code::SERVICE_UNAVAILABLE, None);
+                return;
+            }
+
+            let mut exchange = ServerExchange {
+                peer: peer,
+                resource: idx,
+                token: msg.token,
+                message_id: msg.message_id,
+                confirmable: msg.msg_type == msg_type::CON,
+                observe: false,
+                block1: None,
+                block2: Block {
+                    num: 0,
+                    more: false,
+                    szx: BLOCK_SZX,
+                },
+                response: None,
+            };
+            if let Some(block2) = block2 {
+                exchange.block2 = Block {
+                    num: block2.num,
+                    more: false,
+                    szx: cmp::min(block2.szx, BLOCK_SZX),
+                };
+                // Scale the block number if we use smaller blocks than asked
+                exchange.block2.num = (block2.offset() / exchange.block2.size()) as u32;
+            }
+
+            if msg.code == code::GET && exchange.block2.num > 0 {
+                if let Some(cache) = app.block2_cache.filter(|c| c.resource == idx) {
+                    exchange.response = Some((cache.code, cache.len));
+                    app.server = Some(exchange);
+                    return;
+                }
+            }
+
+            if msg.code == code::GET {
+                match msg.observe() {
+                    Some(0) => {
+                        app.resources[idx].as_mut().map(|resource| {
+                            resource.observer = Some(Observer {
+                                peer: peer,
+                                token: msg.token,
+                                message_id: msg.message_id,
+                                pending: None,
+                            });
+                        });
+                        exchange.observe = true;
+                    }
+                    Some(1) => {
+                        app.resources[idx].as_mut().map(|resource| {
+                            if resource
+                                .observer
+                                .map_or(false, |o| o.peer == peer && o.token == msg.token)
+                            {
+                                resource.observer = None;
+                            }
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            let len = match block1 {
+                Some(block1) => {
+                    let expected = match app.block1 {
+                        Some(t) if t.peer == peer && t.resource == idx => t.next_num,
+                        _ => 0,
+                    };
+                    if block1.num != expected {
+                        app.block1 = None;
+                        self.reply(peer, msg, code::REQUEST_ENTITY_INCOMPLETE, None);
+                        return;
+                    }
+                    let offset = block1.offset();
+                    let end = offset + msg.payload.len();
+                    let copied = kernel_data
+                        .get_readwrite_processbuffer(rw_allow::SERVER_RX)
+                        .and_then(|rx| {
+                            rx.mut_enter(|rx| {
+                                if end > rx.len() {
+                                    false
+                                } else {
+                                    rx[offset..end].copy_from_slice(msg.payload);
+                                    true
+                                }
+                            })
+                        })
+                        .unwrap_or(false);
+                    if !copied {
+                        app.block1 = None;
+                        self.reply(peer, msg, code::REQUEST_ENTITY_TOO_LARGE, None);
+                        return;
+                    }
+                    if block1.more {
+                        app.block1 = Some(Block1Transfer {
+                            peer: peer,
+                            resource: idx,
+                            next_num: block1.num + 1,
+                        });
+                        self.reply(peer, msg, code::CONTINUE, Some(block1));
+                        return;
+                    }
+                    app.block1 = None;
+                    exchange.block1 = Some(block1);
+                    end
+                }
+                None => kernel_data
+                    .get_readwrite_processbuffer(rw_allow::SERVER_RX)
+                    .and_then(|rx| {
+                        rx.mut_enter(|rx| {
+                            let len = cmp::min(rx.len(), msg.payload.len());
+                            rx[..len].copy_from_slice(&msg.payload[..len]);
+                            len
+                        })
+                    })
+                    .unwrap_or(0),
+            };
+
+            app.server = Some(exchange);
+            kernel_data
+                .schedule_upcall(upcall::SERVER_REQUEST, (idx, msg.code as usize, len))
+                .ok();
+        });
+    }
+
+    fn handle_response(&self, peer: Peer, msg: &Message) {
+        let mut matched = false;
+        self.apps.each(|_, app, kernel_data| {
+            let mut client = match app.client {
+                Some(client)
+                    if client.peer == peer
+                        && client.token == msg.token
+                        && client.state != ClientState::Send =>
+                {
+                    client
+                }
+                _ => return,
+            };
+            if msg.msg_type == msg_type::ACK && client.message_id != Some(msg.message_id) {
+                return;
+            }
+            matched = true;
+            if client.state == ClientState::Observing && msg.observe().is_none() {
+                // The server ended the observation
+                client.observe = false;
+            }
+
+            if msg.code == code::CONTINUE {
+                if let Some(block1) = msg.block1() {
+                    // Continue with the next block, in the server's block
+                    // size if it asked for smaller blocks
+                    let next = client.block1.offset() + client.block1.size();
+                    let szx = cmp::min(block1.szx, client.block1.szx);
+                    client.block1 = Block {
+                        num: (next / (16 << szx)) as u32,
+                        more: false,
+                        szx: szx,
+                    };
+                    client.message_id = None;
+                    client.retransmissions = 0;
+                    client.timer = None;
+                    client.state = ClientState::Send;
+                    app.client = Some(client);
+                    return;
+                }
+            }
+
+            let block2 = msg.block2();
+            let offset = block2.map_or(0, |b| b.offset());
+            let end = kernel_data
+                .get_readwrite_processbuffer(rw_allow::CLIENT_RX)
+                .and_then(|rx| {
+                    rx.mut_enter(|rx| {
+                        let start = cmp::min(offset, rx.len());
+                        let end = cmp::min(offset + msg.payload.len(), rx.len());
+                        rx[start..end].copy_from_slice(&msg.payload[..end - start]);
+                        end
+                    })
+                })
+                .unwrap_or(0);
+            client.rx_len = cmp::max(client.rx_len, end);
+
+            if let Some(block2) = block2.filter(|b| b.more) {
+                client.block2 = Some(Block {
+                    num: block2.num + 1,
+                    more: false,
+                    szx: block2.szx,
+                });
+                client.message_id = None;
+                client.retransmissions = 0;
+                client.timer = None;
+                client.state = ClientState::Send;
+                app.client = Some(client);
+                return;
+            }
+
+            kernel_data
+                .schedule_upcall(
+                    upcall::CLIENT_RESPONSE,
+                    (into_statuscode(Ok(())), msg.code as usize, client.rx_len),
+                )
+                .ok();
+            // Only a success response establishes an observation
+            if client.observe && msg.code >> 5 == 2 {
+                client.state = ClientState::Observing;
+                client.message_id = None;
+                client.block2 = None;
+                client.rx_len = 0;
+                client.timer = None;
+                app.client = Some(client);
+            } else {
+                app.client = None;
+            }
+        });
+
+        if msg.msg_type == msg_type::CON {
+            let reply = if matched {
+                msg_type::ACK
+            } else {
+                msg_type::RST
+            };
+            self.queue_empty(peer, reply, msg.message_id);
+        } else if msg.msg_type == msg_type::NON && !matched {
+            self.queue_empty(peer, msg_type::RST, msg.message_id);
+        }
+    }
+
+    fn register(&self, processid: ProcessId) -> Result<usize, ErrorCode> {
+        let path = self
+            .apps
+            .enter(processid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::CFG)
+                    .and_then(|cfg| {
+                        cfg.enter(|cfg| {
+                            if cfg.len() > MAX_PATH_LEN + 2 {
+                                return None;
+                            }
+                            let mut tmp = [0; MAX_PATH_LEN + 2];
+                            cfg.copy_to_slice(&mut tmp[..cfg.len()]);
+                            Path::parse(&tmp[..cfg.len()])
+                        })
+                    })
+                    .unwrap_or(None)
+            })?
+            .ok_or(ErrorCode::INVAL)?;
+
+        let mut taken = false;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                taken |= app.resources.iter().flatten().any(|r| r.path == path);
+            });
+        }
+        if taken {
+            return Err(ErrorCode::BUSY);
+        }
+
+        self.apps.enter(processid, |app, _| {
+            let idx = app
+                .resources
+                .iter()
+                .position(|r| r.is_none())
+                .ok_or(ErrorCode::NOMEM)?;
+            app.resources[idx] = Some(Resource {
+                path: path,
+                observer: None,
+            });
+            Ok(idx)
+        })?
+    }
+
+    fn request(&self, processid: ProcessId, flags: usize, len: usize) -> Result<(), ErrorCode> {
+        let method = (flags & 0xff) as u8;
+        if !message::is_request(method) {
+            return Err(ErrorCode::INVAL);
+        }
+        let token = self.next_random().to_ne_bytes();
+        let jitter = self.next_random() % ACK_RANDOM_MS;
+        self.apps.enter(processid, |app, kernel_data| {
+            if app.client.is_some() {
+                return Err(ErrorCode::BUSY);
+            }
+            let tx_len = kernel_data
+                .get_readonly_processbuffer(ro_allow::TX)
+                .map_or(0, |tx| tx.len());
+            if len > tx_len {
+                return Err(ErrorCode::SIZE);
+            }
+            let (peer, path) = kernel_data
+                .get_readonly_processbuffer(ro_allow::CFG)
+                .and_then(|cfg| {
+                    cfg.enter(|cfg| {
+                        if cfg.len() < 18 || cfg.len() > 18 + MAX_PATH_LEN + 2 {
+                            return None;
+                        }
+                        let mut tmp = [0; 18 + MAX_PATH_LEN + 2];
+                        cfg.copy_to_slice(&mut tmp[..cfg.len()]);
+                        let mut addr = IPAddr([0; 16]);
+                        addr.0.copy_from_slice(&tmp[..16]);
+                        let port = host_slice_to_u16(&tmp[16..18]);
+                        Path::parse(&tmp[18..cfg.len()]).map(|path| {
+                            (
+                                Peer {
+                                    addr: addr,
+                                    port: port,
+                                },
+                                path,
+                            )
+                        })
+                    })
+                })
+                .unwrap_or(None)
+                .ok_or(ErrorCode::INVAL)?;
+
+            app.client = Some(ClientExchange {
+                peer: peer,
+                path: path,
+                method: method,
+                confirmable: flags & CONFIRMABLE != 0,
+                observe: flags & OBSERVE != 0 && method == code::GET,
+                token: Token::new(&token).unwrap_or_default(),
+                message_id: None,
+                payload_len: len,
+                block1: Block {
+                    num: 0,
+                    more: false,
+                    szx: BLOCK_SZX,
+                },
+                block2: None,
+                rx_len: 0,
+                state: ClientState::Send,
+                retransmissions: 0,
+                timeout_ms: ACK_TIMEOUT_MS + jitter,
+                timer: None,
+            });
+            Ok(())
+        })?
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for CoapDriver<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        self.apps.each(|_, app, kernel_data| {
+            let mut client = match app.client {
+                Some(client) => client,
+                None => return,
+            };
+            let expired = client.timer.map_or(false, |(reference, dt)| {
+                now.wrapping_sub(A::Ticks::from(reference)).into_u32() >= dt
+            });
+            if !expired {
+                return;
+            }
+            client.timer = None;
+            match client.state {
+                ClientState::AwaitAck if client.retransmissions < MAX_RETRANSMIT => {
+                    client.retransmissions += 1;
+                    client.timeout_ms *= 2;
+                    client.state = ClientState::Send;
+                    app.client = Some(client);
+                }
+                ClientState::AwaitAck => self.fail_client(app, kernel_data, ErrorCode::NOACK),
+                _ => self.fail_client(app, kernel_data, ErrorCode::FAIL),
+            }
+        });
+        self.do_next_tx();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for CoapDriver<'a, A> {
+    fn send_done(
+        &self,
+        _result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        // Lost messages are recovered by retransmission, so the result only
+        // matters for freeing the buffer.
+        dgram.reset();
+        self.tx_buffer.replace(dgram);
+        self.do_next_tx();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for CoapDriver<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let peer = Peer {
+            addr: src_addr,
+            port: src_port,
+        };
+        match Message::decode(payload) {
+            SResult::Done(_, msg) => {
+                if msg.code == code::EMPTY {
+                    self.handle_empty(peer, &msg);
+                } else if message::is_request(msg.code) {
+                    if msg.msg_type == msg_type::CON || msg.msg_type == msg_type::NON {
+                        self.handle_request(peer, &msg);
+                    }
+                } else if message::is_response(msg.code) {
+                    self.handle_response(peer, &msg);
+                } else if msg.msg_type == msg_type::CON {
+                    self.queue_empty(peer, msg_type::RST, msg.message_id);
+                }
+            }
+            _ => {
+                // Reject malformed confirmable messages
+                if payload.len() >= message::HEADER_LEN && (payload[0] >> 4) & 0x3 == msg_type::CON
+                {
+                    self.queue_empty(
+                        peer,
+                        msg_type::RST,
+                        u16::from_be_bytes([payload[2], payload[3]]),
+                    );
+                }
+            }
+        }
+        self.do_next_tx();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> SyscallDriver for CoapDriver<'a, A> {
+    /// CoAP control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Register the resource path in read-only allow 1. Returns the
+    ///        resource index.
+    /// - `2`: Unregister resource `arg1`.
+    /// - `3`: Respond to the pending request with code `arg1` and `arg2`
+    ///        bytes of payload.
+    /// - `4`: Notify the observer of resource `arg1` with `arg2` bytes of
+    ///        payload. Returns the number of observers notified.
+    /// - `5`: Send a request with method and flags `arg1` and `arg2` bytes
+    ///        of payload to the destination in read-only allow 1.
+    /// - `6`: Cancel the outstanding request.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => match self.register(processid) {
+                Ok(idx) => CommandReturn::success_u32(idx as u32),
+                Err(e) => CommandReturn::failure(e),
+            },
+
+            2 => self
+                .apps
+                .enter(processid, |app, _| {
+                    if arg1 >= MAX_RESOURCES || app.resources[arg1].is_none() {
+                        return CommandReturn::failure(ErrorCode::INVAL);
+                    }
+                    app.resources[arg1] = None;
+                    if app.server.map_or(false, |e| e.resource == arg1) {
+                        app.server = None;
+                    }
+                    if app.block1.map_or(false, |t| t.resource == arg1) {
+                        app.block1 = None;
+                    }
+                    if app.block2_cache.map_or(false, |c| c.resource == arg1) {
+                        app.block2_cache = None;
+                    }
+                    CommandReturn::success()
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
+            3 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, kernel_data| {
+                        let tx_len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::TX)
+                            .map_or(0, |tx| tx.len());
+                        match app.server.as_mut() {
+                            None => Err(ErrorCode::INVAL),
+                            Some(exchange) if exchange.response.is_some() => {
+                                Err(ErrorCode::ALREADY)
+                            }
+                            Some(_) if !message::is_response(arg1 as u8) => Err(ErrorCode::INVAL),
+                            Some(_) if arg2 > tx_len => Err(ErrorCode::SIZE),
+                            Some(exchange) => {
+                                exchange.response = Some((arg1 as u8, arg2));
+                                Ok(())
+                            }
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                if result.is_ok() {
+                    self.do_next_tx();
+                }
+                CommandReturn::from(result)
+            }
+
+            4 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, kernel_data| {
+                        let tx_len = kernel_data
+                            .get_readonly_processbuffer(ro_allow::TX)
+                            .map_or(0, |tx| tx.len());
+                        if arg2 > tx_len {
+                            return Err(ErrorCode::SIZE);
+                        }
+                        match app.resources.get_mut(arg1).and_then(|r| r.as_mut()) {
+                            None => Err(ErrorCode::INVAL),
+                            Some(resource) => Ok(resource.observer.as_mut().map_or(0, |o| {
+                                o.pending = Some(arg2);
+                                1
+                            })),
+                        }
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                match result {
+                    Ok(count) => {
+                        self.do_next_tx();
+                        CommandReturn::success_u32(count)
+                    }
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            5 => {
+                let result = self.request(processid, arg1, arg2);
+                if result.is_ok() {
+                    self.do_next_tx();
+                }
+                CommandReturn::from(result)
+            }
+
+            6 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| app.client.take().map(|_| ()))
+                    .map_err(ErrorCode::from)
+                    .and_then(|cancelled| cancelled.ok_or(ErrorCode::ALREADY));
+                self.arm_timer();
+                CommandReturn::from(result)
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
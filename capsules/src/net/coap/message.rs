@@ -0,0 +1,494 @@
+//! Encoding and decoding of CoAP messages (RFC 7252), including the
+//! Observe (RFC 7641) and Block1/Block2 (RFC 7959) options.
+//!
+//! A message is decoded in place: `Message` borrows its options and payload
+//! from the received datagram. Messages are encoded with an `Encoder`, which
+//! writes the header and token, then options in increasing option number,
+//! then the payload.
+
+use crate::net::stream::SResult;
+
+use kernel::ErrorCode;
+
+pub const HEADER_LEN: usize = 4;
+pub const MAX_TOKEN_LEN: usize = 8;
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// Message types.
+pub mod msg_type {
+    pub const CON: u8 = 0;
+    pub const NON: u8 = 1;
+    pub const ACK: u8 = 2;
+    pub const RST: u8 = 3;
+}
+
+/// Method and response codes, as `class << 5 | detail`.
+pub mod code {
+    pub const EMPTY: u8 = 0x00;
+    pub const GET: u8 = 0x01;
+    pub const POST: u8 = 0x02;
+    pub const PUT: u8 = 0x03;
+    pub const DELETE: u8 = 0x04;
+
+    pub const CREATED: u8 = 0x41;
+    pub const DELETED: u8 = 0x42;
+    pub const VALID: u8 = 0x43;
+    pub const CHANGED: u8 = 0x44;
+    pub const CONTENT: u8 = 0x45;
+    pub const CONTINUE: u8 = 0x5f;
+
+    pub const BAD_REQUEST: u8 = 0x80;
+    pub const BAD_OPTION: u8 = 0x82;
+    pub const NOT_FOUND: u8 = 0x84;
+    pub const METHOD_NOT_ALLOWED: u8 = 0x85;
+    pub const REQUEST_ENTITY_INCOMPLETE: u8 = 0x88;
+    pub const REQUEST_ENTITY_TOO_LARGE: u8 = 0x8d;
+
+    pub const INTERNAL_SERVER_ERROR: u8 = 0xa0;
+    pub const SERVICE_UNAVAILABLE: u8 = 0xa3;
+}
+
+/// Option numbers.
+pub mod option {
+    pub const IF_MATCH: u16 = 1;
+    pub const URI_HOST: u16 = 3;
+    pub const ETAG: u16 = 4;
+    pub const IF_NONE_MATCH: u16 = 5;
+    pub const OBSERVE: u16 = 6;
+    pub const URI_PORT: u16 = 7;
+    pub const LOCATION_PATH: u16 = 8;
+    pub const URI_PATH: u16 = 11;
+    pub const CONTENT_FORMAT: u16 = 12;
+    pub const MAX_AGE: u16 = 14;
+    pub const URI_QUERY: u16 = 15;
+    pub const ACCEPT: u16 = 17;
+    pub const LOCATION_QUERY: u16 = 20;
+    pub const BLOCK2: u16 = 23;
+    pub const BLOCK1: u16 = 27;
+    pub const SIZE2: u16 = 28;
+    pub const PROXY_URI: u16 = 35;
+    pub const PROXY_SCHEME: u16 = 39;
+    pub const SIZE1: u16 = 60;
+
+    /// Whether a recipient must reject a message with an option it does
+    /// not understand.
+    pub fn is_critical(number: u16) -> bool {
+        number & 1 == 1
+    }
+}
+
+/// Whether `code` is a method code, i.e. the message is a request.
+pub fn is_request(code: u8) -> bool {
+    code != code::EMPTY && code >> 5 == 0
+}
+
+/// Whether `code` is a response code.
+pub fn is_response(code: u8) -> bool {
+    (2..=5).contains(&(code >> 5))
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Token {
+    len: u8,
+    bytes: [u8; MAX_TOKEN_LEN],
+}
+
+impl Token {
+    pub fn new(token: &[u8]) -> Option<Token> {
+        if token.len() > MAX_TOKEN_LEN {
+            return None;
+        }
+        let mut bytes = [0; MAX_TOKEN_LEN];
+        bytes[..token.len()].copy_from_slice(token);
+        Some(Token {
+            len: token.len() as u8,
+            bytes: bytes,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+}
+
+/// The value of a Block1 or Block2 option.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Block {
+    /// The number of the block, in units of the block size.
+    pub num: u32,
+    /// Whether more blocks follow.
+    pub more: bool,
+    /// The block size exponent: blocks are `16 << szx` bytes long.
+    pub szx: u8,
+}
+
+impl Block {
+    /// The largest block size exponent, for 1024-byte blocks. 7 is
+    /// reserved.
+    pub const MAX_SZX: u8 = 6;
+
+    pub fn decode(value: u32) -> Option<Block> {
+        let szx = (value & 0x7) as u8;
+        if szx > Block::MAX_SZX || value >> 4 >= 1 << 20 {
+            return None;
+        }
+        Some(Block {
+            num: value >> 4,
+            more: value & 0x8 != 0,
+            szx: szx,
+        })
+    }
+
+    pub fn encode(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+
+    pub fn size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// The offset of the block in the whole body.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// A decoded CoAP message.
+pub struct Message<'a> {
+    pub msg_type: u8,
+    pub code: u8,
+    pub message_id: u16,
+    pub token: Token,
+    options: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// Decodes a message, checking that its options are well-formed.
+    pub fn decode(buf: &'a [u8]) -> SResult<Message<'a>> {
+        stream_len_cond!(buf, HEADER_LEN);
+        if buf[0] >> 6 != VERSION {
+            stream_err!();
+        }
+        let token_len = (buf[0] & 0xf) as usize;
+        if token_len > MAX_TOKEN_LEN {
+            stream_err!();
+        }
+        stream_len_cond!(buf, HEADER_LEN + token_len);
+        let token = Token::new(&buf[HEADER_LEN..HEADER_LEN + token_len]).unwrap_or_default();
+
+        let start = HEADER_LEN + token_len;
+        let mut off = start;
+        let mut number = 0;
+        loop {
+            match parse_option(&buf[off..], number) {
+                Ok(Some((n, _, len))) => {
+                    number = n;
+                    off += len;
+                }
+                Ok(None) => break,
+                Err(()) => stream_err!(),
+            }
+        }
+        let payload = if off < buf.len() {
+            // A payload marker followed by an empty payload is an error
+            if off + 1 == buf.len() {
+                stream_err!();
+            }
+            &buf[off + 1..]
+        } else {
+            &buf[off..]
+        };
+
+        stream_done!(
+            buf.len(),
+            Message {
+                msg_type: (buf[0] >> 4) & 0x3,
+                code: buf[1],
+                message_id: u16::from_be_bytes([buf[2], buf[3]]),
+                token: token,
+                options: &buf[start..off],
+                payload: payload,
+            }
+        )
+    }
+
+    /// Iterates over the options of the message, as (number, value) pairs.
+    pub fn options(&self) -> Options<'a> {
+        Options {
+            buf: self.options,
+            number: 0,
+        }
+    }
+
+    /// Returns the first value of option `number`.
+    pub fn option(&self, number: u16) -> Option<&'a [u8]> {
+        self.options()
+            .find(|&(n, _)| n == number)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns the first value of option `number` as an unsigned integer.
+    pub fn uint_option(&self, number: u16) -> Option<u32> {
+        self.option(number).and_then(|value| {
+            if value.len() > 4 {
+                None
+            } else {
+                Some(value.iter().fold(0, |acc, b| acc << 8 | *b as u32))
+            }
+        })
+    }
+
+    pub fn observe(&self) -> Option<u32> {
+        self.uint_option(option::OBSERVE)
+    }
+
+    pub fn block1(&self) -> Option<Block> {
+        self.uint_option(option::BLOCK1).and_then(Block::decode)
+    }
+
+    pub fn block2(&self) -> Option<Block> {
+        self.uint_option(option::BLOCK2).and_then(Block::decode)
+    }
+}
+
+pub struct Options<'a> {
+    buf: &'a [u8],
+    number: u16,
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u16, &'a [u8])> {
+        match parse_option(self.buf, self.number) {
+            Ok(Some((number, value, len))) => {
+                self.number = number;
+                self.buf = &self.buf[len..];
+                Some((number, value))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses the option at the start of `buf`, which follows option number
+/// `number`. Returns the option number, value and encoded length, or `None`
+/// at the end of the options.
+fn parse_option(buf: &[u8], number: u16) -> Result<Option<(u16, &[u8], usize)>, ()> {
+    if buf.is_empty() || buf[0] == PAYLOAD_MARKER {
+        return Ok(None);
+    }
+    let mut off = 1;
+    let delta = parse_extended(buf[0] >> 4, buf, &mut off)?;
+    let len = parse_extended(buf[0] & 0xf, buf, &mut off)? as usize;
+    let number = number.checked_add(delta).ok_or(())?;
+    let value = buf.get(off..off + len).ok_or(())?;
+    Ok(Some((number, value, off + len)))
+}
+
+fn parse_extended(nibble: u8, buf: &[u8], off: &mut usize) -> Result<u16, ()> {
+    match nibble {
+        0..=12 => Ok(nibble as u16),
+        13 => {
+            let value = *buf.get(*off).ok_or(())? as u16 + 13;
+            *off += 1;
+            Ok(value)
+        }
+        14 => {
+            let bytes = buf.get(*off..*off + 2).ok_or(())?;
+            *off += 2;
+            u16::from_be_bytes([bytes[0], bytes[1]])
+                .checked_add(269)
+                .ok_or(())
+        }
+        _ => Err(()),
+    }
+}
+
+/// Writes a message into a buffer.
+pub struct Encoder<'b> {
+    buf: &'b mut [u8],
+    off: usize,
+    number: u16,
+}
+
+impl<'b> Encoder<'b> {
+    /// Writes the header and token of a message.
+    pub fn new(
+        buf: &'b mut [u8],
+        msg_type: u8,
+        code: u8,
+        message_id: u16,
+        token: &Token,
+    ) -> Result<Encoder<'b>, ErrorCode> {
+        let token = token.as_slice();
+        let len = HEADER_LEN + token.len();
+        if buf.len() < len {
+            return Err(ErrorCode::SIZE);
+        }
+        buf[0] = VERSION << 6 | (msg_type & 0x3) << 4 | token.len() as u8;
+        buf[1] = code;
+        buf[2..4].copy_from_slice(&message_id.to_be_bytes());
+        buf[HEADER_LEN..len].copy_from_slice(token);
+        Ok(Encoder {
+            buf: buf,
+            off: len,
+            number: 0,
+        })
+    }
+
+    /// Adds an option. Options must be added in increasing option number.
+    pub fn option(&mut self, number: u16, value: &[u8]) -> Result<(), ErrorCode> {
+        if number < self.number {
+            return Err(ErrorCode::INVAL);
+        }
+        let delta = number - self.number;
+        let header_len = 1 + extended_len(delta) + extended_len(value.len() as u16);
+        let end = self.off + header_len + value.len();
+        if value.len() > u16::MAX as usize || end > self.buf.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        let mut off = self.off + 1;
+        let delta_nibble = write_extended(delta, self.buf, &mut off);
+        let len_nibble = write_extended(value.len() as u16, self.buf, &mut off);
+        self.buf[self.off] = delta_nibble << 4 | len_nibble;
+        self.buf[off..end].copy_from_slice(value);
+        self.off = end;
+        self.number = number;
+        Ok(())
+    }
+
+    /// Adds an option with an unsigned integer value, in as few bytes as
+    /// possible.
+    pub fn uint_option(&mut self, number: u16, value: u32) -> Result<(), ErrorCode> {
+        let bytes = value.to_be_bytes();
+        let skip = (value.leading_zeros() / 8) as usize;
+        self.option(number, &bytes[skip..])
+    }
+
+    /// Adds one option `number` for each segment of the `/`-separated
+    /// `path`, as for Uri-Path.
+    pub fn path_options(&mut self, number: u16, path: &[u8]) -> Result<(), ErrorCode> {
+        for segment in path.split(|b| *b == b'/').filter(|s| !s.is_empty()) {
+            self.option(number, segment)?;
+        }
+        Ok(())
+    }
+
+    /// Ends the options and returns the space for a `len`-byte payload, to
+    /// be filled in by the caller.
+    pub fn payload(&mut self, len: usize) -> Result<&mut [u8], ErrorCode> {
+        if len == 0 {
+            return Ok(&mut self.buf[self.off..self.off]);
+        }
+        if self.off + 1 + len > self.buf.len() {
+            return Err(ErrorCode::SIZE);
+        }
+        self.buf[self.off] = PAYLOAD_MARKER;
+        let start = self.off + 1;
+        self.off = start + len;
+        Ok(&mut self.buf[start..start + len])
+    }
+
+    /// The length of the message written so far.
+    pub fn len(&self) -> usize {
+        self.off
+    }
+}
+
+fn extended_len(value: u16) -> usize {
+    match value {
+        0..=12 => 0,
+        13..=268 => 1,
+        _ => 2,
+    }
+}
+
+fn write_extended(value: u16, buf: &mut [u8], off: &mut usize) -> u8 {
+    match value {
+        0..=12 => value as u8,
+        13..=268 => {
+            buf[*off] = (value - 13) as u8;
+            *off += 1;
+            13
+        }
+        _ => {
+            buf[*off..*off + 2].copy_from_slice(&(value - 269).to_be_bytes());
+            *off += 2;
+            14
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let mut buf = [0; 64];
+        let token = Token::new(&[0xaa, 0xbb]).unwrap();
+        let mut encoder = Encoder::new(&mut buf, msg_type::CON, code::GET, 0x1234, &token).unwrap();
+        encoder.uint_option(option::OBSERVE, 0).unwrap();
+        encoder
+            .path_options(option::URI_PATH, b"/sensors/temperature")
+            .unwrap();
+        let block = Block {
+            num: 20,
+            more: true,
+            szx: 2,
+        };
+        encoder.uint_option(option::BLOCK2, block.encode()).unwrap();
+        encoder.option(option::SIZE1, &[0x01, 0x00]).unwrap();
+        encoder.payload(3).unwrap().copy_from_slice(b"abc");
+        let len = encoder.len();
+
+        let message = match Message::decode(&buf[..len]) {
+            SResult::Done(_, message) => message,
+            _ => panic!("decoding failed"),
+        };
+        assert_eq!(message.msg_type, msg_type::CON);
+        assert_eq!(message.code, code::GET);
+        assert_eq!(message.message_id, 0x1234);
+        assert_eq!(message.token, token);
+        assert_eq!(message.observe(), Some(0));
+        assert_eq!(message.block2(), Some(block));
+        assert_eq!(message.uint_option(option::SIZE1), Some(256));
+        let mut path = message
+            .options()
+            .filter(|&(n, _)| n == option::URI_PATH)
+            .map(|(_, v)| v);
+        assert_eq!(path.next(), Some(&b"sensors"[..]));
+        assert_eq!(path.next(), Some(&b"temperature"[..]));
+        assert_eq!(path.next(), None);
+        assert_eq!(message.payload, b"abc");
+    }
+
+    #[test]
+    fn options_out_of_order() {
+        let mut buf = [0; 16];
+        let mut encoder =
+            Encoder::new(&mut buf, msg_type::NON, code::CONTENT, 1, &Token::default()).unwrap();
+        encoder.uint_option(option::BLOCK2, 0).unwrap();
+        assert_eq!(
+            encoder.option(option::URI_PATH, b"a"),
+            Err(ErrorCode::INVAL)
+        );
+    }
+
+    #[test]
+    fn malformed_messages() {
+        // Reserved option length nibble
+        let buf = [0x40, 0x01, 0x00, 0x01, 0xbf];
+        assert!(matches!(Message::decode(&buf), SResult::Error(())));
+        // Payload marker without a payload
+        let buf = [0x40, 0x01, 0x00, 0x01, 0xff];
+        assert!(matches!(Message::decode(&buf), SResult::Error(())));
+        // Token longer than the message
+        let buf = [0x44, 0x01, 0x00, 0x01, 0x00];
+        assert!(matches!(Message::decode(&buf), SResult::Needed(_)));
+    }
+}
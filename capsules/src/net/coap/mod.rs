@@ -0,0 +1,10 @@
+//! Constrained Application Protocol (RFC 7252) over UDP.
+//!
+//! `message` encodes and decodes CoAP messages, and `driver` multiplexes the
+//! CoAP port between processes that serve and request resources.
+
+pub mod driver;
+pub mod message;
+
+pub use self::driver::CoapDriver;
+pub use self::driver::DRIVER_NUM;
@@ -0,0 +1,75 @@
+//! Ethernet II framing for IPv6 packets (RFC 2464).
+//!
+//! This file contains the Ethernet header and the mappings between IPv6 and
+//! Ethernet addresses. The IPv6 link layer built on it lives in
+//! `net::ipv6::ipv6_ethernet`.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16};
+use crate::net::stream::{encode_bytes, encode_u16};
+
+use kernel::hil::ethernet::{MacAddress, HEADER_LEN};
+
+pub mod ethertype {
+    pub const IPV6: u16 = 0x86dd;
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct EthernetHeader {
+    pub dst: MacAddress,
+    pub src: MacAddress,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    pub fn new(dst: MacAddress, src: MacAddress, ethertype: u16) -> EthernetHeader {
+        EthernetHeader {
+            dst: dst,
+            src: src,
+            ethertype: ethertype,
+        }
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> SResult<usize> {
+        stream_len_cond!(buf, HEADER_LEN);
+        let off = enc_consume!(buf, 0; encode_bytes, &self.dst.0);
+        let off = enc_consume!(buf, off; encode_bytes, &self.src.0);
+        let off = enc_consume!(buf, off; encode_u16, self.ethertype);
+        stream_done!(off, off);
+    }
+
+    pub fn decode(buf: &[u8]) -> SResult<EthernetHeader> {
+        stream_len_cond!(buf, HEADER_LEN);
+        let mut header = EthernetHeader::new(MacAddress([0; 6]), MacAddress([0; 6]), 0);
+        let off = dec_consume!(buf, 0; decode_bytes, &mut header.dst.0);
+        let off = dec_consume!(buf, off; decode_bytes, &mut header.src.0);
+        let (off, ethertype) = dec_try!(buf, off; decode_u16);
+        header.ethertype = ethertype;
+        stream_done!(off, header);
+    }
+}
+
+/// Returns the Ethernet multicast address (33:33:XX:XX:XX:XX) that packets to
+/// the IPv6 multicast address `addr` are sent to (RFC 2464, section 7).
+pub fn multicast_mac_address(addr: &IPAddr) -> MacAddress {
+    let mut mac = [0x33, 0x33, 0, 0, 0, 0];
+    mac[2..].copy_from_slice(&addr.0[12..16]);
+    MacAddress(mac)
+}
+
+/// Returns the link-local address formed from the modified EUI-64 interface
+/// identifier of `mac` (RFC 2464, sections 4 and 5).
+pub fn link_local_address(mac: &MacAddress) -> IPAddr {
+    let mut addr = IPAddr::new();
+    addr.set_unicast_link_local();
+    addr.0[8] = mac.0[0] ^ 0b00000010;
+    addr.0[9] = mac.0[1];
+    addr.0[10] = mac.0[2];
+    addr.0[11] = 0xff;
+    addr.0[12] = 0xfe;
+    addr.0[13] = mac.0[3];
+    addr.0[14] = mac.0[4];
+    addr.0[15] = mac.0[5];
+    addr
+}
@@ -71,9 +71,11 @@ impl Bitmap {
         for i in 0..total_length / 8 {
             result = result && (self.map[i] == 0xff);
         }
-        // Check last byte.
-        let mask = 0xff >> (8 - (total_length % 8));
-        result = result && (self.map[total_length / 8] == mask);
+        // Check last byte, unless the length ends on a byte boundary.
+        if total_length % 8 != 0 {
+            let mask = 0xff >> (8 - (total_length % 8));
+            result = result && (self.map[total_length / 8] == mask);
+        }
         result
     }
 }
@@ -1,4 +1,6 @@
 pub mod icmpv6_send;
+pub mod ndp;
+pub mod rpl;
 
 // Reexport the exports of the [`icmpv6`] module, to avoid redundant
 // module paths (e.g. `capsules::net::icmpv6::icmpv6::ICMP6Header`)
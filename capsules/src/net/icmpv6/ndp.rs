@@ -0,0 +1,474 @@
+//! Neighbor Discovery for IPv6 (RFC 4861).
+//!
+//! This file contains the encoding and decoding of Neighbor Discovery
+//! messages, and a small neighbor cache mapping IPv6 addresses to link-layer
+//! addresses. It is independent of the link layer: link-layer addresses in
+//! options are carried as byte slices whose length depends on the link (6
+//! bytes for Ethernet; 2 or 8 bytes for IEEE 802.15.4 short and extended
+//! addresses, RFC 4944 section 8). Received options are returned without
+//! their padding trimmed, so links with several address lengths must trim
+//! them based on the option length.
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u32, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u32, encode_u8};
+
+use core::cell::Cell;
+
+/// ICMPv6 message types used by Neighbor Discovery.
+pub mod ndp_type {
+    pub const ROUTER_SOLICITATION: u8 = 133;
+    pub const ROUTER_ADVERTISEMENT: u8 = 134;
+    pub const NEIGHBOR_SOLICITATION: u8 = 135;
+    pub const NEIGHBOR_ADVERTISEMENT: u8 = 136;
+    pub const REDIRECT: u8 = 137;
+}
+
+mod option_type {
+    pub const SOURCE_LINK_ADDR: u8 = 1;
+    pub const TARGET_LINK_ADDR: u8 = 2;
+    pub const PREFIX_INFORMATION: u8 = 3;
+}
+
+/// Hop limit that Neighbor Discovery messages are sent with. Received messages
+/// with any other hop limit must be discarded, since they may have been
+/// forwarded by a router.
+pub const ND_HOP_LIMIT: u8 = 255;
+
+/// Length of the longest supported link-layer address.
+pub const MAX_LINK_ADDR_LEN: usize = 8;
+
+// Router Advertisement flags
+const RA_FLAG_MANAGED: u8 = 1 << 7;
+const RA_FLAG_OTHER: u8 = 1 << 6;
+
+// Neighbor Advertisement flags
+const NA_FLAG_ROUTER: u32 = 1 << 31;
+const NA_FLAG_SOLICITED: u32 = 1 << 30;
+const NA_FLAG_OVERRIDE: u32 = 1 << 29;
+
+// Prefix Information flags
+const PI_FLAG_ON_LINK: u8 = 1 << 7;
+const PI_FLAG_AUTONOMOUS: u8 = 1 << 6;
+const PI_OPTION_LEN: usize = 32;
+
+/// A link-layer address carried in a Neighbor Discovery option.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct LinkAddress {
+    len: usize,
+    bytes: [u8; MAX_LINK_ADDR_LEN],
+}
+
+impl LinkAddress {
+    /// Panics if `addr` is longer than `MAX_LINK_ADDR_LEN`.
+    pub fn new(addr: &[u8]) -> LinkAddress {
+        let mut bytes = [0; MAX_LINK_ADDR_LEN];
+        bytes[..addr.len()].copy_from_slice(addr);
+        LinkAddress {
+            len: addr.len(),
+            bytes: bytes,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    // Options are padded to a multiple of 8 bytes, including the 2-byte
+    // option header
+    fn option_len(&self) -> usize {
+        (2 + self.len + 7) / 8 * 8
+    }
+
+    fn encode_option(&self, buf: &mut [u8], option_type: u8) -> SResult {
+        let option_len = self.option_len();
+        stream_len_cond!(buf, option_len);
+        let off = enc_consume!(buf, 0; encode_u8, option_type);
+        let off = enc_consume!(buf, off; encode_u8, (option_len / 8) as u8);
+        let off = enc_consume!(buf, off; encode_bytes, self.as_slice());
+        buf[off..option_len].iter_mut().for_each(|b| *b = 0);
+        stream_done!(option_len);
+    }
+
+    // Takes as many bytes of the option body as fit, which includes any
+    // padding of addresses shorter than `MAX_LINK_ADDR_LEN`
+    fn decode_option(body: &[u8]) -> LinkAddress {
+        LinkAddress::new(&body[..core::cmp::min(body.len(), MAX_LINK_ADDR_LEN)])
+    }
+}
+
+/// The Prefix Information option of a Router Advertisement. RPL DIO messages
+/// carry the same option body with a different option header.
+#[derive(Copy, Clone, Debug)]
+pub struct PrefixInformation {
+    pub prefix_len: u8,
+    pub on_link: bool,
+    pub autonomous: bool,
+    /// In seconds; 0xffffffff is infinity.
+    pub valid_lifetime: u32,
+    /// In seconds; 0xffffffff is infinity.
+    pub preferred_lifetime: u32,
+    pub prefix: IPAddr,
+}
+
+impl PrefixInformation {
+    fn encode_option(&self, buf: &mut [u8]) -> SResult {
+        let off = enc_consume!(buf, 0; encode_u8, option_type::PREFIX_INFORMATION);
+        let off = enc_consume!(buf, off; encode_u8, (PI_OPTION_LEN / 8) as u8);
+        let off = enc_consume!(buf, off; self; encode_body);
+        stream_done!(off);
+    }
+
+    /// Encodes the option without its type and length.
+    pub(crate) fn encode_body(&self, buf: &mut [u8]) -> SResult {
+        let mut flags = 0;
+        if self.on_link {
+            flags |= PI_FLAG_ON_LINK;
+        }
+        if self.autonomous {
+            flags |= PI_FLAG_AUTONOMOUS;
+        }
+        let off = enc_consume!(buf, 0; encode_u8, self.prefix_len);
+        let off = enc_consume!(buf, off; encode_u8, flags);
+        let off = enc_consume!(buf, off; encode_u32, self.valid_lifetime);
+        let off = enc_consume!(buf, off; encode_u32, self.preferred_lifetime);
+        let off = enc_consume!(buf, off; encode_u32, 0);
+        let off = enc_consume!(buf, off; encode_bytes, &self.prefix.0);
+        stream_done!(off);
+    }
+
+    /// Decodes the option without its type and length.
+    pub(crate) fn decode_body(body: &[u8]) -> SResult<PrefixInformation> {
+        let (off, prefix_len) = dec_try!(body; decode_u8);
+        stream_cond!(prefix_len <= 128);
+        let (off, flags) = dec_try!(body, off; decode_u8);
+        let (off, valid_lifetime) = dec_try!(body, off; decode_u32);
+        let (off, preferred_lifetime) = dec_try!(body, off; decode_u32);
+        let (off, _reserved) = dec_try!(body, off; decode_u32);
+        let mut prefix = IPAddr::new();
+        let off = dec_consume!(body, off; decode_bytes, &mut prefix.0);
+        stream_done!(
+            off,
+            PrefixInformation {
+                prefix_len: prefix_len,
+                on_link: flags & PI_FLAG_ON_LINK != 0,
+                autonomous: flags & PI_FLAG_AUTONOMOUS != 0,
+                valid_lifetime: valid_lifetime,
+                preferred_lifetime: preferred_lifetime,
+                prefix: prefix,
+            }
+        );
+    }
+}
+
+/// The Neighbor Discovery messages that can be encoded and decoded.
+#[derive(Copy, Clone, Debug)]
+pub enum NdpMessage {
+    RouterSolicitation {
+        source_link_addr: Option<LinkAddress>,
+    },
+    /// Only the first Prefix Information option is kept when decoding.
+    RouterAdvertisement {
+        cur_hop_limit: u8,
+        managed: bool,
+        other: bool,
+        /// In seconds; 0 means the sender is not a default router.
+        router_lifetime: u16,
+        /// In milliseconds; 0 means unspecified.
+        reachable_time: u32,
+        /// In milliseconds; 0 means unspecified.
+        retrans_timer: u32,
+        source_link_addr: Option<LinkAddress>,
+        prefix: Option<PrefixInformation>,
+    },
+    NeighborSolicitation {
+        target: IPAddr,
+        source_link_addr: Option<LinkAddress>,
+    },
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_entry: bool,
+        target: IPAddr,
+        target_link_addr: Option<LinkAddress>,
+    },
+}
+
+impl NdpMessage {
+    /// Encodes the complete ICMPv6 message into `buf`, leaving the checksum
+    /// field zero. The checksum depends on the IPv6 pseudo-header and must be
+    /// filled in by the caller, see `ip_utils::compute_icmp_message_checksum`.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let (icmp_type, link_addr) = match *self {
+            NdpMessage::RouterSolicitation { source_link_addr } => (
+                ndp_type::ROUTER_SOLICITATION,
+                source_link_addr.map(|addr| (option_type::SOURCE_LINK_ADDR, addr)),
+            ),
+            NdpMessage::RouterAdvertisement {
+                source_link_addr, ..
+            } => (
+                ndp_type::ROUTER_ADVERTISEMENT,
+                source_link_addr.map(|addr| (option_type::SOURCE_LINK_ADDR, addr)),
+            ),
+            NdpMessage::NeighborSolicitation {
+                source_link_addr, ..
+            } => (
+                ndp_type::NEIGHBOR_SOLICITATION,
+                source_link_addr.map(|addr| (option_type::SOURCE_LINK_ADDR, addr)),
+            ),
+            NdpMessage::NeighborAdvertisement {
+                target_link_addr, ..
+            } => (
+                ndp_type::NEIGHBOR_ADVERTISEMENT,
+                target_link_addr.map(|addr| (option_type::TARGET_LINK_ADDR, addr)),
+            ),
+        };
+
+        let off = enc_consume!(buf, 0; encode_u8, icmp_type);
+        // Code and checksum
+        let off = enc_consume!(buf, off; encode_bytes, &[0; 3]);
+        let mut off = match *self {
+            NdpMessage::RouterSolicitation { .. } => enc_consume!(buf, off; encode_u32, 0),
+            NdpMessage::RouterAdvertisement {
+                cur_hop_limit,
+                managed,
+                other,
+                router_lifetime,
+                reachable_time,
+                retrans_timer,
+                ..
+            } => {
+                let mut flags = 0;
+                if managed {
+                    flags |= RA_FLAG_MANAGED;
+                }
+                if other {
+                    flags |= RA_FLAG_OTHER;
+                }
+                let off = enc_consume!(buf, off; encode_u8, cur_hop_limit);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u16, router_lifetime);
+                let off = enc_consume!(buf, off; encode_u32, reachable_time);
+                enc_consume!(buf, off; encode_u32, retrans_timer)
+            }
+            NdpMessage::NeighborSolicitation { target, .. } => {
+                let off = enc_consume!(buf, off; encode_u32, 0);
+                enc_consume!(buf, off; encode_bytes, &target.0)
+            }
+            NdpMessage::NeighborAdvertisement {
+                router,
+                solicited,
+                override_entry,
+                target,
+                ..
+            } => {
+                let mut flags = 0;
+                if router {
+                    flags |= NA_FLAG_ROUTER;
+                }
+                if solicited {
+                    flags |= NA_FLAG_SOLICITED;
+                }
+                if override_entry {
+                    flags |= NA_FLAG_OVERRIDE;
+                }
+                let off = enc_consume!(buf, off; encode_u32, flags);
+                enc_consume!(buf, off; encode_bytes, &target.0)
+            }
+        };
+        if let Some((option_type, addr)) = link_addr {
+            off = enc_consume!(buf, off; addr; encode_option, option_type);
+        }
+        if let NdpMessage::RouterAdvertisement {
+            prefix: Some(prefix),
+            ..
+        } = *self
+        {
+            off = enc_consume!(buf, off; prefix; encode_option);
+        }
+        stream_done!(off);
+    }
+
+    /// Decodes a Neighbor Discovery message, ignoring unknown options.
+    pub fn decode(buf: &[u8]) -> SResult<NdpMessage> {
+        let (off, icmp_type) = dec_try!(buf; decode_u8);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        stream_cond!(code == 0);
+        // Skip the checksum, which is verified by the caller
+        let off = dec_consume!(buf, off; decode_bytes, &mut [0; 2]);
+
+        let (off, mut message) = match icmp_type {
+            ndp_type::ROUTER_SOLICITATION => {
+                let (off, _reserved) = dec_try!(buf, off; decode_u32);
+                (
+                    off,
+                    NdpMessage::RouterSolicitation {
+                        source_link_addr: None,
+                    },
+                )
+            }
+            ndp_type::ROUTER_ADVERTISEMENT => {
+                let (off, cur_hop_limit) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, router_lifetime) = dec_try!(buf, off; decode_u16);
+                let (off, reachable_time) = dec_try!(buf, off; decode_u32);
+                let (off, retrans_timer) = dec_try!(buf, off; decode_u32);
+                (
+                    off,
+                    NdpMessage::RouterAdvertisement {
+                        cur_hop_limit: cur_hop_limit,
+                        managed: flags & RA_FLAG_MANAGED != 0,
+                        other: flags & RA_FLAG_OTHER != 0,
+                        router_lifetime: router_lifetime,
+                        reachable_time: reachable_time,
+                        retrans_timer: retrans_timer,
+                        source_link_addr: None,
+                        prefix: None,
+                    },
+                )
+            }
+            ndp_type::NEIGHBOR_SOLICITATION => {
+                let (off, _reserved) = dec_try!(buf, off; decode_u32);
+                let mut target = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+                (
+                    off,
+                    NdpMessage::NeighborSolicitation {
+                        target: target,
+                        source_link_addr: None,
+                    },
+                )
+            }
+            ndp_type::NEIGHBOR_ADVERTISEMENT => {
+                let (off, flags) = dec_try!(buf, off; decode_u32);
+                let mut target = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut target.0);
+                (
+                    off,
+                    NdpMessage::NeighborAdvertisement {
+                        router: flags & NA_FLAG_ROUTER != 0,
+                        solicited: flags & NA_FLAG_SOLICITED != 0,
+                        override_entry: flags & NA_FLAG_OVERRIDE != 0,
+                        target: target,
+                        target_link_addr: None,
+                    },
+                )
+            }
+            _ => stream_err!(),
+        };
+
+        let mut off = off;
+        while off < buf.len() {
+            let (_, option) = dec_try!(buf, off; decode_u8);
+            let (_, option_units) = dec_try!(buf, off + 1; decode_u8);
+            let option_len = option_units as usize * 8;
+            // Options with a length of zero must cause the message to be
+            // discarded
+            stream_cond!(option_len > 0);
+            stream_len_cond!(buf, off + option_len);
+            let body = &buf[off + 2..off + option_len];
+            match (option, &mut message) {
+                (
+                    option_type::SOURCE_LINK_ADDR,
+                    NdpMessage::RouterSolicitation { source_link_addr },
+                )
+                | (
+                    option_type::SOURCE_LINK_ADDR,
+                    NdpMessage::RouterAdvertisement {
+                        source_link_addr, ..
+                    },
+                )
+                | (
+                    option_type::SOURCE_LINK_ADDR,
+                    NdpMessage::NeighborSolicitation {
+                        source_link_addr, ..
+                    },
+                )
+                | (
+                    option_type::TARGET_LINK_ADDR,
+                    NdpMessage::NeighborAdvertisement {
+                        target_link_addr: source_link_addr,
+                        ..
+                    },
+                ) => {
+                    *source_link_addr = Some(LinkAddress::decode_option(body));
+                }
+                (
+                    option_type::PREFIX_INFORMATION,
+                    NdpMessage::RouterAdvertisement { prefix, .. },
+                ) => {
+                    if prefix.is_none() && option_len == PI_OPTION_LEN {
+                        let (_, info) = dec_try!(PrefixInformation::decode_body(body));
+                        *prefix = Some(info);
+                    }
+                }
+                _ => {}
+            }
+            off += option_len;
+        }
+        stream_done!(off, message);
+    }
+}
+
+#[derive(Copy, Clone)]
+struct NeighborEntry<L: Copy> {
+    addr: IPAddr,
+    link_addr: L,
+}
+
+/// A fixed-size cache of IPv6 address to link-layer address mappings. When
+/// the cache is full, entries are replaced in round-robin order.
+pub struct NeighborCache<L: Copy, const N: usize> {
+    entries: [Cell<Option<NeighborEntry<L>>>; N],
+    next: Cell<usize>,
+}
+
+impl<L: Copy, const N: usize> NeighborCache<L, N> {
+    const EMPTY: Cell<Option<NeighborEntry<L>>> = Cell::new(None);
+
+    pub fn new() -> NeighborCache<L, N> {
+        NeighborCache {
+            entries: [Self::EMPTY; N],
+            next: Cell::new(0),
+        }
+    }
+
+    pub fn lookup(&self, addr: &IPAddr) -> Option<L> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.get())
+            .find(|entry| entry.addr == *addr)
+            .map(|entry| entry.link_addr)
+    }
+
+    /// Adds or updates the link-layer address of `addr`.
+    pub fn insert(&self, addr: IPAddr, link_addr: L) {
+        let entry = Some(NeighborEntry {
+            addr: addr,
+            link_addr: link_addr,
+        });
+        let existing = self
+            .entries
+            .iter()
+            .find(|e| e.get().map_or(false, |e| e.addr == addr));
+        match existing {
+            Some(cell) => cell.set(entry),
+            None => {
+                if N == 0 {
+                    return;
+                }
+                let index = self.next.get();
+                self.entries[index].set(entry);
+                self.next.set((index + 1) % N);
+            }
+        }
+    }
+
+    pub fn remove(&self, addr: &IPAddr) {
+        self.entries
+            .iter()
+            .filter(|e| e.get().map_or(false, |e| e.addr == *addr))
+            .for_each(|e| e.set(None));
+    }
+}
@@ -0,0 +1,473 @@
+//! RPL control messages (RFC 6550, section 6).
+//!
+//! This file contains the encoding and decoding of the DODAG Information
+//! Solicitation (DIS), DODAG Information Object (DIO), Destination
+//! Advertisement Object (DAO) and DAO-ACK messages, which are carried in
+//! ICMPv6 messages of type 155. The routing protocol built on them lives in
+//! `net::ipv6::rpl`.
+//!
+//! Only the options used by that implementation are decoded: the DODAG
+//! Configuration and Prefix Information options of a DIO, and the RPL Target
+//! and Transit Information options of a DAO. A DAO carries a single target.
+//! Other options are skipped.
+
+use crate::net::icmpv6::ndp::PrefixInformation;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::stream::SResult;
+use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
+use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
+
+/// ICMPv6 type of RPL control messages.
+pub const ICMP_TYPE: u8 = 155;
+
+/// ICMPv6 codes of the RPL control messages.
+pub mod rpl_code {
+    pub const DIS: u8 = 0x00;
+    pub const DIO: u8 = 0x01;
+    pub const DAO: u8 = 0x02;
+    pub const DAO_ACK: u8 = 0x03;
+}
+
+mod option_type {
+    pub const PAD1: u8 = 0x00;
+    pub const PADN: u8 = 0x01;
+    pub const DODAG_CONFIGURATION: u8 = 0x04;
+    pub const RPL_TARGET: u8 = 0x05;
+    pub const TRANSIT_INFORMATION: u8 = 0x06;
+    pub const PREFIX_INFORMATION: u8 = 0x08;
+}
+
+/// Mode of Operation of a DODAG, advertised in its DIOs.
+pub mod mode_of_operation {
+    pub const NO_DOWNWARD_ROUTES: u8 = 0;
+    pub const NON_STORING: u8 = 1;
+    pub const STORING: u8 = 2;
+}
+
+/// The rank of a node that is not attached to a DODAG.
+pub const INFINITE_RANK: u16 = 0xffff;
+
+/// The all-RPL-nodes multicast address, ff02::1a.
+pub const ALL_RPL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x1a]);
+
+// DIO flags
+const DIO_FLAG_GROUNDED: u8 = 1 << 7;
+const DIO_MOP_SHIFT: u8 = 3;
+const DIO_MOP_MASK: u8 = 0b111;
+const DIO_PRF_MASK: u8 = 0b111;
+
+// DAO and DAO-ACK flags
+const DAO_FLAG_ACK: u8 = 1 << 7;
+const DAO_FLAG_DODAG_ID: u8 = 1 << 6;
+const DAO_ACK_FLAG_DODAG_ID: u8 = 1 << 7;
+
+const DODAG_CONFIGURATION_LEN: u8 = 14;
+const PREFIX_INFORMATION_LEN: u8 = 30;
+const TRANSIT_INFORMATION_LEN: u8 = 4;
+
+/// The DODAG Configuration option of a DIO. The defaults are those of
+/// RFC 6550, section 17, with the Objective Function Zero (RFC 6552).
+#[derive(Copy, Clone, Debug)]
+pub struct DodagConfiguration {
+    pub dio_interval_doublings: u8,
+    /// The minimum Trickle interval is 2^`dio_interval_min` ms.
+    pub dio_interval_min: u8,
+    pub dio_redundancy: u8,
+    pub max_rank_increase: u16,
+    pub min_hop_rank_increase: u16,
+    pub objective_code_point: u16,
+    /// In lifetime units; 0xff is infinity.
+    pub default_lifetime: u8,
+    /// In seconds.
+    pub lifetime_unit: u16,
+}
+
+impl Default for DodagConfiguration {
+    fn default() -> DodagConfiguration {
+        DodagConfiguration {
+            dio_interval_doublings: 20,
+            dio_interval_min: 3,
+            dio_redundancy: 10,
+            max_rank_increase: 7 * 256,
+            min_hop_rank_increase: 256,
+            objective_code_point: 0,
+            default_lifetime: 0xff,
+            lifetime_unit: 0xffff,
+        }
+    }
+}
+
+impl DodagConfiguration {
+    fn encode_option(&self, buf: &mut [u8]) -> SResult {
+        let off = enc_consume!(buf, 0; encode_u8, option_type::DODAG_CONFIGURATION);
+        let off = enc_consume!(buf, off; encode_u8, DODAG_CONFIGURATION_LEN);
+        // Flags, authentication and path control size
+        let off = enc_consume!(buf, off; encode_u8, 0);
+        let off = enc_consume!(buf, off; encode_u8, self.dio_interval_doublings);
+        let off = enc_consume!(buf, off; encode_u8, self.dio_interval_min);
+        let off = enc_consume!(buf, off; encode_u8, self.dio_redundancy);
+        let off = enc_consume!(buf, off; encode_u16, self.max_rank_increase);
+        let off = enc_consume!(buf, off; encode_u16, self.min_hop_rank_increase);
+        let off = enc_consume!(buf, off; encode_u16, self.objective_code_point);
+        let off = enc_consume!(buf, off; encode_u8, 0);
+        let off = enc_consume!(buf, off; encode_u8, self.default_lifetime);
+        let off = enc_consume!(buf, off; encode_u16, self.lifetime_unit);
+        stream_done!(off);
+    }
+
+    fn decode_body(body: &[u8]) -> SResult<DodagConfiguration> {
+        let (off, _flags) = dec_try!(body; decode_u8);
+        let (off, dio_interval_doublings) = dec_try!(body, off; decode_u8);
+        let (off, dio_interval_min) = dec_try!(body, off; decode_u8);
+        let (off, dio_redundancy) = dec_try!(body, off; decode_u8);
+        let (off, max_rank_increase) = dec_try!(body, off; decode_u16);
+        let (off, min_hop_rank_increase) = dec_try!(body, off; decode_u16);
+        let (off, objective_code_point) = dec_try!(body, off; decode_u16);
+        let (off, _reserved) = dec_try!(body, off; decode_u8);
+        let (off, default_lifetime) = dec_try!(body, off; decode_u8);
+        let (off, lifetime_unit) = dec_try!(body, off; decode_u16);
+        // A zero increase would make every rank equal
+        stream_cond!(min_hop_rank_increase > 0);
+        stream_done!(
+            off,
+            DodagConfiguration {
+                dio_interval_doublings: dio_interval_doublings,
+                dio_interval_min: dio_interval_min,
+                dio_redundancy: dio_redundancy,
+                max_rank_increase: max_rank_increase,
+                min_hop_rank_increase: min_hop_rank_increase,
+                objective_code_point: objective_code_point,
+                default_lifetime: default_lifetime,
+                lifetime_unit: lifetime_unit,
+            }
+        );
+    }
+}
+
+/// A DODAG Information Object.
+#[derive(Copy, Clone, Debug)]
+pub struct Dio {
+    pub instance_id: u8,
+    pub version: u8,
+    pub rank: u16,
+    pub grounded: bool,
+    pub mode_of_operation: u8,
+    pub preference: u8,
+    /// Destination Advertisement Trigger Sequence Number
+    pub dtsn: u8,
+    pub dodag_id: IPAddr,
+    pub configuration: Option<DodagConfiguration>,
+    pub prefix: Option<PrefixInformation>,
+}
+
+/// The RPL Target option of a DAO.
+#[derive(Copy, Clone, Debug)]
+pub struct Target {
+    pub prefix_len: u8,
+    pub prefix: IPAddr,
+}
+
+/// The Transit Information option of a DAO.
+#[derive(Copy, Clone, Debug)]
+pub struct TransitInformation {
+    pub path_control: u8,
+    pub path_sequence: u8,
+    /// In lifetime units of the DODAG; 0 removes the route.
+    pub path_lifetime: u8,
+    /// The DODAG parent, only present in non-storing mode.
+    pub parent: Option<IPAddr>,
+}
+
+/// A Destination Advertisement Object.
+#[derive(Copy, Clone, Debug)]
+pub struct Dao {
+    pub instance_id: u8,
+    pub ack_requested: bool,
+    pub sequence: u8,
+    pub dodag_id: Option<IPAddr>,
+    pub target: Option<Target>,
+    pub transit: Option<TransitInformation>,
+}
+
+/// An acknowledgement of a DAO.
+#[derive(Copy, Clone, Debug)]
+pub struct DaoAck {
+    pub instance_id: u8,
+    pub sequence: u8,
+    /// 0 is success; values of 128 and above are rejections.
+    pub status: u8,
+    pub dodag_id: Option<IPAddr>,
+}
+
+/// The RPL control messages that can be encoded and decoded.
+#[derive(Copy, Clone, Debug)]
+pub enum RplMessage {
+    Dis,
+    Dio(Dio),
+    Dao(Dao),
+    DaoAck(DaoAck),
+}
+
+impl RplMessage {
+    /// Encodes the complete ICMPv6 message into `buf`, leaving the checksum
+    /// field zero, as `NdpMessage::encode` does.
+    pub fn encode(&self, buf: &mut [u8]) -> SResult {
+        let code = match *self {
+            RplMessage::Dis => rpl_code::DIS,
+            RplMessage::Dio(_) => rpl_code::DIO,
+            RplMessage::Dao(_) => rpl_code::DAO,
+            RplMessage::DaoAck(_) => rpl_code::DAO_ACK,
+        };
+        let off = enc_consume!(buf, 0; encode_u8, ICMP_TYPE);
+        let off = enc_consume!(buf, off; encode_u8, code);
+        // Checksum
+        let off = enc_consume!(buf, off; encode_u16, 0);
+
+        let off = match *self {
+            RplMessage::Dis => {
+                // Flags and reserved, padded to the 8-byte ICMPv6 header that
+                // the IPv6 layer expects with an empty PadN option
+                let off = enc_consume!(buf, off; encode_u16, 0);
+                let off = enc_consume!(buf, off; encode_u8, option_type::PADN);
+                enc_consume!(buf, off; encode_u8, 0)
+            }
+            RplMessage::Dio(ref dio) => {
+                let mut flags = (dio.mode_of_operation & DIO_MOP_MASK) << DIO_MOP_SHIFT
+                    | (dio.preference & DIO_PRF_MASK);
+                if dio.grounded {
+                    flags |= DIO_FLAG_GROUNDED;
+                }
+                let off = enc_consume!(buf, off; encode_u8, dio.instance_id);
+                let off = enc_consume!(buf, off; encode_u8, dio.version);
+                let off = enc_consume!(buf, off; encode_u16, dio.rank);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u8, dio.dtsn);
+                // Flags and reserved
+                let off = enc_consume!(buf, off; encode_u16, 0);
+                let mut off = enc_consume!(buf, off; encode_bytes, &dio.dodag_id.0);
+                if let Some(configuration) = dio.configuration {
+                    off = enc_consume!(buf, off; configuration; encode_option);
+                }
+                if let Some(prefix) = dio.prefix {
+                    off = enc_consume!(buf, off; encode_u8, option_type::PREFIX_INFORMATION);
+                    off = enc_consume!(buf, off; encode_u8, PREFIX_INFORMATION_LEN);
+                    off = enc_consume!(buf, off; prefix; encode_body);
+                }
+                off
+            }
+            RplMessage::Dao(ref dao) => {
+                let mut flags = 0;
+                if dao.ack_requested {
+                    flags |= DAO_FLAG_ACK;
+                }
+                if dao.dodag_id.is_some() {
+                    flags |= DAO_FLAG_DODAG_ID;
+                }
+                let off = enc_consume!(buf, off; encode_u8, dao.instance_id);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u8, 0);
+                let mut off = enc_consume!(buf, off; encode_u8, dao.sequence);
+                if let Some(dodag_id) = dao.dodag_id {
+                    off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+                }
+                if let Some(target) = dao.target {
+                    let prefix_bytes = (target.prefix_len as usize + 7) / 8;
+                    stream_cond!(prefix_bytes <= 16);
+                    off = enc_consume!(buf, off; encode_u8, option_type::RPL_TARGET);
+                    off = enc_consume!(buf, off; encode_u8, 2 + prefix_bytes as u8);
+                    off = enc_consume!(buf, off; encode_u8, 0);
+                    off = enc_consume!(buf, off; encode_u8, target.prefix_len);
+                    off = enc_consume!(buf, off; encode_bytes, &target.prefix.0[..prefix_bytes]);
+                }
+                if let Some(transit) = dao.transit {
+                    let len = match transit.parent {
+                        Some(_) => TRANSIT_INFORMATION_LEN + 16,
+                        None => TRANSIT_INFORMATION_LEN,
+                    };
+                    off = enc_consume!(buf, off; encode_u8, option_type::TRANSIT_INFORMATION);
+                    off = enc_consume!(buf, off; encode_u8, len);
+                    // Flags; the external flag is never set
+                    off = enc_consume!(buf, off; encode_u8, 0);
+                    off = enc_consume!(buf, off; encode_u8, transit.path_control);
+                    off = enc_consume!(buf, off; encode_u8, transit.path_sequence);
+                    off = enc_consume!(buf, off; encode_u8, transit.path_lifetime);
+                    if let Some(parent) = transit.parent {
+                        off = enc_consume!(buf, off; encode_bytes, &parent.0);
+                    }
+                }
+                off
+            }
+            RplMessage::DaoAck(ref ack) => {
+                let flags = if ack.dodag_id.is_some() {
+                    DAO_ACK_FLAG_DODAG_ID
+                } else {
+                    0
+                };
+                let off = enc_consume!(buf, off; encode_u8, ack.instance_id);
+                let off = enc_consume!(buf, off; encode_u8, flags);
+                let off = enc_consume!(buf, off; encode_u8, ack.sequence);
+                let mut off = enc_consume!(buf, off; encode_u8, ack.status);
+                if let Some(dodag_id) = ack.dodag_id {
+                    off = enc_consume!(buf, off; encode_bytes, &dodag_id.0);
+                }
+                off
+            }
+        };
+        stream_done!(off);
+    }
+
+    /// Decodes an RPL control message, skipping options that are not used.
+    pub fn decode(buf: &[u8]) -> SResult<RplMessage> {
+        let (off, icmp_type) = dec_try!(buf; decode_u8);
+        stream_cond!(icmp_type == ICMP_TYPE);
+        let (off, code) = dec_try!(buf, off; decode_u8);
+        // Skip the checksum, which is verified by the caller
+        let off = dec_consume!(buf, off; decode_bytes, &mut [0; 2]);
+
+        let (off, mut message) = match code {
+            rpl_code::DIS => {
+                let (off, _flags) = dec_try!(buf, off; decode_u16);
+                (off, RplMessage::Dis)
+            }
+            rpl_code::DIO => {
+                let (off, instance_id) = dec_try!(buf, off; decode_u8);
+                let (off, version) = dec_try!(buf, off; decode_u8);
+                let (off, rank) = dec_try!(buf, off; decode_u16);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, dtsn) = dec_try!(buf, off; decode_u8);
+                let (off, _reserved) = dec_try!(buf, off; decode_u16);
+                let mut dodag_id = IPAddr::new();
+                let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+                (
+                    off,
+                    RplMessage::Dio(Dio {
+                        instance_id: instance_id,
+                        version: version,
+                        rank: rank,
+                        grounded: flags & DIO_FLAG_GROUNDED != 0,
+                        mode_of_operation: (flags >> DIO_MOP_SHIFT) & DIO_MOP_MASK,
+                        preference: flags & DIO_PRF_MASK,
+                        dtsn: dtsn,
+                        dodag_id: dodag_id,
+                        configuration: None,
+                        prefix: None,
+                    }),
+                )
+            }
+            rpl_code::DAO => {
+                let (off, instance_id) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, _reserved) = dec_try!(buf, off; decode_u8);
+                let (off, sequence) = dec_try!(buf, off; decode_u8);
+                let (off, dodag_id) = if flags & DAO_FLAG_DODAG_ID != 0 {
+                    let mut dodag_id = IPAddr::new();
+                    let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+                    (off, Some(dodag_id))
+                } else {
+                    (off, None)
+                };
+                (
+                    off,
+                    RplMessage::Dao(Dao {
+                        instance_id: instance_id,
+                        ack_requested: flags & DAO_FLAG_ACK != 0,
+                        sequence: sequence,
+                        dodag_id: dodag_id,
+                        target: None,
+                        transit: None,
+                    }),
+                )
+            }
+            rpl_code::DAO_ACK => {
+                let (off, instance_id) = dec_try!(buf, off; decode_u8);
+                let (off, flags) = dec_try!(buf, off; decode_u8);
+                let (off, sequence) = dec_try!(buf, off; decode_u8);
+                let (off, status) = dec_try!(buf, off; decode_u8);
+                let (off, dodag_id) = if flags & DAO_ACK_FLAG_DODAG_ID != 0 {
+                    let mut dodag_id = IPAddr::new();
+                    let off = dec_consume!(buf, off; decode_bytes, &mut dodag_id.0);
+                    (off, Some(dodag_id))
+                } else {
+                    (off, None)
+                };
+                (
+                    off,
+                    RplMessage::DaoAck(DaoAck {
+                        instance_id: instance_id,
+                        sequence: sequence,
+                        status: status,
+                        dodag_id: dodag_id,
+                    }),
+                )
+            }
+            _ => stream_err!(),
+        };
+
+        let mut off = off;
+        while off < buf.len() {
+            let (_, option) = dec_try!(buf, off; decode_u8);
+            if option == option_type::PAD1 {
+                off += 1;
+                continue;
+            }
+            // Unlike Neighbor Discovery options, the length is in bytes and
+            // excludes the type and length fields
+            let (_, len) = dec_try!(buf, off + 1; decode_u8);
+            let end = off + 2 + len as usize;
+            stream_len_cond!(buf, end);
+            let body = &buf[off + 2..end];
+            match (option, &mut message) {
+                (option_type::DODAG_CONFIGURATION, RplMessage::Dio(dio)) => {
+                    if dio.configuration.is_none() {
+                        let (_, configuration) = dec_try!(DodagConfiguration::decode_body(body));
+                        dio.configuration = Some(configuration);
+                    }
+                }
+                (option_type::PREFIX_INFORMATION, RplMessage::Dio(dio)) => {
+                    if dio.prefix.is_none() && len == PREFIX_INFORMATION_LEN {
+                        let (_, prefix) = dec_try!(PrefixInformation::decode_body(body));
+                        dio.prefix = Some(prefix);
+                    }
+                }
+                (option_type::RPL_TARGET, RplMessage::Dao(dao)) => {
+                    if dao.target.is_none() {
+                        let (off, _flags) = dec_try!(body; decode_u8);
+                        let (off, prefix_len) = dec_try!(body, off; decode_u8);
+                        let prefix_bytes = (prefix_len as usize + 7) / 8;
+                        stream_cond!(prefix_bytes <= 16);
+                        let mut prefix = IPAddr::new();
+                        dec_consume!(body, off; decode_bytes, &mut prefix.0[..prefix_bytes]);
+                        dao.target = Some(Target {
+                            prefix_len: prefix_len,
+                            prefix: prefix,
+                        });
+                    }
+                }
+                (option_type::TRANSIT_INFORMATION, RplMessage::Dao(dao)) => {
+                    if dao.transit.is_none() {
+                        let (off, _flags) = dec_try!(body; decode_u8);
+                        let (off, path_control) = dec_try!(body, off; decode_u8);
+                        let (off, path_sequence) = dec_try!(body, off; decode_u8);
+                        let (off, path_lifetime) = dec_try!(body, off; decode_u8);
+                        let parent = if body.len() > off {
+                            let mut parent = IPAddr::new();
+                            dec_consume!(body, off; decode_bytes, &mut parent.0);
+                            Some(parent)
+                        } else {
+                            None
+                        };
+                        dao.transit = Some(TransitInformation {
+                            path_control: path_control,
+                            path_sequence: path_sequence,
+                            path_lifetime: path_lifetime,
+                            parent: parent,
+                        });
+                    }
+                }
+                _ => {}
+            }
+            off = end;
+        }
+        stream_done!(off, message);
+    }
+}
@@ -111,6 +111,18 @@ impl IPAddr {
     pub fn is_multicast(&self) -> bool {
         self.0[0] == 0xff
     }
+
+    /// Returns the solicited-node multicast address (ff02::1:ffXX:XXXX)
+    /// corresponding to this address, as defined in RFC 4291, section 2.7.1.
+    pub fn solicited_node_multicast(&self) -> IPAddr {
+        let mut addr = IPAddr([0; 16]);
+        addr.0[0] = 0xff;
+        addr.0[1] = 0x02;
+        addr.0[11] = 0x01;
+        addr.0[12] = 0xff;
+        addr.0[13..16].copy_from_slice(&self.0[13..16]);
+        addr
+    }
 }
 
 pub fn compute_udp_checksum(
@@ -197,10 +209,23 @@ pub fn compute_icmp_checksum(
 
     // add options
     match icmp_header.get_options() {
-        ICMP6HeaderOptions::Type1 { unused } | ICMP6HeaderOptions::Type3 { unused } => {
+        ICMP6HeaderOptions::Type1 { unused }
+        | ICMP6HeaderOptions::Type3 { unused }
+        | ICMP6HeaderOptions::Type133 { reserved: unused }
+        | ICMP6HeaderOptions::Type135 { reserved: unused }
+        | ICMP6HeaderOptions::Type136 { flags: unused }
+        | ICMP6HeaderOptions::Type155 { base: unused } => {
             sum += unused >> 16; // upper 16 bits
             sum += unused & 0xffff; // lower 16 bits
         }
+        ICMP6HeaderOptions::Type134 {
+            cur_hop_limit,
+            flags,
+            router_lifetime,
+        } => {
+            sum += (cur_hop_limit as u32) << 8 | flags as u32;
+            sum += router_lifetime as u32;
+        }
         ICMP6HeaderOptions::Type128 { id, seqno } | ICMP6HeaderOptions::Type129 { id, seqno } => {
             sum += id as u32;
             sum += seqno as u32;
@@ -215,7 +240,7 @@ pub fn compute_icmp_checksum(
     while sum > 0xffff {
         let sum_upper = sum >> 16;
         let sum_lower = sum & 0xffff;
-        sum += sum_upper + sum_lower;
+        sum = sum_upper + sum_lower;
     }
 
     sum = !sum;
@@ -241,7 +266,7 @@ pub fn compute_ipv6_ph_sum(ip6_header: &IP6Header) -> u32 {
         i += 2;
     }
 
-    sum += ip6_header.payload_len as u32;
+    sum += ip6_header.get_payload_len() as u32;
     sum += ip6_header.next_header as u32;
 
     sum
@@ -253,10 +278,38 @@ pub fn compute_sum(buf: &[u8], len: u16) -> u32 {
     let mut i: usize = 0;
     while i < (len as usize) {
         let msb = (buf[i] as u32) << 8;
-        let lsb = buf[i + 1] as u32;
+        // An odd trailing byte is padded with zero
+        let lsb = if i + 1 < len as usize {
+            buf[i + 1] as u32
+        } else {
+            0
+        };
         sum += msb + lsb;
         i += 2;
     }
 
     sum
 }
+
+/// Computes the checksum of a complete ICMPv6 message, including the IPv6
+/// pseudo-header (RFC 4443, section 2.3). When sending, the checksum field of
+/// `message` must be zero. When computed over a received message, the result
+/// is zero if its checksum is valid. The result is in host byte order.
+pub fn compute_icmp_message_checksum(src_addr: &IPAddr, dst_addr: &IPAddr, message: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in src_addr.0.chunks(2).chain(dst_addr.0.chunks(2)) {
+        sum += (chunk[0] as u32) << 8 | chunk[1] as u32;
+    }
+    sum += message.len() as u32;
+    sum += ip6_nh::ICMP as u32;
+    for chunk in message.chunks(2) {
+        let lsb = chunk.get(1).copied().unwrap_or(0);
+        sum += (chunk[0] as u32) << 8 | lsb as u32;
+    }
+
+    // Fold the carries back into the lower 16 bits
+    while sum > 0xffff {
+        sum = (sum >> 16) + (sum & 0xffff);
+    }
+    !(sum as u16)
+}
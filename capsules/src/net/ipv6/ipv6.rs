@@ -67,7 +67,9 @@
 // (as required by 6LoWPAN) difficult.
 
 use crate::net::icmpv6::ICMP6Header;
-use crate::net::ipv6::ip_utils::{compute_icmp_checksum, compute_udp_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ip_utils::{
+    compute_icmp_checksum, compute_icmp_message_checksum, compute_udp_checksum, ip6_nh, IPAddr,
+};
 use crate::net::stream::SResult;
 use crate::net::stream::{decode_bytes, decode_u16, decode_u8};
 use crate::net::stream::{encode_bytes, encode_u16, encode_u8};
@@ -282,17 +284,9 @@ impl IP6Header {
                 Ok(())
             }
             ip6_nh::ICMP => {
-                // Untested (10/5/18)
-                let mut icmp_header: [u8; ICMP_HDR_LEN] = [0; ICMP_HDR_LEN];
-                icmp_header.copy_from_slice(&buf[..ICMP_HDR_LEN]);
-                let checksum = match ICMP6Header::decode(&icmp_header).done() {
-                    Some((_offset, mut hdr)) => {
-                        hdr.set_len(buf.len() as u16);
-                        u16::from_be(compute_icmp_checksum(&self, &hdr, &buf[ICMP_HDR_LEN..]))
-                    }
-                    None => 0xffff, //Will be dropped, as ones comp -0 checksum is invalid
-                };
-                if checksum != 0 {
+                // The checksum of a valid message, including its checksum
+                // field, sums to zero
+                if compute_icmp_message_checksum(&self.src_addr, &self.dst_addr, buf) != 0 {
                     return Err(ErrorCode::FAIL); //Incorrect cksum
                 }
                 Ok(())
@@ -0,0 +1,443 @@
+//! IPv6 over Ethernet (RFC 2464).
+//!
+//! `IP6EthernetStruct` implements `IP6Sender` on top of an `hil::ethernet`
+//! device and passes received IPv6 packets to an `IP6RecvStruct`, so that the
+//! UDP stack can run over Ethernet in the same way it runs over 6LoWPAN.
+//!
+//! Ethernet addresses of destinations are resolved with Neighbor Discovery
+//! (RFC 4861): if the destination is not in the neighbor cache, a Neighbor
+//! Solicitation is multicast and the packet is held until the matching
+//! Neighbor Advertisement arrives, or `send_done` reports `NOACK` after
+//! `MAX_SOLICITATIONS` unanswered solicitations. Neighbor Solicitations for
+//! our own address are answered. All destinations are assumed to be on-link;
+//! there is no router support.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ip_send = static_init!(
+//!     IP6EthernetStruct<'static, LiteEth<'static, SoCRegisterFmt>, VirtualMuxAlarm<'static, Timer>>,
+//!     IP6EthernetStruct::new(ethmac0, ipv6_alarm, ip6_packet, tx_buf, ip_vis)
+//! );
+//! ethmac0.set_transmit_client(ip_send);
+//! ethmac0.set_receive_client(ip_send);
+//! ipv6_alarm.set_alarm_client(ip_send);
+//! ip_send.set_receiver(ip_receive);
+//! ```
+
+use crate::net::ethernet::{ethertype, multicast_mac_address, EthernetHeader};
+use crate::net::icmpv6::ndp::{LinkAddress, NdpMessage, NeighborCache, ND_HOP_LIMIT};
+use crate::net::ieee802154::MacAddress as IEEE802154MacAddress;
+use crate::net::ipv6::ip_utils::{compute_icmp_message_checksum, ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvStruct;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+
+use core::cell::Cell;
+use core::convert::TryInto;
+
+use kernel::debug;
+use kernel::hil::ethernet::{self, Ethernet, MacAddress};
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+const IP6_HEADER_LEN: usize = 40;
+const MAC_ADDR_LEN: usize = 6;
+
+const NEIGHBOR_CACHE_SIZE: usize = 4;
+
+/// Number of Neighbor Solicitations sent before giving up on a destination
+/// (MAX_MULTICAST_SOLICIT in RFC 4861).
+pub const MAX_SOLICITATIONS: u8 = 3;
+
+/// Time between Neighbor Solicitations (RETRANS_TIMER in RFC 4861).
+const RETRANS_TIMER_MS: u32 = 1000;
+
+/// The all-nodes multicast address, ff02::1.
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+
+pub struct IP6EthernetStruct<'a, E: Ethernet<'a>, A: time::Alarm<'a>> {
+    ethernet: &'a E,
+    alarm: &'a A,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    tx_buf: TakeCell<'static, [u8]>,
+    src_addr: Cell<IPAddr>,
+    neighbors: NeighborCache<MacAddress, NEIGHBOR_CACHE_SIZE>,
+    // Destination of the packet in `ip6_packet` while a send is in progress
+    pending_dst: OptionalCell<IPAddr>,
+    // Ethernet address of `pending_dst`, once it has been resolved
+    next_hop: OptionalCell<MacAddress>,
+    solicitations: Cell<u8>,
+    // Whether the frame currently being transmitted holds `ip6_packet`,
+    // rather than a Neighbor Discovery message
+    packet_in_flight: Cell<bool>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    receiver: OptionalCell<&'a IP6RecvStruct<'a>>,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a, E: Ethernet<'a>, A: time::Alarm<'a>> IP6Sender<'a> for IP6EthernetStruct<'a, E, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_gateway(&self, _gateway: IEEE802154MacAddress) {
+        // Next hops on Ethernet are resolved with Neighbor Discovery, so an
+        // 802.15.4 gateway address has no meaning here.
+    }
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return Err(ErrorCode::FAIL);
+        }
+        if self.pending_dst.is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        if !self.ethernet.link_up() {
+            return Err(ErrorCode::OFF);
+        }
+        self.init_packet(dst, transport_header, payload);
+        self.pending_dst.set(dst);
+
+        let next_hop = if dst.is_multicast() {
+            Some(multicast_mac_address(&dst))
+        } else {
+            self.neighbors.lookup(&dst)
+        };
+        match next_hop {
+            Some(mac) => {
+                self.next_hop.set(mac);
+                let result = self.transmit_pending();
+                if result.is_err() {
+                    self.pending_dst.clear();
+                    self.next_hop.clear();
+                }
+                result
+            }
+            None => {
+                self.solicitations.set(0);
+                self.send_solicitation(dst);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'a, E: Ethernet<'a>, A: time::Alarm<'a>> IP6EthernetStruct<'a, E, A> {
+    pub fn new(
+        ethernet: &'a E,
+        alarm: &'a A,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        tx_buf: &'static mut [u8],
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> IP6EthernetStruct<'a, E, A> {
+        IP6EthernetStruct {
+            ethernet: ethernet,
+            alarm: alarm,
+            ip6_packet: TakeCell::new(ip6_packet),
+            tx_buf: TakeCell::new(tx_buf),
+            src_addr: Cell::new(IPAddr::new()),
+            neighbors: NeighborCache::new(),
+            pending_dst: OptionalCell::empty(),
+            next_hop: OptionalCell::empty(),
+            solicitations: Cell::new(0),
+            packet_in_flight: Cell::new(false),
+            client: OptionalCell::empty(),
+            receiver: OptionalCell::empty(),
+            ip_vis: ip_vis,
+        }
+    }
+
+    /// Set the receiver that IPv6 packets other than Neighbor Discovery
+    /// messages are passed to.
+    pub fn set_receiver(&self, receiver: &'a IP6RecvStruct<'a>) {
+        self.receiver.set(receiver);
+    }
+
+    fn init_packet(
+        &self,
+        dst_addr: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableMutableBuffer<'static, u8>,
+    ) {
+        self.ip6_packet.map_or_else(
+            || {
+                debug!("init packet failed.");
+            },
+            |ip6_packet| {
+                ip6_packet.header = IP6Header::default();
+                ip6_packet.header.src_addr = self.src_addr.get();
+                ip6_packet.header.dst_addr = dst_addr;
+                ip6_packet.set_payload(transport_header, payload);
+                ip6_packet.set_transport_checksum();
+            },
+        );
+    }
+
+    /// Transmits the pending packet to its resolved next hop. If a Neighbor
+    /// Discovery message is still being transmitted, the packet is sent from
+    /// `transmit_done` instead.
+    fn transmit_pending(&self) -> Result<(), ErrorCode> {
+        let dst_mac = self.next_hop.extract().ok_or(ErrorCode::FAIL)?;
+        let tx_buf = match self.tx_buf.take() {
+            Some(tx_buf) => tx_buf,
+            None => return Ok(()),
+        };
+
+        let header = EthernetHeader::new(dst_mac, self.ethernet.mac_address(), ethertype::IPV6);
+        let frame_len = self.ip6_packet.map_or(None, |ip6_packet| {
+            let (off, _) = header.encode(tx_buf).done()?;
+            let (len, _) = ip6_packet.encode(&mut tx_buf[off..]).done()?;
+            Some(off + len)
+        });
+        let frame_len = match frame_len {
+            Some(frame_len) => frame_len,
+            None => {
+                self.tx_buf.replace(tx_buf);
+                return Err(ErrorCode::SIZE);
+            }
+        };
+
+        match self.ethernet.transmit(tx_buf, frame_len) {
+            Ok(()) => {
+                self.packet_in_flight.set(true);
+                Ok(())
+            }
+            Err((ecode, tx_buf)) => {
+                self.tx_buf.replace(tx_buf);
+                Err(ecode)
+            }
+        }
+    }
+
+    fn send_completed(&self, result: Result<(), ErrorCode>) {
+        self.alarm.disarm().ok();
+        self.pending_dst.clear();
+        self.next_hop.clear();
+        self.client.map(move |client| {
+            client.send_done(result);
+        });
+    }
+
+    fn send_solicitation(&self, target: IPAddr) {
+        self.solicitations.set(self.solicitations.get() + 1);
+        let dst = target.solicited_node_multicast();
+        let message = NdpMessage::NeighborSolicitation {
+            target: target,
+            source_link_addr: Some(LinkAddress::new(&self.ethernet.mac_address().0)),
+        };
+        // If the buffer is busy the solicitation is simply retried when the
+        // alarm fires
+        let _ = self.send_ndp(dst, multicast_mac_address(&dst), &message);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RETRANS_TIMER_MS));
+    }
+
+    fn send_ndp(
+        &self,
+        dst_addr: IPAddr,
+        dst_mac: MacAddress,
+        message: &NdpMessage,
+    ) -> Result<(), ErrorCode> {
+        let tx_buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+        let frame_len = match self.encode_ndp(tx_buf, dst_addr, dst_mac, message) {
+            Some(frame_len) => frame_len,
+            None => {
+                self.tx_buf.replace(tx_buf);
+                return Err(ErrorCode::SIZE);
+            }
+        };
+        self.ethernet
+            .transmit(tx_buf, frame_len)
+            .map_err(|(ecode, tx_buf)| {
+                self.tx_buf.replace(tx_buf);
+                ecode
+            })
+    }
+
+    fn encode_ndp(
+        &self,
+        buf: &mut [u8],
+        dst_addr: IPAddr,
+        dst_mac: MacAddress,
+        message: &NdpMessage,
+    ) -> Option<usize> {
+        let src_addr = self.src_addr.get();
+        let header = EthernetHeader::new(dst_mac, self.ethernet.mac_address(), ethertype::IPV6);
+        let (ip6_off, _) = header.encode(buf).done()?;
+        let icmp_off = ip6_off + IP6_HEADER_LEN;
+        let (icmp_len, _) = message.encode(buf.get_mut(icmp_off..)?).done()?;
+        let icmp = &mut buf[icmp_off..icmp_off + icmp_len];
+        let checksum = compute_icmp_message_checksum(&src_addr, &dst_addr, icmp);
+        icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        let mut ip6_header = IP6Header::new();
+        ip6_header.src_addr = src_addr;
+        ip6_header.dst_addr = dst_addr;
+        ip6_header.set_next_header(ip6_nh::ICMP);
+        ip6_header.set_hop_limit(ND_HOP_LIMIT);
+        ip6_header.set_payload_len(icmp_len as u16);
+        ip6_header.encode(&mut buf[ip6_off..]).done()?;
+        Some(icmp_off + icmp_len)
+    }
+
+    /// Handles a received ICMPv6 message if it is a Neighbor Discovery
+    /// message. Returns whether the message was consumed.
+    fn receive_ndp(&self, ip6_header: &IP6Header, message: &[u8]) -> bool {
+        let src_addr = ip6_header.get_src_addr();
+        let decoded = match NdpMessage::decode(message).done() {
+            Some((_, decoded @ NdpMessage::NeighborSolicitation { .. }))
+            | Some((_, decoded @ NdpMessage::NeighborAdvertisement { .. })) => decoded,
+            // Router messages are left to the layers above
+            _ => return false,
+        };
+        if ip6_header.get_hop_limit() != ND_HOP_LIMIT
+            || compute_icmp_message_checksum(&src_addr, &ip6_header.get_dst_addr(), message) != 0
+        {
+            // Invalid Neighbor Discovery messages are silently discarded
+            return true;
+        }
+        let link_mac = |link_addr: Option<LinkAddress>| {
+            link_addr.and_then(|addr| {
+                addr.as_slice()
+                    .get(..MAC_ADDR_LEN)
+                    .and_then(|mac| mac.try_into().ok())
+                    .map(MacAddress)
+            })
+        };
+
+        match decoded {
+            NdpMessage::NeighborSolicitation {
+                target,
+                source_link_addr,
+            } => {
+                if target != self.src_addr.get() {
+                    return true;
+                }
+                // Solicitations from the unspecified address are sent during
+                // duplicate address detection and are answered to all nodes
+                let reply_to = if src_addr.is_unspecified() {
+                    Some((ALL_NODES, multicast_mac_address(&ALL_NODES)))
+                } else {
+                    link_mac(source_link_addr).map(|mac| {
+                        self.neighbors.insert(src_addr, mac);
+                        (src_addr, mac)
+                    })
+                };
+                reply_to.map(|(dst_addr, dst_mac)| {
+                    let reply = NdpMessage::NeighborAdvertisement {
+                        router: false,
+                        solicited: !src_addr.is_unspecified(),
+                        override_entry: true,
+                        target: target,
+                        target_link_addr: Some(LinkAddress::new(&self.ethernet.mac_address().0)),
+                    };
+                    // If the buffer is busy the neighbor will solicit again
+                    let _ = self.send_ndp(dst_addr, dst_mac, &reply);
+                });
+            }
+            NdpMessage::NeighborAdvertisement {
+                target,
+                target_link_addr,
+                ..
+            } => {
+                if let Some(mac) = link_mac(target_link_addr) {
+                    self.neighbors.insert(target, mac);
+                    if self.pending_dst.contains(&target) && self.next_hop.is_none() {
+                        self.alarm.disarm().ok();
+                        self.next_hop.set(mac);
+                        if let Err(ecode) = self.transmit_pending() {
+                            self.send_completed(Err(ecode));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+impl<'a, E: Ethernet<'a>, A: time::Alarm<'a>> time::AlarmClient for IP6EthernetStruct<'a, E, A> {
+    fn alarm(&self) {
+        if self.next_hop.is_some() {
+            return;
+        }
+        self.pending_dst.extract().map(|dst| {
+            if self.solicitations.get() >= MAX_SOLICITATIONS {
+                self.send_completed(Err(ErrorCode::NOACK));
+            } else {
+                self.send_solicitation(dst);
+            }
+        });
+    }
+}
+
+impl<'a, E: Ethernet<'a>, A: time::Alarm<'a>> ethernet::TxClient for IP6EthernetStruct<'a, E, A> {
+    fn transmit_done(&self, result: Result<(), ErrorCode>, frame: &'static mut [u8], _len: usize) {
+        self.tx_buf.replace(frame);
+        if self.packet_in_flight.get() {
+            self.packet_in_flight.set(false);
+            self.send_completed(result);
+        } else if self.pending_dst.is_some() && self.next_hop.is_some() {
+            // The packet was resolved while a Neighbor Discovery message
+            // held the buffer
+            if let Err(ecode) = self.transmit_pending() {
+                self.send_completed(Err(ecode));
+            }
+        }
+    }
+}
+
+impl<'a, E: Ethernet<'a>, A: time::Alarm<'a>> ethernet::RxClient for IP6EthernetStruct<'a, E, A> {
+    fn received_frame(&self, frame: &[u8]) {
+        let (off, header) = match EthernetHeader::decode(frame).done() {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        if header.ethertype != ethertype::IPV6
+            || (header.dst != self.ethernet.mac_address() && !header.dst.is_multicast())
+        {
+            return;
+        }
+
+        let packet = &frame[off..];
+        let ip6_header = match IP6Header::decode(packet).done() {
+            Some((_, ip6_header)) => ip6_header,
+            None => return,
+        };
+        // Short frames are padded to the minimum Ethernet frame length
+        let packet_len = ip6_header.get_total_len() as usize;
+        if packet_len > packet.len() {
+            return;
+        }
+        let packet = &packet[..packet_len];
+
+        if ip6_header.get_next_header() == ip6_nh::ICMP
+            && self.receive_ndp(&ip6_header, &packet[IP6_HEADER_LEN..])
+        {
+            return;
+        }
+        self.receiver
+            .map(|receiver| receiver.receive_packet(packet));
+    }
+}
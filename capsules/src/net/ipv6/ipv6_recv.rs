@@ -1,6 +1,10 @@
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_send::IP6Forwarder;
 use crate::net::ipv6::IP6Header;
 use crate::net::sixlowpan::sixlowpan_state::SixlowpanRxClient;
 
+use core::cell::Cell;
+
 use kernel::debug;
 use kernel::utilities::cells::OptionalCell;
 use kernel::ErrorCode;
@@ -32,6 +36,23 @@ pub trait IP6RecvClient {
     fn receive(&self, header: IP6Header, payload: &[u8]);
 }
 
+/// The number of multicast groups that can be joined with
+/// `IP6MulticastGroups`, in addition to the groups that are always received.
+pub const MAX_MULTICAST_GROUPS: usize = 4;
+
+/// Membership of IPv6 multicast groups. Packets to the all-nodes,
+/// all-routers, all-RPL-nodes and solicited-node groups are always received;
+/// packets to other multicast groups are dropped unless the group has been
+/// joined.
+pub trait IP6MulticastGroups {
+    /// Join `group`. Returns INVAL if `group` is not a multicast address, or
+    /// NOMEM if no more groups can be joined. Joining a group twice succeeds.
+    fn join_group(&self, group: IPAddr) -> Result<(), ErrorCode>;
+
+    /// Leave `group`. Returns INVAL if the group was not joined.
+    fn leave_group(&self, group: IPAddr) -> Result<(), ErrorCode>;
+}
+
 /// Currently only one implementation of this trait should exist,
 /// as we do not multiplex received packets based on the address.
 /// The receiver receives IP packets destined for any local address.
@@ -43,6 +64,10 @@ pub trait IP6Receiver<'a> {
 
 pub struct IP6RecvStruct<'a> {
     client: OptionalCell<&'a dyn IP6RecvClient>,
+    forwarder: OptionalCell<&'a dyn IP6Forwarder>,
+    local_addrs: OptionalCell<&'a [Cell<IPAddr>]>,
+    groups: [Cell<Option<IPAddr>>; MAX_MULTICAST_GROUPS],
+    forward_dropped: Cell<usize>,
 }
 
 impl<'a> IP6Receiver<'a> for IP6RecvStruct<'a> {
@@ -55,19 +80,77 @@ impl<'a> IP6RecvStruct<'a> {
     pub fn new() -> IP6RecvStruct<'a> {
         IP6RecvStruct {
             client: OptionalCell::empty(),
+            forwarder: OptionalCell::empty(),
+            local_addrs: OptionalCell::empty(),
+            groups: Default::default(),
+            forward_dropped: Cell::new(0),
         }
     }
-}
 
-impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
-    fn receive(&self, buf: &[u8], len: usize, result: Result<(), ErrorCode>) {
-        // TODO: Drop here?
-        if len > buf.len() || result != Ok(()) {
-            return;
+    /// The number of packets addressed to other nodes that were dropped
+    /// instead of forwarded, because their hop limit ran out, the sender was
+    /// busy with another packet, or there was no route to the destination.
+    /// Once a forwarder is set, this also counts the packets 6LoWPAN failed
+    /// to reassemble, as their destination is not known.
+    pub fn forward_dropped(&self) -> usize {
+        self.forward_dropped.get()
+    }
+
+    /// Whether packets to the multicast address `dst` are received.
+    fn accepts_multicast(&self, dst: IPAddr) -> bool {
+        let addr = dst.0;
+        // All-nodes and all-routers, of any scope
+        let all_nodes_or_routers =
+            addr[2..15].iter().all(|b| *b == 0) && (addr[15] == 1 || addr[15] == 2);
+        // All-RPL-nodes, ff02::1a
+        let all_rpl_nodes =
+            addr[1] == 0x02 && addr[2..15].iter().all(|b| *b == 0) && addr[15] == 0x1a;
+        // Solicited-node, ff02::1:ffXX:XXXX
+        let solicited_node = addr[..13] == [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff];
+        all_nodes_or_routers
+            || all_rpl_nodes
+            || solicited_node
+            || self
+                .groups
+                .iter()
+                .any(|group| group.get().map_or(false, |g| g == dst))
+    }
+
+    /// Enables forwarding: unicast packets with a global destination that is
+    /// not one of `local_addrs` are passed to `forwarder` instead of the
+    /// client.
+    pub fn set_forwarder(&self, forwarder: &'a dyn IP6Forwarder, local_addrs: &'a [Cell<IPAddr>]) {
+        self.forwarder.set(forwarder);
+        self.local_addrs.set(local_addrs);
+    }
+
+    /// Forwards the packet if it is addressed to another node. Returns true
+    /// if the packet was consumed, whether or not it could be forwarded.
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> bool {
+        let dst = header.get_dst_addr();
+        if dst.is_multicast() || dst.is_unicast_link_local() {
+            return false;
+        }
+        let is_local = self
+            .local_addrs
+            .map_or(true, |addrs| addrs.iter().any(|addr| addr.get() == dst));
+        if is_local {
+            return false;
         }
+        self.forwarder.map(|forwarder| {
+            if header.get_hop_limit() <= 1 || forwarder.forward(header, payload).is_err() {
+                self.forward_dropped
+                    .set(self.forward_dropped.get().wrapping_add(1));
+            }
+        });
+        true
+    }
+
+    /// Handles a complete, uncompressed IPv6 packet received by a link layer.
+    pub fn receive_packet(&self, buf: &[u8]) {
         match IP6Header::decode(buf).done() {
             Some((offset, ip6_header)) => {
-                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..len]);
+                let checksum_result = ip6_header.check_transport_checksum(&buf[offset..]);
                 if checksum_result == Err(ErrorCode::FAIL) {
                     debug!("cksum fail!: {:?}", checksum_result);
                     return; //Dropped.
@@ -75,8 +158,16 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
                 // Note: Protocols for which checksum verification is not implemented (TCP, etc.)
                 // are automatically assumed as fine, rather than dropped
 
+                let dst = ip6_header.get_dst_addr();
+                if dst.is_multicast() && !self.accepts_multicast(dst) {
+                    return;
+                }
+
+                if self.forward(ip6_header, &buf[offset..]) {
+                    return;
+                }
                 self.client
-                    .map(|client| client.receive(ip6_header, &buf[offset..len]));
+                    .map(|client| client.receive(ip6_header, &buf[offset..]));
             }
             None => {
                 debug!("failed to decode ipv6 header");
@@ -85,3 +176,41 @@ impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
         }
     }
 }
+
+impl<'a> IP6MulticastGroups for IP6RecvStruct<'a> {
+    fn join_group(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        if !group.is_multicast() {
+            return Err(ErrorCode::INVAL);
+        }
+        if self.groups.iter().any(|g| g.get() == Some(group)) {
+            return Ok(());
+        }
+        self.groups
+            .iter()
+            .find(|g| g.get().is_none())
+            .map(|g| g.set(Some(group)))
+            .ok_or(ErrorCode::NOMEM)
+    }
+
+    fn leave_group(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        self.groups
+            .iter()
+            .find(|g| g.get() == Some(group))
+            .map(|g| g.set(None))
+            .ok_or(ErrorCode::INVAL)
+    }
+}
+
+impl<'a> SixlowpanRxClient for IP6RecvStruct<'a> {
+    fn receive(&self, buf: &[u8], len: usize, result: Result<(), ErrorCode>) {
+        // A packet that failed to be reassembled is dropped
+        if len > buf.len() || result != Ok(()) {
+            if self.forwarder.is_some() {
+                self.forward_dropped
+                    .set(self.forward_dropped.get().wrapping_add(1));
+            }
+            return;
+        }
+        self.receive_packet(&buf[..len]);
+    }
+}
@@ -5,7 +5,10 @@
 //! when a transmission has completed.
 //!
 //! This file also includes an implementation of the `IP6Sender` trait, which
-//! sends an IPv6 packet using 6LoWPAN.
+//! sends an IPv6 packet using 6LoWPAN. The same implementation forwards
+//! packets received for other nodes through the
+//! [IP6Forwarder](trait.IP6Forwarder.html) trait, choosing the next hop with
+//! the [Router](../routing/trait.Router.html) set by the routing protocol.
 
 // Additional Work and Known Problems
 // ----------------------------------
@@ -17,11 +20,14 @@
 // interface.
 
 use crate::ieee802154::device::{MacDevice, TxClient};
+use crate::net::icmpv6::ICMP6Header;
 use crate::net::ieee802154::MacAddress;
-use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::routing::Router;
 use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
 use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
 use crate::net::sixlowpan::sixlowpan_state::TxState;
+use crate::net::udp::UDPHeader;
 
 use core::cell::Cell;
 
@@ -31,6 +37,8 @@ use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
 use kernel::ErrorCode;
 
+const BROADCAST_SHORT_ADDR: u16 = 0xffff;
+
 /// This trait must be implemented by upper layers in order to receive
 /// the `send_done` callback when a transmission has completed. The upper
 /// layer must then call `IP6Sender.set_client` in order to receive this
@@ -90,6 +98,21 @@ pub trait IP6Sender<'a> {
     ) -> Result<(), ErrorCode>;
 }
 
+/// This trait sends packets received from other nodes on towards their
+/// destination. It is used by the receive path for packets that are not
+/// addressed to this node.
+pub trait IP6Forwarder {
+    /// Sends the packet with the given header and transport payload (the
+    /// transport header followed by its payload) to the next hop towards its
+    /// destination, decrementing its hop limit. The transport checksum is
+    /// left unchanged. No `send_done` callback is issued for forwarded
+    /// packets.
+    ///
+    /// Returns BUSY if a packet is already being sent, and NOSUPPORT if the
+    /// transport protocol cannot be forwarded.
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> Result<(), ErrorCode>;
+}
+
 /// This struct is a specific implementation of the `IP6Sender` trait. This
 /// struct sends the packet using 6LoWPAN over a generic `MacDevice` object.
 pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
@@ -103,9 +126,11 @@ pub struct IP6SendStruct<'a, A: time::Alarm<'a>> {
     tx_buf: TakeCell<'static, [u8]>,
     sixlowpan: TxState<'a>,
     radio: &'a dyn MacDevice<'a>,
-    dst_mac_addr: MacAddress,
     src_mac_addr: MacAddress,
     client: OptionalCell<&'a dyn IP6SendClient>,
+    router: OptionalCell<&'a dyn Router>,
+    busy: Cell<bool>,
+    forwarding: Cell<bool>,
     ip_vis: &'static IpVisibilityCapability,
 }
 
@@ -137,15 +162,68 @@ impl<'a, A: time::Alarm<'a>> IP6Sender<'a> for IP6SendStruct<'a, A> {
         if !net_cap.remote_addr_valid(dst, self.ip_vis) {
             return Err(ErrorCode::FAIL);
         }
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
         let _ = self.sixlowpan.init(
             self.src_mac_addr,
-            self.dst_mac_addr,
+            self.next_hop(dst),
             self.radio.get_pan(),
             None,
         );
         self.init_packet(dst, transport_header, payload);
-        let ret = self.send_next_fragment();
-        ret
+        self.start_send(false)
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> IP6Forwarder for IP6SendStruct<'a, A> {
+    fn forward(&self, header: IP6Header, payload: &[u8]) -> Result<(), ErrorCode> {
+        if self.busy.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let transport_header = match header.get_next_header() {
+            ip6_nh::UDP => UDPHeader::decode(payload)
+                .done()
+                .filter(|(_, udp_header)| udp_header.get_len() as usize <= payload.len())
+                .map(|(_, udp_header)| TransportHeader::UDP(udp_header)),
+            ip6_nh::ICMP => ICMP6Header::decode(payload)
+                .done()
+                .map(|(_, mut icmp_header)| {
+                    icmp_header.set_len(payload.len() as u16);
+                    TransportHeader::ICMP(icmp_header)
+                }),
+            _ => None,
+        }
+        .ok_or(ErrorCode::NOSUPPORT)?;
+        let body = match transport_header {
+            TransportHeader::UDP(udp_header) => {
+                &payload[udp_header.get_hdr_size()..udp_header.get_len() as usize]
+            }
+            TransportHeader::ICMP(icmp_header) => &payload[icmp_header.get_hdr_size()..],
+            _ => return Err(ErrorCode::NOSUPPORT),
+        };
+
+        let dst = header.get_dst_addr();
+        self.ip6_packet
+            .map_or(Err(ErrorCode::NOMEM), |ip6_packet| {
+                if body.len() > ip6_packet.payload.payload.len() {
+                    return Err(ErrorCode::SIZE);
+                }
+                ip6_packet.payload.payload[..body.len()].copy_from_slice(body);
+                ip6_packet.payload.header = transport_header;
+                ip6_packet.header = header;
+                ip6_packet
+                    .header
+                    .set_hop_limit(header.get_hop_limit().saturating_sub(1));
+                Ok(())
+            })?;
+        let _ = self.sixlowpan.init(
+            self.src_mac_addr,
+            self.next_hop(dst),
+            self.radio.get_pan(),
+            None,
+        );
+        self.start_send(true)
     }
 }
 
@@ -168,13 +246,48 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
             tx_buf: TakeCell::new(tx_buf),
             sixlowpan: sixlowpan,
             radio: radio,
-            dst_mac_addr: dst_mac_addr,
             src_mac_addr: src_mac_addr,
             client: OptionalCell::empty(),
+            router: OptionalCell::empty(),
+            busy: Cell::new(false),
+            forwarding: Cell::new(false),
             ip_vis: ip_vis,
         }
     }
 
+    /// Sets the router that is consulted for the next hop of unicast packets
+    /// that are not link-local.
+    pub fn set_router(&self, router: &'a dyn Router) {
+        self.router.set(router);
+    }
+
+    /// Returns the MAC address that packets to `dst` are sent to. Multicast
+    /// packets are broadcast. Link-local addresses are formed from the MAC
+    /// address of their interface, so the MAC address is recovered from the
+    /// interface identifier, as in RFC 6775. Other packets are sent to the
+    /// next hop given by the router, if any, and to the gateway otherwise.
+    fn next_hop(&self, dst: IPAddr) -> MacAddress {
+        if dst.is_multicast() {
+            MacAddress::Short(BROADCAST_SHORT_ADDR)
+        } else if dst.is_unicast_link_local() {
+            iid_mac_address(&dst)
+        } else {
+            self.router
+                .and_then(|router| router.next_hop(&dst))
+                .map_or(self.gateway.get(), |next_hop| iid_mac_address(&next_hop))
+        }
+    }
+
+    fn start_send(&self, forwarding: bool) -> Result<(), ErrorCode> {
+        self.busy.set(true);
+        self.forwarding.set(forwarding);
+        let ret = self.send_next_fragment();
+        if ret.is_err() {
+            self.busy.set(false);
+        }
+        ret
+    }
+
     fn init_packet(
         &self,
         dst_addr: IPAddr,
@@ -243,12 +356,30 @@ impl<'a, A: time::Alarm<'a>> IP6SendStruct<'a, A> {
     }
 
     fn send_completed(&self, result: Result<(), ErrorCode>) {
+        self.busy.set(false);
+        if self.forwarding.get() {
+            return;
+        }
         self.client.map(move |client| {
             client.send_done(result);
         });
     }
 }
 
+/// Recovers the MAC address of a neighbor from the interface identifier of
+/// one of its addresses.
+fn iid_mac_address(addr: &IPAddr) -> MacAddress {
+    let iid = &addr.0[8..16];
+    if iid[..6] == [0, 0, 0, 0xff, 0xfe, 0] {
+        MacAddress::Short((iid[6] as u16) << 8 | iid[7] as u16)
+    } else {
+        let mut long_addr = [0; 8];
+        long_addr.copy_from_slice(iid);
+        long_addr[0] ^= 0b00000010;
+        MacAddress::Long(long_addr)
+    }
+}
+
 impl<'a, A: time::Alarm<'a>> time::AlarmClient for IP6SendStruct<'a, A> {
     fn alarm(&self) {
         let result = self.send_next_fragment();
@@ -263,9 +394,7 @@ impl<'a, A: time::Alarm<'a>> TxClient for IP6SendStruct<'a, A> {
         self.tx_buf.replace(tx_buf);
         if result != Ok(()) {
             debug!("Send Failed: {:?}, acked: {}", result, acked);
-            self.client.map(move |client| {
-                client.send_done(result);
-            });
+            self.send_completed(result);
         } else {
             // Below code adds delay between fragments. Despite some efforts
             // to fix this bug, I find that without it the receiving imix cannot
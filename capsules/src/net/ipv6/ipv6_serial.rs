@@ -0,0 +1,224 @@
+//! IPv6 over a serial line.
+//!
+//! `IP6SerialStruct` implements `IP6Sender` on top of a UART and passes
+//! received IPv6 packets to an `IP6RecvStruct`, so that the UDP stack can be
+//! used without an 802.15.4 radio. Packets are framed with SLIP or HDLC-like
+//! framing (see `net::serial`). A serial link is point-to-point, so there is
+//! no link-layer addressing or neighbor discovery: every packet is sent to the
+//! peer at the other end of the line.
+//!
+//! With SLIP framing, a Linux host can be attached to the board with
+//!
+//! ```text
+//! slattach -L -s 115200 -p slip /dev/ttyUSB0 &
+//! ip link set sl0 up
+//! ip -6 addr add fe80::1/64 dev sl0
+//! ```
+//!
+//! The UART should not be shared with the console, since console output
+//! would corrupt frames.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let ip_send = static_init!(
+//!     IP6SerialStruct<'static>,
+//!     IP6SerialStruct::new(uart_device, Framing::Slip, ip6_packet, packet_buf, tx_buf, rx_byte, rx_frame, ip_vis)
+//! );
+//! uart_device.set_transmit_client(ip_send);
+//! uart_device.set_receive_client(ip_send);
+//! ip_send.set_receiver(ip_receive);
+//! ip_send.start();
+//! ```
+
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::ipv6::ipv6_recv::IP6RecvStruct;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, IP6Packet, TransportHeader};
+use crate::net::network_capabilities::{IpVisibilityCapability, NetworkCapability};
+use crate::net::serial::{FrameDecoder, Framing};
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::hil::uart;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+pub struct IP6SerialStruct<'a> {
+    uart: &'a dyn uart::UartData<'a>,
+    framing: Framing,
+    ip6_packet: TakeCell<'static, IP6Packet<'static>>,
+    // The encoded packet, before framing
+    packet_buf: TakeCell<'static, [u8]>,
+    tx_buf: TakeCell<'static, [u8]>,
+    rx_byte: TakeCell<'static, [u8]>,
+    rx_frame: TakeCell<'static, [u8]>,
+    decoder: Cell<FrameDecoder>,
+    src_addr: Cell<IPAddr>,
+    client: OptionalCell<&'a dyn IP6SendClient>,
+    receiver: OptionalCell<&'a IP6RecvStruct<'a>>,
+    ip_vis: &'static IpVisibilityCapability,
+}
+
+impl<'a> IP6Sender<'a> for IP6SerialStruct<'a> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.src_addr.set(src_addr);
+    }
+
+    fn set_gateway(&self, _gateway: MacAddress) {
+        // A serial link has a single peer, so there is no next hop to set.
+    }
+
+    fn set_header(&mut self, ip6_header: IP6Header) {
+        self.ip6_packet
+            .map(|ip6_packet| ip6_packet.header = ip6_header);
+    }
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if !net_cap.remote_addr_valid(dst, self.ip_vis) {
+            return Err(ErrorCode::FAIL);
+        }
+        let tx_buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+        let frame_len = self.packet_buf.map_or(None, |packet_buf| {
+            self.ip6_packet.map_or(None, |ip6_packet| {
+                ip6_packet.header = IP6Header::default();
+                ip6_packet.header.src_addr = self.src_addr.get();
+                ip6_packet.header.dst_addr = dst;
+                ip6_packet.set_payload(transport_header, payload);
+                ip6_packet.set_transport_checksum();
+                let (len, _) = ip6_packet.encode(packet_buf).done()?;
+                self.framing.encode(&packet_buf[..len], tx_buf)
+            })
+        });
+        let frame_len = match frame_len {
+            Some(frame_len) => frame_len,
+            None => {
+                self.tx_buf.replace(tx_buf);
+                return Err(ErrorCode::SIZE);
+            }
+        };
+
+        self.uart
+            .transmit_buffer(tx_buf, frame_len)
+            .map_err(|(ecode, tx_buf)| {
+                self.tx_buf.replace(tx_buf);
+                ecode
+            })
+    }
+}
+
+impl<'a> IP6SerialStruct<'a> {
+    pub fn new(
+        uart: &'a dyn uart::UartData<'a>,
+        framing: Framing,
+        ip6_packet: &'static mut IP6Packet<'static>,
+        packet_buf: &'static mut [u8],
+        tx_buf: &'static mut [u8],
+        rx_byte: &'static mut [u8],
+        rx_frame: &'static mut [u8],
+        ip_vis: &'static IpVisibilityCapability,
+    ) -> IP6SerialStruct<'a> {
+        IP6SerialStruct {
+            uart: uart,
+            framing: framing,
+            ip6_packet: TakeCell::new(ip6_packet),
+            packet_buf: TakeCell::new(packet_buf),
+            tx_buf: TakeCell::new(tx_buf),
+            rx_byte: TakeCell::new(rx_byte),
+            rx_frame: TakeCell::new(rx_frame),
+            decoder: Cell::new(FrameDecoder::new(framing)),
+            src_addr: Cell::new(IPAddr::new()),
+            client: OptionalCell::empty(),
+            receiver: OptionalCell::empty(),
+            ip_vis: ip_vis,
+        }
+    }
+
+    /// Set the receiver that received IPv6 packets are passed to.
+    pub fn set_receiver(&self, receiver: &'a IP6RecvStruct<'a>) {
+        self.receiver.set(receiver);
+    }
+
+    /// Start receiving from the UART.
+    pub fn start(&self) -> Result<(), ErrorCode> {
+        let rx_byte = self.rx_byte.take().ok_or(ErrorCode::ALREADY)?;
+        self.uart
+            .receive_buffer(rx_byte, 1)
+            .map_err(|(ecode, rx_byte)| {
+                self.rx_byte.replace(rx_byte);
+                ecode
+            })
+    }
+
+    fn receive_frame(&self, packet: &[u8]) {
+        let ip6_header = match IP6Header::decode(packet).done() {
+            Some((_, ip6_header)) => ip6_header,
+            None => return,
+        };
+        let packet_len = ip6_header.get_total_len() as usize;
+        if packet_len > packet.len() {
+            debug!("serial: truncated IPv6 packet");
+            return;
+        }
+        self.receiver
+            .map(|receiver| receiver.receive_packet(&packet[..packet_len]));
+    }
+}
+
+impl<'a> uart::TransmitClient for IP6SerialStruct<'a> {
+    fn transmitted_buffer(
+        &self,
+        tx_buffer: &'static mut [u8],
+        _tx_len: usize,
+        rval: Result<(), ErrorCode>,
+    ) {
+        self.tx_buf.replace(tx_buffer);
+        self.client.map(move |client| {
+            client.send_done(rval);
+        });
+    }
+}
+
+impl<'a> uart::ReceiveClient for IP6SerialStruct<'a> {
+    fn received_buffer(
+        &self,
+        rx_buffer: &'static mut [u8],
+        rx_len: usize,
+        rval: Result<(), ErrorCode>,
+        _error: uart::Error,
+    ) {
+        if rval.is_err() {
+            // Drop the frame in progress: bytes may have been lost
+            self.decoder.set(FrameDecoder::new(self.framing));
+        } else {
+            self.rx_frame.take().map(|rx_frame| {
+                let mut decoder = self.decoder.get();
+                for byte in rx_buffer[..rx_len].iter() {
+                    if let Some(len) = decoder.push(*byte, rx_frame) {
+                        self.receive_frame(&rx_frame[..len]);
+                    }
+                }
+                self.decoder.set(decoder);
+                self.rx_frame.replace(rx_frame);
+            });
+        }
+        if let Err((ecode, rx_buffer)) = self.uart.receive_buffer(rx_buffer, 1) {
+            debug!("serial: failed to restart receive: {:?}", ecode);
+            self.rx_byte.replace(rx_buffer);
+        }
+    }
+}
@@ -0,0 +1,505 @@
+//! IPv6 Neighbor Discovery (RFC 4861, RFC 6775) and stateless address
+//! autoconfiguration (RFC 4862) for the 6LoWPAN interface.
+//!
+//! `NeighborDiscovery` sits between the UDP stack and the IPv6 layer. It
+//! passes packets through in both directions, and handles the Neighbor
+//! Discovery messages itself:
+//!
+//! - The link-local address is formed from the 802.15.4 source address of
+//!   the interface, and is only assigned once duplicate address detection
+//!   (DAD) has completed without a reply.
+//! - Router Solicitations are then sent to the all-routers address until a
+//!   Router Advertisement arrives. An advertised prefix with the autonomous
+//!   flag set is combined with the interface identifier to form a global
+//!   address, which is also assigned after DAD. The router becomes the
+//!   gateway of the IPv6 sender.
+//! - Neighbor Solicitations for assigned addresses are answered with
+//!   Neighbor Advertisements.
+//!
+//! Assigned addresses are published through `addresses()`, which the UDP
+//! driver uses as its interface list, and the preferred address (the global
+//! address once there is one) is set as the source address of the IPv6
+//! sender.
+//!
+//! As on other 6LoWPAN nodes (RFC 6775), link-local destinations are
+//! resolved from their interface identifier and everything else is sent to
+//! the router, so no neighbor cache is needed. Addresses do not expire, and
+//! only the first prefix of an advertisement is used.
+//!
+//! In route-over meshes the prefix is often distributed by the routing
+//! protocol instead of Router Advertisements; such prefixes are passed in
+//! through the `PrefixClient` trait.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let nd = static_init!(
+//!     NeighborDiscovery<'static, IP6SendStruct<'static, VirtualMuxAlarm<'static, A>>, VirtualMuxAlarm<'static, A>>,
+//!     NeighborDiscovery::new(ip_send, nd_alarm, src_mac_addr, &mut ND_BUF, net_cap)
+//! );
+//! nd_alarm.set_alarm_client(nd);
+//! ip_send.set_client(nd);
+//! ip_receive.set_client(nd);
+//! nd.set_client(udp_send_mux);
+//! nd.set_receive_client(udp_recv_mux);
+//! nd.start();
+//! ```
+
+use crate::net::icmpv6::ndp::{LinkAddress, NdpMessage, PrefixInformation, ND_HOP_LIMIT};
+use crate::net::icmpv6::ICMP6Header;
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::hil::time::{self, ConvertTicks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// Number of addresses of the interface: the link-local address and one
+/// global address.
+pub const NUM_ADDRESSES: usize = 2;
+const LINK_LOCAL: usize = 0;
+const GLOBAL: usize = 1;
+
+/// Length of the buffer Neighbor Discovery messages are built in. This fits a
+/// Router Advertisement with an extended source address and a prefix.
+pub const BUF_LEN: usize = 64;
+
+const ICMP_HDR_LEN: usize = 8;
+
+/// Delay before the first message is sent (MAX_RTR_SOLICITATION_DELAY).
+const START_DELAY_MS: u32 = 1000;
+
+/// Time to wait for a reply to a DAD solicitation (RETRANS_TIMER).
+const RETRANS_TIMER_MS: u32 = 1000;
+
+/// Number of Router Solicitations sent before giving up
+/// (MAX_RTR_SOLICITATIONS).
+const MAX_RTR_SOLICITATIONS: u8 = 3;
+const RTR_SOLICITATION_INTERVAL_MS: u32 = 4000;
+
+/// The all-nodes multicast address, ff02::1.
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01]);
+/// The all-routers multicast address, ff02::2.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+/// Receives prefixes advertised by protocols other than Neighbor Discovery,
+/// such as the Prefix Information option of an RPL DIO.
+pub trait PrefixClient {
+    fn receive_prefix(&self, prefix: &PrefixInformation);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting to send the first solicitation.
+    Starting,
+    /// Waiting for replies to a DAD solicitation for the tentative address,
+    /// which is assigned to the given slot if there are none.
+    Dad(usize),
+    /// Waiting for a Router Advertisement after the given number of
+    /// solicitations.
+    Soliciting(u8),
+}
+
+pub struct NeighborDiscovery<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> {
+    ip_sender: &'a S,
+    alarm: &'a A,
+    src_mac_addr: MacAddress,
+    addresses: [Cell<IPAddr>; NUM_ADDRESSES],
+    tentative: Cell<IPAddr>,
+    state: Cell<State>,
+    buf: TakeCell<'static, [u8]>,
+    // A message that could not be sent while a packet of the upper layer was
+    // in flight, with whether it is sent from the unspecified address
+    pending: OptionalCell<(IPAddr, NdpMessage, bool)>,
+    nd_in_flight: Cell<bool>,
+    upper_in_flight: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+    send_client: OptionalCell<&'a dyn IP6SendClient>,
+    recv_client: OptionalCell<&'a dyn IP6RecvClient>,
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> NeighborDiscovery<'a, S, A> {
+    pub fn new(
+        ip_sender: &'a S,
+        alarm: &'a A,
+        src_mac_addr: MacAddress,
+        buf: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> NeighborDiscovery<'a, S, A> {
+        NeighborDiscovery {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            src_mac_addr: src_mac_addr,
+            addresses: [Cell::new(IPAddr::new()), Cell::new(IPAddr::new())],
+            tentative: Cell::new(IPAddr::new()),
+            state: Cell::new(State::Idle),
+            buf: TakeCell::new(buf),
+            pending: OptionalCell::empty(),
+            nd_in_flight: Cell::new(false),
+            upper_in_flight: Cell::new(false),
+            net_cap: net_cap,
+            send_client: OptionalCell::empty(),
+            recv_client: OptionalCell::empty(),
+        }
+    }
+
+    /// Starts address autoconfiguration. The first solicitation is sent after
+    /// a short delay, so this can be called while the board is being set up.
+    pub fn start(&self) {
+        self.state.set(State::Starting);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(START_DELAY_MS));
+    }
+
+    /// The addresses of the interface, unspecified until they are assigned.
+    /// The first is the link-local address and the second the global address.
+    pub fn addresses(&self) -> &[Cell<IPAddr>] {
+        &self.addresses
+    }
+
+    /// Set the client that received packets other than Neighbor Discovery
+    /// messages are passed to.
+    pub fn set_receive_client(&self, client: &'a dyn IP6RecvClient) {
+        self.recv_client.set(client);
+    }
+
+    fn is_assigned(&self, addr: &IPAddr) -> bool {
+        !addr.is_unspecified() && self.addresses.iter().any(|slot| slot.get() == *addr)
+    }
+
+    fn link_address(&self) -> LinkAddress {
+        match self.src_mac_addr {
+            MacAddress::Short(short_addr) => LinkAddress::new(&short_addr.to_be_bytes()),
+            MacAddress::Long(long_addr) => LinkAddress::new(&long_addr),
+        }
+    }
+
+    /// The preferred source address: the global address if there is one.
+    fn preferred_address(&self) -> IPAddr {
+        let global = self.addresses[GLOBAL].get();
+        if global.is_unspecified() {
+            self.addresses[LINK_LOCAL].get()
+        } else {
+            global
+        }
+    }
+
+    /// Sends a DAD solicitation for the tentative address, from the
+    /// unspecified address to its solicited-node multicast address.
+    fn start_dad(&self, slot: usize, addr: IPAddr) {
+        self.tentative.set(addr);
+        self.state.set(State::Dad(slot));
+        let solicitation = NdpMessage::NeighborSolicitation {
+            target: addr,
+            source_link_addr: None,
+        };
+        self.send_ndp(addr.solicited_node_multicast(), solicitation, true);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(RETRANS_TIMER_MS));
+    }
+
+    fn solicit_router(&self, count: u8) {
+        self.state.set(State::Soliciting(count + 1));
+        let solicitation = NdpMessage::RouterSolicitation {
+            source_link_addr: Some(self.link_address()),
+        };
+        self.send_ndp(ALL_ROUTERS, solicitation, false);
+        self.alarm.set_alarm(
+            self.alarm.now(),
+            self.alarm.ticks_from_ms(RTR_SOLICITATION_INTERVAL_MS),
+        );
+    }
+
+    /// Handles a message from another node claiming `addr`. Returns whether
+    /// it is our tentative address.
+    fn duplicate_detected(&self, addr: IPAddr) -> bool {
+        if let State::Dad(_) = self.state.get() {
+            if self.tentative.get() == addr {
+                // The address is not assigned. Without a link-local address
+                // no router is solicited, so autoconfiguration stops.
+                debug!("Duplicate address detected: {:?}", addr);
+                let _ = self.alarm.disarm();
+                self.state.set(State::Idle);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Forms a global address from an advertised prefix, and starts DAD for
+    /// it unless autoconfiguration is already in progress or a global address
+    /// is assigned.
+    fn autoconfigure(&self, prefix: &PrefixInformation) {
+        if !prefix.autonomous || prefix.prefix_len != 64 {
+            return;
+        }
+        let link_local = self.addresses[LINK_LOCAL].get();
+        if link_local.is_unspecified() || !self.addresses[GLOBAL].get().is_unspecified() {
+            return;
+        }
+        let mut addr = link_local;
+        addr.0[..8].copy_from_slice(&prefix.prefix.0[..8]);
+        if addr.is_unicast_link_local() {
+            return;
+        }
+        match self.state.get() {
+            State::Idle => {}
+            State::Soliciting(_) => {
+                // A prefix is all that is needed from a router
+                let _ = self.alarm.disarm();
+            }
+            State::Starting | State::Dad(_) => return,
+        }
+        self.start_dad(GLOBAL, addr);
+    }
+
+    /// Sends a Neighbor Discovery message, or holds it until the packet of
+    /// the upper layer has been sent. Messages are dropped if another is
+    /// already waiting; the protocol retransmits where it matters.
+    fn send_ndp(&self, dst: IPAddr, message: NdpMessage, unspecified_src: bool) {
+        if self.nd_in_flight.get() || self.upper_in_flight.get() {
+            if self.pending.is_none() {
+                self.pending.set((dst, message, unspecified_src));
+            }
+            return;
+        }
+        if let Err(ecode) = self.transmit_ndp(dst, message, unspecified_src) {
+            debug!("Failed to send ND message: {:?}", ecode);
+        }
+    }
+
+    fn transmit_ndp(
+        &self,
+        dst: IPAddr,
+        message: NdpMessage,
+        unspecified_src: bool,
+    ) -> Result<(), ErrorCode> {
+        let buf = self.buf.take().ok_or(ErrorCode::BUSY)?;
+        let len = match message.encode(buf).done() {
+            Some((len, _)) => len,
+            None => {
+                self.buf.replace(buf);
+                return Err(ErrorCode::SIZE);
+            }
+        };
+        let mut icmp_header = match ICMP6Header::decode(&buf[..ICMP_HDR_LEN]).done() {
+            Some((_, icmp_header)) => icmp_header,
+            None => {
+                self.buf.replace(buf);
+                return Err(ErrorCode::FAIL);
+            }
+        };
+        icmp_header.set_len(len as u16);
+
+        // The IPv6 sender copies the payload and the source address into its
+        // packet, so both can be restored as soon as `send_to` returns
+        if unspecified_src {
+            self.ip_sender.set_addr(IPAddr::new());
+        }
+        let mut payload = LeasableMutableBuffer::new(buf);
+        payload.slice(ICMP_HDR_LEN..len);
+        let result = self.ip_sender.send_to(
+            dst,
+            TransportHeader::ICMP(icmp_header),
+            &payload,
+            self.net_cap,
+        );
+        self.buf.replace(payload.take());
+        if unspecified_src {
+            self.ip_sender.set_addr(self.preferred_address());
+        }
+        if result.is_ok() {
+            self.nd_in_flight.set(true);
+        }
+        result
+    }
+
+    fn send_pending(&self) {
+        self.pending.take().map(|(dst, message, unspecified_src)| {
+            if let Err(ecode) = self.transmit_ndp(dst, message, unspecified_src) {
+                debug!("Failed to send ND message: {:?}", ecode);
+            }
+        });
+    }
+
+    /// Handles a Neighbor Discovery message. Returns whether `payload` was
+    /// one.
+    fn receive_ndp(&self, header: &IP6Header, payload: &[u8]) -> bool {
+        let message = match NdpMessage::decode(payload).done() {
+            Some((_, message)) => message,
+            None => return false,
+        };
+        // Messages that may have been forwarded by a router are discarded
+        if header.get_hop_limit() != ND_HOP_LIMIT {
+            return true;
+        }
+        let src_addr = header.get_src_addr();
+
+        match message {
+            NdpMessage::NeighborSolicitation { target, .. } => {
+                // A solicitation from the unspecified address for our
+                // tentative address means another node is running DAD for it
+                let duplicate = src_addr.is_unspecified() && self.duplicate_detected(target);
+                if !duplicate && self.is_assigned(&target) {
+                    let advertisement = NdpMessage::NeighborAdvertisement {
+                        router: false,
+                        solicited: !src_addr.is_unspecified(),
+                        override_entry: true,
+                        target: target,
+                        target_link_addr: Some(self.link_address()),
+                    };
+                    let dst = if src_addr.is_unspecified() {
+                        ALL_NODES
+                    } else {
+                        src_addr
+                    };
+                    self.send_ndp(dst, advertisement, false);
+                }
+            }
+            NdpMessage::NeighborAdvertisement { target, .. } => {
+                self.duplicate_detected(target);
+            }
+            NdpMessage::RouterAdvertisement {
+                router_lifetime,
+                source_link_addr,
+                prefix,
+                ..
+            } => {
+                if !src_addr.is_unicast_link_local() {
+                    return true;
+                }
+                if router_lifetime > 0 {
+                    source_link_addr
+                        .and_then(|addr| link_mac_address(&addr))
+                        .map(|gateway| self.ip_sender.set_gateway(gateway));
+                }
+                if let State::Soliciting(_) = self.state.get() {
+                    let _ = self.alarm.disarm();
+                    self.state.set(State::Idle);
+                }
+                prefix.map(|prefix| self.autoconfigure(&prefix));
+            }
+            // Only routers answer Router Solicitations
+            NdpMessage::RouterSolicitation { .. } => {}
+        }
+        true
+    }
+}
+
+/// Converts the link-layer address of an option to an 802.15.4 address. The
+/// option of a short address is padded to 6 bytes (RFC 4944, section 8).
+fn link_mac_address(addr: &LinkAddress) -> Option<MacAddress> {
+    let bytes = addr.as_slice();
+    match bytes.len() {
+        6 => Some(MacAddress::Short((bytes[0] as u16) << 8 | bytes[1] as u16)),
+        8 => {
+            let mut long_addr = [0; 8];
+            long_addr.copy_from_slice(bytes);
+            Some(MacAddress::Long(long_addr))
+        }
+        _ => None,
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> time::AlarmClient for NeighborDiscovery<'a, S, A> {
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Idle => {}
+            State::Starting => {
+                self.start_dad(LINK_LOCAL, IPAddr::generate_from_mac(self.src_mac_addr));
+            }
+            State::Dad(slot) => {
+                // No node has claimed the tentative address
+                self.addresses[slot].set(self.tentative.get());
+                self.ip_sender.set_addr(self.preferred_address());
+                if slot == LINK_LOCAL {
+                    self.solicit_router(0);
+                } else {
+                    self.state.set(State::Idle);
+                }
+            }
+            State::Soliciting(count) => {
+                if count < MAX_RTR_SOLICITATIONS {
+                    self.solicit_router(count);
+                } else {
+                    self.state.set(State::Idle);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> PrefixClient for NeighborDiscovery<'a, S, A> {
+    fn receive_prefix(&self, prefix: &PrefixInformation) {
+        self.autoconfigure(prefix);
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> IP6Sender<'a> for NeighborDiscovery<'a, S, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.send_client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.ip_sender.set_addr(src_addr);
+    }
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        self.ip_sender.set_gateway(gateway);
+    }
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if self.nd_in_flight.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let result = self
+            .ip_sender
+            .send_to(dst, transport_header, payload, net_cap);
+        if result.is_ok() {
+            self.upper_in_flight.set(true);
+        }
+        result
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> IP6SendClient for NeighborDiscovery<'a, S, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if self.nd_in_flight.get() {
+            self.nd_in_flight.set(false);
+        } else {
+            self.upper_in_flight.set(false);
+            self.send_client.map(|client| client.send_done(result));
+        }
+        if !self.nd_in_flight.get() && !self.upper_in_flight.get() {
+            self.send_pending();
+        }
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> IP6RecvClient for NeighborDiscovery<'a, S, A> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        if header.get_next_header() == ip6_nh::ICMP && self.receive_ndp(&header, payload) {
+            return;
+        }
+        self.recv_client
+            .map(|client| client.receive(header, payload));
+    }
+}
@@ -0,0 +1,166 @@
+//! Next-hop selection for IPv6 packets that are not sent to a neighbor on the
+//! local link.
+//!
+//! The [Router](trait.Router.html) trait is consulted by `IP6SendStruct` to
+//! pick the next hop of unicast packets, both those sent from this node and
+//! those it forwards. A routing protocol such as RPL implements it, typically
+//! on top of a [RoutingTable](struct.RoutingTable.html).
+
+use crate::net::ipv6::ip_utils::IPAddr;
+
+use core::cell::Cell;
+
+/// Provides the next hop towards a destination address.
+pub trait Router {
+    /// Returns the link-local address of the neighbor that packets to `dst`
+    /// should be sent to, or `None` if there is no route, in which case the
+    /// packet is sent to the default gateway.
+    fn next_hop(&self, dst: &IPAddr) -> Option<IPAddr>;
+}
+
+#[derive(Copy, Clone)]
+struct Route {
+    target: IPAddr,
+    next_hop: IPAddr,
+    /// Remaining lifetime in seconds, or `None` if the route does not expire.
+    lifetime: Option<u32>,
+}
+
+/// A table of host routes with room for `N` entries. When the table is full,
+/// new routes replace existing ones in round-robin order. Routes are removed
+/// once their lifetime runs out, as counted by calls to `age`.
+pub struct RoutingTable<const N: usize> {
+    routes: [Cell<Option<Route>>; N],
+    next: Cell<usize>,
+}
+
+impl<const N: usize> RoutingTable<N> {
+    pub fn new() -> RoutingTable<N> {
+        const EMPTY: Cell<Option<Route>> = Cell::new(None);
+        RoutingTable {
+            routes: [EMPTY; N],
+            next: Cell::new(0),
+        }
+    }
+
+    /// Adds or updates the route to `target`, which expires after
+    /// `lifetime` seconds, or never if `lifetime` is `None`.
+    pub fn insert(&self, target: IPAddr, next_hop: IPAddr, lifetime: Option<u32>) {
+        let route = Some(Route {
+            target: target,
+            next_hop: next_hop,
+            lifetime: lifetime,
+        });
+        if let Some(slot) = self.find(&target) {
+            self.routes[slot].set(route);
+        } else if let Some(slot) = self.routes.iter().position(|r| r.get().is_none()) {
+            self.routes[slot].set(route);
+        } else if N > 0 {
+            let slot = self.next.get();
+            self.routes[slot].set(route);
+            self.next.set((slot + 1) % N);
+        }
+    }
+
+    /// Returns the next hop towards `target`, if there is a route to it.
+    pub fn lookup(&self, target: &IPAddr) -> Option<IPAddr> {
+        self.find(target)
+            .and_then(|slot| self.routes[slot].get())
+            .map(|route| route.next_hop)
+    }
+
+    /// Removes the route to `target`.
+    pub fn remove(&self, target: &IPAddr) {
+        if let Some(slot) = self.find(target) {
+            self.routes[slot].set(None);
+        }
+    }
+
+    /// Removes all routes through `next_hop`.
+    pub fn remove_next_hop(&self, next_hop: &IPAddr) {
+        for route in self.routes.iter() {
+            if route.get().map_or(false, |r| r.next_hop == *next_hop) {
+                route.set(None);
+            }
+        }
+    }
+
+    /// Removes all routes.
+    pub fn clear(&self) {
+        for route in self.routes.iter() {
+            route.set(None);
+        }
+    }
+
+    /// Counts `seconds` off the lifetime of every route, removing the
+    /// routes that expire.
+    pub fn age(&self, seconds: u32) {
+        for route in self.routes.iter() {
+            route.set(route.get().and_then(|mut r| match r.lifetime {
+                Some(lifetime) if lifetime <= seconds => None,
+                Some(lifetime) => {
+                    r.lifetime = Some(lifetime - seconds);
+                    Some(r)
+                }
+                None => Some(r),
+            }));
+        }
+    }
+
+    fn find(&self, target: &IPAddr) -> Option<usize> {
+        self.routes
+            .iter()
+            .position(|r| r.get().map_or(false, |r| r.target == *target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(last: u8) -> IPAddr {
+        let mut addr = IPAddr::new();
+        addr.0[0] = 0xfd;
+        addr.0[15] = last;
+        addr
+    }
+
+    #[test]
+    fn routes_expire() {
+        let table: RoutingTable<4> = RoutingTable::new();
+        table.insert(addr(1), addr(10), Some(60));
+        table.insert(addr(2), addr(10), None);
+        table.insert(addr(3), addr(11), Some(30));
+
+        table.age(29);
+        assert!(table.lookup(&addr(1)) == Some(addr(10)));
+        assert!(table.lookup(&addr(3)) == Some(addr(11)));
+
+        table.age(1);
+        assert!(table.lookup(&addr(3)).is_none());
+        assert!(table.lookup(&addr(1)) == Some(addr(10)));
+
+        // A new DAO refreshes the lifetime
+        table.insert(addr(1), addr(11), Some(60));
+        table.age(59);
+        assert!(table.lookup(&addr(1)) == Some(addr(11)));
+        table.age(1);
+        assert!(table.lookup(&addr(1)).is_none());
+
+        table.age(u32::MAX);
+        assert!(table.lookup(&addr(2)) == Some(addr(10)));
+        table.clear();
+        assert!(table.lookup(&addr(2)).is_none());
+    }
+
+    #[test]
+    fn full_table_replaces_round_robin() {
+        let table: RoutingTable<2> = RoutingTable::new();
+        table.insert(addr(1), addr(10), None);
+        table.insert(addr(2), addr(10), None);
+        table.insert(addr(3), addr(10), None);
+        assert!(table.lookup(&addr(1)).is_none());
+        assert!(table.lookup(&addr(2)) == Some(addr(10)));
+        assert!(table.lookup(&addr(3)) == Some(addr(10)));
+    }
+}
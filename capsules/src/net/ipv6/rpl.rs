@@ -0,0 +1,934 @@
+//! RPL, the IPv6 Routing Protocol for Low-Power and Lossy Networks
+//! (RFC 6550), with the Objective Function Zero (RFC 6552).
+//!
+//! `Rpl` sits between `NeighborDiscovery` and the UDP stack, passing packets
+//! through in both directions like `NeighborDiscovery` does, and handling
+//! the RPL control messages itself:
+//!
+//! - While detached, DODAG Information Solicitations are multicast
+//!   periodically once the link-local address is assigned.
+//! - DODAG Information Objects (DIOs) are used to join a DODAG and to select
+//!   the preferred parent: the neighbor with the lowest rank, changing only
+//!   for an improvement of at least MinHopRankIncrease. Once joined, DIOs are
+//!   multicast on a Trickle timer (RFC 6206). A prefix in the DIO is passed
+//!   to address autoconfiguration.
+//! - Destination Advertisement Objects (DAOs) advertise the global address
+//!   of the node to the preferred parent, which installs a route and
+//!   propagates the DAO towards the root. DAOs are retransmitted on Trickle
+//!   events until they are acknowledged, and sent again before half of the
+//!   route lifetime of the DODAG has passed.
+//! - Routes expire after the lifetime advertised in the DAO that installed
+//!   them. A preferred parent that has not sent a DIO for
+//!   `PARENT_TIMEOUT_INTERVALS` maximum Trickle intervals is abandoned, after
+//!   it has been asked for a DIO with a unicast DIS.
+//!
+//! `Rpl` implements `Router`, so that `IP6SendStruct` sends packets for
+//! which there is a downward route to the child it was learned from, and
+//! everything else to the preferred parent. Together with the forwarding of
+//! `IP6RecvStruct`, this lets packets cross several hops of the mesh in both
+//! directions.
+//!
+//! A node becomes the root of a storing-mode DODAG with `start_root`.
+//!
+//! Known limitations: non-storing mode, which needs source routing headers
+//! (RFC 6554), is not implemented, and DIOs of non-storing DODAGs are
+//! ignored. The RPL hop-by-hop option, used to detect loops in the data path,
+//! is neither added nor checked, so a routing loop lasts until the hop limit
+//! of the packets runs out.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let rpl = static_init!(
+//!     Rpl<'static, NeighborDiscovery<'static, ...>, VirtualMuxAlarm<'static, A>>,
+//!     Rpl::new(nd, rpl_alarm, nd.addresses(), &mut RPL_BUF, net_cap)
+//! );
+//! rpl_alarm.set_alarm_client(rpl);
+//! nd.set_client(rpl);
+//! nd.set_receive_client(rpl);
+//! rpl.set_prefix_client(nd);
+//! rpl.set_client(udp_send_mux);
+//! rpl.set_receive_client(udp_recv_mux);
+//! ip_send.set_router(rpl);
+//! rpl.start();
+//! ```
+
+use crate::net::icmpv6::ndp::PrefixInformation;
+use crate::net::icmpv6::rpl::{mode_of_operation, Dao, DaoAck, Dio, DodagConfiguration};
+use crate::net::icmpv6::rpl::{RplMessage, Target, TransitInformation};
+use crate::net::icmpv6::rpl::{ALL_RPL_NODES, ICMP_TYPE, INFINITE_RANK};
+use crate::net::icmpv6::ICMP6Header;
+use crate::net::ieee802154::MacAddress;
+use crate::net::ipv6::ip_utils::{ip6_nh, IPAddr};
+use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_send::{IP6SendClient, IP6Sender};
+use crate::net::ipv6::neighbor_discovery::PrefixClient;
+use crate::net::ipv6::routing::{Router, RoutingTable};
+use crate::net::ipv6::{IP6Header, TransportHeader};
+use crate::net::network_capabilities::NetworkCapability;
+
+use core::cell::Cell;
+
+use kernel::debug;
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// Length of the buffer RPL messages are built in. This fits a DIO with a
+/// DODAG Configuration and a Prefix Information option.
+pub const BUF_LEN: usize = 96;
+
+/// Number of downward routes kept in storing mode.
+pub const NUM_ROUTES: usize = 16;
+
+const ICMP_HDR_LEN: usize = 8;
+
+// Indices into the addresses of the interface, see
+// `NeighborDiscovery::addresses`
+const LINK_LOCAL: usize = 0;
+const GLOBAL: usize = 1;
+
+/// Interval between DODAG Information Solicitations while detached.
+const DIS_INTERVAL_MS: u32 = 10_000;
+
+/// Upper bound of the Trickle interval, so that it fits an alarm.
+const MAX_TRICKLE_INTERVAL_MS: u32 = 1 << 22;
+
+/// Number of maximum Trickle intervals without a DIO from the preferred
+/// parent after which it is abandoned.
+const PARENT_TIMEOUT_INTERVALS: u32 = 3;
+
+/// DAO path lifetime that never expires.
+const INFINITE_LIFETIME: u8 = 0xff;
+
+/// The default RPL instance.
+const DEFAULT_INSTANCE: u8 = 0;
+
+/// Initial value of the lollipop counters (RFC 6550, section 7.2).
+const SEQUENCE_INIT: u8 = 240;
+
+/// OF0 rank increase, in units of MinHopRankIncrease (DEFAULT_STEP_OF_RANK
+/// with the default rank factor and stretch).
+const STEP_OF_RANK: u16 = 3;
+
+/// Objective Code Point of OF0.
+const OCP_OF0: u16 = 0;
+
+/// The configuration advertised by the root. The Trickle parameters are
+/// slower than the defaults of RFC 6550, which suit mains-powered routers.
+const ROOT_CONFIGURATION: DodagConfiguration = DodagConfiguration {
+    dio_interval_doublings: 8,
+    dio_interval_min: 12,
+    dio_redundancy: 10,
+    max_rank_increase: 7 * 256,
+    min_hop_rank_increase: 256,
+    objective_code_point: OCP_OF0,
+    default_lifetime: 60,
+    lifetime_unit: 60,
+};
+
+/// The DODAG this node is a member of.
+#[derive(Copy, Clone)]
+struct Dodag {
+    instance_id: u8,
+    version: u8,
+    dodag_id: IPAddr,
+    mode_of_operation: u8,
+    grounded: bool,
+    preference: u8,
+    configuration: DodagConfiguration,
+    prefix: Option<PrefixInformation>,
+    rank: u16,
+    dtsn: u8,
+}
+
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    dtsn: u8,
+    /// Time since the last DIO from this parent.
+    silent_ms: u32,
+    /// Whether a DIO has been solicited since the last one was heard.
+    solicited: bool,
+}
+
+impl Parent {
+    fn new(addr: IPAddr, dtsn: u8) -> Parent {
+        Parent {
+            addr: addr,
+            dtsn: dtsn,
+            silent_ms: 0,
+            solicited: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Timer {
+    /// Soliciting DIOs while detached.
+    Dis,
+    /// Waiting for the transmission time of the Trickle interval, after
+    /// which the given number of ms remain in the interval.
+    TrickleTransmit(u32),
+    /// Waiting for the end of the Trickle interval.
+    TrickleInterval,
+}
+
+pub struct Rpl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> {
+    ip_sender: &'a S,
+    alarm: &'a A,
+    addresses: &'a [Cell<IPAddr>],
+    buf: TakeCell<'static, [u8]>,
+    // A message that could not be sent while another packet was in flight,
+    // with its destination and source addresses
+    pending: OptionalCell<(IPAddr, IPAddr, RplMessage)>,
+    rpl_in_flight: Cell<bool>,
+    upper_in_flight: Cell<bool>,
+    net_cap: &'static NetworkCapability,
+    send_client: OptionalCell<&'a dyn IP6SendClient>,
+    recv_client: OptionalCell<&'a dyn IP6RecvClient>,
+    prefix_client: OptionalCell<&'a dyn PrefixClient>,
+    routes: RoutingTable<NUM_ROUTES>,
+    dodag: OptionalCell<Dodag>,
+    parent: OptionalCell<Parent>,
+    root_prefix: OptionalCell<IPAddr>,
+    timer: Cell<Timer>,
+    // The time up to which parents, routes and our DAO have been aged, and
+    // the ms left over from aging the routes in seconds
+    clock: Cell<A::Ticks>,
+    clock_ms: Cell<u32>,
+    trickle_interval: Cell<u32>,
+    trickle_counter: Cell<u8>,
+    rng: Cell<u32>,
+    dao_sequence: Cell<u8>,
+    path_sequence: Cell<u8>,
+    dao_pending: Cell<bool>,
+    // Seconds since our DAO was last scheduled
+    dao_age: Cell<u32>,
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> Rpl<'a, S, A> {
+    pub fn new(
+        ip_sender: &'a S,
+        alarm: &'a A,
+        addresses: &'a [Cell<IPAddr>],
+        buf: &'static mut [u8],
+        net_cap: &'static NetworkCapability,
+    ) -> Rpl<'a, S, A> {
+        Rpl {
+            ip_sender: ip_sender,
+            alarm: alarm,
+            addresses: addresses,
+            buf: TakeCell::new(buf),
+            pending: OptionalCell::empty(),
+            rpl_in_flight: Cell::new(false),
+            upper_in_flight: Cell::new(false),
+            net_cap: net_cap,
+            send_client: OptionalCell::empty(),
+            recv_client: OptionalCell::empty(),
+            prefix_client: OptionalCell::empty(),
+            routes: RoutingTable::new(),
+            dodag: OptionalCell::empty(),
+            parent: OptionalCell::empty(),
+            root_prefix: OptionalCell::empty(),
+            timer: Cell::new(Timer::Dis),
+            clock: Cell::new(A::Ticks::from(0)),
+            clock_ms: Cell::new(0),
+            trickle_interval: Cell::new(0),
+            trickle_counter: Cell::new(0),
+            rng: Cell::new(0),
+            dao_sequence: Cell::new(SEQUENCE_INIT),
+            path_sequence: Cell::new(SEQUENCE_INIT),
+            dao_pending: Cell::new(false),
+            dao_age: Cell::new(0),
+        }
+    }
+
+    /// Starts looking for a DODAG to join.
+    pub fn start(&self) {
+        self.clock.set(self.alarm.now());
+        self.set_timer(Timer::Dis, DIS_INTERVAL_MS);
+    }
+
+    /// Makes this node the root of a storing-mode DODAG for the /64 `prefix`.
+    /// The DODAG is created once the link-local address is assigned; its
+    /// DODAGID is the address of the root in `prefix`.
+    pub fn start_root(&self, prefix: IPAddr) {
+        self.root_prefix.set(prefix);
+        self.start();
+    }
+
+    /// Set the client that received packets other than RPL messages are
+    /// passed to.
+    pub fn set_receive_client(&self, client: &'a dyn IP6RecvClient) {
+        self.recv_client.set(client);
+    }
+
+    /// Set the client that prefixes advertised in DIOs are passed to.
+    pub fn set_prefix_client(&self, client: &'a dyn PrefixClient) {
+        self.prefix_client.set(client);
+    }
+
+    fn is_root(&self) -> bool {
+        self.root_prefix.is_some()
+    }
+
+    fn link_local(&self) -> IPAddr {
+        self.addresses[LINK_LOCAL].get()
+    }
+
+    fn global(&self) -> IPAddr {
+        self.addresses[GLOBAL].get()
+    }
+
+    fn preferred_address(&self) -> IPAddr {
+        let global = self.global();
+        if global.is_unspecified() {
+            self.link_local()
+        } else {
+            global
+        }
+    }
+
+    fn set_timer(&self, timer: Timer, ms: u32) {
+        // Account for the time spent in a timer that is cancelled
+        self.advance_clock();
+        self.timer.set(timer);
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    /// Ages the preferred parent, the routes and our DAO by the time since the
+    /// clock was last advanced.
+    fn advance_clock(&self) {
+        let now = self.alarm.now();
+        let elapsed_ms = self.alarm.ticks_to_ms(now.wrapping_sub(self.clock.get()));
+        self.clock.set(now);
+
+        let total_ms = self.clock_ms.get().saturating_add(elapsed_ms);
+        let seconds = total_ms / 1000;
+        self.clock_ms.set(total_ms % 1000);
+        if seconds > 0 {
+            self.routes.age(seconds);
+            self.dao_age.set(self.dao_age.get().saturating_add(seconds));
+        }
+        if let Some(mut parent) = self.parent.extract() {
+            parent.silent_ms = parent.silent_ms.saturating_add(elapsed_ms);
+            self.parent.set(parent);
+        }
+    }
+
+    /// Solicits a DIO from a preferred parent that has been silent for a
+    /// while, abandons it once it times out, and refreshes our DAO before
+    /// the routes to us expire. Returns whether we are still in the DODAG.
+    fn check_lifetimes(&self) -> bool {
+        let (dodag, parent) = match (self.dodag.extract(), self.parent.extract()) {
+            (Some(dodag), Some(parent)) => (dodag, parent),
+            (dodag, _) => return dodag.is_some(),
+        };
+        let timeout = parent_timeout(&dodag.configuration);
+        if parent.silent_ms >= timeout {
+            self.detach();
+            return false;
+        }
+        if parent.silent_ms >= timeout / 3 * 2 && !parent.solicited {
+            self.parent.set(Parent {
+                solicited: true,
+                ..parent
+            });
+            self.send(parent.addr, self.link_local(), RplMessage::Dis);
+        }
+        if let Some(lifetime) =
+            route_lifetime(dodag.configuration.default_lifetime, &dodag.configuration)
+        {
+            if self.dao_age.get() >= lifetime / 2 {
+                self.schedule_dao();
+            }
+        }
+        true
+    }
+
+    /// A xorshift generator, seeded from the interface identifier so that
+    /// neighbors pick different Trickle transmission times.
+    fn random(&self) -> u32 {
+        let mut x = self.rng.get();
+        if x == 0 {
+            let iid = &self.link_local().0[12..16];
+            x = u32::from_be_bytes([iid[0], iid[1], iid[2], iid[3]]) | 1;
+        }
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng.set(x);
+        x
+    }
+
+    /// Restarts the Trickle timer with the minimum interval, after an
+    /// inconsistency or a change of the DODAG.
+    fn trickle_reset(&self) {
+        self.dodag.map(|dodag| {
+            self.trickle_interval.set(trickle_min(&dodag.configuration));
+        });
+        self.trickle_start_interval();
+    }
+
+    fn trickle_start_interval(&self) {
+        self.trickle_counter.set(0);
+        let interval = self.trickle_interval.get().max(2);
+        let t = interval / 2 + self.random() % (interval / 2);
+        self.set_timer(Timer::TrickleTransmit(interval - t), t);
+    }
+
+    /// Counts a consistent DIO heard from a neighbor, which suppresses our
+    /// own DIO once enough have been heard.
+    fn trickle_consistent(&self) {
+        self.trickle_counter
+            .set(self.trickle_counter.get().saturating_add(1));
+    }
+
+    /// Creates the DODAG of the root once its link-local address is known.
+    fn create_root_dodag(&self, prefix: IPAddr) {
+        let mut dodag_id = self.link_local();
+        dodag_id.0[..8].copy_from_slice(&prefix.0[..8]);
+        let prefix_information = PrefixInformation {
+            prefix_len: 64,
+            on_link: false,
+            autonomous: true,
+            valid_lifetime: 0xffffffff,
+            preferred_lifetime: 0xffffffff,
+            prefix: prefix,
+        };
+        self.dodag.set(Dodag {
+            instance_id: DEFAULT_INSTANCE,
+            version: SEQUENCE_INIT,
+            dodag_id: dodag_id,
+            mode_of_operation: mode_of_operation::STORING,
+            grounded: true,
+            preference: 0,
+            configuration: ROOT_CONFIGURATION,
+            prefix: Some(prefix_information),
+            rank: ROOT_CONFIGURATION.min_hop_rank_increase,
+            dtsn: SEQUENCE_INIT,
+        });
+        self.prefix_client
+            .map(|client| client.receive_prefix(&prefix_information));
+        self.trickle_reset();
+    }
+
+    /// Joins the DODAG advertised in `dio`, with its sender as parent.
+    fn join(&self, src: IPAddr, dio: &Dio) {
+        let configuration = dio.configuration.unwrap_or_default();
+        self.dodag.set(Dodag {
+            instance_id: dio.instance_id,
+            version: dio.version,
+            dodag_id: dio.dodag_id,
+            mode_of_operation: dio.mode_of_operation,
+            grounded: dio.grounded,
+            preference: dio.preference,
+            configuration: configuration,
+            prefix: dio.prefix,
+            rank: rank_through(dio.rank, &configuration),
+            dtsn: SEQUENCE_INIT,
+        });
+        self.parent.set(Parent::new(src, dio.dtsn));
+        dio.prefix.map(|prefix| {
+            self.prefix_client
+                .map(|client| client.receive_prefix(&prefix));
+        });
+        self.trickle_reset();
+        self.schedule_dao();
+    }
+
+    /// Leaves the DODAG, advertising an infinite rank so that children look
+    /// for another parent, and starts soliciting DIOs again.
+    fn detach(&self) {
+        if let Some(dodag) = self.dodag.take() {
+            let dio = Dio {
+                rank: INFINITE_RANK,
+                ..self.dio(&dodag)
+            };
+            self.send(ALL_RPL_NODES, self.link_local(), RplMessage::Dio(dio));
+        }
+        self.parent.clear();
+        self.routes.clear();
+        self.dao_pending.set(false);
+        self.set_timer(Timer::Dis, DIS_INTERVAL_MS);
+    }
+
+    fn dio(&self, dodag: &Dodag) -> Dio {
+        Dio {
+            instance_id: dodag.instance_id,
+            version: dodag.version,
+            rank: dodag.rank,
+            grounded: dodag.grounded,
+            mode_of_operation: dodag.mode_of_operation,
+            preference: dodag.preference,
+            dtsn: dodag.dtsn,
+            dodag_id: dodag.dodag_id,
+            configuration: Some(dodag.configuration),
+            prefix: dodag.prefix,
+        }
+    }
+
+    /// Sends a DAO for our global address now, and again on Trickle events
+    /// until it is acknowledged.
+    fn schedule_dao(&self) {
+        let downward = self.dodag.map_or(false, |dodag| {
+            dodag.mode_of_operation != mode_of_operation::NO_DOWNWARD_ROUTES
+        });
+        if downward && !self.is_root() {
+            self.path_sequence
+                .set(self.path_sequence.get().wrapping_add(1));
+            self.dao_pending.set(true);
+            self.dao_age.set(0);
+            self.send_dao();
+        }
+    }
+
+    fn send_dao(&self) {
+        let global = self.global();
+        if global.is_unspecified() {
+            // Sent once address autoconfiguration has completed
+            return;
+        }
+        let (dodag, parent) = match (self.dodag.extract(), self.parent.extract()) {
+            (Some(dodag), Some(parent)) => (dodag, parent),
+            _ => return,
+        };
+        self.dao_sequence
+            .set(self.dao_sequence.get().wrapping_add(1));
+        let dao = Dao {
+            instance_id: dodag.instance_id,
+            ack_requested: true,
+            sequence: self.dao_sequence.get(),
+            dodag_id: None,
+            target: Some(Target {
+                prefix_len: 128,
+                prefix: global,
+            }),
+            transit: Some(TransitInformation {
+                path_control: 0,
+                path_sequence: self.path_sequence.get(),
+                path_lifetime: dodag.configuration.default_lifetime,
+                parent: None,
+            }),
+        };
+        self.send(parent.addr, self.link_local(), RplMessage::Dao(dao));
+    }
+
+    fn receive_dio(&self, src: IPAddr, dio: &Dio) {
+        if !src.is_unicast_link_local() || dio.rank == INFINITE_RANK && self.parent.is_none() {
+            return;
+        }
+        let configuration = dio.configuration.unwrap_or_default();
+        // Non-storing DODAGs need source routing, which is not implemented
+        if configuration.objective_code_point != OCP_OF0
+            || (dio.mode_of_operation != mode_of_operation::STORING
+                && dio.mode_of_operation != mode_of_operation::NO_DOWNWARD_ROUTES)
+        {
+            return;
+        }
+        let dodag = match self.dodag.extract() {
+            Some(dodag) => dodag,
+            None => {
+                // A root waiting for its link-local address joins no other
+                // DODAG
+                if !self.is_root() {
+                    self.join(src, dio);
+                }
+                return;
+            }
+        };
+        if dio.instance_id != dodag.instance_id || dio.dodag_id != dodag.dodag_id {
+            // Only one DODAG is joined at a time
+            return;
+        }
+        if self.is_root() {
+            if dio.version == dodag.version {
+                self.trickle_consistent();
+            }
+            return;
+        }
+        if is_newer(dio.version, dodag.version) {
+            // The root rebuilt the DODAG
+            if dio.rank != INFINITE_RANK {
+                self.join(src, dio);
+            }
+            return;
+        }
+        if dio.version != dodag.version {
+            return;
+        }
+
+        let parent = self.parent.extract();
+        if parent.map_or(false, |parent| parent.addr == src) {
+            if dio.rank == INFINITE_RANK {
+                self.detach();
+                return;
+            }
+            let parent = parent.unwrap();
+            let rank = rank_through(dio.rank, &dodag.configuration);
+            let mut updated = dodag;
+            updated.rank = rank;
+            updated.prefix = dio.prefix.or(dodag.prefix);
+            if dio.dtsn != parent.dtsn && dodag.mode_of_operation == mode_of_operation::STORING {
+                // Ask our own subtree to refresh its routes as well
+                updated.dtsn = updated.dtsn.wrapping_add(1);
+            }
+            self.dodag.set(updated);
+            self.parent.set(Parent::new(src, dio.dtsn));
+            if rank != dodag.rank {
+                self.trickle_reset();
+            } else {
+                self.trickle_consistent();
+            }
+            if dio.dtsn != parent.dtsn {
+                self.schedule_dao();
+            }
+        } else if dio.rank != INFINITE_RANK {
+            let rank = rank_through(dio.rank, &dodag.configuration);
+            if rank.saturating_add(dodag.configuration.min_hop_rank_increase) <= dodag.rank {
+                // A better parent; routes through the old one are left to be
+                // replaced by newer DAOs
+                let mut updated = dodag;
+                updated.rank = rank;
+                self.dodag.set(updated);
+                self.parent.set(Parent::new(src, dio.dtsn));
+                self.trickle_reset();
+                self.schedule_dao();
+            } else {
+                self.trickle_consistent();
+            }
+        }
+    }
+
+    fn receive_dis(&self, src: IPAddr, dst: IPAddr) {
+        self.dodag.map(|dodag| {
+            if dst.is_multicast() {
+                self.trickle_reset();
+            } else {
+                self.send(src, self.link_local(), RplMessage::Dio(self.dio(&dodag)));
+            }
+        });
+    }
+
+    fn receive_dao(&self, src: IPAddr, dao: &Dao) {
+        let dodag = match self.dodag.extract() {
+            Some(dodag) => dodag,
+            None => return,
+        };
+        if dao.instance_id != dodag.instance_id
+            || dodag.mode_of_operation != mode_of_operation::STORING
+            || !src.is_unicast_link_local()
+        {
+            return;
+        }
+        let (target, transit) = match (dao.target, dao.transit) {
+            (Some(target), Some(transit)) => (target, transit),
+            _ => return,
+        };
+        if target.prefix_len != 128 {
+            return;
+        }
+        if transit.path_lifetime == 0 {
+            self.routes.remove(&target.prefix);
+        } else {
+            let lifetime = route_lifetime(transit.path_lifetime, &dodag.configuration);
+            self.routes.insert(target.prefix, src, lifetime);
+        }
+        if dao.ack_requested {
+            let ack = DaoAck {
+                instance_id: dao.instance_id,
+                sequence: dao.sequence,
+                status: 0,
+                dodag_id: dao.dodag_id,
+            };
+            self.send(src, self.link_local(), RplMessage::DaoAck(ack));
+        }
+        if !self.is_root() {
+            self.parent.map(|parent| {
+                self.dao_sequence
+                    .set(self.dao_sequence.get().wrapping_add(1));
+                let propagated = Dao {
+                    instance_id: dao.instance_id,
+                    ack_requested: false,
+                    sequence: self.dao_sequence.get(),
+                    dodag_id: None,
+                    target: Some(target),
+                    transit: Some(transit),
+                };
+                self.send(parent.addr, self.link_local(), RplMessage::Dao(propagated));
+            });
+        }
+    }
+
+    fn receive_dao_ack(&self, ack: &DaoAck) {
+        if ack.sequence == self.dao_sequence.get() {
+            if ack.status >= 128 {
+                debug!("DAO rejected: {}", ack.status);
+            }
+            self.dao_pending.set(false);
+        }
+    }
+
+    /// Sends an RPL message from `src`, or holds it until the packet in
+    /// flight has been sent. Messages are dropped if another is already
+    /// waiting; DIOs and DAOs are retransmitted on Trickle events.
+    fn send(&self, dst: IPAddr, src: IPAddr, message: RplMessage) {
+        if src.is_unspecified() {
+            return;
+        }
+        if self.rpl_in_flight.get() || self.upper_in_flight.get() {
+            self.hold(dst, src, message);
+            return;
+        }
+        self.transmit_or_hold(dst, src, message);
+    }
+
+    fn hold(&self, dst: IPAddr, src: IPAddr, message: RplMessage) {
+        if self.pending.is_none() {
+            self.pending.set((dst, src, message));
+        }
+    }
+
+    /// Transmits a message, or holds it if the layer below is busy sending a
+    /// Neighbor Discovery message. As that completion is not reported here,
+    /// held messages are also retried when the timer fires.
+    fn transmit_or_hold(&self, dst: IPAddr, src: IPAddr, message: RplMessage) {
+        match self.transmit(dst, src, message) {
+            Ok(()) => {}
+            Err(ErrorCode::BUSY) => self.hold(dst, src, message),
+            Err(ecode) => debug!("Failed to send RPL message: {:?}", ecode),
+        }
+    }
+
+    fn transmit(&self, dst: IPAddr, src: IPAddr, message: RplMessage) -> Result<(), ErrorCode> {
+        let buf = self.buf.take().ok_or(ErrorCode::BUSY)?;
+        let len = match message.encode(buf).done() {
+            Some((len, _)) => len,
+            None => {
+                self.buf.replace(buf);
+                return Err(ErrorCode::SIZE);
+            }
+        };
+        let mut icmp_header = match ICMP6Header::decode(&buf[..ICMP_HDR_LEN]).done() {
+            Some((_, icmp_header)) => icmp_header,
+            None => {
+                self.buf.replace(buf);
+                return Err(ErrorCode::FAIL);
+            }
+        };
+        icmp_header.set_len(len as u16);
+
+        // The IPv6 sender copies the payload and the source address into its
+        // packet, so both can be restored as soon as `send_to` returns
+        self.ip_sender.set_addr(src);
+        let mut payload = LeasableMutableBuffer::new(buf);
+        payload.slice(ICMP_HDR_LEN..len);
+        let result = self.ip_sender.send_to(
+            dst,
+            TransportHeader::ICMP(icmp_header),
+            &payload,
+            self.net_cap,
+        );
+        self.buf.replace(payload.take());
+        self.ip_sender.set_addr(self.preferred_address());
+        if result.is_ok() {
+            self.rpl_in_flight.set(true);
+        }
+        result
+    }
+
+    fn send_pending(&self) {
+        self.pending
+            .take()
+            .map(|(dst, src, message)| self.transmit_or_hold(dst, src, message));
+    }
+
+    /// Handles an RPL message. Returns whether `payload` was one.
+    fn receive_rpl(&self, header: &IP6Header, payload: &[u8]) -> bool {
+        if payload.first() != Some(&ICMP_TYPE) {
+            return false;
+        }
+        let src = header.get_src_addr();
+        match RplMessage::decode(payload).done() {
+            Some((_, RplMessage::Dis)) => self.receive_dis(src, header.get_dst_addr()),
+            Some((_, RplMessage::Dio(dio))) => self.receive_dio(src, &dio),
+            Some((_, RplMessage::Dao(dao))) => self.receive_dao(src, &dao),
+            Some((_, RplMessage::DaoAck(ack))) => self.receive_dao_ack(&ack),
+            None => debug!("Malformed RPL message"),
+        }
+        true
+    }
+}
+
+/// The minimum Trickle interval in ms, 2^DIOIntervalMin.
+fn trickle_min(configuration: &DodagConfiguration) -> u32 {
+    let interval = 1u64 << configuration.dio_interval_min.min(32);
+    interval.min(MAX_TRICKLE_INTERVAL_MS as u64) as u32
+}
+
+/// The maximum Trickle interval in ms, the minimum interval doubled
+/// DIOIntervalDoublings times.
+fn trickle_max(configuration: &DodagConfiguration) -> u32 {
+    let interval =
+        (trickle_min(configuration) as u64) << configuration.dio_interval_doublings.min(32);
+    interval.min(MAX_TRICKLE_INTERVAL_MS as u64) as u32
+}
+
+/// The time in ms without a DIO after which the preferred parent is
+/// abandoned.
+fn parent_timeout(configuration: &DodagConfiguration) -> u32 {
+    trickle_max(configuration).saturating_mul(PARENT_TIMEOUT_INTERVALS)
+}
+
+/// The lifetime in seconds of a route advertised with a path lifetime of
+/// `lifetime` units, or `None` if it does not expire.
+fn route_lifetime(lifetime: u8, configuration: &DodagConfiguration) -> Option<u32> {
+    if lifetime == INFINITE_LIFETIME {
+        None
+    } else {
+        Some(lifetime as u32 * configuration.lifetime_unit as u32)
+    }
+}
+
+/// The rank of this node through a parent with the given rank.
+fn rank_through(parent_rank: u16, configuration: &DodagConfiguration) -> u16 {
+    let increase = STEP_OF_RANK.saturating_mul(configuration.min_hop_rank_increase);
+    let rank = parent_rank.saturating_add(increase);
+    if rank == u16::MAX {
+        INFINITE_RANK
+    } else {
+        rank
+    }
+}
+
+/// Compares two values of a lollipop counter, ignoring the distinction
+/// between its linear and circular regions.
+fn is_newer(a: u8, b: u8) -> bool {
+    (a.wrapping_sub(b) as i8) > 0
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> Router for Rpl<'a, S, A> {
+    fn next_hop(&self, dst: &IPAddr) -> Option<IPAddr> {
+        self.routes.lookup(dst).or_else(|| {
+            if self.is_root() {
+                None
+            } else {
+                self.parent.extract().map(|parent| parent.addr)
+            }
+        })
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> time::AlarmClient for Rpl<'a, S, A> {
+    fn alarm(&self) {
+        self.advance_clock();
+        if !self.rpl_in_flight.get() && !self.upper_in_flight.get() {
+            self.send_pending();
+        }
+        if self.timer.get() != Timer::Dis && !self.check_lifetimes() {
+            // Detached from the DODAG, which restarted the DIS timer
+            return;
+        }
+        match self.timer.get() {
+            Timer::Dis => {
+                if self.link_local().is_unspecified() {
+                    self.set_timer(Timer::Dis, DIS_INTERVAL_MS);
+                } else if let Some(prefix) = self.root_prefix.extract() {
+                    self.create_root_dodag(prefix);
+                } else {
+                    self.send(ALL_RPL_NODES, self.link_local(), RplMessage::Dis);
+                    self.set_timer(Timer::Dis, DIS_INTERVAL_MS);
+                }
+            }
+            Timer::TrickleTransmit(remaining) => {
+                let redundancy = self
+                    .dodag
+                    .map_or(0, |dodag| dodag.configuration.dio_redundancy);
+                if redundancy == 0 || self.trickle_counter.get() < redundancy {
+                    self.dodag.map(|dodag| {
+                        self.send(
+                            ALL_RPL_NODES,
+                            self.link_local(),
+                            RplMessage::Dio(self.dio(&dodag)),
+                        );
+                    });
+                }
+                if self.dao_pending.get() {
+                    self.send_dao();
+                }
+                self.set_timer(Timer::TrickleInterval, remaining);
+            }
+            Timer::TrickleInterval => {
+                self.dodag.map(|dodag| {
+                    let doubled = self.trickle_interval.get().saturating_mul(2);
+                    self.trickle_interval
+                        .set(doubled.min(trickle_max(&dodag.configuration)));
+                });
+                self.trickle_start_interval();
+            }
+        }
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> IP6Sender<'a> for Rpl<'a, S, A> {
+    fn set_client(&self, client: &'a dyn IP6SendClient) {
+        self.send_client.set(client);
+    }
+
+    fn set_addr(&self, src_addr: IPAddr) {
+        self.ip_sender.set_addr(src_addr);
+    }
+
+    fn set_gateway(&self, gateway: MacAddress) {
+        self.ip_sender.set_gateway(gateway);
+    }
+
+    fn set_header(&mut self, _ip6_header: IP6Header) {}
+
+    fn send_to(
+        &self,
+        dst: IPAddr,
+        transport_header: TransportHeader,
+        payload: &LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Result<(), ErrorCode> {
+        if self.rpl_in_flight.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let result = self
+            .ip_sender
+            .send_to(dst, transport_header, payload, net_cap);
+        if result.is_ok() {
+            self.upper_in_flight.set(true);
+        }
+        result
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> IP6SendClient for Rpl<'a, S, A> {
+    fn send_done(&self, result: Result<(), ErrorCode>) {
+        if self.rpl_in_flight.get() {
+            self.rpl_in_flight.set(false);
+        } else {
+            self.upper_in_flight.set(false);
+            self.send_client.map(|client| client.send_done(result));
+        }
+        if !self.rpl_in_flight.get() && !self.upper_in_flight.get() {
+            self.send_pending();
+        }
+    }
+}
+
+impl<'a, S: IP6Sender<'a>, A: time::Alarm<'a>> IP6RecvClient for Rpl<'a, S, A> {
+    fn receive(&self, header: IP6Header, payload: &[u8]) {
+        if header.get_next_header() == ip6_nh::ICMP && self.receive_rpl(&header, payload) {
+            return;
+        }
+        self.recv_client
+            .map(|client| client.receive(header, payload));
+    }
+}
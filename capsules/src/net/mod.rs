@@ -1,14 +1,18 @@
-//! Modules for IPv6 over 6LoWPAN stack
+//! Modules for the IPv6 stack, over 6LoWPAN, Ethernet or a serial line
 
 pub mod frag_utils;
+pub mod serial;
 pub mod sixlowpan;
 pub mod util;
 #[macro_use]
 pub mod stream;
+pub mod coap;
+pub mod ethernet;
 pub mod icmpv6;
 pub mod ieee802154;
 pub mod ipv6;
 pub mod network_capabilities;
+pub mod sntp;
 pub mod tcp;
 pub mod thread;
 pub mod udp;
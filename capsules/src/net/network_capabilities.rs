@@ -24,6 +24,7 @@ const MAX_ADDR_SET_SIZE: usize = 8;
 const MAX_PORT_SET_SIZE: usize = 8;
 
 use kernel::capabilities::NetworkCapabilityCreationCapability;
+use kernel::network_permissions::NetworkPermissions;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddrRange {
@@ -130,6 +131,27 @@ impl NetworkCapability {
         }
     }
 
+    /// Creates the capability described by the network permissions a process
+    /// declares in its TBF header.
+    pub fn from_permissions(
+        permissions: &NetworkPermissions,
+        create_net_cap: &dyn NetworkCapabilityCreationCapability,
+    ) -> NetworkCapability {
+        let (local_min, local_max) = permissions.local_ports();
+        let (remote_min, remote_max) = permissions.remote_ports();
+        let remote_addrs = match permissions.remote_prefix() {
+            (_, 0) => AddrRange::Any,
+            (prefix, 128) => AddrRange::Addr(IPAddr(prefix)),
+            (prefix, prefix_len) => AddrRange::Subnet(IPAddr(prefix), prefix_len),
+        };
+        NetworkCapability::new(
+            remote_addrs,
+            PortRange::Range(remote_min, remote_max),
+            PortRange::Range(local_min, local_max),
+            create_net_cap,
+        )
+    }
+
     pub fn get_range(&self, _ip_cap: &'static IpVisibilityCapability) -> AddrRange {
         self.remote_addrs
     }
@@ -0,0 +1,250 @@
+//! Framing of packets over a serial line.
+//!
+//! Two framings are supported:
+//!
+//! - SLIP (RFC 1055), which Linux supports natively through `slattach`.
+//! - HDLC-like framing (RFC 1662): frames are delimited by flag bytes,
+//!   octet-stuffed, and carry a 16-bit FCS so that corrupted frames are
+//!   dropped rather than passed up. There is no PPP address, control or
+//!   protocol field; every frame is an IPv6 packet.
+//!
+//! Frames are encoded with `Framing::encode`, and decoded one byte at a time
+//! with a `FrameDecoder`, so that a link layer can receive from a UART
+//! without knowing frame lengths in advance.
+
+/// SLIP special characters.
+pub mod slip {
+    pub const END: u8 = 0xc0;
+    pub const ESC: u8 = 0xdb;
+    pub const ESC_END: u8 = 0xdc;
+    pub const ESC_ESC: u8 = 0xdd;
+}
+
+/// HDLC-like framing special characters and FCS constants.
+pub mod hdlc {
+    pub const FLAG: u8 = 0x7e;
+    pub const ESCAPE: u8 = 0x7d;
+    pub const ESCAPE_XOR: u8 = 0x20;
+    pub const XON: u8 = 0x11;
+    pub const XOFF: u8 = 0x13;
+
+    pub const FCS_INIT: u16 = 0xffff;
+    /// The FCS of a frame including its own (complemented) FCS.
+    pub const FCS_GOOD: u16 = 0xf0b8;
+    pub const FCS_LEN: usize = 2;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Framing {
+    Slip,
+    Hdlc,
+}
+
+/// Updates a 16-bit FCS (RFC 1662, appendix C) with one byte.
+pub fn fcs16(fcs: u16, byte: u8) -> u16 {
+    let mut fcs = fcs ^ byte as u16;
+    for _ in 0..8 {
+        fcs = if fcs & 1 != 0 {
+            (fcs >> 1) ^ 0x8408
+        } else {
+            fcs >> 1
+        };
+    }
+    fcs
+}
+
+impl Framing {
+    /// The longest frame `packet_len` bytes of packet can be encoded into,
+    /// when every byte has to be escaped.
+    pub fn max_frame_len(&self, packet_len: usize) -> usize {
+        match self {
+            Framing::Slip => 2 * packet_len + 2,
+            Framing::Hdlc => 2 * (packet_len + hdlc::FCS_LEN) + 2,
+        }
+    }
+
+    fn escape(&self, byte: u8) -> Option<[u8; 2]> {
+        match (self, byte) {
+            (Framing::Slip, slip::END) => Some([slip::ESC, slip::ESC_END]),
+            (Framing::Slip, slip::ESC) => Some([slip::ESC, slip::ESC_ESC]),
+            (Framing::Hdlc, hdlc::FLAG)
+            | (Framing::Hdlc, hdlc::ESCAPE)
+            | (Framing::Hdlc, hdlc::XON)
+            | (Framing::Hdlc, hdlc::XOFF) => Some([hdlc::ESCAPE, byte ^ hdlc::ESCAPE_XOR]),
+            _ => None,
+        }
+    }
+
+    fn delimiter(&self) -> u8 {
+        match self {
+            Framing::Slip => slip::END,
+            Framing::Hdlc => hdlc::FLAG,
+        }
+    }
+
+    /// Encodes `packet` as a frame in `buf`. Returns the length of the frame,
+    /// or `None` if it does not fit.
+    pub fn encode(&self, packet: &[u8], buf: &mut [u8]) -> Option<usize> {
+        // A leading delimiter flushes any line noise received by the peer
+        *buf.get_mut(0)? = self.delimiter();
+        let mut off = 1;
+        let mut fcs = hdlc::FCS_INIT;
+        for byte in packet.iter() {
+            fcs = fcs16(fcs, *byte);
+            self.put_escaped(*byte, buf, &mut off)?;
+        }
+        if *self == Framing::Hdlc {
+            for byte in (!fcs).to_le_bytes().iter() {
+                self.put_escaped(*byte, buf, &mut off)?;
+            }
+        }
+        *buf.get_mut(off)? = self.delimiter();
+        Some(off + 1)
+    }
+
+    fn put_escaped(&self, byte: u8, buf: &mut [u8], off: &mut usize) -> Option<()> {
+        match self.escape(byte) {
+            Some(escaped) => {
+                buf.get_mut(*off..*off + 2)?.copy_from_slice(&escaped);
+                *off += 2;
+            }
+            None => {
+                *buf.get_mut(*off)? = byte;
+                *off += 1;
+            }
+        }
+        Some(())
+    }
+}
+
+/// Reassembles frames from a stream of received bytes.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameDecoder {
+    framing: Framing,
+    len: usize,
+    escaped: bool,
+    overflow: bool,
+    fcs: u16,
+}
+
+impl FrameDecoder {
+    pub fn new(framing: Framing) -> FrameDecoder {
+        FrameDecoder {
+            framing: framing,
+            len: 0,
+            escaped: false,
+            overflow: false,
+            fcs: hdlc::FCS_INIT,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = FrameDecoder::new(self.framing);
+    }
+
+    /// Decodes one received byte into `buf`. Returns the length of the
+    /// packet at the start of `buf` when `byte` completes a valid frame.
+    /// Frames that overflow `buf`, fail the FCS check or are aborted are
+    /// dropped.
+    pub fn push(&mut self, byte: u8, buf: &mut [u8]) -> Option<usize> {
+        let (delimiter, escape) = match self.framing {
+            Framing::Slip => (slip::END, slip::ESC),
+            Framing::Hdlc => (hdlc::FLAG, hdlc::ESCAPE),
+        };
+        if byte == delimiter {
+            let frame = *self;
+            self.reset();
+            // An escape directly before the delimiter aborts the frame
+            if frame.escaped || frame.overflow {
+                return None;
+            }
+            return match frame.framing {
+                Framing::Slip if frame.len > 0 => Some(frame.len),
+                Framing::Hdlc if frame.len > hdlc::FCS_LEN && frame.fcs == hdlc::FCS_GOOD => {
+                    Some(frame.len - hdlc::FCS_LEN)
+                }
+                _ => None,
+            };
+        }
+        if byte == escape && !self.escaped {
+            self.escaped = true;
+            return None;
+        }
+
+        let byte = if self.escaped {
+            self.escaped = false;
+            match (self.framing, byte) {
+                (Framing::Slip, slip::ESC_END) => slip::END,
+                (Framing::Slip, slip::ESC_ESC) => slip::ESC,
+                // RFC 1055 leaves other escaped bytes as they are
+                (Framing::Slip, _) => byte,
+                (Framing::Hdlc, _) => byte ^ hdlc::ESCAPE_XOR,
+            }
+        } else {
+            byte
+        };
+        self.fcs = fcs16(self.fcs, byte);
+        match buf.get_mut(self.len) {
+            Some(slot) => {
+                *slot = byte;
+                self.len += 1;
+            }
+            None => self.overflow = true,
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(framing: Framing, packet: &[u8]) {
+        let mut frame = [0; 64];
+        let len = framing.encode(packet, &mut frame).unwrap();
+        assert!(len <= framing.max_frame_len(packet.len()));
+        assert_eq!(frame[0], frame[len - 1]);
+        assert!(!frame[1..len - 1].contains(&frame[0]));
+
+        let mut decoder = FrameDecoder::new(framing);
+        let mut buf = [0; 32];
+        let mut decoded = None;
+        for byte in frame[..len].iter() {
+            if let Some(n) = decoder.push(*byte, &mut buf) {
+                decoded = Some(n);
+            }
+        }
+        assert_eq!(decoded, Some(packet.len()));
+        assert_eq!(&buf[..packet.len()], packet);
+    }
+
+    #[test]
+    fn slip_roundtrip() {
+        roundtrip(
+            Framing::Slip,
+            &[0x60, slip::END, 0x01, slip::ESC, slip::ESC_END],
+        );
+    }
+
+    #[test]
+    fn hdlc_roundtrip() {
+        roundtrip(
+            Framing::Hdlc,
+            &[0x60, hdlc::FLAG, hdlc::ESCAPE, hdlc::XON, 0x20],
+        );
+    }
+
+    #[test]
+    fn hdlc_drops_corrupted_frames() {
+        let mut frame = [0; 32];
+        let len = Framing::Hdlc
+            .encode(&[0x60, 0x00, 0x01], &mut frame)
+            .unwrap();
+        frame[2] ^= 0x04;
+        let mut decoder = FrameDecoder::new(Framing::Hdlc);
+        let mut buf = [0; 32];
+        assert!(frame[..len]
+            .iter()
+            .all(|byte| decoder.push(*byte, &mut buf).is_none()));
+    }
+}
@@ -685,7 +685,8 @@ impl<'a> RxState<'a> {
     // Checks if a given RxState is free or expired (and thus, can be freed).
     // This function implements the reassembly timeout for 6LoWPAN lazily.
     fn is_busy(&self, frequency: u32, current_time: u32) -> bool {
-        let expired = current_time >= (self.start_time.get() + FRAG_TIMEOUT * frequency);
+        let elapsed = current_time.wrapping_sub(self.start_time.get());
+        let expired = elapsed >= FRAG_TIMEOUT * frequency;
         if expired {
             self.end_receive(None, Err(ErrorCode::FAIL));
         }
@@ -915,7 +916,7 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         let rx_state = self
             .rx_states
             .iter()
-            .find(|state| !state.is_busy(self.clock.now().into_u32(), A::Frequency::frequency()));
+            .find(|state| !state.is_busy(A::Frequency::frequency(), self.clock.now().into_u32()));
         rx_state.map_or((None, Err(ErrorCode::NOMEM)), |state| {
             state.start_receive(
                 src_mac_addr,
@@ -980,7 +981,7 @@ impl<'a, A: time::Alarm<'a>, C: ContextStore> Sixlowpan<'a, A, C> {
         // Else find a free state
         if rx_state.is_none() {
             rx_state = self.rx_states.iter().find(|state| {
-                !state.is_busy(self.clock.now().into_u32(), A::Frequency::frequency())
+                !state.is_busy(A::Frequency::frequency(), self.clock.now().into_u32())
             });
             // Initialize new state
             rx_state.map(|state| {
@@ -0,0 +1,329 @@
+//! Simple Network Time Protocol (SNTPv4, RFC 4330) client.
+//!
+//! `Sntp` asks an NTP server for the current time over the kernel UDP stack
+//! and passes the UTC time, in milliseconds since the Unix epoch, to its
+//! `TimeSourceClient`. It is normally the time source of a
+//! `wall_clock::WallClock`. The request is retransmitted if no valid reply
+//! arrives within `TIMEOUT_MS`, up to `MAX_ATTEMPTS` times. The time is
+//! corrected for half of the round-trip delay.
+//!
+//! Replies are only accepted from the configured server, and only if they
+//! echo the transmit timestamp of the request, which the client fills with a
+//! random cookie rather than its own (unknown) time.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let sntp = static_init!(
+//!     capsules::net::sntp::Sntp<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::net::sntp::Sntp::new(udp_send, sntp_alarm, server, tx_buffer, net_cap)
+//! );
+//! udp_send.set_client(sntp);
+//! udp_recv.set_client(sntp);
+//! sntp_alarm.set_alarm_client(sntp);
+//! ```
+
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+use crate::wall_clock::{TimeSource, TimeSourceClient};
+
+use core::cell::Cell;
+
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+/// The UDP port NTP servers listen on. Clients send from it too.
+pub const NTP_PORT: u16 = 123;
+/// The length of an NTP message without extension fields or authenticator.
+pub const NTP_PACKET_LEN: usize = 48;
+
+const TIMEOUT_MS: u32 = 2000;
+const MAX_ATTEMPTS: u8 = 3;
+
+/// Seconds from the start of NTP era 0 (1900) to the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator value of a server whose clock is not synchronized.
+const LEAP_ALARM: u8 = 3;
+
+// Offsets of the fields of an NTP message.
+const STRATUM: usize = 1;
+const ORIGINATE_TIMESTAMP: usize = 24;
+const RECEIVE_TIMESTAMP: usize = 32;
+const TRANSMIT_TIMESTAMP: usize = 40;
+
+/// Converts an NTP timestamp to milliseconds since the Unix epoch.
+///
+/// NTP seconds wrap in 2036. As in RFC 4330, section 3, timestamps with the
+/// most significant bit clear are taken to be in the era that starts then, so
+/// that times from 1968 to 2104 can be represented.
+pub fn ntp_to_unix_ms(timestamp: u64) -> u64 {
+    let seconds = timestamp >> 32;
+    let fraction = timestamp & 0xffff_ffff;
+    let seconds = if seconds & 0x8000_0000 != 0 {
+        seconds
+    } else {
+        seconds + (1 << 32)
+    };
+    (seconds - NTP_UNIX_OFFSET) * 1000 + ((fraction * 1000) >> 32)
+}
+
+fn read_timestamp(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Checks that `reply` is a valid server reply to a request with transmit
+/// timestamp `cookie`. Returns the server's receive and transmit timestamps.
+pub fn parse_reply(reply: &[u8], cookie: u64) -> Result<(u64, u64), ErrorCode> {
+    if reply.len() < NTP_PACKET_LEN {
+        return Err(ErrorCode::SIZE);
+    }
+    let leap = reply[0] >> 6;
+    let version = (reply[0] >> 3) & 0x7;
+    let mode = reply[0] & 0x7;
+    if mode != MODE_SERVER || !(3..=VERSION).contains(&version) {
+        return Err(ErrorCode::INVAL);
+    }
+    if read_timestamp(reply, ORIGINATE_TIMESTAMP) != cookie {
+        return Err(ErrorCode::INVAL);
+    }
+    // Stratum 0 is a "kiss-o'-death" message, telling the client to back
+    // off or go away.
+    if leap == LEAP_ALARM || reply[STRATUM] == 0 || reply[STRATUM] > 15 {
+        return Err(ErrorCode::FAIL);
+    }
+    let transmit = read_timestamp(reply, TRANSMIT_TIMESTAMP);
+    if transmit == 0 {
+        return Err(ErrorCode::FAIL);
+    }
+    Ok((read_timestamp(reply, RECEIVE_TIMESTAMP), transmit))
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting for `send_done` or the reply.
+    Pending {
+        /// The random transmit timestamp of the request.
+        cookie: u64,
+        /// When the request was sent, in alarm ticks.
+        sent_at: u32,
+        attempts: u8,
+    },
+}
+
+pub struct Sntp<'a, A: time::Alarm<'a>> {
+    sender: &'a dyn UDPSender<'a>,
+    alarm: &'a A,
+    server: Cell<IPAddr>,
+    tx_buffer: MapCell<LeasableMutableBuffer<'static, u8>>,
+    state: Cell<State>,
+    random: Cell<u32>,
+    client: OptionalCell<&'a dyn TimeSourceClient>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, A: time::Alarm<'a>> Sntp<'a, A> {
+    pub fn new(
+        sender: &'a dyn UDPSender<'a>,
+        alarm: &'a A,
+        server: IPAddr,
+        tx_buffer: LeasableMutableBuffer<'static, u8>,
+        net_cap: &'static NetworkCapability,
+    ) -> Sntp<'a, A> {
+        Sntp {
+            sender: sender,
+            alarm: alarm,
+            server: Cell::new(server),
+            tx_buffer: MapCell::new(tx_buffer),
+            state: Cell::new(State::Idle),
+            random: Cell::new(0x6d2b_79f5),
+            client: OptionalCell::empty(),
+            net_cap: net_cap,
+        }
+    }
+
+    /// Sets the address of the NTP server used by later requests.
+    pub fn set_server(&self, server: IPAddr) {
+        self.server.set(server);
+    }
+
+    /// Returns a pseudo-random number for request cookies. The cookie only
+    /// needs to be hard to guess for off-path attackers, so the alarm counter
+    /// is mixed into a xorshift generator.
+    fn next_random(&self) -> u32 {
+        let mut x = self.random.get() ^ self.alarm.now().into_u32();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random.set(x);
+        x
+    }
+
+    fn send_request(&self, attempts: u8) -> Result<(), ErrorCode> {
+        let mut buf = self.tx_buffer.take().ok_or(ErrorCode::BUSY)?;
+        if buf.len() < NTP_PACKET_LEN {
+            self.tx_buffer.replace(buf);
+            return Err(ErrorCode::SIZE);
+        }
+        let cookie = (self.next_random() as u64) << 32 | self.next_random() as u64;
+        buf[..NTP_PACKET_LEN].iter_mut().for_each(|b| *b = 0);
+        buf[0] = VERSION << 3 | MODE_CLIENT;
+        buf[TRANSMIT_TIMESTAMP..TRANSMIT_TIMESTAMP + 8].copy_from_slice(&cookie.to_be_bytes());
+        buf.slice(0..NTP_PACKET_LEN);
+
+        let now = self.alarm.now();
+        match self
+            .sender
+            .send_to(self.server.get(), NTP_PORT, buf, self.net_cap)
+        {
+            Ok(()) => {
+                self.state.set(State::Pending {
+                    cookie: cookie,
+                    sent_at: now.into_u32(),
+                    attempts: attempts,
+                });
+                self.alarm
+                    .set_alarm(now, self.alarm.ticks_from_ms(TIMEOUT_MS));
+                Ok(())
+            }
+            Err(mut buf) => {
+                buf.reset();
+                self.tx_buffer.replace(buf);
+                Err(ErrorCode::FAIL)
+            }
+        }
+    }
+
+    fn finish(&self, result: Result<u64, ErrorCode>) {
+        self.state.set(State::Idle);
+        let _ = self.alarm.disarm();
+        self.client.map(|client| client.time_received(result));
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TimeSource<'a> for Sntp<'a, A> {
+    fn set_client(&self, client: &'a dyn TimeSourceClient) {
+        self.client.set(client);
+    }
+
+    fn request_time(&self) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        self.send_request(1)
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for Sntp<'a, A> {
+    fn alarm(&self) {
+        if let State::Pending { attempts, .. } = self.state.get() {
+            if attempts >= MAX_ATTEMPTS {
+                self.finish(Err(ErrorCode::NOACK));
+            } else if let Err(e) = self.send_request(attempts + 1) {
+                self.finish(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPSendClient for Sntp<'a, A> {
+    fn send_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        mut dgram: LeasableMutableBuffer<'static, u8>,
+    ) {
+        dgram.reset();
+        self.tx_buffer.replace(dgram);
+        // A lost request is retransmitted when the timeout expires, but a
+        // request that could not be sent at all will not get a reply.
+        if let Err(e) = result {
+            if self.state.get() != State::Idle {
+                self.finish(Err(e));
+            }
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> UDPRecvClient for Sntp<'a, A> {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        _dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        let (cookie, sent_at) = match self.state.get() {
+            State::Pending {
+                cookie, sent_at, ..
+            } => (cookie, sent_at),
+            State::Idle => return,
+        };
+        if src_addr != self.server.get() || src_port != NTP_PORT {
+            return;
+        }
+        let (receive, transmit) = match parse_reply(payload, cookie) {
+            Ok(timestamps) => timestamps,
+            // Keep waiting: the real reply may still arrive
+            Err(ErrorCode::INVAL) | Err(ErrorCode::SIZE) => return,
+            Err(e) => return self.finish(Err(e)),
+        };
+
+        // The reply took half of the round trip, less the time the server
+        // held the request, to get here.
+        let round_trip_ms = self
+            .alarm
+            .ticks_to_ms(self.alarm.now().wrapping_sub(A::Ticks::from(sent_at)))
+            as u64;
+        let server_ms = ntp_to_unix_ms(transmit).saturating_sub(ntp_to_unix_ms(receive));
+        let utc_ms = ntp_to_unix_ms(transmit) + round_trip_ms.saturating_sub(server_ms) / 2;
+        self.finish(Ok(utc_ms));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ntp_eras() {
+        // 2022-01-01T00:00:00.5Z
+        let timestamp = (3_849_984_000u64 << 32) | 0x8000_0000;
+        assert_eq!(ntp_to_unix_ms(timestamp), 1_640_995_200_500);
+        // 2036-02-07T06:28:16Z is the start of era 1
+        assert_eq!(ntp_to_unix_ms(0), 2_085_978_496_000);
+    }
+
+    #[test]
+    fn reply_validation() {
+        let cookie: u64 = 0x0123_4567_89ab_cdef;
+        let mut reply = [0u8; NTP_PACKET_LEN];
+        reply[0] = VERSION << 3 | MODE_SERVER;
+        reply[STRATUM] = 2;
+        reply[ORIGINATE_TIMESTAMP..ORIGINATE_TIMESTAMP + 8].copy_from_slice(&cookie.to_be_bytes());
+        reply[RECEIVE_TIMESTAMP..RECEIVE_TIMESTAMP + 8]
+            .copy_from_slice(&[0xe5, 0, 0, 0, 0, 0, 0, 1]);
+        reply[TRANSMIT_TIMESTAMP..TRANSMIT_TIMESTAMP + 8]
+            .copy_from_slice(&[0xe5, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(
+            parse_reply(&reply, cookie),
+            Ok((0xe500_0000_0000_0001, 0xe500_0000_0000_0002))
+        );
+        assert_eq!(parse_reply(&reply, cookie + 1), Err(ErrorCode::INVAL));
+        assert_eq!(parse_reply(&reply[..40], cookie), Err(ErrorCode::SIZE));
+
+        reply[STRATUM] = 0;
+        assert_eq!(parse_reply(&reply, cookie), Err(ErrorCode::FAIL));
+    }
+}
@@ -0,0 +1,931 @@
+//! Mesh Link Establishment (MLE) for Thread end devices, as outlined in
+//! Chapter 4 of the Thread 1.1.1 Specification.
+//!
+//! MLE messages consist of a command type and a series of TLV parameters
+//! (see the `tlv` module). They are carried in UDP datagrams to and from
+//! port 19788, between link-local addresses.
+//!
+//! MLE for network attaching comprises a four-step handshake that works
+//! as follows:
+//!
+//! 1. A child device multicasts a Parent Request MLE command.
+//! 2. Each potential parent device on the network unicasts a Parent
+//!    Response MLE command.
+//! 3. The child device selects a parent based on a hierarchy of
+//!    connectivity metrics and unicasts a Child ID Request MLE
+//!    command.
+//! 4. The selected parent unicasts a Child ID Response MLE command.
+//!
+//! `Mle` implements this handshake for a Minimal End Device (MED) or a
+//! Sleepy End Device (SED) attaching to an existing Thread network:
+//!
+//! - `start` derives the MAC and MLE keys from the network master key and
+//!   the key sequence with HMAC-SHA256 (Section 7.1.4).
+//! - The Parent Request is first sent to routers only and, if no router
+//!   answers within 750 ms, to routers and REEDs for another 1250 ms.
+//!   The Parent Response with the best link quality, then parent priority,
+//!   then number of link quality 3 neighbors wins.
+//! - The Child ID Request is sent up to three times. Once a Child ID
+//!   Response arrives, the short address assigned by the parent (the
+//!   RLOC16) is configured on the MAC device and the client is notified.
+//! - While attached, a Child Update Request is sent every half timeout to
+//!   keep the link alive. If the parent does not answer three of them, the
+//!   device detaches and starts attaching again after a backoff, as it does
+//!   when an attach attempt fails.
+//!
+//! MLE messages are secured with AES-CCM using the MLE key, with the
+//! auxiliary security header of IEEE 802.15.4 and the IPv6 source and
+//! destination addresses authenticated along with it (Section 4.4). `Mle`
+//! also implements the `KeyProcedure` and `DeviceProcedure` traits of the
+//! 802.15.4 framer, providing the MAC key and the address of the parent,
+//! so that data frames can be secured at the MAC layer.
+//!
+//! Known limitations: the link-local address used for MLE must be the one
+//! derived from the extended address of the MAC device. Key rotation is not
+//! supported: messages with another key sequence are dropped. A SED does
+//! not poll its parent for data; the device mode only changes what the
+//! parent is told. The link-layer frame counter sent to the parent is
+//! always zero, as the MAC frame counter is not visible to this layer.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let mle = static_init!(
+//!     Mle<'static, UDPSendStruct<...>, VirtualMuxAlarm<'static, A>, C, D>,
+//!     Mle::new(udp_send, mle_alarm, ccm, hmac, mac_device, DeviceMode::Minimal,
+//!              240, &mut MLE_TX_BUF, &mut MLE_RX_BUF, &mut MLE_DIGEST_BUF, net_cap)
+//! );
+//! udp_send.set_binding(tx_binding); // bound to MLE_PORT
+//! udp_send.set_client(mle);
+//! udp_recv.set_binding(rx_binding);
+//! udp_recv.set_client(mle);
+//! mle_alarm.set_alarm_client(mle);
+//! ccm.set_client(mle);
+//! hmac.set_client(mle);
+//! mle.start(&MASTER_KEY, 0);
+//! ```
+
+use crate::ieee802154::device::MacDevice;
+use crate::ieee802154::framer::{DeviceProcedure, KeyProcedure};
+use crate::net::ieee802154::{KeyId, KeyIdMode, MacAddress, SecurityLevel};
+use crate::net::ipv6::ip_utils::IPAddr;
+use crate::net::network_capabilities::NetworkCapability;
+use crate::net::stream::SResult;
+use crate::net::thread::tlv::{LinkMode, MulticastResponder, Tlv, TlvType};
+use crate::net::udp::udp_recv::UDPRecvClient;
+use crate::net::udp::udp_send::{UDPSendClient, UDPSender};
+
+use core::cell::Cell;
+
+use kernel::hil::digest;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM, CCM_NONCE_LENGTH};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use kernel::ErrorCode;
+
+/// The UDP port MLE messages are sent from and to.
+pub const MLE_PORT: u16 = 19788;
+
+/// Length of the buffers MLE messages are built and decrypted in. This
+/// leaves room for 100 bytes of TLVs after the addresses and headers.
+pub const BUF_LEN: usize = 150;
+
+/// MLE command types (Section 4.3).
+pub mod command {
+    pub const LINK_REQUEST: u8 = 0;
+    pub const LINK_ACCEPT: u8 = 1;
+    pub const LINK_ACCEPT_AND_REQUEST: u8 = 2;
+    pub const LINK_REJECT: u8 = 3;
+    pub const ADVERTISEMENT: u8 = 4;
+    pub const UPDATE: u8 = 5;
+    pub const UPDATE_REQUEST: u8 = 6;
+    pub const DATA_REQUEST: u8 = 7;
+    pub const DATA_RESPONSE: u8 = 8;
+    pub const PARENT_REQUEST: u8 = 9;
+    pub const PARENT_RESPONSE: u8 = 10;
+    pub const CHILD_ID_REQUEST: u8 = 11;
+    pub const CHILD_ID_RESPONSE: u8 = 12;
+    pub const CHILD_UPDATE_REQUEST: u8 = 13;
+    pub const CHILD_UPDATE_RESPONSE: u8 = 14;
+}
+
+/// Security suite byte of secured MLE messages. Messages with any other
+/// suite are dropped.
+const SECURITY_SUITE_154: u8 = 0;
+
+const SECURITY_LEVEL: SecurityLevel = SecurityLevel::EncMic32;
+const SECURITY_CONTROL: u8 = SECURITY_LEVEL as u8 | KeyIdMode::Source4Index as u8;
+const MIC_LEN: usize = 4;
+
+// Layout of the buffers: the IPv6 source and destination addresses, then
+// the auxiliary security header, then the command and TLVs, then the MIC.
+// The addresses and the auxiliary header form the additional authenticated
+// data. Once encrypted, the security suite byte is written just before the
+// auxiliary header and the message is sent from there.
+const AUX_OFF: usize = 32;
+const AUX_LEN: usize = 10;
+const MESSAGE_OFF: usize = AUX_OFF + AUX_LEN;
+const SUITE_OFF: usize = AUX_OFF - 1;
+
+/// The Thread version advertised in the Version TLV.
+const THREAD_VERSION: u16 = 2;
+
+const PARENT_REQUEST_ROUTERS_MS: u32 = 750;
+const PARENT_REQUEST_REEDS_MS: u32 = 1250;
+const RESPONSE_TIMEOUT_MS: u32 = 1250;
+const ATTACH_BACKOFF_MS: u32 = 5000;
+const MAX_ATTEMPTS: u8 = 3;
+
+/// Short address of a device that is not attached.
+const SHORT_ADDR_NONE: u16 = 0xfffe;
+
+/// The link-local all-routers multicast address, ff02::2.
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+/// The kind of end device that attaches.
+#[derive(Copy, Clone, PartialEq)]
+pub enum DeviceMode {
+    /// Minimal End Device: the receiver stays on when idle.
+    Minimal,
+    /// Sleepy End Device: the receiver is off when idle.
+    Sleepy,
+}
+
+impl DeviceMode {
+    fn mode_tlv(&self) -> u8 {
+        match *self {
+            DeviceMode::Minimal => {
+                LinkMode::ReceiverOnWhenIdle as u8 | LinkMode::SecureDataRequests as u8
+            }
+            DeviceMode::Sleepy => LinkMode::SecureDataRequests as u8,
+        }
+    }
+}
+
+/// Notified when the device attaches to or detaches from its parent.
+pub trait MleClient {
+    /// The device attached, with `rloc16` as its short address.
+    fn attached(&self, rloc16: u16);
+
+    /// The device lost its parent. It keeps trying to attach.
+    fn detached(&self);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Disabled,
+    DerivingKeys,
+    Detached,
+    /// The round of Parent Requests: 0 for routers, 1 for routers and REEDs.
+    ParentRequest(u8),
+    /// The number of Child ID Requests sent.
+    ChildIdRequest(u8),
+    Attached,
+    /// The number of Child Update Requests sent.
+    ChildUpdate(u8),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum CryptOp {
+    Idle,
+    Encrypt,
+    Decrypt,
+}
+
+#[derive(Copy, Clone)]
+struct Parent {
+    addr: IPAddr,
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    challenge: [u8; 8],
+    link_quality: u8,
+    priority: i8,
+    link_quality_3: u8,
+    frame_counter: u32,
+}
+
+impl Parent {
+    fn preference(&self) -> (u8, i8, u8) {
+        (self.link_quality, self.priority, self.link_quality_3)
+    }
+}
+
+pub struct Mle<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    udp_sender: &'a U,
+    alarm: &'a A,
+    ccm: &'a C,
+    hmac: &'a D,
+    mac_device: &'a dyn MacDevice<'a>,
+    client: OptionalCell<&'a dyn MleClient>,
+    mode: DeviceMode,
+    timeout: u32,
+    state: Cell<State>,
+    crypt_op: Cell<CryptOp>,
+    tx_buf: TakeCell<'static, [u8]>,
+    tx_dst: Cell<IPAddr>,
+    tx_len: Cell<usize>,
+    rx_buf: TakeCell<'static, [u8]>,
+    rx_src: Cell<IPAddr>,
+    rx_frame_counter: Cell<u32>,
+    rx_len: Cell<usize>,
+    digest_buf: TakeCell<'static, [u8; 32]>,
+    key_sequence: Cell<u32>,
+    mle_key: Cell<[u8; 16]>,
+    mac_key: Cell<[u8; 16]>,
+    frame_counter: Cell<u32>,
+    challenge: Cell<[u8; 8]>,
+    random: Cell<u64>,
+    candidate: Cell<Option<Parent>>,
+    parent: Cell<Option<Parent>>,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    /// `timeout` is the child timeout requested from the parent, in
+    /// seconds. `tx_buf` and `rx_buf` must be at least `BUF_LEN` bytes
+    /// long.
+    pub fn new(
+        udp_sender: &'a U,
+        alarm: &'a A,
+        ccm: &'a C,
+        hmac: &'a D,
+        mac_device: &'a dyn MacDevice<'a>,
+        mode: DeviceMode,
+        timeout: u32,
+        tx_buf: &'static mut [u8],
+        rx_buf: &'static mut [u8],
+        digest_buf: &'static mut [u8; 32],
+        net_cap: &'static NetworkCapability,
+    ) -> Mle<'a, U, A, C, D> {
+        Mle {
+            udp_sender: udp_sender,
+            alarm: alarm,
+            ccm: ccm,
+            hmac: hmac,
+            mac_device: mac_device,
+            client: OptionalCell::empty(),
+            mode: mode,
+            timeout: timeout,
+            state: Cell::new(State::Disabled),
+            crypt_op: Cell::new(CryptOp::Idle),
+            tx_buf: TakeCell::new(tx_buf),
+            tx_dst: Cell::new(IPAddr::new()),
+            tx_len: Cell::new(0),
+            rx_buf: TakeCell::new(rx_buf),
+            rx_src: Cell::new(IPAddr::new()),
+            rx_frame_counter: Cell::new(0),
+            rx_len: Cell::new(0),
+            digest_buf: TakeCell::new(digest_buf),
+            key_sequence: Cell::new(0),
+            mle_key: Cell::new([0; 16]),
+            mac_key: Cell::new([0; 16]),
+            frame_counter: Cell::new(0),
+            challenge: Cell::new([0; 8]),
+            random: Cell::new(1),
+            candidate: Cell::new(None),
+            parent: Cell::new(None),
+            net_cap: net_cap,
+        }
+    }
+
+    pub fn set_client(&self, client: &'a dyn MleClient) {
+        self.client.set(client);
+    }
+
+    /// Derives the keys of the network with the master key `master_key` at
+    /// key sequence `key_sequence`, then attaches to it.
+    pub fn start(&self, master_key: &[u8; 16], key_sequence: u32) -> Result<(), ErrorCode> {
+        if self.state.get() != State::Disabled {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.hmac.set_mode_hmacsha256(master_key)?;
+        let buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+
+        // The HMAC input is the key sequence followed by "Thread"
+        buf[..4].copy_from_slice(&key_sequence.to_be_bytes());
+        buf[4..10].copy_from_slice(b"Thread");
+        let mut data = LeasableMutableBuffer::new(buf);
+        data.slice(..10);
+        match self.hmac.add_mut_data(data) {
+            Ok(()) => {
+                let ext_addr = u64::from_be_bytes(self.mac_device.get_address_long());
+                let now = self.alarm.now().into_u32() as u64;
+                self.random.set((ext_addr ^ (now << 32) ^ now) | 1);
+                self.key_sequence.set(key_sequence);
+                self.state.set(State::DerivingKeys);
+                Ok(())
+            }
+            Err((e, data)) => {
+                self.tx_buf.replace(data.take());
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the short address of the device, if it is attached.
+    pub fn rloc16(&self) -> Option<u16> {
+        match self.state.get() {
+            State::Attached | State::ChildUpdate(_) => Some(self.mac_device.get_address()),
+            _ => None,
+        }
+    }
+
+    fn keys_valid(&self) -> bool {
+        match self.state.get() {
+            State::Disabled | State::DerivingKeys => false,
+            _ => true,
+        }
+    }
+
+    fn set_alarm_ms(&self, ms: u32) {
+        self.alarm
+            .set_alarm(self.alarm.now(), self.alarm.ticks_from_ms(ms));
+    }
+
+    fn keep_alive_ms(&self) -> u32 {
+        self.timeout.saturating_mul(1000) / 2
+    }
+
+    fn link_local_address(&self) -> IPAddr {
+        IPAddr::generate_from_mac(MacAddress::Long(self.mac_device.get_address_long()))
+    }
+
+    /// A new random challenge, which responses are expected to carry.
+    fn new_challenge(&self) -> [u8; 8] {
+        // xorshift64
+        let mut x = self.random.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.random.set(x);
+        let challenge = x.to_be_bytes();
+        self.challenge.set(challenge);
+        challenge
+    }
+
+    fn attach(&self) {
+        self.candidate.set(None);
+        self.state.set(State::ParentRequest(0));
+        self.send_parent_request(MulticastResponder::Router as u8);
+        self.set_alarm_ms(PARENT_REQUEST_ROUTERS_MS);
+    }
+
+    /// Gives up the current attach attempt and tries again later.
+    fn backoff(&self) {
+        self.candidate.set(None);
+        self.state.set(State::Detached);
+        self.set_alarm_ms(ATTACH_BACKOFF_MS);
+    }
+
+    fn detach(&self) {
+        self.parent.set(None);
+        self.mac_device.set_address(SHORT_ADDR_NONE);
+        self.mac_device.config_commit();
+        self.backoff();
+        self.client.map(|c| c.detached());
+    }
+
+    fn send_parent_request(&self, scan_mask: u8) {
+        let challenge = self.new_challenge();
+        let _ = self.send_message(
+            ALL_ROUTERS,
+            command::PARENT_REQUEST,
+            &[
+                Tlv::Mode(self.mode.mode_tlv()),
+                Tlv::Challenge(challenge),
+                Tlv::ScanMask(scan_mask),
+                Tlv::Version(THREAD_VERSION),
+            ],
+        );
+    }
+
+    fn send_child_id_request(&self) {
+        let requested = [TlvType::Address16 as u8, TlvType::NetworkData as u8];
+        self.candidate.get().map(|parent| {
+            self.send_message(
+                parent.addr,
+                command::CHILD_ID_REQUEST,
+                &[
+                    Tlv::Response(parent.challenge),
+                    Tlv::LinkLayerFrameCounter(0),
+                    Tlv::MleFrameCounter(self.frame_counter.get()),
+                    Tlv::Mode(self.mode.mode_tlv()),
+                    Tlv::Timeout(self.timeout),
+                    Tlv::Version(THREAD_VERSION),
+                    Tlv::TlvRequest(&requested),
+                ],
+            )
+        });
+    }
+
+    fn send_child_update_request(&self) {
+        let challenge = self.new_challenge();
+        let rloc16 = self.mac_device.get_address();
+        self.parent.get().map(|parent| {
+            self.send_message(
+                parent.addr,
+                command::CHILD_UPDATE_REQUEST,
+                &[
+                    Tlv::SourceAddress(rloc16),
+                    Tlv::Mode(self.mode.mode_tlv()),
+                    Tlv::Challenge(challenge),
+                    Tlv::Timeout(self.timeout),
+                ],
+            )
+        });
+    }
+
+    /// Builds an MLE message and starts encrypting it; it is sent once
+    /// encrypted. Failures are not retried: the timeout of the state the
+    /// message was sent in takes care of that.
+    fn send_message(&self, dst: IPAddr, command: u8, tlvs: &[Tlv]) -> Result<(), ErrorCode> {
+        if self.crypt_op.get() != CryptOp::Idle {
+            return Err(ErrorCode::BUSY);
+        }
+        let buf = self.tx_buf.take().ok_or(ErrorCode::BUSY)?;
+        let end = buf.len() - MIC_LEN;
+        buf[MESSAGE_OFF] = command;
+        let mut off = MESSAGE_OFF + 1;
+        for tlv in tlvs {
+            match tlv.encode(&mut buf[off..end]) {
+                SResult::Done(len, ()) => off += len,
+                _ => {
+                    self.tx_buf.replace(buf);
+                    return Err(ErrorCode::SIZE);
+                }
+            }
+        }
+
+        let frame_counter = self.frame_counter.get();
+        self.frame_counter.set(frame_counter.wrapping_add(1));
+        buf[..16].copy_from_slice(&self.link_local_address().0);
+        buf[16..AUX_OFF].copy_from_slice(&dst.0);
+        buf[AUX_OFF] = SECURITY_CONTROL;
+        buf[AUX_OFF + 1..AUX_OFF + 5].copy_from_slice(&frame_counter.to_le_bytes());
+        buf[AUX_OFF + 5..AUX_OFF + 9].copy_from_slice(&self.key_sequence.get().to_be_bytes());
+        buf[AUX_OFF + 9] = key_index(self.key_sequence.get());
+
+        let m_len = off - MESSAGE_OFF;
+        self.tx_dst.set(dst);
+        self.tx_len.set(m_len);
+        let ext_addr = self.mac_device.get_address_long();
+        self.crypt(buf, ext_addr, frame_counter, m_len, CryptOp::Encrypt)
+            .map_err(|(e, buf)| {
+                self.tx_buf.replace(buf);
+                e
+            })
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        ext_addr: [u8; 8],
+        frame_counter: u32,
+        m_len: usize,
+        op: CryptOp,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        let nonce = ccm_nonce(&ext_addr, frame_counter);
+        let res = self
+            .ccm
+            .set_key(&self.mle_key.get())
+            .and_then(|()| self.ccm.set_nonce(&nonce));
+        if let Err(e) = res {
+            return Err((e, buf));
+        }
+        self.crypt_op.set(op);
+        let encrypting = op == CryptOp::Encrypt;
+        self.ccm
+            .crypt(buf, 0, MESSAGE_OFF, m_len, MIC_LEN, true, encrypting)
+            .map_err(|e| {
+                self.crypt_op.set(CryptOp::Idle);
+                e
+            })
+    }
+
+    fn receive_message(&self, message: &[u8]) {
+        let src = self.rx_src.get();
+        let frame_counter = self.rx_frame_counter.get();
+        let (command, tlvs) = match message.split_first() {
+            Some(split) => split,
+            None => return,
+        };
+        match (*command, self.state.get()) {
+            (command::PARENT_RESPONSE, State::ParentRequest(_)) => {
+                self.receive_parent_response(src, frame_counter, tlvs)
+            }
+            (command::CHILD_ID_RESPONSE, State::ChildIdRequest(_)) => {
+                self.receive_child_id_response(src, frame_counter, tlvs)
+            }
+            (command::CHILD_UPDATE_RESPONSE, State::ChildUpdate(_)) => {
+                self.receive_child_update_response(src, frame_counter, tlvs)
+            }
+            _ => {}
+        }
+    }
+
+    fn receive_parent_response(&self, src: IPAddr, frame_counter: u32, tlvs: &[u8]) {
+        let mut response = None;
+        let mut challenge = None;
+        let mut rloc16 = None;
+        let mut link_margin = None;
+        let mut connectivity = None;
+        for_each_tlv(tlvs, |tlv| match tlv {
+            Tlv::Response(r) => response = Some(r),
+            Tlv::Challenge(c) => challenge = Some(c),
+            Tlv::SourceAddress(a) => rloc16 = Some(a),
+            Tlv::LinkMargin(m) => link_margin = Some(m),
+            Tlv::Connectivity {
+                parent_priority,
+                link_quality_3,
+                ..
+            } => connectivity = Some((parent_priority, link_quality_3)),
+            _ => {}
+        });
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        if let (Some(challenge), Some(rloc16), Some(link_margin), Some((priority, lq3))) =
+            (challenge, rloc16, link_margin, connectivity)
+        {
+            let parent = Parent {
+                addr: src,
+                ext_addr: ext_addr_from_link_local(&src),
+                rloc16: rloc16,
+                challenge: challenge,
+                link_quality: link_quality(link_margin),
+                priority: parent_priority(priority),
+                link_quality_3: lq3,
+                frame_counter: frame_counter,
+            };
+            let better = self
+                .candidate
+                .get()
+                .map_or(true, |c| parent.preference() > c.preference());
+            if better {
+                self.candidate.set(Some(parent));
+            }
+        }
+    }
+
+    fn receive_child_id_response(&self, src: IPAddr, frame_counter: u32, tlvs: &[u8]) {
+        let mut parent = match self.candidate.get() {
+            Some(c) if c.addr == src && frame_counter > c.frame_counter => c,
+            _ => return,
+        };
+        let mut source = None;
+        let mut address16 = None;
+        for_each_tlv(tlvs, |tlv| match tlv {
+            Tlv::SourceAddress(a) => source = Some(a),
+            Tlv::Address16(a) => address16 = Some(a),
+            _ => {}
+        });
+        if source != Some(parent.rloc16) {
+            return;
+        }
+        // The child's RLOC16 carries the router ID of its parent
+        let rloc16 = match address16 {
+            Some(a) if a & 0xfc00 == parent.rloc16 & 0xfc00 => a,
+            _ => return,
+        };
+
+        parent.frame_counter = frame_counter;
+        self.parent.set(Some(parent));
+        self.candidate.set(None);
+        self.mac_device.set_address(rloc16);
+        self.mac_device.config_commit();
+        self.state.set(State::Attached);
+        self.set_alarm_ms(self.keep_alive_ms());
+        self.client.map(|c| c.attached(rloc16));
+    }
+
+    fn receive_child_update_response(&self, src: IPAddr, frame_counter: u32, tlvs: &[u8]) {
+        let mut parent = match self.parent.get() {
+            Some(p) if p.addr == src && frame_counter > p.frame_counter => p,
+            _ => return,
+        };
+        let mut response = None;
+        for_each_tlv(tlvs, |tlv| {
+            if let Tlv::Response(r) = tlv {
+                response = Some(r);
+            }
+        });
+        if response != Some(self.challenge.get()) {
+            return;
+        }
+        parent.frame_counter = frame_counter;
+        self.parent.set(Some(parent));
+        self.state.set(State::Attached);
+        self.set_alarm_ms(self.keep_alive_ms());
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> time::AlarmClient
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn alarm(&self) {
+        match self.state.get() {
+            State::Disabled | State::DerivingKeys => {}
+            State::Detached => self.attach(),
+            State::ParentRequest(0) if self.candidate.get().is_none() => {
+                self.state.set(State::ParentRequest(1));
+                self.send_parent_request(
+                    MulticastResponder::Router as u8 | MulticastResponder::EndDevice as u8,
+                );
+                self.set_alarm_ms(PARENT_REQUEST_REEDS_MS);
+            }
+            State::ParentRequest(_) => {
+                if self.candidate.get().is_some() {
+                    self.state.set(State::ChildIdRequest(1));
+                    self.send_child_id_request();
+                    self.set_alarm_ms(RESPONSE_TIMEOUT_MS);
+                } else {
+                    self.backoff();
+                }
+            }
+            State::ChildIdRequest(n) if n < MAX_ATTEMPTS => {
+                self.state.set(State::ChildIdRequest(n + 1));
+                self.send_child_id_request();
+                self.set_alarm_ms(RESPONSE_TIMEOUT_MS);
+            }
+            State::ChildIdRequest(_) => self.backoff(),
+            State::Attached => {
+                self.state.set(State::ChildUpdate(1));
+                self.send_child_update_request();
+                self.set_alarm_ms(RESPONSE_TIMEOUT_MS);
+            }
+            State::ChildUpdate(n) if n < MAX_ATTEMPTS => {
+                self.state.set(State::ChildUpdate(n + 1));
+                self.send_child_update_request();
+                self.set_alarm_ms(RESPONSE_TIMEOUT_MS);
+            }
+            State::ChildUpdate(_) => self.detach(),
+        }
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> CCMClient
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn crypt_done(&self, buf: &'static mut [u8], res: Result<(), ErrorCode>, tag_is_valid: bool) {
+        match self.crypt_op.replace(CryptOp::Idle) {
+            CryptOp::Encrypt => {
+                if res.is_err() {
+                    self.tx_buf.replace(buf);
+                    return;
+                }
+                buf[SUITE_OFF] = SECURITY_SUITE_154;
+                let end = MESSAGE_OFF + self.tx_len.get() + MIC_LEN;
+                let mut dgram = LeasableMutableBuffer::new(buf);
+                dgram.slice(SUITE_OFF..end);
+                let res = self
+                    .udp_sender
+                    .send_to(self.tx_dst.get(), MLE_PORT, dgram, self.net_cap);
+                if let Err(dgram) = res {
+                    self.tx_buf.replace(dgram.take());
+                }
+            }
+            CryptOp::Decrypt => {
+                if res.is_ok() && tag_is_valid {
+                    let end = MESSAGE_OFF + self.rx_len.get();
+                    self.receive_message(&buf[MESSAGE_OFF..end]);
+                }
+                self.rx_buf.replace(buf);
+            }
+            CryptOp::Idle => {}
+        }
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> UDPSendClient
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn send_done(&self, _result: Result<(), ErrorCode>, dgram: LeasableMutableBuffer<'static, u8>) {
+        self.tx_buf.replace(dgram.take());
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> UDPRecvClient
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) {
+        if src_port != MLE_PORT || dst_port != MLE_PORT || !src_addr.is_unicast_link_local() {
+            return;
+        }
+        if !self.keys_valid() || self.crypt_op.get() != CryptOp::Idle {
+            return;
+        }
+        if payload.len() < 1 + AUX_LEN + 1 + MIC_LEN || payload[0] != SECURITY_SUITE_154 {
+            return;
+        }
+        let aux = &payload[1..1 + AUX_LEN];
+        let key_sequence = u32::from_be_bytes([aux[5], aux[6], aux[7], aux[8]]);
+        if aux[0] != SECURITY_CONTROL || key_sequence != self.key_sequence.get() {
+            return;
+        }
+        let frame_counter = u32::from_le_bytes([aux[1], aux[2], aux[3], aux[4]]);
+
+        if let Some(buf) = self.rx_buf.take() {
+            let len = payload.len() - 1;
+            if AUX_OFF + len > buf.len() {
+                self.rx_buf.replace(buf);
+                return;
+            }
+            buf[..16].copy_from_slice(&src_addr.0);
+            buf[16..AUX_OFF].copy_from_slice(&dst_addr.0);
+            buf[AUX_OFF..AUX_OFF + len].copy_from_slice(&payload[1..]);
+
+            let m_len = len - AUX_LEN - MIC_LEN;
+            self.rx_src.set(src_addr);
+            self.rx_frame_counter.set(frame_counter);
+            self.rx_len.set(m_len);
+            let ext_addr = ext_addr_from_link_local(&src_addr);
+            if let Err((_, buf)) = self.crypt(buf, ext_addr, frame_counter, m_len, CryptOp::Decrypt)
+            {
+                self.rx_buf.replace(buf);
+            }
+        }
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> digest::ClientData<32>
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn add_data_done(&self, _result: Result<(), ErrorCode>, _data: LeasableBuffer<'static, u8>) {}
+
+    fn add_mut_data_done(
+        &self,
+        result: Result<(), ErrorCode>,
+        data: LeasableMutableBuffer<'static, u8>,
+    ) {
+        self.tx_buf.replace(data.take());
+        if self.state.get() != State::DerivingKeys {
+            return;
+        }
+        let res = result.and_then(|()| {
+            self.digest_buf
+                .take()
+                .map_or(Err(ErrorCode::BUSY), |digest| {
+                    self.hmac.run(digest).map_err(|(e, digest)| {
+                        self.digest_buf.replace(digest);
+                        e
+                    })
+                })
+        });
+        if res.is_err() {
+            self.state.set(State::Disabled);
+        }
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> digest::ClientHash<32>
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn hash_done(&self, result: Result<(), ErrorCode>, digest: &'static mut [u8; 32]) {
+        if self.state.get() == State::DerivingKeys {
+            if result.is_ok() {
+                // The MLE key is the first half of the HMAC, the MAC key the
+                // second half
+                let mut key = [0; 16];
+                key.copy_from_slice(&digest[..16]);
+                self.mle_key.set(key);
+                key.copy_from_slice(&digest[16..]);
+                self.mac_key.set(key);
+                self.state.set(State::Detached);
+                self.attach();
+            } else {
+                self.state.set(State::Disabled);
+            }
+        }
+        self.digest_buf.replace(digest);
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> digest::ClientVerify<32>
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    fn verification_done(&self, _result: Result<bool, ErrorCode>, compare: &'static mut [u8; 32]) {
+        self.digest_buf.replace(compare);
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> KeyProcedure
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    /// Thread data frames are secured with the MAC key, identified by its
+    /// key index.
+    fn lookup_key(&self, _level: SecurityLevel, key_id: KeyId) -> Option<[u8; 16]> {
+        match key_id {
+            KeyId::Index(index)
+                if self.keys_valid() && index == key_index(self.key_sequence.get()) =>
+            {
+                Some(self.mac_key.get())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a, U: UDPSender<'a>, A: time::Alarm<'a>, C: AES128CCM<'a>, D> DeviceProcedure
+    for Mle<'a, U, A, C, D>
+where
+    D: digest::Digest<'a, 32> + digest::HmacSha256,
+{
+    /// An end device only exchanges secured frames with its parent.
+    fn lookup_addr_long(&self, addr: MacAddress) -> Option<[u8; 8]> {
+        self.parent
+            .get()
+            .or(self.candidate.get())
+            .and_then(|parent| match addr {
+                MacAddress::Long(long) if long == parent.ext_addr => Some(long),
+                MacAddress::Short(short) if short == parent.rloc16 => Some(parent.ext_addr),
+                _ => None,
+            })
+    }
+}
+
+/// Calls `f` on each TLV in `buf`, skipping the ones that cannot be
+/// decoded.
+fn for_each_tlv<'b>(mut buf: &'b [u8], mut f: impl FnMut(Tlv<'b>)) {
+    while buf.len() >= 2 {
+        let len = 2 + buf[1] as usize;
+        if len > buf.len() {
+            return;
+        }
+        if let SResult::Done(_, tlv) = Tlv::decode(&buf[..len]) {
+            f(tlv);
+        }
+        buf = &buf[len..];
+    }
+}
+
+/// The key index of a key sequence, as used in the auxiliary security
+/// header.
+fn key_index(key_sequence: u32) -> u8 {
+    (key_sequence & 0x7f) as u8 + 1
+}
+
+fn ccm_nonce(ext_addr: &[u8; 8], frame_counter: u32) -> [u8; CCM_NONCE_LENGTH] {
+    let mut nonce = [0; CCM_NONCE_LENGTH];
+    nonce[..8].copy_from_slice(ext_addr);
+    nonce[8..12].copy_from_slice(&frame_counter.to_be_bytes());
+    nonce[12] = SECURITY_LEVEL as u8;
+    nonce
+}
+
+/// The extended address a link-local address was derived from.
+fn ext_addr_from_link_local(addr: &IPAddr) -> [u8; 8] {
+    let mut ext_addr = [0; 8];
+    ext_addr.copy_from_slice(&addr.0[8..]);
+    ext_addr[0] ^= 0b00000010;
+    ext_addr
+}
+
+/// Link quality from the link margin in dB (Section 4.7.7.2).
+fn link_quality(link_margin: u8) -> u8 {
+    match link_margin {
+        m if m > 20 => 3,
+        m if m > 10 => 2,
+        m if m > 2 => 1,
+        _ => 0,
+    }
+}
+
+/// Parent priority from the Connectivity TLV, from -1 (low) to 1 (high).
+fn parent_priority(priority: u8) -> i8 {
+    match priority >> 6 {
+        0b01 => 1,
+        0b11 => -1,
+        _ => 0,
+    }
+}
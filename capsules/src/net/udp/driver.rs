@@ -2,12 +2,26 @@
 //!
 //! Implements a userspace interface for sending and receiving UDP messages.
 //! Processes use this driver to send UDP packets from a common interface
-//! and bind to UDP ports for receiving packets.
+//! and bind to UDP ports for receiving packets. Received datagrams are copied
+//! into the process's read buffer as they arrive, or, if the process has
+//! allowed a receive queue buffer, queued in that buffer until the process
+//! dequeues them. Processes can join IPv6 multicast groups to receive
+//! datagrams sent to them.
+//!
+//! Each process gets its own `NetworkCapability`, built from the network
+//! permissions in its TBF header, which limits the ports it may bind to and
+//! send to and the remote addresses it may communicate with. All processes
+//! are also limited by the board-wide capability when they bind, send and
+//! receive, and processes that do not declare network permissions are only
+//! limited by it.
 //! Also exposes a list of interface addresses to the application (currently
 //! hard-coded).
 
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::network_capabilities::NetworkCapability;
+use crate::net::ipv6::ipv6_recv::{IP6MulticastGroups, MAX_MULTICAST_GROUPS};
+use crate::net::network_capabilities::{
+    IpVisibilityCapability, NetworkCapability, UdpVisibilityCapability,
+};
 use crate::net::stream::encode_u16;
 use crate::net::stream::encode_u8;
 use crate::net::stream::SResult;
@@ -22,18 +36,24 @@ use core::convert::TryInto;
 use core::mem::size_of;
 use core::{cmp, mem};
 
-use kernel::capabilities::UdpDriverCapability;
+use kernel::capabilities::{NetworkCapabilityCreationCapability, UdpDriverCapability};
 use kernel::debug;
-use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
-use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::processbuffer::{
+    ReadWriteProcessBuffer, ReadableProcessBuffer, ReadableProcessSlice, WriteableProcessBuffer,
+    WriteableProcessSlice,
+};
 use kernel::syscall::{CommandReturn, SyscallDriver};
-use kernel::utilities::cells::MapCell;
+use kernel::utilities::cells::{MapCell, OptionalCell};
 use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
 use kernel::{ErrorCode, ProcessId};
 
 use crate::driver;
 pub const DRIVER_NUM: usize = driver::NUM::Udp as usize;
 
+/// The number of multicast groups each process can join.
+pub const MAX_GROUPS: usize = 2;
+
 /// Ids for read-only allow buffers
 mod ro_allow {
     pub const WRITE: usize = 0;
@@ -46,10 +66,19 @@ mod rw_allow {
     pub const READ: usize = 0;
     pub const CFG: usize = 1;
     pub const RX_CFG: usize = 2;
+    pub const RX_QUEUE: usize = 3;
     /// The number of allow buffers the kernel stores for this grant
-    pub const COUNT: u8 = 3;
+    pub const COUNT: u8 = 4;
 }
 
+/// The length of the source and destination written into the rx config
+/// buffer.
+const RX_CFG_LEN: usize = 2 * size_of::<UDPEndpoint>();
+
+/// The length of the header in front of each datagram in the receive queue:
+/// the payload length, the source and the destination.
+const RX_RECORD_HEADER_LEN: usize = 2 + RX_CFG_LEN;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct UDPEndpoint {
     addr: IPAddr,
@@ -89,6 +118,144 @@ impl UDPEndpoint {
 pub struct App {
     pending_tx: Option<[UDPEndpoint; 2]>,
     bound_port: Option<UDPEndpoint>,
+    /// Address and length of the receive queue buffer that the queue state
+    /// below refers to. The queue starts empty when another buffer is
+    /// allowed.
+    rx_queue_buf: (usize, usize),
+    /// Offset of the oldest queued datagram in the receive queue buffer.
+    rx_head: usize,
+    /// Number of bytes of the receive queue buffer in use.
+    rx_used: usize,
+    rx_count: usize,
+    /// Datagrams dropped because the queue was full.
+    dropped_full: u32,
+    /// Datagrams dropped because they did not fit in the queue or read
+    /// buffer.
+    dropped_size: u32,
+    groups: [Option<IPAddr>; MAX_GROUPS],
+    /// The network capability of this process, created on first use.
+    net_cap: Option<NetworkCapability>,
+}
+
+impl App {
+    /// Whether a datagram to `dst` is addressed to this app's binding or
+    /// one of its multicast groups.
+    fn accepts(&self, dst: &UDPEndpoint) -> bool {
+        self.bound_port.map_or(false, |bound| {
+            bound.port == dst.port
+                && (bound.addr == dst.addr || self.groups.iter().any(|g| *g == Some(dst.addr)))
+        })
+    }
+
+    /// Empties the receive queue if `queue` is not the buffer it was kept
+    /// in.
+    fn sync_rx_queue(&mut self, queue: &ReadWriteProcessBuffer) {
+        let buf = (queue.ptr() as usize, queue.len());
+        if buf != self.rx_queue_buf {
+            self.rx_queue_buf = buf;
+            self.rx_head = 0;
+            self.rx_used = 0;
+            self.rx_count = 0;
+        }
+    }
+
+    /// Appends a datagram to the receive queue held in `ring`.
+    fn enqueue(
+        &mut self,
+        ring: &WriteableProcessSlice,
+        src: &UDPEndpoint,
+        dst: &UDPEndpoint,
+        payload: &[u8],
+    ) -> bool {
+        let record_len = RX_RECORD_HEADER_LEN + payload.len();
+        if record_len > ring.len() || payload.len() > u16::MAX as usize {
+            self.dropped_size = self.dropped_size.saturating_add(1);
+            return false;
+        }
+        if record_len > ring.len() - self.rx_used {
+            self.dropped_full = self.dropped_full.saturating_add(1);
+            return false;
+        }
+        let mut header = [0; RX_RECORD_HEADER_LEN];
+        header[..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        src.encode(&mut header, 2);
+        dst.encode(&mut header, 2 + size_of::<UDPEndpoint>());
+        let tail = self.rx_head + self.rx_used;
+        for (i, byte) in header.iter().chain(payload.iter()).enumerate() {
+            ring[(tail + i) % ring.len()].set(*byte);
+        }
+        self.rx_used += record_len;
+        self.rx_count += 1;
+        true
+    }
+
+    /// Returns the header of the oldest datagram in the receive queue held
+    /// in `ring`. A queue the process has written over is emptied.
+    fn peek(&mut self, ring: &ReadableProcessSlice) -> Option<[u8; RX_RECORD_HEADER_LEN]> {
+        if self.rx_count == 0 {
+            return None;
+        }
+        let mut header = [0; RX_RECORD_HEADER_LEN];
+        for (i, byte) in header.iter_mut().enumerate() {
+            *byte = ring[(self.rx_head + i) % ring.len()].get();
+        }
+        let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+        if RX_RECORD_HEADER_LEN + len > self.rx_used {
+            self.rx_head = 0;
+            self.rx_used = 0;
+            self.rx_count = 0;
+            return None;
+        }
+        Some(header)
+    }
+
+    /// Removes the oldest datagram, of payload length `len`, from the
+    /// receive queue.
+    fn pop(&mut self, len: usize) {
+        let record_len = RX_RECORD_HEADER_LEN + len;
+        self.rx_head = (self.rx_head + record_len) % self.rx_queue_buf.1;
+        self.rx_used -= record_len;
+        self.rx_count -= 1;
+    }
+
+    /// Copies a datagram straight into the read buffer, as done for apps
+    /// that have not allowed a receive queue buffer.
+    fn deliver(&mut self, kernel_data: &GrantKernelData, cfg: &[u8], payload: &[u8]) -> bool {
+        let len = payload.len();
+        let res = kernel_data
+            .get_readwrite_processbuffer(rw_allow::READ)
+            .and_then(|read| {
+                read.mut_enter(|rbuf| {
+                    if rbuf.len() >= len {
+                        rbuf[..len].copy_from_slice(payload);
+                        Ok(())
+                    } else {
+                        Err(ErrorCode::SIZE) //packet does not fit
+                    }
+                })
+            })
+            .unwrap_or(Ok(()));
+        if res.is_err() {
+            self.dropped_size = self.dropped_size.saturating_add(1);
+            return false;
+        }
+        write_rx_cfg(kernel_data, cfg);
+        true
+    }
+}
+
+/// Writes the source and destination of a received datagram into the rx
+/// config buffer, if it has the right length.
+fn write_rx_cfg(kernel_data: &GrantKernelData, cfg: &[u8]) {
+    let _ = kernel_data
+        .get_readwrite_processbuffer(rw_allow::RX_CFG)
+        .and_then(|rx_cfg| {
+            rx_cfg.mut_enter(|buf| {
+                if buf.len() == RX_CFG_LEN {
+                    buf.copy_from_slice(cfg);
+                }
+            })
+        });
 }
 
 #[allow(dead_code)]
@@ -106,8 +273,10 @@ pub struct UDPDriver<'a> {
     /// ID of app whose transmission request is being processed.
     current_app: Cell<Option<ProcessId>>,
 
-    /// List of IP Addresses of the interfaces on the device
-    interface_list: &'static [IPAddr],
+    /// List of IP Addresses of the interfaces on the device. Addresses may be
+    /// configured at runtime, and unconfigured slots hold the unspecified
+    /// address.
+    interface_list: &'static [Cell<IPAddr>],
 
     /// Maximum length payload that an app can transmit via this driver
     max_tx_pyld_len: usize,
@@ -119,7 +288,18 @@ pub struct UDPDriver<'a> {
 
     driver_send_cap: &'static dyn UdpDriverCapability,
 
+    /// The board-wide capability that all datagrams are sent with.
     net_cap: &'static NetworkCapability,
+
+    /// Used to create the network capability of each process.
+    create_net_cap: &'static dyn NetworkCapabilityCreationCapability,
+    udp_vis: &'static UdpVisibilityCapability,
+    ip_vis: &'static IpVisibilityCapability,
+
+    /// IPv6 multicast group membership, shared by all apps.
+    multicast: OptionalCell<&'a dyn IP6MulticastGroups>,
+    /// The groups the driver has joined on behalf of apps.
+    joined_groups: [Cell<Option<IPAddr>>; MAX_MULTICAST_GROUPS],
 }
 
 impl<'a> UDPDriver<'a> {
@@ -131,12 +311,15 @@ impl<'a> UDPDriver<'a> {
             AllowRoCount<{ ro_allow::COUNT }>,
             AllowRwCount<{ rw_allow::COUNT }>,
         >,
-        interface_list: &'static [IPAddr],
+        interface_list: &'static [Cell<IPAddr>],
         max_tx_pyld_len: usize,
         port_table: &'static UdpPortManager,
         kernel_buffer: LeasableMutableBuffer<'static, u8>,
         driver_send_cap: &'static dyn UdpDriverCapability,
         net_cap: &'static NetworkCapability,
+        create_net_cap: &'static dyn NetworkCapabilityCreationCapability,
+        udp_vis: &'static UdpVisibilityCapability,
+        ip_vis: &'static IpVisibilityCapability,
     ) -> UDPDriver<'a> {
         UDPDriver {
             sender: sender,
@@ -148,9 +331,171 @@ impl<'a> UDPDriver<'a> {
             kernel_buffer: MapCell::new(kernel_buffer),
             driver_send_cap: driver_send_cap,
             net_cap: net_cap,
+            create_net_cap: create_net_cap,
+            udp_vis: udp_vis,
+            ip_vis: ip_vis,
+            multicast: OptionalCell::empty(),
+            joined_groups: Default::default(),
         }
     }
 
+    /// Returns the network capability of `appid`, creating it from the
+    /// network permissions in the process's TBF header the first time it is
+    /// needed. Processes without network permissions get the board-wide
+    /// capability.
+    fn app_net_cap<'b>(&self, appid: ProcessId, app: &'b mut App) -> &'b NetworkCapability {
+        app.net_cap.get_or_insert_with(|| {
+            appid.get_network_permissions().map_or_else(
+                || {
+                    NetworkCapability::new(
+                        self.net_cap.get_range(self.ip_vis),
+                        self.net_cap.get_remote_ports(self.udp_vis),
+                        self.net_cap.get_local_ports(self.udp_vis),
+                        self.create_net_cap,
+                    )
+                },
+                |permissions| {
+                    NetworkCapability::from_permissions(&permissions, self.create_net_cap)
+                },
+            )
+        })
+    }
+
+    /// Set the IPv6 layer that multicast groups joined by apps are passed
+    /// to. Without it, joining a group returns NOSUPPORT.
+    pub fn set_multicast_groups(&self, multicast: &'a dyn IP6MulticastGroups) {
+        self.multicast.set(multicast);
+    }
+
+    /// Reads the group address for commands 7 and 8 from the config buffer.
+    fn read_group(&self, kernel_data: &kernel::grant::GrantKernelData) -> Option<IPAddr> {
+        kernel_data
+            .get_readwrite_processbuffer(rw_allow::CFG)
+            .and_then(|cfg| {
+                cfg.enter(|cfg| {
+                    if cfg.len() != size_of::<IPAddr>() {
+                        return None;
+                    }
+                    let mut addr = IPAddr::new();
+                    cfg.copy_to_slice(&mut addr.0);
+                    Some(addr)
+                })
+            })
+            .unwrap_or(None)
+            .filter(|addr| addr.is_multicast())
+    }
+
+    fn join_group(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        let multicast = self.multicast.extract().ok_or(ErrorCode::NOSUPPORT)?;
+        let group = self
+            .apps
+            .enter(appid, |app, kernel_data| -> Result<IPAddr, ErrorCode> {
+                let group = self.read_group(kernel_data).ok_or(ErrorCode::INVAL)?;
+                if !app.groups.contains(&Some(group)) && !app.groups.contains(&None) {
+                    return Err(ErrorCode::NOMEM);
+                }
+                Ok(group)
+            })??;
+        let result = match multicast.join_group(group) {
+            Err(ErrorCode::NOMEM) => {
+                // Apps that exited may have left groups joined
+                self.leave_unused_groups(multicast);
+                multicast.join_group(group)
+            }
+            result => result,
+        };
+        result?;
+        if !self.joined_groups.iter().any(|g| g.get() == Some(group)) {
+            self.joined_groups
+                .iter()
+                .find(|g| g.get().is_none())
+                .map(|g| g.set(Some(group)));
+        }
+        self.apps.enter(appid, |app, _| {
+            if !app.groups.contains(&Some(group)) {
+                app.groups
+                    .iter_mut()
+                    .find(|g| g.is_none())
+                    .map(|g| *g = Some(group));
+            }
+        })?;
+        Ok(())
+    }
+
+    fn leave_group(&self, appid: ProcessId) -> Result<(), ErrorCode> {
+        let multicast = self.multicast.extract().ok_or(ErrorCode::NOSUPPORT)?;
+        self.apps
+            .enter(appid, |app, kernel_data| -> Result<(), ErrorCode> {
+                let group = self.read_group(kernel_data).ok_or(ErrorCode::INVAL)?;
+                let slot = app
+                    .groups
+                    .iter_mut()
+                    .find(|g| **g == Some(group))
+                    .ok_or(ErrorCode::INVAL)?;
+                *slot = None;
+                Ok(())
+            })??;
+        self.leave_unused_groups(multicast);
+        Ok(())
+    }
+
+    /// Leaves the groups joined by the driver that no app is a member of
+    /// anymore, including the groups of apps that exited. The IPv6 layer
+    /// keeps receiving a group while any app is a member.
+    fn leave_unused_groups(&self, multicast: &dyn IP6MulticastGroups) {
+        for joined in self.joined_groups.iter() {
+            if let Some(group) = joined.get() {
+                let mut member = false;
+                for app in self.apps.iter() {
+                    app.enter(|app, _| {
+                        member |= app.groups.contains(&Some(group));
+                    });
+                }
+                if !member {
+                    let _ = multicast.leave_group(group);
+                    joined.set(None);
+                }
+            }
+        }
+    }
+
+    /// Copies the oldest queued datagram into the app's read buffer, and its
+    /// source and destination into the rx config buffer.
+    fn dequeue(&self, appid: ProcessId) -> Result<(u32, u32), ErrorCode> {
+        self.apps.enter(appid, |app, kernel_data| {
+            let queue = kernel_data.get_readwrite_processbuffer(rw_allow::RX_QUEUE)?;
+            app.sync_rx_queue(&queue);
+            let header = queue
+                .enter(|ring| app.peek(ring))
+                .unwrap_or(None)
+                .ok_or(ErrorCode::FAIL)?;
+            let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+            let start = app.rx_head + RX_RECORD_HEADER_LEN;
+            kernel_data
+                .get_readwrite_processbuffer(rw_allow::READ)
+                .and_then(|read| {
+                    queue
+                        .enter(|ring| {
+                            read.mut_enter(|rbuf| {
+                                if rbuf.len() < len {
+                                    return Err(ErrorCode::SIZE);
+                                }
+                                for i in 0..len {
+                                    rbuf[i].set(ring[(start + i) % ring.len()].get());
+                                }
+                                Ok(())
+                            })
+                        })
+                        .and_then(|res| res)
+                })
+                .unwrap_or(Err(ErrorCode::INVAL))?;
+
+            write_rx_cfg(kernel_data, &header[2..]);
+            app.pop(len);
+            Ok((len as u32, app.rx_count as u32))
+        })?
+    }
+
     /// If the driver is currently idle and there are pending transmissions,
     /// pick an app with a pending transmission and return its `ProcessId`.
     fn get_next_tx_if_idle(&self) -> Option<ProcessId> {
@@ -306,12 +651,22 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///
     /// ### `allow_num`
     ///
-    /// - `0`: Read buffer. Will contain the received payload.
+    /// - `0`: Read buffer. Will contain the payload of the received datagram,
+    ///        or, if a receive queue buffer is allowed, of the datagram
+    ///        dequeued with command `5`.
     /// - `1`: Config buffer. Used to contain miscellaneous data associated with
-    ///        some commands, namely source/destination addresses and ports.
+    ///        some commands, namely source/destination addresses and ports,
+    ///        and multicast group addresses.
     /// - `2`: Rx config buffer. Used to contain source/destination addresses
     ///        and ports for receives (separate from `2` because receives may
-    ///        be waiting for an incoming packet asynchronously).
+    ///        be waiting for an incoming packet asynchronously). The source and
+    ///        then the destination of the received or dequeued datagram are
+    ///        written into it; the destination tells multicast datagrams apart.
+    /// - `3`: Receive queue buffer. While a non-empty buffer is allowed,
+    ///        received datagrams are queued in it instead of being copied
+    ///        into the read buffer, and are read with command `5`. The queue
+    ///        is kept by the kernel and starts empty when another buffer is
+    ///        allowed; each datagram takes its payload length plus 38 bytes.
 
     /// Setup shared buffers.
     ///
@@ -325,9 +680,9 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     //
     // ### `subscribe_num`
     //
-    // - `0`: Setup callback for when packet is received. If no port has
-    //        been bound, return RESERVE to indicate that port binding is
-    //        is a prerequisite to reception.
+    // - `0`: Setup callback for when a packet is received. The arguments are
+    //        the payload length and, if a receive queue buffer is allowed,
+    //        the number of queued datagrams, which are read with command `5`.
     // - `1`: Setup callback for when packet is transmitted. Notably,
     //        this callback receives the result of the send_done callback
     //        from udp_send.rs, which does not currently pass information
@@ -340,7 +695,8 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     /// - `0`: Driver check.
     /// - `1`: Get the interface list
     ///        app_cfg (out): 16 * `n` bytes: the list of interface IPv6 addresses, length
-    ///                       limited by `app_cfg` length.
+    ///                       limited by `app_cfg` length. Addresses that have
+    ///                       not been configured yet are unspecified (`::`).
     ///        Returns INVAL if the cfg buffer is the wrong size, or not available.
     /// - `2`: Transmit payload.
     ///        Returns BUSY is this process already has a pending tx.
@@ -361,12 +717,15 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///        Currently, only will transmit if the app has bound to the port passed in the tx_cfg
     ///        buf as the source address. If no port is bound, returns RESERVE, if it tries to
     ///        send on a port other than the port which is bound, returns INVALID.
+    ///        Returns FAIL if the app's network capability does not allow sending to the
+    ///        destination address or port.
     ///        Notably, the currently transmit implementation allows for starvation - an
     ///        an app with a lower app id can send constantly and starve an app with a
     ///        later ID.
     /// - `3`: Bind to the address in rx_cfg. Returns Ok(()) if that addr/port combo is free,
     ///        returns INVAL if the address requested is not a local interface, or if the port
     ///        requested is 0. Returns BUSY if that port is already bound to by another app.
+    ///        Returns FAIL if the app's network capability does not allow binding to the port.
     ///        This command should be called after allow() is called on the rx_cfg buffer, and
     ///        before subscribe() is used to set up the recv callback. Additionally, apps can only
     ///        send on ports after they have bound to said port. If this command is called
@@ -375,9 +734,21 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
     ///        the current implementation of this only allows for each app to bind to a single
     ///        port at a time, as such an implementation conserves memory (and is similar
     ///        to the approach applied by TinyOS and Riot).
-    ///        /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
+    /// - `4`: Returns the maximum payload that can be transmitted by apps using this driver.
     ///        This represents the size of the payload buffer in the kernel. Apps can use this
     ///        syscall to ensure they do not attempt to send too-large messages.
+    /// - `5`: Dequeue the oldest received datagram into the read buffer, and its source and
+    ///        destination into rx_cfg. Returns the payload length and the number of datagrams
+    ///        still queued. Returns FAIL if the queue is empty, and SIZE if the payload does
+    ///        not fit in the read buffer, in which case the datagram stays queued. Only used
+    ///        with a receive queue buffer allowed.
+    /// - `6`: Returns the number of datagrams dropped because the queue was full, and the
+    ///        number dropped because they did not fit in the queue or read buffer, and resets
+    ///        both counters.
+    /// - `7`: Join the IPv6 multicast group whose address is in the config buffer. Datagrams
+    ///        sent to the group and the bound port are then received. Returns NOMEM if the app
+    ///        or the IPv6 layer cannot join more groups.
+    /// - `8`: Leave the multicast group whose address is in the config buffer.
 
     fn command(
         &self,
@@ -406,7 +777,7 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                                     let iface_size = size_of::<IPAddr>();
                                     for i in 0..n_ifaces_to_copy {
                                         cfg[i * iface_size..(i + 1) * iface_size]
-                                            .copy_from_slice(&self.interface_list[i].0);
+                                            .copy_from_slice(&self.interface_list[i].get().0);
                                     }
                                     // Returns total number of interfaces
                                     CommandReturn::success_u32(self.interface_list.len() as u32)
@@ -461,8 +832,16 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                                 })
                             })
                             .unwrap_or(None);
-                        if next_tx.is_none() {
-                            return Err(ErrorCode::INVAL);
+                        let [src, dst] = match next_tx {
+                            Some(next_tx) => next_tx,
+                            None => return Err(ErrorCode::INVAL),
+                        };
+                        let net_cap = self.app_net_cap(appid, app);
+                        if !net_cap.local_port_valid(src.port, self.udp_vis)
+                            || !net_cap.remote_port_valid(dst.port, self.udp_vis)
+                            || !net_cap.remote_addr_valid(dst.addr, self.ip_vis)
+                        {
+                            return Err(ErrorCode::FAIL);
                         }
                         app.pending_tx = next_tx;
                         Ok(())
@@ -513,13 +892,24 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                             // Check that requested addr is a local interface
                             let mut requested_is_local = false;
                             for i in 0..self.interface_list.len() {
-                                if requested_addr.addr == self.interface_list[i] {
+                                let iface_addr = self.interface_list[i].get();
+                                if !iface_addr.is_unspecified() && requested_addr.addr == iface_addr
+                                {
                                     requested_is_local = true;
                                 }
                             }
                             if !requested_is_local {
                                 return Err(Err(ErrorCode::INVAL));
                             }
+                            if !self
+                                .app_net_cap(appid, app)
+                                .local_port_valid(requested_addr.port, self.udp_vis)
+                                || !self
+                                    .net_cap
+                                    .local_port_valid(requested_addr.port, self.udp_vis)
+                            {
+                                return Err(Err(ErrorCode::FAIL));
+                            }
                             Ok(Some(requested_addr))
                         })
                     })
@@ -552,6 +942,21 @@ impl<'a> SyscallDriver for UDPDriver<'a> {
                 }
             }
             4 => CommandReturn::success_u32(self.max_tx_pyld_len as u32),
+            5 => match self.dequeue(appid) {
+                Ok((len, remaining)) => CommandReturn::success_u32_u32(len, remaining),
+                Err(e) => CommandReturn::failure(e),
+            },
+            6 => self
+                .apps
+                .enter(appid, |app, _| {
+                    let counts = (app.dropped_full, app.dropped_size);
+                    app.dropped_full = 0;
+                    app.dropped_size = 0;
+                    CommandReturn::success_u32_u32(counts.0, counts.1)
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+            7 => CommandReturn::from(self.join_group(appid)),
+            8 => CommandReturn::from(self.leave_group(appid)),
             _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
         }
     }
@@ -591,52 +996,47 @@ impl<'a> UDPRecvClient for UDPDriver<'a> {
         dst_port: u16,
         payload: &[u8],
     ) {
-        self.apps.each(|_, app, kernel_data| {
-            if app.bound_port.is_some() {
-                let mut for_me = false;
-                app.bound_port.as_ref().map(|requested_addr| {
-                    if requested_addr.addr == dst_addr && requested_addr.port == dst_port {
-                        for_me = true;
-                    }
-                });
-                if for_me {
-                    let len = payload.len();
-                    let res = kernel_data
-                        .get_readwrite_processbuffer(rw_allow::READ)
-                        .and_then(|read| {
-                            read.mut_enter(|rbuf| {
-                                if rbuf.len() >= len {
-                                    rbuf[..len].copy_from_slice(&payload[..len]);
-                                    Ok(())
-                                } else {
-                                    Err(ErrorCode::SIZE) //packet does not fit
-                                }
-                            })
-                        })
-                        .unwrap_or(Ok(()));
-                    if res.is_ok() {
-                        // Write address of sender into rx_cfg so it can be read by client
-                        let sender_addr = UDPEndpoint {
-                            addr: src_addr,
-                            port: src_port,
-                        };
-                        kernel_data.schedule_upcall(0, (len, 0, 0)).ok();
-                        const CFG_LEN: usize = 2 * size_of::<UDPEndpoint>();
-                        let _ = kernel_data
-                            .get_readwrite_processbuffer(rw_allow::RX_CFG)
-                            .and_then(|rx_cfg| {
-                                rx_cfg.mut_enter(|cfg| {
-                                    if cfg.len() != CFG_LEN {
-                                        return Err(ErrorCode::INVAL);
-                                    }
-                                    let mut tmp_cfg_buffer: [u8; CFG_LEN] = [0; CFG_LEN];
-                                    sender_addr.encode(&mut tmp_cfg_buffer, 0);
-                                    cfg.copy_from_slice(&tmp_cfg_buffer);
-                                    Ok(())
-                                })
-                            })
-                            .unwrap_or(Err(ErrorCode::INVAL));
-                    }
+        let src = UDPEndpoint {
+            addr: src_addr,
+            port: src_port,
+        };
+        let dst = UDPEndpoint {
+            addr: dst_addr,
+            port: dst_port,
+        };
+        self.apps.each(|appid, app, kernel_data| {
+            // Datagrams to a multicast group are delivered to every member
+            if !app.accepts(&dst) {
+                return;
+            }
+            let net_cap = self.app_net_cap(appid, app);
+            if !net_cap.remote_addr_valid(src_addr, self.ip_vis)
+                || !net_cap.remote_port_valid(src_port, self.udp_vis)
+                || !self.net_cap.remote_addr_valid(src_addr, self.ip_vis)
+                || !self.net_cap.remote_port_valid(src_port, self.udp_vis)
+            {
+                return;
+            }
+            let queue = match kernel_data.get_readwrite_processbuffer(rw_allow::RX_QUEUE) {
+                Ok(queue) => queue,
+                Err(_) => return,
+            };
+            app.sync_rx_queue(&queue);
+            if queue.len() > 0 {
+                let queued = queue
+                    .mut_enter(|ring| app.enqueue(ring, &src, &dst, payload))
+                    .unwrap_or(false);
+                if queued {
+                    kernel_data
+                        .schedule_upcall(0, (payload.len(), app.rx_count, 0))
+                        .ok();
+                }
+            } else {
+                let mut cfg = [0; RX_CFG_LEN];
+                src.encode(&mut cfg, 0);
+                dst.encode(&mut cfg, size_of::<UDPEndpoint>());
+                if app.deliver(kernel_data, &cfg, payload) {
+                    kernel_data.schedule_upcall(0, (payload.len(), 0, 0)).ok();
                 }
             }
         });
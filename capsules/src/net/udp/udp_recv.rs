@@ -6,7 +6,7 @@
 //! correctness when dispatching received packets to the appropriate client.
 
 use crate::net::ipv6::ip_utils::IPAddr;
-use crate::net::ipv6::ipv6_recv::IP6RecvClient;
+use crate::net::ipv6::ipv6_recv::{IP6MulticastGroups, IP6RecvClient};
 use crate::net::ipv6::IP6Header;
 use crate::net::udp::driver::UDPDriver;
 use crate::net::udp::udp_port_table::{PortQuery, UdpPortBindingRx};
@@ -15,10 +15,12 @@ use crate::net::udp::UDPHeader;
 use kernel::collections::list::{List, ListLink, ListNode};
 use kernel::debug;
 use kernel::utilities::cells::{MapCell, OptionalCell};
+use kernel::ErrorCode;
 
 pub struct MuxUdpReceiver<'a> {
     rcvr_list: List<'a, UDPReceiver<'a>>,
     driver: OptionalCell<&'static UDPDriver<'static>>,
+    multicast: OptionalCell<&'a dyn IP6MulticastGroups>,
 }
 
 impl<'a> MuxUdpReceiver<'a> {
@@ -26,6 +28,7 @@ impl<'a> MuxUdpReceiver<'a> {
         MuxUdpReceiver {
             rcvr_list: List::new(),
             driver: OptionalCell::empty(),
+            multicast: OptionalCell::empty(),
         }
     }
 
@@ -36,6 +39,27 @@ impl<'a> MuxUdpReceiver<'a> {
     pub fn set_driver(&self, driver_ref: &'static UDPDriver) {
         self.driver.replace(driver_ref);
     }
+
+    /// Set the IPv6 receiver that multicast group membership is passed to.
+    pub fn set_multicast_groups(&self, multicast: &'a dyn IP6MulticastGroups) {
+        self.multicast.set(multicast);
+    }
+}
+
+impl<'a> IP6MulticastGroups for MuxUdpReceiver<'a> {
+    fn join_group(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        self.multicast
+            .map_or(Err(ErrorCode::NOSUPPORT), |multicast| {
+                multicast.join_group(group)
+            })
+    }
+
+    fn leave_group(&self, group: IPAddr) -> Result<(), ErrorCode> {
+        self.multicast
+            .map_or(Err(ErrorCode::NOSUPPORT), |multicast| {
+                multicast.leave_group(group)
+            })
+    }
 }
 
 impl<'a> IP6RecvClient for MuxUdpReceiver<'a> {
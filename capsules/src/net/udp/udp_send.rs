@@ -71,6 +71,11 @@ impl<'a, T: IP6Sender<'a>> MuxUdpSender<'a, T> {
                     debug!("No buffer available to take.");
                     Err(ErrorCode::FAIL)
                 }
+            };
+            // No send_done will follow a failed send, so the caller must not
+            // stay at the head of the queue
+            if ret.is_err() {
+                self.sender_list.pop_head();
             }
         } else {
             caller.net_cap.replace(net_cap); //store capability with sender
@@ -0,0 +1,143 @@
+//! Provides userspace with access to barometric pressure sensors.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### `subscribe` System Call
+//!
+//! The `subscribe` system call supports the single `subscribe_number` zero,
+//! which is used to provide a callback that will return back the result of
+//! a pressure reading: a status code, and the pressure in pascals if the
+//! reading succeeded.
+//!
+//! ### `command` System Call
+//!
+//! The `command` system call support one argument `cmd` which is used to specify the specific
+//! operation, currently the following cmd's are supported:
+//!
+//! * `0`: check whether the driver exist
+//! * `1`: read the pressure
+//!
+//! Several processes can ask for a reading at the same time, in which case
+//! they all get the result of the same reading.
+//!
+//! The possible return from the 'command' system call indicates the following:
+//!
+//! * `Ok(())`:    The operation has been successful.
+//! * `BUSY`:      The process is already waiting for a reading.
+//! * `ENOSUPPORT`: Invalid `cmd`.
+//! * `NOMEM`:     No sufficient memory available.
+//!
+//! Usage
+//! -----
+//!
+//! You need a device that provides the `hil::sensors::PressureDriver` trait.
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let grant_cap = create_capability!(capabilities::MemoryAllocationCapability);
+//!
+//! let pressure = static_init!(
+//!        capsules::pressure::PressureSensor<'static>,
+//!        capsules::pressure::PressureSensor::new(bme280,
+//!                                                board_kernel.create_grant(&grant_cap)));
+//!
+//! kernel::hil::sensors::PressureDriver::set_client(bme280, pressure);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Pressure as usize;
+
+#[derive(Default)]
+pub struct App {
+    subscribed: bool,
+}
+
+pub struct PressureSensor<'a> {
+    driver: &'a dyn hil::sensors::PressureDriver<'a>,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    busy: Cell<bool>,
+}
+
+impl<'a> PressureSensor<'a> {
+    pub fn new(
+        driver: &'a dyn hil::sensors::PressureDriver<'a>,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> PressureSensor<'a> {
+        PressureSensor {
+            driver: driver,
+            apps: grant,
+            busy: Cell::new(false),
+        }
+    }
+
+    fn enqueue_command(&self, appid: ProcessId) -> CommandReturn {
+        self.apps
+            .enter(appid, |app, _| {
+                if app.subscribed {
+                    return CommandReturn::failure(ErrorCode::BUSY);
+                }
+                // Processes that ask while a reading is in progress share its
+                // result
+                if !self.busy.get() {
+                    if let Err(error) = self.driver.read_pressure() {
+                        return CommandReturn::failure(error);
+                    }
+                    self.busy.set(true);
+                }
+                app.subscribed = true;
+                CommandReturn::success()
+            })
+            .unwrap_or_else(|err| CommandReturn::failure(err.into()))
+    }
+}
+
+impl hil::sensors::PressureClient for PressureSensor<'_> {
+    fn callback(&self, pressure: Result<u32, ErrorCode>) {
+        self.busy.set(false);
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, upcalls| {
+                if app.subscribed {
+                    app.subscribed = false;
+                    upcalls
+                        .schedule_upcall(
+                            0,
+                            (
+                                into_statuscode(pressure.map(|_| ())),
+                                pressure.unwrap_or(0) as usize,
+                                0,
+                            ),
+                        )
+                        .ok();
+                }
+            });
+        }
+    }
+}
+
+impl SyscallDriver for PressureSensor<'_> {
+    fn command(&self, command_num: usize, _: usize, _: usize, appid: ProcessId) -> CommandReturn {
+        match command_num {
+            // check whether the driver exists!!
+            0 => CommandReturn::success(),
+
+            // read pressure
+            1 => self.enqueue_command(appid),
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
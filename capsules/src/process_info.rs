@@ -0,0 +1,393 @@
+//! Provides userspace with information about the processes running on the
+//! board.
+//!
+//! This capsule is a "ps"-like interface that allows a trusted management
+//! application to inspect the state and resource usage of every process loaded
+//! by the kernel. Because it exposes information about other processes, the
+//! capsule requires a `ProcessManagementCapability` and should only be
+//! included on boards where this is acceptable.
+//!
+//! Process Info Layout
+//! -------------------
+//!
+//! Command 3 writes a fixed-size record describing one process into the
+//! allowed buffer. All fields are little-endian. New fields will only ever be
+//! appended, so userspace should use the length returned by the command.
+//!
+//! ```text
+//! Offset  Size  Field
+//!      0     4  Process ID
+//!      4     4  State (see below)
+//!      8     8  CPU time executed since the process started, in us
+//!     16     4  Number of syscalls
+//!     20     4  Number of dropped upcalls
+//!     24     4  Number of restarts
+//!     28     4  Number of timeslice expirations
+//!     32     4  Number of grants allocated
+//!     36     4  Total number of grants in the kernel
+//!     40     4  Bytes of process memory used by the kernel (grant region)
+//!     44     4  Bytes of heap in use (0 if unknown)
+//!     48     4  Stack high-water mark in bytes (0 if unknown)
+//!     52     4  Total bytes of RAM allocated to the process
+//! ```
+//!
+//! The state is encoded as:
+//!
+//! - `0`: Unstarted
+//! - `1`: Running
+//! - `2`: Yielded
+//! - `3`: StoppedRunning
+//! - `4`: StoppedYielded
+//! - `5`: Faulted
+//! - `6`: Terminated
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let process_info = static_init!(
+//!     capsules::process_info::ProcessInfo<Capability>,
+//!     capsules::process_info::ProcessInfo::new(
+//!         board_kernel,
+//!         board_kernel.create_grant(capsules::process_info::DRIVER_NUM, &grant_cap),
+//!         Capability,
+//!     )
+//! );
+//! ```
+
+use kernel::capabilities::ProcessManagementCapability;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::introspection::KernelInfo;
+use kernel::process::{self, Process, ProcessAddresses, ProcessId};
+use kernel::processbuffer::WriteableProcessBuffer;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::{ErrorCode, Kernel};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::ProcessInfo as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const INFO: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// Size in bytes of the record written by command 3.
+pub const PROCESS_INFO_LEN: usize = 56;
+
+/// Convert a process state to the value reported to userspace.
+fn state_to_u32(state: process::State) -> u32 {
+    match state {
+        process::State::Unstarted => 0,
+        process::State::Running => 1,
+        process::State::Yielded => 2,
+        process::State::StoppedRunning => 3,
+        process::State::StoppedYielded => 4,
+        process::State::Faulted => 5,
+        process::State::Terminated => 6,
+    }
+}
+
+/// The values in the record of one process.
+struct Record {
+    process_id: usize,
+    state: process::State,
+    cpu_time_us: u64,
+    syscalls: usize,
+    dropped_upcalls: usize,
+    restarts: usize,
+    timeslice_expirations: usize,
+    grants_used: usize,
+    grants_total: usize,
+    addresses: ProcessAddresses,
+}
+
+impl Record {
+    /// Serialize the record in the layout described above.
+    fn encode(&self, out: &mut [u8; PROCESS_INFO_LEN]) {
+        let addresses = &self.addresses;
+        let heap = addresses
+            .sram_heap_start
+            .map_or(0, |start| addresses.sram_app_brk.saturating_sub(start));
+        let stack = match (addresses.sram_stack_top, addresses.sram_stack_bottom) {
+            (Some(top), Some(bottom)) => top.saturating_sub(bottom),
+            _ => 0,
+        };
+
+        let words: [u32; 10] = [
+            self.syscalls as u32,
+            self.dropped_upcalls as u32,
+            self.restarts as u32,
+            self.timeslice_expirations as u32,
+            self.grants_used as u32,
+            self.grants_total as u32,
+            (addresses.sram_end - addresses.sram_grant_start) as u32,
+            heap as u32,
+            stack as u32,
+            (addresses.sram_end - addresses.sram_start) as u32,
+        ];
+
+        out[0..4].copy_from_slice(&(self.process_id as u32).to_le_bytes());
+        out[4..8].copy_from_slice(&state_to_u32(self.state).to_le_bytes());
+        out[8..16].copy_from_slice(&self.cpu_time_us.to_le_bytes());
+        for (i, word) in words.iter().enumerate() {
+            let offset = 16 + i * 4;
+            out[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+        }
+    }
+}
+
+pub struct ProcessInfo<C: ProcessManagementCapability> {
+    kernel: &'static Kernel,
+    apps: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    capability: C,
+}
+
+impl<C: ProcessManagementCapability> ProcessInfo<C> {
+    pub fn new(
+        kernel: &'static Kernel,
+        grant: Grant<(), UpcallCount<0>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+        capability: C,
+    ) -> ProcessInfo<C> {
+        ProcessInfo {
+            kernel,
+            apps: grant,
+            capability,
+        }
+    }
+
+    /// Serialize the information about `process` into `out`.
+    fn process_record(&self, process: &dyn Process, out: &mut [u8; PROCESS_INFO_LEN]) {
+        let info = KernelInfo::new(self.kernel);
+        let process_id = process.processid();
+        let (grants_used, grants_total) = info.number_app_grant_uses(process_id, &self.capability);
+        Record {
+            process_id: process_id.id(),
+            state: process.get_state(),
+            cpu_time_us: info.app_cpu_time_us(process_id, &self.capability),
+            syscalls: info.number_app_syscalls(process_id, &self.capability),
+            dropped_upcalls: info.number_app_dropped_upcalls(process_id, &self.capability),
+            restarts: info.number_app_restarts(process_id, &self.capability),
+            timeslice_expirations: info
+                .number_app_timeslice_expirations(process_id, &self.capability),
+            grants_used,
+            grants_total,
+            addresses: process.get_addresses(),
+        }
+        .encode(out);
+    }
+
+    /// Run `closure` on the process with identifier `id`, if it exists.
+    fn with_process<F, R>(&self, id: usize, closure: F) -> Option<R>
+    where
+        F: Fn(&dyn Process) -> R,
+    {
+        let mut result = None;
+        self.kernel
+            .process_each_capability(&self.capability, |process| {
+                if result.is_none() && process.processid().id() == id {
+                    result = Some(closure(process));
+                }
+            });
+        result
+    }
+
+    /// Copy `data` into the buffer the calling app shared with allow. Returns
+    /// the number of bytes copied, or `SIZE` if the buffer is too small.
+    fn copy_to_app(&self, appid: ProcessId, data: &[u8]) -> Result<usize, ErrorCode> {
+        self.apps
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readwrite_processbuffer(rw_allow::INFO)
+                    .and_then(|buffer| {
+                        buffer.mut_enter(|buf| {
+                            if buf.len() < data.len() {
+                                Err(ErrorCode::SIZE)
+                            } else {
+                                buf[..data.len()].copy_from_slice(data);
+                                Ok(data.len())
+                            }
+                        })
+                    })
+                    .unwrap_or(Err(ErrorCode::RESERVE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+}
+
+impl<C: ProcessManagementCapability> SyscallDriver for ProcessInfo<C> {
+    /// Query process information.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Return the number of processes loaded on the board.
+    /// - `2`: Fill the allowed buffer with the IDs of all loaded processes, as
+    ///        little-endian `u32`s. Returns the number of IDs that fit in the
+    ///        buffer.
+    /// - `3`: Fill the allowed buffer with the information record for the
+    ///        process whose ID is `data`. Returns the number of bytes written.
+    /// - `4`: Copy the name of the process whose ID is `data` into the allowed
+    ///        buffer. Returns the length of the name.
+    fn command(
+        &self,
+        command_num: usize,
+        data: usize,
+        _: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+
+            1 => {
+                let info = KernelInfo::new(self.kernel);
+                CommandReturn::success_u32(info.number_loaded_processes(&self.capability) as u32)
+            }
+
+            2 => {
+                let res = self
+                    .apps
+                    .enter(appid, |_, kernel_data| {
+                        kernel_data
+                            .get_readwrite_processbuffer(rw_allow::INFO)
+                            .and_then(|buffer| {
+                                buffer.mut_enter(|buf| {
+                                    let mut count = 0;
+                                    self.kernel.process_each_capability(
+                                        &self.capability,
+                                        |process| {
+                                            let offset = count * 4;
+                                            if offset + 4 <= buf.len() {
+                                                let id = process.processid().id() as u32;
+                                                buf[offset..offset + 4]
+                                                    .copy_from_slice(&id.to_le_bytes());
+                                                count += 1;
+                                            }
+                                        },
+                                    );
+                                    count
+                                })
+                            })
+                            .map_err(ErrorCode::from)
+                    })
+                    .unwrap_or_else(|err| Err(err.into()));
+                match res {
+                    Ok(count) => CommandReturn::success_u32(count as u32),
+                    Err(e) => CommandReturn::failure(e),
+                }
+            }
+
+            3 => {
+                let record = self.with_process(data, |process| {
+                    let mut record = [0; PROCESS_INFO_LEN];
+                    self.process_record(process, &mut record);
+                    record
+                });
+                match record {
+                    Some(record) => match self.copy_to_app(appid, &record) {
+                        Ok(len) => CommandReturn::success_u32(len as u32),
+                        Err(e) => CommandReturn::failure(e),
+                    },
+                    None => CommandReturn::failure(ErrorCode::INVAL),
+                }
+            }
+
+            4 => {
+                let name = self.with_process(data, |process| process.get_process_name());
+                match name {
+                    Some(name) => match self.copy_to_app(appid, name.as_bytes()) {
+                        Ok(len) => CommandReturn::success_u32(len as u32),
+                        Err(e) => CommandReturn::failure(e),
+                    },
+                    None => CommandReturn::failure(ErrorCode::INVAL),
+                }
+            }
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(record: &[u8; PROCESS_INFO_LEN], offset: usize) -> u32 {
+        u32::from_le_bytes(record[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn record() -> Record {
+        Record {
+            process_id: 7,
+            state: process::State::Yielded,
+            cpu_time_us: 0x1_0000_0002,
+            syscalls: 100,
+            dropped_upcalls: 1,
+            restarts: 2,
+            timeslice_expirations: 3,
+            grants_used: 4,
+            grants_total: 9,
+            addresses: ProcessAddresses {
+                flash_start: 0x40000,
+                flash_non_protected_start: 0x40040,
+                flash_end: 0x48000,
+                sram_start: 0x2000_8000,
+                sram_app_brk: 0x2000_9000,
+                sram_grant_start: 0x2000_9c00,
+                sram_end: 0x2000_a000,
+                sram_heap_start: Some(0x2000_8c00),
+                sram_stack_top: Some(0x2000_8800),
+                sram_stack_bottom: Some(0x2000_8500),
+            },
+        }
+    }
+
+    #[test]
+    fn record_layout() {
+        let mut out = [0; PROCESS_INFO_LEN];
+        record().encode(&mut out);
+
+        assert_eq!(word(&out, 0), 7);
+        assert_eq!(word(&out, 4), 2);
+        assert_eq!(
+            u64::from_le_bytes(out[8..16].try_into().unwrap()),
+            0x1_0000_0002
+        );
+        assert_eq!(word(&out, 16), 100);
+        assert_eq!(word(&out, 20), 1);
+        assert_eq!(word(&out, 24), 2);
+        assert_eq!(word(&out, 28), 3);
+        assert_eq!(word(&out, 32), 4);
+        assert_eq!(word(&out, 36), 9);
+        assert_eq!(word(&out, 40), 0x400);
+        assert_eq!(word(&out, 44), 0x400);
+        assert_eq!(word(&out, 48), 0x300);
+        assert_eq!(word(&out, 52), 0x2000);
+    }
+
+    #[test]
+    fn unknown_stack_and_heap_are_zero() {
+        let mut record = record();
+        record.addresses.sram_heap_start = None;
+        record.addresses.sram_stack_bottom = None;
+        let mut out = [0xff; PROCESS_INFO_LEN];
+        record.encode(&mut out);
+
+        assert_eq!(word(&out, 44), 0);
+        assert_eq!(word(&out, 48), 0);
+
+        // A stack pointer above the top the process reported is not
+        // counted as stack in use
+        record.addresses.sram_stack_bottom = Some(0x2000_8900);
+        record.encode(&mut out);
+        assert_eq!(word(&out, 48), 0);
+    }
+}
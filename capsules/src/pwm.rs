@@ -0,0 +1,243 @@
+//! Provides userspace with PWM outputs, for dimming LEDs or driving servos.
+//!
+//! The board gives the driver an array of PWM pins, usually `PwmPinUser`s of
+//! a `virtual_pwm::MuxPwm`, which userspace addresses as channels by their
+//! index. Each channel is owned by one process at a time: the first process
+//! to configure or start it. The owner releases it by stopping it, or by
+//! exiting, after which the next process to use the channel takes it over.
+//! The driver checks the owners of all channels on every command, so the
+//! output of an owner that exits is stopped the next time any process uses
+//! the driver.
+//!
+//! A channel's frequency and duty cycle are set separately, and take effect
+//! immediately if the channel is running. Both are checked against the
+//! limits of the underlying hardware, which userspace can read with commands
+//! 5 and 6. The duty cycle is in the units of the hardware: a duty cycle of
+//! `get_maximum_duty_cycle` keeps the output high for the whole period.
+//!
+//! Channels that share a `MuxPwm` are run one at a time by the mux, so one
+//! started while another is running only starts once the other stops.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Command
+//!
+//! * 0: driver check. Returns the number of channels.
+//! * 1: set the frequency of channel `arg1` to `arg2` Hz.
+//! * 2: set the duty cycle of channel `arg1` to `arg2`.
+//! * 3: start channel `arg1`, with the frequency and duty cycle set with
+//!   commands 1 and 2.
+//! * 4: stop channel `arg1`, releasing it.
+//! * 5: get the highest frequency of channel `arg1`, in Hz.
+//! * 6: get the duty cycle of channel `arg1` that is 100%.
+//!
+//! Commands 1 to 6 return `INVAL` for channels that do not exist. Commands 1
+//! and 2 return `INVAL` for values beyond the limits of the channel, and
+//! command 3 returns `INVAL` if no frequency has been set. Commands 1 to 4
+//! return `RESERVE` if another process owns the channel.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let pwm_pins = static_init!(
+//!     [&'static dyn kernel::hil::pwm::PwmPin; 2],
+//!     [pwm_pin_a0, pwm_pin_a1]
+//! );
+//! let pwm = static_init!(
+//!     capsules::pwm::Pwm<'static, 2>,
+//!     capsules::pwm::Pwm::new(
+//!         pwm_pins,
+//!         board_kernel.create_grant(capsules::pwm::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! ```
+
+use core::cell::Cell;
+
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::pwm::PwmPin;
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::Pwm as usize;
+
+#[derive(Default)]
+pub struct App {}
+
+/// The settings of a channel, and the process they belong to.
+struct Channel {
+    owner: OptionalCell<ProcessId>,
+    frequency_hz: Cell<usize>,
+    duty_cycle: Cell<usize>,
+    running: Cell<bool>,
+}
+
+impl Channel {
+    const fn new() -> Channel {
+        Channel {
+            owner: OptionalCell::empty(),
+            frequency_hz: Cell::new(0),
+            duty_cycle: Cell::new(0),
+            running: Cell::new(false),
+        }
+    }
+}
+
+pub struct Pwm<'a, const NUM_PINS: usize> {
+    pins: &'a [&'a dyn PwmPin; NUM_PINS],
+    channels: [Channel; NUM_PINS],
+    apps: Grant<App, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl<'a, const NUM_PINS: usize> Pwm<'a, NUM_PINS> {
+    pub fn new(
+        pins: &'a [&'a dyn PwmPin; NUM_PINS],
+        grant: Grant<App, UpcallCount<0>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> Pwm<'a, NUM_PINS> {
+        const EMPTY: Channel = Channel::new();
+        Pwm {
+            pins,
+            channels: [EMPTY; NUM_PINS],
+            apps: grant,
+        }
+    }
+
+    /// Stops and releases the channels whose owner no longer exists.
+    fn release_dead_owners(&self) {
+        for (channel, state) in self.channels.iter().enumerate() {
+            let dead = state
+                .owner
+                .map_or(false, |owner| self.apps.enter(*owner, |_, _| {}).is_err());
+            if dead {
+                if state.running.get() {
+                    let _ = self.pins[channel].stop();
+                }
+                state.running.set(false);
+                state.frequency_hz.set(0);
+                state.duty_cycle.set(0);
+                state.owner.clear();
+            }
+        }
+    }
+
+    /// Makes `processid` the owner of `channel`, unless another process
+    /// already is.
+    fn claim(&self, channel: usize, processid: ProcessId) -> Result<(), ErrorCode> {
+        let state = &self.channels[channel];
+        match state.owner.extract() {
+            Some(owner) if owner != processid => Err(ErrorCode::RESERVE),
+            _ => {
+                state.owner.set(processid);
+                Ok(())
+            }
+        }
+    }
+
+    /// Restarts `channel` with its new settings, if it is running.
+    fn update(&self, channel: usize) -> Result<(), ErrorCode> {
+        let state = &self.channels[channel];
+        if state.running.get() {
+            self.pins[channel].start(state.frequency_hz.get(), state.duty_cycle.get())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_frequency(&self, channel: usize, frequency_hz: usize) -> Result<(), ErrorCode> {
+        if frequency_hz == 0 || frequency_hz > self.pins[channel].get_maximum_frequency_hz() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.channels[channel].frequency_hz.set(frequency_hz);
+        self.update(channel)
+    }
+
+    fn set_duty_cycle(&self, channel: usize, duty_cycle: usize) -> Result<(), ErrorCode> {
+        if duty_cycle > self.pins[channel].get_maximum_duty_cycle() {
+            return Err(ErrorCode::INVAL);
+        }
+        self.channels[channel].duty_cycle.set(duty_cycle);
+        self.update(channel)
+    }
+
+    fn start(&self, channel: usize) -> Result<(), ErrorCode> {
+        let state = &self.channels[channel];
+        if state.frequency_hz.get() == 0 {
+            return Err(ErrorCode::INVAL);
+        }
+        self.pins[channel].start(state.frequency_hz.get(), state.duty_cycle.get())?;
+        state.running.set(true);
+        Ok(())
+    }
+
+    fn stop(&self, channel: usize) -> Result<(), ErrorCode> {
+        let state = &self.channels[channel];
+        let result = if state.running.get() {
+            self.pins[channel].stop()
+        } else {
+            Ok(())
+        };
+        state.running.set(false);
+        state.owner.clear();
+        result
+    }
+}
+
+impl<'a, const NUM_PINS: usize> SyscallDriver for Pwm<'a, NUM_PINS> {
+    /// Control the PWM channels.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, returns the number of channels.
+    /// - `1`: Set the frequency of channel `data1` to `data2` Hz.
+    /// - `2`: Set the duty cycle of channel `data1` to `data2`.
+    /// - `3`: Start channel `data1`.
+    /// - `4`: Stop channel `data1`.
+    /// - `5`: Get the maximum frequency of channel `data1`.
+    /// - `6`: Get the maximum duty cycle of channel `data1`.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        self.release_dead_owners();
+        if command_num == 0 {
+            return CommandReturn::success_u32(NUM_PINS as u32);
+        }
+        let channel = data1;
+        if channel >= NUM_PINS {
+            return CommandReturn::failure(ErrorCode::INVAL);
+        }
+        match command_num {
+            1..=4 => {
+                let result = self
+                    .claim(channel, processid)
+                    .and_then(|()| match command_num {
+                        1 => self.set_frequency(channel, data2),
+                        2 => self.set_duty_cycle(channel, data2),
+                        3 => self.start(channel),
+                        _ => self.stop(channel),
+                    });
+                CommandReturn::from(result)
+            }
+
+            5 => CommandReturn::success_u32(self.pins[channel].get_maximum_frequency_hz() as u32),
+
+            6 => CommandReturn::success_u32(self.pins[channel].get_maximum_duty_cycle() as u32),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
@@ -1288,6 +1288,14 @@ impl<S: spi::SpiMasterDevice> radio::RadioConfig for RF233<'_, S> {
         }
     }
 
+    fn energy_detect(&self, _duration_us: u32) -> Result<(), ErrorCode> {
+        // The RF233 can measure energy through PHY_ED_LEVEL, but the state
+        // machine driving the SPI bus does not support it yet.
+        Err(ErrorCode::NOSUPPORT)
+    }
+
+    fn set_energy_detect_client(&self, _client: &'static dyn radio::EnergyDetectClient) {}
+
     fn get_address(&self) -> u16 {
         self.addr.get()
     }
@@ -455,9 +455,8 @@ pub struct SynchronousRandom<'a> {
     seed: Cell<u32>,
 }
 
-#[allow(dead_code)]
 impl<'a> SynchronousRandom<'a> {
-    fn new(rgen: &'a dyn Rng<'a>) -> SynchronousRandom {
+    pub fn new(rgen: &'a dyn Rng<'a>) -> SynchronousRandom {
         SynchronousRandom {
             rgen: rgen,
             seed: Cell::new(0),
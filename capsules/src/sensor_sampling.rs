@@ -0,0 +1,735 @@
+//! Samples sensors periodically on behalf of processes, and only wakes a
+//! process when a sample is worth looking at.
+//!
+//! The one-shot sensor drivers (`temperature`, `humidity`, `pressure`, ...)
+//! need a process to wake up for every reading it wants, even when most of
+//! the readings do not interest it. This driver instead reads a sensor at a
+//! period the process picks, and collects the samples into a batch buffer the
+//! process has shared with the kernel. The process is only woken up when:
+//!
+//! * a sample moves across the edge of a threshold window the process has
+//!   set, in either direction,
+//! * a sample differs from the last sample the process was woken up for by
+//!   at least a delta the process has set,
+//! * the batch buffer has filled up, or
+//! * the sensor has failed to be read `FAILURES_REPORTED` times in a row.
+//!
+//! Sensors are added by the board through the `SampledSensor` adapters at the
+//! end of this file, one for each scalar `hil::sensors` trait. An adapter
+//! takes over the client of its sensor, so a sensor given to this driver
+//! should not also be given to a one-shot driver. Samples are in the units of
+//! the underlying trait, as an `i32`.
+//!
+//! Each process can sample one sensor at a time, and several processes can
+//! sample the same or different sensors at different periods. The driver
+//! reads one sensor at a time; processes that are due for the same sensor
+//! share a reading.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Allow
+//!
+//! * ReadWrite 0: The batch buffer. Samples are appended to it as
+//!   little-endian `i32`s, until it is emptied with command 6. A process that
+//!   only wants to be woken up does not need to allow one.
+//!
+//! ### Subscribe
+//!
+//! * 0: called when a sample wakes the process up, with the reason
+//!      (`WAKE_BATCH_FULL`, `WAKE_THRESHOLD` or `WAKE_DELTA`), the number of
+//!      samples in the batch buffer, and the sample itself. When the sensor
+//!      keeps failing, it is called with `WAKE_ERROR`, the number of samples
+//!      in the batch buffer, and the status code of the last failure. The
+//!      process is not told again until a reading has succeeded.
+//!
+//! ### Command
+//!
+//! * 0: driver check. Returns the number of sensors.
+//! * 1: start sampling sensor `arg1` every `arg2` milliseconds. The first
+//!      sample is taken straight away.
+//! * 2: stop sampling.
+//! * 3: set the threshold window to the samples from `arg1` to `arg2`,
+//!      inclusive, both taken as an `i32`.
+//! * 4: clear the threshold window.
+//! * 5: set the delta to `arg1`. A delta of 0 turns delta wake ups off.
+//! * 6: empty the batch buffer. Returns the number of samples that were lost
+//!      since it was last emptied, because the buffer was full or the sensor
+//!      could not be read.
+//!
+//! Command 1 returns `NODEVICE` for sensors that do not exist, and `INVAL` if
+//! the period is zero or longer than the alarm can time. Command 3 returns
+//! `INVAL` if `arg1` is greater than `arg2`.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! # use kernel::static_init;
+//!
+//! let sampled_temperature = static_init!(
+//!     capsules::sensor_sampling::SampledTemperature<'static>,
+//!     capsules::sensor_sampling::SampledTemperature::new(lsm303agr)
+//! );
+//! kernel::hil::sensors::TemperatureDriver::set_client(lsm303agr, sampled_temperature);
+//! let sampled_sensors = static_init!(
+//!     [&'static dyn capsules::sensor_sampling::SampledSensor; 1],
+//!     [sampled_temperature]
+//! );
+//! let sensor_sampling = static_init!(
+//!     capsules::sensor_sampling::SensorSampling<'static, VirtualMuxAlarm<'static, Rtc>>,
+//!     capsules::sensor_sampling::SensorSampling::new(
+//!         sampled_sensors,
+//!         sensor_sampling_alarm,
+//!         board_kernel.create_grant(capsules::sensor_sampling::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! sensor_sampling_alarm.set_alarm_client(sensor_sampling);
+//! sampled_temperature.set_client(sensor_sampling);
+//! ```
+
+use core::cmp::Ordering;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, GrantKernelData, UpcallCount};
+use kernel::hil;
+use kernel::hil::time::{Frequency, Ticks};
+use kernel::processbuffer::{ReadableProcessBuffer, WriteableProcessBuffer};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+/// Syscall driver number.
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::SensorSampling as usize;
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    pub const BATCH: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant
+    pub const COUNT: u8 = 1;
+}
+
+/// The process was woken up because its batch buffer is full.
+pub const WAKE_BATCH_FULL: usize = 0;
+/// The process was woken up because a sample crossed its threshold window.
+pub const WAKE_THRESHOLD: usize = 1;
+/// The process was woken up because a sample moved by at least its delta.
+pub const WAKE_DELTA: usize = 2;
+/// The process was woken up because its sensor keeps failing.
+pub const WAKE_ERROR: usize = 3;
+
+/// The number of failed readings in a row after which a process is woken
+/// up with `WAKE_ERROR`. Fewer failures only count as lost samples.
+pub const FAILURES_REPORTED: usize = 3;
+
+/// The size of a sample in the batch buffer.
+const SAMPLE_LEN: usize = 4;
+
+/// A scalar sensor that `SensorSampling` can read.
+pub trait SampledSensor<'a> {
+    fn set_client(&self, client: &'a dyn SampledSensorClient);
+
+    /// Starts a reading, which is passed to `sample_ready`.
+    fn read(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client for receiving samples from a `SampledSensor`.
+pub trait SampledSensorClient {
+    fn sample_ready(&self, sample: Result<i32, ErrorCode>);
+}
+
+pub struct App<T: Ticks> {
+    /// The sensor the process samples, if any.
+    sensor: Option<usize>,
+    /// The time of the last sample that was due, and the sampling period.
+    reference: T,
+    period: T,
+    /// Whether the process waits for the next reading of its sensor.
+    pending: bool,
+
+    window: Option<(i32, i32)>,
+    /// Where the last sample was, relative to the threshold window.
+    zone: Ordering,
+    delta: u32,
+    /// The last sample the process was woken up for.
+    baseline: Option<i32>,
+
+    /// The number of samples in the batch buffer.
+    queued: usize,
+    lost: usize,
+    /// The number of readings in a row that failed.
+    failures: usize,
+}
+
+impl<T: Ticks> Default for App<T> {
+    fn default() -> App<T> {
+        App {
+            sensor: None,
+            reference: T::from(0),
+            period: T::from(0),
+            pending: false,
+            window: None,
+            zone: Ordering::Equal,
+            delta: 0,
+            baseline: None,
+            queued: 0,
+            lost: 0,
+            failures: 0,
+        }
+    }
+}
+
+impl<T: Ticks> App<T> {
+    fn is_due(&self, now: T) -> bool {
+        !now.within_range(self.reference, self.reference.wrapping_add(self.period))
+    }
+
+    // Adds a sample to the batch buffer, and wakes the process up if the
+    // sample is worth it.
+    fn record(&mut self, kernel_data: &GrantKernelData, sample: i32) {
+        self.failures = 0;
+        let capacity = kernel_data
+            .get_readwrite_processbuffer(rw_allow::BATCH)
+            .map_or(0, |batch| batch.len() / SAMPLE_LEN);
+        let mut filled = false;
+        if self.queued < capacity {
+            let offset = self.queued * SAMPLE_LEN;
+            let _ = kernel_data
+                .get_readwrite_processbuffer(rw_allow::BATCH)
+                .and_then(|batch| {
+                    batch.mut_enter(|batch| {
+                        batch[offset..offset + SAMPLE_LEN].copy_from_slice(&sample.to_le_bytes())
+                    })
+                });
+            self.queued += 1;
+            filled = self.queued == capacity;
+        } else if capacity > 0 {
+            self.lost += 1;
+        }
+
+        if let Some(reason) = self.wake_reason(sample, filled) {
+            kernel_data
+                .schedule_upcall(0, (reason, self.queued, sample as usize))
+                .ok();
+        }
+    }
+
+    // Returns why `sample` wakes the process up, if it does, given whether
+    // it filled the batch buffer.
+    fn wake_reason(&mut self, sample: i32, filled: bool) -> Option<usize> {
+        let zone = self.window.map_or(Ordering::Equal, |(low, high)| {
+            if sample < low {
+                Ordering::Less
+            } else if sample > high {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        });
+        let crossed = zone != self.zone;
+        self.zone = zone;
+
+        let moved = match self.baseline {
+            Some(baseline) => self.delta > 0 && sample.abs_diff(baseline) >= self.delta,
+            None => {
+                self.baseline = Some(sample);
+                false
+            }
+        };
+
+        let reason = if crossed {
+            WAKE_THRESHOLD
+        } else if moved {
+            WAKE_DELTA
+        } else if filled {
+            WAKE_BATCH_FULL
+        } else {
+            return None;
+        };
+        self.baseline = Some(sample);
+        Some(reason)
+    }
+
+    // Counts a failed reading as a lost sample, and wakes the process up if
+    // the sensor keeps failing.
+    fn fail(&mut self, kernel_data: &GrantKernelData, error: ErrorCode) {
+        self.lost += 1;
+        if self.count_failure() {
+            kernel_data
+                .schedule_upcall(0, (WAKE_ERROR, self.queued, into_statuscode(Err(error))))
+                .ok();
+        }
+    }
+
+    // Returns whether the failure just counted is the one to report.
+    fn count_failure(&mut self) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        self.failures == FAILURES_REPORTED
+    }
+}
+
+pub struct SensorSampling<'a, A: hil::time::Alarm<'a>> {
+    sensors: &'a [&'a dyn SampledSensor<'a>],
+    alarm: &'a A,
+    apps: Grant<App<A::Ticks>, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<{ rw_allow::COUNT }>>,
+    /// The sensor being read, if any.
+    reading: OptionalCell<usize>,
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SensorSampling<'a, A> {
+    pub fn new(
+        sensors: &'a [&'a dyn SampledSensor<'a>],
+        alarm: &'a A,
+        grant: Grant<
+            App<A::Ticks>,
+            UpcallCount<1>,
+            AllowRoCount<0>,
+            AllowRwCount<{ rw_allow::COUNT }>,
+        >,
+    ) -> SensorSampling<'a, A> {
+        SensorSampling {
+            sensors,
+            alarm,
+            apps: grant,
+            reading: OptionalCell::empty(),
+        }
+    }
+
+    fn start(
+        &self,
+        sensor: usize,
+        period_ms: usize,
+        processid: ProcessId,
+    ) -> Result<(), ErrorCode> {
+        if sensor >= self.sensors.len() {
+            return Err(ErrorCode::NODEVICE);
+        }
+        let period = (period_ms as u64)
+            .checked_mul(A::Frequency::frequency() as u64)
+            .map(|ticks| A::Ticks::from_or_max(ticks / 1000))
+            .filter(|period| period.into_u32() > 0 && *period <= A::Ticks::half_max_value())
+            .ok_or(ErrorCode::INVAL)?;
+
+        self.apps
+            .enter(processid, |app, _| {
+                app.sensor = Some(sensor);
+                app.reference = self.alarm.now();
+                app.period = period;
+                app.pending = true;
+                app.zone = Ordering::Equal;
+                app.baseline = None;
+                app.failures = 0;
+            })
+            .map_err(ErrorCode::from)?;
+        self.start_next_read();
+        self.schedule_alarm();
+        Ok(())
+    }
+
+    fn stop(&self, processid: ProcessId) -> Result<(), ErrorCode> {
+        self.apps
+            .enter(processid, |app, _| {
+                app.sensor = None;
+                app.pending = false;
+            })
+            .map_err(ErrorCode::from)?;
+        self.schedule_alarm();
+        Ok(())
+    }
+
+    // Sets the alarm for the next process that is due, or disarms it if no
+    // process is sampling.
+    fn schedule_alarm(&self) {
+        let now = self.alarm.now();
+        let mut next: Option<A::Ticks> = None;
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.sensor.is_some() {
+                    let remaining = if app.is_due(now) {
+                        A::Ticks::from(0)
+                    } else {
+                        app.reference.wrapping_add(app.period).wrapping_sub(now)
+                    };
+                    next = Some(next.map_or(remaining, |next| next.min(remaining)));
+                }
+            });
+        }
+        match next {
+            Some(dt) => self.alarm.set_alarm(now, dt),
+            None => {
+                let _ = self.alarm.disarm();
+            }
+        }
+    }
+
+    // Starts reading a sensor that processes wait for, unless a sensor is
+    // already being read.
+    fn start_next_read(&self) {
+        while self.reading.is_none() {
+            let mut next = None;
+            for cntr in self.apps.iter() {
+                cntr.enter(|app, _| {
+                    if app.pending {
+                        next = next.or(app.sensor);
+                    }
+                });
+            }
+            let sensor = match next {
+                Some(sensor) => sensor,
+                None => return,
+            };
+
+            // The sensor may report its reading before `read` returns
+            self.reading.set(sensor);
+            if let Err(error) = self.sensors[sensor].read() {
+                self.reading.clear();
+                self.deliver(sensor, Err(error));
+            }
+        }
+    }
+
+    // Gives a reading of `sensor` to the processes waiting for it.
+    fn deliver(&self, sensor: usize, sample: Result<i32, ErrorCode>) {
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, kernel_data| {
+                if app.pending && app.sensor == Some(sensor) {
+                    app.pending = false;
+                    match sample {
+                        Ok(sample) => app.record(kernel_data, sample),
+                        Err(error) => app.fail(kernel_data, error),
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> hil::time::AlarmClient for SensorSampling<'a, A> {
+    fn alarm(&self) {
+        let now = self.alarm.now();
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if app.sensor.is_some() && app.is_due(now) {
+                    app.pending = true;
+                    app.reference = app.reference.wrapping_add(app.period);
+                    if app.is_due(now) {
+                        // Too far behind to catch up, so the samples in
+                        // between are skipped
+                        app.reference = now;
+                    }
+                }
+            });
+        }
+        self.start_next_read();
+        self.schedule_alarm();
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SampledSensorClient for SensorSampling<'a, A> {
+    fn sample_ready(&self, sample: Result<i32, ErrorCode>) {
+        if let Some(sensor) = self.reading.take() {
+            self.deliver(sensor, sample);
+            self.start_next_read();
+        }
+    }
+}
+
+impl<'a, A: hil::time::Alarm<'a>> SyscallDriver for SensorSampling<'a, A> {
+    /// Control sensor sampling.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, returns the number of sensors.
+    /// - `1`: Start sampling sensor `data1` every `data2` milliseconds.
+    /// - `2`: Stop sampling.
+    /// - `3`: Set the threshold window to `data1..=data2`.
+    /// - `4`: Clear the threshold window.
+    /// - `5`: Set the delta to `data1`.
+    /// - `6`: Empty the batch buffer, returns the number of lost samples.
+    fn command(
+        &self,
+        command_num: usize,
+        data1: usize,
+        data2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success_u32(self.sensors.len() as u32),
+
+            1 => CommandReturn::from(self.start(data1, data2, processid)),
+
+            2 => CommandReturn::from(self.stop(processid)),
+
+            3 => {
+                let (low, high) = (data1 as i32, data2 as i32);
+                if low > high {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| app.window = Some((low, high)))
+                    .map_err(ErrorCode::from);
+                CommandReturn::from(result)
+            }
+
+            4 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        app.window = None;
+                        app.zone = Ordering::Equal;
+                    })
+                    .map_err(ErrorCode::from);
+                CommandReturn::from(result)
+            }
+
+            5 => {
+                let result = self
+                    .apps
+                    .enter(processid, |app, _| app.delta = data1 as u32)
+                    .map_err(ErrorCode::from);
+                CommandReturn::from(result)
+            }
+
+            6 => self
+                .apps
+                .enter(processid, |app, _| {
+                    app.queued = 0;
+                    let lost = core::mem::replace(&mut app.lost, 0);
+                    CommandReturn::success_u32(lost as u32)
+                })
+                .unwrap_or_else(|err| CommandReturn::failure(err.into())),
+
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
+
+/// Samples a `hil::sensors::TemperatureDriver`, in hundredths of degrees
+/// centigrade.
+pub struct SampledTemperature<'a> {
+    driver: &'a dyn hil::sensors::TemperatureDriver<'a>,
+    client: OptionalCell<&'a dyn SampledSensorClient>,
+}
+
+impl<'a> SampledTemperature<'a> {
+    pub fn new(driver: &'a dyn hil::sensors::TemperatureDriver<'a>) -> SampledTemperature<'a> {
+        SampledTemperature {
+            driver,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a> SampledSensor<'a> for SampledTemperature<'a> {
+    fn set_client(&self, client: &'a dyn SampledSensorClient) {
+        self.client.set(client);
+    }
+
+    fn read(&self) -> Result<(), ErrorCode> {
+        self.driver.read_temperature()
+    }
+}
+
+impl hil::sensors::TemperatureClient for SampledTemperature<'_> {
+    fn callback(&self, value: usize) {
+        // Temperatures below zero are passed as the bits of an `i32`
+        self.client
+            .map(|client| client.sample_ready(Ok(value as i32)));
+    }
+}
+
+/// Samples a `hil::sensors::HumidityDriver`, in hundredths of percent.
+pub struct SampledHumidity<'a> {
+    driver: &'a dyn hil::sensors::HumidityDriver<'a>,
+    client: OptionalCell<&'a dyn SampledSensorClient>,
+}
+
+impl<'a> SampledHumidity<'a> {
+    pub fn new(driver: &'a dyn hil::sensors::HumidityDriver<'a>) -> SampledHumidity<'a> {
+        SampledHumidity {
+            driver,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a> SampledSensor<'a> for SampledHumidity<'a> {
+    fn set_client(&self, client: &'a dyn SampledSensorClient) {
+        self.client.set(client);
+    }
+
+    fn read(&self) -> Result<(), ErrorCode> {
+        self.driver.read_humidity()
+    }
+}
+
+impl hil::sensors::HumidityClient for SampledHumidity<'_> {
+    fn callback(&self, value: usize) {
+        self.client
+            .map(|client| client.sample_ready(Ok(value as i32)));
+    }
+}
+
+/// Samples a `hil::sensors::PressureDriver`, in pascals.
+pub struct SampledPressure<'a> {
+    driver: &'a dyn hil::sensors::PressureDriver<'a>,
+    client: OptionalCell<&'a dyn SampledSensorClient>,
+}
+
+impl<'a> SampledPressure<'a> {
+    pub fn new(driver: &'a dyn hil::sensors::PressureDriver<'a>) -> SampledPressure<'a> {
+        SampledPressure {
+            driver,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a> SampledSensor<'a> for SampledPressure<'a> {
+    fn set_client(&self, client: &'a dyn SampledSensorClient) {
+        self.client.set(client);
+    }
+
+    fn read(&self) -> Result<(), ErrorCode> {
+        self.driver.read_pressure()
+    }
+}
+
+impl hil::sensors::PressureClient for SampledPressure<'_> {
+    fn callback(&self, pressure: Result<u32, ErrorCode>) {
+        self.client
+            .map(|client| client.sample_ready(pressure.map(|pressure| pressure as i32)));
+    }
+}
+
+/// Samples a `hil::sensors::AmbientLight` sensor, in lux.
+pub struct SampledAmbientLight<'a> {
+    driver: &'a dyn hil::sensors::AmbientLight<'a>,
+    client: OptionalCell<&'a dyn SampledSensorClient>,
+}
+
+impl<'a> SampledAmbientLight<'a> {
+    pub fn new(driver: &'a dyn hil::sensors::AmbientLight<'a>) -> SampledAmbientLight<'a> {
+        SampledAmbientLight {
+            driver,
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a> SampledSensor<'a> for SampledAmbientLight<'a> {
+    fn set_client(&self, client: &'a dyn SampledSensorClient) {
+        self.client.set(client);
+    }
+
+    fn read(&self) -> Result<(), ErrorCode> {
+        self.driver.read_light_intensity()
+    }
+}
+
+impl hil::sensors::AmbientLightClient for SampledAmbientLight<'_> {
+    fn callback(&self, lux: usize) {
+        self.client
+            .map(|client| client.sample_ready(Ok(lux as i32)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel::hil::time::Ticks32;
+
+    fn app(window: Option<(i32, i32)>, delta: u32) -> App<Ticks32> {
+        App {
+            window,
+            delta,
+            ..App::default()
+        }
+    }
+
+    #[test]
+    fn crossing_the_window_wakes_up_both_ways() {
+        let mut app = app(Some((10, 20)), 0);
+        assert_eq!(app.wake_reason(15, false), None);
+        assert_eq!(app.wake_reason(25, false), Some(WAKE_THRESHOLD));
+        assert_eq!(app.wake_reason(30, false), None);
+        assert_eq!(app.wake_reason(20, false), Some(WAKE_THRESHOLD));
+        assert_eq!(app.wake_reason(5, false), Some(WAKE_THRESHOLD));
+        assert_eq!(app.wake_reason(15, false), Some(WAKE_THRESHOLD));
+    }
+
+    #[test]
+    fn moving_by_the_delta_wakes_up() {
+        let mut app = app(None, 5);
+        assert_eq!(app.wake_reason(100, false), None);
+        assert_eq!(app.wake_reason(104, false), None);
+        assert_eq!(app.wake_reason(96, false), None);
+        assert_eq!(app.wake_reason(95, false), Some(WAKE_DELTA));
+        assert_eq!(app.wake_reason(-100, false), Some(WAKE_DELTA));
+    }
+
+    #[test]
+    fn the_baseline_only_moves_on_wake_up() {
+        // Small steps add up until they reach the delta.
+        let mut app = app(None, 5);
+        assert_eq!(app.wake_reason(0, false), None);
+        assert_eq!(app.wake_reason(3, false), None);
+        assert_eq!(app.wake_reason(6, false), Some(WAKE_DELTA));
+        assert_eq!(app.wake_reason(9, false), None);
+        assert_eq!(app.wake_reason(11, false), Some(WAKE_DELTA));
+    }
+
+    #[test]
+    fn no_delta_never_wakes_up() {
+        let mut app = app(None, 0);
+        assert_eq!(app.wake_reason(0, false), None);
+        assert_eq!(app.wake_reason(i32::MAX, false), None);
+        assert_eq!(app.wake_reason(i32::MIN, false), None);
+    }
+
+    #[test]
+    fn a_full_batch_wakes_up_last() {
+        let mut app = app(Some((10, 20)), 5);
+        assert_eq!(app.wake_reason(15, true), Some(WAKE_BATCH_FULL));
+        assert_eq!(app.wake_reason(25, true), Some(WAKE_THRESHOLD));
+        assert_eq!(app.wake_reason(35, true), Some(WAKE_DELTA));
+        assert_eq!(app.wake_reason(36, false), None);
+    }
+
+    #[test]
+    fn only_persistent_failures_are_reported() {
+        let mut app = app(None, 0);
+        for _ in 1..FAILURES_REPORTED {
+            assert!(!app.count_failure());
+        }
+        assert!(app.count_failure());
+        assert!(!app.count_failure());
+        assert!(!app.count_failure());
+    }
+
+    #[test]
+    fn samples_are_due_once_per_period() {
+        let app = App::<Ticks32> {
+            reference: 1000.into(),
+            period: 100.into(),
+            ..App::default()
+        };
+        assert!(!app.is_due(1000.into()));
+        assert!(!app.is_due(1099.into()));
+        assert!(app.is_due(1100.into()));
+
+        let wrapped = App::<Ticks32> {
+            reference: u32::MAX.into(),
+            period: 100.into(),
+            ..App::default()
+        };
+        assert!(!wrapped.is_due(50.into()));
+        assert!(wrapped.is_due(99.into()));
+    }
+}
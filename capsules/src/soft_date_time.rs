@@ -0,0 +1,201 @@
+//! Software date and time over an alarm's counter.
+//!
+//! `SoftDateTime` implements the `hil::date_time` traits for boards whose
+//! chip has no calendar real-time clock. It counts whole seconds of the
+//! counter behind a `time::Alarm`, which can be a virtual alarm, and moves its
+//! reference point forward on every alarm so that the counter never wraps
+//! unnoticed. The clock starts at the Unix epoch at boot, and keeps time as
+//! long as the counter runs, including while the chip sleeps if the counter
+//! is in an always-on domain.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let soft_date_time = static_init!(
+//!     capsules::soft_date_time::SoftDateTime<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::soft_date_time::SoftDateTime::new(date_time_alarm, dynamic_deferred_caller)
+//! );
+//! date_time_alarm.set_alarm_client(soft_date_time);
+//! soft_date_time.initialize_callback_handle(
+//!     dynamic_deferred_caller.register(soft_date_time).unwrap(),
+//! );
+//! soft_date_time.start();
+//! ```
+
+use core::cell::Cell;
+
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::date_time::{
+    DateTime, DateTimeAlarm, DateTimeAlarmClient, DateTimeClient, DateTimeValues,
+};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Get,
+    Set,
+}
+
+pub struct SoftDateTime<'a, A: time::Alarm<'a>> {
+    alarm: &'a A,
+    /// Seconds since the Unix epoch at `reference`.
+    seconds: Cell<u64>,
+    reference: Cell<A::Ticks>,
+    calendar_alarm: Cell<Option<u64>>,
+    operation: Cell<Option<Operation>>,
+    client: OptionalCell<&'a dyn DateTimeClient>,
+    alarm_client: OptionalCell<&'a dyn DateTimeAlarmClient>,
+    deferred_caller: &'a DynamicDeferredCall,
+    handle: OptionalCell<DeferredCallHandle>,
+}
+
+impl<'a, A: time::Alarm<'a>> SoftDateTime<'a, A> {
+    pub fn new(alarm: &'a A, deferred_caller: &'a DynamicDeferredCall) -> SoftDateTime<'a, A> {
+        SoftDateTime {
+            alarm: alarm,
+            seconds: Cell::new(0),
+            reference: Cell::new(A::Ticks::from(0)),
+            calendar_alarm: Cell::new(None),
+            operation: Cell::new(None),
+            client: OptionalCell::empty(),
+            alarm_client: OptionalCell::empty(),
+            deferred_caller: deferred_caller,
+            handle: OptionalCell::empty(),
+        }
+    }
+
+    pub fn initialize_callback_handle(&self, handle: DeferredCallHandle) {
+        self.handle.replace(handle);
+    }
+
+    /// Starts the clock at the Unix epoch.
+    pub fn start(&self) {
+        self.reference.set(self.alarm.now());
+        self.rearm();
+    }
+
+    /// Moves the reference point forward by the whole seconds that elapsed
+    /// since it was set.
+    fn advance(&self) {
+        let reference = self.reference.get();
+        let elapsed = self.alarm.now().wrapping_sub(reference);
+        let seconds = self.alarm.ticks_to_seconds(elapsed);
+        self.reference
+            .set(reference.wrapping_add(self.alarm.ticks_from_seconds(seconds)));
+        self.seconds.set(self.seconds.get() + seconds as u64);
+    }
+
+    /// Arms the alarm for the calendar alarm if it is near enough, and
+    /// otherwise for as late as the counter allows.
+    fn rearm(&self) {
+        let max_seconds = self.alarm.ticks_to_seconds(A::Ticks::half_max_value());
+        let remaining = self
+            .calendar_alarm
+            .get()
+            .map(|alarm| alarm.saturating_sub(self.seconds.get()));
+        match remaining {
+            Some(remaining) if remaining < max_seconds as u64 => {
+                let dt = self.alarm.ticks_from_seconds(remaining as u32);
+                self.alarm.set_alarm(self.reference.get(), dt);
+            }
+            _ => self
+                .alarm
+                .set_alarm(self.alarm.now(), A::Ticks::half_max_value()),
+        }
+    }
+
+    fn start_operation(&self, operation: Operation) -> Result<(), ErrorCode> {
+        if self.operation.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.handle.map_or(Err(ErrorCode::OFF), |handle| {
+            self.operation.set(Some(operation));
+            self.deferred_caller.set(*handle);
+            Ok(())
+        })
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for SoftDateTime<'a, A> {
+    fn alarm(&self) {
+        self.advance();
+        let fired = self
+            .calendar_alarm
+            .get()
+            .map_or(false, |alarm| alarm <= self.seconds.get());
+        if fired {
+            self.calendar_alarm.set(None);
+        }
+        self.rearm();
+        if fired {
+            self.alarm_client.map(|client| client.alarm_fired());
+        }
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> DateTime<'a> for SoftDateTime<'a, A> {
+    fn get_date_time(&self) -> Result<(), ErrorCode> {
+        self.start_operation(Operation::Get)
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        let seconds = date_time.to_unix_seconds()?;
+        self.start_operation(Operation::Set)?;
+        self.reference.set(self.alarm.now());
+        self.seconds.set(seconds);
+        self.rearm();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn DateTimeClient) {
+        self.client.set(client);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> DateTimeAlarm<'a> for SoftDateTime<'a, A> {
+    fn set_alarm(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        self.calendar_alarm.set(Some(date_time.to_unix_seconds()?));
+        self.advance();
+        self.rearm();
+        Ok(())
+    }
+
+    fn get_alarm(&self) -> Option<DateTimeValues> {
+        self.calendar_alarm
+            .get()
+            .map(DateTimeValues::from_unix_seconds)
+    }
+
+    fn disarm_alarm(&self) -> Result<(), ErrorCode> {
+        self.calendar_alarm.set(None);
+        self.advance();
+        self.rearm();
+        Ok(())
+    }
+
+    fn set_alarm_client(&self, client: &'a dyn DateTimeAlarmClient) {
+        self.alarm_client.set(client);
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> DynamicDeferredCallClient for SoftDateTime<'a, A> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        match self.operation.take() {
+            Some(Operation::Get) => {
+                self.advance();
+                let date_time = DateTimeValues::from_unix_seconds(self.seconds.get());
+                self.client
+                    .map(|client| client.get_date_time_done(Ok(date_time)));
+            }
+            Some(Operation::Set) => {
+                self.client.map(|client| client.set_date_time_done(Ok(())));
+            }
+            None => {}
+        }
+    }
+}
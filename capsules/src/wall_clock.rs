@@ -0,0 +1,257 @@
+//! Wall-clock (UTC) time service.
+//!
+//! `WallClock` keeps UTC time as an offset over a monotonic alarm. The time
+//! can be set by a process, by the kernel, or by a `TimeSource` such as the
+//! SNTP client in `net::sntp`. Until then, the time is unknown. The reference
+//! point is moved forward by whole seconds from time to time, so that the
+//! elapsed ticks never wrap and no rounding error accumulates; the accuracy is
+//! that of the underlying alarm.
+//!
+//! Other capsules can hold a reference to the `WallClock` and call `now_ms()`
+//! to timestamp events.
+//!
+//! Userspace Interface
+//! -------------------
+//!
+//! ### Subscribe
+//!
+//! * 0: a time synchronization requested by the process completed. The
+//!   arguments are a status code and, on success, the new UTC time in seconds
+//!   since the Unix epoch and the milliseconds within the second.
+//!
+//! ### Command
+//!
+//! * 0: driver check.
+//! * 1: get the current UTC time. Returns the seconds since the Unix epoch
+//!   and the milliseconds within the second, or `FAIL` if the time has not
+//!   been set.
+//! * 2: set the current UTC time to `arg1` seconds since the Unix epoch plus
+//!   `arg2` milliseconds. As the time is shared by all processes, setting it
+//!   returns `NOSUPPORT` unless the board allows processes to set the time.
+//! * 3: synchronize with the time source. Returns `NOSUPPORT` if the board has
+//!   no time source. If a synchronization is already in progress, the process
+//!   is notified when it completes.
+//!
+//! Usage
+//! -----
+//!
+//! ```rust
+//! let wall_clock = static_init!(
+//!     capsules::wall_clock::WallClock<'static, VirtualMuxAlarm<'static, Ast>>,
+//!     capsules::wall_clock::WallClock::new(
+//!         wall_clock_alarm,
+//!         false,
+//!         board_kernel.create_grant(capsules::wall_clock::DRIVER_NUM, &grant_cap)
+//!     )
+//! );
+//! wall_clock_alarm.set_alarm_client(wall_clock);
+//! wall_clock.set_time_source(sntp);
+//! sntp.set_client(wall_clock);
+//! ```
+
+use core::cell::Cell;
+
+use kernel::errorcode::into_statuscode;
+use kernel::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use kernel::hil::time::{self, ConvertTicks, Ticks};
+use kernel::syscall::{CommandReturn, SyscallDriver};
+use kernel::utilities::cells::OptionalCell;
+use kernel::{ErrorCode, ProcessId};
+
+use crate::driver;
+pub const DRIVER_NUM: usize = driver::NUM::WallClock as usize;
+
+/// A source of UTC time, such as an SNTP client.
+pub trait TimeSource<'a> {
+    fn set_client(&self, client: &'a dyn TimeSourceClient);
+
+    /// Requests the current time. The client's `time_received` is called with
+    /// the result. Returns `BUSY` if a request is already in progress.
+    fn request_time(&self) -> Result<(), ErrorCode>;
+}
+
+pub trait TimeSourceClient {
+    /// Called when a `TimeSource` request completes, with the UTC time in
+    /// milliseconds since the Unix epoch at the moment of the call.
+    fn time_received(&self, result: Result<u64, ErrorCode>);
+}
+
+#[derive(Default)]
+pub struct App {
+    sync_pending: bool,
+}
+
+pub struct WallClock<'a, A: time::Alarm<'a>> {
+    alarm: &'a A,
+    /// The UTC time in milliseconds at `reference`, once the time is set.
+    utc_ms: Cell<Option<u64>>,
+    reference: Cell<A::Ticks>,
+    source: OptionalCell<&'a dyn TimeSource<'a>>,
+    syncing: Cell<bool>,
+    /// Whether processes may set the time with command 2.
+    userspace_set: bool,
+    apps: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+}
+
+impl<'a, A: time::Alarm<'a>> WallClock<'a, A> {
+    pub fn new(
+        alarm: &'a A,
+        userspace_set: bool,
+        grant: Grant<App, UpcallCount<1>, AllowRoCount<0>, AllowRwCount<0>>,
+    ) -> WallClock<'a, A> {
+        WallClock {
+            alarm: alarm,
+            utc_ms: Cell::new(None),
+            reference: Cell::new(A::Ticks::from(0)),
+            source: OptionalCell::empty(),
+            syncing: Cell::new(false),
+            userspace_set: userspace_set,
+            apps: grant,
+        }
+    }
+
+    pub fn set_time_source(&self, source: &'a dyn TimeSource<'a>) {
+        self.source.set(source);
+    }
+
+    /// Returns the current UTC time in milliseconds since the Unix epoch, or
+    /// `None` if the time has not been set.
+    pub fn now_ms(&self) -> Option<u64> {
+        let utc_ms = self.utc_ms.get()?;
+        let elapsed = self.alarm.now().wrapping_sub(self.reference.get());
+        Some(utc_ms + self.alarm.ticks_to_ms(elapsed) as u64)
+    }
+
+    /// Sets the current UTC time, in milliseconds since the Unix epoch.
+    pub fn set_ms(&self, utc_ms: u64) {
+        let now = self.alarm.now();
+        self.reference.set(now);
+        self.utc_ms.set(Some(utc_ms));
+        self.alarm.set_alarm(now, A::Ticks::half_max_value());
+    }
+
+    /// Synchronizes with the time source. Returns `NOSUPPORT` if there is no
+    /// time source. If a synchronization is already in progress, this
+    /// succeeds without starting another one.
+    pub fn sync(&self) -> Result<(), ErrorCode> {
+        if self.syncing.get() {
+            return Ok(());
+        }
+        self.source
+            .map_or(Err(ErrorCode::NOSUPPORT), |source| source.request_time())?;
+        self.syncing.set(true);
+        Ok(())
+    }
+
+    /// Moves the reference point forward by the whole seconds that elapsed
+    /// since it was set.
+    fn advance(&self) {
+        if let Some(utc_ms) = self.utc_ms.get() {
+            let reference = self.reference.get();
+            let elapsed = self.alarm.now().wrapping_sub(reference);
+            let seconds = self.alarm.ticks_to_seconds(elapsed);
+            self.reference
+                .set(reference.wrapping_add(self.alarm.ticks_from_seconds(seconds)));
+            self.utc_ms.set(Some(utc_ms + seconds as u64 * 1000));
+            self.alarm
+                .set_alarm(self.alarm.now(), A::Ticks::half_max_value());
+        }
+    }
+
+    fn split(utc_ms: u64) -> (u32, u32) {
+        ((utc_ms / 1000) as u32, (utc_ms % 1000) as u32)
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> time::AlarmClient for WallClock<'a, A> {
+    fn alarm(&self) {
+        self.advance();
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> TimeSourceClient for WallClock<'a, A> {
+    fn time_received(&self, result: Result<u64, ErrorCode>) {
+        self.syncing.set(false);
+        if let Ok(utc_ms) = result {
+            self.set_ms(utc_ms);
+        }
+        let (seconds, ms) = result.map_or((0, 0), Self::split);
+        self.apps.each(|_, app, kernel_data| {
+            if app.sync_pending {
+                app.sync_pending = false;
+                kernel_data
+                    .schedule_upcall(
+                        0,
+                        (
+                            into_statuscode(result.map(|_| ())),
+                            seconds as usize,
+                            ms as usize,
+                        ),
+                    )
+                    .ok();
+            }
+        });
+    }
+}
+
+impl<'a, A: time::Alarm<'a>> SyscallDriver for WallClock<'a, A> {
+    /// Wall clock control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Get the UTC time as seconds since the Unix epoch and
+    ///        milliseconds. Returns FAIL if the time is not set.
+    /// - `2`: Set the UTC time to `arg1` seconds and `arg2` milliseconds.
+    ///        Returns NOSUPPORT if the board does not allow processes to set
+    ///        the time.
+    /// - `3`: Synchronize with the time source, with an upcall when done.
+    fn command(
+        &self,
+        command_num: usize,
+        arg1: usize,
+        arg2: usize,
+        processid: ProcessId,
+    ) -> CommandReturn {
+        match command_num {
+            0 => CommandReturn::success(),
+            1 => match self.now_ms() {
+                Some(utc_ms) => {
+                    let (seconds, ms) = Self::split(utc_ms);
+                    CommandReturn::success_u32_u32(seconds, ms)
+                }
+                None => CommandReturn::failure(ErrorCode::FAIL),
+            },
+            2 => {
+                if !self.userspace_set {
+                    return CommandReturn::failure(ErrorCode::NOSUPPORT);
+                }
+                if arg2 >= 1000 {
+                    return CommandReturn::failure(ErrorCode::INVAL);
+                }
+                self.set_ms(arg1 as u64 * 1000 + arg2 as u64);
+                CommandReturn::success()
+            }
+            3 => {
+                let res = self
+                    .apps
+                    .enter(processid, |app, _| {
+                        app.sync_pending = true;
+                    })
+                    .map_err(ErrorCode::from)
+                    .and_then(|()| self.sync());
+                if res.is_err() {
+                    let _ = self.apps.enter(processid, |app, _| {
+                        app.sync_pending = false;
+                    });
+                }
+                CommandReturn::from(res)
+            }
+            _ => CommandReturn::failure(ErrorCode::NOSUPPORT),
+        }
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), kernel::process::Error> {
+        self.apps.enter(processid, |_, _| {})
+    }
+}
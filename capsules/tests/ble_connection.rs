@@ -0,0 +1,818 @@
+//! Connects a simulated central to the BLE link layer and GATT server, over a
+//! simulated radio and alarm.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+
+use capsules::ble_connection::{AttServer, BleConnection, BUF_LEN};
+use capsules::ble_gatt::{properties, GattClient, GattServer};
+
+use kernel::hil::ble_advertising::{BleConnectionDriver, ConnectionClient, RadioChannel};
+use kernel::hil::time::*;
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use common::{leak, leak_buf, FakeAlarm};
+
+const ADDRESS: [u8; 6] = [0xf0, 0x11, 0x22, 0x33, 0x44, 0xf5];
+const CENTRAL: [u8; 6] = [0x01, 0x02, 0x03, 0x04, 0x05, 0xc6];
+const ACCESS_ADDRESS: u32 = 0x8e89_bed6 ^ 0x1234_5678;
+const CRC_INIT: u32 = 0x55_5555;
+/// 30 ms
+const INTERVAL: u16 = 24;
+/// 1 s
+const TIMEOUT: u16 = 100;
+/// Channels 9, 10, 21 and 22 are not used.
+const CHANNEL_MAP: [u8; 5] = [0xff, 0xf9, 0x9f, 0xff, 0x1f];
+const HOP: u8 = 7;
+
+const LLID_CONTINUATION: u8 = 0b01;
+const LLID_START: u8 = 0b10;
+const LLID_CONTROL: u8 = 0b11;
+
+type Link<'a> = BleConnection<'a, SimRadio<'a>, FakeAlarm<'a>>;
+
+#[derive(Clone, Copy, Debug)]
+enum Operation {
+    Advertise {
+        len: usize,
+        channel: RadioChannel,
+    },
+    Event {
+        channel: RadioChannel,
+        access_address: u32,
+        crc_init: u32,
+        timeout_us: u32,
+    },
+}
+
+/// Holds the buffer of the operation the link layer started until the test
+/// plays the central's part in it.
+struct SimRadio<'a> {
+    buf: TakeCell<'static, [u8]>,
+    operation: Cell<Option<Operation>>,
+    client: OptionalCell<&'a dyn ConnectionClient>,
+}
+
+impl SimRadio<'_> {
+    fn new() -> Self {
+        Self {
+            buf: TakeCell::empty(),
+            operation: Cell::new(None),
+            client: OptionalCell::empty(),
+        }
+    }
+}
+
+impl<'a> BleConnectionDriver<'a> for SimRadio<'a> {
+    fn transmit_connectable_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        channel: RadioChannel,
+    ) {
+        assert!(self.operation.get().is_none());
+        self.buf.replace(buf);
+        self.operation
+            .set(Some(Operation::Advertise { len, channel }));
+    }
+
+    fn connection_event(
+        &self,
+        buf: &'static mut [u8],
+        channel: RadioChannel,
+        access_address: u32,
+        crc_init: u32,
+        timeout_us: u32,
+    ) {
+        assert!(self.operation.get().is_none());
+        self.buf.replace(buf);
+        self.operation.set(Some(Operation::Event {
+            channel,
+            access_address,
+            crc_init,
+            timeout_us,
+        }));
+    }
+
+    fn set_connection_client(&self, client: &'a dyn ConnectionClient) {
+        self.client.set(client);
+    }
+}
+
+#[derive(Default)]
+struct Recorder {
+    written: RefCell<Vec<(u16, Vec<u8>)>>,
+    connected: Cell<Option<bool>>,
+}
+
+impl GattClient for Recorder {
+    fn written(&self, handle: u16, value: &[u8]) {
+        self.written.borrow_mut().push((handle, value.to_vec()));
+    }
+
+    fn connection_changed(&self, connected: bool) {
+        self.connected.set(Some(connected));
+    }
+}
+
+/// Records what the link layer tells the layer above it.
+#[derive(Default)]
+struct Events {
+    connected: Cell<usize>,
+    disconnected: Cell<usize>,
+}
+
+impl AttServer for Events {
+    fn att_received(&self, _pdu: &[u8], _response: &mut [u8]) -> usize {
+        0
+    }
+
+    fn att_sent(&self) {}
+
+    fn connected(&self) {
+        self.connected.set(self.connected.get() + 1);
+    }
+
+    fn disconnected(&self) {
+        self.disconnected.set(self.disconnected.get() + 1);
+    }
+}
+
+/// The central's side of a connection.
+struct Central {
+    anchor_us: u32,
+    interval_us: u32,
+    channel_map: [u8; 5],
+    last_unmapped_channel: u8,
+    sn: bool,
+    nesn: bool,
+    event_counter: u16,
+    update: Option<(u32, u16)>,
+}
+
+impl Central {
+    fn is_used(&self, channel: u8) -> bool {
+        self.channel_map[channel as usize / 8] & (1 << (channel % 8)) != 0
+    }
+
+    /// Channel selection algorithm #1.
+    fn next_channel(&mut self) -> u8 {
+        let unmapped = (self.last_unmapped_channel + HOP) % 37;
+        self.last_unmapped_channel = unmapped;
+        if self.is_used(unmapped) {
+            return unmapped;
+        }
+        let used: Vec<u8> = (0..37).filter(|c| self.is_used(*c)).collect();
+        used[unmapped as usize % used.len()]
+    }
+
+    /// Moves to the next connection event.
+    fn advance(&mut self) {
+        self.anchor_us += self.interval_us;
+        self.event_counter += 1;
+        if let Some((interval_us, instant)) = self.update {
+            if self.event_counter == instant {
+                self.interval_us = interval_us;
+                self.update = None;
+            }
+        }
+    }
+}
+
+fn data_channel(index: u8) -> RadioChannel {
+    // Data channel indices match the order of the enum's data channels
+    let channels = [
+        RadioChannel::DataChannel0,
+        RadioChannel::DataChannel1,
+        RadioChannel::DataChannel2,
+        RadioChannel::DataChannel3,
+        RadioChannel::DataChannel4,
+        RadioChannel::DataChannel5,
+        RadioChannel::DataChannel6,
+        RadioChannel::DataChannel7,
+        RadioChannel::DataChannel8,
+        RadioChannel::DataChannel9,
+        RadioChannel::DataChannel10,
+        RadioChannel::DataChannel11,
+        RadioChannel::DataChannel12,
+        RadioChannel::DataChannel13,
+        RadioChannel::DataChannel14,
+        RadioChannel::DataChannel15,
+        RadioChannel::DataChannel16,
+        RadioChannel::DataChannel17,
+        RadioChannel::DataChannel18,
+        RadioChannel::DataChannel19,
+        RadioChannel::DataChannel20,
+        RadioChannel::DataChannel21,
+        RadioChannel::DataChannel22,
+        RadioChannel::DataChannel23,
+        RadioChannel::DataChannel24,
+        RadioChannel::DataChannel25,
+        RadioChannel::DataChannel26,
+        RadioChannel::DataChannel27,
+        RadioChannel::DataChannel28,
+        RadioChannel::DataChannel29,
+        RadioChannel::DataChannel30,
+        RadioChannel::DataChannel31,
+        RadioChannel::DataChannel32,
+        RadioChannel::DataChannel33,
+        RadioChannel::DataChannel34,
+        RadioChannel::DataChannel35,
+        RadioChannel::DataChannel36,
+    ];
+    channels[index as usize]
+}
+
+/// Waits for the next advertisement, and returns its channel and PDU.
+fn next_advertisement(radio: &SimRadio, alarm: &FakeAlarm) -> (RadioChannel, Vec<u8>) {
+    if radio.operation.get().is_none() {
+        alarm.trigger();
+    }
+    match radio.operation.get() {
+        Some(Operation::Advertise { len, channel }) => {
+            (channel, radio.buf.map(|buf| buf[..len].to_vec()).unwrap())
+        }
+        operation => panic!("expected an advertisement, not {:?}", operation),
+    }
+}
+
+/// Ends the current advertisement, after sending `request` if there is one.
+/// Returns the advertiser's response.
+fn finish_advertisement(radio: &SimRadio, request: Option<&[u8]>) -> Option<Vec<u8>> {
+    radio.operation.set(None);
+    let buf = radio.buf.take().unwrap();
+    let response = request.and_then(|request| {
+        buf[..request.len()].copy_from_slice(request);
+        let len = radio
+            .client
+            .map(|client| client.advertising_request(buf, request.len()))
+            .unwrap();
+        if len > 0 {
+            Some(buf[..len].to_vec())
+        } else {
+            None
+        }
+    });
+    radio
+        .client
+        .map(|client| client.advertisement_done(buf, Ok(())));
+    response
+}
+
+fn connect_ind(win_offset: u16) -> Vec<u8> {
+    let mut pdu = vec![0x05 | 0x40 | 0x80, 34];
+    pdu.extend_from_slice(&CENTRAL);
+    pdu.extend_from_slice(&ADDRESS);
+    pdu.extend_from_slice(&ACCESS_ADDRESS.to_le_bytes());
+    pdu.extend_from_slice(&CRC_INIT.to_le_bytes()[..3]);
+    // Transmit window size of 2.5 ms
+    pdu.push(2);
+    pdu.extend_from_slice(&win_offset.to_le_bytes());
+    pdu.extend_from_slice(&INTERVAL.to_le_bytes());
+    pdu.extend_from_slice(&0u16.to_le_bytes());
+    pdu.extend_from_slice(&TIMEOUT.to_le_bytes());
+    pdu.extend_from_slice(&CHANNEL_MAP);
+    // 50 ppm sleep clock accuracy
+    pdu.push(HOP | 5 << 5);
+    pdu
+}
+
+/// Sends a connection request after the first advertisement.
+fn connect(radio: &SimRadio, alarm: &FakeAlarm) -> Central {
+    let (channel, _) = next_advertisement(radio, alarm);
+    assert_eq!(channel, RadioChannel::AdvertisingChannel37);
+    let now = alarm.now().into_u32();
+    assert_eq!(finish_advertisement(radio, Some(&connect_ind(1))), None);
+    Central {
+        // The first packet is sent at the start of the transmit window
+        anchor_us: now + 1250 + 1250,
+        interval_us: INTERVAL as u32 * 1250,
+        channel_map: CHANNEL_MAP,
+        last_unmapped_channel: 0,
+        sn: false,
+        nesn: false,
+        event_counter: 0,
+        update: None,
+    }
+}
+
+/// Plays the central's part in the next connection event, sending a PDU and
+/// returning the peripheral's response.
+fn exchange(
+    radio: &SimRadio,
+    alarm: &FakeAlarm,
+    central: &mut Central,
+    llid: u8,
+    payload: &[u8],
+) -> (u8, Vec<u8>) {
+    alarm.trigger();
+    let channel = central.next_channel();
+    match radio.operation.take() {
+        Some(Operation::Event {
+            channel: event_channel,
+            access_address,
+            crc_init,
+            timeout_us,
+        }) => {
+            assert_eq!(event_channel, data_channel(channel));
+            assert_eq!(access_address, ACCESS_ADDRESS);
+            assert_eq!(crc_init, CRC_INIT);
+            // The peripheral listens when the central transmits
+            let start = alarm.now().into_u32();
+            assert!(start <= central.anchor_us);
+            assert!(central.anchor_us <= start + timeout_us);
+        }
+        operation => panic!("expected a connection event, not {:?}", operation),
+    }
+
+    let buf = radio.buf.take().unwrap();
+    buf[0] = llid | (central.nesn as u8) << 2 | (central.sn as u8) << 3;
+    buf[1] = payload.len() as u8;
+    buf[2..2 + payload.len()].copy_from_slice(payload);
+    let airtime = (8 + 2 + payload.len() as u32) * 8;
+    alarm.set_now(central.anchor_us + airtime);
+    let len = radio
+        .client
+        .map(|client| client.data_received(buf, 2 + payload.len(), true))
+        .unwrap();
+    assert!(len >= 2);
+
+    // Every packet is received, so each one is acknowledged
+    let header = buf[0];
+    assert_eq!(header & 0x04 != 0, !central.sn);
+    central.sn = !central.sn;
+    assert_eq!(header & 0x08 != 0, central.nesn);
+    central.nesn = !central.nesn;
+    let response = (header & 0b11, buf[2..len].to_vec());
+    assert_eq!(buf[1] as usize, len - 2);
+
+    radio
+        .client
+        .map(|client| client.connection_event_done(buf, Ok(())));
+    central.advance();
+    response
+}
+
+/// Lets the next connection event pass without the central transmitting.
+fn miss_event(radio: &SimRadio, alarm: &FakeAlarm, central: &mut Central) {
+    alarm.trigger();
+    central.next_channel();
+    assert!(matches!(
+        radio.operation.take(),
+        Some(Operation::Event { .. })
+    ));
+    let buf = radio.buf.take().unwrap();
+    radio
+        .client
+        .map(|client| client.connection_event_done(buf, Err(ErrorCode::NOACK)));
+    central.advance();
+}
+
+fn l2cap(cid: u16, data: &[u8]) -> Vec<u8> {
+    let mut frame = (data.len() as u16).to_le_bytes().to_vec();
+    frame.extend_from_slice(&cid.to_le_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Sends an ATT request and returns the response.
+fn att(radio: &SimRadio, alarm: &FakeAlarm, central: &mut Central, request: &[u8]) -> Vec<u8> {
+    let (llid, frame) = exchange(radio, alarm, central, LLID_START, &l2cap(4, request));
+    assert_eq!(llid, LLID_START);
+    assert_eq!(frame[2..4], [4, 0]);
+    assert_eq!(frame[0] as usize, frame.len() - 4);
+    frame[4..].to_vec()
+}
+
+#[test]
+fn advertises_and_answers_scan_requests() {
+    let radio = SimRadio::new();
+    let alarm = FakeAlarm::new(leak(Cell::new(1_000)));
+    let link: Link = BleConnection::new(&radio, &alarm, leak_buf(BUF_LEN), ADDRESS);
+    radio.set_connection_client(&link);
+    alarm.set_alarm_client(&link);
+
+    let adv_data = [0x02, 0x01, 0x06];
+    let scan_rsp_data = [0x05, 0x09, b'T', b'o', b'c', b'k'];
+    link.set_advertising_data(&adv_data).unwrap();
+    link.set_scan_response_data(&scan_rsp_data).unwrap();
+    assert_eq!(link.set_advertising_data(&[0; 32]), Err(ErrorCode::SIZE));
+    link.start_advertising(100);
+
+    let mut expected = vec![0x40, 9];
+    expected.extend_from_slice(&ADDRESS);
+    expected.extend_from_slice(&adv_data);
+    for channel in &[
+        RadioChannel::AdvertisingChannel37,
+        RadioChannel::AdvertisingChannel38,
+        RadioChannel::AdvertisingChannel39,
+    ] {
+        assert_eq!(
+            next_advertisement(&radio, &alarm),
+            (*channel, expected.clone())
+        );
+        let response = if *channel == RadioChannel::AdvertisingChannel38 {
+            let mut scan_req = vec![0x03 | 0x40 | 0x80, 12];
+            scan_req.extend_from_slice(&CENTRAL);
+            scan_req.extend_from_slice(&ADDRESS);
+            finish_advertisement(&radio, Some(&scan_req))
+        } else {
+            // Requests for other advertisers are ignored
+            let mut scan_req = vec![0x03, 12];
+            scan_req.extend_from_slice(&CENTRAL);
+            scan_req.extend_from_slice(&CENTRAL);
+            finish_advertisement(&radio, Some(&scan_req))
+        };
+        if *channel == RadioChannel::AdvertisingChannel38 {
+            let mut scan_rsp = vec![0x04 | 0x40, 12];
+            scan_rsp.extend_from_slice(&ADDRESS);
+            scan_rsp.extend_from_slice(&scan_rsp_data);
+            assert_eq!(response, Some(scan_rsp));
+        } else {
+            assert_eq!(response, None);
+        }
+    }
+
+    // The next advertising event is one interval and up to 10 ms later
+    assert!(alarm.is_armed());
+    let delay = alarm.get_alarm().wrapping_sub(alarm.now()).into_u32();
+    assert!((100_000..=110_000).contains(&delay));
+    assert_eq!(
+        next_advertisement(&radio, &alarm).0,
+        RadioChannel::AdvertisingChannel37
+    );
+
+    // Stopping takes effect once the radio is done
+    link.stop();
+    finish_advertisement(&radio, None);
+    assert!(!alarm.is_armed());
+    assert!(radio.operation.get().is_none());
+}
+
+#[test]
+fn connects_and_hops_channels() {
+    let radio = SimRadio::new();
+    let alarm = FakeAlarm::new(leak(Cell::new(1_000)));
+    let link: Link = BleConnection::new(&radio, &alarm, leak_buf(BUF_LEN), ADDRESS);
+    let events = Events::default();
+    radio.set_connection_client(&link);
+    alarm.set_alarm_client(&link);
+    link.set_att_server(&events);
+    link.start_advertising(100);
+
+    let mut central = connect(&radio, &alarm);
+    assert!(link.is_connected());
+    assert_eq!(events.connected.get(), 1);
+
+    // The first event starts just before the transmit window, which is
+    // 2.5 ms after the request
+    assert_eq!(central.anchor_us - alarm.get_alarm().into_u32(), 16);
+
+    // Channels follow the hop sequence, remapping unused channels
+    let mut channels = vec![];
+    for _ in 0..40 {
+        let channel = central.last_unmapped_channel;
+        let (llid, payload) = exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+        assert_eq!((llid, payload), (LLID_CONTINUATION, vec![]));
+        channels.push((channel + HOP) % 37);
+        assert!(alarm.is_armed());
+    }
+    assert!(channels.contains(&9));
+
+    // Once synchronized, the peripheral wakes up just before the anchor
+    // point: 100 ppm of 30 ms, plus 16 us
+    let wake = alarm.get_alarm().into_u32();
+    assert_eq!(central.anchor_us - wake, 3 + 16);
+    assert_eq!(events.disconnected.get(), 0);
+}
+
+#[test]
+fn serves_gatt() {
+    let radio = SimRadio::new();
+    let alarm = FakeAlarm::new(leak(Cell::new(1_000)));
+    let link: Link = BleConnection::new(&radio, &alarm, leak_buf(BUF_LEN), ADDRESS);
+    let gatt = GattServer::new(b"Tock");
+    let recorder = Recorder::default();
+    radio.set_connection_client(&link);
+    alarm.set_alarm_client(&link);
+    gatt.set_bearer(&link);
+    gatt.set_client(&recorder);
+    link.set_att_server(&gatt);
+
+    assert_eq!(gatt.add_characteristic(0xfff1, 0), Err(ErrorCode::INVAL));
+    assert_eq!(gatt.add_service(0xfff0), Ok(6));
+    let value = gatt
+        .add_characteristic(
+            0xfff1,
+            properties::READ | properties::WRITE | properties::NOTIFY,
+        )
+        .unwrap();
+    assert_eq!(value, 8);
+    assert_eq!(gatt.add_service(0xfff8), Ok(10));
+    let command = gatt
+        .add_characteristic(0xfff9, properties::WRITE_WITHOUT_RESPONSE)
+        .unwrap();
+    assert_eq!(command, 12);
+    gatt.set_value(value, b"hi").unwrap();
+
+    link.start_advertising(100);
+    let mut central = connect(&radio, &alarm);
+    assert_eq!(recorder.connected.get(), Some(true));
+
+    // Exchange MTU
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x02, 247, 0]),
+        [0x03, 23, 0]
+    );
+    // Discover the primary services
+    assert_eq!(
+        att(
+            &radio,
+            &alarm,
+            &mut central,
+            &[0x10, 1, 0, 0xff, 0xff, 0x00, 0x28]
+        ),
+        [0x11, 6, 1, 0, 5, 0, 0x00, 0x18, 6, 0, 9, 0, 0xf0, 0xff, 10, 0, 12, 0, 0xf8, 0xff]
+    );
+    assert_eq!(
+        att(
+            &radio,
+            &alarm,
+            &mut central,
+            &[0x10, 13, 0, 0xff, 0xff, 0x00, 0x28]
+        ),
+        [0x01, 0x10, 13, 0, 0x0a]
+    );
+    assert_eq!(
+        att(
+            &radio,
+            &alarm,
+            &mut central,
+            &[0x06, 1, 0, 0xff, 0xff, 0x00, 0x28, 0xf8, 0xff]
+        ),
+        [0x07, 10, 0, 12, 0]
+    );
+    // Discover the characteristics of the first service
+    assert_eq!(
+        att(
+            &radio,
+            &alarm,
+            &mut central,
+            &[0x08, 6, 0, 9, 0, 0x03, 0x28]
+        ),
+        [0x09, 7, 7, 0, 0x1a, 8, 0, 0xf1, 0xff]
+    );
+    // and its descriptors
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x04, 9, 0, 9, 0]),
+        [0x05, 1, 9, 0, 0x02, 0x29]
+    );
+    // Read the device name and the value
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0a, 3, 0]),
+        [0x0b, b'T', b'o', b'c', b'k']
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0c, 3, 0, 2, 0]),
+        [0x0d, b'c', b'k']
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0a, 8, 0]),
+        [0x0b, b'h', b'i']
+    );
+    // The write-only characteristic cannot be read or written with a
+    // response
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0a, 12, 0]),
+        [0x01, 0x0a, 12, 0, 0x02]
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x12, 12, 0, 1]),
+        [0x01, 0x12, 12, 0, 0x03]
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0a, 40, 0]),
+        [0x01, 0x0a, 40, 0, 0x01]
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x20]),
+        [0x01, 0x20, 0, 0, 0x06]
+    );
+
+    // Write the value
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x12, 8, 0, 1, 2, 3]),
+        [0x13]
+    );
+    // Commands have no response
+    let (llid, payload) = exchange(
+        &radio,
+        &alarm,
+        &mut central,
+        LLID_START,
+        &l2cap(4, &[0x52, 12, 0, 7]),
+    );
+    assert_eq!((llid, payload), (LLID_CONTINUATION, vec![]));
+    assert_eq!(
+        *recorder.written.borrow(),
+        vec![(8, vec![1, 2, 3]), (12, vec![7])]
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0a, 8, 0]),
+        [0x0b, 1, 2, 3]
+    );
+
+    // Changes are only notified once the central enables notifications
+    gatt.set_value(value, b"a").unwrap();
+    let (llid, _) = exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    assert_eq!(llid, LLID_CONTINUATION);
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x12, 9, 0, 1, 0]),
+        [0x13]
+    );
+    assert_eq!(
+        att(&radio, &alarm, &mut central, &[0x0a, 9, 0]),
+        [0x0b, 1, 0]
+    );
+    gatt.set_value(value, b"b").unwrap();
+    gatt.set_value(value, b"c").unwrap();
+    // The second change is notified once the first notification is received
+    for expected in &[b'b', b'c'] {
+        let (llid, frame) = exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+        assert_eq!(llid, LLID_START);
+        assert_eq!(frame, l2cap(4, &[0x1b, 8, 0, *expected]));
+    }
+    let (llid, _) = exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    assert_eq!(llid, LLID_CONTINUATION);
+    assert_eq!(gatt.set_value(value, &[0; 21]), Err(ErrorCode::SIZE));
+    assert_eq!(gatt.set_value(7, b"x"), Err(ErrorCode::INVAL));
+
+    // The central terminates the connection
+    let (llid, _) = exchange(&radio, &alarm, &mut central, LLID_CONTROL, &[0x02, 0x13]);
+    assert_eq!(llid, LLID_CONTINUATION);
+    assert!(!link.is_connected());
+    assert_eq!(recorder.connected.get(), Some(false));
+    // Advertising resumes
+    assert_eq!(
+        next_advertisement(&radio, &alarm).0,
+        RadioChannel::AdvertisingChannel37
+    );
+}
+
+#[test]
+fn handles_control_procedures() {
+    let radio = SimRadio::new();
+    let alarm = FakeAlarm::new(leak(Cell::new(1_000)));
+    let link: Link = BleConnection::new(&radio, &alarm, leak_buf(BUF_LEN), ADDRESS);
+    let events = Events::default();
+    radio.set_connection_client(&link);
+    alarm.set_alarm_client(&link);
+    link.set_att_server(&events);
+    link.start_advertising(100);
+    let mut central = connect(&radio, &alarm);
+
+    assert_eq!(
+        exchange(
+            &radio,
+            &alarm,
+            &mut central,
+            LLID_CONTROL,
+            &[0x0c, 0x09, 0x59, 0x00, 0x01, 0x00]
+        ),
+        (LLID_CONTROL, vec![0x0c, 0x08, 0xff, 0xff, 0, 0])
+    );
+    assert_eq!(
+        exchange(
+            &radio,
+            &alarm,
+            &mut central,
+            LLID_CONTROL,
+            &[0x08, 0xff, 0, 0, 0, 0, 0, 0, 0]
+        ),
+        (LLID_CONTROL, vec![0x09, 0, 0, 0, 0, 0, 0, 0, 0])
+    );
+    assert_eq!(
+        exchange(&radio, &alarm, &mut central, LLID_CONTROL, &[0x12]),
+        (LLID_CONTROL, vec![0x13])
+    );
+    let mut enc_req = vec![0x03];
+    enc_req.extend_from_slice(&[0; 22]);
+    assert_eq!(
+        exchange(&radio, &alarm, &mut central, LLID_CONTROL, &enc_req),
+        (LLID_CONTROL, vec![0x0d, 0x1a])
+    );
+    assert_eq!(
+        exchange(&radio, &alarm, &mut central, LLID_CONTROL, &[0x30]),
+        (LLID_CONTROL, vec![0x07, 0x30])
+    );
+    // Pairing is not supported
+    assert_eq!(
+        exchange(
+            &radio,
+            &alarm,
+            &mut central,
+            LLID_START,
+            &l2cap(6, &[0x01, 0x03, 0x00, 0x01, 0x10, 0x07, 0x07])
+        ),
+        (LLID_START, l2cap(6, &[0x05, 0x05]))
+    );
+    // and neither are L2CAP signaling requests
+    assert_eq!(
+        exchange(
+            &radio,
+            &alarm,
+            &mut central,
+            LLID_START,
+            &l2cap(
+                5,
+                &[0x14, 0x09, 0x0a, 0x00, 0x80, 0, 0x40, 0, 23, 0, 23, 0, 1, 0]
+            )
+        ),
+        (LLID_START, l2cap(5, &[0x01, 0x09, 2, 0, 0, 0]))
+    );
+
+    // Update the connection to a 50 ms interval in 3 events, with the first
+    // anchor point 2.5 ms into the transmit window
+    let instant = central.event_counter + 3;
+    let mut update = vec![0x00, 4];
+    update.extend_from_slice(&2u16.to_le_bytes());
+    update.extend_from_slice(&40u16.to_le_bytes());
+    update.extend_from_slice(&0u16.to_le_bytes());
+    update.extend_from_slice(&200u16.to_le_bytes());
+    update.extend_from_slice(&instant.to_le_bytes());
+    exchange(&radio, &alarm, &mut central, LLID_CONTROL, &update);
+    central.update = Some((50_000, instant));
+    while central.update.is_some() {
+        exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    }
+    // The central transmits in the window that starts 2.5 ms after the old
+    // anchor point
+    central.anchor_us += 2500 + 2 * 1250;
+    for _ in 0..5 {
+        exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    }
+    assert_eq!(central.interval_us, 50_000);
+
+    // Switch to four channels
+    let instant = central.event_counter + 2;
+    let mut map_ind = vec![0x01, 0x0f, 0, 0, 0, 0];
+    map_ind.extend_from_slice(&instant.to_le_bytes());
+    exchange(&radio, &alarm, &mut central, LLID_CONTROL, &map_ind);
+    exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    central.channel_map = [0x0f, 0, 0, 0, 0];
+    for _ in 0..8 {
+        exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    }
+
+    // The peripheral terminates the connection once the central acknowledges
+    link.stop();
+    assert_eq!(
+        exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]),
+        (LLID_CONTROL, vec![0x02, 0x13])
+    );
+    assert!(link.is_connected());
+    exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    assert!(!link.is_connected());
+    assert_eq!(events.disconnected.get(), 1);
+    // Advertising was stopped too
+    assert!(!alarm.is_armed());
+}
+
+#[test]
+fn drops_lost_connections() {
+    let radio = SimRadio::new();
+    let alarm = FakeAlarm::new(leak(Cell::new(1_000)));
+    let link: Link = BleConnection::new(&radio, &alarm, leak_buf(BUF_LEN), ADDRESS);
+    let events = Events::default();
+    radio.set_connection_client(&link);
+    alarm.set_alarm_client(&link);
+    link.set_att_server(&events);
+    link.start_advertising(100);
+
+    // A connection that is never established is dropped after six events
+    let mut central = connect(&radio, &alarm);
+    for _ in 0..6 {
+        assert!(link.is_connected());
+        miss_event(&radio, &alarm, &mut central);
+    }
+    assert!(!link.is_connected());
+    assert_eq!(events.disconnected.get(), 1);
+
+    // An established one is dropped after the supervision timeout
+    let mut central = connect(&radio, &alarm);
+    exchange(&radio, &alarm, &mut central, LLID_CONTINUATION, &[]);
+    let mut missed = 0;
+    while link.is_connected() {
+        miss_event(&radio, &alarm, &mut central);
+        missed += 1;
+    }
+    // 1 s at 30 ms intervals
+    assert_eq!(missed, 33);
+    assert_eq!(events.disconnected.get(), 2);
+    assert_eq!(
+        next_advertisement(&radio, &alarm).0,
+        RadioChannel::AdvertisingChannel37
+    );
+}
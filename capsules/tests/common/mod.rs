@@ -0,0 +1,271 @@
+//! Fakes shared by the integration tests: alarms on a simulated clock, a
+//! random number generator and a CCM engine, and a network of simulated
+//! 802.15.4 radios to run the MAC and the layers above it on.
+//!
+//! Each test binary only uses some of them.
+#![allow(dead_code)]
+
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+
+use capsules::ieee802154::csma::CsmaMac;
+use capsules::ieee802154::mac::Mac;
+use capsules::ieee802154::sim_radio::{SimMedium, SimRadio};
+
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::rng::Random;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::*;
+use kernel::utilities::cells::OptionalCell;
+use kernel::ErrorCode;
+
+pub fn leak<T>(t: T) -> &'static T {
+    Box::leak(Box::new(t))
+}
+
+pub fn leak_buf(len: usize) -> &'static mut [u8] {
+    Box::leak(vec![0; len].into_boxed_slice())
+}
+
+/// An alarm on a clock that may be shared with other alarms, so that several
+/// nodes or layers see the same time.
+pub struct FakeAlarm<'a, F = Freq1MHz> {
+    clock: &'static Cell<u32>,
+    reference: Cell<Ticks32>,
+    dt: Cell<Ticks32>,
+    armed: Cell<bool>,
+    client: OptionalCell<&'a dyn AlarmClient>,
+    _frequency: PhantomData<F>,
+}
+
+impl<F> FakeAlarm<'_, F> {
+    pub fn new(clock: &'static Cell<u32>) -> Self {
+        Self {
+            clock,
+            reference: Cell::new(0u32.into()),
+            dt: Cell::new(0u32.into()),
+            armed: Cell::new(false),
+            client: OptionalCell::empty(),
+            _frequency: PhantomData,
+        }
+    }
+
+    pub fn expiry(&self) -> Option<u32> {
+        if self.armed.get() {
+            Some(self.reference.get().wrapping_add(self.dt.get()).into_u32())
+        } else {
+            None
+        }
+    }
+
+    pub fn set_now(&self, now: u32) {
+        self.clock.set(now);
+    }
+
+    /// Fast forwards the clock to the alarm, unless it is already past it,
+    /// and fires it.
+    pub fn trigger(&self) {
+        let expiry = self.expiry().expect("the alarm is not armed");
+        self.armed.set(false);
+        self.clock.set(self.clock.get().max(expiry));
+        self.client.map(|c| c.alarm());
+    }
+}
+
+impl<F: Frequency> Time for FakeAlarm<'_, F> {
+    type Ticks = Ticks32;
+    type Frequency = F;
+
+    fn now(&self) -> Ticks32 {
+        self.clock.get().into()
+    }
+}
+
+impl<'a, F: Frequency> Alarm<'a> for FakeAlarm<'a, F> {
+    fn set_alarm_client(&self, client: &'a dyn AlarmClient) {
+        self.client.set(client);
+    }
+
+    fn set_alarm(&self, reference: Self::Ticks, dt: Self::Ticks) {
+        self.reference.set(reference);
+        self.dt.set(dt);
+        self.armed.set(true);
+    }
+
+    fn get_alarm(&self) -> Self::Ticks {
+        self.reference.get().wrapping_add(self.dt.get())
+    }
+
+    fn disarm(&self) -> Result<(), ErrorCode> {
+        self.armed.set(false);
+        Ok(())
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+
+    fn minimum_dt(&self) -> Self::Ticks {
+        0u32.into()
+    }
+}
+
+/// Counts up, so that backoffs are short but differ between nodes.
+pub struct FakeRandom(pub Cell<u32>);
+
+impl<'a> Random<'a> for FakeRandom {
+    fn initialize(&'a self) {}
+    fn reseed(&self, seed: u32) {
+        self.0.set(seed);
+    }
+    fn random(&self) -> u32 {
+        self.0.set(self.0.get().wrapping_add(1));
+        self.0.get()
+    }
+}
+
+/// Frames are exchanged unsecured, so encryption is never used.
+pub struct FakeCcm;
+
+impl<'a> AES128CCM<'a> for FakeCcm {
+    fn set_client(&'a self, _: &'a dyn CCMClient) {}
+    fn set_key(&self, _: &[u8]) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+    fn set_nonce(&self, _: &[u8]) -> Result<(), ErrorCode> {
+        Ok(())
+    }
+    fn crypt(
+        &self,
+        _: &'static mut [u8],
+        _: usize,
+        _: usize,
+        _: usize,
+        _: usize,
+        _: bool,
+        _: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        panic!("unexpected encryption");
+    }
+}
+
+pub type Medium = SimMedium<'static, FakeAlarm<'static>>;
+pub type Radio = SimRadio<'static, FakeAlarm<'static>>;
+pub type Csma = CsmaMac<'static, Radio, FakeAlarm<'static>>;
+
+/// Simulated radios on one medium, and alarms on the clock they share.
+pub struct Network {
+    pub medium: &'static Medium,
+    pub clock: &'static Cell<u32>,
+    alarms: RefCell<Vec<&'static FakeAlarm<'static>>>,
+}
+
+impl Network {
+    pub fn new() -> Network {
+        let clock = leak(Cell::new(0));
+        let medium_alarm = leak(FakeAlarm::new(clock));
+        let medium = leak(SimMedium::new(medium_alarm));
+        medium_alarm.set_alarm_client(medium);
+        Network {
+            medium,
+            clock,
+            alarms: RefCell::new(vec![medium_alarm]),
+        }
+    }
+
+    /// Returns a new alarm, which is fired when the network runs.
+    pub fn alarm(&self) -> &'static FakeAlarm<'static> {
+        let alarm = leak(FakeAlarm::new(self.clock));
+        self.alarms.borrow_mut().push(alarm);
+        alarm
+    }
+
+    /// Connects a new radio to the medium and turns it on.
+    pub fn radio(&self) -> &'static Radio {
+        let radio = leak(SimRadio::new(self.medium));
+        self.medium.add_radio(radio).unwrap();
+        radio.start().unwrap();
+        radio
+    }
+
+    /// Fires the alarm that expires first, returning whether there was one.
+    pub fn step(&self) -> bool {
+        let next = self
+            .alarms
+            .borrow()
+            .iter()
+            .filter(|alarm| alarm.expiry().is_some())
+            .min_by_key(|alarm| alarm.expiry())
+            .copied();
+        match next {
+            Some(alarm) => {
+                alarm.trigger();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Fires alarms in order until none are left.
+    pub fn run(&self) {
+        for _ in 0..100_000 {
+            if !self.step() {
+                return;
+            }
+        }
+        panic!("the network never went idle at {} us", self.clock.get());
+    }
+
+    /// Fires alarms in order until `done` returns true.
+    pub fn run_until(&self, done: impl Fn() -> bool) {
+        while !done() {
+            assert!(self.step(), "the network went idle first");
+        }
+    }
+
+    /// Fires the alarms that expire in the next `us` microseconds, in order,
+    /// and then moves the clock to the end of that time.
+    pub fn run_for(&self, us: u32) {
+        let end = self.clock.get() + us;
+        loop {
+            let next = self
+                .alarms
+                .borrow()
+                .iter()
+                .filter_map(|alarm| alarm.expiry().map(|expiry| (expiry, *alarm)))
+                .filter(|(expiry, _)| *expiry <= end)
+                .min_by_key(|(expiry, _)| *expiry);
+            match next {
+                Some((_, alarm)) => alarm.trigger(),
+                None => break,
+            }
+        }
+        self.clock.set(end);
+    }
+}
+
+/// A simulated radio with the CSMA-CA MAC on top of it, with the short
+/// address `index + 1` and the long address `[index; 8]`.
+pub struct Node {
+    pub radio: &'static Radio,
+    pub mac: &'static Csma,
+}
+
+impl Node {
+    pub fn new(net: &Network, index: usize, pan: u16) -> Node {
+        let radio = net.radio();
+        radio.set_address(index as u16 + 1);
+        radio.set_address_long([index as u8; 8]);
+        radio.set_pan(pan);
+
+        let alarm = net.alarm();
+        let random = leak(FakeRandom(Cell::new(index as u32 * 7)));
+        let mac = leak(CsmaMac::new(radio, alarm, random));
+        alarm.set_alarm_client(mac);
+        radio.set_transmit_client(mac);
+        radio.set_receive_client(mac, leak_buf(radio::MAX_BUF_SIZE));
+        radio.set_energy_detect_client(mac);
+        mac.initialize(leak_buf(radio::MAX_BUF_SIZE)).unwrap();
+        Node { radio, mac }
+    }
+}
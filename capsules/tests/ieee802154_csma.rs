@@ -0,0 +1,285 @@
+//! Sends frames between nodes running the software CSMA-CA MAC over
+//! simulated radios, on a channel that can be made busy and can lose frames.
+
+mod common;
+
+use std::cell::RefCell;
+
+use capsules::ieee802154::csma::CsmaParams;
+use capsules::ieee802154::mac::{Mac, TxStats};
+use capsules::net::ieee802154::{FrameType, FrameVersion, Header, MacAddress};
+
+use kernel::hil::radio::{self, RadioConfig};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::ErrorCode;
+
+use common::{leak, leak_buf, Csma, Network, Node};
+
+const PAN: u16 = 0xabcd;
+const BROADCAST: u16 = 0xffff;
+const CHANNEL: u8 = 26;
+
+/// Records what the MAC reports to the layer above it.
+struct Client {
+    mac: OptionalCell<&'static Csma>,
+    sent: RefCell<Vec<(bool, Result<(), ErrorCode>)>>,
+    received: RefCell<Vec<Vec<u8>>>,
+    tx_buf: TakeCell<'static, [u8]>,
+}
+
+impl radio::TxClient for Client {
+    fn send_done(&self, buf: &'static mut [u8], acked: bool, result: Result<(), ErrorCode>) {
+        self.tx_buf.replace(buf);
+        self.sent.borrow_mut().push((acked, result));
+    }
+}
+
+impl radio::RxClient for Client {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        frame_len: usize,
+        _crc_valid: bool,
+        _result: Result<(), ErrorCode>,
+    ) {
+        self.received
+            .borrow_mut()
+            .push(buf[radio::PSDU_OFFSET..radio::PSDU_OFFSET + frame_len].to_vec());
+        self.mac.map(move |mac| mac.set_receive_buffer(buf));
+    }
+}
+
+/// A node whose MAC is used directly, recording what it reports.
+struct Station {
+    node: Node,
+    client: &'static Client,
+}
+
+impl Station {
+    fn new(net: &Network, index: usize) -> Station {
+        let node = Node::new(net, index, PAN);
+        let client = leak(Client {
+            mac: OptionalCell::new(node.mac),
+            sent: RefCell::new(Vec::new()),
+            received: RefCell::new(Vec::new()),
+            tx_buf: TakeCell::new(leak_buf(radio::MAX_BUF_SIZE)),
+        });
+        node.mac.set_transmit_client(client);
+        node.mac.set_receive_client(client);
+        Station { node, client }
+    }
+
+    /// Sends a data frame with sequence number `seq` and `payload` to the
+    /// short address `dst`.
+    fn send(&self, dst: u16, seq: u8, payload: &[u8]) {
+        let buf = self.client.tx_buf.take().unwrap();
+        let header = Header {
+            frame_type: FrameType::Data,
+            frame_pending: false,
+            ack_requested: true,
+            version: FrameVersion::V2006,
+            seq: Some(seq),
+            dst_pan: Some(PAN),
+            dst_addr: Some(MacAddress::Short(dst)),
+            src_pan: Some(PAN),
+            src_addr: Some(MacAddress::Short(self.node.radio.get_address())),
+            security: None,
+            header_ies: Default::default(),
+            header_ies_len: 0,
+            payload_ies: Default::default(),
+            payload_ies_len: 0,
+        };
+        let (off, _) = header
+            .encode(&mut buf[radio::PSDU_OFFSET..], true)
+            .done()
+            .unwrap();
+        let start = radio::PSDU_OFFSET + off;
+        buf[start..start + payload.len()].copy_from_slice(payload);
+        let frame_len = off + payload.len();
+        assert!(self.node.mac.transmit(buf, frame_len).is_ok());
+    }
+
+    fn payloads(&self) -> Vec<u8> {
+        self.client
+            .received
+            .borrow()
+            .iter()
+            .map(|frame| *frame.last().unwrap())
+            .collect()
+    }
+
+    fn sent(&self) -> Vec<(bool, Result<(), ErrorCode>)> {
+        self.client.sent.borrow_mut().drain(..).collect()
+    }
+
+    fn transmissions(&self) -> usize {
+        self.node.radio.transmissions()
+    }
+}
+
+fn network(stations: usize) -> (Network, Vec<Station>) {
+    let net = Network::new();
+    let stations = (0..stations).map(|i| Station::new(&net, i)).collect();
+    net.run();
+    (net, stations)
+}
+
+#[test]
+fn unicast_frame_is_acknowledged() {
+    let (net, stations) = network(2);
+    stations[0].send(2, 7, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(true, Ok(()))]);
+    assert_eq!(stations[0].node.mac.last_tx_stats(), TxStats::default());
+    assert_eq!(stations[1].payloads(), vec![0x42]);
+    // The ACK is not passed up
+    assert_eq!(stations[0].payloads(), vec![]);
+}
+
+#[test]
+fn broadcast_frame_is_not_acknowledged() {
+    let (net, stations) = network(3);
+    stations[0].send(BROADCAST, 1, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(false, Ok(()))]);
+    assert_eq!(stations[1].payloads(), vec![0x42]);
+    assert_eq!(stations[2].payloads(), vec![0x42]);
+    assert_eq!(stations[1].transmissions(), 0);
+    assert_eq!(stations[2].transmissions(), 0);
+}
+
+#[test]
+fn frames_for_others_are_not_acknowledged() {
+    let (net, stations) = network(3);
+    stations[0].send(2, 1, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(true, Ok(()))]);
+    assert_eq!(stations[2].payloads(), vec![]);
+    assert_eq!(stations[2].transmissions(), 0);
+}
+
+#[test]
+fn unacknowledged_frame_is_retried() {
+    let (net, stations) = network(1);
+    stations[0].send(2, 1, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(false, Err(ErrorCode::NOACK))]);
+    let max_frame_retries = CsmaParams::default().max_frame_retries;
+    assert_eq!(
+        stations[0].node.mac.last_tx_stats(),
+        TxStats {
+            busy_backoffs: 0,
+            retries: max_frame_retries,
+        }
+    );
+    assert_eq!(stations[0].transmissions(), max_frame_retries as usize + 1);
+}
+
+#[test]
+fn retries_follow_params() {
+    let (net, stations) = network(1);
+    let params = CsmaParams {
+        max_frame_retries: 0,
+        ..CsmaParams::default()
+    };
+    assert_eq!(stations[0].node.mac.set_params(params), Ok(()));
+    stations[0].send(2, 1, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(false, Err(ErrorCode::NOACK))]);
+    assert_eq!(stations[0].transmissions(), 1);
+}
+
+#[test]
+fn lost_ack_causes_retransmission_without_duplicate() {
+    let (net, stations) = network(2);
+    // Node 1 only sends ACKs
+    net.medium.lose_next(stations[1].node.radio, 1);
+    stations[0].send(2, 9, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(true, Ok(()))]);
+    assert_eq!(stations[0].node.mac.last_tx_stats().retries, 1);
+    assert_eq!(stations[0].transmissions(), 2);
+    // Both copies are acknowledged, but only one is passed up
+    assert_eq!(stations[1].transmissions(), 2);
+    assert_eq!(stations[1].payloads(), vec![0x42]);
+
+    // A new frame with another sequence number is passed up
+    stations[0].send(2, 10, &[0x43]);
+    net.run();
+    assert_eq!(stations[1].payloads(), vec![0x42, 0x43]);
+}
+
+#[test]
+fn busy_channel_fails_after_max_backoffs() {
+    let (net, stations) = network(2);
+    net.medium.set_noise(CHANNEL, true);
+    stations[0].send(2, 1, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(false, Err(ErrorCode::BUSY))]);
+    assert_eq!(
+        stations[0].node.mac.last_tx_stats().busy_backoffs,
+        CsmaParams::default().max_csma_backoffs + 1
+    );
+    assert_eq!(stations[0].transmissions(), 0);
+    assert_eq!(stations[1].payloads(), vec![]);
+}
+
+#[test]
+fn channel_clears_during_backoff() {
+    let (net, stations) = network(2);
+    net.medium.set_noise(CHANNEL, true);
+    stations[0].send(2, 1, &[0x42]);
+    // Clear the channel after the first assessment
+    net.run_until(|| stations[0].node.radio.energy_detections() == 1);
+    net.medium.set_noise(CHANNEL, false);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(true, Ok(()))]);
+    assert_eq!(stations[0].node.mac.last_tx_stats().busy_backoffs, 1);
+    assert_eq!(stations[1].payloads(), vec![0x42]);
+}
+
+#[test]
+fn radio_without_energy_detection_still_sends() {
+    let (net, stations) = network(2);
+    stations[0].node.radio.set_energy_detection(false);
+    stations[0].send(2, 1, &[0x42]);
+    net.run();
+
+    assert_eq!(stations[0].sent(), vec![(true, Ok(()))]);
+    assert_eq!(stations[1].payloads(), vec![0x42]);
+}
+
+#[test]
+fn invalid_params_are_rejected() {
+    let (_net, stations) = network(1);
+    let mac = stations[0].node.mac;
+    for params in [
+        CsmaParams {
+            min_be: 6,
+            ..CsmaParams::default()
+        },
+        CsmaParams {
+            max_be: 9,
+            ..CsmaParams::default()
+        },
+        CsmaParams {
+            max_csma_backoffs: 6,
+            ..CsmaParams::default()
+        },
+        CsmaParams {
+            max_frame_retries: 8,
+            ..CsmaParams::default()
+        },
+    ] {
+        assert_eq!(mac.set_params(params), Err(ErrorCode::INVAL));
+    }
+    assert_eq!(mac.params(), CsmaParams::default());
+}
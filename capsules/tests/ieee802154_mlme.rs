@@ -0,0 +1,321 @@
+//! Scans for, joins and leaves a PAN between a coordinator and a device, each
+//! running the 802.15.4 MAC management entity over the framer and an
+//! always-on MAC, on simulated radios.
+
+mod common;
+
+use std::cell::RefCell;
+
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::framer::Framer;
+use capsules::ieee802154::mac::{AwakeMac, Mac};
+use capsules::ieee802154::mlme::{
+    capability, AssociationStatus, DisassociationReason, MacManagement, Mlme, MlmeClient, ScanType,
+};
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::net::ieee802154::MacAddress;
+
+use kernel::hil::radio::{self, RadioConfig, RadioData};
+use kernel::hil::time::Alarm;
+use kernel::ErrorCode;
+
+use common::{leak, leak_buf, FakeAlarm, FakeCcm, Network, Radio};
+
+const PAN: u16 = 0xabcd;
+const PAN_CHANNEL: u8 = 15;
+const HOME_CHANNEL: u8 = 26;
+const COORDINATOR_ADDR_LONG: [u8; 8] = [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17];
+const DEVICE_ADDR_LONG: [u8; 8] = [0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27];
+const ALL_CHANNELS: u32 = 0x07ff_f800;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Event {
+    ScanDone(Result<(), ErrorCode>),
+    AssociateDone(AssociationStatus, u16),
+    Disassociated(DisassociationReason),
+    DeviceAssociated(u16, [u8; 8]),
+    DeviceDisassociated(u16),
+}
+
+#[derive(Default)]
+struct Events(RefCell<Vec<Event>>);
+
+impl Events {
+    fn take(&self) -> Vec<Event> {
+        self.0.borrow_mut().drain(..).collect()
+    }
+}
+
+impl MlmeClient for Events {
+    fn scan_done(&self, result: Result<(), ErrorCode>) {
+        self.0.borrow_mut().push(Event::ScanDone(result));
+    }
+    fn associate_done(&self, status: AssociationStatus, short_addr: u16) {
+        self.0
+            .borrow_mut()
+            .push(Event::AssociateDone(status, short_addr));
+    }
+    fn disassociated(&self, reason: DisassociationReason) {
+        self.0.borrow_mut().push(Event::Disassociated(reason));
+    }
+    fn device_associated(&self, short_addr: u16, addr_long: [u8; 8]) {
+        self.0
+            .borrow_mut()
+            .push(Event::DeviceAssociated(short_addr, addr_long));
+    }
+    fn device_disassociated(&self, short_addr: u16) {
+        self.0
+            .borrow_mut()
+            .push(Event::DeviceDisassociated(short_addr));
+    }
+}
+
+type Management = MacManagement<'static, Radio, FakeAlarm<'static>>;
+
+struct Node {
+    radio: &'static Radio,
+    mlme: &'static Management,
+    events: &'static Events,
+}
+
+impl Node {
+    fn new(net: &Network, addr_long: [u8; 8]) -> Node {
+        let radio = net.radio();
+        radio.set_address(0xffff);
+        radio.set_address_long(addr_long);
+        radio.set_pan(0xffff);
+        radio.set_channel(HOME_CHANNEL).unwrap();
+        let awake_mac = leak(AwakeMac::new(radio));
+        radio.set_transmit_client(awake_mac);
+        radio.set_receive_client(awake_mac, leak_buf(radio::MAX_BUF_SIZE));
+        let framer = leak(Framer::new(awake_mac, leak(FakeCcm)));
+        awake_mac.set_transmit_client(framer);
+        awake_mac.set_receive_client(framer);
+        awake_mac.set_config_client(framer);
+        let mux_mac = leak(MuxMac::new(framer));
+        framer.set_transmit_client(mux_mac);
+        framer.set_receive_client(mux_mac);
+        let mac_user = leak(MacUser::new(mux_mac));
+        mux_mac.add_user(mac_user);
+
+        let alarm = net.alarm();
+        let mlme = leak(MacManagement::new(
+            mac_user,
+            radio,
+            alarm,
+            leak_buf(radio::MAX_BUF_SIZE),
+        ));
+        mac_user.set_transmit_client(mlme);
+        mac_user.set_receive_client(mlme);
+        radio.set_energy_detect_client(mlme);
+        alarm.set_alarm_client(mlme);
+        let events = leak(Events::default());
+        mlme.set_client(events);
+        Node {
+            radio,
+            mlme,
+            events,
+        }
+    }
+}
+
+/// A coordinator and a device in radio range of each other.
+struct Pan {
+    net: Network,
+    coordinator: Node,
+    device: Node,
+}
+
+impl Pan {
+    fn new() -> Pan {
+        let net = Network::new();
+        let coordinator = Node::new(&net, COORDINATOR_ADDR_LONG);
+        let device = Node::new(&net, DEVICE_ADDR_LONG);
+        net.run();
+        Pan {
+            net,
+            coordinator,
+            device,
+        }
+    }
+
+    fn run(&self) {
+        self.net.run();
+    }
+
+    fn start_coordinator(&self, association_permit: bool) {
+        assert_eq!(
+            self.coordinator
+                .mlme
+                .start_coordinator(PAN, PAN_CHANNEL, association_permit),
+            Ok(())
+        );
+        self.run();
+    }
+
+    fn associate(&self) {
+        assert_eq!(
+            self.device.mlme.associate(
+                PAN_CHANNEL,
+                PAN,
+                MacAddress::Short(0),
+                capability::ALLOCATE_ADDRESS
+            ),
+            Ok(())
+        );
+        self.run();
+    }
+}
+
+#[test]
+fn energy_detection_scan_measures_each_channel() {
+    let pan = Pan::new();
+    pan.net.medium.set_noise(PAN_CHANNEL, true);
+    let channels = (1 << 11) | (1 << PAN_CHANNEL) | (1 << 20);
+    assert_eq!(
+        pan.device.mlme.scan(ScanType::EnergyDetect, channels, 0),
+        Ok(())
+    );
+    pan.run();
+
+    assert_eq!(pan.device.events.take(), vec![Event::ScanDone(Ok(()))]);
+    assert_eq!(pan.device.mlme.energy_level(11), Some(0));
+    assert_eq!(pan.device.mlme.energy_level(PAN_CHANNEL), Some(0xff));
+    assert_eq!(pan.device.mlme.energy_level(20), Some(0));
+    assert_eq!(pan.device.mlme.energy_level(12), None);
+    assert_eq!(pan.device.radio.get_channel(), HOME_CHANNEL);
+}
+
+#[test]
+fn scan_rejects_invalid_channels() {
+    let pan = Pan::new();
+    assert_eq!(
+        pan.device.mlme.scan(ScanType::Active, 1 << 10, 0),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(
+        pan.device.mlme.scan(ScanType::Active, 0, 0),
+        Err(ErrorCode::INVAL)
+    );
+}
+
+#[test]
+fn active_scan_finds_coordinator() {
+    let pan = Pan::new();
+    pan.start_coordinator(true);
+
+    assert_eq!(
+        pan.device.mlme.scan(ScanType::Active, ALL_CHANNELS, 2),
+        Ok(())
+    );
+    pan.run();
+
+    assert_eq!(pan.device.events.take(), vec![Event::ScanDone(Ok(()))]);
+    let descriptor = pan.device.mlme.pan_descriptor(0).unwrap();
+    assert_eq!(descriptor.channel, PAN_CHANNEL);
+    assert_eq!(descriptor.pan, PAN);
+    assert_eq!(descriptor.coordinator, MacAddress::Short(0));
+    assert!(descriptor.association_permit());
+    assert!(descriptor.pan_coordinator());
+    assert_eq!(pan.device.mlme.pan_descriptor(1), None);
+    assert_eq!(pan.device.radio.get_channel(), HOME_CHANNEL);
+}
+
+#[test]
+fn passive_scan_only_hears_beacons() {
+    let pan = Pan::new();
+    pan.start_coordinator(true);
+
+    // No one asks the coordinator for a beacon, so none is heard
+    assert_eq!(
+        pan.device.mlme.scan(ScanType::Passive, ALL_CHANNELS, 0),
+        Ok(())
+    );
+    pan.run();
+
+    assert_eq!(pan.device.events.take(), vec![Event::ScanDone(Ok(()))]);
+    assert_eq!(pan.device.mlme.pan_descriptor(0), None);
+}
+
+#[test]
+fn association_assigns_short_address() {
+    let pan = Pan::new();
+    pan.start_coordinator(true);
+    pan.associate();
+
+    assert_eq!(
+        pan.device.events.take(),
+        vec![Event::AssociateDone(AssociationStatus::Success, 1)]
+    );
+    assert_eq!(
+        pan.coordinator.events.take(),
+        vec![Event::DeviceAssociated(1, DEVICE_ADDR_LONG)]
+    );
+    assert_eq!(pan.device.radio.get_address(), 1);
+    assert_eq!(pan.device.radio.get_pan(), PAN);
+    assert_eq!(pan.device.radio.get_channel(), PAN_CHANNEL);
+
+    // Associating twice is refused
+    assert_eq!(
+        pan.device
+            .mlme
+            .associate(PAN_CHANNEL, PAN, MacAddress::Short(0), 0),
+        Err(ErrorCode::ALREADY)
+    );
+}
+
+#[test]
+fn association_denied_without_permit() {
+    let pan = Pan::new();
+    pan.start_coordinator(false);
+    pan.associate();
+
+    assert_eq!(
+        pan.device.events.take(),
+        vec![Event::AssociateDone(
+            AssociationStatus::PanAccessDenied,
+            0xffff
+        )]
+    );
+    assert_eq!(pan.coordinator.events.take(), vec![]);
+}
+
+#[test]
+fn association_times_out_without_coordinator() {
+    let pan = Pan::new();
+    pan.associate();
+
+    assert_eq!(
+        pan.device.events.take(),
+        vec![Event::AssociateDone(AssociationStatus::NoData, 0xffff)]
+    );
+}
+
+#[test]
+fn disassociation_notifies_coordinator() {
+    let pan = Pan::new();
+    pan.start_coordinator(true);
+    pan.associate();
+    pan.device.events.take();
+    pan.coordinator.events.take();
+
+    assert_eq!(pan.device.mlme.disassociate(), Ok(()));
+    pan.run();
+
+    assert_eq!(
+        pan.device.events.take(),
+        vec![Event::Disassociated(DisassociationReason::DeviceRequest)]
+    );
+    assert_eq!(
+        pan.coordinator.events.take(),
+        vec![Event::DeviceDisassociated(1)]
+    );
+    assert_eq!(pan.device.radio.get_address(), 0xffff);
+
+    // The address is free again for the next device to join
+    pan.associate();
+    assert_eq!(
+        pan.device.events.take(),
+        vec![Event::AssociateDone(AssociationStatus::Success, 1)]
+    );
+}
@@ -0,0 +1,572 @@
+//! Sends UDP datagrams between nodes running the whole 6LoWPAN stack, from
+//! `UDPSendStruct` down to `CsmaMac`, over simulated radios: between
+//! neighbors by their link-local addresses, and across a mesh routed by RPL.
+//!
+//! This test lives outside of the crate because the UDP port table and the
+//! `NetworkCapability` the datagrams are sent with require implementing
+//! unsafe capability traits, which the capsules crate forbids.
+
+mod common;
+
+use std::cell::RefCell;
+
+use capsules::ieee802154::csma::CsmaParams;
+use capsules::ieee802154::device::MacDevice;
+use capsules::ieee802154::framer::Framer;
+use capsules::ieee802154::mac::Mac;
+use capsules::ieee802154::sim_radio::LinkParams;
+use capsules::ieee802154::virtual_mac::{MacUser, MuxMac};
+use capsules::net::ieee802154::MacAddress;
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::ipv6::ipv6_recv::{IP6Receiver, IP6RecvStruct};
+use capsules::net::ipv6::ipv6_send::{IP6SendStruct, IP6Sender};
+use capsules::net::ipv6::neighbor_discovery::{self, NeighborDiscovery};
+use capsules::net::ipv6::rpl::{self, Rpl};
+use capsules::net::ipv6::{IP6Packet, IPPayload, TransportHeader};
+use capsules::net::network_capabilities::{
+    AddrRange, IpVisibilityCapability, NetworkCapability, PortRange, UdpVisibilityCapability,
+};
+use capsules::net::sixlowpan::sixlowpan_compression::Context;
+use capsules::net::sixlowpan::sixlowpan_state::{RxState, Sixlowpan, SixlowpanState, TxState};
+use capsules::net::udp::udp_port_table::{PortQuery, UdpPortManager, MAX_NUM_BOUND_PORTS};
+use capsules::net::udp::udp_recv::{MuxUdpReceiver, UDPReceiver, UDPRecvClient};
+use capsules::net::udp::udp_send::{MuxUdpSender, UDPSendClient, UDPSendStruct, UDPSender};
+use capsules::net::udp::UDPHeader;
+
+use kernel::capabilities::{
+    CreatePortTableCapability, NetworkCapabilityCreationCapability, UdpDriverCapability,
+};
+use kernel::hil::radio::{self, RadioData};
+use kernel::hil::time::Alarm;
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::leasable_buffer::LeasableMutableBuffer;
+use kernel::ErrorCode;
+
+use common::{leak, leak_buf, FakeAlarm, FakeCcm, Network, Node, Radio};
+
+const PAN: u16 = 0xabcd;
+const PORT: u16 = 5683;
+const MAX_PAYLOAD_LEN: usize = 200;
+const ALL_NODES: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+const MESH_PREFIX: IPAddr = IPAddr([0xfd, 0, 0, 0, 0, 0, 0, 0x01, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+struct TestCap;
+unsafe impl NetworkCapabilityCreationCapability for TestCap {}
+unsafe impl CreatePortTableCapability for TestCap {}
+unsafe impl UdpDriverCapability for TestCap {}
+
+fn short_addr(index: usize) -> u16 {
+    index as u16 + 1
+}
+
+fn link_local(index: usize) -> IPAddr {
+    IPAddr::generate_from_mac(MacAddress::Short(short_addr(index)))
+}
+
+/// A payload that differs for each datagram and each byte in it.
+fn payload(id: u8, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|i| id.wrapping_mul(31).wrapping_add(i as u8))
+        .collect()
+}
+
+/// No ports are bound by userspace.
+struct NoUserPorts;
+
+impl PortQuery for NoUserPorts {
+    fn is_bound(&self, _port: u16) -> bool {
+        false
+    }
+}
+
+/// Records the datagrams a node sends and receives.
+#[derive(Default)]
+struct Datagrams {
+    sent: RefCell<Vec<Result<(), ErrorCode>>>,
+    received: RefCell<Vec<(IPAddr, IPAddr, u16, Vec<u8>)>>,
+    buf: RefCell<Option<LeasableMutableBuffer<'static, u8>>>,
+}
+
+impl UDPSendClient for Datagrams {
+    fn send_done(&self, result: Result<(), ErrorCode>, dgram: LeasableMutableBuffer<'static, u8>) {
+        self.sent.borrow_mut().push(result);
+        *self.buf.borrow_mut() = Some(dgram);
+    }
+}
+
+impl UDPRecvClient for Datagrams {
+    fn receive(
+        &self,
+        src_addr: IPAddr,
+        dst_addr: IPAddr,
+        src_port: u16,
+        _dst_port: u16,
+        payload: &[u8],
+    ) {
+        self.received
+            .borrow_mut()
+            .push((src_addr, dst_addr, src_port, payload.to_vec()));
+    }
+}
+
+/// Records the sequence number of each frame a radio hears.
+struct Sniffer {
+    radio: OptionalCell<&'static Radio>,
+    seqs: RefCell<Vec<u8>>,
+}
+
+impl radio::RxClient for Sniffer {
+    fn receive(
+        &self,
+        buf: &'static mut [u8],
+        _frame_len: usize,
+        _crc_valid: bool,
+        _result: Result<(), ErrorCode>,
+    ) {
+        self.seqs.borrow_mut().push(buf[radio::PSDU_OFFSET + 2]);
+        self.radio.map(move |radio| radio.set_receive_buffer(buf));
+    }
+}
+
+type IpSender = IP6SendStruct<'static, FakeAlarm<'static>>;
+type Nd = NeighborDiscovery<'static, IpSender, FakeAlarm<'static>>;
+type Router = Rpl<'static, Nd, FakeAlarm<'static>>;
+
+/// A node's layers from the radio up to IPv6, wired as by
+/// `UDPMuxComponent`.
+struct IpStack {
+    node: Node,
+    ip_send: &'static IpSender,
+    ip_receive: &'static IP6RecvStruct<'static>,
+}
+
+impl IpStack {
+    fn new(net: &Network, index: usize) -> IpStack {
+        let node = Node::new(net, index, PAN);
+        let mac = node.mac;
+        let framer = leak(Framer::new(mac, leak(FakeCcm)));
+        mac.set_transmit_client(framer);
+        mac.set_receive_client(framer);
+        mac.set_config_client(framer);
+        let mux_mac = leak(MuxMac::new(framer));
+        framer.set_transmit_client(mux_mac);
+        framer.set_receive_client(mux_mac);
+        let mac_user = leak(MacUser::new(mux_mac));
+        mux_mac.add_user(mac_user);
+
+        let ip_alarm = net.alarm();
+        let sixlowpan = leak(Sixlowpan::new(
+            Context {
+                prefix: [0; 16],
+                prefix_len: 0,
+                id: 0,
+                compress: false,
+            },
+            ip_alarm,
+        ));
+        let sixlowpan_state = sixlowpan as &dyn SixlowpanState;
+        sixlowpan_state.add_rx_state(leak(RxState::new(leak_buf(1280))));
+        sixlowpan_state.add_rx_state(leak(RxState::new(leak_buf(1280))));
+        mac_user.set_receive_client(sixlowpan);
+
+        let ip_vis = leak(IpVisibilityCapability::new(&TestCap));
+        let ip6_packet = Box::leak(Box::new(IP6Packet::new(IPPayload {
+            header: TransportHeader::UDP(UDPHeader::new()),
+            payload: leak_buf(MAX_PAYLOAD_LEN),
+        })));
+        let ip_send = leak(IP6SendStruct::new(
+            ip6_packet,
+            ip_alarm,
+            leak_buf(radio::MAX_BUF_SIZE),
+            TxState::new(sixlowpan_state),
+            mac_user,
+            MacAddress::Short(0xffff),
+            MacAddress::Short(short_addr(index)),
+            ip_vis,
+        ));
+        ip_alarm.set_alarm_client(ip_send);
+        mac_user.set_transmit_client(ip_send);
+
+        let ip_receive = leak(IP6RecvStruct::new());
+        sixlowpan_state.set_rx_client(ip_receive);
+        IpStack {
+            node,
+            ip_send,
+            ip_receive,
+        }
+    }
+}
+
+fn any_net_cap() -> &'static NetworkCapability {
+    leak(NetworkCapability::new(
+        AddrRange::Any,
+        PortRange::Any,
+        PortRange::Any,
+        &TestCap,
+    ))
+}
+
+/// A node with a UDP socket bound to `PORT` on top of `sender`, recording
+/// the datagrams it sends and receives.
+struct Host<S: IP6Sender<'static> + 'static> {
+    node: Node,
+    udp_send: &'static UDPSendStruct<'static, S>,
+    datagrams: &'static Datagrams,
+    net_cap: &'static NetworkCapability,
+}
+
+impl<S: IP6Sender<'static>> Host<S> {
+    /// Binds the socket. Received datagrams are expected to be passed to
+    /// `udp_recv_mux`.
+    fn new(node: Node, sender: &'static S, udp_recv_mux: &'static MuxUdpReceiver<'static>) -> Self {
+        let udp_vis = leak(UdpVisibilityCapability::new(&TestCap));
+        let udp_send_mux = leak(MuxUdpSender::new(sender));
+        sender.set_client(udp_send_mux);
+
+        let port_table = leak(UdpPortManager::new(
+            &TestCap,
+            Box::leak(Box::new([None; MAX_NUM_BOUND_PORTS])),
+            udp_vis,
+        ));
+        port_table.set_user_ports(leak(NoUserPorts), &TestCap);
+        let net_cap = any_net_cap();
+
+        let udp_send = leak(UDPSendStruct::new(udp_send_mux, udp_vis));
+        let udp_recv = leak(UDPReceiver::new());
+        let socket = port_table.create_socket().ok().unwrap();
+        let (send_binding, recv_binding) = port_table.bind(socket, PORT, net_cap).ok().unwrap();
+        udp_send.set_binding(send_binding);
+        udp_recv.set_binding(recv_binding);
+        udp_recv_mux.add_client(udp_recv);
+
+        let datagrams = leak(Datagrams::default());
+        *datagrams.buf.borrow_mut() = Some(LeasableMutableBuffer::new(leak_buf(MAX_PAYLOAD_LEN)));
+        udp_send.set_client(datagrams);
+        udp_recv.set_client(datagrams);
+
+        Host {
+            node,
+            udp_send,
+            datagrams,
+            net_cap,
+        }
+    }
+
+    fn send(&self, dst: IPAddr, data: &[u8]) {
+        let mut buf = self.datagrams.buf.borrow_mut().take().unwrap();
+        buf.reset();
+        buf[..data.len()].copy_from_slice(data);
+        buf.slice(0..data.len());
+        assert!(self.udp_send.send_to(dst, PORT, buf, self.net_cap).is_ok());
+    }
+
+    fn sent(&self) -> Vec<Result<(), ErrorCode>> {
+        self.datagrams.sent.borrow_mut().drain(..).collect()
+    }
+
+    /// The source and payload of each datagram received.
+    fn received(&self) -> Vec<(IPAddr, Vec<u8>)> {
+        self.datagrams
+            .received
+            .borrow()
+            .iter()
+            .map(|(src, _, src_port, payload)| {
+                assert_eq!(*src_port, PORT);
+                (*src, payload.clone())
+            })
+            .collect()
+    }
+}
+
+/// A host on the link-local network, without Neighbor Discovery and RPL:
+/// nodes use link-local addresses, and are all one hop apart.
+fn link_local_host(net: &Network, index: usize) -> Host<IpSender> {
+    let stack = IpStack::new(net, index);
+    stack.ip_send.set_addr(link_local(index));
+    let udp_recv_mux = leak(MuxUdpReceiver::new());
+    stack.ip_receive.set_client(udp_recv_mux);
+    Host::new(stack.node, stack.ip_send, udp_recv_mux)
+}
+
+/// Connects `count` link-local hosts, and lets their radios start.
+fn network(count: usize) -> (Network, Vec<Host<IpSender>>) {
+    let net = Network::new();
+    let hosts = (0..count).map(|i| link_local_host(&net, i)).collect();
+    net.run();
+    (net, hosts)
+}
+
+/// Adds a radio that only listens, recording the frames it hears.
+fn sniffer(net: &Network) -> &'static Sniffer {
+    let radio = net.radio();
+    let sniffer = leak(Sniffer {
+        radio: OptionalCell::new(radio),
+        seqs: RefCell::new(Vec::new()),
+    });
+    radio.set_receive_client(sniffer, leak_buf(radio::MAX_BUF_SIZE));
+    sniffer
+}
+
+/// A host of a mesh, which gets its global address and its routes from RPL.
+struct MeshHost {
+    host: Host<Router>,
+    nd: &'static Nd,
+    rpl: &'static Router,
+    ip_receive: &'static IP6RecvStruct<'static>,
+}
+
+impl MeshHost {
+    fn new(net: &Network, index: usize) -> MeshHost {
+        let stack = IpStack::new(net, index);
+        let ip_send = stack.ip_send;
+        let ip_receive = stack.ip_receive;
+
+        let nd_alarm = net.alarm();
+        let nd = leak(NeighborDiscovery::new(
+            ip_send,
+            nd_alarm,
+            MacAddress::Short(short_addr(index)),
+            leak_buf(neighbor_discovery::BUF_LEN),
+            any_net_cap(),
+        ));
+        nd_alarm.set_alarm_client(nd);
+        ip_send.set_client(nd);
+        ip_receive.set_client(nd);
+
+        let rpl_alarm = net.alarm();
+        let rpl = leak(Rpl::new(
+            nd,
+            rpl_alarm,
+            nd.addresses(),
+            leak_buf(rpl::BUF_LEN),
+            any_net_cap(),
+        ));
+        rpl_alarm.set_alarm_client(rpl);
+        nd.set_client(rpl);
+        nd.set_receive_client(rpl);
+        rpl.set_prefix_client(nd);
+        ip_send.set_router(rpl);
+        ip_receive.set_forwarder(ip_send, nd.addresses());
+
+        let udp_recv_mux = leak(MuxUdpReceiver::new());
+        rpl.set_receive_client(udp_recv_mux);
+        MeshHost {
+            host: Host::new(stack.node, rpl, udp_recv_mux),
+            nd,
+            rpl,
+            ip_receive,
+        }
+    }
+
+    fn global_addr(&self) -> IPAddr {
+        self.nd.addresses()[1].get()
+    }
+}
+
+#[test]
+fn unicast_datagram_is_delivered() {
+    let (net, hosts) = network(3);
+    let data = payload(1, 20);
+    hosts[0].send(link_local(1), &data);
+    net.run();
+
+    assert_eq!(hosts[0].sent(), vec![Ok(())]);
+    assert_eq!(hosts[1].received(), vec![(link_local(0), data)]);
+    assert_eq!(hosts[2].received(), vec![]);
+    assert_eq!(hosts[0].node.mac.last_tx_stats().retries, 0);
+}
+
+#[test]
+fn fragmented_datagram_is_reassembled() {
+    let (net, hosts) = network(2);
+    let sniffer = sniffer(&net);
+    let data = payload(2, 180);
+    hosts[0].send(link_local(1), &data);
+    net.run();
+
+    assert_eq!(hosts[0].sent(), vec![Ok(())]);
+    assert_eq!(hosts[1].received(), vec![(link_local(0), data)]);
+    // Each fragment and its ACK
+    assert!(sniffer.seqs.borrow().len() >= 4);
+}
+
+#[test]
+fn lossy_link_is_recovered_by_retransmissions() {
+    let (net, hosts) = network(2);
+    net.medium.set_seed(0x5eed);
+    net.medium
+        .set_params(LinkParams {
+            loss_percent: 20,
+            ..LinkParams::default()
+        })
+        .unwrap();
+    for host in hosts.iter() {
+        host.node
+            .mac
+            .set_params(CsmaParams {
+                max_frame_retries: 7,
+                ..CsmaParams::default()
+            })
+            .unwrap();
+    }
+    let sniffer = sniffer(&net);
+
+    let datagrams: Vec<_> = (0..5).map(|id| payload(id, 150)).collect();
+    for data in datagrams.iter() {
+        hosts[0].send(link_local(1), data);
+        net.run();
+        assert_eq!(hosts[0].sent(), vec![Ok(())]);
+    }
+
+    let received: Vec<_> = hosts[1]
+        .received()
+        .into_iter()
+        .map(|(_, data)| data)
+        .collect();
+    assert_eq!(received, datagrams);
+    // Some frames had to be sent again, so the sniffer heard a sequence
+    // number more than once
+    let mut seqs = sniffer.seqs.borrow().clone();
+    let heard = seqs.len();
+    seqs.dedup();
+    assert!(seqs.len() < heard);
+}
+
+#[test]
+fn fragments_are_reassembled_after_a_long_uptime() {
+    // The reassembly timeout of 60 s is measured in alarm ticks, so it must
+    // hold after the clock passes 60 s times the frequency, and while it
+    // wraps around
+    for start in [80_000_000, u32::MAX - 50_000] {
+        let (net, hosts) = network(2);
+        net.clock.set(start);
+        let data = payload(9, 180);
+        hosts[0].send(link_local(1), &data);
+        net.run();
+
+        assert_eq!(hosts[0].sent(), vec![Ok(())]);
+        assert_eq!(hosts[1].received(), vec![(link_local(0), data)]);
+    }
+}
+
+#[test]
+fn reordered_fragments_are_reassembled() {
+    let (net, hosts) = network(3);
+    net.medium.set_seed(42);
+    // Fragments are sent 100 ms apart, so a larger jitter reorders them
+    net.medium
+        .set_params(LinkParams {
+            loss_percent: 0,
+            delay_us: 1_000,
+            jitter_us: 400_000,
+        })
+        .unwrap();
+    let sniffer = sniffer(&net);
+    let data = payload(3, 180);
+    // Multicast datagrams are not acknowledged, so only the data frames are
+    // on the air
+    hosts[0].send(ALL_NODES, &data);
+    net.run();
+
+    let seqs = sniffer.seqs.borrow().clone();
+    let mut sorted = seqs.clone();
+    sorted.sort_unstable();
+    assert!(seqs.len() > 1);
+    assert_ne!(seqs, sorted);
+
+    assert_eq!(hosts[0].sent(), vec![Ok(())]);
+    assert_eq!(hosts[1].received(), vec![(link_local(0), data.clone())]);
+    assert_eq!(hosts[2].received(), vec![(link_local(0), data)]);
+}
+
+#[test]
+fn nodes_out_of_range_do_not_hear_each_other() {
+    let (net, hosts) = network(3);
+    net.medium
+        .set_in_range(hosts[0].node.radio, hosts[2].node.radio, false);
+    let data = payload(4, 20);
+    hosts[0].send(ALL_NODES, &data);
+    net.run();
+
+    assert_eq!(hosts[1].received(), vec![(link_local(0), data)]);
+    assert_eq!(hosts[2].received(), vec![]);
+}
+
+#[test]
+fn concurrent_senders_are_both_delivered() {
+    let (net, hosts) = network(3);
+    let first = payload(5, 20);
+    let second = payload(6, 20);
+    hosts[0].send(link_local(2), &first);
+    hosts[1].send(link_local(2), &second);
+    net.run();
+
+    assert_eq!(hosts[0].sent(), vec![Ok(())]);
+    assert_eq!(hosts[1].sent(), vec![Ok(())]);
+    let mut received = hosts[2].received();
+    received.sort_by_key(|(src, _)| src.0);
+    assert_eq!(
+        received,
+        vec![(link_local(0), first), (link_local(1), second)]
+    );
+}
+
+#[test]
+fn invalid_link_params_are_rejected() {
+    let (net, _hosts) = network(1);
+    assert_eq!(
+        net.medium.set_params(LinkParams {
+            loss_percent: 101,
+            ..LinkParams::default()
+        }),
+        Err(ErrorCode::INVAL)
+    );
+    assert_eq!(net.medium.params(), LinkParams::default());
+}
+
+#[test]
+fn datagrams_are_forwarded_across_a_mesh() {
+    // A line of nodes, each in range of its neighbors only, with the root of
+    // the DODAG at one end
+    let net = Network::new();
+    let hosts: Vec<_> = (0..4).map(|i| MeshHost::new(&net, i)).collect();
+    for (a, b) in [(0, 2), (0, 3), (1, 3)] {
+        net.medium
+            .set_in_range(hosts[a].host.node.radio, hosts[b].host.node.radio, false);
+    }
+    hosts[0].rpl.start_root(MESH_PREFIX);
+    for host in hosts[1..].iter() {
+        host.rpl.start();
+    }
+    for host in hosts.iter() {
+        host.nd.start();
+    }
+    // Long enough for each node to join the DODAG and advertise its address
+    net.run_for(120_000_000);
+
+    let root = hosts[0].global_addr();
+    let leaf = hosts[3].global_addr();
+    for host in hosts.iter() {
+        assert_eq!(host.global_addr().0[..8], MESH_PREFIX.0[..8]);
+    }
+
+    // Upwards, to the preferred parent of each hop
+    let up = payload(7, 40);
+    hosts[3].host.send(root, &up);
+    net.run_for(1_000_000);
+    assert_eq!(hosts[3].host.sent(), vec![Ok(())]);
+    assert_eq!(hosts[0].host.received(), vec![(leaf, up)]);
+
+    // Downwards, along the routes installed by the DAOs
+    let down = payload(8, 120);
+    hosts[0].host.send(leaf, &down);
+    net.run_for(1_000_000);
+    assert_eq!(hosts[0].host.sent(), vec![Ok(())]);
+    assert_eq!(hosts[3].host.received(), vec![(root, down)]);
+
+    // The nodes in between only forwarded the datagrams
+    for host in hosts[1..3].iter() {
+        assert_eq!(host.host.received(), vec![]);
+        assert_eq!(host.ip_receive.forward_dropped(), 0);
+    }
+}
@@ -0,0 +1,830 @@
+//! Attaches a Thread end device to simulated parents, exchanging MLE
+//! messages with them over a simulated link.
+//!
+//! This test lives outside of the crate because creating the
+//! `NetworkCapability` the MLE capsule sends with requires implementing an
+//! unsafe capability trait, which the capsules crate forbids.
+
+mod common;
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use capsules::ieee802154::device::{MacDevice, RxClient, TxClient};
+use capsules::ieee802154::framer::{DeviceProcedure, Frame, KeyProcedure};
+use capsules::ieee802154::mac::TxStats;
+use capsules::net::ieee802154::{FrameType, KeyId, MacAddress, PanID, SecurityLevel};
+use capsules::net::ipv6::ip_utils::IPAddr;
+use capsules::net::network_capabilities::{AddrRange, NetworkCapability, PortRange};
+use capsules::net::stream::SResult;
+use capsules::net::thread::mle::{command, DeviceMode, Mle, MleClient, BUF_LEN, MLE_PORT};
+use capsules::net::thread::tlv::Tlv;
+use capsules::net::udp::udp_port_table::UdpPortBindingTx;
+use capsules::net::udp::udp_recv::UDPRecvClient;
+use capsules::net::udp::udp_send::{UDPSendClient, UDPSender};
+use capsules::net::udp::UDPHeader;
+
+use kernel::capabilities::{NetworkCapabilityCreationCapability, UdpDriverCapability};
+use kernel::hil::digest;
+use kernel::hil::symmetric_encryption::{CCMClient, AES128CCM};
+use kernel::hil::time::{Alarm, Freq1KHz};
+use kernel::utilities::cells::{OptionalCell, TakeCell};
+use kernel::utilities::leasable_buffer::{LeasableBuffer, LeasableMutableBuffer};
+use kernel::ErrorCode;
+
+use common::{leak, FakeAlarm};
+
+const MASTER_KEY: [u8; 16] = [
+    0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff,
+];
+const KEY_SEQUENCE: u32 = 0;
+const CHILD_EXT_ADDR: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+const ALL_ROUTERS: IPAddr = IPAddr([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+
+struct TestCap;
+unsafe impl NetworkCapabilityCreationCapability for TestCap {}
+
+fn link_local(ext_addr: [u8; 8]) -> IPAddr {
+    IPAddr::generate_from_mac(MacAddress::Long(ext_addr))
+}
+
+// The fake cryptography below is deterministic and shared between the
+// device and the simulated parents; it only has to detect tampering.
+
+fn fnv(seed: u32, parts: &[&[u8]]) -> u32 {
+    let mut h = 0x811c9dc5u32 ^ seed;
+    for part in parts {
+        for b in part.iter() {
+            h ^= *b as u32;
+            h = h.wrapping_mul(0x01000193);
+        }
+    }
+    h
+}
+
+fn fake_hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut out = [0; 32];
+    for i in 0..8 {
+        out[i * 4..i * 4 + 4].copy_from_slice(&fnv(i as u32, &[key, data]).to_be_bytes());
+    }
+    out
+}
+
+/// The keys derived from `MASTER_KEY` and `KEY_SEQUENCE`: (MLE key, MAC key).
+fn thread_keys() -> ([u8; 16], [u8; 16]) {
+    let mut input = KEY_SEQUENCE.to_be_bytes().to_vec();
+    input.extend_from_slice(b"Thread");
+    let hmac = fake_hmac(&MASTER_KEY, &input);
+    let mut mle_key = [0; 16];
+    let mut mac_key = [0; 16];
+    mle_key.copy_from_slice(&hmac[..16]);
+    mac_key.copy_from_slice(&hmac[16..]);
+    (mle_key, mac_key)
+}
+
+fn keystream(key: &[u8], nonce: &[u8], m: &mut [u8]) {
+    for (i, b) in m.iter_mut().enumerate() {
+        *b ^= fnv(i as u32, &[key, nonce]) as u8;
+    }
+}
+
+fn fake_ccm(
+    key: &[u8],
+    nonce: &[u8],
+    buf: &mut [u8],
+    a_off: usize,
+    m_off: usize,
+    m_len: usize,
+    encrypting: bool,
+) -> bool {
+    let end = m_off + m_len;
+    if !encrypting {
+        keystream(key, nonce, &mut buf[m_off..end]);
+    }
+    let tag = fnv(0, &[key, nonce, &buf[a_off..end]]).to_be_bytes();
+    let valid = encrypting || buf[end..end + 4] == tag;
+    if encrypting {
+        keystream(key, nonce, &mut buf[m_off..end]);
+        buf[end..end + 4].copy_from_slice(&tag);
+    }
+    valid
+}
+
+fn nonce(ext_addr: [u8; 8], frame_counter: u32) -> Vec<u8> {
+    let mut nonce = ext_addr.to_vec();
+    nonce.extend_from_slice(&frame_counter.to_be_bytes());
+    nonce.push(5);
+    nonce
+}
+
+/// Builds a secured MLE message as sent in a UDP datagram.
+fn seal_message(
+    key: &[u8; 16],
+    src: IPAddr,
+    dst: IPAddr,
+    ext_addr: [u8; 8],
+    frame_counter: u32,
+    message: &[u8],
+) -> Vec<u8> {
+    let mut buf = src.0.to_vec();
+    buf.extend_from_slice(&dst.0);
+    buf.push(0x15);
+    buf.extend_from_slice(&frame_counter.to_le_bytes());
+    buf.extend_from_slice(&KEY_SEQUENCE.to_be_bytes());
+    buf.push((KEY_SEQUENCE & 0x7f) as u8 + 1);
+    buf.extend_from_slice(message);
+    buf.extend_from_slice(&[0; 4]);
+    let nonce = nonce(ext_addr, frame_counter);
+    fake_ccm(key, &nonce, &mut buf, 0, 42, message.len(), true);
+    let mut dgram = vec![0];
+    dgram.extend_from_slice(&buf[32..]);
+    dgram
+}
+
+/// Verifies and decrypts an MLE message sent from the link-local address
+/// `src`.
+fn open_message(key: &[u8; 16], src: IPAddr, dst: IPAddr, dgram: &[u8]) -> Option<Vec<u8>> {
+    if dgram.len() < 15 || dgram[0] != 0 || dgram[1] != 0x15 {
+        return None;
+    }
+    let mut ext_addr = [0; 8];
+    ext_addr.copy_from_slice(&src.0[8..]);
+    ext_addr[0] ^= 2;
+    let frame_counter = u32::from_le_bytes([dgram[2], dgram[3], dgram[4], dgram[5]]);
+    let mut buf = src.0.to_vec();
+    buf.extend_from_slice(&dst.0);
+    buf.extend_from_slice(&dgram[1..]);
+    let m_len = buf.len() - 42 - 4;
+    let nonce = nonce(ext_addr, frame_counter);
+    if fake_ccm(key, &nonce, &mut buf, 0, 42, m_len, false) {
+        Some(buf[42..42 + m_len].to_vec())
+    } else {
+        None
+    }
+}
+
+fn encode_message(command: u8, tlvs: &[Tlv]) -> Vec<u8> {
+    let mut message = vec![command];
+    for tlv in tlvs {
+        let mut buf = [0; 64];
+        match tlv.encode(&mut buf) {
+            SResult::Done(len, ()) => message.extend_from_slice(&buf[..len]),
+            _ => panic!("TLV does not fit"),
+        }
+    }
+    message
+}
+
+/// The challenge or response in an MLE message, if any.
+fn find_tlv(message: &[u8], challenge: bool) -> Option<[u8; 8]> {
+    let mut buf = &message[1..];
+    while buf.len() >= 2 {
+        let len = 2 + buf[1] as usize;
+        match Tlv::decode(&buf[..len]) {
+            SResult::Done(_, Tlv::Challenge(c)) if challenge => return Some(c),
+            SResult::Done(_, Tlv::Response(r)) if !challenge => return Some(r),
+            _ => {}
+        }
+        buf = &buf[len..];
+    }
+    None
+}
+
+/// Holds the datagram being sent until the link delivers it.
+struct FakeUdp<'a> {
+    client: OptionalCell<&'a dyn UDPSendClient>,
+    sending: RefCell<Option<(IPAddr, LeasableMutableBuffer<'static, u8>)>>,
+}
+
+impl FakeUdp<'_> {
+    fn complete(&self) -> Option<(IPAddr, Vec<u8>)> {
+        let (dst, dgram) = self.sending.borrow_mut().take()?;
+        let bytes = dgram[..].to_vec();
+        self.client.map(|c| c.send_done(Ok(()), dgram));
+        Some((dst, bytes))
+    }
+}
+
+impl<'a> UDPSender<'a> for FakeUdp<'a> {
+    fn set_client(&self, client: &'a dyn UDPSendClient) {
+        self.client.set(client);
+    }
+
+    fn send_to(
+        &'a self,
+        dest: IPAddr,
+        dst_port: u16,
+        buf: LeasableMutableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>> {
+        assert_eq!(dst_port, MLE_PORT);
+        assert!(self.sending.borrow().is_none());
+        *self.sending.borrow_mut() = Some((dest, buf));
+        Ok(())
+    }
+
+    fn driver_send_to(
+        &'a self,
+        _dest: IPAddr,
+        _dst_port: u16,
+        _src_port: u16,
+        buf: LeasableMutableBuffer<'static, u8>,
+        _driver_send_cap: &dyn UdpDriverCapability,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn send(
+        &'a self,
+        _dest: IPAddr,
+        _udp_header: UDPHeader,
+        buf: LeasableMutableBuffer<'static, u8>,
+        _net_cap: &'static NetworkCapability,
+    ) -> Result<(), LeasableMutableBuffer<'static, u8>> {
+        Err(buf)
+    }
+
+    fn get_binding(&self) -> Option<UdpPortBindingTx> {
+        None
+    }
+
+    fn is_bound(&self) -> bool {
+        true
+    }
+
+    fn set_binding(&self, binding: UdpPortBindingTx) -> Option<UdpPortBindingTx> {
+        Some(binding)
+    }
+}
+
+struct FakeCcm<'a> {
+    client: OptionalCell<&'a dyn CCMClient>,
+    key: RefCell<Vec<u8>>,
+    nonce: RefCell<Vec<u8>>,
+    buf: TakeCell<'static, [u8]>,
+    params: Cell<(usize, usize, usize, bool)>,
+}
+
+impl FakeCcm<'_> {
+    fn complete(&self) -> bool {
+        self.buf.take().map_or(false, |buf| {
+            let (a_off, m_off, m_len, encrypting) = self.params.get();
+            let valid = fake_ccm(
+                &self.key.borrow(),
+                &self.nonce.borrow(),
+                buf,
+                a_off,
+                m_off,
+                m_len,
+                encrypting,
+            );
+            self.client.map(|c| c.crypt_done(buf, Ok(()), valid));
+            true
+        })
+    }
+}
+
+impl<'a> AES128CCM<'a> for FakeCcm<'a> {
+    fn set_client(&'a self, client: &'a dyn CCMClient) {
+        self.client.set(client);
+    }
+
+    fn set_key(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        *self.key.borrow_mut() = key.to_vec();
+        Ok(())
+    }
+
+    fn set_nonce(&self, nonce: &[u8]) -> Result<(), ErrorCode> {
+        *self.nonce.borrow_mut() = nonce.to_vec();
+        Ok(())
+    }
+
+    fn crypt(
+        &self,
+        buf: &'static mut [u8],
+        a_off: usize,
+        m_off: usize,
+        m_len: usize,
+        mic_len: usize,
+        confidential: bool,
+        encrypting: bool,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        assert_eq!(mic_len, 4);
+        assert!(confidential);
+        if self.buf.is_some() {
+            return Err((ErrorCode::BUSY, buf));
+        }
+        self.params.set((a_off, m_off, m_len, encrypting));
+        self.buf.replace(buf);
+        Ok(())
+    }
+}
+
+enum HmacOp {
+    Idle,
+    AddData(LeasableMutableBuffer<'static, u8>),
+    Run(&'static mut [u8; 32]),
+}
+
+struct FakeHmac<'a> {
+    client: OptionalCell<&'a dyn digest::Client<32>>,
+    key: RefCell<Vec<u8>>,
+    data: RefCell<Vec<u8>>,
+    op: RefCell<HmacOp>,
+}
+
+impl FakeHmac<'_> {
+    fn complete(&self) -> bool {
+        let op = self.op.replace(HmacOp::Idle);
+        match op {
+            HmacOp::Idle => false,
+            HmacOp::AddData(data) => {
+                self.client.map(|c| c.add_mut_data_done(Ok(()), data));
+                true
+            }
+            HmacOp::Run(digest) => {
+                *digest = fake_hmac(&self.key.borrow(), &self.data.borrow());
+                self.client.map(|c| c.hash_done(Ok(()), digest));
+                true
+            }
+        }
+    }
+}
+
+impl<'a> digest::DigestData<'a, 32> for FakeHmac<'a> {
+    fn add_data(
+        &self,
+        data: LeasableBuffer<'static, u8>,
+    ) -> Result<(), (ErrorCode, LeasableBuffer<'static, u8>)> {
+        Err((ErrorCode::NOSUPPORT, data))
+    }
+
+    fn add_mut_data(
+        &self,
+        data: LeasableMutableBuffer<'static, u8>,
+    ) -> Result<(), (ErrorCode, LeasableMutableBuffer<'static, u8>)> {
+        self.data.borrow_mut().extend_from_slice(&data[..]);
+        *self.op.borrow_mut() = HmacOp::AddData(data);
+        Ok(())
+    }
+
+    fn clear_data(&self) {
+        self.data.borrow_mut().clear();
+    }
+}
+
+impl<'a> digest::DigestHash<'a, 32> for FakeHmac<'a> {
+    fn run(
+        &'a self,
+        digest: &'static mut [u8; 32],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+        *self.op.borrow_mut() = HmacOp::Run(digest);
+        Ok(())
+    }
+}
+
+impl<'a> digest::DigestVerify<'a, 32> for FakeHmac<'a> {
+    fn verify(
+        &'a self,
+        compare: &'static mut [u8; 32],
+    ) -> Result<(), (ErrorCode, &'static mut [u8; 32])> {
+        Err((ErrorCode::NOSUPPORT, compare))
+    }
+}
+
+impl<'a> digest::Digest<'a, 32> for FakeHmac<'a> {
+    fn set_client(&'a self, client: &'a dyn digest::Client<32>) {
+        self.client.set(client);
+    }
+}
+
+impl digest::HmacSha256 for FakeHmac<'_> {
+    fn set_mode_hmacsha256(&self, key: &[u8]) -> Result<(), ErrorCode> {
+        *self.key.borrow_mut() = key.to_vec();
+        self.data.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+struct FakeMac {
+    addr: Cell<u16>,
+    addr_long: Cell<[u8; 8]>,
+    pan: Cell<u16>,
+}
+
+impl<'a> MacDevice<'a> for FakeMac {
+    fn set_transmit_client(&self, _client: &'a dyn TxClient) {}
+
+    fn set_receive_client(&self, _client: &'a dyn RxClient) {}
+
+    fn get_address(&self) -> u16 {
+        self.addr.get()
+    }
+
+    fn get_address_long(&self) -> [u8; 8] {
+        self.addr_long.get()
+    }
+
+    fn get_pan(&self) -> u16 {
+        self.pan.get()
+    }
+
+    fn set_address(&self, addr: u16) {
+        self.addr.set(addr);
+    }
+
+    fn set_address_long(&self, addr: [u8; 8]) {
+        self.addr_long.set(addr);
+    }
+
+    fn set_pan(&self, id: u16) {
+        self.pan.set(id);
+    }
+
+    fn config_commit(&self) {}
+
+    fn is_on(&self) -> bool {
+        true
+    }
+
+    fn prepare_data_frame(
+        &self,
+        buf: &'static mut [u8],
+        _dst_pan: PanID,
+        _dst_addr: MacAddress,
+        _src_pan: PanID,
+        _src_addr: MacAddress,
+        _security_needed: Option<(SecurityLevel, KeyId)>,
+    ) -> Result<Frame, &'static mut [u8]> {
+        Err(buf)
+    }
+
+    fn prepare_frame(
+        &self,
+        buf: &'static mut [u8],
+        _frame_type: FrameType,
+        _dst: Option<(PanID, MacAddress)>,
+        _src: Option<(PanID, MacAddress)>,
+        _ack_requested: bool,
+    ) -> Result<Frame, &'static mut [u8]> {
+        Err(buf)
+    }
+
+    fn transmit(&self, frame: Frame) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        Err((ErrorCode::NOSUPPORT, frame.into_buf()))
+    }
+
+    fn last_tx_stats(&self) -> TxStats {
+        TxStats::default()
+    }
+}
+
+/// A Thread router on the simulated link that accepts the device as its
+/// child.
+struct SimParent {
+    ext_addr: [u8; 8],
+    rloc16: u16,
+    child_rloc16: u16,
+    link_margin: u8,
+    challenge: [u8; 8],
+    frame_counter: Cell<u32>,
+    /// Whether the parent answers at all.
+    responsive: Cell<bool>,
+    /// Whether the replies are corrupted on the link.
+    corrupt: Cell<bool>,
+    /// The commands received from the device.
+    received: RefCell<Vec<u8>>,
+}
+
+impl SimParent {
+    fn new(id: u8, link_margin: u8) -> SimParent {
+        SimParent {
+            ext_addr: [0x02, 0, 0, 0, 0, 0, 0, id],
+            rloc16: (id as u16) << 10,
+            child_rloc16: ((id as u16) << 10) | 1,
+            link_margin: link_margin,
+            challenge: [id; 8],
+            frame_counter: Cell::new(100),
+            responsive: Cell::new(true),
+            corrupt: Cell::new(false),
+            received: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn addr(&self) -> IPAddr {
+        link_local(self.ext_addr)
+    }
+
+    /// Handles a datagram from the device, returning the reply, if any.
+    fn handle(&self, src: IPAddr, dst: IPAddr, dgram: &[u8]) -> Option<Vec<u8>> {
+        let (mle_key, _) = thread_keys();
+        let message = open_message(&mle_key, src, dst, dgram)?;
+        self.received.borrow_mut().push(message[0]);
+        if !self.responsive.get() {
+            return None;
+        }
+        let reply = match message[0] {
+            command::PARENT_REQUEST => encode_message(
+                command::PARENT_RESPONSE,
+                &[
+                    Tlv::SourceAddress(self.rloc16),
+                    Tlv::LeaderData {
+                        partition_id: 1,
+                        weighting: 64,
+                        data_version: 0,
+                        stable_data_version: 0,
+                        leader_router_id: 0,
+                    },
+                    Tlv::LinkLayerFrameCounter(0),
+                    Tlv::Response(find_tlv(&message, true)?),
+                    Tlv::Challenge(self.challenge),
+                    Tlv::LinkMargin(self.link_margin),
+                    Tlv::Connectivity {
+                        parent_priority: 0,
+                        link_quality_3: 1,
+                        link_quality_2: 0,
+                        link_quality_1: 0,
+                        leader_cost: 1,
+                        id_sequence: 0,
+                        active_routers: 2,
+                        sed_buffer_size: Some(1280),
+                        sed_datagram_count: Some(1),
+                    },
+                    Tlv::Version(2),
+                ],
+            ),
+            command::CHILD_ID_REQUEST if find_tlv(&message, false) == Some(self.challenge) => {
+                encode_message(
+                    command::CHILD_ID_RESPONSE,
+                    &[
+                        Tlv::SourceAddress(self.rloc16),
+                        Tlv::Address16(self.child_rloc16),
+                        Tlv::NetworkData(&[]),
+                    ],
+                )
+            }
+            command::CHILD_UPDATE_REQUEST => encode_message(
+                command::CHILD_UPDATE_RESPONSE,
+                &[
+                    Tlv::SourceAddress(self.child_rloc16),
+                    Tlv::Response(find_tlv(&message, true)?),
+                ],
+            ),
+            _ => return None,
+        };
+        let frame_counter = self.frame_counter.get();
+        self.frame_counter.set(frame_counter + 1);
+        let mut dgram = seal_message(
+            &mle_key,
+            self.addr(),
+            src,
+            self.ext_addr,
+            frame_counter,
+            &reply,
+        );
+        if self.corrupt.get() {
+            dgram[12] ^= 0xff;
+        }
+        Some(dgram)
+    }
+}
+
+struct TestClient {
+    attached: Cell<Option<u16>>,
+    detached: Cell<usize>,
+}
+
+impl MleClient for TestClient {
+    fn attached(&self, rloc16: u16) {
+        self.attached.set(Some(rloc16));
+    }
+
+    fn detached(&self) {
+        self.attached.set(None);
+        self.detached.set(self.detached.get() + 1);
+    }
+}
+
+type TestMle = Mle<
+    'static,
+    FakeUdp<'static>,
+    FakeAlarm<'static, Freq1KHz>,
+    FakeCcm<'static>,
+    FakeHmac<'static>,
+>;
+
+/// The device under test, with the simulated link to its neighbors.
+struct Link {
+    mle: &'static TestMle,
+    alarm: &'static FakeAlarm<'static, Freq1KHz>,
+    udp: &'static FakeUdp<'static>,
+    ccm: &'static FakeCcm<'static>,
+    hmac: &'static FakeHmac<'static>,
+    mac: &'static FakeMac,
+    client: &'static TestClient,
+    parents: Vec<&'static SimParent>,
+}
+
+impl Link {
+    fn new(parents: Vec<&'static SimParent>) -> Link {
+        let alarm = leak(FakeAlarm::new(leak(Cell::new(1_000))));
+        let udp = leak(FakeUdp {
+            client: OptionalCell::empty(),
+            sending: RefCell::new(None),
+        });
+        let ccm = leak(FakeCcm {
+            client: OptionalCell::empty(),
+            key: RefCell::new(Vec::new()),
+            nonce: RefCell::new(Vec::new()),
+            buf: TakeCell::empty(),
+            params: Cell::new((0, 0, 0, false)),
+        });
+        let hmac = leak(FakeHmac {
+            client: OptionalCell::empty(),
+            key: RefCell::new(Vec::new()),
+            data: RefCell::new(Vec::new()),
+            op: RefCell::new(HmacOp::Idle),
+        });
+        let mac = leak(FakeMac {
+            addr: Cell::new(0xfffe),
+            addr_long: Cell::new(CHILD_EXT_ADDR),
+            pan: Cell::new(0xface),
+        });
+        let client = leak(TestClient {
+            attached: Cell::new(None),
+            detached: Cell::new(0),
+        });
+        let net_cap = leak(NetworkCapability::new(
+            AddrRange::Any,
+            PortRange::Any,
+            PortRange::Any,
+            &TestCap,
+        ));
+        let mle: &'static TestMle = leak(Mle::new(
+            udp,
+            alarm,
+            ccm,
+            hmac,
+            mac,
+            DeviceMode::Sleepy,
+            240,
+            Box::leak(Box::new([0; BUF_LEN])),
+            Box::leak(Box::new([0; BUF_LEN])),
+            Box::leak(Box::new([0; 32])),
+            net_cap,
+        ));
+        udp.set_client(mle);
+        alarm.set_alarm_client(mle);
+        ccm.set_client(mle);
+        digest::Digest::set_client(hmac, mle);
+        mle.set_client(client);
+        Link {
+            mle: mle,
+            alarm: alarm,
+            udp: udp,
+            ccm: ccm,
+            hmac: hmac,
+            mac: mac,
+            client: client,
+            parents: parents,
+        }
+    }
+
+    /// Completes pending operations and delivers the datagrams on the link
+    /// until there is nothing left to do.
+    fn run(&self) {
+        let child = link_local(CHILD_EXT_ADDR);
+        let mut replies = VecDeque::new();
+        loop {
+            if self.hmac.complete() || self.ccm.complete() {
+                continue;
+            }
+            if let Some((dst, dgram)) = self.udp.complete() {
+                for parent in self.parents.iter() {
+                    if dst == ALL_ROUTERS || dst == parent.addr() {
+                        if let Some(reply) = parent.handle(child, dst, &dgram) {
+                            replies.push_back((parent.addr(), reply));
+                        }
+                    }
+                }
+                continue;
+            }
+            match replies.pop_front() {
+                Some((src, reply)) => self.mle.receive(src, child, MLE_PORT, MLE_PORT, &reply),
+                None => break,
+            }
+        }
+    }
+
+    /// Fires the alarm of the device, then runs the link.
+    fn fire(&self) {
+        self.alarm.trigger();
+        self.run();
+    }
+
+    fn attach(&self) {
+        self.mle.start(&MASTER_KEY, KEY_SEQUENCE).unwrap();
+        self.run();
+        // Parent Response timeout, then Child ID Request
+        self.fire();
+    }
+}
+
+#[test]
+fn attaches_to_parent() {
+    let parent = leak(SimParent::new(1, 30));
+    let link = Link::new(vec![parent]);
+    link.attach();
+
+    assert_eq!(
+        *parent.received.borrow(),
+        [command::PARENT_REQUEST, command::CHILD_ID_REQUEST]
+    );
+    assert_eq!(link.client.attached.get(), Some(parent.child_rloc16));
+    assert_eq!(link.mle.rloc16(), Some(parent.child_rloc16));
+    assert_eq!(link.mac.addr.get(), parent.child_rloc16);
+
+    // The MAC key and the parent are available to the framer
+    let (_, mac_key) = thread_keys();
+    assert_eq!(
+        link.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(1)),
+        Some(mac_key)
+    );
+    assert_eq!(
+        link.mle
+            .lookup_key(SecurityLevel::EncMic32, KeyId::Index(2)),
+        None
+    );
+    assert_eq!(
+        link.mle.lookup_addr_long(MacAddress::Short(parent.rloc16)),
+        Some(parent.ext_addr)
+    );
+}
+
+#[test]
+fn prefers_best_link_quality() {
+    let weak = leak(SimParent::new(1, 5));
+    let strong = leak(SimParent::new(2, 25));
+    let link = Link::new(vec![weak, strong]);
+    link.attach();
+
+    assert_eq!(link.client.attached.get(), Some(strong.child_rloc16));
+    assert_eq!(*weak.received.borrow(), [command::PARENT_REQUEST]);
+}
+
+#[test]
+fn ignores_tampered_responses() {
+    let parent = leak(SimParent::new(1, 30));
+    parent.corrupt.set(true);
+    let link = Link::new(vec![parent]);
+    link.attach();
+
+    // No Parent Response was accepted: the device asked routers and REEDs
+    // again instead of sending a Child ID Request
+    assert_eq!(
+        *parent.received.borrow(),
+        [command::PARENT_REQUEST, command::PARENT_REQUEST]
+    );
+    assert_eq!(link.client.attached.get(), None);
+
+    // It backs off and attaches once the link is healthy again
+    link.fire();
+    parent.corrupt.set(false);
+    link.fire();
+    link.fire();
+    assert_eq!(link.client.attached.get(), Some(parent.child_rloc16));
+}
+
+#[test]
+fn detaches_after_missed_child_updates() {
+    let parent = leak(SimParent::new(1, 30));
+    let link = Link::new(vec![parent]);
+    link.attach();
+    assert_eq!(link.client.attached.get(), Some(parent.child_rloc16));
+
+    // A Child Update Response keeps the device attached
+    link.fire();
+    assert_eq!(
+        parent.received.borrow().last(),
+        Some(&command::CHILD_UPDATE_REQUEST)
+    );
+    assert_eq!(link.mle.rloc16(), Some(parent.child_rloc16));
+
+    parent.responsive.set(false);
+    parent.received.borrow_mut().clear();
+    link.fire();
+    link.fire();
+    link.fire();
+    assert_eq!(link.client.detached.get(), 0);
+    link.fire();
+
+    assert_eq!(
+        *parent.received.borrow(),
+        [command::CHILD_UPDATE_REQUEST; 3]
+    );
+    assert_eq!(link.client.detached.get(), 1);
+    assert_eq!(link.mle.rloc16(), None);
+    assert_eq!(link.mac.addr.get(), 0xfffe);
+}
@@ -1,6 +1,7 @@
 //! BLE driver.
 
 use core::cell::Cell;
+use kernel::deferred_call::DeferredCall;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
 use kernel::utilities::cells::OptionalCell;
@@ -12,6 +13,8 @@ use kernel::utilities::registers::{
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
 
+use crate::deferred_calls::DeferredCallTask;
+
 const BLE_BASE: StaticRef<BleRegisters> =
     unsafe { StaticRef::new(0x5000_C000 as *const BleRegisters) };
 
@@ -255,12 +258,19 @@ register_bitfields![u32,
 
 static mut PAYLOAD: [u8; 40] = [0x00; 40];
 
+static DEFERRED_CALL: DeferredCall<DeferredCallTask> =
+    unsafe { DeferredCall::new(DeferredCallTask::Ble) };
+
 pub struct Ble<'a> {
     registers: StaticRef<BleRegisters>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
+    scan_client: OptionalCell<&'a dyn ble_advertising::ScanClient>,
 
     buffer: TakeCell<'static, [u8]>,
+    /// The buffer of a scan, returned from a deferred call as scanning is
+    /// not supported.
+    scan_buffer: TakeCell<'static, [u8]>,
     write_len: Cell<usize>,
 
     read_len: Cell<usize>,
@@ -273,7 +283,9 @@ impl<'a> Ble<'a> {
             registers: BLE_BASE,
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            scan_client: OptionalCell::empty(),
             buffer: TakeCell::empty(),
+            scan_buffer: TakeCell::empty(),
             write_len: Cell::new(0),
             read_len: Cell::new(0),
             read_index: Cell::new(0),
@@ -422,6 +434,13 @@ impl<'a> Ble<'a> {
         }
     }
 
+    pub fn handle_deferred_call(&self) {
+        self.scan_buffer.take().map(|buf| {
+            self.scan_client
+                .map(move |client| client.scan_done(buf, Err(ErrorCode::NOSUPPORT)));
+        });
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.inten.set(0x18381);
     }
@@ -477,6 +496,18 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Ble<'a> {
     }
 }
 
+impl<'a> ble_advertising::BleScanDriver<'a> for Ble<'a> {
+    fn scan(&self, buf: &'static mut [u8], _channel: RadioChannel) {
+        // The radio cannot receive yet, so the scan ends right away
+        self.scan_buffer.replace(buf);
+        DEFERRED_CALL.set();
+    }
+
+    fn set_scan_client(&self, client: &'a dyn ble_advertising::ScanClient) {
+        self.scan_client.set(client);
+    }
+}
+
 impl ble_advertising::BleConfig for Ble<'_> {
     fn set_tx_power(&self, _tx_power: u8) -> Result<(), ErrorCode> {
         Ok(())
@@ -2,16 +2,19 @@
 
 use core::fmt::Write;
 use cortexm4::{self, CortexM4, CortexMVariant};
+use kernel::deferred_call;
 use kernel::platform::chip::Chip;
 use kernel::platform::chip::InterruptService;
 
-pub struct Apollo3<I: InterruptService<()> + 'static> {
+use crate::deferred_calls::DeferredCallTask;
+
+pub struct Apollo3<I: InterruptService<DeferredCallTask> + 'static> {
     mpu: cortexm4::mpu::MPU,
     userspace_kernel_boundary: cortexm4::syscall::SysCall,
     interrupt_service: &'static I,
 }
 
-impl<I: InterruptService<()> + 'static> Apollo3<I> {
+impl<I: InterruptService<DeferredCallTask> + 'static> Apollo3<I> {
     pub unsafe fn new(interrupt_service: &'static I) -> Self {
         Self {
             mpu: cortexm4::mpu::MPU::new(),
@@ -57,7 +60,7 @@ impl Apollo3DefaultPeripherals {
     }
 }
 
-impl kernel::platform::chip::InterruptService<()> for Apollo3DefaultPeripherals {
+impl kernel::platform::chip::InterruptService<DeferredCallTask> for Apollo3DefaultPeripherals {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
         use crate::nvic;
         match interrupt {
@@ -76,19 +79,26 @@ impl kernel::platform::chip::InterruptService<()> for Apollo3DefaultPeripherals
         }
         true
     }
-    unsafe fn service_deferred_call(&self, _: ()) -> bool {
-        false
+    unsafe fn service_deferred_call(&self, task: DeferredCallTask) -> bool {
+        match task {
+            DeferredCallTask::Ble => self.ble.handle_deferred_call(),
+        }
+        true
     }
 }
 
-impl<I: InterruptService<()> + 'static> Chip for Apollo3<I> {
+impl<I: InterruptService<DeferredCallTask> + 'static> Chip for Apollo3<I> {
     type MPU = cortexm4::mpu::MPU;
     type UserspaceKernelBoundary = cortexm4::syscall::SysCall;
 
     fn service_pending_interrupts(&self) {
         unsafe {
             loop {
-                if let Some(interrupt) = cortexm4::nvic::next_pending() {
+                if let Some(task) = deferred_call::DeferredCall::next_pending() {
+                    if !self.interrupt_service.service_deferred_call(task) {
+                        panic!("unhandled deferred call");
+                    }
+                } else if let Some(interrupt) = cortexm4::nvic::next_pending() {
                     if !self.interrupt_service.service_interrupt(interrupt) {
                         panic!("unhandled interrupt, {}", interrupt);
                     }
@@ -104,7 +114,7 @@ impl<I: InterruptService<()> + 'static> Chip for Apollo3<I> {
     }
 
     fn has_pending_interrupts(&self) -> bool {
-        unsafe { cortexm4::nvic::has_pending() }
+        unsafe { cortexm4::nvic::has_pending() || deferred_call::has_tasks() }
     }
 
     fn mpu(&self) -> &cortexm4::mpu::MPU {
@@ -9,6 +9,7 @@ pub mod ble;
 pub mod cachectrl;
 pub mod chip;
 pub mod clkgen;
+pub mod deferred_calls;
 pub mod gpio;
 pub mod iom;
 pub mod mcuctrl;
@@ -29,7 +29,7 @@ pub struct E310xDefaultPeripherals<'a> {
     pub pwm0: sifive::pwm::Pwm,
     pub pwm1: sifive::pwm::Pwm,
     pub pwm2: sifive::pwm::Pwm,
-    pub rtc: sifive::rtc::Rtc,
+    pub rtc: sifive::rtc::Rtc<'a>,
     pub watchdog: sifive::watchdog::Watchdog,
 }
 
@@ -52,6 +52,7 @@ impl<'a> InterruptService<()> for E310xDefaultPeripherals<'a> {
     unsafe fn service_interrupt(&self, interrupt: u32) -> bool {
         match interrupt {
             interrupts::UART0 => self.uart0.handle_interrupt(),
+            interrupts::RTC => self.rtc.handle_interrupt(),
             int_pin @ interrupts::GPIO0..=interrupts::GPIO31 => {
                 let pin = &self.gpio_port[(int_pin - interrupts::GPIO0) as usize];
                 pin.handle_interrupt();
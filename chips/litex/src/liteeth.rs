@@ -9,6 +9,7 @@ use crate::litex_registers::{LiteXSoCRegisterConfiguration, Read, Write};
 use core::cell::Cell;
 use core::slice;
 use kernel::debug;
+use kernel::hil::ethernet::{self, MacAddress};
 use kernel::utilities::cells::{OptionalCell, TakeCell};
 use kernel::utilities::StaticRef;
 use kernel::ErrorCode;
@@ -80,11 +81,6 @@ impl<R: LiteXSoCRegisterConfiguration> LiteEthMacRegisters<R> {
     }
 }
 
-pub trait LiteEthClient {
-    fn tx_done(&self, rc: Result<(), ErrorCode>, packet_buffer: &'static mut [u8]);
-    fn rx_packet(&self, packet: &'static mut [u8], len: usize);
-}
-
 pub struct LiteEth<'a, R: LiteXSoCRegisterConfiguration> {
     mac_regs: StaticRef<LiteEthMacRegisters<R>>,
     mac_memory_base: usize,
@@ -92,9 +88,11 @@ pub struct LiteEth<'a, R: LiteXSoCRegisterConfiguration> {
     slot_size: usize,
     rx_slots: usize,
     tx_slots: usize,
-    client: OptionalCell<&'a dyn LiteEthClient>,
+    mac_address: Cell<MacAddress>,
+    tx_client: OptionalCell<&'a dyn ethernet::TxClient>,
+    rx_client: OptionalCell<&'a dyn ethernet::RxClient>,
     tx_packet: TakeCell<'static, [u8]>,
-    rx_buffer: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
     initialized: Cell<bool>,
 }
 
@@ -106,7 +104,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         slot_size: usize,
         rx_slots: usize,
         tx_slots: usize,
-        rx_buffer: &'static mut [u8],
+        mac_address: MacAddress,
     ) -> LiteEth<'a, R> {
         LiteEth {
             mac_regs,
@@ -115,17 +113,15 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
             slot_size,
             rx_slots,
             tx_slots,
-            client: OptionalCell::empty(),
+            mac_address: Cell::new(mac_address),
+            tx_client: OptionalCell::empty(),
+            rx_client: OptionalCell::empty(),
             tx_packet: TakeCell::empty(),
-            rx_buffer: TakeCell::new(rx_buffer),
+            tx_len: Cell::new(0),
             initialized: Cell::new(false),
         }
     }
 
-    pub fn set_client(&self, client: &'a dyn LiteEthClient) {
-        self.client.set(client);
-    }
-
     pub fn initialize(&self) {
         // Sanity check the memory parameters
         //
@@ -156,7 +152,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
     }
 
     unsafe fn get_slot_buffer<'s>(&'s self, tx: bool, slot_id: usize) -> Option<&'s mut [u8]> {
-        if (tx && slot_id > self.tx_slots) || (!tx && slot_id > self.rx_slots) {
+        if (tx && slot_id >= self.tx_slots) || (!tx && slot_id >= self.rx_slots) {
             return None;
         }
 
@@ -173,85 +169,56 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         ))
     }
 
-    pub fn return_rx_buffer(&self, rx_buffer: &'static mut [u8]) {
-        // Assert that we won't overwrite a buffer
-        assert!(
-            self.rx_buffer.is_none(),
-            "LiteEth: return RX buffer while one is registered"
-        );
-
-        // Put the buffer back
-        self.rx_buffer.replace(rx_buffer);
-
-        // In case we received a packet RX interrupt but couldn't
-        // handle it due to the missing buffer, reenable RX interrupts
-        self.mac_regs.rx_ev().enable_event(LITEETH_RX_EVENT);
-    }
-
     fn rx_interrupt(&self) {
-        // Check whether we have a buffer to read the packet into. If
-        // not, we must disable, but not clear the event and enable it
-        // again as soon as we get the buffer back from the client
-        if self.rx_buffer.is_none() {
-            self.mac_regs.rx_ev().disable_event(LITEETH_RX_EVENT);
+        // Get the frame length. If it exceeds the slot size, discard
+        // the packet
+        let pkt_len = self.mac_regs.rx_length.get() as usize;
+        if pkt_len > self.slot_size {
+            debug!("LiteEth: discarding ethernet packet with len {}", pkt_len);
         } else {
-            // Get the buffer first to be able to check the length
-            let rx_buffer = self.rx_buffer.take().unwrap();
-
-            // Get the frame length. If it exceeds the length of the
-            // rx_buffer, discard the packet, put the buffer back
-            let pkt_len = self.mac_regs.rx_length.get() as usize;
-            if pkt_len > rx_buffer.len() {
-                debug!("LiteEth: discarding ethernet packet with len {}", pkt_len);
-
-                // Acknowledge the interrupt so that the HW may use the slot again
-                self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
-
-                // Replace the buffer
-                self.rx_buffer.replace(rx_buffer);
-            } else {
-                // Obtain the packet slot id
-                let slot_id: usize = self.mac_regs.rx_slot.get().into();
-
-                // Get the slot buffer reference
-                let slot = unsafe {
-                    self.get_slot_buffer(false, slot_id).unwrap() // Unwrap fail = LiteEth: invalid RX slot id
-                };
-
-                // Copy the packet into the buffer
-                rx_buffer[..pkt_len].copy_from_slice(&slot[..pkt_len]);
-
-                // Since all data is copied, acknowledge the interrupt
-                // so that the slot is ready for use again
-                self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
-
-                self.client
-                    .map(move |client| client.rx_packet(rx_buffer, pkt_len));
-            }
+            // Obtain the packet slot id
+            let slot_id: usize = self.mac_regs.rx_slot.get().into();
+
+            // Get the slot buffer reference
+            let slot = unsafe {
+                self.get_slot_buffer(false, slot_id).unwrap() // Unwrap fail = LiteEth: invalid RX slot id
+            };
+
+            // The client is handed the packet straight from the slot,
+            // which stays valid until the event is acknowledged below
+            self.rx_client
+                .map(|client| client.received_frame(&slot[..pkt_len]));
         }
+
+        // Acknowledge the interrupt so that the HW may use the slot again
+        self.mac_regs.rx_ev().clear_event(LITEETH_RX_EVENT);
     }
 
     /// Transmit an ethernet packet over the interface
     ///
     /// For now this will only use a single slot on the interface and
     /// is therefore blocking. A client must wait until a callback to
-    /// `tx_done` prior to sending a new packet.
-    pub fn transmit(
+    /// `transmit_done` prior to sending a new packet.
+    fn transmit_packet(
         &self,
         packet: &'static mut [u8],
         len: usize,
-    ) -> Result<(), (Result<(), ErrorCode>, &'static mut [u8])> {
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        if !self.initialized.get() {
+            return Err((ErrorCode::OFF, packet));
+        }
+
         if packet.len() < len || len > u16::MAX as usize {
-            return Err((Err(ErrorCode::INVAL), packet));
+            return Err((ErrorCode::INVAL, packet));
         }
 
         if self.tx_packet.is_some() {
-            return Err((Err(ErrorCode::BUSY), packet));
+            return Err((ErrorCode::BUSY, packet));
         }
 
         let slot = unsafe { self.get_slot_buffer(true, 0) }.unwrap(); // Unwrap fail = LiteEth: no TX slot
         if slot.len() < len {
-            return Err((Err(ErrorCode::SIZE), packet));
+            return Err((ErrorCode::SIZE, packet));
         }
 
         // Copy the packet into the slot HW buffer
@@ -260,6 +227,7 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         // Put the currently transmitting packet into the designated
         // TakeCell
         self.tx_packet.replace(packet);
+        self.tx_len.set(len);
 
         // Set the slot and packet length
         self.mac_regs.tx_slot.set(0);
@@ -287,8 +255,9 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
 
         // We use only one slot, so this event is unambiguous
         let packet = self.tx_packet.take().unwrap(); // Unwrap fail = LiteEth: TakeCell empty in tx callback
-        self.client
-            .map(move |client| client.tx_done(Ok(()), packet));
+        let len = self.tx_len.get();
+        self.tx_client
+            .map(move |client| client.transmit_done(Ok(()), packet, len));
     }
 
     pub fn service_interrupt(&self) {
@@ -305,3 +274,37 @@ impl<'a, R: LiteXSoCRegisterConfiguration> LiteEth<'a, R> {
         }
     }
 }
+
+impl<'a, R: LiteXSoCRegisterConfiguration> ethernet::Ethernet<'a> for LiteEth<'a, R> {
+    fn set_transmit_client(&self, client: &'a dyn ethernet::TxClient) {
+        self.tx_client.set(client);
+    }
+
+    fn set_receive_client(&self, client: &'a dyn ethernet::RxClient) {
+        self.rx_client.set(client);
+    }
+
+    fn mac_address(&self) -> MacAddress {
+        self.mac_address.get()
+    }
+
+    fn set_mac_address(&self, address: MacAddress) -> Result<(), ErrorCode> {
+        // LiteEth does not filter by destination address in hardware,
+        // so the address is only used by clients building frames
+        self.mac_address.set(address);
+        Ok(())
+    }
+
+    fn link_up(&self) -> bool {
+        // The PHY link state is not exposed through the MAC CSRs
+        self.initialized.get()
+    }
+
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])> {
+        self.transmit_packet(frame, len)
+    }
+}
@@ -32,11 +32,19 @@
 //! * Payload - 2 to 255 bytes
 //!
 //! * CRC - 3 bytes
+//!
+//! ### Connections
+//! In connectable advertising and connection events, the shortcuts switch the
+//! radio between receiving and transmitting `T_IFS_US` after the end of a
+//! packet, while the response is written from the end-of-packet interrupt.
+//! TIMER1 ends receptions that time out.
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel::hil::ble_advertising;
 use kernel::hil::ble_advertising::RadioChannel;
+use kernel::hil::time::{Alarm, AlarmClient, ConvertTicks, Time};
 use kernel::utilities::cells::OptionalCell;
 use kernel::utilities::cells::TakeCell;
 use kernel::utilities::registers::interfaces::{Readable, Writeable};
@@ -530,14 +538,67 @@ register_bitfields! [u32,
 static mut PAYLOAD: [u8; nrf5x::constants::RADIO_PAYLOAD_LENGTH] =
     [0x00; nrf5x::constants::RADIO_PAYLOAD_LENGTH];
 
+#[derive(Clone, Copy, PartialEq)]
+enum ScanState {
+    Off,
+    Listening,
+    /// Sending a scan request.
+    Requesting,
+    AwaitingResponse,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ConnectionState {
+    Off,
+    /// Sending a connectable advertisement.
+    Advertising,
+    AwaitingRequest,
+    /// Sending the response to a request after an advertisement.
+    RespondingToRequest,
+    /// Listening for the central in a connection event.
+    Listening,
+    /// Sending the response to the central.
+    Answering,
+}
+
+/// How long to listen for a request after a connectable advertisement. The
+/// request starts `T_IFS_US` after the advertisement ends; the rest covers
+/// the time until the timer is set, and the longest request's address.
+const REQUEST_WINDOW_US: u32 = 500;
+
 pub struct Radio<'a> {
     registers: StaticRef<RadioRegisters>,
     tx_power: Cell<TxPower>,
     rx_client: OptionalCell<&'a dyn ble_advertising::RxClient>,
     tx_client: OptionalCell<&'a dyn ble_advertising::TxClient>,
+    scan_client: OptionalCell<&'a dyn ble_advertising::ScanClient>,
+    scan_state: Cell<ScanState>,
+    connection_client: OptionalCell<&'a dyn ble_advertising::ConnectionClient>,
+    connection_state: Cell<ConnectionState>,
+    /// Whether the timer ends the current reception if nothing is received.
+    rx_timeout: Cell<bool>,
+    timer: OptionalCell<&'a crate::timer::TimerAlarm<'a>>,
     buffer: TakeCell<'static, [u8]>,
 }
 
+impl<'a> AlarmClient for Radio<'a> {
+    fn alarm(&self) {
+        // A packet whose address is received is allowed to finish
+        if !self.rx_timeout.get() || self.registers.event_address.is_set(Event::READY) {
+            return;
+        }
+        self.rx_timeout.set(false);
+        self.disable_all_interrupts();
+        // An advertisement is done even if nobody sent a request
+        let result = match self.connection_state.get() {
+            ConnectionState::Listening => Err(ErrorCode::NOACK),
+            _ => Ok(()),
+        };
+        self.finish_connection_operation(result);
+        self.enable_interrupts();
+    }
+}
+
 impl<'a> Radio<'a> {
     pub fn new() -> Radio<'a> {
         Radio {
@@ -545,10 +606,20 @@ impl<'a> Radio<'a> {
             tx_power: Cell::new(TxPower::ZerodBm),
             rx_client: OptionalCell::empty(),
             tx_client: OptionalCell::empty(),
+            scan_client: OptionalCell::empty(),
+            scan_state: Cell::new(ScanState::Off),
+            connection_client: OptionalCell::empty(),
+            connection_state: Cell::new(ConnectionState::Off),
+            rx_timeout: Cell::new(false),
+            timer: OptionalCell::empty(),
             buffer: TakeCell::empty(),
         }
     }
 
+    pub fn set_timer_ref(&self, timer: &'a crate::timer::TimerAlarm<'a>) {
+        self.timer.set(timer);
+    }
+
     pub fn is_enabled(&self) -> bool {
         self.registers.mode.matches_all(Mode::MODE::BLE_1MBIT)
     }
@@ -601,12 +672,19 @@ impl<'a> Radio<'a> {
 
         if self.registers.event_ready.is_set(Event::READY) {
             self.registers.event_ready.write(Event::READY::CLEAR);
-            self.registers.event_end.write(Event::READY::CLEAR);
-            self.registers.task_start.write(Task::ENABLE::SET);
+            // In connections, the READY_START shortcut starts the radio
+            if self.connection_state.get() == ConnectionState::Off {
+                self.registers.event_end.write(Event::READY::CLEAR);
+                self.registers.task_start.write(Task::ENABLE::SET);
+            }
         }
 
         if self.registers.event_address.is_set(Event::READY) {
             self.registers.event_address.write(Event::READY::CLEAR);
+            if self.rx_timeout.get() {
+                self.rx_timeout.set(false);
+                self.timer.map(|timer| timer.disarm());
+            }
         }
         if self.registers.event_payload.is_set(Event::READY) {
             self.registers.event_payload.write(Event::READY::CLEAR);
@@ -622,6 +700,18 @@ impl<'a> Radio<'a> {
                 Err(ErrorCode::FAIL)
             };
 
+            if self.scan_state.get() != ScanState::Off {
+                self.handle_scan_end(result);
+                self.enable_interrupts();
+                return;
+            }
+
+            if self.connection_state.get() != ConnectionState::Off {
+                self.handle_connection_end(result);
+                self.enable_interrupts();
+                return;
+            }
+
             match self.registers.state.get() {
                 nrf5x::constants::RADIO_STATE_TXRU
                 | nrf5x::constants::RADIO_STATE_TXIDLE
@@ -652,6 +742,133 @@ impl<'a> Radio<'a> {
         self.enable_interrupts();
     }
 
+    /// Switches between receiving and transmitting, which the shortcuts do
+    /// as soon as the radio is disabled.
+    fn turn_around(&self, shorts: u32) {
+        self.registers.shorts.set(shorts);
+        self.registers.event_ready.write(Event::READY::CLEAR);
+        self.registers.task_disable.write(Task::ENABLE::SET);
+    }
+
+    fn handle_scan_end(&self, result: Result<(), ErrorCode>) {
+        match self.scan_state.get() {
+            ScanState::Requesting => {
+                // The scan request is sent, so listen for the response
+                self.scan_state.set(ScanState::AwaitingResponse);
+                self.turn_around(
+                    (Shortcut::ADDRESS_RSSISTART::SET + Shortcut::DISABLED_RXEN::SET).value,
+                );
+            }
+            ScanState::Listening if result.is_err() => {
+                // Keep listening for a valid packet
+                self.registers.task_start.write(Task::ENABLE::SET);
+            }
+            state => {
+                let mut request_len = 0;
+                if result.is_ok() {
+                    // RSSISAMPLE holds the magnitude of the signal strength in dBm
+                    let rssi = -(self.registers.rssisample.read(RssiSample::RSSISAMPLE) as i16);
+                    self.buffer.map(|buf| unsafe {
+                        let len = cmp::min(PAYLOAD[1] as usize + 2, buf.len());
+                        buf[..len].copy_from_slice(&PAYLOAD[..len]);
+                        request_len = self.scan_client.map_or(0, |client| {
+                            client.advertisement_received(buf, len, rssi as i8)
+                        });
+                        if state == ScanState::Listening && request_len > 0 {
+                            PAYLOAD[..request_len].copy_from_slice(&buf[..request_len]);
+                        }
+                    });
+                }
+                if state == ScanState::Listening && request_len > 0 {
+                    self.scan_state.set(ScanState::Requesting);
+                    self.turn_around(Shortcut::DISABLED_TXEN::SET.value);
+                } else {
+                    self.scan_state.set(ScanState::Off);
+                    self.radio_off();
+                    if let Some(buf) = self.buffer.take() {
+                        self.scan_client.map(|client| client.scan_done(buf, result));
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_connection_end(&self, result: Result<(), ErrorCode>) {
+        match self.connection_state.get() {
+            ConnectionState::Advertising => {
+                // The radio is already turning around to receive a request,
+                // and will transmit the response to it
+                self.registers.shorts.write(
+                    Shortcut::READY_START::SET
+                        + Shortcut::END_DISABLE::SET
+                        + Shortcut::DISABLED_TXEN::SET,
+                );
+                self.connection_state.set(ConnectionState::AwaitingRequest);
+                self.start_rx_timeout(REQUEST_WINDOW_US);
+            }
+            state @ (ConnectionState::AwaitingRequest | ConnectionState::Listening) => {
+                // The radio is already turning around to transmit the
+                // response, so it must not turn around again after it
+                self.registers
+                    .shorts
+                    .write(Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET);
+                let mut response_len = 0;
+                self.buffer.map(|buf| unsafe {
+                    let len = cmp::min(PAYLOAD[1] as usize + 2, buf.len());
+                    buf[..len].copy_from_slice(&PAYLOAD[..len]);
+                    response_len = self.connection_client.map_or(0, |client| {
+                        if state == ConnectionState::Listening {
+                            client.data_received(buf, len, result.is_ok())
+                        } else if result.is_ok() {
+                            client.advertising_request(buf, len)
+                        } else {
+                            0
+                        }
+                    });
+                    response_len = cmp::min(response_len, buf.len());
+                    PAYLOAD[..response_len].copy_from_slice(&buf[..response_len]);
+                });
+                if response_len > 0 {
+                    self.connection_state
+                        .set(if state == ConnectionState::Listening {
+                            ConnectionState::Answering
+                        } else {
+                            ConnectionState::RespondingToRequest
+                        });
+                } else {
+                    self.finish_connection_operation(Ok(()));
+                }
+            }
+            ConnectionState::RespondingToRequest | ConnectionState::Answering => {
+                self.finish_connection_operation(Ok(()))
+            }
+            ConnectionState::Off => (),
+        }
+    }
+
+    /// Ends the current reception, unless a packet starts arriving, after
+    /// `timeout_us` microseconds.
+    fn start_rx_timeout(&self, timeout_us: u32) {
+        self.timer.map(|timer| {
+            self.rx_timeout.set(true);
+            timer.set_alarm(timer.now(), timer.ticks_from_us(timeout_us));
+        });
+    }
+
+    fn finish_connection_operation(&self, result: Result<(), ErrorCode>) {
+        let state = self.connection_state.get();
+        self.connection_state.set(ConnectionState::Off);
+        self.radio_off();
+        if let Some(buf) = self.buffer.take() {
+            self.connection_client.map(|client| match state {
+                ConnectionState::Listening | ConnectionState::Answering => {
+                    client.connection_event_done(buf, result)
+                }
+                _ => client.advertisement_done(buf, result),
+            });
+        }
+    }
+
     pub fn enable_interrupts(&self) {
         self.registers.intenset.write(
             Interrupt::READY::SET
@@ -701,6 +918,7 @@ impl<'a> Radio<'a> {
         self.ble_set_advertising_access_address();
 
         self.ble_set_crc_config();
+        self.ble_set_inter_frame_spacing();
 
         self.set_dma_ptr();
     }
@@ -718,6 +936,14 @@ impl<'a> Radio<'a> {
             .set(nrf5x::constants::RADIO_CRCPOLY_BLE);
     }
 
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 4.1 Timing Requirements
+    // Used by the shortcuts between the end of a packet and the response to it
+    fn ble_set_inter_frame_spacing(&self) {
+        self.registers
+            .tifs
+            .write(InterFrameSpacing::TIFS.val(ble_advertising::T_IFS_US));
+    }
+
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
     // Set access address to 0x8E89BED6
     fn ble_set_advertising_access_address(&self) {
@@ -725,6 +951,15 @@ impl<'a> Radio<'a> {
         self.registers.base0.set(0x89bed600);
     }
 
+    // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1.2 Access Address
+    // and section 3.1.1 CRC Generation
+    // The most significant byte of the access address is the prefix
+    fn ble_set_connection_access_address(&self, access_address: u32, crc_init: u32) {
+        self.registers.prefix0.set(access_address >> 24);
+        self.registers.base0.set(access_address << 8);
+        self.registers.crcinit.set(crc_init & 0x00ffffff);
+    }
+
     // Packet configuration
     // BLUETOOTH SPECIFICATION Version 4.2 [Vol 6, Part B], section 2.1 Packet Format
     //
@@ -811,6 +1046,67 @@ impl<'a> ble_advertising::BleAdvertisementDriver<'a> for Radio<'a> {
     }
 }
 
+impl<'a> ble_advertising::BleScanDriver<'a> for Radio<'a> {
+    fn scan(&self, buf: &'static mut [u8], channel: RadioChannel) {
+        self.buffer.replace(buf);
+        self.scan_state.set(ScanState::Listening);
+        self.ble_initialize(channel);
+        // Sample the RSSI of each packet
+        self.registers
+            .shorts
+            .write(Shortcut::ADDRESS_RSSISTART::SET);
+        self.rx();
+        self.enable_interrupts();
+    }
+
+    fn set_scan_client(&self, client: &'a dyn ble_advertising::ScanClient) {
+        self.scan_client.set(client);
+    }
+}
+
+impl<'a> ble_advertising::BleConnectionDriver<'a> for Radio<'a> {
+    fn transmit_connectable_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        _len: usize,
+        channel: RadioChannel,
+    ) {
+        let res = self.replace_radio_buffer(buf);
+        self.buffer.replace(res);
+        self.connection_state.set(ConnectionState::Advertising);
+        self.ble_initialize(channel);
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_RXEN::SET,
+        );
+        self.tx();
+        self.enable_interrupts();
+    }
+
+    fn connection_event(
+        &self,
+        buf: &'static mut [u8],
+        channel: RadioChannel,
+        access_address: u32,
+        crc_init: u32,
+        timeout_us: u32,
+    ) {
+        self.buffer.replace(buf);
+        self.connection_state.set(ConnectionState::Listening);
+        self.ble_initialize(channel);
+        self.ble_set_connection_access_address(access_address, crc_init);
+        self.registers.shorts.write(
+            Shortcut::READY_START::SET + Shortcut::END_DISABLE::SET + Shortcut::DISABLED_TXEN::SET,
+        );
+        self.rx();
+        self.enable_interrupts();
+        self.start_rx_timeout(timeout_us);
+    }
+
+    fn set_connection_client(&self, client: &'a dyn ble_advertising::ConnectionClient) {
+        self.connection_client.set(client);
+    }
+}
+
 impl ble_advertising::BleConfig for Radio<'_> {
     // The BLE Advertising Driver validates that the `tx_power` is between -20 to 10 dBm but then
     // underlying chip must validate if the current `tx_power` is supported as well
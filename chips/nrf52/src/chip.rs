@@ -79,6 +79,8 @@ impl<'a> Nrf52DefaultPeripherals<'a> {
     pub fn init(&'a self) {
         self.ieee802154_radio.set_timer_ref(&self.timer0);
         self.timer0.set_alarm_client(&self.ieee802154_radio);
+        self.ble_radio.set_timer_ref(&self.timer1);
+        self.timer1.set_alarm_client(&self.ble_radio);
     }
 }
 impl<'a> kernel::platform::chip::InterruptService<DeferredCallTask>
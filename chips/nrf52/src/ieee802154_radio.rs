@@ -1,6 +1,7 @@
 //! IEEE 802.15.4 radio driver for nRF52
 
 use core::cell::Cell;
+use core::cmp;
 use core::convert::TryFrom;
 use kernel;
 use kernel::hil::radio::{self, PowerClient};
@@ -29,6 +30,10 @@ pub const IEEE802154_ACK_TIME: usize = 512; //microseconds = 32 symbols
 pub const IEEE802154_MAX_POLLING_ATTEMPTS: u8 = 4;
 pub const IEEE802154_MIN_BE: u8 = 3;
 pub const IEEE802154_MAX_BE: u8 = 5;
+/// The duration of one energy detection iteration, in microseconds
+const ED_ITERATION_US: u32 = 128;
+/// The factor from the energy level measured to an IEEE 802.15.4 ED value
+const ED_RESULT_FACTOR: u32 = 4;
 pub const RAM_LEN_BITS: usize = 8;
 pub const RAM_S1_BITS: usize = 0;
 pub const PREBUF_LEN_BYTES: usize = 2;
@@ -151,8 +156,14 @@ struct RadioRegisters {
     /// Stop the bit counter
     /// - Address: 0x030 - 0x034
     task_ccastop: WriteOnly<u32, Task::Register>,
+    /// Start the energy detect measurement used in IEEE 802.15.4 mode
+    /// - Address: 0x034 - 0x038
+    task_edstart: WriteOnly<u32, Task::Register>,
+    /// Stop the energy detect measurement
+    /// - Address: 0x038 - 0x03c
+    task_edstop: WriteOnly<u32, Task::Register>,
     /// Reserved
-    _reserved2: [u32; 51],
+    _reserved2: [u32; 49],
     /// Radio has ramped up and is ready to be started
     /// - Address: 0x100 - 0x104
     event_ready: ReadWrite<u32, Event::Register>,
@@ -193,8 +204,12 @@ struct RadioRegisters {
     /// IEEE 802.15.4 length field received
     /// - Address: 0x138 - 0x13c
     event_framestart: ReadWrite<u32, Event::Register>,
-    /// Reserved
-    _reserved5: [u32; 2],
+    /// Sampling of energy detection complete
+    /// - Address: 0x13c - 0x140
+    event_edend: ReadWrite<u32, Event::Register>,
+    /// The sampling of energy detection has stopped
+    /// - Address: 0x140 - 0x144
+    event_edstopped: ReadWrite<u32, Event::Register>,
     /// Wireless medium in idle - clear to send
     /// - Address: 0x144-0x148
     event_ccaidle: ReadWrite<u32, Event::Register>,
@@ -321,7 +336,13 @@ struct RadioRegisters {
     /// - Address: 0x650 - 0x654
     modecnf0: ReadWrite<u32, RadioModeConfig::Register>,
     /// Reserved
-    _reserved16: [u32; 6],
+    _reserved16: [u32; 4],
+    /// Number of iterations to perform the energy detection
+    /// - Address: 0x664 - 0x668
+    edcnt: ReadWrite<u32, EnergyDetectCount::Register>,
+    /// Energy detect level
+    /// - Address: 0x668 - 0x66C
+    edsample: ReadOnly<u32, EnergyDetectSample::Register>,
     /// Clear Channel Assesment (CCA) control register
     /// - Address: 0x66C - 0x670
     ccactrl: ReadWrite<u32, CCAControl::Register>,
@@ -386,8 +407,10 @@ register_bitfields! [u32,
         CRCOK OFFSET(12) NUMBITS(1),
         /// CRCERROR event
         CRCERROR OFFSET(13) NUMBITS(1),
-        /// CCAIDLE event
+        /// FRAMESTART event
         FRAMESTART OFFSET(14) NUMBITS(1),
+        /// EDEND event
+        EDEND OFFSET(15) NUMBITS(1),
         /// CCAIDLE event
         CCAIDLE OFFSET(17) NUMBITS(1),
         /// CCABUSY event
@@ -639,6 +662,17 @@ register_bitfields! [u32,
         CCACORRTHRESH OFFSET(16) NUMBITS(8) [],
         CCACORRCNT OFFSET(24) NUMBITS(8) []
     ],
+    /// Energy detect count register
+    EnergyDetectCount [
+        /// The number of 128 us iterations, minus one, the peak energy is
+        /// measured over
+        EDCNT OFFSET(0) NUMBITS(21)
+    ],
+    /// Energy detect sample register
+    EnergyDetectSample [
+        /// The peak energy level measured
+        EDLVL OFFSET(0) NUMBITS(8)
+    ],
     /// Radio mode configuration register
     RadioModeConfig [
         /// Radio ramp-up time
@@ -673,6 +707,8 @@ pub struct Radio<'p> {
     random_nonce: Cell<u32>,
     channel: Cell<RadioChannel>,
     transmitting: Cell<bool>,
+    energy_detecting: Cell<bool>,
+    ed_client: OptionalCell<&'static dyn radio::EnergyDetectClient>,
     timer0: OptionalCell<&'p crate::timer::TimerAlarm<'p>>,
 }
 
@@ -699,6 +735,8 @@ impl<'p> Radio<'p> {
             random_nonce: Cell::new(0xDEADBEEF),
             channel: Cell::new(RadioChannel::DataChannel26),
             transmitting: Cell::new(false),
+            energy_detecting: Cell::new(false),
+            ed_client: OptionalCell::empty(),
             timer0: OptionalCell::empty(),
         }
     }
@@ -775,6 +813,10 @@ impl<'p> Radio<'p> {
                 && self.registers.state.get() == nrf5x::constants::RADIO_STATE_RXIDLE
             {
                 self.registers.task_ccastart.write(Task::ENABLE::SET);
+            } else if self.energy_detecting.get()
+                && self.registers.state.get() == nrf5x::constants::RADIO_STATE_RXIDLE
+            {
+                self.registers.task_edstart.write(Task::ENABLE::SET);
             } else {
                 self.registers.task_start.write(Task::ENABLE::SET);
             }
@@ -784,6 +826,20 @@ impl<'p> Radio<'p> {
             self.registers.event_framestart.write(Event::READY::CLEAR);
         }
 
+        if self.registers.event_edend.is_set(Event::READY) {
+            self.registers.event_edend.write(Event::READY::CLEAR);
+            self.energy_detecting.set(false);
+            // Scale the level to the range of IEEE 802.15.4 ED values, as
+            // recommended in section 6.20.12.6 of the NRF52840 Datasheet
+            let level = self.registers.edsample.read(EnergyDetectSample::EDLVL);
+            let energy = cmp::min(level * ED_RESULT_FACTOR, 0xff) as u8;
+            // Go back to receiving
+            self.radio_off();
+            self.radio_initialize();
+            self.ed_client
+                .map(|client| client.energy_detect_done(Ok(energy)));
+        }
+
         //   IF we receive the go ahead (channel is clear)
         // THEN start the transmit part of the radio
         if self.registers.event_ccaidle.is_set(Event::READY) {
@@ -883,7 +939,8 @@ impl<'p> Radio<'p> {
                 + Interrupt::CCAIDLE::SET
                 + Interrupt::CCABUSY::SET
                 + Interrupt::END::SET
-                + Interrupt::FRAMESTART::SET,
+                + Interrupt::FRAMESTART::SET
+                + Interrupt::EDEND::SET,
         );
     }
 
@@ -1109,6 +1166,26 @@ impl<'p> kernel::hil::radio::RadioConfig for Radio<'p> {
         }
     }
 
+    fn energy_detect(&self, duration_us: u32) -> Result<(), ErrorCode> {
+        if self.transmitting.get() || self.energy_detecting.get() {
+            return Err(ErrorCode::BUSY);
+        }
+        let iterations = cmp::max(duration_us / ED_ITERATION_US, 1);
+        self.registers
+            .edcnt
+            .write(EnergyDetectCount::EDCNT.val(iterations - 1));
+        self.energy_detecting.set(true);
+
+        // The measurement starts once the receiver has ramped up
+        self.radio_off();
+        self.radio_initialize();
+        Ok(())
+    }
+
+    fn set_energy_detect_client(&self, client: &'static dyn radio::EnergyDetectClient) {
+        self.ed_client.set(client);
+    }
+
     fn set_tx_power(&self, tx_power: i8) -> Result<(), ErrorCode> {
         // Convert u8 to TxPower
         match nrf5x::constants::TxPower::try_from(tx_power as u8) {
@@ -1,8 +1,22 @@
 //! Real Time Clock (RTC) driver.
+//!
+//! The RTC is a counter in the always-on domain, clocked by the 32.768 kHz
+//! low-frequency clock. Scaled by 2^15 it counts seconds, which this driver
+//! uses to keep the calendar date and time, and its compare interrupt
+//! provides a calendar alarm that can wake the chip.
 
-use kernel::utilities::registers::interfaces::Writeable;
+use core::cell::Cell;
+use kernel::dynamic_deferred_call::{
+    DeferredCallHandle, DynamicDeferredCall, DynamicDeferredCallClient,
+};
+use kernel::hil::date_time::{
+    DateTime, DateTimeAlarm, DateTimeAlarmClient, DateTimeClient, DateTimeValues,
+};
+use kernel::utilities::cells::OptionalCell;
+use kernel::utilities::registers::interfaces::{Readable, Writeable};
 use kernel::utilities::registers::{register_bitfields, ReadWrite};
 use kernel::utilities::StaticRef;
+use kernel::ErrorCode;
 
 #[repr(C)]
 pub struct RtcRegisters {
@@ -37,13 +51,39 @@ register_bitfields![u32,
     ]
 ];
 
-pub struct Rtc {
+/// Scales the 32.768 kHz counter to one count per second.
+const SCALE_SECONDS: u32 = 15;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Operation {
+    Get,
+    Set,
+}
+
+pub struct Rtc<'a> {
     registers: StaticRef<RtcRegisters>,
+    /// Seconds since the Unix epoch when the scaled counter was 0.
+    epoch_offset: Cell<u64>,
+    alarm: Cell<Option<u64>>,
+    operation: Cell<Option<Operation>>,
+    client: OptionalCell<&'a dyn DateTimeClient>,
+    alarm_client: OptionalCell<&'a dyn DateTimeAlarmClient>,
+    deferred_caller: OptionalCell<&'static DynamicDeferredCall>,
+    deferred_handle: OptionalCell<DeferredCallHandle>,
 }
 
-impl Rtc {
-    pub const fn new(base: StaticRef<RtcRegisters>) -> Rtc {
-        Rtc { registers: base }
+impl<'a> Rtc<'a> {
+    pub const fn new(base: StaticRef<RtcRegisters>) -> Rtc<'a> {
+        Rtc {
+            registers: base,
+            epoch_offset: Cell::new(0),
+            alarm: Cell::new(None),
+            operation: Cell::new(None),
+            client: OptionalCell::empty(),
+            alarm_client: OptionalCell::empty(),
+            deferred_caller: OptionalCell::empty(),
+            deferred_handle: OptionalCell::empty(),
+        }
     }
 
     /// Disable the RTC so it does not generate interrupts.
@@ -56,4 +96,121 @@ impl Rtc {
         // Set the compare time to as large as possible
         regs.rtccmp.set(0xFFFF_FFFF);
     }
+
+    /// Starts counting seconds for the date and time, from the Unix epoch.
+    /// `get_date_time` and `set_date_time` complete through the deferred
+    /// call.
+    pub fn initialize(
+        &self,
+        deferred_caller: &'static DynamicDeferredCall,
+        deferred_handle: DeferredCallHandle,
+    ) {
+        let regs = self.registers;
+        self.deferred_caller.set(deferred_caller);
+        self.deferred_handle.set(deferred_handle);
+
+        regs.rtccmp.set(0xFFFF_FFFF);
+        regs.rtclo.set(0);
+        regs.rtchi.set(0);
+        regs.rtccfg
+            .write(rtccfg::enalways::SET + rtccfg::scale.val(SCALE_SECONDS));
+    }
+
+    fn now(&self) -> u64 {
+        self.epoch_offset.get() + self.registers.rtcs.get() as u64
+    }
+
+    /// Programs the compare register for the armed alarm, if any.
+    fn program_alarm(&self) {
+        let compare = match self.alarm.get() {
+            // An alarm before the counter started fires immediately
+            Some(alarm) => alarm.saturating_sub(self.epoch_offset.get()),
+            None => 0xFFFF_FFFF,
+        };
+        self.registers
+            .rtccmp
+            .set(core::cmp::min(compare, 0xFFFF_FFFF) as u32);
+    }
+
+    fn start_operation(&self, operation: Operation) -> Result<(), ErrorCode> {
+        if self.operation.get().is_some() {
+            return Err(ErrorCode::BUSY);
+        }
+        self.deferred_handle.map_or(Err(ErrorCode::OFF), |handle| {
+            self.operation.set(Some(operation));
+            self.deferred_caller.map(|caller| caller.set(*handle));
+            Ok(())
+        })
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = self.registers;
+        if regs.rtccfg.is_set(rtccfg::cmpip) {
+            // The interrupt is pending while the counter is past the
+            // compare value.
+            regs.rtccmp.set(0xFFFF_FFFF);
+            if self.alarm.take().is_some() {
+                self.alarm_client.map(|client| client.alarm_fired());
+            }
+        }
+    }
+}
+
+impl<'a> DateTime<'a> for Rtc<'a> {
+    fn get_date_time(&self) -> Result<(), ErrorCode> {
+        self.start_operation(Operation::Get)
+    }
+
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        let seconds = date_time.to_unix_seconds()?;
+        self.start_operation(Operation::Set)?;
+        // Restarting the counter keeps it far from overflowing
+        self.registers.rtclo.set(0);
+        self.registers.rtchi.set(0);
+        self.epoch_offset.set(seconds);
+        self.program_alarm();
+        Ok(())
+    }
+
+    fn set_client(&self, client: &'a dyn DateTimeClient) {
+        self.client.set(client);
+    }
+}
+
+impl<'a> DateTimeAlarm<'a> for Rtc<'a> {
+    fn set_alarm(&self, date_time: DateTimeValues) -> Result<(), ErrorCode> {
+        self.alarm.set(Some(date_time.to_unix_seconds()?));
+        self.program_alarm();
+        Ok(())
+    }
+
+    fn get_alarm(&self) -> Option<DateTimeValues> {
+        self.alarm.get().map(DateTimeValues::from_unix_seconds)
+    }
+
+    fn disarm_alarm(&self) -> Result<(), ErrorCode> {
+        self.alarm.set(None);
+        self.program_alarm();
+        Ok(())
+    }
+
+    fn set_alarm_client(&self, client: &'a dyn DateTimeAlarmClient) {
+        self.alarm_client.set(client);
+    }
+}
+
+impl<'a> DynamicDeferredCallClient for Rtc<'a> {
+    fn call(&self, _handle: DeferredCallHandle) {
+        match self.operation.take() {
+            Some(Operation::Get) => {
+                let date_time = DateTimeValues::from_unix_seconds(self.now());
+                self.client
+                    .map(|client| client.get_date_time_done(Ok(date_time)));
+            }
+            Some(Operation::Set) => {
+                self.client.map(|client| client.set_date_time_done(Ok(())));
+            }
+            None => {}
+        }
+    }
 }
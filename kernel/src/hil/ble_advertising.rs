@@ -68,6 +68,101 @@ pub trait TxClient {
     fn transmit_event(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>);
 }
 
+/// A radio that can scan the advertising channels, actively or passively.
+///
+/// As with connections, a scan request must be sent `T_IFS_US` after the
+/// advertisement it answers, so the radio calls the client directly from its
+/// end-of-packet handling to ask whether to send one.
+pub trait BleScanDriver<'a> {
+    /// Listens on `channel` until an advertising channel PDU is received, and
+    /// passes it to `ScanClient::advertisement_received`. If the client
+    /// provides a scan request, the radio transmits it and then listens until
+    /// the next PDU, which is passed to the client as well. Once done with the
+    /// channel, the radio calls `ScanClient::scan_done`.
+    fn scan(&self, buf: &'static mut [u8], channel: RadioChannel);
+
+    fn set_scan_client(&self, client: &'a dyn ScanClient);
+}
+
+pub trait ScanClient {
+    /// Called when a PDU with a valid CRC is received while scanning. `buf`
+    /// holds the `len` bytes of the PDU, and `rssi` is its received signal
+    /// strength in dBm. After an advertisement, the client may overwrite
+    /// `buf` with a scan request and return its length, or return 0 to not
+    /// send one. The return value is ignored for the PDU after a scan request.
+    fn advertisement_received(&self, buf: &mut [u8], len: usize, rssi: i8) -> usize;
+
+    /// Called when the radio is done scanning a channel.
+    fn scan_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>);
+}
+
+/// The inter frame space: the time between the end of a packet and the start
+/// of the response to it, in microseconds.
+pub const T_IFS_US: u32 = 150;
+
+/// A radio that can take part in connections as a peripheral.
+///
+/// Packets in a connection are exchanged `T_IFS_US` apart, which leaves no time
+/// to go through the kernel's scheduler. The radio instead calls the client
+/// directly when a packet is received, from its end-of-packet handling, and
+/// the client writes the response into the same buffer while the radio turns
+/// around. The radio then transmits the response `T_IFS_US` after the end of
+/// the received packet.
+///
+/// Buffers hold a complete PDU, starting with its two-byte header.
+pub trait BleConnectionDriver<'a> {
+    /// Transmits a connectable advertising PDU on `channel`, then listens on
+    /// the same channel for a request from a scanner or initiator. If one is
+    /// received, `ConnectionClient::advertising_request` is called and may
+    /// provide a response. `ConnectionClient::advertisement_done` is called
+    /// once the radio is done with the channel.
+    fn transmit_connectable_advertisement(
+        &self,
+        buf: &'static mut [u8],
+        len: usize,
+        channel: RadioChannel,
+    );
+
+    /// Listens on a data channel for a PDU from the central, for at most
+    /// `timeout_us` microseconds, using the connection's access address and
+    /// CRC initialization value. If one is received,
+    /// `ConnectionClient::data_received` is called to provide the response.
+    /// `ConnectionClient::connection_event_done` is called once the response
+    /// is sent or the timeout passes.
+    fn connection_event(
+        &self,
+        buf: &'static mut [u8],
+        channel: RadioChannel,
+        access_address: u32,
+        crc_init: u32,
+        timeout_us: u32,
+    );
+
+    fn set_connection_client(&self, client: &'a dyn ConnectionClient);
+}
+
+pub trait ConnectionClient {
+    /// Called when a request with a valid CRC is received after a connectable
+    /// advertisement. `buf` holds the `len` bytes of the request. The client
+    /// may overwrite it with a response and return the response's length, or
+    /// return 0 to not respond.
+    fn advertising_request(&self, buf: &mut [u8], len: usize) -> usize;
+
+    /// Called when a connectable advertisement is done.
+    fn advertisement_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>);
+
+    /// Called when a data channel PDU is received, with `crc_ok` indicating
+    /// whether its CRC is valid. `buf` holds the `len` bytes of the PDU. The
+    /// client overwrites it with the response and returns the response's
+    /// length.
+    fn data_received(&self, buf: &mut [u8], len: usize, crc_ok: bool) -> usize;
+
+    /// Called when a connection event is done. `result` is `Ok` if a packet
+    /// was received and answered, and `Err(ErrorCode::NOACK)` if nothing was
+    /// received before the timeout.
+    fn connection_event_done(&self, buf: &'static mut [u8], result: Result<(), ErrorCode>);
+}
+
 // Bluetooth Core Specification:Vol. 6. Part B, section 1.4.1 Advertising and Data Channel Indices
 #[derive(PartialEq, Debug, Copy, Clone)]
 pub enum RadioChannel {
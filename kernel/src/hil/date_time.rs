@@ -0,0 +1,321 @@
+//! Interfaces for real-time clocks that keep the calendar date and time.
+//!
+//! Unlike the counters and alarms in `hil::time`, a real-time clock counts
+//! calendar time, usually in an always-on power domain that keeps running
+//! while the rest of the chip sleeps. Calendar alarms let the chip sleep until
+//! a given date and time.
+//!
+//! Dates are in the Gregorian calendar, from 1970 onwards, and times have a
+//! resolution of one second. There are no time zones or daylight saving time:
+//! clocks are expected to keep UTC.
+
+use core::convert::TryFrom;
+
+use crate::ErrorCode;
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// Days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar.
+const DAYS_TO_UNIX_EPOCH: u64 = 719468;
+
+/// Days in a 400-year cycle of the Gregorian calendar.
+const DAYS_PER_ERA: u64 = 146097;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DayOfWeek {
+    Sunday = 0,
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+}
+
+impl TryFrom<usize> for DayOfWeek {
+    type Error = ErrorCode;
+
+    fn try_from(day: usize) -> Result<DayOfWeek, ErrorCode> {
+        match day {
+            0 => Ok(DayOfWeek::Sunday),
+            1 => Ok(DayOfWeek::Monday),
+            2 => Ok(DayOfWeek::Tuesday),
+            3 => Ok(DayOfWeek::Wednesday),
+            4 => Ok(DayOfWeek::Thursday),
+            5 => Ok(DayOfWeek::Friday),
+            6 => Ok(DayOfWeek::Saturday),
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Month {
+    January = 1,
+    February = 2,
+    March = 3,
+    April = 4,
+    May = 5,
+    June = 6,
+    July = 7,
+    August = 8,
+    September = 9,
+    October = 10,
+    November = 11,
+    December = 12,
+}
+
+impl TryFrom<usize> for Month {
+    type Error = ErrorCode;
+
+    fn try_from(month: usize) -> Result<Month, ErrorCode> {
+        match month {
+            1 => Ok(Month::January),
+            2 => Ok(Month::February),
+            3 => Ok(Month::March),
+            4 => Ok(Month::April),
+            5 => Ok(Month::May),
+            6 => Ok(Month::June),
+            7 => Ok(Month::July),
+            8 => Ok(Month::August),
+            9 => Ok(Month::September),
+            10 => Ok(Month::October),
+            11 => Ok(Month::November),
+            12 => Ok(Month::December),
+            _ => Err(ErrorCode::INVAL),
+        }
+    }
+}
+
+/// A calendar date and time of day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTimeValues {
+    pub year: u16,
+    pub month: Month,
+    /// The day of the month, starting at 1.
+    pub day: u8,
+    /// The day of the week. This follows from the date, and is ignored when
+    /// setting a clock or an alarm.
+    pub day_of_week: DayOfWeek,
+    pub hour: u8,
+    pub minute: u8,
+    pub seconds: u8,
+}
+
+fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: Month) -> u8 {
+    match month {
+        Month::February if is_leap_year(year) => 29,
+        Month::February => 28,
+        Month::April | Month::June | Month::September | Month::November => 30,
+        _ => 31,
+    }
+}
+
+impl DateTimeValues {
+    /// Converts seconds since the Unix epoch (1970-01-01 00:00:00 UTC) to a
+    /// date and time. Years past 65535 wrap around.
+    pub fn from_unix_seconds(seconds: u64) -> DateTimeValues {
+        let days = seconds / SECONDS_PER_DAY;
+        let time = seconds % SECONDS_PER_DAY;
+
+        // Counts years from March, so that the leap day is the last day of
+        // the year. See http://howardhinnant.github.io/date_algorithms.html
+        let days_since_0000_03_01 = days + DAYS_TO_UNIX_EPOCH;
+        let era = days_since_0000_03_01 / DAYS_PER_ERA;
+        let day_of_era = days_since_0000_03_01 % DAYS_PER_ERA;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        DateTimeValues {
+            year: year as u16,
+            // Both are in range by construction
+            month: Month::try_from(month as usize).unwrap_or(Month::January),
+            day: day as u8,
+            // 1970-01-01 was a Thursday
+            day_of_week: DayOfWeek::try_from(((days + 4) % 7) as usize)
+                .unwrap_or(DayOfWeek::Sunday),
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            seconds: (time % 60) as u8,
+        }
+    }
+
+    /// Converts the date and time to seconds since the Unix epoch. Returns
+    /// `INVAL` if any field is out of range or the date is before 1970.
+    pub fn to_unix_seconds(&self) -> Result<u64, ErrorCode> {
+        if self.year < 1970
+            || self.day == 0
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour >= 24
+            || self.minute >= 60
+            || self.seconds >= 60
+        {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let month = self.month as u64;
+        let year = self.year as u64 - if month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * month_from_march + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * DAYS_PER_ERA + day_of_era - DAYS_TO_UNIX_EPOCH;
+
+        Ok(days * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.seconds as u64)
+    }
+}
+
+/// A clock that keeps the calendar date and time.
+///
+/// Reading and setting the clock are split-phase, as many real-time clocks
+/// are external chips on a bus. Only one operation can be in progress at a
+/// time.
+pub trait DateTime<'a> {
+    /// Reads the current date and time. On success, `get_date_time_done` is
+    /// called with the result. Returns `BUSY` if another operation is in
+    /// progress.
+    fn get_date_time(&self) -> Result<(), ErrorCode>;
+
+    /// Sets the current date and time. On success, `set_date_time_done` is
+    /// called once the clock is updated. Returns `INVAL` if `date_time` is
+    /// not a valid date and time, and `BUSY` if another operation is in
+    /// progress.
+    fn set_date_time(&self, date_time: DateTimeValues) -> Result<(), ErrorCode>;
+
+    fn set_client(&self, client: &'a dyn DateTimeClient);
+}
+
+pub trait DateTimeClient {
+    /// Called when a `get_date_time` call completes.
+    fn get_date_time_done(&self, datetime: Result<DateTimeValues, ErrorCode>);
+
+    /// Called when a `set_date_time` call completes.
+    fn set_date_time_done(&self, result: Result<(), ErrorCode>);
+}
+
+/// A calendar alarm on a `DateTime` clock.
+///
+/// The alarm fires once, when the clock reaches the alarm date and time. It
+/// must fire even if the chip is sleeping at the time, and wake it up: a
+/// clock that cannot wake the chip from the sleep state the kernel enters
+/// when idle should not implement this trait. Setting the clock does not move
+/// the alarm, which stays at the same date and time.
+pub trait DateTimeAlarm<'a> {
+    /// Arms the alarm for `date_time`, replacing any alarm that was set
+    /// before. If the date and time has already passed, the alarm fires as
+    /// soon as possible. Returns `INVAL` if `date_time` is not a valid date
+    /// and time.
+    fn set_alarm(&self, date_time: DateTimeValues) -> Result<(), ErrorCode>;
+
+    /// Returns the date and time the alarm is set for, if it is armed.
+    fn get_alarm(&self) -> Option<DateTimeValues>;
+
+    /// Disarms the alarm, so that it does not fire.
+    fn disarm_alarm(&self) -> Result<(), ErrorCode>;
+
+    fn set_alarm_client(&self, client: &'a dyn DateTimeAlarmClient);
+}
+
+pub trait DateTimeAlarmClient {
+    /// Called when the alarm date and time is reached. The alarm is disarmed
+    /// when this is called.
+    fn alarm_fired(&self);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date_time(year: u16, month: Month, day: u8, hour: u8, minute: u8) -> DateTimeValues {
+        DateTimeValues {
+            year,
+            month,
+            day,
+            day_of_week: DayOfWeek::Sunday,
+            hour,
+            minute,
+            seconds: 0,
+        }
+    }
+
+    #[test]
+    fn unix_epoch() {
+        let epoch = DateTimeValues::from_unix_seconds(0);
+        assert_eq!(
+            epoch,
+            DateTimeValues {
+                day_of_week: DayOfWeek::Thursday,
+                ..date_time(1970, Month::January, 1, 0, 0)
+            }
+        );
+        assert_eq!(epoch.to_unix_seconds(), Ok(0));
+    }
+
+    #[test]
+    fn leap_days() {
+        let leap_day = DateTimeValues::from_unix_seconds(951_825_600);
+        assert_eq!(
+            leap_day,
+            DateTimeValues {
+                day_of_week: DayOfWeek::Tuesday,
+                ..date_time(2000, Month::February, 29, 12, 0)
+            }
+        );
+        assert_eq!(leap_day.to_unix_seconds(), Ok(951_825_600));
+
+        assert_eq!(
+            date_time(2100, Month::February, 29, 0, 0).to_unix_seconds(),
+            Err(ErrorCode::INVAL)
+        );
+        assert_eq!(
+            date_time(2100, Month::March, 1, 0, 0).to_unix_seconds(),
+            Ok(4_107_542_400)
+        );
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut seconds = 0;
+        while seconds < 200 * 365 * SECONDS_PER_DAY {
+            let date_time = DateTimeValues::from_unix_seconds(seconds);
+            assert_eq!(date_time.to_unix_seconds(), Ok(seconds));
+            seconds += 3 * SECONDS_PER_DAY + 3723;
+        }
+    }
+
+    #[test]
+    fn invalid_fields() {
+        assert!(date_time(1969, Month::December, 31, 0, 0)
+            .to_unix_seconds()
+            .is_err());
+        assert!(date_time(2022, Month::April, 31, 0, 0)
+            .to_unix_seconds()
+            .is_err());
+        assert!(date_time(2022, Month::April, 0, 0, 0)
+            .to_unix_seconds()
+            .is_err());
+        assert!(date_time(2022, Month::April, 30, 24, 0)
+            .to_unix_seconds()
+            .is_err());
+        assert!(date_time(2022, Month::April, 30, 23, 60)
+            .to_unix_seconds()
+            .is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Interface for sending and receiving Ethernet frames.
+//!
+//! Frames passed through this interface are Ethernet II frames starting with
+//! the destination MAC address. The preamble, start frame delimiter and frame
+//! check sequence are handled by the device and are not included.
+//!
+//! Devices are not required to filter received frames by destination address,
+//! so receive clients must be prepared to handle frames that are not addressed
+//! to them.
+
+use crate::ErrorCode;
+
+/// Length of the Ethernet II header: destination MAC address, source MAC
+/// address and EtherType.
+pub const HEADER_LEN: usize = 14;
+
+/// Maximum length of a frame without a VLAN tag, excluding the frame check
+/// sequence.
+pub const MAX_FRAME_LEN: usize = HEADER_LEN + 1500;
+
+/// A 48-bit Ethernet MAC address.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct MacAddress(pub [u8; 6]);
+
+impl MacAddress {
+    pub const BROADCAST: MacAddress = MacAddress([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Returns whether this is a group (multicast or broadcast) address.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+pub trait TxClient {
+    /// Called when a frame passed to `transmit` has been sent, or when sending
+    /// it failed. `len` is the length that was passed to `transmit`.
+    fn transmit_done(&self, result: Result<(), ErrorCode>, frame: &'static mut [u8], len: usize);
+}
+
+pub trait RxClient {
+    /// Called when a frame is received. The frame is only valid for the
+    /// duration of the call.
+    fn received_frame(&self, frame: &[u8]);
+}
+
+pub trait Ethernet<'a> {
+    fn set_transmit_client(&self, client: &'a dyn TxClient);
+    fn set_receive_client(&self, client: &'a dyn RxClient);
+
+    /// The MAC address used by this device.
+    fn mac_address(&self) -> MacAddress;
+
+    /// Change the MAC address used by this device.
+    fn set_mac_address(&self, address: MacAddress) -> Result<(), ErrorCode>;
+
+    /// Returns whether the link is up. Devices that cannot detect the link
+    /// state report the link as up.
+    fn link_up(&self) -> bool;
+
+    /// Send the first `len` bytes of `frame`. The frame must already contain
+    /// the Ethernet header. On success, `transmit_done` is called once the
+    /// frame has been sent.
+    ///
+    /// Returns `BUSY` if another frame is being sent, `SIZE` if the frame is
+    /// too long for the device and `OFF` if the link is down.
+    fn transmit(
+        &self,
+        frame: &'static mut [u8],
+        len: usize,
+    ) -> Result<(), (ErrorCode, &'static mut [u8])>;
+}
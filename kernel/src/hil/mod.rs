@@ -6,9 +6,11 @@ pub mod ble_advertising;
 pub mod bus8080;
 pub mod crc;
 pub mod dac;
+pub mod date_time;
 pub mod digest;
 pub mod eic;
 pub mod entropy;
+pub mod ethernet;
 pub mod flash;
 pub mod gpio;
 pub mod gpio_async;
@@ -31,6 +31,12 @@ pub trait PowerClient {
     fn changed(&self, on: bool);
 }
 
+pub trait EnergyDetectClient {
+    /// Called when an energy detection is done, with the peak energy measured
+    /// on the channel as an IEEE 802.15.4 ED value, from 0 to 255.
+    fn energy_detect_done(&self, result: Result<u8, ErrorCode>);
+}
+
 /// These constants are used for interacting with the SPI buffer, which contains
 /// a 1-byte SPI command, a 1-byte PHY header, and then the 802.15.4 frame. In
 /// theory, the number of extra bytes in front of the frame can depend on the
@@ -101,6 +107,12 @@ pub trait RadioConfig {
     fn set_pan(&self, id: u16);
     fn set_tx_power(&self, power: i8) -> Result<(), ErrorCode>;
     fn set_channel(&self, chan: u8) -> Result<(), ErrorCode>;
+
+    /// Measures the energy on the current channel for about `duration_us`
+    /// microseconds, and reports the result to the energy detect client.
+    /// Returns `BUSY` while the radio is transmitting or already measuring.
+    fn energy_detect(&self, duration_us: u32) -> Result<(), ErrorCode>;
+    fn set_energy_detect_client(&self, client: &'static dyn EnergyDetectClient);
 }
 
 pub trait RadioData {
@@ -31,6 +31,21 @@ pub trait HumidityClient {
     fn callback(&self, value: usize);
 }
 
+/// A basic interface for a barometric pressure sensor
+pub trait PressureDriver<'a> {
+    fn set_client(&self, client: &'a dyn PressureClient);
+    fn read_pressure(&self) -> Result<(), ErrorCode>;
+}
+
+/// Client for receiving pressure readings.
+pub trait PressureClient {
+    /// Called when a pressure reading has completed.
+    ///
+    /// - `pressure`: the pressure in pascals, or the error that stopped the
+    /// reading.
+    fn callback(&self, pressure: Result<u32, ErrorCode>);
+}
+
 /// A basic interface for a Air Quality sensor
 pub trait AirQualityDriver<'a> {
     /// Set the client to be notified when the capsule has data ready.
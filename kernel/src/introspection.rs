@@ -123,6 +123,17 @@ impl KernelInfo {
             .process_map_or(0, app, |process| process.debug_timeslice_expiration_count())
     }
 
+    /// Returns the total number of microseconds this app has spent executing
+    /// since it was last started, as measured by the scheduler timer.
+    pub fn app_cpu_time_us(
+        &self,
+        app: ProcessId,
+        _capability: &dyn ProcessManagementCapability,
+    ) -> u64 {
+        self.kernel
+            .process_map_or(0, app, |process| process.debug_cpu_time_us())
+    }
+
     /// Returns a tuple of the (the number of grants in the grant region this
     /// app has allocated, total number of grants that exist in the system).
     pub fn number_app_grant_uses(
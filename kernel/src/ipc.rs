@@ -6,6 +6,7 @@
 use crate::capabilities::MemoryAllocationCapability;
 use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
 use crate::kernel::Kernel;
+use crate::platform::mpu;
 use crate::process;
 use crate::process::ProcessId;
 use crate::processbuffer::ReadableProcessBuffer;
@@ -82,7 +83,12 @@ impl<const NUM_PROCS: u8> IPC<NUM_PROCS> {
                         self.data
                             .kernel
                             .process_map_or(None, schedule_on, |process| {
-                                process.add_mpu_region(slice.ptr(), slice.len(), slice.len())
+                                process.add_mpu_region(
+                                    slice.ptr(),
+                                    slice.len(),
+                                    slice.len(),
+                                    mpu::Permissions::ReadWriteOnly,
+                                )
                             });
                         (slice.len(), slice.ptr() as usize)
                     }
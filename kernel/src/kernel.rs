@@ -456,6 +456,9 @@ impl Kernel {
                             self.process_map_or((), appid, |process| {
                                 let (reason, time_executed) =
                                     self.do_process(resources, chip, process, ipc, timeslice_us);
+                                if let Some(time) = time_executed {
+                                    process.debug_cpu_time_executed(time);
+                                }
                                 scheduler.result(reason, time_executed);
                             });
                         }
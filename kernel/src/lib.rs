@@ -104,10 +104,12 @@ pub mod grant;
 pub mod hil;
 pub mod introspection;
 pub mod ipc;
+pub mod network_permissions;
 pub mod platform;
 pub mod process;
 pub mod processbuffer;
 pub mod scheduler;
+pub mod shared_memory;
 pub mod storage_permissions;
 pub mod syscall;
 pub mod upcall;
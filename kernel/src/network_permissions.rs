@@ -0,0 +1,49 @@
+//! Network permissions of a process.
+
+/// The UDP ports and remote addresses a process may use, as declared in its
+/// TBF header.
+///
+/// Networking capsules use these to restrict what each process can do on top
+/// of any board-wide restrictions. Port ranges are inclusive, and remote
+/// addresses are given as an IPv6 prefix, where a prefix length of 0 allows
+/// any address.
+#[derive(Clone, Copy, Debug)]
+pub struct NetworkPermissions {
+    local_ports: (u16, u16),
+    remote_ports: (u16, u16),
+    remote_prefix: [u8; 16],
+    remote_prefix_len: usize,
+}
+
+impl NetworkPermissions {
+    pub(crate) fn new(
+        local_ports: (u16, u16),
+        remote_ports: (u16, u16),
+        remote_prefix: [u8; 16],
+        remote_prefix_len: usize,
+    ) -> Self {
+        NetworkPermissions {
+            local_ports,
+            remote_ports,
+            remote_prefix,
+            remote_prefix_len: core::cmp::min(remote_prefix_len, 128),
+        }
+    }
+
+    /// The lowest and highest local port the process may bind to and send
+    /// from.
+    pub fn local_ports(&self) -> (u16, u16) {
+        self.local_ports
+    }
+
+    /// The lowest and highest remote port the process may send to.
+    pub fn remote_ports(&self) -> (u16, u16) {
+        self.remote_ports
+    }
+
+    /// The prefix, and its length in bits, of the remote addresses the
+    /// process may communicate with.
+    pub fn remote_prefix(&self) -> ([u8; 16], usize) {
+        (self.remote_prefix, self.remote_prefix_len)
+    }
+}
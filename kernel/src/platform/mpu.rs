@@ -268,6 +268,34 @@ pub trait MPU {
         }
     }
 
+    /// Makes the start of app-owned memory inaccessible in user mode.
+    ///
+    /// Processes place their stack at the start of their memory and grow it
+    /// down towards the start. An implementation that can exclude part of the
+    /// MPU region for app-owned memory should exclude at least the first
+    /// `min_size` bytes of it and store the change in `config`, so that a
+    /// process that overflows its stack faults inside the excluded memory.
+    ///
+    /// # Arguments
+    ///
+    /// - `min_size`: minimum size of the guard region
+    /// - `config`:   MPU region configuration
+    ///
+    /// # Return Value
+    ///
+    /// Returns the start and size of the guard region. If the MPU cannot
+    /// exclude part of app-owned memory, or the MPU region for app-owned
+    /// memory was never created, returns None. If None is returned no changes
+    /// are made.
+    #[allow(unused_variables)]
+    fn allocate_stack_guard_region(
+        &self,
+        min_size: usize,
+        config: &mut Self::MpuConfig,
+    ) -> Option<Region> {
+        None
+    }
+
     /// Configures the MPU with the provided region configuration.
     ///
     /// An implementation must ensure that all memory locations not covered by
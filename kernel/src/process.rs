@@ -10,6 +10,7 @@ use crate::capabilities;
 use crate::errorcode::ErrorCode;
 use crate::ipc;
 use crate::kernel::Kernel;
+use crate::network_permissions;
 use crate::platform::mpu::{self};
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
 use crate::storage_permissions;
@@ -176,6 +177,14 @@ impl ProcessId {
         self.kernel
             .process_map_or(None, *self, |process| process.get_storage_permissions())
     }
+
+    /// Get the network permissions for the process. These restrict the UDP
+    /// ports and remote addresses the process may use. Returns `None` if the
+    /// process does not declare network permissions.
+    pub fn get_network_permissions(&self) -> Option<network_permissions::NetworkPermissions> {
+        self.kernel
+            .process_map_or(None, *self, |process| process.get_network_permissions())
+    }
 }
 
 /// This trait represents a generic process that the Tock scheduler can
@@ -263,6 +272,13 @@ pub trait Process {
     /// this will return `Some(Some(completion_code))`.
     fn get_completion_code(&self) -> Option<Option<u32>>;
 
+    /// Get the reason the process most recently faulted.
+    ///
+    /// Returns `None` if the process has never faulted. The reason is recorded
+    /// before the process's fault policy is consulted, so fault policies and
+    /// process printers can use it to report why the process faulted.
+    fn get_fault_reason(&self) -> Option<FaultReason>;
+
     /// Stop and clear a process's state, putting it into the `Terminated`
     /// state.
     ///
@@ -414,6 +430,19 @@ pub trait Process {
     /// Returns `None` if the process has no storage permissions.
     fn get_storage_permissions(&self) -> Option<storage_permissions::StoragePermissions>;
 
+    /// Check whether this process is allowed to communicate with the IPC
+    /// service named `service_name`.
+    ///
+    /// Processes that do not declare any IPC services in their TBF header are
+    /// not allowed to communicate with any service.
+    fn ipc_service_permitted(&self, service_name: &str) -> bool;
+
+    /// Get the network permissions for the process.
+    ///
+    /// Returns `None` if the process does not declare network permissions in
+    /// its TBF header.
+    fn get_network_permissions(&self) -> Option<network_permissions::NetworkPermissions>;
+
     // mpu
 
     /// Configure the MPU to use the process's allocated regions.
@@ -423,8 +452,8 @@ pub trait Process {
     fn setup_mpu(&self);
 
     /// Allocate a new MPU region for the process that is at least
-    /// `min_region_size` bytes and lies within the specified stretch of
-    /// unallocated memory.
+    /// `min_region_size` bytes, lies within the specified stretch of
+    /// unallocated memory, and grants the process `permissions` to the region.
     ///
     /// It is not valid to call this function when the process is inactive (i.e.
     /// the process will not run again).
@@ -433,6 +462,7 @@ pub trait Process {
         unallocated_memory_start: *const u8,
         unallocated_memory_size: usize,
         min_region_size: usize,
+        permissions: mpu::Permissions,
     ) -> Option<mpu::Region>;
 
     /// Removes an MPU region from the process that has been previouly added with
@@ -611,6 +641,16 @@ pub trait Process {
     /// Increment the number of times the process has exceeded its timeslice.
     fn debug_timeslice_expired(&self);
 
+    /// Returns the total time, in microseconds, this process has spent
+    /// executing since it was last started. Only time accounted by a scheduler
+    /// timer is included, so processes run cooperatively (without a timeslice)
+    /// do not accumulate execution time.
+    fn debug_cpu_time_us(&self) -> u64;
+
+    /// Add `time_us` microseconds to the total time this process has spent
+    /// executing.
+    fn debug_cpu_time_executed(&self, time_us: u32);
+
     /// Increment the number of times the process called a syscall and record
     /// the last syscall that was called.
     fn debug_syscall_called(&self, last_syscall: Syscall);
@@ -759,6 +799,19 @@ impl<'a> ProcessStateCell<'a> {
     }
 }
 
+/// Why a process faulted, to the extent the kernel is able to determine it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum FaultReason {
+    /// The kernel does not know of a more specific cause for the fault. This
+    /// includes faults forced by the kernel itself.
+    Unknown,
+
+    /// The process's stack grew into the guard region at the start of the
+    /// process's memory, or, if the MPU provides no guard region, down to the
+    /// start of the process's memory.
+    StackOverflow,
+}
+
 /// The action the kernel should take when a process encounters a fault.
 ///
 /// When an exception occurs during a process's execution (a common example is a
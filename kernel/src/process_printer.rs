@@ -2,7 +2,7 @@
 
 use core::fmt::Write;
 
-use crate::process::Process;
+use crate::process::{FaultReason, Process};
 use crate::utilities::binary_write::BinaryWrite;
 use crate::utilities::binary_write::WriteToBinaryOffsetWrapper;
 
@@ -147,6 +147,12 @@ impl ProcessPrinter for ProcessPrinterText {
             None => bww.write_str(" Completion Code: None\r\n"),
         };
 
+        let _ = match process.get_fault_reason() {
+            Some(FaultReason::StackOverflow) => bww.write_str(" Fault Reason: Stack Overflow\r\n"),
+            Some(FaultReason::Unknown) => bww.write_str(" Fault Reason: Unknown\r\n"),
+            None => Ok(()),
+        };
+
         let _ = bww.write_fmt(format_args!(
             "\
                  \r\n\
@@ -15,11 +15,13 @@ use crate::config;
 use crate::debug;
 use crate::errorcode::ErrorCode;
 use crate::kernel::Kernel;
+use crate::network_permissions;
 use crate::platform::chip::Chip;
 use crate::platform::mpu::{self, MPU};
 use crate::process::{Error, FunctionCall, FunctionCallSource, Process, State, Task};
-use crate::process::{FaultAction, ProcessCustomGrantIdentifer, ProcessId, ProcessStateCell};
+use crate::process::{FaultAction, FaultReason, ProcessCustomGrantIdentifer};
 use crate::process::{ProcessAddresses, ProcessSizes};
+use crate::process::{ProcessId, ProcessStateCell};
 use crate::process_policies::ProcessFaultPolicy;
 use crate::process_utilities::ProcessLoadError;
 use crate::processbuffer::{ReadOnlyProcessBuffer, ReadWriteProcessBuffer};
@@ -29,6 +31,145 @@ use crate::upcall::UpcallId;
 use crate::utilities::cells::{MapCell, NumericCellExt, OptionalCell};
 use tock_tbf::types::CommandPermissions;
 
+/// Value written to every word of process memory before a process starts, so
+/// the kernel can later determine how much of its stack the process has used.
+const STACK_PAINT_PATTERN: u32 = 0xC0DE_57AC;
+
+/// Minimum size of the guard region at the start of process memory, below
+/// the process's stack, that the process is not allowed to access.
+const STACK_GUARD_SIZE: usize = 32;
+
+/// Fill every word from `start` up to `end` with `STACK_PAINT_PATTERN`.
+///
+/// ### Safety
+///
+/// The memory must be valid for writes and must not be in use.
+unsafe fn paint(start: *mut u8, end: *const u8) {
+    let start = start as *mut u32;
+    let words = (end as usize - start as usize) / mem::size_of::<u32>();
+    for i in 0..words {
+        ptr::write_volatile(start.add(i), STACK_PAINT_PATTERN);
+    }
+}
+
+/// The parts of a process binary that are needed to load a process that is
+/// not position independent at addresses other than the ones it was linked
+/// for. See `tock_tbf::types::TbfHeaderV2Relocations`.
+struct Relocations {
+    link_flash_start: u32,
+    link_ram_start: u32,
+    /// Size of the linked RAM range, i.e. the RAM the process requested.
+    link_ram_size: u32,
+    /// The process binary, not including the protected region.
+    binary: &'static [u8],
+    /// The initial contents of process RAM.
+    data: &'static [u8],
+    /// Offset from the start of process memory to copy `data` to.
+    data_ram_offset: usize,
+    /// The relocation table.
+    table: &'static [u8],
+}
+
+impl Relocations {
+    /// Get the relocation information from the TBF header of a process, and
+    /// check that it only refers to the process binary and to the RAM the
+    /// process requested. Returns `Ok(None)` if the process is not
+    /// relocatable.
+    fn new(
+        header: &tock_tbf::types::TbfHeader,
+        app_flash: &'static [u8],
+    ) -> Result<Option<Relocations>, ProcessLoadError> {
+        let (link_flash_start, link_ram_start) = match header.get_relocation_link_addresses() {
+            Some(addresses) => addresses,
+            None => return Ok(None),
+        };
+        let (data_offset, data_ram_offset, data_size) = header
+            .get_relocation_data()
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        let (table_offset, table_count) = header
+            .get_relocation_table()
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+
+        let binary = app_flash
+            .get(header.get_protected_size() as usize..)
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        let data_end = data_offset
+            .checked_add(data_size)
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        let data = binary
+            .get(data_offset as usize..data_end as usize)
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        let table_end = table_count
+            .checked_mul(mem::size_of::<u32>() as u32)
+            .and_then(|table_size| table_offset.checked_add(table_size))
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        let table = binary
+            .get(table_offset as usize..table_end as usize)
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        // The relocation table is not part of the initial RAM contents.
+        if !data.is_empty()
+            && !table.is_empty()
+            && table_offset < data_end
+            && data_offset < table_end
+        {
+            return Err(ProcessLoadError::InvalidRelocations);
+        }
+
+        // The initial RAM contents must fit in the RAM the process requested.
+        let link_ram_size = header.get_minimum_app_ram_size();
+        let data_ram_end = data_ram_offset
+            .checked_add(data_size)
+            .ok_or(ProcessLoadError::InvalidRelocations)?;
+        if data_ram_offset % mem::size_of::<u32>() as u32 != 0 || data_ram_end > link_ram_size {
+            return Err(ProcessLoadError::InvalidRelocations);
+        }
+
+        let relocations = Relocations {
+            link_flash_start,
+            link_ram_start,
+            link_ram_size,
+            binary,
+            data,
+            data_ram_offset: data_ram_offset as usize,
+            table,
+        };
+
+        // Every relocation must be an aligned word within the initial RAM
+        // contents.
+        let data_ram_end = data_ram_end as usize;
+        if relocations.entries().any(|offset| {
+            offset % mem::size_of::<u32>() != 0
+                || offset < relocations.data_ram_offset
+                || offset + mem::size_of::<u32>() > data_ram_end
+        }) {
+            return Err(ProcessLoadError::InvalidRelocations);
+        }
+
+        Ok(Some(relocations))
+    }
+
+    /// Iterate the RAM offsets of the words to relocate.
+    fn entries(&self) -> impl Iterator<Item = usize> + '_ {
+        self.table
+            .chunks_exact(mem::size_of::<u32>())
+            .map(|entry| u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) as usize)
+    }
+
+    /// Move `value` by the difference between the linked and actual addresses
+    /// if it points into the process binary or the process's RAM.
+    fn relocate(&self, value: u32, ram_start: u32) -> u32 {
+        let flash_offset = value.wrapping_sub(self.link_flash_start);
+        let ram_offset = value.wrapping_sub(self.link_ram_start);
+        if (flash_offset as usize) < self.binary.len() {
+            (self.binary.as_ptr() as u32).wrapping_add(flash_offset)
+        } else if ram_offset < self.link_ram_size {
+            ram_start.wrapping_add(ram_offset)
+        } else {
+            value
+        }
+    }
+}
+
 /// State for helping with debugging apps.
 ///
 /// These pointers and counters are not strictly required for kernel operation,
@@ -68,6 +209,100 @@ struct ProcessStandardDebug {
     /// How many times this process has been paused because it exceeded its
     /// timeslice.
     timeslice_expiration_count: usize,
+
+    /// How many microseconds this process has executed for, as measured by
+    /// the scheduler timer.
+    cpu_time_us: u64,
+}
+
+impl ProcessStandardDebug {
+    fn new(fixed_address_flash: Option<u32>, fixed_address_ram: Option<u32>) -> Self {
+        ProcessStandardDebug {
+            fixed_address_flash: fixed_address_flash,
+            fixed_address_ram: fixed_address_ram,
+            app_heap_start_pointer: None,
+            app_stack_start_pointer: None,
+            app_stack_min_pointer: None,
+            syscall_count: 0,
+            last_syscall: None,
+            dropped_upcall_count: 0,
+            timeslice_expiration_count: 0,
+            cpu_time_us: 0,
+        }
+    }
+
+    /// Adds the time the process executed for in one timeslice.
+    fn cpu_time_executed(&mut self, time_us: u32) {
+        self.cpu_time_us = self.cpu_time_us.saturating_add(time_us as u64);
+    }
+
+    /// Returns the lowest address the process's stack is known to have
+    /// reached.
+    ///
+    /// This is the lower of the lowest stack pointer observed on a context
+    /// switch and the lowest word of the stack that no longer holds the paint
+    /// pattern. The painted measurement is only available once the process
+    /// has told the kernel where its stack starts, as processes place their
+    /// stack at the bottom of their memory and grow it down towards
+    /// `memory_start`.
+    ///
+    /// ### Safety
+    ///
+    /// The memory from `memory_start` up to the start of the stack must be
+    /// valid for reads.
+    unsafe fn stack_low_water_mark(&self, memory_start: *const u8) -> Option<*const u8> {
+        let painted = self.app_stack_start_pointer.map(|stack_start| {
+            let start = memory_start as *const u32;
+            let words = (stack_start as usize - memory_start as usize) / mem::size_of::<u32>();
+            (0..words)
+                .find(|i| ptr::read_volatile(start.add(*i)) != STACK_PAINT_PATTERN)
+                .map_or(stack_start, |i| start.add(i) as *const u8)
+        });
+
+        match (self.app_stack_min_pointer, painted) {
+            (Some(observed), Some(painted)) => Some(cmp::min(observed, painted)),
+            (observed, painted) => observed.or(painted),
+        }
+    }
+
+    /// Check whether the process's stack has overflowed, i.e. has grown down
+    /// to `stack_floor`, the lowest address the stack may use.
+    ///
+    /// Where the MPU supports it, `stack_floor` is the end of a guard region
+    /// the process cannot access, so a stack that grows into it faults with
+    /// the stack pointer inside the guard. Otherwise the stack floor is the
+    /// start of process memory, and the paint pattern tells whether the stack
+    /// has used every word down to it.
+    ///
+    /// ### Safety
+    ///
+    /// The memory from `memory_start` up to the start of the stack must be
+    /// valid for reads.
+    unsafe fn stack_overflowed(&self, memory_start: *const u8, stack_floor: *const u8) -> bool {
+        let below_floor = self
+            .app_stack_min_pointer
+            .map_or(false, |sp| sp < stack_floor);
+        let exhausted = self.app_stack_start_pointer.is_some()
+            && self
+                .stack_low_water_mark(memory_start)
+                .map_or(false, |mark| mark <= stack_floor);
+
+        below_floor || exhausted
+    }
+
+    /// Why the process faulted, as far as the kernel can tell.
+    ///
+    /// ### Safety
+    ///
+    /// The memory from `memory_start` up to the start of the stack must be
+    /// valid for reads.
+    unsafe fn fault_reason(&self, memory_start: *const u8, stack_floor: *const u8) -> FaultReason {
+        if self.stack_overflowed(memory_start, stack_floor) {
+            FaultReason::StackOverflow
+        } else {
+            FaultReason::Unknown
+        }
+    }
 }
 
 /// Entry that is stored in the grant pointer table at the top of process
@@ -168,6 +403,14 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// Collection of pointers to the TBF header in flash.
     header: tock_tbf::types::TbfHeader,
 
+    /// The validated relocation information if the process is not position
+    /// independent.
+    relocations: Option<Relocations>,
+
+    /// The region at the start of process memory that the MPU keeps the
+    /// process from accessing, if the MPU supports one.
+    stack_guard: Cell<Option<mpu::Region>>,
+
     /// State saved on behalf of the process each time the app switches to the
     /// kernel.
     stored_state:
@@ -212,6 +455,10 @@ pub struct ProcessStandard<'a, C: 'static + Chip> {
     /// be stored as `Some(completion code)`.
     completion_code: OptionalCell<Option<u32>>,
 
+    /// Why the process most recently faulted. This is empty if the process
+    /// has never faulted.
+    fault_reason: OptionalCell<FaultReason>,
+
     /// Name of the app.
     process_name: &'static str,
 
@@ -322,6 +569,15 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
     }
 
     fn set_fault_state(&self) {
+        // Record why the process faulted first, so that the fault policy can
+        // take the reason into account.
+        //
+        // The process's stack is inside its memory, which is still allocated.
+        let reason = self.debug.map_or(FaultReason::Unknown, |debug| unsafe {
+            debug.fault_reason(self.mem_start(), self.stack_floor())
+        });
+        self.fault_reason.set(reason);
+
         // Use the per-process fault policy to determine what action the kernel
         // should take since the process faulted.
         let action = self.fault_policy.action(self);
@@ -330,7 +586,12 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             FaultAction::Panic => {
                 // process faulted. Panic and print status
                 self.state.update(State::Faulted);
-                panic!("Process {} had a fault", self.process_name);
+                match reason {
+                    FaultReason::StackOverflow => {
+                        panic!("Process {} overflowed its stack", self.process_name)
+                    }
+                    FaultReason::Unknown => panic!("Process {} had a fault", self.process_name),
+                }
             }
             FaultAction::Restart => {
                 self.try_restart(None);
@@ -432,6 +693,29 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         ))
     }
 
+    fn ipc_service_permitted(&self, service_name: &str) -> bool {
+        self.header
+            .get_ipc_services()
+            .map_or(false, |(count, services)| {
+                services
+                    .iter()
+                    .take(count)
+                    .any(|service| *service == service_name)
+            })
+    }
+
+    fn get_network_permissions(&self) -> Option<network_permissions::NetworkPermissions> {
+        let local_ports = self.header.get_network_local_ports()?;
+        let remote_ports = self.header.get_network_remote_ports()?;
+        let (remote_prefix, remote_prefix_len) = self.header.get_network_remote_prefix()?;
+        Some(network_permissions::NetworkPermissions::new(
+            local_ports,
+            remote_ports,
+            remote_prefix,
+            remote_prefix_len as usize,
+        ))
+    }
+
     fn number_writeable_flash_regions(&self) -> usize {
         self.header.number_writeable_flash_regions()
     }
@@ -471,13 +755,14 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         unallocated_memory_start: *const u8,
         unallocated_memory_size: usize,
         min_region_size: usize,
+        permissions: mpu::Permissions,
     ) -> Option<mpu::Region> {
         self.mpu_config.and_then(|mut config| {
             let new_region = self.chip.mpu().allocate_region(
                 unallocated_memory_start,
                 unallocated_memory_size,
                 min_region_size,
-                mpu::Permissions::ReadWriteOnly,
+                permissions,
                 &mut config,
             );
 
@@ -941,6 +1226,10 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
         self.completion_code.extract()
     }
 
+    fn get_fault_reason(&self) -> Option<FaultReason> {
+        self.fault_reason.extract()
+    }
+
     fn set_syscall_return_value(&self, return_value: SyscallReturn) {
         match self.stored_state.map(|stored_state| unsafe {
             // Actually set the return value for a particular process.
@@ -1081,6 +1370,14 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             .map(|debug| debug.timeslice_expiration_count += 1);
     }
 
+    fn debug_cpu_time_us(&self) -> u64 {
+        self.debug.map_or(0, |debug| debug.cpu_time_us)
+    }
+
+    fn debug_cpu_time_executed(&self, time_us: u32) {
+        self.debug.map(|debug| debug.cpu_time_executed(time_us));
+    }
+
     fn debug_syscall_called(&self, last_syscall: Syscall) {
         self.debug.map(|debug| {
             debug.syscall_count += 1;
@@ -1107,9 +1404,7 @@ impl<C: Chip> Process for ProcessStandard<'_, C> {
             sram_stack_top: self.debug.map_or(None, |debug| {
                 debug.app_stack_start_pointer.map(|p| p as usize)
             }),
-            sram_stack_bottom: self.debug.map_or(None, |debug| {
-                debug.app_stack_min_pointer.map(|p| p as usize)
-            }),
+            sram_stack_bottom: self.stack_low_water_mark().map(|p| p as usize),
         }
     }
 
@@ -1334,6 +1629,10 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         }
 
+        // Check that the relocation information of a process that is not
+        // position independent is valid before committing any memory to it.
+        let relocations = Relocations::new(&tbf_header, app_flash)?;
+
         // Otherwise, actually load the app.
         let process_ram_requested_size = tbf_header.get_minimum_app_ram_size() as usize;
         let init_fn = app_flash
@@ -1480,6 +1779,12 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             }
         };
 
+        // Keep the process's stack, which is at the start of its memory, from
+        // growing beyond it, if the MPU can protect part of app memory.
+        let stack_guard = chip
+            .mpu()
+            .allocate_stack_guard_region(STACK_GUARD_SIZE, &mut mpu_config);
+
         // Get a slice for the memory dedicated to the process. This can fail if
         // the MPU returns a region of memory that is not inside of the
         // `remaining_memory` slice passed to `create()` to allocate the
@@ -1589,6 +1894,8 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.memory_start = app_memory.as_ptr();
         process.memory_len = app_memory.len();
         process.header = tbf_header;
+        process.relocations = relocations;
+        process.stack_guard = Cell::new(stack_guard);
         process.kernel_memory_break = Cell::new(kernel_memory_break);
         process.app_break = Cell::new(initial_app_brk);
         process.grant_pointers = MapCell::new(grant_pointers);
@@ -1601,6 +1908,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.fault_policy = fault_policy;
         process.restart_count = Cell::new(0);
         process.completion_code = OptionalCell::empty();
+        process.fault_reason = OptionalCell::empty();
 
         process.mpu_config = MapCell::new(mpu_config);
         process.mpu_regions = [
@@ -1614,17 +1922,18 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         process.tasks = MapCell::new(tasks);
         process.process_name = process_name.unwrap_or("");
 
-        process.debug = MapCell::new(ProcessStandardDebug {
-            fixed_address_flash: fixed_address_flash,
-            fixed_address_ram: fixed_address_ram,
-            app_heap_start_pointer: None,
-            app_stack_start_pointer: None,
-            app_stack_min_pointer: None,
-            syscall_count: 0,
-            last_syscall: None,
-            dropped_upcall_count: 0,
-            timeslice_expiration_count: 0,
-        });
+        process.debug = MapCell::new(ProcessStandardDebug::new(
+            fixed_address_flash,
+            fixed_address_ram,
+        ));
+
+        // Paint the process's memory so the stack high-water mark can be
+        // measured once the process runs.
+        process.paint_memory();
+
+        // If the process was not compiled to be position independent, set up
+        // its RAM for the addresses it was actually loaded at.
+        process.load_relocatable_data();
 
         let flash_protected_size = process.header.get_protected_size() as usize;
         let flash_app_start_addr = app_flash.as_ptr() as usize + flash_protected_size;
@@ -1696,6 +2005,7 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
             debug.last_syscall = None;
             debug.dropped_upcall_count = 0;
             debug.timeslice_expiration_count = 0;
+            debug.cpu_time_us = 0;
         });
 
         // FLASH
@@ -1767,6 +2077,11 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
                 return Err(ErrorCode::NOMEM);
             }
         };
+        self.stack_guard.set(
+            self.chip
+                .mpu()
+                .allocate_stack_guard_region(STACK_GUARD_SIZE, &mut mpu_config),
+        );
 
         // Reset memory pointers now that we know the layout of the process
         // memory and know that we can configure the MPU.
@@ -1788,6 +2103,13 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         // Drop the old config and use the clean one
         self.mpu_config.replace(mpu_config);
 
+        // Repaint the process's memory so the stack high-water mark reflects
+        // only the new execution of the process.
+        unsafe {
+            self.paint_memory();
+            self.load_relocatable_data();
+        }
+
         // Handle any architecture-specific requirements for a process when it
         // first starts (as it would when it is new).
         let ukb_init_process = self.stored_state.map_or(Err(()), |stored_state| unsafe {
@@ -1975,6 +2297,63 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         current_state != State::Terminated && current_state != State::Faulted
     }
 
+    /// Fill all of the process's memory below the kernel memory break with
+    /// `STACK_PAINT_PATTERN`.
+    ///
+    /// Scanning for the first word that no longer holds the pattern later
+    /// reveals how deep the process's stack has ever grown, even if the
+    /// process never made a syscall while its stack was at its deepest.
+    ///
+    /// ### Safety
+    ///
+    /// This overwrites all process-accessible memory, so it must only be
+    /// called before the process starts executing.
+    unsafe fn paint_memory(&self) {
+        paint(self.mem_start() as *mut u8, self.kernel_memory_break());
+    }
+
+    /// Copy the initial RAM contents of a process that is not position
+    /// independent into its memory, and relocate them for the flash and RAM
+    /// addresses the process was actually loaded at. Does nothing for position
+    /// independent processes.
+    ///
+    /// ### Safety
+    ///
+    /// This overwrites process-accessible memory, so it must only be called
+    /// before the process starts executing.
+    unsafe fn load_relocatable_data(&self) {
+        if let Some(relocations) = &self.relocations {
+            let ram_start = self.mem_start() as *mut u8;
+            ptr::copy_nonoverlapping(
+                relocations.data.as_ptr(),
+                ram_start.add(relocations.data_ram_offset),
+                relocations.data.len(),
+            );
+            for offset in relocations.entries() {
+                let word = ram_start.add(offset) as *mut u32;
+                let value = ptr::read_volatile(word);
+                ptr::write_volatile(word, relocations.relocate(value, ram_start as u32));
+            }
+        }
+    }
+
+    /// Returns the lowest address the process's stack is known to have
+    /// reached.
+    fn stack_low_water_mark(&self) -> Option<*const u8> {
+        // The stack is inside process memory, which is still allocated.
+        self.debug.map_or(None, |debug| unsafe {
+            debug.stack_low_water_mark(self.mem_start())
+        })
+    }
+
+    /// The lowest address the process's stack may use: the end of the stack
+    /// guard region, or the start of process memory if there is none.
+    fn stack_floor(&self) -> *const u8 {
+        self.stack_guard.get().map_or(self.mem_start(), |guard| {
+            guard.start_address().wrapping_add(guard.size())
+        })
+    }
+
     /// The start address of allocated RAM for this process.
     fn mem_start(&self) -> *const u8 {
         self.memory_start
@@ -2015,3 +2394,290 @@ impl<C: 'static + Chip> ProcessStandard<'_, C> {
         self.app_break.get()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const LINK_FLASH_START: u32 = 0x4_0000;
+    const LINK_RAM_START: u32 = 0x2000_8000;
+    const RAM_SIZE: u32 = 256;
+    const BINARY_LEN: usize = 64;
+
+    /// Relocation TLV fields of a process whose initial RAM contents are the
+    /// first 16 bytes of its binary, copied to offset 16 of its RAM, with a
+    /// two entry relocation table at offset 32 of its binary.
+    const RELOCATIONS: [u32; 7] = [LINK_FLASH_START, LINK_RAM_START, 0, 16, 16, 32, 2];
+
+    fn tlv(tipe: u16, words: &[u32]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&tipe.to_le_bytes());
+        entry.extend_from_slice(&(words.len() as u16 * 4).to_le_bytes());
+        for word in words {
+            entry.extend_from_slice(&word.to_le_bytes());
+        }
+        entry
+    }
+
+    /// Build a TBF with the given relocation TLV fields, if any, followed by
+    /// a binary that holds the relocation `table` at binary offset 32, and
+    /// get its relocation information.
+    fn relocations(
+        fields: Option<[u32; 7]>,
+        table: &[u32],
+    ) -> Result<Option<Relocations>, ProcessLoadError> {
+        let mut body = tlv(1, &[0, 0, RAM_SIZE]);
+        if let Some(fields) = fields {
+            body.extend(tlv(10, &fields));
+        }
+        let header_size = 16 + body.len();
+
+        let mut app = Vec::new();
+        app.extend_from_slice(&2u16.to_le_bytes());
+        app.extend_from_slice(&(header_size as u16).to_le_bytes());
+        app.extend_from_slice(&((header_size + BINARY_LEN) as u32).to_le_bytes());
+        app.extend_from_slice(&1u32.to_le_bytes());
+        app.extend_from_slice(&0u32.to_le_bytes());
+        app.extend(body);
+        let checksum = app
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 3)
+            .fold(0, |checksum, (_, word)| {
+                checksum ^ u32::from_le_bytes([word[0], word[1], word[2], word[3]])
+            });
+        app[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+        app.resize(header_size + 32, 0);
+        for entry in table {
+            app.extend_from_slice(&entry.to_le_bytes());
+        }
+        app.resize(header_size + BINARY_LEN, 0);
+
+        let app: &'static [u8] = Box::leak(app.into_boxed_slice());
+        let header = tock_tbf::parse::parse_tbf_header(&app[..header_size], 2).unwrap();
+        Relocations::new(&header, app)
+    }
+
+    fn with(field: usize, value: u32) -> Option<[u32; 7]> {
+        let mut fields = RELOCATIONS;
+        fields[field] = value;
+        Some(fields)
+    }
+
+    fn invalid(result: Result<Option<Relocations>, ProcessLoadError>) -> bool {
+        matches!(result, Err(ProcessLoadError::InvalidRelocations))
+    }
+
+    #[test]
+    fn relocations_are_range_checked() {
+        assert!(matches!(relocations(None, &[]), Ok(None)));
+        let valid = relocations(Some(RELOCATIONS), &[16, 20]).unwrap().unwrap();
+        assert_eq!(valid.data.len(), 16);
+        assert_eq!(valid.data_ram_offset, 16);
+        assert_eq!(valid.entries().collect::<Vec<_>>(), [16, 20]);
+
+        // Initial RAM contents past the end of the binary.
+        assert!(invalid(relocations(with(2, 56), &[16, 20])));
+        assert!(invalid(relocations(with(4, u32::MAX), &[16, 20])));
+        // Initial RAM contents that do not fit in the requested RAM, or are
+        // not word aligned.
+        assert!(invalid(relocations(with(3, RAM_SIZE - 8), &[16, 20])));
+        assert!(invalid(relocations(with(3, 18), &[18, 22])));
+        // A relocation table past the end of the binary.
+        assert!(invalid(relocations(with(6, 9), &[16, 20])));
+        assert!(invalid(relocations(with(6, u32::MAX), &[16, 20])));
+        // Relocations outside the initial RAM contents, or not word aligned.
+        assert!(invalid(relocations(Some(RELOCATIONS), &[16, 32])));
+        assert!(invalid(relocations(Some(RELOCATIONS), &[12, 20])));
+        assert!(invalid(relocations(Some(RELOCATIONS), &[16, 18])));
+    }
+
+    #[test]
+    fn relocation_table_overlapping_data_is_invalid() {
+        let mut fields = RELOCATIONS;
+        // Initial RAM contents from binary offset 24 to 40 cover the first
+        // entry of the table at offset 32.
+        fields[2] = 24;
+        assert!(invalid(relocations(Some(fields), &[16, 20])));
+
+        // Directly after the table is fine.
+        fields[2] = 40;
+        assert!(relocations(Some(fields), &[16, 20]).unwrap().is_some());
+    }
+
+    #[test]
+    fn relocate_moves_values_into_the_process() {
+        let relocations = relocations(Some(RELOCATIONS), &[16, 20]).unwrap().unwrap();
+        let binary_start = relocations.binary.as_ptr() as u32;
+        let ram_start = 0x2000_0000;
+
+        // Values inside the linked binary and RAM move with the process.
+        assert_eq!(
+            relocations.relocate(LINK_FLASH_START, ram_start),
+            binary_start
+        );
+        assert_eq!(
+            relocations.relocate(LINK_FLASH_START + BINARY_LEN as u32 - 4, ram_start),
+            binary_start + BINARY_LEN as u32 - 4
+        );
+        assert_eq!(relocations.relocate(LINK_RAM_START, ram_start), ram_start);
+        assert_eq!(
+            relocations.relocate(LINK_RAM_START + RAM_SIZE - 1, ram_start),
+            ram_start + RAM_SIZE - 1
+        );
+
+        // Anything else, e.g. peripheral addresses or plain integers, is left
+        // alone.
+        for value in [
+            0,
+            LINK_FLASH_START - 1,
+            LINK_FLASH_START + BINARY_LEN as u32,
+            LINK_RAM_START - 1,
+            LINK_RAM_START + RAM_SIZE,
+            0x4000_0000,
+        ] {
+            assert_eq!(relocations.relocate(value, ram_start), value);
+        }
+    }
+
+    /// Paint `memory` and describe a process whose stack starts at word 24
+    /// of it.
+    fn painted(memory: &mut [u32; 32]) -> ProcessStandardDebug {
+        let range = memory.as_mut_ptr_range();
+        unsafe { paint(range.start as *mut u8, range.end as *const u8) };
+
+        let mut debug = ProcessStandardDebug::new(None, None);
+        debug.app_stack_start_pointer = Some(word(memory, 24));
+        debug
+    }
+
+    fn word(memory: &[u32], index: usize) -> *const u8 {
+        memory.as_ptr().wrapping_add(index) as *const u8
+    }
+
+    #[test]
+    fn paint_fills_whole_words() {
+        let mut memory = [0u32; 8];
+        let start = memory.as_mut_ptr() as *mut u8;
+        // A trailing partial word is left alone.
+        unsafe { paint(start, start.wrapping_add(7 * 4 + 2)) };
+        assert_eq!(memory[..7], [STACK_PAINT_PATTERN; 7]);
+        assert_eq!(memory[7], 0);
+    }
+
+    #[test]
+    fn stack_low_water_mark_is_lowest_used_word() {
+        let mut memory = [0; 32];
+        let mut debug = painted(&mut memory);
+        let start = memory.as_ptr() as *const u8;
+        assert_eq!(
+            unsafe { debug.stack_low_water_mark(start) },
+            Some(word(&memory, 24))
+        );
+
+        // The deepest word the stack wrote to counts, even if the stack
+        // pointer was never seen there.
+        memory[20] = 0;
+        memory[16] = STACK_PAINT_PATTERN + 1;
+        debug.app_stack_min_pointer = Some(word(&memory, 22));
+        assert_eq!(
+            unsafe { debug.stack_low_water_mark(start) },
+            Some(word(&memory, 16))
+        );
+
+        // A lower observed stack pointer wins, e.g. one that pushed the
+        // paint pattern itself.
+        debug.app_stack_min_pointer = Some(word(&memory, 12));
+        assert_eq!(
+            unsafe { debug.stack_low_water_mark(start) },
+            Some(word(&memory, 12))
+        );
+
+        // Without the start of the stack only observed pointers are known.
+        debug.app_stack_start_pointer = None;
+        assert_eq!(
+            unsafe { debug.stack_low_water_mark(start) },
+            Some(word(&memory, 12))
+        );
+        debug.app_stack_min_pointer = None;
+        assert_eq!(unsafe { debug.stack_low_water_mark(start) }, None);
+    }
+
+    #[test]
+    fn fault_reason_without_stack_guard() {
+        let mut memory = [0; 32];
+        let mut debug = painted(&mut memory);
+        let start = memory.as_ptr() as *const u8;
+        memory[1] = 0;
+        assert_eq!(
+            unsafe { debug.fault_reason(start, start) },
+            FaultReason::Unknown
+        );
+
+        // Writing the last word of memory uses up the stack.
+        memory[0] = 0;
+        assert_eq!(
+            unsafe { debug.fault_reason(start, start) },
+            FaultReason::StackOverflow
+        );
+
+        // As does a stack pointer below the start of memory.
+        let mut memory = [0; 32];
+        let mut debug = painted(&mut memory);
+        let start = memory.as_ptr() as *const u8;
+        debug.app_stack_min_pointer = Some(start.wrapping_sub(4));
+        assert_eq!(
+            unsafe { debug.fault_reason(start, start) },
+            FaultReason::StackOverflow
+        );
+    }
+
+    #[test]
+    fn fault_reason_with_stack_guard() {
+        // The process cannot write to the first 8 words.
+        let mut memory = [0; 32];
+        let mut debug = painted(&mut memory);
+        let start = memory.as_ptr() as *const u8;
+        let floor = word(&memory, 8);
+        memory[9] = 0;
+        debug.app_stack_min_pointer = Some(word(&memory, 9));
+        assert_eq!(
+            unsafe { debug.fault_reason(start, floor) },
+            FaultReason::Unknown
+        );
+
+        // The stack pointer is inside the guard when the process faults.
+        debug.app_stack_min_pointer = Some(word(&memory, 7));
+        assert_eq!(
+            unsafe { debug.fault_reason(start, floor) },
+            FaultReason::StackOverflow
+        );
+
+        // Using the stack down to the guard, with the guard itself untouched.
+        debug.app_stack_min_pointer = None;
+        memory[8] = 0;
+        assert_eq!(
+            unsafe { debug.fault_reason(start, floor) },
+            FaultReason::StackOverflow
+        );
+    }
+
+    #[test]
+    fn cpu_time_accumulates_over_timeslices() {
+        let mut debug = ProcessStandardDebug::new(None, None);
+        debug.cpu_time_executed(10_000);
+        debug.cpu_time_executed(u32::MAX);
+        debug.cpu_time_executed(1);
+        assert_eq!(debug.cpu_time_us, 10_001 + u32::MAX as u64);
+
+        debug.cpu_time_us = u64::MAX - 1;
+        debug.cpu_time_executed(5);
+        assert_eq!(debug.cpu_time_us, u64::MAX);
+    }
+}
@@ -59,6 +59,11 @@ pub enum ProcessLoadError {
     /// KernelVersion TBF header.
     IncompatibleKernelVersion { version: Option<(u16, u16)> },
 
+    /// A process that is not position independent included relocation
+    /// information in its TBF header that refers to data outside of its binary
+    /// or outside of the RAM it requested.
+    InvalidRelocations,
+
     /// Process loading error due (likely) to a bug in the kernel. If you get
     /// this error please open a bug report.
     InternalError,
@@ -124,6 +129,10 @@ impl fmt::Debug for ProcessLoadError {
                 None => write!(f, "Process did not provide a TBF kernel version header"),
             },
 
+            ProcessLoadError::InvalidRelocations => {
+                write!(f, "App relocation information is invalid")
+            }
+
             ProcessLoadError::InternalError => write!(f, "Error in kernel. Likely a bug."),
         }
     }
@@ -0,0 +1,769 @@
+//! Memory sharing between processes enforced by the MPU.
+//!
+//! This is a special syscall driver that allows a process to create a named
+//! region in its own memory and grant other processes, identified by package
+//! name, read-only or read-write access to it. A process that has been granted
+//! access attaches to the region, at which point the kernel adds an MPU region
+//! covering the shared memory to that process's MPU configuration.
+//!
+//! Sharing is revoked when the owner revokes it, when the owner restarts, or
+//! when the process that attached to the region restarts. A region is also
+//! destroyed if its memory is no longer below the owner's application break.
+//! `ProcessStandard` does not let a process move its break below memory it
+//! has allowed, which pins the break above every region until the owner
+//! restarts, but the kernel checks the break again whenever a process attaches
+//! and before every attached process runs. Because the kernel must remove the
+//! MPU region before the attached process runs again, the `SharedMemory`
+//! struct implements `ContextSwitchCallback`, and boards that use this driver
+//! must return it from `KernelResources::context_switch_callback()`.
+//!
+//! As with IPC, the memory being shared must satisfy the alignment and size
+//! requirements of the MPU on the chip, and it is up to the owning process to
+//! choose a suitable buffer.
+//!
+//! Usage
+//! -----
+//!
+//! The owning process:
+//!
+//! 1. Allows the memory to share with `allow_readwrite` 0, and the region name
+//!    with `allow_readonly` 0, then calls command 1 to create the region.
+//! 2. Allows the package name of the other process with `allow_readonly` 1,
+//!    then calls command 2 to grant that process access.
+//!
+//! The process given access allows the owner's package name with
+//! `allow_readonly` 1 and the region name with `allow_readonly` 0, then calls
+//! command 4 to attach to the region. On success the address and length of
+//! the shared memory are returned.
+
+use crate::capabilities::MemoryAllocationCapability;
+use crate::grant::{AllowRoCount, AllowRwCount, Grant, UpcallCount};
+use crate::kernel::Kernel;
+use crate::platform::mpu;
+use crate::platform::ContextSwitchCallback;
+use crate::process::{self, Process, ProcessId};
+use crate::processbuffer::{ReadableProcessBuffer, ReadableProcessSlice};
+use crate::syscall_driver::{CommandReturn, SyscallDriver};
+use crate::ErrorCode;
+use core::ops::Range;
+
+/// Syscall number
+pub const DRIVER_NUM: usize = 0x10002;
+
+/// Maximum number of regions a single process can create.
+pub const MAX_REGIONS: usize = 2;
+
+/// Maximum number of processes a single region can be shared with.
+pub const MAX_GRANTEES: usize = 4;
+
+/// Maximum number of regions owned by other processes a single process can be
+/// attached to.
+pub const MAX_ATTACHMENTS: usize = 4;
+
+/// Maximum length of a region name in bytes.
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Ids for read-only allow buffers
+mod ro_allow {
+    /// The name of the region to create or attach to.
+    pub(super) const REGION_NAME: usize = 0;
+    /// The package name of the other process.
+    pub(super) const PROCESS_NAME: usize = 1;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub(super) const COUNT: u8 = 2;
+}
+
+/// Ids for read-write allow buffers
+mod rw_allow {
+    /// The memory to share when creating a region.
+    pub(super) const REGION: usize = 0;
+    /// The number of allow buffers the kernel stores for this grant.
+    pub(super) const COUNT: u8 = 1;
+}
+
+/// Access a process is granted to a shared region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+impl Access {
+    fn permissions(self) -> mpu::Permissions {
+        match self {
+            Access::ReadOnly => mpu::Permissions::ReadOnly,
+            Access::ReadWrite => mpu::Permissions::ReadWriteOnly,
+        }
+    }
+}
+
+/// A region of a process's memory that can be shared with other processes.
+#[derive(Copy, Clone)]
+struct Region {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    start: *const u8,
+    len: usize,
+    grantees: [Option<(ProcessId, Access)>; MAX_GRANTEES],
+}
+
+impl Region {
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    fn access_for(&self, processid: ProcessId) -> Option<Access> {
+        self.grantees.iter().find_map(|grantee| match grantee {
+            Some((id, access)) if *id == processid => Some(*access),
+            _ => None,
+        })
+    }
+
+    /// Whether the memory of the region is inside `memory`, the memory its
+    /// owner has access to.
+    fn in_memory(&self, memory: &Range<usize>) -> bool {
+        let start = self.start as usize;
+        start >= memory.start
+            && start
+                .checked_add(self.len)
+                .map_or(false, |end| end <= memory.end)
+    }
+
+    /// Give `grantee` `access` to the region, or revoke its access if
+    /// `access` is `None`. Entries for processes that `exists` no longer
+    /// accepts are dropped first.
+    fn set_access(
+        &mut self,
+        grantee: ProcessId,
+        access: Option<Access>,
+        exists: impl Fn(&ProcessId) -> bool,
+    ) -> Result<(), ErrorCode> {
+        for entry in self.grantees.iter_mut() {
+            if let Some((id, _)) = entry {
+                if *id == grantee || !exists(id) {
+                    *entry = None;
+                }
+            }
+        }
+
+        match access {
+            Some(access) => {
+                let slot = self
+                    .grantees
+                    .iter_mut()
+                    .find(|entry| entry.is_none())
+                    .ok_or(ErrorCode::NOMEM)?;
+                *slot = Some((grantee, access));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// A region owned by another process that a process has attached to.
+#[derive(Copy, Clone)]
+struct Attachment {
+    owner: ProcessId,
+    region_index: usize,
+    access: Access,
+    mpu_region: mpu::Region,
+}
+
+/// State that is stored in each process's grant region to support shared
+/// memory.
+#[derive(Default)]
+struct SharedMemoryData {
+    /// Regions this process has created in its own memory.
+    regions: [Option<Region>; MAX_REGIONS],
+    /// Regions in other processes' memory this process has attached to.
+    attachments: [Option<Attachment>; MAX_ATTACHMENTS],
+}
+
+impl SharedMemoryData {
+    /// Add a region named `name` over `len` bytes at `start`, returning its
+    /// index.
+    fn add_region(
+        &mut self,
+        name: &[u8],
+        start: *const u8,
+        len: usize,
+    ) -> Result<usize, ErrorCode> {
+        if len == 0 || name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(ErrorCode::INVAL);
+        }
+        if self
+            .regions
+            .iter()
+            .flatten()
+            .any(|region| region.name() == name)
+        {
+            return Err(ErrorCode::ALREADY);
+        }
+
+        let index = self
+            .regions
+            .iter()
+            .position(|region| region.is_none())
+            .ok_or(ErrorCode::NOMEM)?;
+        let mut region = Region {
+            name: [0; MAX_NAME_LEN],
+            name_len: name.len(),
+            start,
+            len,
+            grantees: [None; MAX_GRANTEES],
+        };
+        region.name[..name.len()].copy_from_slice(name);
+        self.regions[index] = Some(region);
+        Ok(index)
+    }
+
+    /// Find the first region `matches` accepts, returning its index. The
+    /// region is destroyed instead if it is no longer inside `memory`, the
+    /// memory this process has access to.
+    fn find_region(
+        &mut self,
+        matches: impl Fn(&Region) -> bool,
+        memory: &Range<usize>,
+    ) -> Option<(usize, Region)> {
+        let (index, entry) = self
+            .regions
+            .iter_mut()
+            .enumerate()
+            .find(|(_, region)| region.map_or(false, |region| matches(&region)))?;
+        let region = (*entry)?;
+        if !region.in_memory(memory) {
+            *entry = None;
+            return None;
+        }
+        Some((index, region))
+    }
+
+    /// Whether `processid` still has `access` to region `index`. The region
+    /// is destroyed if it is no longer inside `memory`, the memory this
+    /// process has access to.
+    fn permits(
+        &mut self,
+        index: usize,
+        processid: ProcessId,
+        access: Access,
+        memory: &Range<usize>,
+    ) -> bool {
+        let entry = &mut self.regions[index];
+        match entry {
+            Some(region) if !region.in_memory(memory) => {
+                *entry = None;
+                false
+            }
+            Some(region) => region.access_for(processid) == Some(access),
+            None => false,
+        }
+    }
+
+    /// Find a free slot for an attachment to region `region_index` of
+    /// `owner`.
+    fn attachment_slot(&self, owner: ProcessId, region_index: usize) -> Result<usize, ErrorCode> {
+        if self
+            .attachments
+            .iter()
+            .flatten()
+            .any(|attachment| attachment.owner == owner && attachment.region_index == region_index)
+        {
+            return Err(ErrorCode::ALREADY);
+        }
+        self.attachments
+            .iter()
+            .position(|attachment| attachment.is_none())
+            .ok_or(ErrorCode::NOMEM)
+    }
+
+    /// Remove the attachment whose MPU region starts at `address`, returning
+    /// that MPU region.
+    fn remove_attachment(&mut self, address: usize) -> Result<mpu::Region, ErrorCode> {
+        self.attachments
+            .iter_mut()
+            .find(|attachment| {
+                attachment.map_or(false, |attachment| {
+                    attachment.mpu_region.start_address() as usize == address
+                })
+            })
+            .and_then(|attachment| attachment.take())
+            .map(|attachment| attachment.mpu_region)
+            .ok_or(ErrorCode::INVAL)
+    }
+}
+
+/// Whether the contents of `slice` are equal to `bytes`.
+fn slice_equals(slice: &ReadableProcessSlice, bytes: &[u8]) -> bool {
+    slice.len() == bytes.len()
+        && slice
+            .iter()
+            .zip(bytes.iter())
+            .all(|(c1, c2)| c1.get() == *c2)
+}
+
+/// The shared memory mechanism struct.
+pub struct SharedMemory {
+    /// The grant regions for each process that holds the per-process shared
+    /// memory data.
+    data: Grant<
+        SharedMemoryData,
+        UpcallCount<0>,
+        AllowRoCount<{ ro_allow::COUNT }>,
+        AllowRwCount<{ rw_allow::COUNT }>,
+    >,
+}
+
+impl SharedMemory {
+    pub fn new(
+        kernel: &'static Kernel,
+        driver_num: usize,
+        capability: &dyn MemoryAllocationCapability,
+    ) -> Self {
+        Self {
+            data: kernel.create_grant(driver_num, capability),
+        }
+    }
+
+    /// Find the process whose package name matches the buffer `appid` shared
+    /// in the `PROCESS_NAME` read-only allow slot.
+    fn find_named_process(&self, appid: ProcessId) -> Result<ProcessId, ErrorCode> {
+        self.data
+            .enter(appid, |_, kernel_data| {
+                kernel_data
+                    .get_readonly_processbuffer(ro_allow::PROCESS_NAME)
+                    .and_then(|name| {
+                        name.enter(|name| {
+                            self.data.kernel.process_until(|p| {
+                                if slice_equals(name, p.get_process_name().as_bytes()) {
+                                    Some(p.processid())
+                                } else {
+                                    None
+                                }
+                            })
+                        })
+                    })
+                    .map_err(ErrorCode::from)
+                    .and_then(|found| found.ok_or(ErrorCode::NODEVICE))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Create a region from the buffers `appid` has allowed, returning its
+    /// index.
+    fn create(&self, appid: ProcessId) -> Result<usize, ErrorCode> {
+        self.data
+            .enter(appid, |data, kernel_data| {
+                let (start, len) = kernel_data
+                    .get_readwrite_processbuffer(rw_allow::REGION)
+                    .map(|buffer| (buffer.ptr(), buffer.len()))
+                    .map_err(ErrorCode::from)?;
+
+                let mut name = [0; MAX_NAME_LEN];
+                let name_len = kernel_data
+                    .get_readonly_processbuffer(ro_allow::REGION_NAME)
+                    .and_then(|buffer| {
+                        buffer.enter(|slice| {
+                            if slice.len() > MAX_NAME_LEN {
+                                Err(ErrorCode::INVAL)
+                            } else {
+                                slice.copy_to_slice(&mut name[..slice.len()]);
+                                Ok(slice.len())
+                            }
+                        })
+                    })
+                    .map_err(ErrorCode::from)??;
+
+                data.add_region(&name[..name_len], start, len)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Give the process named in `appid`'s `PROCESS_NAME` allow buffer
+    /// `access` to region `index`, or revoke its access if `access` is
+    /// `None`.
+    fn set_access(
+        &self,
+        appid: ProcessId,
+        index: usize,
+        access: Option<Access>,
+    ) -> Result<(), ErrorCode> {
+        let grantee = self.find_named_process(appid)?;
+        if grantee == appid {
+            return Err(ErrorCode::INVAL);
+        }
+
+        self.data
+            .enter(appid, |data, _| {
+                data.regions
+                    .get_mut(index)
+                    .and_then(|region| region.as_mut())
+                    .ok_or(ErrorCode::INVAL)?
+                    .set_access(grantee, access, |id| {
+                        self.data.kernel.processid_is_valid(id)
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Remove region `index` owned by `appid`, revoking access for every
+    /// process it was shared with.
+    fn destroy(&self, appid: ProcessId, index: usize) -> Result<(), ErrorCode> {
+        self.data
+            .enter(appid, |data, _| {
+                data.regions
+                    .get_mut(index)
+                    .and_then(|region| region.take())
+                    .map(|_| ())
+                    .ok_or(ErrorCode::INVAL)
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Attach `appid` to a region owned by another process, mapping the
+    /// shared memory into `appid`'s MPU configuration.
+    fn attach(&self, appid: ProcessId) -> Result<(*const u8, usize), ErrorCode> {
+        let owner = self.find_named_process(appid)?;
+        if owner == appid {
+            return Err(ErrorCode::INVAL);
+        }
+
+        let memory = self.memory_of(owner).ok_or(ErrorCode::NODEVICE)?;
+
+        self.data
+            .enter(appid, |data, kernel_data| {
+                // Find the region with the requested name in the owner's
+                // memory, and check that it was shared with us.
+                let (region_index, region) = kernel_data
+                    .get_readonly_processbuffer(ro_allow::REGION_NAME)
+                    .and_then(|name| {
+                        name.enter(|name| {
+                            self.data.enter(owner, |owner_data, _| {
+                                owner_data.find_region(
+                                    |region| slice_equals(name, region.name()),
+                                    &memory,
+                                )
+                            })
+                        })
+                    })
+                    .map_err(ErrorCode::from)?
+                    .map_err(ErrorCode::from)?
+                    .ok_or(ErrorCode::NODEVICE)?;
+                let access = region.access_for(appid).ok_or(ErrorCode::NODEVICE)?;
+                let slot = data.attachment_slot(owner, region_index)?;
+
+                let mpu_region = self
+                    .data
+                    .kernel
+                    .process_map_or(None, appid, |process| {
+                        process.add_mpu_region(
+                            region.start,
+                            region.len,
+                            region.len,
+                            access.permissions(),
+                        )
+                    })
+                    .ok_or(ErrorCode::NOMEM)?;
+
+                data.attachments[slot] = Some(Attachment {
+                    owner,
+                    region_index,
+                    access,
+                    mpu_region,
+                });
+                Ok((region.start, region.len))
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// Detach `appid` from the shared region that starts at `address`,
+    /// removing it from `appid`'s MPU configuration.
+    fn detach(&self, appid: ProcessId, address: usize) -> Result<(), ErrorCode> {
+        self.data
+            .enter(appid, |data, _| {
+                let mpu_region = data.remove_attachment(address)?;
+                self.data
+                    .kernel
+                    .process_map_or(Err(ErrorCode::FAIL), appid, |process| {
+                        process.remove_mpu_region(mpu_region)
+                    })
+            })
+            .unwrap_or_else(|err| Err(err.into()))
+    }
+
+    /// The memory `owner` has access to, from the start of its RAM up to its
+    /// application break.
+    fn memory_of(&self, owner: ProcessId) -> Option<Range<usize>> {
+        self.data.kernel.process_map_or(None, owner, |process| {
+            let addresses = process.get_addresses();
+            Some(addresses.sram_start..addresses.sram_app_brk)
+        })
+    }
+
+    /// Whether `attachment` held by `processid` is still permitted by the
+    /// owner of the region. A region whose memory is no longer below the
+    /// owner's application break is destroyed.
+    fn attachment_valid(&self, processid: ProcessId, attachment: &Attachment) -> bool {
+        // If the owner restarted or exited its `ProcessId` is no longer valid
+        // and entering its grant fails.
+        let memory = match self.memory_of(attachment.owner) {
+            Some(memory) => memory,
+            None => return false,
+        };
+        self.data
+            .enter(attachment.owner, |owner_data, _| {
+                owner_data.permits(
+                    attachment.region_index,
+                    processid,
+                    attachment.access,
+                    &memory,
+                )
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl ContextSwitchCallback for SharedMemory {
+    /// Remove any shared regions the process is no longer allowed to access
+    /// from its MPU configuration before it runs.
+    fn context_switch_hook(&self, process: &dyn Process) {
+        let processid = process.processid();
+        if let Some(grant) = self.data.iter().find(|pg| pg.processid() == processid) {
+            grant.enter(|data, _| {
+                for entry in data.attachments.iter_mut() {
+                    if let Some(attachment) = entry {
+                        if !self.attachment_valid(processid, attachment) {
+                            let _ = process.remove_mpu_region(attachment.mpu_region);
+                            *entry = None;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+impl SyscallDriver for SharedMemory {
+    /// Create, share and attach to shared memory regions.
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check, always returns Ok(())
+    /// - `1`: Create a region from the memory passed to `allow_readwrite` 0,
+    ///        named with the buffer passed to `allow_readonly` 0. Returns the
+    ///        index of the new region.
+    /// - `2`: Grant the process whose package name was passed to
+    ///        `allow_readonly` 1 access to the region with index `data1`.
+    ///        `data2` selects read-only (0) or read-write (1) access.
+    /// - `3`: Revoke the access of the process whose package name was passed
+    ///        to `allow_readonly` 1 to the region with index `data1`.
+    /// - `4`: Attach to the region named in `allow_readonly` 0 owned by the
+    ///        process named in `allow_readonly` 1. Returns the address and
+    ///        length of the shared memory.
+    /// - `5`: Detach from the attached region starting at address `data1`.
+    /// - `6`: Destroy the region with index `data1`, revoking access for all
+    ///        processes it was shared with.
+    fn command(
+        &self,
+        command_number: usize,
+        data1: usize,
+        data2: usize,
+        appid: ProcessId,
+    ) -> CommandReturn {
+        let result = match command_number {
+            0 => Ok(CommandReturn::success()),
+            1 => self
+                .create(appid)
+                .map(|index| CommandReturn::success_u32(index as u32)),
+            2 => {
+                let access = match data2 {
+                    0 => Ok(Access::ReadOnly),
+                    1 => Ok(Access::ReadWrite),
+                    _ => Err(ErrorCode::INVAL),
+                };
+                access
+                    .and_then(|access| self.set_access(appid, data1, Some(access)))
+                    .map(|()| CommandReturn::success())
+            }
+            3 => self
+                .set_access(appid, data1, None)
+                .map(|()| CommandReturn::success()),
+            4 => self
+                .attach(appid)
+                .map(|(start, len)| CommandReturn::success_u32_u32(start as u32, len as u32)),
+            5 => self.detach(appid, data1).map(|()| CommandReturn::success()),
+            6 => self
+                .destroy(appid, data1)
+                .map(|()| CommandReturn::success()),
+            _ => Err(ErrorCode::NOSUPPORT),
+        };
+        result.unwrap_or_else(CommandReturn::failure)
+    }
+
+    fn allocate_grant(&self, processid: ProcessId) -> Result<(), process::Error> {
+        self.data.enter(processid, |_, _| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+
+    use super::*;
+
+    const START: usize = 0x2000_0000;
+
+    fn processid(identifier: usize) -> ProcessId {
+        let kernel: &'static Kernel = Box::leak(Box::new(Kernel::new(&[])));
+        ProcessId::new(kernel, identifier, identifier)
+    }
+
+    fn memory(len: usize) -> Range<usize> {
+        START..START + len
+    }
+
+    /// An owner with region 0 named "shm" over its first 64 bytes, shared
+    /// read-write with `client`.
+    fn shared(client: ProcessId) -> SharedMemoryData {
+        let mut data = SharedMemoryData::default();
+        assert_eq!(data.add_region(b"shm", START as *const u8, 64), Ok(0));
+        let region = data.regions[0].as_mut().unwrap();
+        assert_eq!(
+            region.set_access(client, Some(Access::ReadWrite), |_| true),
+            Ok(())
+        );
+        data
+    }
+
+    #[test]
+    fn attachment_kept_while_valid() {
+        let client = processid(1);
+        let mut data = shared(client);
+        assert!(data.permits(0, client, Access::ReadWrite, &memory(256)));
+        assert!(!data.permits(0, client, Access::ReadOnly, &memory(256)));
+        assert!(!data.permits(0, processid(2), Access::ReadWrite, &memory(256)));
+    }
+
+    #[test]
+    fn access_revoked_removes_region() {
+        let client = processid(1);
+        let mut data = shared(client);
+        let region = data.regions[0].as_mut().unwrap();
+        assert_eq!(region.set_access(client, None, |_| true), Ok(()));
+        assert!(!data.permits(0, client, Access::ReadWrite, &memory(256)));
+    }
+
+    #[test]
+    fn region_above_break_is_destroyed() {
+        let client = processid(1);
+        let mut data = shared(client);
+        assert!(!data.permits(0, client, Access::ReadWrite, &memory(32)));
+
+        // The region no longer exists, so it is not found even once the break
+        // moves back up.
+        assert!(data.regions[0].is_none());
+        assert!(!data.permits(0, client, Access::ReadWrite, &memory(256)));
+        assert!(data
+            .find_region(|region| region.name() == b"shm", &memory(256))
+            .is_none());
+    }
+
+    #[test]
+    fn find_region_checks_break() {
+        let mut data = shared(processid(1));
+        let (index, region) = data
+            .find_region(|region| region.name() == b"shm", &memory(256))
+            .unwrap();
+        assert_eq!((index, region.len), (0, 64));
+        assert!(data
+            .find_region(|region| region.name() == b"other", &memory(256))
+            .is_none());
+        assert!(data
+            .find_region(|region| region.name() == b"shm", &memory(32))
+            .is_none());
+    }
+
+    #[test]
+    fn region_below_memory_is_not_in_memory() {
+        let data = shared(processid(1));
+        let region = data.regions[0].unwrap();
+        assert!(region.in_memory(&memory(64)));
+        assert!(!region.in_memory(&(START + 1..START + 256)));
+        assert!(!region.in_memory(&memory(63)));
+    }
+
+    #[test]
+    fn add_region_checks_name_and_space() {
+        let mut data = SharedMemoryData::default();
+        let start = START as *const u8;
+        assert_eq!(data.add_region(b"shm", start, 0), Err(ErrorCode::INVAL));
+        assert_eq!(data.add_region(b"", start, 8), Err(ErrorCode::INVAL));
+        assert_eq!(
+            data.add_region(&[b'a'; MAX_NAME_LEN + 1], start, 8),
+            Err(ErrorCode::INVAL)
+        );
+
+        for i in 0..MAX_REGIONS {
+            assert_eq!(data.add_region(&[b'a' + i as u8], start, 8), Ok(i));
+        }
+        assert_eq!(data.add_region(b"a", start, 8), Err(ErrorCode::ALREADY));
+        assert_eq!(data.add_region(b"z", start, 8), Err(ErrorCode::NOMEM));
+    }
+
+    #[test]
+    fn set_access_drops_stale_grantees() {
+        let mut data = shared(processid(1));
+        let region = data.regions[0].as_mut().unwrap();
+        for i in 2..=MAX_GRANTEES {
+            assert_eq!(
+                region.set_access(processid(i), Some(Access::ReadOnly), |_| true),
+                Ok(())
+            );
+        }
+        let extra = processid(MAX_GRANTEES + 1);
+        assert_eq!(
+            region.set_access(extra, Some(Access::ReadOnly), |_| true),
+            Err(ErrorCode::NOMEM)
+        );
+
+        // Once process 1 no longer exists its entry makes room.
+        let stale = processid(1);
+        assert_eq!(
+            region.set_access(extra, Some(Access::ReadOnly), |id| *id != stale),
+            Ok(())
+        );
+        assert_eq!(region.access_for(stale), None);
+        assert_eq!(region.access_for(extra), Some(Access::ReadOnly));
+
+        // Changing the access of an existing grantee replaces its entry.
+        assert_eq!(
+            region.set_access(extra, Some(Access::ReadWrite), |_| true),
+            Ok(())
+        );
+        assert_eq!(region.access_for(extra), Some(Access::ReadWrite));
+    }
+
+    #[test]
+    fn attachments_are_unique() {
+        let owner = processid(0);
+        let mut data = SharedMemoryData::default();
+        assert_eq!(data.attachment_slot(owner, 0), Ok(0));
+        data.attachments[0] = Some(Attachment {
+            owner,
+            region_index: 0,
+            access: Access::ReadOnly,
+            mpu_region: mpu::Region::new(START as *const u8, 64),
+        });
+        assert_eq!(data.attachment_slot(owner, 0), Err(ErrorCode::ALREADY));
+        assert_eq!(data.attachment_slot(owner, 1), Ok(1));
+
+        assert!(data.remove_attachment(START + 4).is_err());
+        assert_eq!(
+            data.remove_attachment(START).map(|region| region.size()),
+            Ok(64)
+        );
+        assert_eq!(data.attachment_slot(owner, 0), Ok(0));
+    }
+}
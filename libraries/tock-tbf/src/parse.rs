@@ -133,6 +133,10 @@ pub fn parse_tbf_header(
                 let mut permissions_pointer: Option<types::TbfHeaderV2Permissions<8>> = None;
                 let mut persistent_acls_pointer: Option<types::TbfHeaderV2PersistentAcl<8>> = None;
                 let mut kernel_version: Option<types::TbfHeaderV2KernelVersion> = None;
+                let mut ipc_services_pointer: Option<types::TbfHeaderV2IpcServices<8>> = None;
+                let mut relocations_pointer: Option<types::TbfHeaderV2Relocations> = None;
+                let mut network_permissions_pointer: Option<types::TbfHeaderV2NetworkPermissions> =
+                    None;
 
                 // Iterate the remainder of the header looking for TLV entries.
                 while remaining.len() > 0 {
@@ -259,6 +263,47 @@ pub fn parse_tbf_header(
                             }
                         }
 
+                        types::TbfHeaderTypes::TbfHeaderIpcServices => {
+                            ipc_services_pointer = Some(
+                                remaining
+                                    .get(0..tlv_header.length as usize)
+                                    .ok_or(types::TbfParseError::NotEnoughFlash)?
+                                    .try_into()?,
+                            );
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderRelocations => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2Relocations>();
+                            if tlv_header.length as usize == entry_len {
+                                relocations_pointer = Some(
+                                    remaining
+                                        .get(0..entry_len)
+                                        .ok_or(types::TbfParseError::NotEnoughFlash)?
+                                        .try_into()?,
+                                );
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
+                        types::TbfHeaderTypes::TbfHeaderNetworkPermissions => {
+                            let entry_len = mem::size_of::<types::TbfHeaderV2NetworkPermissions>();
+                            if tlv_header.length as usize == entry_len {
+                                network_permissions_pointer = Some(
+                                    remaining
+                                        .get(0..entry_len)
+                                        .ok_or(types::TbfParseError::NotEnoughFlash)?
+                                        .try_into()?,
+                                );
+                            } else {
+                                return Err(types::TbfParseError::BadTlvEntry(
+                                    tlv_header.tipe as usize,
+                                ));
+                            }
+                        }
+
                         _ => {}
                     }
 
@@ -279,6 +324,9 @@ pub fn parse_tbf_header(
                     permissions: permissions_pointer,
                     persistent_acls: persistent_acls_pointer,
                     kernel_version: kernel_version,
+                    ipc_services: ipc_services_pointer,
+                    relocations: relocations_pointer,
+                    network_permissions: network_permissions_pointer,
                 };
 
                 Ok(types::TbfHeader::TbfHeaderV2(tbf_header))
@@ -287,3 +335,370 @@ pub fn parse_tbf_header(
         _ => Err(types::TbfParseError::UnsupportedVersion(version)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::types::{TbfHeader, TbfHeaderTypes, TbfParseError};
+
+    /// Encode a TLV entry with the given type and length field, padding the
+    /// value to a multiple of four bytes.
+    fn tlv(tipe: TbfHeaderTypes, length: usize, value: &[u8]) -> Vec<u8> {
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&(tipe as u16).to_le_bytes());
+        entry.extend_from_slice(&(length as u16).to_le_bytes());
+        entry.extend_from_slice(value);
+        entry.resize(4 + align4!(value.len()), 0);
+        entry
+    }
+
+    /// Build a version 2 TBF header with the TLV entries in `body` and parse
+    /// it.
+    fn parse(body: &[Vec<u8>]) -> Result<TbfHeader, TbfParseError> {
+        let body = body.concat();
+        let header_size = 16 + body.len();
+        let mut header = Vec::new();
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&(header_size as u16).to_le_bytes());
+        header.extend_from_slice(&(header_size as u32).to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&body);
+
+        let checksum = header
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 3)
+            .fold(0, |checksum, (_, chunk)| {
+                checksum ^ u32::from_le_bytes(chunk.try_into().unwrap())
+            });
+        header[12..16].copy_from_slice(&checksum.to_le_bytes());
+
+        parse_tbf_header(Box::leak(header.into_boxed_slice()), 2)
+    }
+
+    fn kernel_version(major: u16, minor: u16) -> Vec<u8> {
+        let mut value = Vec::new();
+        value.extend_from_slice(&major.to_le_bytes());
+        value.extend_from_slice(&minor.to_le_bytes());
+        tlv(TbfHeaderTypes::TbfHeaderKernelVersion, value.len(), &value)
+    }
+
+    fn ipc_services_value(names: &[&str]) -> Vec<u8> {
+        let mut value = Vec::new();
+        value.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        for name in names {
+            value.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            value.extend_from_slice(name.as_bytes());
+        }
+        value
+    }
+
+    fn ipc_services(names: &[&str]) -> Vec<u8> {
+        let value = ipc_services_value(names);
+        tlv(TbfHeaderTypes::TbfHeaderIpcServices, value.len(), &value)
+    }
+
+    #[test]
+    fn ipc_services_round_trip() {
+        // Odd length names leave the following length fields unaligned, and
+        // the entry itself needs padding before the next TLV.
+        let header = parse(&[
+            ipc_services(&["org.tock.a", "svc", "x"]),
+            kernel_version(2, 1),
+        ])
+        .unwrap();
+        let (count, services) = header.get_ipc_services().unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(&services[..count], &["org.tock.a", "svc", "x"]);
+        assert_eq!(header.get_kernel_version(), Some((2, 1)));
+    }
+
+    #[test]
+    fn ipc_services_absent() {
+        let header = parse(&[kernel_version(2, 1)]).unwrap();
+        assert!(header.get_ipc_services().is_none());
+    }
+
+    #[test]
+    fn ipc_services_truncated() {
+        // The count promises a second name that is not in the entry.
+        let mut value = ipc_services_value(&["svc"]);
+        value[0] = 2;
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderIpcServices,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(result, Err(TbfParseError::NotEnoughFlash)));
+
+        // The name length runs past the end of the entry.
+        let mut value = ipc_services_value(&["svc"]);
+        value[2] = 8;
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderIpcServices,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(result, Err(TbfParseError::NotEnoughFlash)));
+    }
+
+    #[test]
+    fn ipc_services_oversized() {
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h", "i"];
+        let result = parse(&[ipc_services(&names)]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::TooManyEntries(tipe)) if tipe == TbfHeaderTypes::TbfHeaderIpcServices as usize
+        ));
+
+        // The TLV length runs past the end of the header.
+        let value = ipc_services_value(&["svc"]);
+        let mut entry = tlv(TbfHeaderTypes::TbfHeaderIpcServices, value.len(), &value);
+        entry[2] = 64;
+        let result = parse(&[entry]);
+        assert!(matches!(result, Err(TbfParseError::NotEnoughFlash)));
+    }
+
+    #[test]
+    fn ipc_services_misaligned() {
+        // A TLV length that is not a multiple of four still skips the padding
+        // to reach the next entry.
+        let value = ipc_services_value(&["ab"]);
+        assert_eq!(value.len() % 4, 2);
+        let header = parse(&[
+            tlv(TbfHeaderTypes::TbfHeaderIpcServices, value.len(), &value),
+            kernel_version(2, 0),
+        ])
+        .unwrap();
+        assert_eq!(header.get_ipc_services().unwrap().0, 1);
+        assert_eq!(header.get_kernel_version(), Some((2, 0)));
+
+        // Names must be UTF-8.
+        let mut value = ipc_services_value(&["ab"]);
+        value[4] = 0xff;
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderIpcServices,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderIpcServices as usize
+        ));
+    }
+
+    fn relocations_value(words: &[u32]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    const RELOCATION_WORDS: [u32; 7] = [0x40000, 0x20000000, 0x800, 0x0, 0x104, 0x904, 12];
+
+    #[test]
+    fn relocations_round_trip() {
+        let value = relocations_value(&RELOCATION_WORDS);
+        let header = parse(&[
+            tlv(TbfHeaderTypes::TbfHeaderRelocations, value.len(), &value),
+            kernel_version(2, 1),
+        ])
+        .unwrap();
+        assert_eq!(
+            header.get_relocation_link_addresses(),
+            Some((0x40000, 0x20000000))
+        );
+        assert_eq!(header.get_relocation_data(), Some((0x800, 0x0, 0x104)));
+        assert_eq!(header.get_relocation_table(), Some((0x904, 12)));
+        assert_eq!(header.get_kernel_version(), Some((2, 1)));
+    }
+
+    #[test]
+    fn relocations_absent() {
+        let header = parse(&[kernel_version(2, 1)]).unwrap();
+        assert!(header.get_relocation_link_addresses().is_none());
+        assert!(header.get_relocation_data().is_none());
+        assert!(header.get_relocation_table().is_none());
+    }
+
+    #[test]
+    fn relocations_truncated() {
+        let value = relocations_value(&RELOCATION_WORDS[..6]);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderRelocations,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderRelocations as usize
+        ));
+
+        // The length is right but the header ends before the entry does.
+        let value = relocations_value(&RELOCATION_WORDS);
+        let mut entry = tlv(TbfHeaderTypes::TbfHeaderRelocations, value.len(), &value);
+        entry.truncate(entry.len() - 4);
+        let result = parse(&[entry]);
+        assert!(matches!(result, Err(TbfParseError::NotEnoughFlash)));
+    }
+
+    #[test]
+    fn relocations_oversized() {
+        let mut words = RELOCATION_WORDS.to_vec();
+        words.push(0);
+        let value = relocations_value(&words);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderRelocations,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderRelocations as usize
+        ));
+    }
+
+    #[test]
+    fn relocations_misaligned() {
+        let mut value = relocations_value(&RELOCATION_WORDS);
+        value.push(0);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderRelocations,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderRelocations as usize
+        ));
+    }
+
+    const REMOTE_PREFIX: [u8; 16] = [0xfd, 0x00, 0x0b, 0x0b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+    fn network_permissions_value(prefix_len: u16) -> Vec<u8> {
+        let mut value = Vec::new();
+        for port in [1000u16, 1999, 5683, 5683] {
+            value.extend_from_slice(&port.to_le_bytes());
+        }
+        value.extend_from_slice(&REMOTE_PREFIX);
+        value.extend_from_slice(&prefix_len.to_le_bytes());
+        value
+    }
+
+    #[test]
+    fn network_permissions_round_trip() {
+        // The entry is 26 bytes long, so it needs padding before the next TLV.
+        let value = network_permissions_value(32);
+        assert_eq!(value.len() % 4, 2);
+        let header = parse(&[
+            tlv(
+                TbfHeaderTypes::TbfHeaderNetworkPermissions,
+                value.len(),
+                &value,
+            ),
+            kernel_version(2, 1),
+        ])
+        .unwrap();
+        assert_eq!(header.get_network_local_ports(), Some((1000, 1999)));
+        assert_eq!(header.get_network_remote_ports(), Some((5683, 5683)));
+        assert_eq!(
+            header.get_network_remote_prefix(),
+            Some((REMOTE_PREFIX, 32))
+        );
+        assert_eq!(header.get_kernel_version(), Some((2, 1)));
+    }
+
+    #[test]
+    fn network_permissions_absent() {
+        let header = parse(&[kernel_version(2, 1)]).unwrap();
+        assert!(header.get_network_local_ports().is_none());
+        assert!(header.get_network_remote_ports().is_none());
+        assert!(header.get_network_remote_prefix().is_none());
+    }
+
+    #[test]
+    fn network_permissions_truncated() {
+        let value = network_permissions_value(32);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderNetworkPermissions,
+            value.len() - 2,
+            &value[..value.len() - 2],
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderNetworkPermissions as usize
+        ));
+
+        // The length is right but the header ends before the entry does.
+        let mut entry = tlv(
+            TbfHeaderTypes::TbfHeaderNetworkPermissions,
+            value.len(),
+            &value,
+        );
+        entry.truncate(entry.len() - 8);
+        let result = parse(&[entry]);
+        assert!(matches!(result, Err(TbfParseError::NotEnoughFlash)));
+    }
+
+    #[test]
+    fn network_permissions_oversized() {
+        let mut value = network_permissions_value(32);
+        value.extend_from_slice(&[0, 0]);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderNetworkPermissions,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderNetworkPermissions as usize
+        ));
+
+        // A prefix cannot be longer than an address.
+        let value = network_permissions_value(129);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderNetworkPermissions,
+            value.len(),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderNetworkPermissions as usize
+        ));
+    }
+
+    #[test]
+    fn network_permissions_misaligned() {
+        // A length padded up to a multiple of four is not accepted.
+        let value = network_permissions_value(128);
+        let result = parse(&[tlv(
+            TbfHeaderTypes::TbfHeaderNetworkPermissions,
+            align4!(value.len()),
+            &value,
+        )]);
+        assert!(matches!(
+            result,
+            Err(TbfParseError::BadTlvEntry(tipe)) if tipe == TbfHeaderTypes::TbfHeaderNetworkPermissions as usize
+        ));
+
+        // The full prefix length is allowed, and the entry after the padding
+        // is still found.
+        let header = parse(&[
+            tlv(
+                TbfHeaderTypes::TbfHeaderNetworkPermissions,
+                value.len(),
+                &value,
+            ),
+            kernel_version(2, 0),
+        ])
+        .unwrap();
+        assert_eq!(
+            header.get_network_remote_prefix(),
+            Some((REMOTE_PREFIX, 128))
+        );
+        assert_eq!(header.get_kernel_version(), Some((2, 0)));
+    }
+}
@@ -5,6 +5,7 @@ use core::fmt;
 use core::mem::size_of;
 
 const NUM_PERSISTENT_ACLS: usize = 8;
+const NUM_IPC_SERVICES: usize = 8;
 
 /// Error when parsing just the beginning of the TBF header. This is only used
 /// when establishing the linked list structure of apps installed in flash.
@@ -124,6 +125,9 @@ pub enum TbfHeaderTypes {
     TbfHeaderPermissions = 6,
     TbfHeaderPersistentAcl = 7,
     TbfHeaderKernelVersion = 8,
+    TbfHeaderIpcServices = 9,
+    TbfHeaderRelocations = 10,
+    TbfHeaderNetworkPermissions = 11,
 
     /// Some field in the header that we do not understand. Since the TLV format
     /// specifies the length of each section, if we get a field we do not
@@ -213,6 +217,63 @@ pub struct TbfHeaderV2KernelVersion {
     minor: u16,
 }
 
+/// Information needed to load a process that was not compiled to be position
+/// independent at an address other than the one it was linked for.
+///
+/// The kernel copies the initial contents of the process's RAM (e.g. `.data`
+/// and the GOT) from flash into process memory, and then adjusts each word
+/// listed in the relocation table. Words that point into the linked flash or
+/// RAM range are moved by the difference between the linked and the actual
+/// addresses.
+///
+/// Flash offsets are from the start of the process binary (i.e. after the
+/// protected region). RAM offsets are from the start of process memory.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TbfHeaderV2Relocations {
+    /// The address the process binary was linked to run from.
+    link_flash_start: u32,
+    /// The address the start of process RAM was linked at.
+    link_ram_start: u32,
+    /// The flash offset of the initial RAM contents.
+    data_offset: u32,
+    /// The RAM offset the initial RAM contents are copied to.
+    data_ram_offset: u32,
+    /// The size of the initial RAM contents in bytes.
+    data_size: u32,
+    /// The flash offset of the relocation table. Each entry is a little-endian
+    /// `u32` RAM offset of a word within the initial RAM contents.
+    relocations_offset: u32,
+    /// The number of entries in the relocation table.
+    relocations_count: u32,
+}
+
+/// The UDP ports and remote addresses this app may use.
+///
+/// Port ranges are inclusive. Remote addresses are given as an IPv6 prefix; a
+/// prefix length of 0 allows any address.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TbfHeaderV2NetworkPermissions {
+    /// The lowest local port the app may bind to and send from.
+    local_port_min: u16,
+    /// The highest local port the app may bind to and send from.
+    local_port_max: u16,
+    /// The lowest remote port the app may send to.
+    remote_port_min: u16,
+    /// The highest remote port the app may send to.
+    remote_port_max: u16,
+    /// The prefix of the remote addresses the app may communicate with.
+    remote_prefix: [u8; 16],
+    /// The length of `remote_prefix` in bits, at most 128.
+    remote_prefix_len: u16,
+}
+
+/// A list of the package names of IPC services this app may communicate with.
+#[derive(Clone, Copy, Debug)]
+pub struct TbfHeaderV2IpcServices<const L: usize> {
+    length: u16,
+    services: [&'static str; L],
+}
+
 // Conversion functions from slices to the various TBF fields.
 
 impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Base {
@@ -261,6 +322,9 @@ impl core::convert::TryFrom<u16> for TbfHeaderTypes {
             6 => Ok(TbfHeaderTypes::TbfHeaderPermissions),
             7 => Ok(TbfHeaderTypes::TbfHeaderPersistentAcl),
             8 => Ok(TbfHeaderTypes::TbfHeaderKernelVersion),
+            9 => Ok(TbfHeaderTypes::TbfHeaderIpcServices),
+            10 => Ok(TbfHeaderTypes::TbfHeaderRelocations),
+            11 => Ok(TbfHeaderTypes::TbfHeaderNetworkPermissions),
             _ => Ok(TbfHeaderTypes::Unknown),
         }
     }
@@ -497,6 +561,101 @@ impl core::convert::TryFrom<&[u8]> for TbfHeaderV2KernelVersion {
     }
 }
 
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2Relocations {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2Relocations, Self::Error> {
+        let word = |i: usize| -> Result<u32, TbfParseError> {
+            Ok(u32::from_le_bytes(
+                b.get(i * 4..(i + 1) * 4)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ))
+        };
+        Ok(TbfHeaderV2Relocations {
+            link_flash_start: word(0)?,
+            link_ram_start: word(1)?,
+            data_offset: word(2)?,
+            data_ram_offset: word(3)?,
+            data_size: word(4)?,
+            relocations_offset: word(5)?,
+            relocations_count: word(6)?,
+        })
+    }
+}
+
+impl core::convert::TryFrom<&[u8]> for TbfHeaderV2NetworkPermissions {
+    type Error = TbfParseError;
+
+    fn try_from(b: &[u8]) -> Result<TbfHeaderV2NetworkPermissions, Self::Error> {
+        let half_word = |i: usize| -> Result<u16, TbfParseError> {
+            Ok(u16::from_le_bytes(
+                b.get(i..i + 2)
+                    .ok_or(TbfParseError::InternalError)?
+                    .try_into()?,
+            ))
+        };
+        let remote_prefix_len = half_word(24)?;
+        if remote_prefix_len > 128 {
+            return Err(TbfParseError::BadTlvEntry(
+                TbfHeaderTypes::TbfHeaderNetworkPermissions as usize,
+            ));
+        }
+        Ok(TbfHeaderV2NetworkPermissions {
+            local_port_min: half_word(0)?,
+            local_port_max: half_word(2)?,
+            remote_port_min: half_word(4)?,
+            remote_port_max: half_word(6)?,
+            remote_prefix: b
+                .get(8..24)
+                .ok_or(TbfParseError::InternalError)?
+                .try_into()?,
+            remote_prefix_len,
+        })
+    }
+}
+
+impl<const L: usize> core::convert::TryFrom<&'static [u8]> for TbfHeaderV2IpcServices<L> {
+    type Error = TbfParseError;
+
+    fn try_from(b: &'static [u8]) -> Result<TbfHeaderV2IpcServices<L>, Self::Error> {
+        let number_services = u16::from_le_bytes(
+            b.get(0..2)
+                .ok_or(TbfParseError::NotEnoughFlash)?
+                .try_into()?,
+        );
+
+        let mut services: [&'static str; L] = [""; L];
+        let mut start = 2;
+        for i in 0..number_services as usize {
+            let name_len = u16::from_le_bytes(
+                b.get(start..start + 2)
+                    .ok_or(TbfParseError::NotEnoughFlash)?
+                    .try_into()?,
+            ) as usize;
+            let name_buf = b
+                .get(start + 2..start + 2 + name_len)
+                .ok_or(TbfParseError::NotEnoughFlash)?;
+            start += 2 + name_len;
+
+            if let Some(service) = services.get_mut(i) {
+                *service = core::str::from_utf8(name_buf).or(Err(TbfParseError::BadTlvEntry(
+                    TbfHeaderTypes::TbfHeaderIpcServices as usize,
+                )))?;
+            } else {
+                return Err(TbfParseError::TooManyEntries(
+                    TbfHeaderTypes::TbfHeaderIpcServices as usize,
+                ));
+            }
+        }
+
+        Ok(TbfHeaderV2IpcServices {
+            length: number_services,
+            services,
+        })
+    }
+}
+
 /// The command permissions specified by the TBF header.
 ///
 /// Use the `get_command_permissions()` function to retrieve these.
@@ -526,6 +685,9 @@ pub struct TbfHeaderV2 {
     pub(crate) permissions: Option<TbfHeaderV2Permissions<8>>,
     pub(crate) persistent_acls: Option<TbfHeaderV2PersistentAcl<NUM_PERSISTENT_ACLS>>,
     pub(crate) kernel_version: Option<TbfHeaderV2KernelVersion>,
+    pub(crate) ipc_services: Option<TbfHeaderV2IpcServices<NUM_IPC_SERVICES>>,
+    pub(crate) relocations: Option<TbfHeaderV2Relocations>,
+    pub(crate) network_permissions: Option<TbfHeaderV2NetworkPermissions>,
 }
 
 /// Type that represents the fields of the Tock Binary Format header.
@@ -745,4 +907,94 @@ impl TbfHeader {
             _ => None,
         }
     }
+
+    /// Get the number of valid IPC service names and the names of the IPC
+    /// services this process is allowed to communicate with.
+    /// Returns `None` if the IPC services header is not included.
+    pub fn get_ipc_services(&self) -> Option<(usize, [&'static str; NUM_IPC_SERVICES])> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => match hd.ipc_services {
+                Some(ipc_services) => Some((ipc_services.length.into(), ipc_services.services)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Get the flash and RAM addresses a relocatable process was linked for.
+    /// Returns `None` if the relocations header is not included.
+    pub fn get_relocation_link_addresses(&self) -> Option<(u32, u32)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .relocations
+                .map(|relocations| (relocations.link_flash_start, relocations.link_ram_start)),
+            _ => None,
+        }
+    }
+
+    /// Get the flash offset, RAM offset and size of the initial RAM contents of
+    /// a relocatable process. Returns `None` if the relocations header is not
+    /// included.
+    pub fn get_relocation_data(&self) -> Option<(u32, u32, u32)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.relocations.map(|relocations| {
+                (
+                    relocations.data_offset,
+                    relocations.data_ram_offset,
+                    relocations.data_size,
+                )
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the offset and number of entries of the relocation table of a
+    /// relocatable process. Returns `None` if the relocations header is not
+    /// included.
+    pub fn get_relocation_table(&self) -> Option<(u32, u32)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd.relocations.map(|relocations| {
+                (
+                    relocations.relocations_offset,
+                    relocations.relocations_count,
+                )
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the inclusive range of local UDP ports this process may bind to
+    /// and send from. Returns `None` if the network permissions header is not
+    /// included.
+    pub fn get_network_local_ports(&self) -> Option<(u16, u16)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .network_permissions
+                .map(|perms| (perms.local_port_min, perms.local_port_max)),
+            _ => None,
+        }
+    }
+
+    /// Get the inclusive range of remote UDP ports this process may send to.
+    /// Returns `None` if the network permissions header is not included.
+    pub fn get_network_remote_ports(&self) -> Option<(u16, u16)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .network_permissions
+                .map(|perms| (perms.remote_port_min, perms.remote_port_max)),
+            _ => None,
+        }
+    }
+
+    /// Get the IPv6 prefix and prefix length of the remote addresses this
+    /// process may communicate with. Returns `None` if the network permissions
+    /// header is not included.
+    pub fn get_network_remote_prefix(&self) -> Option<([u8; 16], u16)> {
+        match self {
+            TbfHeader::TbfHeaderV2(hd) => hd
+                .network_permissions
+                .map(|perms| (perms.remote_prefix, perms.remote_prefix_len)),
+            _ => None,
+        }
+    }
 }